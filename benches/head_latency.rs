@@ -0,0 +1,111 @@
+//! Latency of upload lookups while a large chunk is written.
+//!
+//! HEAD requests only read information about uploads,
+//! but they share async workers with PATCH requests.
+//! This benchmark polls information about an upload
+//! while big chunks are written to another one and reports
+//! latency of the lookups. Writes are made in two ways:
+//!
+//! * `inline` - chunks are written by blocking calls right on the async worker;
+//! * `storage` - chunks are written by the file storage.
+//!
+//! Run it with:
+//!
+//! ```bash
+//! cargo bench --bench head_latency
+//! ```
+use std::{
+    io::Write,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use rustus::{
+    info_storages::{file_info_storage::FileInfoStorage, FileInfo, InfoStorage},
+    storages::{file_storage::FileStorage, Storage},
+};
+
+/// Size of every written chunk.
+const CHUNK_SIZE: usize = 64 * 1024 * 1024;
+/// Number of chunks written in every run.
+const CHUNKS: usize = 8;
+/// Interval between lookups.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Write chunk by blocking calls on the current worker.
+fn write_inline(path: &Path, bytes: &Bytes) {
+    let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+    file.write_all(bytes.as_ref()).unwrap();
+    file.sync_data().unwrap();
+}
+
+/// Poll information about the upload until all chunks are written.
+///
+/// Returns latencies of all lookups.
+async fn measure(data_dir: &Path, inline: bool) -> Vec<Duration> {
+    let mut storage = FileStorage::new(data_dir.join("data"), String::new(), true);
+    storage.prepare().await.unwrap();
+    let mut info_storage = FileInfoStorage::new(data_dir.join("info"));
+    info_storage.prepare().await.unwrap();
+
+    let polled = FileInfo::new("polled", Some(0), None, storage.to_string(), None);
+    info_storage.set_info(&polled, true).await.unwrap();
+    let mut written = FileInfo::new(
+        "written",
+        Some(CHUNK_SIZE * CHUNKS),
+        None,
+        storage.to_string(),
+        None,
+    );
+    written.path = Some(storage.create_file(&written).await.unwrap());
+
+    let writer = tokio::task::spawn_local(async move {
+        let chunk = Bytes::from(vec![0u8; CHUNK_SIZE]);
+        for _ in 0..CHUNKS {
+            if inline {
+                write_inline(Path::new(written.path.as_ref().unwrap()), &chunk);
+            } else {
+                storage.add_bytes(&written, chunk.clone()).await.unwrap();
+            }
+            written.offset += CHUNK_SIZE;
+            // Lets lookups run between chunks in both ways.
+            tokio::task::yield_now().await;
+        }
+    });
+
+    let mut latencies = Vec::new();
+    while !writer.is_finished() {
+        let sent = Instant::now();
+        tokio::time::sleep(POLL_INTERVAL).await;
+        info_storage.get_info("polled").await.unwrap();
+        latencies.push(sent.elapsed().saturating_sub(POLL_INTERVAL));
+    }
+    writer.await.unwrap();
+    latencies.sort();
+    latencies
+}
+
+/// Get latency of the given percentile.
+fn percentile(latencies: &[Duration], percent: usize) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    latencies[(latencies.len() - 1) * percent / 100]
+}
+
+fn main() {
+    actix_rt::System::new().block_on(async {
+        for (name, inline) in [("inline", true), ("storage", false)] {
+            let dir = tempdir::TempDir::new("head_latency").unwrap();
+            let latencies = measure(dir.path(), inline).await;
+            println!(
+                "{name:>8}: lookups: {:>6}, p50: {:>10?}, p99: {:>10?}, max: {:>10?}",
+                latencies.len(),
+                percentile(&latencies, 50),
+                percentile(&latencies, 99),
+                latencies.last().copied().unwrap_or_default(),
+            );
+        }
+    });
+}
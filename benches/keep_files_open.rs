@@ -0,0 +1,86 @@
+//! Throughput of uploads with lots of small chunks.
+//!
+//! Every chunk of an upload is written by a separate call
+//! to the file storage. This benchmark writes the same chunks
+//! in three ways and reports time and throughput of every run:
+//!
+//! * `reopen` - files are opened for every chunk;
+//! * `open` - files are kept open between chunks (`--keep-files-open`);
+//! * `open+fsync` - files are kept open and synced to disk
+//!   after every `FSYNC_THRESHOLD` bytes (`--keep-files-open-flush-size`).
+//!
+//! Run it with:
+//!
+//! ```bash
+//! cargo bench --bench keep_files_open
+//! ```
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use rustus::{
+    info_storages::FileInfo,
+    storages::{file_storage::FileStorage, Storage},
+};
+
+/// Sizes of written chunks.
+const CHUNK_SIZES: [usize; 3] = [1024, 16 * 1024, 256 * 1024];
+/// Number of bytes written in every run.
+const UPLOAD_SIZE: usize = 64 * 1024 * 1024;
+/// Number of bytes after which kept open files are synced.
+const FSYNC_THRESHOLD: usize = 4 * 1024 * 1024;
+/// Idle time after which kept open files are closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Write one upload by chunks of the given size.
+///
+/// Returns time spent on writing chunks.
+async fn measure(data_dir: &Path, storage: FileStorage, chunk_size: usize) -> Duration {
+    let mut storage = storage;
+    storage.prepare().await.unwrap();
+    let mut file_info = FileInfo::new("upload", None, None, storage.to_string(), None);
+    file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+
+    let chunk = Bytes::from(vec![0u8; chunk_size]);
+    let started = Instant::now();
+    for _ in 0..UPLOAD_SIZE / chunk_size {
+        storage.add_bytes(&file_info, chunk.clone()).await.unwrap();
+        file_info.offset += chunk_size;
+    }
+    let elapsed = started.elapsed();
+
+    assert_eq!(
+        std::fs::metadata(data_dir.join(file_info.path.unwrap()))
+            .unwrap()
+            .len(),
+        UPLOAD_SIZE as u64
+    );
+    elapsed
+}
+
+fn main() {
+    actix_rt::System::new().block_on(async {
+        for chunk_size in CHUNK_SIZES {
+            for name in ["reopen", "open", "open+fsync"] {
+                let dir = tempdir::TempDir::new("keep_files_open").unwrap();
+                let storage = FileStorage::new(dir.path().join("data"), String::new(), false);
+                let storage = match name {
+                    "open" => storage.with_open_files(IDLE_TIMEOUT, None),
+                    "open+fsync" => storage.with_open_files(IDLE_TIMEOUT, Some(FSYNC_THRESHOLD)),
+                    _ => storage,
+                };
+                let elapsed = measure(dir.path(), storage, chunk_size).await;
+                #[allow(clippy::cast_precision_loss)]
+                let throughput = UPLOAD_SIZE as f64 / elapsed.as_secs_f64() / 1024.0 / 1024.0;
+                println!(
+                    "{:>4}KB chunks, {name:>10}: chunks: {:>6}, time: {:>10?}, {throughput:>8.1} MB/s",
+                    chunk_size / 1024,
+                    UPLOAD_SIZE / chunk_size,
+                    elapsed,
+                );
+            }
+        }
+    });
+}
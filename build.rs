@@ -0,0 +1,59 @@
+//! Build information which is returned at `/version`.
+//!
+//! Commit can be set with `RUSTUS_GIT_COMMIT` if sources
+//! are built without git, E.G. in docker.
+//! Build time is taken from `SOURCE_DATE_EPOCH` for reproducible builds.
+use std::{
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=RUSTUS_GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let commit = std::env::var("RUSTUS_GIT_COMMIT")
+        .ok()
+        .filter(|commit| !commit.is_empty())
+        .or_else(git_commit)
+        .unwrap_or_default();
+    println!("cargo:rustc-env=RUSTUS_GIT_COMMIT={commit}");
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default()
+        });
+    println!("cargo:rustc-env=RUSTUS_BUILD_TIMESTAMP={timestamp}");
+}
+
+/// Get hash of the current commit.
+fn git_commit() -> Option<String> {
+    let head = Path::new(".git/HEAD");
+    if head.exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        // HEAD usually points to a branch, which is changed by commits.
+        let branch = std::fs::read_to_string(head).ok().and_then(|head| {
+            head.trim()
+                .strip_prefix("ref: ")
+                .map(|branch| Path::new(".git").join(branch))
+        });
+        if let Some(branch) = branch.filter(|branch| branch.exists()) {
+            println!("cargo:rerun-if-changed={}", branch.display());
+        }
+    }
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout)
+        .ok()
+        .map(|commit| String::from(commit.trim()))
+        .filter(|commit| !commit.is_empty())
+}
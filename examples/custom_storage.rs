@@ -0,0 +1,100 @@
+//! Example of a custom storage.
+//!
+//! This example registers storage that keeps uploads in memory
+//! and starts rustus. Run it with:
+//!
+//! ```bash
+//! cargo run --example custom_storage -- --storage memory-storage
+//! ```
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use bytes::Bytes;
+use derive_more::Display;
+use rustus::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    storages::{register_storage, Storage},
+};
+
+/// Storage that keeps all uploads in memory.
+#[derive(Display, Clone, Default)]
+#[display(fmt = "memory_storage")]
+struct MemoryStorage {
+    files: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl MemoryStorage {
+    fn files(&self) -> std::sync::MutexGuard<'_, HashMap<String, Vec<u8>>> {
+        self.files
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for MemoryStorage {
+    async fn prepare(&mut self) -> RustusResult<()> {
+        Ok(())
+    }
+
+    async fn get_contents(
+        &self,
+        file_info: &FileInfo,
+        _request: &HttpRequest,
+    ) -> RustusResult<HttpResponse> {
+        let contents = self
+            .files()
+            .get(file_info.id.as_str())
+            .cloned()
+            .ok_or(RustusError::FileNotFound)?;
+        Ok(HttpResponse::Ok().body(contents))
+    }
+
+    async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+        self.files()
+            .get_mut(file_info.id.as_str())
+            .ok_or(RustusError::FileNotFound)?
+            .extend_from_slice(bytes.as_ref());
+        Ok(())
+    }
+
+    async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
+        self.files().insert(file_info.id.clone(), Vec::new());
+        Ok(file_info.id.clone())
+    }
+
+    async fn concat_files(
+        &self,
+        file_info: &FileInfo,
+        parts_info: Vec<FileInfo>,
+    ) -> RustusResult<()> {
+        let mut files = self.files();
+        let mut contents = Vec::new();
+        for part in parts_info {
+            let part_contents = files
+                .get(part.id.as_str())
+                .ok_or(RustusError::FileNotFound)?;
+            contents.extend_from_slice(part_contents);
+        }
+        files.insert(file_info.id.clone(), contents);
+        Ok(())
+    }
+
+    async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+        self.files().remove(file_info.id.as_str());
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    // Storage must be registered before configuration is parsed.
+    let storage = MemoryStorage::default();
+    register_storage("memory-storage", move |_config| Box::new(storage.clone()));
+    rustus::run().await
+}
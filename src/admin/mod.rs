@@ -0,0 +1,179 @@
+use actix_web::{
+    dev::{Service, ServiceRequest},
+    guard,
+    http::header::AUTHORIZATION,
+    web,
+};
+
+use crate::{errors::RustusError, utils::import, RustusConf, RustusResult, State};
+
+mod routes;
+
+/// Check the admin token of the request.
+///
+/// Import has its own token, so it's checked by the route.
+fn authorize(config: &RustusConf, request: &ServiceRequest) -> RustusResult<()> {
+    let Some(token) = &config.admin_token else {
+        return Ok(());
+    };
+    if request.match_info().unprocessed().trim_end_matches('/') == "/import" {
+        return Ok(());
+    }
+    let auth = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if import::check_token(token, auth) {
+        Ok(())
+    } else {
+        Err(RustusError::Unauthorized(String::from(
+            "admin token is missing or wrong",
+        )))
+    }
+}
+
+/// Configure admin API.
+///
+/// Admin API is used by operators to get
+/// information about uploads. It's not a part of TUS protocol.
+///
+/// `GET /admin/tenants/{tenant}/usage` - get usage of a tenant.
+/// `GET /admin/uploads` - find uploads by status, metadata and creation time.
+/// `GET /admin/uploads/{file_id}` - get information and data location of an upload.
+/// `POST /admin/uploads/{file_id}/signed-url` - issue signed download URL.
+/// `POST /admin/uploads/{file_id}/truncate` - remove bytes after the given offset.
+/// `POST /admin/uploads/{file_id}/copy` - copy a finished upload into a new one.
+/// `POST /admin/uploads/{file_id}/restore` - restore terminated upload from the trash.
+/// `PATCH /admin/uploads/{file_id}/metadata` - update metadata keys of an upload.
+/// `POST /admin/import` - create finished upload from a local file.
+/// `GET /admin/orphans` - find uploads whose data or information is missing.
+/// `GET /admin/drain` - check if drain mode is enabled.
+/// `PUT /admin/drain` - stop accepting new uploads.
+/// `DELETE /admin/drain` - accept new uploads again.
+/// `GET /admin/cleanup/pause` - check if background removal of uploads is paused.
+/// `PUT /admin/cleanup/pause` - pause background removal of uploads.
+/// `DELETE /admin/cleanup/pause` - resume background removal of uploads.
+/// `GET /admin/hooks` - get hooks recorded by debug notifier.
+/// `DELETE /admin/hooks` - remove recorded hooks.
+#[allow(clippy::module_name_repetitions, clippy::too_many_lines)]
+pub fn admin_service(state: State) -> impl Fn(&mut web::ServiceConfig) {
+    move |web_app| {
+        web_app.service(
+            web::scope("/admin")
+                .app_data(web::Data::new(state.clone()))
+                // Only operators with the admin token can use the API.
+                .wrap_fn({
+                    let config = state.config.clone();
+                    move |req, srv| {
+                        let call = match authorize(&config, &req) {
+                            Ok(()) => Ok(srv.call(req)),
+                            Err(err) => Err(req.error_response(err)),
+                        };
+                        async move {
+                            match call {
+                                Ok(fut) => Ok(fut.await?.map_into_boxed_body()),
+                                Err(response) => Ok(response),
+                            }
+                        }
+                    }
+                })
+                .service(
+                    web::resource("/tenants/{tenant}/usage")
+                        .name("admin:tenant_usage")
+                        .guard(guard::Get())
+                        .to(routes::tenant_usage),
+                )
+                .service(
+                    web::resource("/uploads")
+                        .name("admin:list_uploads")
+                        .guard(guard::Get())
+                        .to(routes::list_uploads),
+                )
+                .service(
+                    web::resource("/uploads/{file_id}")
+                        .name("admin:upload_info")
+                        .guard(guard::Get())
+                        .to(routes::upload_info),
+                )
+                .service(
+                    web::resource("/uploads/{file_id}/signed-url")
+                        .name("admin:signed_url")
+                        .guard(guard::Post())
+                        .to(routes::signed_url),
+                )
+                .service(
+                    web::resource("/uploads/{file_id}/truncate")
+                        .name("admin:truncate")
+                        .guard(guard::Post())
+                        .to(routes::truncate),
+                )
+                .service(
+                    web::resource("/uploads/{file_id}/copy")
+                        .name("admin:copy_upload")
+                        .guard(guard::Post())
+                        .to(routes::copy_upload),
+                )
+                .service(
+                    web::resource("/uploads/{file_id}/restore")
+                        .name("admin:restore")
+                        .guard(guard::Post())
+                        .to(routes::restore),
+                )
+                .service(
+                    web::resource("/uploads/{file_id}/metadata")
+                        .name("admin:update_metadata")
+                        .guard(guard::Patch())
+                        .to(routes::update_metadata),
+                )
+                .service(
+                    web::resource("/import")
+                        .name("admin:import")
+                        .guard(guard::Post())
+                        .to(routes::import_file),
+                )
+                .service(
+                    web::resource("/orphans")
+                        .name("admin:orphans")
+                        .guard(guard::Get())
+                        .to(routes::orphans),
+                )
+                .service(
+                    web::resource("/drain")
+                        .name("admin:drain")
+                        .route(web::get().to(routes::drain_status))
+                        .route(web::put().to(routes::enable_drain))
+                        .route(web::delete().to(routes::disable_drain)),
+                )
+                .service(
+                    web::resource("/cleanup/pause")
+                        .name("admin:cleanup_pause")
+                        .route(web::get().to(routes::cleanup_status))
+                        .route(web::put().to(routes::pause_cleanup))
+                        .route(web::delete().to(routes::resume_cleanup)),
+                )
+                .service(
+                    web::resource("/hooks")
+                        .name("admin:debug_hooks")
+                        .route(web::get().to(routes::debug_hooks))
+                        .route(web::delete().to(routes::clear_debug_hooks)),
+                ),
+        );
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::admin_service;
+    use crate::state::State;
+    use actix_web::{dev::ServiceResponse, test::init_service, App};
+
+    pub async fn get_admin_service(
+        state: State,
+    ) -> impl actix_web::dev::Service<
+        actix_http::Request,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+    > {
+        init_service(App::new().configure(admin_service(state))).await
+    }
+}
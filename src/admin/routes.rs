@@ -0,0 +1,1363 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    background,
+    errors::RustusError,
+    info_storages::{FileInfo, UploadFilter},
+    utils::{
+        durability,
+        import::{self, ImportSource},
+        metadata, orphans, pre_create, quota, signature, trash,
+        unique::{self, Uniqueness},
+    },
+    RustusResult, State,
+};
+
+/// Find uploads which match the filter.
+///
+/// Filter is passed in query parameters.
+pub async fn list_uploads(
+    query: web::Query<UploadFilter>,
+    state: web::Data<State>,
+) -> RustusResult<HttpResponse> {
+    let uploads = state.info_storage.list_files(&query).await?;
+    Ok(HttpResponse::Ok().json(json!({ "uploads": uploads })))
+}
+
+/// Get information about the upload with location of its data.
+///
+/// Location of the data is sensitive,
+/// so it's returned only if admin API is protected by a token.
+pub async fn upload_info(
+    request: HttpRequest,
+    state: web::Data<State>,
+) -> RustusResult<HttpResponse> {
+    let file_id = request
+        .match_info()
+        .get("file_id")
+        .ok_or(RustusError::FileNotFound)?;
+    let file_info = state.info_storage.get_info(file_id).await?;
+    if file_info.storage != state.data_storage.to_string() {
+        return Err(RustusError::FileNotFound);
+    }
+    let mut body = serde_json::to_value(&file_info)?;
+    if state.config.admin_token.is_some() {
+        body["location"] = json!(state.data_storage.data_location(&file_info));
+    }
+    Ok(HttpResponse::Ok().json(body))
+}
+
+#[derive(Deserialize)]
+pub struct SignedUrlQuery {
+    /// Lifetime of the URL in seconds.
+    ttl: Option<u64>,
+}
+
+/// Get current usage of a tenant.
+///
+/// Usage is calculated from all known uploads,
+/// including unfinished ones.
+pub async fn tenant_usage(
+    request: HttpRequest,
+    state: web::Data<State>,
+) -> RustusResult<HttpResponse> {
+    let tenant = request
+        .match_info()
+        .get("tenant")
+        .ok_or(RustusError::FileNotFound)?;
+    let usage = quota::tenant_usage(
+        state.info_storage.as_ref(),
+        state.config.tenant_metadata_key.as_str(),
+        tenant,
+    )
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "tenant": tenant,
+        "usage": usage,
+        "quota": state.config.tenant_quota(tenant),
+    })))
+}
+
+/// Issue signed download URL for a finished upload.
+///
+/// URL expires after `ttl` seconds.
+/// If `ttl` isn't set, default lifetime is used.
+pub async fn signed_url(
+    request: HttpRequest,
+    query: web::Query<SignedUrlQuery>,
+    state: web::Data<State>,
+) -> RustusResult<HttpResponse> {
+    let Some(secret) = &state.config.download_signing_secret else {
+        return Ok(HttpResponse::NotFound().body("Signed URLs are disabled."));
+    };
+    let file_id = request
+        .match_info()
+        .get("file_id")
+        .ok_or(RustusError::FileNotFound)?;
+    let file_info = state.info_storage.get_info(file_id).await?;
+    if file_info.length != Some(file_info.offset) {
+        return Ok(HttpResponse::BadRequest().body("Upload isn't finished."));
+    }
+    let ttl = query.ttl.unwrap_or(state.config.signed_url_ttl);
+    let expires = i64::try_from(ttl)
+        .ok()
+        .and_then(|ttl| chrono::Utc::now().timestamp().checked_add(ttl));
+    let Some(expires) = expires else {
+        return Ok(HttpResponse::BadRequest().body("TTL is too big."));
+    };
+    let path = format!(
+        "/{}/{}/?expires={}&signature={}",
+        state.config.base_url(),
+        file_info.id,
+        expires,
+        signature::sign(secret, file_info.id.as_str(), expires),
+    );
+    let url = if state.config.relative_location {
+        path
+    } else {
+        let conn_info = request.connection_info();
+        format!("{}://{}{}", conn_info.scheme(), conn_info.host(), path)
+    };
+    Ok(HttpResponse::Ok().json(json!({
+        "url": url,
+        "expires": expires,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct TruncateRequest {
+    /// New offset of the upload.
+    offset: usize,
+    /// New length of the upload.
+    ///
+    /// It's used if the client declared bigger length than needed.
+    length: Option<usize>,
+}
+
+/// Truncate data of an unfinished upload.
+///
+/// Bytes after the new offset are removed,
+/// so the client can send them again.
+/// Chunks of the upload are rejected until it's done.
+pub async fn truncate(
+    request: HttpRequest,
+    body: web::Json<TruncateRequest>,
+    state: web::Data<State>,
+) -> RustusResult<HttpResponse> {
+    let file_id = request
+        .match_info()
+        .get("file_id")
+        .ok_or(RustusError::FileNotFound)?;
+    let Some(_guard) = state.active_chunks.acquire_exclusive(file_id) else {
+        return Ok(HttpResponse::Conflict().body("Upload is being written."));
+    };
+    let mut file_info = state.info_storage.get_info(file_id).await?;
+    if file_info.storage != state.data_storage.to_string() {
+        return Err(RustusError::FileNotFound);
+    }
+    if file_info.is_final || file_info.length == Some(file_info.offset) {
+        return Ok(HttpResponse::BadRequest().body("Finished uploads can't be truncated."));
+    }
+    if state.data_storage.accepts_out_of_order() {
+        return Ok(HttpResponse::BadRequest().body("Storage doesn't support truncation."));
+    }
+    if body.offset > file_info.offset {
+        return Ok(HttpResponse::BadRequest().body(format!(
+            "Offset can't be greater than current offset {}.",
+            file_info.offset
+        )));
+    }
+    if let Some(length) = body.length {
+        // Upload with length equal to offset would be finished without hooks.
+        if length <= body.offset {
+            return Ok(HttpResponse::BadRequest().body("Length must be greater than offset."));
+        }
+        if file_info.length.is_some_and(|current| length > current) {
+            return Ok(HttpResponse::BadRequest().body("Length can only be decreased."));
+        }
+    }
+    if let Some(alignment) = state.data_storage.chunk_alignment() {
+        if !body.offset.is_multiple_of(alignment) {
+            return Ok(HttpResponse::BadRequest().body(format!(
+                "Upload can be truncated only at multiples of {alignment} bytes."
+            )));
+        }
+    }
+    if let Some(encryption) = &mut file_info.encryption {
+        // MAC of remaining bytes can't be computed without the key.
+        if body.offset < file_info.offset {
+            if body.offset > 0 {
+                return Ok(HttpResponse::BadRequest()
+                    .body("Encrypted uploads can be truncated only to the beginning."));
+            }
+            encryption.reset()?;
+        }
+    }
+    file_info.offset = body.offset;
+    // Truncated chunks must be written again if they are retried.
+    file_info.chunk_tokens.clear();
+    if let Some(length) = body.length {
+        file_info.length = Some(length);
+        file_info.deferred_size = false;
+    }
+    // Offset is saved before data is truncated, so stale bytes are never
+    // followed by new chunks. If truncation is interrupted,
+    // it's finished by the next chunk or the next truncation.
+    file_info.truncating = true;
+    state.info_storage.save_info(&mut file_info).await?;
+    durability::finish_truncation(&state, &mut file_info).await?;
+    log::info!(
+        "Upload {} was truncated to {} bytes.",
+        file_info.id,
+        file_info.offset
+    );
+    Ok(HttpResponse::Ok().json(json!({
+        "id": file_info.id,
+        "offset": file_info.offset,
+        "length": file_info.length,
+    })))
+}
+
+/// Update metadata keys of an upload.
+///
+/// Keys with `null` values are removed, other keys are kept.
+/// Information is updated only if it wasn't changed since
+/// it was read, so concurrent updates of different keys aren't lost.
+/// Stale updates are retried until they succeed.
+pub async fn update_metadata(
+    request: HttpRequest,
+    body: web::Json<HashMap<String, Option<String>>>,
+    state: web::Data<State>,
+) -> RustusResult<HttpResponse> {
+    let file_id = request
+        .match_info()
+        .get("file_id")
+        .ok_or(RustusError::FileNotFound)?;
+    let values = metadata::transform(
+        body.iter()
+            .filter_map(|(key, value)| Some((key.clone(), value.clone()?)))
+            .collect(),
+        state.config.metadata_normalizers.as_slice(),
+        state.config.metadata_aliases.as_slice(),
+    );
+    loop {
+        let mut file_info = state.info_storage.get_info(file_id).await?;
+        if file_info.storage != state.data_storage.to_string() {
+            return Err(RustusError::FileNotFound);
+        }
+        file_info
+            .metadata
+            .retain(|key, _| body.get(key).is_none_or(Option::is_some));
+        file_info.metadata.extend(values.clone());
+        match state.info_storage.update_info(&mut file_info).await {
+            Ok(()) => return Ok(HttpResponse::Ok().json(&file_info)),
+            Err(RustusError::VersionConflict(_)) => {
+                log::debug!("Information about upload {file_id} was changed, retrying.");
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Restore terminated upload from the trash.
+///
+/// It's possible only until grace period of the upload is over.
+pub async fn restore(request: HttpRequest, state: web::Data<State>) -> RustusResult<HttpResponse> {
+    let file_id = request
+        .match_info()
+        .get("file_id")
+        .ok_or(RustusError::FileNotFound)?;
+    let file_info = trash::restore(&state, file_id).await?;
+    log::info!("Upload {} was restored from the trash.", file_info.id);
+    Ok(HttpResponse::Ok().json(&file_info))
+}
+
+#[derive(Deserialize)]
+pub struct ImportRequest {
+    /// Path to the imported file.
+    path: PathBuf,
+    /// Metadata of the new upload.
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+    /// Link the file instead of copying it.
+    #[serde(default)]
+    hard_link: bool,
+}
+
+/// Copy data of the imported file and save the upload.
+///
+/// Data is removed if the upload can't be saved.
+async fn import_data(
+    state: &State,
+    file_info: &mut FileInfo,
+    source: &ImportSource,
+    hard_link: bool,
+) -> RustusResult<()> {
+    state.data_storage.create_upload(file_info).await?;
+    let mut result = state
+        .data_storage
+        .import_file(file_info, source, hard_link)
+        .await;
+    if result.is_ok() {
+        file_info.offset = file_info.length.unwrap_or_default();
+        result = durability::verify_size(state, file_info).await;
+    }
+    if result.is_ok() {
+        result = state.info_storage.set_info(file_info, true).await;
+    }
+    if result.is_err() {
+        state.data_storage.remove_file(file_info).await.ok();
+    }
+    result
+}
+
+/// Create finished upload from a local file.
+///
+/// The file must be in one of import directories.
+/// Relative paths are resolved in these directories.
+/// Pre-create hook, unique metadata and quotas are checked
+/// and post-finish hook is sent as for uploaded files.
+pub async fn import_file(
+    request: HttpRequest,
+    body: web::Json<ImportRequest>,
+    state: web::Data<State>,
+) -> RustusResult<HttpResponse> {
+    let Some(token) = &state.config.import_token else {
+        return Ok(HttpResponse::NotFound().body("Import is disabled."));
+    };
+    if state.config.import_dirs.is_empty() {
+        return Ok(HttpResponse::NotFound().body("Import is disabled."));
+    }
+    let auth = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !import::check_token(token, auth) {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+    if state.is_draining() {
+        return Err(RustusError::Draining(state.config.drain_retry_after));
+    }
+    // Missing files get the same response,
+    // so it doesn't tell which files exist.
+    let Some(source) = import::resolve_source(&state.config.import_dirs, body.path.as_path())
+    else {
+        return Ok(HttpResponse::Forbidden().body("File can't be imported."));
+    };
+    // Size is taken from the opened file, which is the one copied.
+    let length = usize::try_from(source.file.metadata()?.len())
+        .map_err(|err| RustusError::UnableToWrite(err.to_string()))?;
+    if length == 0 && !state.config.allow_empty {
+        return Ok(HttpResponse::BadRequest().body("Empty files can't be imported."));
+    }
+    if let Some(max_file_size) = state.config.max_file_size {
+        if length > max_file_size {
+            return Ok(HttpResponse::BadRequest().body(format!(
+                "File size should be less than or equal to {max_file_size}"
+            )));
+        }
+    }
+    let mut meta = metadata::transform(
+        body.metadata.clone(),
+        state.config.metadata_normalizers.as_slice(),
+        state.config.metadata_aliases.as_slice(),
+    );
+    metadata::assign_server_metadata(state.config.server_metadata.as_slice(), &mut meta);
+    if let Some(key) = metadata::mismatched_key(&meta, state.config.metadata_patterns.as_slice()) {
+        return Ok(
+            HttpResponse::BadRequest().body(format!("Metadata value of `{key}` isn't allowed."))
+        );
+    }
+    let mut file_info = FileInfo::new(
+        uuid::Uuid::new_v4().to_string().as_str(),
+        Some(length),
+        None,
+        state.data_storage.to_string(),
+        Some(meta),
+    );
+    // Imported uploads are created as uploads of clients are.
+    pre_create::send(&state, &request, &mut file_info).await?;
+    if file_info.length != Some(length) {
+        return Ok(HttpResponse::BadRequest().body("Length of imported upload can't be changed."));
+    }
+    let _unique = match unique::check(&state, &file_info).await? {
+        Uniqueness::Unique(guard) => guard,
+        Uniqueness::Duplicate(response) => return Ok(response),
+    };
+    if !quota::reserve(&state, &file_info, length).await? {
+        return Ok(HttpResponse::PayloadTooLarge().body("Quota of the tenant is exceeded."));
+    }
+    if let Err(err) = import_data(&state, &mut file_info, &source, body.hard_link).await {
+        quota::release(&state, &file_info, length);
+        return Err(err);
+    }
+    durability::sync_finished(&state, &file_info).await?;
+    log::info!(
+        "File {} was imported as upload {}.",
+        source.path.display(),
+        file_info.id
+    );
+    background::notify_finished(&state, &request, &file_info);
+    Ok(HttpResponse::Created().json(json!({
+        "id": file_info.id,
+        "offset": file_info.offset,
+        "length": file_info.length,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct CopyRequest {
+    /// Metadata of the copy.
+    ///
+    /// Metadata of the source is used if it's not set.
+    #[serde(default)]
+    metadata: Option<HashMap<String, String>>,
+}
+
+/// Copy data of the source and save the copy.
+///
+/// Data is removed if the copy can't be saved.
+async fn copy_data(state: &State, source: &FileInfo, file_info: &mut FileInfo) -> RustusResult<()> {
+    state.data_storage.create_upload(file_info).await?;
+    let mut result = state.data_storage.copy_file(source, file_info).await;
+    if result.is_ok() {
+        file_info.offset = source.offset;
+        result = durability::verify_size(state, file_info).await;
+    }
+    if result.is_ok() {
+        result = state.info_storage.set_info(file_info, true).await;
+    }
+    if result.is_err() {
+        state.data_storage.remove_file(file_info).await.ok();
+    }
+    result
+}
+
+/// Copy a finished upload into a new upload.
+///
+/// Data is copied by the storage, so clients
+/// don't have to download and upload it again.
+/// Unique metadata and quotas are checked
+/// and post-finish hook is sent as for uploaded files.
+pub async fn copy_upload(
+    request: HttpRequest,
+    body: web::Json<CopyRequest>,
+    state: web::Data<State>,
+) -> RustusResult<HttpResponse> {
+    let file_id = request
+        .match_info()
+        .get("file_id")
+        .ok_or(RustusError::FileNotFound)?;
+    // Source can't be moved by compaction while it's copied.
+    let Some(_guard) = state.active_chunks.acquire_exclusive(file_id) else {
+        return Ok(HttpResponse::Conflict().body("Upload is being written."));
+    };
+    let source = state.info_storage.get_info(file_id).await?;
+    if source.storage != state.data_storage.to_string() {
+        return Err(RustusError::FileNotFound);
+    }
+    if source.length != Some(source.offset) {
+        return Ok(HttpResponse::BadRequest().body("Only finished uploads can be copied."));
+    }
+    let mut meta = match &body.metadata {
+        Some(meta) => metadata::transform(
+            meta.clone(),
+            state.config.metadata_normalizers.as_slice(),
+            state.config.metadata_aliases.as_slice(),
+        ),
+        None => source.metadata.clone(),
+    };
+    // Copy is a new upload, so server metadata is assigned again.
+    metadata::assign_server_metadata(state.config.server_metadata.as_slice(), &mut meta);
+    if let Some(key) = metadata::mismatched_key(&meta, state.config.metadata_patterns.as_slice()) {
+        return Ok(
+            HttpResponse::BadRequest().body(format!("Metadata value of `{key}` isn't allowed."))
+        );
+    }
+    let mut file_info = FileInfo::new(
+        uuid::Uuid::new_v4().to_string().as_str(),
+        source.length,
+        None,
+        state.data_storage.to_string(),
+        Some(meta),
+    );
+    // Data is copied as is, so it's decrypted with the same key.
+    file_info.encryption = source.encryption.clone();
+    let _unique = match unique::check(&state, &file_info).await? {
+        Uniqueness::Unique(guard) => guard,
+        Uniqueness::Duplicate(response) => return Ok(response),
+    };
+    let length = source.offset;
+    if !quota::reserve(&state, &file_info, length).await? {
+        return Ok(HttpResponse::PayloadTooLarge().body("Quota of the tenant is exceeded."));
+    }
+    if let Err(err) = copy_data(&state, &source, &mut file_info).await {
+        quota::release(&state, &file_info, length);
+        return Err(err);
+    }
+    durability::sync_finished(&state, &file_info).await?;
+    log::info!(
+        "Upload {} was copied to upload {}.",
+        source.id,
+        file_info.id
+    );
+    background::notify_finished(&state, &request, &file_info);
+    Ok(HttpResponse::Created().json(json!({
+        "id": file_info.id,
+        "offset": file_info.offset,
+        "length": file_info.length,
+    })))
+}
+
+/// Find uploads whose data or information is missing.
+///
+/// It reads all uploads, so it may take a while.
+pub async fn orphans(state: web::Data<State>) -> RustusResult<HttpResponse> {
+    Ok(HttpResponse::Ok().json(orphans::scan(&state).await?))
+}
+
+/// Check if drain mode is enabled.
+#[allow(clippy::unused_async)]
+pub async fn drain_status(state: web::Data<State>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "draining": state.is_draining() }))
+}
+
+/// Enable drain mode.
+///
+/// New uploads are rejected with 503,
+/// while existing uploads can be finished.
+#[allow(clippy::unused_async)]
+pub async fn enable_drain(state: web::Data<State>) -> HttpResponse {
+    state.set_draining(true);
+    log::info!("Drain mode is enabled. New uploads are rejected.");
+    HttpResponse::Ok().json(json!({ "draining": true }))
+}
+
+/// Disable drain mode.
+#[allow(clippy::unused_async)]
+pub async fn disable_drain(state: web::Data<State>) -> HttpResponse {
+    state.set_draining(false);
+    log::info!("Drain mode is disabled.");
+    HttpResponse::Ok().json(json!({ "draining": false }))
+}
+
+/// Check if background removal of uploads is paused.
+#[allow(clippy::unused_async)]
+pub async fn cleanup_status(state: web::Data<State>) -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "paused": state.cleanup.is_paused() }))
+}
+
+/// Pause background removal of uploads.
+///
+/// Response is sent after running removals are finished,
+/// so no uploads are removed after it.
+pub async fn pause_cleanup(state: web::Data<State>) -> HttpResponse {
+    state.cleanup.pause().await;
+    log::info!("Background removal of uploads is paused.");
+    HttpResponse::Ok().json(json!({ "paused": true }))
+}
+
+/// Resume background removal of uploads.
+#[allow(clippy::unused_async)]
+pub async fn resume_cleanup(state: web::Data<State>) -> HttpResponse {
+    state.cleanup.resume();
+    log::info!("Background removal of uploads is resumed.");
+    HttpResponse::Ok().json(json!({ "paused": false }))
+}
+
+/// Get hooks recorded by debug notifier.
+#[allow(clippy::unused_async)]
+pub async fn debug_hooks(state: web::Data<State>) -> HttpResponse {
+    match state.notification_manager.debug_notifier() {
+        Some(notifier) => HttpResponse::Ok().json(notifier.records()),
+        None => HttpResponse::NotFound().body("Debug hooks are disabled."),
+    }
+}
+
+/// Remove all hooks recorded by debug notifier.
+#[allow(clippy::unused_async)]
+pub async fn clear_debug_hooks(state: web::Data<State>) -> HttpResponse {
+    match state.notification_manager.debug_notifier() {
+        Some(notifier) => {
+            notifier.clear();
+            HttpResponse::NoContent().finish()
+        }
+        None => HttpResponse::NotFound().body("Debug hooks are disabled."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        admin::test::get_admin_service,
+        errors::RustusError,
+        notifiers::Hook,
+        utils::metadata::{MetadataPattern, ServerMetadata},
+        NotificationManager, State,
+    };
+    use actix_web::{
+        http::{header::HeaderMap, StatusCode},
+        test::{call_service, read_body_json, TestRequest},
+    };
+    use serde_json::{json, Value};
+    use std::{path::PathBuf, str::FromStr};
+
+    #[actix_rt::test]
+    async fn success() {
+        let state = State::test_new().await;
+        let mut file_info = state.create_test_file().await;
+        file_info.metadata.insert("tenant".into(), "acme".into());
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        state.create_test_file().await;
+        let rustus = get_admin_service(state.clone()).await;
+        let request = TestRequest::get()
+            .uri("/admin/tenants/acme/usage")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body["usage"], 10);
+        assert!(body["quota"].is_null());
+    }
+
+    #[actix_rt::test]
+    async fn admin_token() {
+        let mut state = State::test_new().await;
+        state.config.admin_token = Some(String::from("admin"));
+        let rustus = get_admin_service(state.clone()).await;
+        for token in [None, Some("Bearer wrong")] {
+            let mut request = TestRequest::get().uri("/admin/uploads");
+            if let Some(token) = token {
+                request = request.insert_header(("Authorization", token));
+            }
+            let resp = call_service(&rustus, request.to_request()).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        }
+        let request = TestRequest::get()
+            .uri("/admin/uploads")
+            .insert_header(("Authorization", "Bearer admin"))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn upload_info() {
+        let mut state = State::test_new().await;
+        let file_info = state.create_test_file().await;
+        let rustus = get_admin_service(state.clone()).await;
+        let request = TestRequest::get()
+            .uri(format!("/admin/uploads/{}", file_info.id).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body["id"], file_info.id.as_str());
+        // Location isn't returned without the admin token.
+        assert!(body.get("location").is_none());
+
+        state.config.admin_token = Some(String::from("admin"));
+        let rustus = get_admin_service(state.clone()).await;
+        let request = TestRequest::get()
+            .uri(format!("/admin/uploads/{}", file_info.id).as_str())
+            .insert_header(("Authorization", "Bearer admin"))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body["location"]["storage"], "file_storage");
+        assert_eq!(body["location"]["path"], file_info.path.unwrap().as_str());
+        assert!(body["location"].get("bucket").is_none());
+        let request = TestRequest::get()
+            .uri("/admin/uploads/unknown")
+            .insert_header(("Authorization", "Bearer admin"))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn list_uploads() {
+        let state = State::test_new().await;
+        let mut finished = state.create_test_file().await;
+        finished.offset = 10;
+        finished.metadata.insert("tenant".into(), "acme".into());
+        state.info_storage.set_info(&finished, false).await.unwrap();
+        let mut unfinished = state.create_test_file().await;
+        unfinished.metadata.insert("tenant".into(), "acme".into());
+        state
+            .info_storage
+            .set_info(&unfinished, false)
+            .await
+            .unwrap();
+        state.create_test_file().await;
+        let rustus = get_admin_service(state.clone()).await;
+        let request = TestRequest::get()
+            .uri("/admin/uploads?metadata=tenant%3Dacme&status=incomplete")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = read_body_json(resp).await;
+        let uploads = body["uploads"].as_array().unwrap();
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0]["id"], unfinished.id.as_str());
+        let request = TestRequest::get()
+            .uri("/admin/uploads?limit=2")
+            .to_request();
+        let body: Value = read_body_json(call_service(&rustus, request).await).await;
+        assert_eq!(body["uploads"].as_array().unwrap().len(), 2);
+        let request = TestRequest::get()
+            .uri("/admin/uploads?status=unknown")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn debug_hooks() {
+        let mut state = State::test_new().await;
+        state.config.notification_opts.hooks_debug = true;
+        state.notification_manager = NotificationManager::new(&state.config).await.unwrap();
+        state
+            .notification_manager
+            .send_message(
+                "{\"id\": \"memes\"}".into(),
+                Hook::PostCreate,
+                &HeaderMap::new(),
+            )
+            .await
+            .unwrap();
+        let rustus = get_admin_service(state.clone()).await;
+        let request = TestRequest::get().uri("/admin/hooks").to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body[0]["hook"], "post-create");
+        assert_eq!(body[0]["message"]["id"], "memes");
+
+        let request = TestRequest::delete().uri("/admin/hooks").to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let records = state
+            .notification_manager
+            .debug_notifier()
+            .unwrap()
+            .records();
+        assert!(records.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn debug_hooks_disabled() {
+        let state = State::test_new().await;
+        let rustus = get_admin_service(state).await;
+        let request = TestRequest::get().uri("/admin/hooks").to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn signed_url() {
+        let mut state = State::test_new().await;
+        state.config.download_signing_secret = Some(String::from("secret"));
+        let mut file_info = state.create_test_file().await;
+        file_info.offset = 10;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let rustus = get_admin_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(format!("/admin/uploads/{}/signed-url?ttl=60", file_info.id).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = read_body_json(resp).await;
+        let expires = body["expires"].as_i64().unwrap();
+        assert!(expires <= chrono::Utc::now().timestamp() + 60);
+        let url = body["url"].as_str().unwrap();
+        assert!(url.contains(state.config.file_url(file_info.id.as_str()).as_str()));
+        assert!(url.contains(format!("expires={expires}").as_str()));
+        assert!(url.contains("signature="));
+    }
+
+    #[actix_rt::test]
+    async fn signed_url_unfinished_upload() {
+        let mut state = State::test_new().await;
+        state.config.download_signing_secret = Some(String::from("secret"));
+        let file_info = state.create_test_file().await;
+        let rustus = get_admin_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(format!("/admin/uploads/{}/signed-url", file_info.id).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn signed_url_ttl_overflow() {
+        let mut state = State::test_new().await;
+        state.config.download_signing_secret = Some(String::from("secret"));
+        let mut file_info = state.create_test_file().await;
+        file_info.offset = 10;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let rustus = get_admin_service(state.clone()).await;
+        for ttl in [u64::MAX, i64::MAX.unsigned_abs()] {
+            let request = TestRequest::post()
+                .uri(format!("/admin/uploads/{}/signed-url?ttl={ttl}", file_info.id).as_str())
+                .to_request();
+            let resp = call_service(&rustus, request).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn signed_url_disabled() {
+        let state = State::test_new().await;
+        let file_info = state.create_test_file().await;
+        let rustus = get_admin_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(format!("/admin/uploads/{}/signed-url", file_info.id).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn orphans() {
+        let state = State::test_new().await;
+        let without_data = state.create_test_file().await;
+        std::fs::remove_file(without_data.path.as_deref().unwrap()).unwrap();
+        let without_info = state.create_test_file().await;
+        state
+            .info_storage
+            .remove_info(without_info.id.as_str())
+            .await
+            .unwrap();
+        let rustus = get_admin_service(state.clone()).await;
+        let request = TestRequest::get().uri("/admin/orphans").to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body["missing_data"][0], without_data.id);
+        assert_eq!(body["missing_info"][0], without_info.path.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn toggle_drain() {
+        let state = State::test_new().await;
+        let rustus = get_admin_service(state.clone()).await;
+        let request = TestRequest::put().uri("/admin/drain").to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(state.is_draining());
+        let request = TestRequest::get().uri("/admin/drain").to_request();
+        let body: Value = read_body_json(call_service(&rustus, request).await).await;
+        assert_eq!(body["draining"], true);
+        let request = TestRequest::delete().uri("/admin/drain").to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(!state.is_draining());
+    }
+
+    #[actix_rt::test]
+    async fn toggle_cleanup() {
+        let state = State::test_new().await;
+        let rustus = get_admin_service(state.clone()).await;
+        let request = TestRequest::put().uri("/admin/cleanup/pause").to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(state.cleanup.start().await.is_none());
+        let request = TestRequest::get().uri("/admin/cleanup/pause").to_request();
+        let body: Value = read_body_json(call_service(&rustus, request).await).await;
+        assert_eq!(body["paused"], true);
+        let request = TestRequest::delete()
+            .uri("/admin/cleanup/pause")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(!state.cleanup.is_paused());
+    }
+
+    async fn written_upload(state: &State) -> crate::info_storages::FileInfo {
+        let mut file_info = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&file_info, bytes::Bytes::from("memes"))
+            .await
+            .unwrap();
+        file_info.offset = 5;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        file_info
+    }
+
+    fn truncate_request(file_id: &str, body: Value) -> actix_http::Request {
+        TestRequest::post()
+            .uri(format!("/admin/uploads/{file_id}/truncate").as_str())
+            .set_json(body)
+            .to_request()
+    }
+
+    #[actix_rt::test]
+    async fn truncate() {
+        let state = State::test_new().await;
+        let file_info = written_upload(&state).await;
+        let rustus = get_admin_service(state.clone()).await;
+        let request = truncate_request(
+            file_info.id.as_str(),
+            serde_json::json!({"offset": 2, "length": 8}),
+        );
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body["offset"], 2);
+        assert_eq!(body["length"], 8);
+        let new_info = state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .unwrap();
+        assert_eq!(new_info.offset, 2);
+        assert_eq!(new_info.length, Some(8));
+        assert_eq!(
+            std::fs::read_to_string(new_info.path.unwrap()).unwrap(),
+            "me"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn restore_upload() {
+        let mut state = State::test_new().await;
+        state.enable_test_trash(3600).await;
+        let file_info = written_upload(&state).await;
+        let tus = crate::server::test::get_service(state.clone()).await;
+        let request = TestRequest::delete()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&tus, request).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert!(state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .is_err());
+
+        let rustus = get_admin_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(format!("/admin/uploads/{}/restore", file_info.id).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body["id"], file_info.id.as_str());
+        assert_eq!(body["offset"], 5);
+        // Both data and information are back.
+        let request = TestRequest::default()
+            .method(actix_web::http::Method::HEAD)
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&tus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let request = TestRequest::post()
+            .uri(format!("/admin/uploads/{}/restore", file_info.id).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn update_metadata() {
+        let state = State::test_new().await;
+        let mut file_info = state.create_test_file().await;
+        file_info
+            .metadata
+            .insert("filename".into(), "cat.png".into());
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let rustus = get_admin_service(state.clone()).await;
+        let request = TestRequest::patch()
+            .uri(format!("/admin/uploads/{}/metadata", file_info.id).as_str())
+            .set_json(json!({"scan": "clean"}))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body["version"], 1);
+
+        // Update made with stale information is rejected.
+        let err = state
+            .info_storage
+            .update_info(&mut file_info.clone())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RustusError::VersionConflict(_)));
+
+        let request = TestRequest::patch()
+            .uri(format!("/admin/uploads/{}/metadata", file_info.id).as_str())
+            .set_json(json!({"thumbnail": "thumb.png", "filename": null}))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let info = state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .unwrap();
+        assert_eq!(info.version, 2);
+        assert_eq!(info.metadata.get("scan").unwrap(), "clean");
+        assert_eq!(info.metadata.get("thumbnail").unwrap(), "thumb.png");
+        assert!(info.metadata.get("filename").is_none());
+
+        // Writers of the upload keep updated metadata.
+        let mut writer = info.clone();
+        let request = TestRequest::patch()
+            .uri(format!("/admin/uploads/{}/metadata", file_info.id).as_str())
+            .set_json(json!({"scan": "infected"}))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        writer.offset = 5;
+        state.info_storage.save_info(&mut writer).await.unwrap();
+        let info = state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .unwrap();
+        assert_eq!(info.offset, 5);
+        assert_eq!(info.metadata.get("scan").unwrap(), "infected");
+    }
+
+    #[actix_rt::test]
+    async fn truncate_beyond_offset() {
+        let state = State::test_new().await;
+        let file_info = written_upload(&state).await;
+        let rustus = get_admin_service(state.clone()).await;
+        for body in [
+            serde_json::json!({"offset": 6}),
+            serde_json::json!({"offset": 2, "length": 20}),
+            serde_json::json!({"offset": 2, "length": 2}),
+        ] {
+            let resp = call_service(&rustus, truncate_request(file_info.id.as_str(), body)).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+        let new_info = state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .unwrap();
+        assert_eq!(new_info.offset, 5);
+    }
+
+    #[actix_rt::test]
+    async fn truncate_while_writing() {
+        let state = State::test_new().await;
+        let file_info = written_upload(&state).await;
+        let rustus = get_admin_service(state.clone()).await;
+        let chunk = state
+            .active_chunks
+            .acquire(file_info.id.as_str(), 1)
+            .unwrap();
+        let request = truncate_request(file_info.id.as_str(), serde_json::json!({"offset": 0}));
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        drop(chunk);
+        let request = truncate_request(file_info.id.as_str(), serde_json::json!({"offset": 0}));
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(state.active_chunks.count(file_info.id.as_str()), 0);
+    }
+
+    #[actix_rt::test]
+    async fn truncate_finished() {
+        let state = State::test_new().await;
+        let mut file_info = written_upload(&state).await;
+        file_info.length = Some(5);
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let rustus = get_admin_service(state.clone()).await;
+        let request = truncate_request(file_info.id.as_str(), serde_json::json!({"offset": 0}));
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// Upload with all its bytes written.
+    async fn finished_upload(state: &State) -> crate::info_storages::FileInfo {
+        let mut file_info = written_upload(state).await;
+        file_info.length = Some(5);
+        file_info
+            .metadata
+            .insert("filename".into(), "memes.txt".into());
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        file_info
+    }
+
+    fn copy_request(file_id: &str, body: Value) -> actix_http::Request {
+        TestRequest::post()
+            .uri(format!("/admin/uploads/{file_id}/copy").as_str())
+            .set_json(body)
+            .to_request()
+    }
+
+    #[actix_rt::test]
+    async fn copy_upload() {
+        let mut state = State::test_new().await;
+        state.config.notification_opts.hooks_debug = true;
+        state.notification_manager = NotificationManager::new(&state.config).await.unwrap();
+        let source = finished_upload(&state).await;
+        let rustus = get_admin_service(state.clone()).await;
+        let body = serde_json::json!({"metadata": {"filename": "branch.txt"}});
+        let resp = call_service(&rustus, copy_request(source.id.as_str(), body)).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body: Value = read_body_json(resp).await;
+        assert_ne!(body["id"], source.id);
+        assert_eq!(body["offset"], 5);
+        let copy = state
+            .info_storage
+            .get_info(body["id"].as_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(copy.length, Some(5));
+        assert_eq!(copy.metadata["filename"], "branch.txt");
+        assert_ne!(copy.path, source.path);
+        let contents = std::fs::read_to_string(copy.path.unwrap()).unwrap();
+        assert_eq!(contents, "memes");
+        let contents = std::fs::read_to_string(source.path.unwrap()).unwrap();
+        assert_eq!(contents, "memes");
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let records = state
+            .notification_manager
+            .debug_notifier()
+            .unwrap()
+            .records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].hook, "post-finish");
+    }
+
+    #[actix_rt::test]
+    async fn copy_keeps_metadata() {
+        let state = State::test_new().await;
+        let source = finished_upload(&state).await;
+        let rustus = get_admin_service(state.clone()).await;
+        let resp = call_service(
+            &rustus,
+            copy_request(source.id.as_str(), serde_json::json!({})),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body: Value = read_body_json(resp).await;
+        let copy = state
+            .info_storage
+            .get_info(body["id"].as_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(copy.metadata, source.metadata);
+    }
+
+    #[actix_rt::test]
+    async fn copy_creation_checks() {
+        let mut state = State::test_new().await;
+        state.config.default_tenant_quota = Some(4);
+        state.config.unique_metadata_key = Some(String::from("filename"));
+        state.config.metadata_patterns =
+            vec![MetadataPattern::from_str(r"filename=[\w.-]+").unwrap()];
+        let source = finished_upload(&state).await;
+        let rustus = get_admin_service(state.clone()).await;
+        let files = || {
+            std::fs::read_dir(&state.config.storage_opts.data_dir)
+                .unwrap()
+                .count()
+        };
+        let stored = files();
+        let copy =
+            |metadata: Value| copy_request(source.id.as_str(), json!({ "metadata": metadata }));
+        let resp = call_service(&rustus, copy(json!({"filename": "memes.txt"}))).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let resp = call_service(&rustus, copy(json!({"filename": "me mes.txt"}))).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        // Tenant has no space for 5 bytes.
+        let other = json!({"filename": "other.txt", "tenant": "acme"});
+        let resp = call_service(&rustus, copy(other)).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(files(), stored);
+    }
+
+    #[actix_rt::test]
+    async fn copy_unfinished() {
+        let state = State::test_new().await;
+        let file_info = written_upload(&state).await;
+        let rustus = get_admin_service(state.clone()).await;
+        let request = copy_request(file_info.id.as_str(), serde_json::json!({}));
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let request = copy_request("unknown", serde_json::json!({}));
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// State with import from a temporary directory.
+    async fn import_state() -> (State, tempdir::TempDir) {
+        let mut state = State::test_new().await;
+        let dir = tempdir::TempDir::new("import").unwrap();
+        std::fs::create_dir(dir.path().join("allowed")).unwrap();
+        std::fs::write(dir.path().join("allowed/file"), "memes").unwrap();
+        std::fs::write(dir.path().join("secret"), "memes").unwrap();
+        state.config.import_dirs = vec![dir.path().join("allowed")];
+        state.config.import_token = Some(String::from("token"));
+        (state, dir)
+    }
+
+    fn import_request(token: &str, body: Value) -> actix_http::Request {
+        TestRequest::post()
+            .uri("/admin/import")
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .set_json(body)
+            .to_request()
+    }
+
+    #[actix_rt::test]
+    async fn import_file() {
+        let (mut state, dir) = import_state().await;
+        state.config.server_metadata = vec![ServerMetadata::from_str("origin=node-1").unwrap()];
+        state.config.notification_opts.hooks_debug = true;
+        state.notification_manager = NotificationManager::new(&state.config).await.unwrap();
+        let rustus = get_admin_service(state.clone()).await;
+        let body = serde_json::json!({
+            "path": dir.path().join("allowed/file"),
+            "metadata": {"filename": "memes.txt"},
+        });
+        let resp = call_service(&rustus, import_request("token", body)).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body: Value = read_body_json(resp).await;
+        assert_eq!(body["offset"], 5);
+        let file_info = state
+            .info_storage
+            .get_info(body["id"].as_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(file_info.length, Some(5));
+        assert_eq!(file_info.metadata["filename"], "memes.txt");
+        assert_eq!(file_info.metadata["origin"], "node-1");
+        let contents = std::fs::read_to_string(file_info.path.unwrap()).unwrap();
+        assert_eq!(contents, "memes");
+        // Source is kept.
+        assert!(dir.path().join("allowed/file").exists());
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let records = state
+            .notification_manager
+            .debug_notifier()
+            .unwrap()
+            .records();
+        let hooks: Vec<_> = records.iter().map(|record| record.hook.as_str()).collect();
+        assert_eq!(hooks, ["pre-create", "post-finish"]);
+    }
+
+    #[actix_rt::test]
+    async fn import_unauthorized() {
+        let (mut state, dir) = import_state().await;
+        let body = serde_json::json!({"path": dir.path().join("allowed/file")});
+        let rustus = get_admin_service(state.clone()).await;
+        let resp = call_service(&rustus, import_request("other", body.clone())).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        state.config.import_token = None;
+        let rustus = get_admin_service(state.clone()).await;
+        let resp = call_service(&rustus, import_request("token", body)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn import_outside_dirs() {
+        let (state, dir) = import_state().await;
+        let rustus = get_admin_service(state.clone()).await;
+        let data_file = state.create_test_file().await.path.unwrap();
+        // Missing files can't be told from forbidden ones.
+        for path in [
+            PathBuf::from(data_file),
+            dir.path().join("allowed/../secret"),
+            PathBuf::from("../secret"),
+            dir.path().join("allowed"),
+            dir.path().join("allowed/unknown"),
+        ] {
+            let body = serde_json::json!({ "path": path });
+            let resp = call_service(&rustus, import_request("token", body)).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn import_relative_path() {
+        let (state, _dir) = import_state().await;
+        let rustus = get_admin_service(state.clone()).await;
+        let body = serde_json::json!({"path": "file"});
+        let resp = call_service(&rustus, import_request("token", body)).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+
+    #[actix_rt::test]
+    async fn import_creation_checks() {
+        let (mut state, dir) = import_state().await;
+        state.config.default_tenant_quota = Some(4);
+        state.config.unique_metadata_key = Some(String::from("filename"));
+        let rustus = get_admin_service(state.clone()).await;
+        let body = |metadata: Value| {
+            serde_json::json!({
+                "path": dir.path().join("allowed/file"),
+                "metadata": metadata,
+            })
+        };
+        let memes = json!({"filename": "memes.txt"});
+        let resp = call_service(&rustus, import_request("token", body(memes.clone()))).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let resp = call_service(&rustus, import_request("token", body(memes))).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        // Tenant has no space for 5 bytes.
+        let other = json!({"filename": "other.txt", "tenant": "acme"});
+        let resp = call_service(&rustus, import_request("token", body(other))).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_rt::test]
+    async fn import_pre_create_hook() {
+        let (mut state, dir) = import_state().await;
+        state.config.notification_opts.hooks = vec![Hook::PreCreate];
+        state.config.notification_opts.hooks_debug = true;
+        state.notification_manager = NotificationManager::new(&state.config).await.unwrap();
+        let rustus = get_admin_service(state.clone()).await;
+        let body = serde_json::json!({"path": dir.path().join("allowed/file")});
+        let resp = call_service(&rustus, import_request("token", body)).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let records = state
+            .notification_manager
+            .debug_notifier()
+            .unwrap()
+            .records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].hook, "pre-create");
+    }
+}
@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use actix_web::http::header::HeaderMap;
+use log::{debug, error, info};
+
+use crate::{
+    background::{eviction, partials},
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    State,
+};
+
+/// Remove uploads which were created, but never written.
+///
+/// Clients often create uploads and abandon them
+/// before the first chunk, so these uploads are removed
+/// much earlier than uploads which make progress.
+pub async fn run(state: State, ttl: u64) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.empty_cleanup_interval));
+    loop {
+        interval.tick().await;
+        let Some(_permit) = state.cleanup.start().await else {
+            continue;
+        };
+        match expire(&state, ttl).await {
+            Ok(0) => {}
+            Ok(removed) => info!("Removed {removed} abandoned empty uploads."),
+            Err(err) => error!("Cannot remove abandoned empty uploads: {err}"),
+        }
+    }
+}
+
+/// Check if no bytes were written to the upload.
+///
+/// Uploads of zero length are finished, so they are kept.
+fn is_empty(upload: &FileInfo) -> bool {
+    upload.offset == 0 && upload.received.is_empty() && upload.length != Some(0) && !upload.is_final
+}
+
+/// Remove empty uploads which weren't written for `ttl` seconds.
+///
+/// Parts of unfinished final uploads and uploads
+/// whose first chunk is being written are skipped.
+/// Returns number of removed uploads.
+pub async fn expire(state: &State, ttl: u64) -> RustusResult<usize> {
+    let uploads = state.info_storage.list_info().await?;
+    let referenced = partials::referenced_parts(uploads.as_slice());
+    let ttl = chrono::Duration::seconds(i64::try_from(ttl).unwrap_or(i64::MAX / 1000));
+    let now = chrono::Utc::now();
+    let storage_name = state.data_storage.to_string();
+    let headers = HeaderMap::new();
+    let mut removed = 0;
+    for upload in uploads {
+        if state.cleanup.is_paused() {
+            break;
+        }
+        if !is_empty(&upload)
+            || upload.storage != storage_name
+            || now - upload.last_modified() <= ttl
+            || referenced.contains(&upload.id)
+        {
+            continue;
+        }
+        if remove_abandoned(state, &upload, ttl, &headers).await? {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Remove the listed upload if it's still empty.
+///
+/// The upload could be written since it was listed,
+/// so its information is read again once it's locked.
+async fn remove_abandoned(
+    state: &State,
+    listed: &FileInfo,
+    ttl: chrono::Duration,
+    headers: &HeaderMap,
+) -> RustusResult<bool> {
+    let Some(_guard) = state.active_chunks.acquire_exclusive(listed.id.as_str()) else {
+        debug!("Empty upload {} is being written.", listed.id);
+        return Ok(false);
+    };
+    let upload = match state.info_storage.get_info(listed.id.as_str()).await {
+        Ok(upload) => upload,
+        Err(RustusError::FileNotFound) => return Ok(false),
+        Err(err) => return Err(err),
+    };
+    if !is_empty(&upload) || chrono::Utc::now() - upload.last_modified() <= ttl {
+        return Ok(false);
+    }
+    eviction::remove(state, &upload, headers).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expire, remove_abandoned};
+    use crate::State;
+
+    #[actix_rt::test]
+    async fn expire_empty() {
+        let state = State::test_new().await;
+        let mut abandoned = state.create_test_file().await;
+        let mut fresh = state.create_test_file().await;
+        let mut started = state.create_test_file().await;
+        let mut zero_length = state.create_test_file().await;
+        let mut writing = state.create_test_file().await;
+        for upload in [
+            &mut abandoned,
+            &mut fresh,
+            &mut started,
+            &mut zero_length,
+            &mut writing,
+        ] {
+            upload.created_at -= chrono::Duration::minutes(10);
+        }
+        fresh.created_at = chrono::Utc::now();
+        started.offset = 1;
+        zero_length.length = Some(0);
+        for upload in [&abandoned, &fresh, &started, &zero_length, &writing] {
+            state.info_storage.set_info(upload, false).await.unwrap();
+        }
+        let guard = state
+            .active_chunks
+            .acquire(writing.id.as_str(), usize::MAX)
+            .unwrap();
+        assert_eq!(expire(&state, 300).await.unwrap(), 1);
+        let exists = |id: String| {
+            let state = state.clone();
+            async move { state.info_storage.get_info(id.as_str()).await.is_ok() }
+        };
+        assert!(!exists(abandoned.id.clone()).await);
+        assert!(exists(fresh.id.clone()).await);
+        assert!(exists(started.id.clone()).await);
+        assert!(exists(zero_length.id.clone()).await);
+        assert!(exists(writing.id.clone()).await);
+        drop(guard);
+        assert_eq!(expire(&state, 300).await.unwrap(), 1);
+        assert!(!exists(writing.id.clone()).await);
+    }
+
+    #[actix_rt::test]
+    async fn written_after_listing() {
+        let state = State::test_new().await;
+        let mut listed = state.create_test_file().await;
+        listed.created_at -= chrono::Duration::minutes(10);
+        let mut written = listed.clone();
+        written.offset = 1;
+        state.info_storage.set_info(&written, false).await.unwrap();
+        let headers = actix_web::http::header::HeaderMap::new();
+        let ttl = chrono::Duration::seconds(300);
+        assert!(!remove_abandoned(&state, &listed, ttl, &headers)
+            .await
+            .unwrap());
+        assert!(state
+            .info_storage
+            .get_info(listed.id.as_str())
+            .await
+            .is_ok());
+    }
+}
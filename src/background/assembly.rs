@@ -0,0 +1,242 @@
+use actix_web::{web, HttpRequest};
+use derive_more::{Display, From};
+use log::{error, info, warn};
+use strum::EnumIter;
+
+use crate::{
+    background::{checksum, notify_finished},
+    errors::{RustusError, RustusResult},
+    from_str,
+    info_storages::FileInfo,
+    metrics::RustusMetrics,
+    utils::{active_chunks::ChunkGuard, durability},
+    State,
+};
+
+/// Response to HEAD requests of final uploads which are being assembled.
+#[allow(clippy::module_name_repetitions)]
+#[derive(PartialEq, Eq, Debug, Display, EnumIter, From, Clone, Copy)]
+pub enum AssemblyResponse {
+    /// Report the length of the final upload
+    /// and the size of concatenated data as its offset.
+    #[display(fmt = "progress")]
+    Progress,
+    /// Respond with `425 Too Early` until the upload is assembled.
+    #[display(fmt = "too-early")]
+    TooEarly,
+}
+
+from_str!(AssemblyResponse, "assembly response");
+
+/// Concatenate parts into the final upload.
+///
+/// Parts are removed only if the final upload has the expected size.
+pub async fn concat_parts(
+    state: &State,
+    file_info: &mut FileInfo,
+    parts_info: Vec<FileInfo>,
+) -> RustusResult<()> {
+    state
+        .data_storage
+        .concat_files(file_info, parts_info.clone())
+        .await?;
+    let final_size = parts_info.iter().filter_map(|part| part.length).sum();
+    file_info.offset = final_size;
+    file_info.length = Some(final_size);
+    // Parts are kept, so the final upload can be created again.
+    if let Err(err) = durability::verify_size(state, file_info).await {
+        state.data_storage.remove_file(file_info).await.ok();
+        return Err(err);
+    }
+    if state.config.remove_parts {
+        for part in parts_info {
+            state.data_storage.remove_file(&part).await?;
+            state.info_storage.remove_info(part.id.as_str()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Assemble the final upload and mark it as finished.
+///
+/// Checksum is computed before the upload is finished,
+/// so clients never see finished uploads without it.
+async fn assemble(
+    state: &State,
+    file_info: &mut FileInfo,
+    parts_info: Vec<FileInfo>,
+) -> RustusResult<()> {
+    concat_parts(state, file_info, parts_info).await?;
+    if state.config.upload_checksum {
+        checksum::add_checksum(state, file_info, None).await;
+    }
+    file_info.assembling = false;
+    finish_assembly(state, file_info).await?;
+    durability::sync_finished(state, file_info).await
+}
+
+/// Save information about the assembled upload.
+///
+/// Information is read again, so the upload
+/// removed during assembly isn't brought back.
+/// Concurrent updates are retried.
+async fn finish_assembly(state: &State, file_info: &mut FileInfo) -> RustusResult<()> {
+    loop {
+        let mut current = state.info_storage.get_info(file_info.id.as_str()).await?;
+        if !current.assembling {
+            // The upload was failed and created again.
+            return Err(RustusError::FileNotFound);
+        }
+        current.offset = file_info.offset;
+        current.length = file_info.length;
+        current.checksum = file_info.checksum.clone();
+        current.assembling = false;
+        match state.info_storage.update_info(&mut current).await {
+            Err(RustusError::VersionConflict(_)) => continue,
+            result => result?,
+        }
+        *file_info = current;
+        return Ok(());
+    }
+}
+
+/// Fail assemblies interrupted by a restart.
+///
+/// Assembly isn't resumed, since parts are locked
+/// only while it runs. Final uploads are removed,
+/// so clients can create them again.
+///
+/// # Errors
+///
+/// Returns an error if uploads cannot be listed.
+pub async fn fail_interrupted(state: &State) -> RustusResult<()> {
+    let storage_name = state.data_storage.to_string();
+    for file_info in state.info_storage.list_info().await? {
+        if !file_info.assembling || file_info.storage != storage_name {
+            continue;
+        }
+        info!("Assembly of upload {} was interrupted.", file_info.id);
+        if let Err(err) = state.data_storage.remove_file(&file_info).await {
+            if !matches!(err, RustusError::FileNotFound) {
+                warn!("Cannot remove data of upload {}: {}", file_info.id, err);
+                continue;
+            }
+        }
+        if let Err(err) = state.info_storage.remove_info(file_info.id.as_str()).await {
+            warn!("Cannot remove upload {}: {}", file_info.id, err);
+        }
+    }
+    Ok(())
+}
+
+/// Assemble the final upload in background.
+///
+/// Parts are locked until they are concatenated.
+/// If assembly fails, the final upload is removed,
+/// so it can be created again.
+pub fn spawn_assembly(
+    state: &web::Data<State>,
+    metrics: &web::Data<RustusMetrics>,
+    request: &HttpRequest,
+    file_info: &FileInfo,
+    parts_info: Vec<FileInfo>,
+    parts_guards: Vec<ChunkGuard>,
+) {
+    let state = state.clone();
+    let metrics = metrics.clone();
+    let request = request.clone();
+    let mut file_info = file_info.clone();
+    tokio::task::spawn_local(async move {
+        let result = assemble(&state, &mut file_info, parts_info).await;
+        drop(parts_guards);
+        if let Err(err) = result {
+            error!("Cannot assemble upload {}: {}", file_info.id, err);
+            state.data_storage.remove_file(&file_info).await.ok();
+            state
+                .info_storage
+                .remove_info(file_info.id.as_str())
+                .await
+                .ok();
+            return;
+        }
+        metrics.observe_finished(&file_info);
+        notify_finished(&state, &request, &file_info);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fail_interrupted, finish_assembly};
+    use crate::{errors::RustusError, State};
+    use std::path::PathBuf;
+
+    #[actix_rt::test]
+    async fn removed_during_assembly() {
+        let state = State::test_new().await;
+        let mut file_info = state.create_test_file().await;
+        file_info.is_final = true;
+        file_info.assembling = true;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        state
+            .info_storage
+            .remove_info(file_info.id.as_str())
+            .await
+            .unwrap();
+        let err = finish_assembly(&state, &mut file_info).await.unwrap_err();
+        assert!(matches!(err, RustusError::FileNotFound));
+        assert!(state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .is_err());
+    }
+
+    #[actix_rt::test]
+    async fn finished_assembly() {
+        let state = State::test_new().await;
+        let mut file_info = state.create_test_file().await;
+        file_info.is_final = true;
+        file_info.assembling = true;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        file_info.offset = 10;
+        file_info.assembling = false;
+        finish_assembly(&state, &mut file_info).await.unwrap();
+        let stored = state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .unwrap();
+        assert!(!stored.assembling);
+        assert_eq!(stored.offset, 10);
+    }
+
+    #[actix_rt::test]
+    async fn interrupted_assembly() {
+        let state = State::test_new().await;
+        let valid = state.create_test_file().await;
+        let mut interrupted = state.create_test_file().await;
+        interrupted.is_final = true;
+        interrupted.assembling = true;
+        state
+            .info_storage
+            .set_info(&interrupted, false)
+            .await
+            .unwrap();
+        fail_interrupted(&state).await.unwrap();
+        assert!(state
+            .info_storage
+            .get_info(interrupted.id.as_str())
+            .await
+            .is_err());
+        assert!(!PathBuf::from(interrupted.path.unwrap()).exists());
+        assert!(state.info_storage.get_info(valid.id.as_str()).await.is_ok());
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt::Write;
+
+use actix_web::{web, HttpRequest};
+use digest::Digest;
+use futures::StreamExt;
+use log::{debug, warn};
+
+use crate::{
+    errors::RustusResult,
+    info_storages::FileInfo,
+    notifiers::Hook,
+    utils::{
+        encryption::{self, UploadKey},
+        hashes::Hasher,
+    },
+    State,
+};
+
+/// Hash contents of the upload.
+///
+/// Contents are streamed from the storage,
+/// so the whole file is never loaded in memory.
+/// If `limit` is set, only this many first bytes are hashed.
+/// Encrypted uploads are hashed after decryption,
+/// so the checksum is computed for the original contents.
+///
+/// Returns number of hashed bytes.
+pub async fn hash_contents(
+    state: &State,
+    file_info: &FileInfo,
+    key: Option<&UploadKey>,
+    hasher: &mut Hasher,
+    limit: Option<usize>,
+) -> RustusResult<usize> {
+    let end = limit.unwrap_or(file_info.offset);
+    let mut body =
+        encryption::read_data(state.data_storage.as_ref(), file_info, key, 0..end).await?;
+    let mut total = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        hasher.update(chunk.as_ref());
+        total += chunk.len();
+    }
+    Ok(total)
+}
+
+/// Compute sha256 checksum of the upload.
+///
+/// # Errors
+///
+/// Returns an error if contents of the upload can't be read.
+pub async fn compute(
+    state: &State,
+    file_info: &FileInfo,
+    key: Option<&UploadKey>,
+) -> RustusResult<String> {
+    let mut hasher = Hasher::Sha256(sha2::Sha256::new());
+    hash_contents(state, file_info, key, &mut hasher, None).await?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        }))
+}
+
+/// Compute checksum of finished upload in background.
+///
+/// Post-finish hook is sent after the checksum is saved,
+/// so it's included in the hook's payload.
+#[allow(clippy::module_name_repetitions)]
+pub fn spawn_checksum(
+    state: &web::Data<State>,
+    file_info: &FileInfo,
+    request: &HttpRequest,
+    key: Option<UploadKey>,
+) {
+    let state = state.clone();
+    let mut file_info = file_info.clone();
+    let request = request.clone();
+    tokio::task::spawn_local(async move {
+        add_checksum(&state, &mut file_info, key.as_ref()).await;
+        if !state.config.hook_is_active(Hook::PostFinish) {
+            return;
+        }
+        let message = state.config.notification_opts.hooks_format.format(
+            &request,
+            &file_info,
+            &state.config.client_ip,
+        );
+        state
+            .notification_manager
+            .deliver_upload(message, Hook::PostFinish, &file_info, request.headers())
+            .await;
+    });
+}
+
+/// Compute checksum of finished upload and save it.
+///
+/// Errors are logged and the checksum is left empty,
+/// since they must not prevent hooks from being sent.
+/// Encrypted uploads can't be hashed without the key.
+pub async fn add_checksum(state: &State, file_info: &mut FileInfo, key: Option<&UploadKey>) {
+    if file_info.encryption.is_some() && key.is_none() {
+        debug!(
+            "Checksum of encrypted upload {} isn't computed without the key.",
+            file_info.id
+        );
+        return;
+    }
+    match compute(state, file_info, key).await {
+        Ok(checksum) => file_info.checksum = Some(checksum),
+        Err(err) => {
+            warn!(
+                "Cannot compute checksum of upload {}: {}",
+                file_info.id, err
+            );
+            return;
+        }
+    }
+    save(state, file_info).await;
+}
+
+/// Save computed checksum of the upload.
+///
+/// Errors are logged, since the checksum can be computed again.
+pub async fn save(state: &State, file_info: &FileInfo) {
+    // Upload might have been removed while checksum was computed.
+    let Ok(mut current) = state.info_storage.get_info(file_info.id.as_str()).await else {
+        return;
+    };
+    current.checksum = file_info.checksum.clone();
+    if let Err(err) = state.info_storage.save_info(&mut current).await {
+        warn!("Cannot save checksum of upload {}: {}", file_info.id, err);
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use tokio::sync::{RwLock, RwLockReadGuard};
+
+/// Switch which pauses background removal of uploads.
+///
+/// Removal tasks run only while they hold a permit,
+/// so pausing waits until running removals are finished.
+/// It's shared between all workers.
+#[derive(Clone, Default)]
+pub struct CleanupSwitch {
+    paused: Arc<AtomicBool>,
+    running: Arc<RwLock<()>>,
+}
+
+impl CleanupSwitch {
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Get a permit to remove uploads.
+    ///
+    /// Returns `None` if removal is paused.
+    pub async fn start(&self) -> Option<RwLockReadGuard<'_, ()>> {
+        let permit = self.running.read().await;
+        if self.is_paused() {
+            return None;
+        }
+        Some(permit)
+    }
+
+    /// Pause removal of uploads.
+    ///
+    /// It returns after all running removals are finished.
+    pub async fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+        drop(self.running.write().await);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CleanupSwitch;
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn pause_waits_for_removal() {
+        let switch = CleanupSwitch::default();
+        let permit = switch.start().await.unwrap();
+        let pausing = switch.clone();
+        let paused = tokio::spawn(async move { pausing.pause().await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // Running removal isn't interrupted.
+        assert!(switch.is_paused());
+        assert!(!paused.is_finished());
+        drop(permit);
+        paused.await.unwrap();
+        assert!(switch.start().await.is_none());
+        switch.resume();
+        assert!(switch.start().await.is_some());
+    }
+}
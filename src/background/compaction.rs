@@ -0,0 +1,256 @@
+use std::time::Duration;
+
+use log::{error, info, warn};
+
+use crate::{errors::RustusResult, info_storages::FileInfo, State};
+
+/// Parse fraction of removed data which triggers compaction.
+pub fn parse_threshold(input: &str) -> Result<f64, String> {
+    input
+        .parse::<f64>()
+        .ok()
+        .filter(|threshold| (0.0..=1.0).contains(threshold))
+        .ok_or_else(|| format!("'{input}' is not a number between 0 and 1"))
+}
+
+/// Metrics of the compaction task.
+#[derive(Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct CompactionMetrics {
+    pub reclaimed_bytes: prometheus::IntCounter,
+    /// Unix timestamp of the last finished compaction.
+    pub last_compaction: prometheus::IntGauge,
+}
+
+impl CompactionMetrics {
+    pub fn register(&self, registry: &prometheus::Registry) -> RustusResult<()> {
+        registry.register(Box::new(self.reclaimed_bytes.clone()))?;
+        registry.register(Box::new(self.last_compaction.clone()))?;
+        Ok(())
+    }
+}
+
+impl Default for CompactionMetrics {
+    fn default() -> Self {
+        // Names of metrics are valid, so they are always created.
+        Self {
+            reclaimed_bytes: prometheus::IntCounter::new(
+                "compaction_reclaimed_bytes",
+                "Number of bytes reclaimed by compaction",
+            )
+            .unwrap(),
+            last_compaction: prometheus::IntGauge::new(
+                "last_compaction_timestamp",
+                "Time of the last compaction",
+            )
+            .unwrap(),
+        }
+    }
+}
+
+/// Periodically reclaim space of removed uploads.
+pub async fn run(state: State, interval: u64) {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval));
+    loop {
+        interval.tick().await;
+        let Some(_permit) = state.cleanup.start().await else {
+            continue;
+        };
+        match compact(&state).await {
+            Ok(0) => {}
+            Ok(reclaimed) => info!("Compaction reclaimed {reclaimed} bytes."),
+            Err(err) => error!("Cannot compact storages: {err}"),
+        }
+    }
+}
+
+/// Compact data and info storages.
+///
+/// Only finished uploads are moved, since they are never
+/// written again. While they are moved, no chunks can be written
+/// to them. Old data is removed after the upload is updated,
+/// so the upload can be read at any time.
+///
+/// Returns number of reclaimed bytes.
+pub async fn compact(state: &State) -> RustusResult<u64> {
+    let threshold = state.config.storage_opts.compaction_threshold;
+    let storage_name = state.data_storage.to_string();
+    let mut uploads = Vec::new();
+    let mut guards = Vec::new();
+    for upload in state.info_storage.list_info().await? {
+        if upload.length != Some(upload.offset) || upload.storage != storage_name {
+            continue;
+        }
+        if let Some(guard) = state.active_chunks.acquire_exclusive(upload.id.as_str()) {
+            guards.push(guard);
+            uploads.push(upload);
+        }
+    }
+    let compaction = state.data_storage.compact(&uploads, threshold).await?;
+    for (upload, path) in compaction.moved {
+        if let Err(err) = relocate(state, &upload, path.as_str()).await {
+            warn!("Cannot move upload {}: {err}", upload.id);
+        }
+    }
+    drop(guards);
+    let reclaimed = compaction.reclaimed + state.info_storage.compact(threshold).await?;
+    state.compaction_metrics.reclaimed_bytes.inc_by(reclaimed);
+    state
+        .compaction_metrics
+        .last_compaction
+        .set(chrono::Utc::now().timestamp());
+    Ok(reclaimed)
+}
+
+/// Point the upload to its new path and remove old data.
+///
+/// New path is always in the main backend of the storage.
+/// If the upload was changed while it was moved,
+/// its copy is removed instead.
+pub(super) async fn relocate(state: &State, upload: &FileInfo, path: &str) -> RustusResult<()> {
+    let mut copy = upload.clone();
+    copy.path = Some(String::from(path));
+    copy.backend = None;
+    let current = match state.info_storage.get_info(upload.id.as_str()).await {
+        Ok(current) if current.path == upload.path && current.backend == upload.backend => current,
+        _ => return state.data_storage.remove_file(&copy).await,
+    };
+    let mut moved = current;
+    moved.path = copy.path.clone();
+    moved.backend = None;
+    if let Err(err) = state.info_storage.update_info(&mut moved).await {
+        state.data_storage.remove_file(&copy).await?;
+        return Err(err);
+    }
+    state.data_storage.remove_file(upload).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compact, parse_threshold};
+    use crate::{
+        info_storages::FileInfo,
+        storages::{file_storage::FileStorage, packed_storage::PackedStorage},
+        State, Storage,
+    };
+    use bytes::Bytes;
+
+    async fn packed_state() -> State {
+        let mut state = State::test_new().await;
+        let data_dir = state.config.storage_opts.data_dir.clone();
+        let files = FileStorage::new(data_dir.clone(), String::new(), false);
+        let mut storage = PackedStorage::new(files, data_dir.as_path(), 10, 10, false);
+        storage.prepare().await.unwrap();
+        state.data_storage = Box::new(storage);
+        state
+    }
+
+    async fn upload(state: &State, data: &str) -> FileInfo {
+        let mut file_info = FileInfo::new(
+            uuid::Uuid::new_v4().to_string().as_str(),
+            Some(data.len()),
+            None,
+            state.data_storage.to_string(),
+            None,
+        );
+        state
+            .data_storage
+            .create_upload(&mut file_info)
+            .await
+            .unwrap();
+        state
+            .data_storage
+            .add_bytes(&file_info, Bytes::from(String::from(data)))
+            .await
+            .unwrap();
+        file_info.offset = data.len();
+        state.info_storage.set_info(&file_info, true).await.unwrap();
+        file_info
+    }
+
+    fn pack(file_info: &FileInfo) -> std::path::PathBuf {
+        let path = file_info.path.as_deref().unwrap();
+        std::path::PathBuf::from(path.rsplit_once('#').unwrap().0)
+    }
+
+    async fn remove(state: &State, file_info: &FileInfo) {
+        state
+            .info_storage
+            .remove_info(file_info.id.as_str())
+            .await
+            .unwrap();
+        state.data_storage.remove_file(file_info).await.unwrap();
+    }
+
+    async fn contents(state: &State, file_info: &FileInfo) -> Bytes {
+        let request = actix_web::test::TestRequest::get().to_http_request();
+        let response = state
+            .data_storage
+            .get_contents(file_info, &request)
+            .await
+            .unwrap();
+        actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn thresholds() {
+        assert_eq!(parse_threshold("0.3"), Ok(0.3));
+        assert!(parse_threshold("1.5").is_err());
+        assert!(parse_threshold("memes").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn fragmented_pack() {
+        let state = packed_state().await;
+        let removed = upload(&state, "memes").await;
+        let kept = upload(&state, "test").await;
+        // Next upload starts a new pack.
+        let active = upload(&state, "active").await;
+        let old_pack = pack(&kept);
+        assert_ne!(old_pack, pack(&active));
+        remove(&state, &removed).await;
+
+        let reclaimed = compact(&state).await.unwrap();
+        assert_eq!(reclaimed, 5);
+        assert!(!old_pack.exists());
+        let moved = state.info_storage.get_info(kept.id.as_str()).await.unwrap();
+        assert_eq!(pack(&moved), pack(&active));
+        assert_eq!(contents(&state, &moved).await, "test");
+        assert_eq!(state.compaction_metrics.reclaimed_bytes.get(), 5);
+        assert!(state.compaction_metrics.last_compaction.get() > 0);
+    }
+
+    #[actix_rt::test]
+    async fn active_uploads_are_kept() {
+        let mut state = packed_state().await;
+        state.config.storage_opts.compaction_threshold = 0.1;
+        let removed = upload(&state, "memes").await;
+        let writing = upload(&state, "test").await;
+        upload(&state, "active").await;
+        remove(&state, &removed).await;
+        // Upload which is being written can't be moved.
+        let _guard = state.active_chunks.acquire(writing.id.as_str(), 1).unwrap();
+        assert_eq!(compact(&state).await.unwrap(), 0);
+        let info = state
+            .info_storage
+            .get_info(writing.id.as_str())
+            .await
+            .unwrap();
+        assert_eq!(info.path, writing.path);
+        assert!(pack(&writing).exists());
+    }
+
+    #[actix_rt::test]
+    async fn threshold_not_reached() {
+        let mut state = packed_state().await;
+        state.config.storage_opts.compaction_threshold = 0.9;
+        let removed = upload(&state, "memes").await;
+        let kept = upload(&state, "test").await;
+        upload(&state, "active").await;
+        remove(&state, &removed).await;
+        assert_eq!(compact(&state).await.unwrap(), 0);
+        assert!(pack(&kept).exists());
+    }
+}
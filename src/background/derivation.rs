@@ -0,0 +1,267 @@
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use actix_web::{web, HttpRequest};
+use log::{debug, error, info, warn};
+use tokio::process::Command;
+
+use crate::{
+    background::{self, export},
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    utils::{durability, import::ImportSource, metadata},
+    State,
+};
+
+/// Metadata key which links derived upload to its source.
+///
+/// It's set only by rustus, values sent by clients are removed.
+pub const DERIVED_FROM_KEY: &str = "derived_from";
+
+/// Remove the link to the source from metadata sent by a client.
+///
+/// Otherwise clients could skip derivation of their uploads
+/// or pretend that uploads were derived from others.
+pub fn strip_derived_from(metadata: &mut HashMap<String, String>) {
+    metadata.remove(DERIVED_FROM_KEY);
+}
+
+/// Start derivation of a file from finished upload in background.
+///
+/// It does nothing if derive command isn't configured.
+/// Derived, partial and encrypted uploads are skipped.
+pub fn spawn_derivation(state: &web::Data<State>, file_info: &FileInfo, request: &HttpRequest) {
+    if state.config.storage_opts.derive_command.is_none()
+        || file_info.is_partial
+        || file_info.encryption.is_some()
+        || file_info.metadata.contains_key(DERIVED_FROM_KEY)
+    {
+        return;
+    }
+    tokio::task::spawn_local(run(state.clone(), file_info.clone(), request.clone()));
+}
+
+/// Derive a file from the upload with retries.
+///
+/// Failed derivations never affect the source upload.
+async fn run(state: web::Data<State>, file_info: FileInfo, request: HttpRequest) {
+    let Some(command) = state.config.storage_opts.derive_command.clone() else {
+        return;
+    };
+    // Derivations wait for their turn, so temporary copies
+    // of uploads don't fill the disk.
+    let Ok(_permit) = state.derivations.acquire().await else {
+        return;
+    };
+    let retries = state.config.storage_opts.derive_retries;
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+        }
+        match derive(&state, &file_info, command.as_str()).await {
+            Ok(Some(derived)) => {
+                info!(
+                    "Upload {} was derived from upload {}.",
+                    derived.id, file_info.id
+                );
+                background::notify_finished(&state, &request, &derived);
+                return;
+            }
+            Ok(None) => {
+                debug!("Nothing was derived from upload {}.", file_info.id);
+                return;
+            }
+            Err(err) => warn!(
+                "Cannot derive a file from upload {}. Attempt: {}. Reason: {}",
+                file_info.id,
+                attempt + 1,
+                err
+            ),
+        }
+    }
+    error!(
+        "Derivation from upload {} failed after {} retries.",
+        file_info.id, retries
+    );
+}
+
+/// Run derive command in a temporary directory
+/// and create a new upload from its output.
+///
+/// Returns `None` if the command didn't create the output file.
+async fn derive(
+    state: &State,
+    file_info: &FileInfo,
+    command: &str,
+) -> RustusResult<Option<FileInfo>> {
+    let work_dir =
+        std::env::temp_dir().join(format!("rustus-derive-{}", uuid::Uuid::new_v4().simple()));
+    tokio::fs::create_dir_all(work_dir.as_path()).await?;
+    let derived = derive_in(state, file_info, command, work_dir.as_path()).await;
+    if let Err(err) = tokio::fs::remove_dir_all(work_dir.as_path()).await {
+        warn!("Cannot remove directory {}: {}", work_dir.display(), err);
+    }
+    derived
+}
+
+async fn derive_in(
+    state: &State,
+    file_info: &FileInfo,
+    command: &str,
+    work_dir: &Path,
+) -> RustusResult<Option<FileInfo>> {
+    let source = work_dir.join("source");
+    let output = work_dir.join("output");
+    export::write_contents(state, file_info, None, source.as_path()).await?;
+    debug!("Running command: {}", command);
+    let timeout = state.config.storage_opts.derive_timeout;
+    // Command is killed if it doesn't finish in time.
+    let status = Command::new(command)
+        .arg(file_info.id.as_str())
+        .arg(source.as_path())
+        .arg(output.as_path())
+        .arg(serde_json::to_string(file_info)?)
+        .kill_on_drop(true)
+        .status();
+    let status = tokio::time::timeout(Duration::from_secs(timeout), status)
+        .await
+        .map_err(|_| {
+            RustusError::DerivationFailed(format!("command didn't finish in {timeout} seconds"))
+        })??;
+    if !status.success() {
+        return Err(RustusError::DerivationFailed(format!(
+            "command returned {status}"
+        )));
+    }
+    let length = match tokio::fs::metadata(output.as_path()).await {
+        Ok(meta) => usize::try_from(meta.len())
+            .map_err(|err| RustusError::DerivationFailed(err.to_string()))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    if length == 0 {
+        return Ok(None);
+    }
+    // Derived upload belongs to the owner and the tenant of the source.
+    let mut meta = [
+        state.config.owner_metadata_key.as_str(),
+        state.config.tenant_metadata_key.as_str(),
+    ]
+    .into_iter()
+    .filter_map(|key| Some((String::from(key), file_info.metadata.get(key)?.clone())))
+    .collect::<HashMap<_, _>>();
+    metadata::assign_server_metadata(state.config.server_metadata.as_slice(), &mut meta);
+    meta.insert(String::from(DERIVED_FROM_KEY), file_info.id.clone());
+    let mut derived = FileInfo::new(
+        uuid::Uuid::new_v4().to_string().as_str(),
+        Some(length),
+        None,
+        state.data_storage.to_string(),
+        Some(meta),
+    );
+    state.data_storage.create_upload(&mut derived).await?;
+    if let Err(err) = state
+        .data_storage
+        .import_file(&derived, &ImportSource::open(output.as_path())?, false)
+        .await
+    {
+        state.data_storage.remove_file(&derived).await.ok();
+        return Err(err);
+    }
+    derived.offset = length;
+    if let Err(err) = durability::verify_size(state, &derived).await {
+        state.data_storage.remove_file(&derived).await.ok();
+        return Err(err);
+    }
+    state.info_storage.set_info(&derived, true).await?;
+    durability::sync_finished(state, &derived).await?;
+    Ok(Some(derived))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{derive, DERIVED_FROM_KEY};
+    use crate::{errors::RustusError, utils::metadata::ServerMetadata, State};
+    use std::{io::Write, os::unix::fs::PermissionsExt, str::FromStr};
+
+    /// Create executable script in the directory.
+    fn script(dir: &std::path::Path, body: &str) -> String {
+        let path = dir.join("derive.sh");
+        let mut file = std::fs::File::create(path.as_path()).unwrap();
+        file.write_all(format!("#!/bin/sh\n{body}\n").as_bytes())
+            .unwrap();
+        file.set_permissions(std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+        file.sync_all().unwrap();
+        path.display().to_string()
+    }
+
+    #[actix_rt::test]
+    async fn derived_upload() {
+        let mut state = State::test_new().await;
+        state.config.server_metadata = vec![ServerMetadata::from_str("origin=node-1").unwrap()];
+        let mut file_info = state.create_test_file().await;
+        std::fs::write(file_info.path.clone().unwrap(), "memes").unwrap();
+        for (key, value) in [
+            ("owner", "alice"),
+            ("tenant", "acme"),
+            ("filename", "a.txt"),
+        ] {
+            file_info.metadata.insert(key.into(), value.into());
+        }
+        file_info.length = Some(5);
+        file_info.offset = 5;
+        let dir = tempdir::TempDir::new("derivation").unwrap();
+        let command = script(dir.path(), r#"echo "$1" > "$3"; cat "$2" >> "$3""#);
+        let derived = derive(&state, &file_info, command.as_str())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(derived.metadata.get(DERIVED_FROM_KEY), Some(&file_info.id));
+        assert_eq!(derived.metadata["origin"], "node-1");
+        assert_eq!(derived.metadata["owner"], "alice");
+        assert_eq!(derived.metadata["tenant"], "acme");
+        // Other metadata of the source isn't copied.
+        assert!(!derived.metadata.contains_key("filename"));
+        let contents = std::fs::read_to_string(derived.path.clone().unwrap()).unwrap();
+        assert_eq!(contents, format!("{}\nmemes", file_info.id));
+        let info = state
+            .info_storage
+            .get_info(derived.id.as_str())
+            .await
+            .unwrap();
+        assert_eq!(info.length, Some(info.offset));
+    }
+
+    #[actix_rt::test]
+    async fn derivation_timeout() {
+        let mut state = State::test_new().await;
+        state.config.storage_opts.derive_timeout = 1;
+        let file_info = state.create_test_file().await;
+        let dir = tempdir::TempDir::new("derivation").unwrap();
+        let command = script(dir.path(), r#"sleep 10; echo "memes" > "$3""#);
+        let started = std::time::Instant::now();
+        let err = derive(&state, &file_info, command.as_str())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, RustusError::DerivationFailed(_)));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[actix_rt::test]
+    async fn failed_derivation() {
+        let state = State::test_new().await;
+        let file_info = state.create_test_file().await;
+        let dir = tempdir::TempDir::new("derivation").unwrap();
+        // Command may decide that nothing should be derived.
+        let command = script(dir.path(), "exit 0");
+        let derived = derive(&state, &file_info, command.as_str()).await.unwrap();
+        assert!(derived.is_none());
+        let command = script(dir.path(), "exit 1");
+        assert!(derive(&state, &file_info, command.as_str()).await.is_err());
+        assert!(state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .is_ok());
+    }
+}
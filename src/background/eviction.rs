@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use actix_web::http::header::HeaderMap;
+use log::{debug, error, info, warn};
+
+use crate::{
+    errors::RustusResult, info_storages::FileInfo, notifiers::Hook, utils::tombstones, State,
+};
+
+/// Keep total size of uploads under the storage budget.
+///
+/// This task periodically checks how many bytes are stored
+/// and evicts the oldest finished uploads if budget is exceeded.
+pub async fn run(state: State, budget: usize) {
+    let mut interval = tokio::time::interval(Duration::from_secs(
+        state.config.storage_opts.storage_budget_check_interval,
+    ));
+    loop {
+        interval.tick().await;
+        let Some(_permit) = state.cleanup.start().await else {
+            continue;
+        };
+        match evict(&state, budget).await {
+            Ok(0) => {}
+            Ok(evicted) => info!("Evicted {evicted} uploads to fit storage budget."),
+            Err(err) => error!("Cannot evict uploads: {err}"),
+        }
+    }
+}
+
+/// Remove the oldest finished uploads until
+/// total size of uploads fits the budget.
+///
+/// Only uploads of the current data storage are counted.
+/// Unfinished uploads are never removed.
+/// Returns number of evicted uploads.
+pub async fn evict(state: &State, budget: usize) -> RustusResult<usize> {
+    let storage_name = state.data_storage.to_string();
+    let mut uploads = state.info_storage.list_info().await?;
+    uploads.retain(|upload| upload.storage == storage_name);
+    let mut total_size: usize = uploads.iter().map(|upload| upload.offset).sum();
+    if total_size <= budget {
+        return Ok(0);
+    }
+    debug!("Storage budget is exceeded. Total size: {total_size}, budget: {budget}.");
+    uploads.retain(|upload| upload.length == Some(upload.offset));
+    uploads.sort_by_key(|upload| upload.created_at);
+
+    let headers = HeaderMap::new();
+    let mut evicted = 0;
+    for upload in uploads {
+        // Removal is paused between uploads.
+        if total_size <= budget || state.cleanup.is_paused() {
+            break;
+        }
+        if !remove(state, &upload, &headers).await? {
+            continue;
+        }
+        total_size -= upload.offset;
+        evicted += 1;
+    }
+    Ok(evicted)
+}
+
+/// Remove upload which isn't needed anymore.
+///
+/// It works like termination, but hooks have no request.
+/// Returns false if pre-terminate hook cancelled the removal.
+pub(super) async fn remove(
+    state: &State,
+    upload: &FileInfo,
+    headers: &HeaderMap,
+) -> RustusResult<bool> {
+    let hooks_format = &state.config.notification_opts.hooks_format;
+    if state.config.hook_is_active(Hook::PreTerminate) {
+        let message = hooks_format.format_without_request(upload);
+        if let Err(err) = state
+            .notification_manager
+            .send_message(message, Hook::PreTerminate, headers)
+            .await
+        {
+            warn!("Removal of upload {} was cancelled: {err}", upload.id);
+            return Ok(false);
+        }
+    }
+    state.info_storage.remove_info(upload.id.as_str()).await?;
+    state.data_storage.remove_file(upload).await?;
+    tombstones::bury(state, upload.id.as_str()).await;
+    if state.config.hook_is_active(Hook::TerminateCleanup) {
+        let message = hooks_format.format_without_request(upload);
+        state
+            .notification_manager
+            .deliver_cleanup(message, headers)
+            .await;
+    }
+    if state.config.hook_is_active(Hook::PostTerminate) {
+        let message = hooks_format.format_without_request(upload);
+        if let Err(err) = state
+            .notification_manager
+            .send_message(message, Hook::PostTerminate, headers)
+            .await
+        {
+            warn!("Cannot send post-terminate hook for {}: {err}", upload.id);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::evict;
+    use crate::State;
+
+    #[actix_rt::test]
+    async fn evict_oldest_finished() {
+        let state = State::test_new().await;
+        let mut oldest = state.create_test_file().await;
+        let mut newest = state.create_test_file().await;
+        let unfinished = state.create_test_file().await;
+        oldest.offset = 10;
+        oldest.created_at -= chrono::Duration::hours(1);
+        newest.offset = 10;
+        state.info_storage.set_info(&oldest, false).await.unwrap();
+        state.info_storage.set_info(&newest, false).await.unwrap();
+
+        let evicted = evict(&state, 15).await.unwrap();
+        assert_eq!(evicted, 1);
+        assert!(state
+            .info_storage
+            .get_info(oldest.id.as_str())
+            .await
+            .is_err());
+        assert!(state
+            .info_storage
+            .get_info(newest.id.as_str())
+            .await
+            .is_ok());
+        assert!(state
+            .info_storage
+            .get_info(unfinished.id.as_str())
+            .await
+            .is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn other_storages_not_counted() {
+        let state = State::test_new().await;
+        let mut upload = state.create_test_file().await;
+        upload.offset = 10;
+        state.info_storage.set_info(&upload, false).await.unwrap();
+        let mut other = state.create_test_file().await;
+        other.offset = 10;
+        other.storage = String::from("s3_storage");
+        other.created_at -= chrono::Duration::hours(1);
+        state.info_storage.set_info(&other, false).await.unwrap();
+
+        let evicted = evict(&state, 15).await.unwrap();
+        assert_eq!(evicted, 0);
+        assert!(state
+            .info_storage
+            .get_info(upload.id.as_str())
+            .await
+            .is_ok());
+        assert!(state.info_storage.get_info(other.id.as_str()).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn unfinished_never_evicted() {
+        let state = State::test_new().await;
+        let mut unfinished = state.create_test_file().await;
+        unfinished.offset = 5;
+        state
+            .info_storage
+            .set_info(&unfinished, false)
+            .await
+            .unwrap();
+        let evicted = evict(&state, 1).await.unwrap();
+        assert_eq!(evicted, 0);
+        assert!(state
+            .info_storage
+            .get_info(unfinished.id.as_str())
+            .await
+            .is_ok());
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    cell::Cell,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use actix_web::web;
+use futures::StreamExt;
+use log::{debug, error, warn};
+use s3::Bucket;
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::StreamReader;
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    storages::s3_hybrid_storage::build_bucket,
+    utils::encryption::{self, UploadKey},
+    State,
+};
+
+/// Location where finished uploads are exported.
+enum ExportTarget {
+    Dir(PathBuf),
+    S3(Box<Bucket>),
+}
+
+/// Get configured export location.
+///
+/// # Errors
+///
+/// Returns an error if the export bucket can't be created.
+fn export_target(state: &State) -> RustusResult<Option<ExportTarget>> {
+    let opts = &state.config.storage_opts;
+    if let Some(export_dir) = &opts.export_dir {
+        return Ok(Some(ExportTarget::Dir(export_dir.clone())));
+    }
+    let Some(bucket_name) = &opts.export_s3_bucket else {
+        return Ok(None);
+    };
+    build_bucket(
+        opts.s3_url.clone().unwrap_or_default(),
+        opts.s3_region.clone().unwrap_or_default(),
+        &opts.s3_access_key,
+        &opts.s3_secret_key,
+        &opts.s3_security_token,
+        &opts.s3_session_token,
+        &opts.s3_profile,
+        &opts.s3_headers,
+        bucket_name.as_str(),
+        opts.s3_force_path_style,
+    )
+    .map(|bucket| Some(ExportTarget::S3(Box::new(bucket))))
+}
+
+/// Start exporting finished upload in background.
+///
+/// It does nothing if neither export directory
+/// nor export bucket is configured.
+/// Encrypted uploads are exported decrypted, so they
+/// can't be exported without the key.
+#[allow(clippy::module_name_repetitions)]
+pub fn spawn_export(state: &web::Data<State>, file_info: &FileInfo, key: Option<UploadKey>) {
+    let storage_opts = &state.config.storage_opts;
+    if storage_opts.export_dir.is_none() && storage_opts.export_s3_bucket.is_none() {
+        return;
+    }
+    if file_info.encryption.is_some() && key.is_none() {
+        warn!(
+            "Encrypted upload {} isn't exported without the key.",
+            file_info.id
+        );
+        return;
+    }
+    tokio::task::spawn_local(run(state.clone(), file_info.clone(), key));
+}
+
+/// Export upload with retries.
+///
+/// The original upload is removed only
+/// when export is confirmed, if it's enabled.
+async fn run(state: web::Data<State>, file_info: FileInfo, key: Option<UploadKey>) {
+    let target = match export_target(&state) {
+        Ok(Some(target)) => target,
+        Ok(None) => return,
+        Err(err) => {
+            error!("Cannot export upload {}: {}", file_info.id, err);
+            return;
+        }
+    };
+    let retries = state.config.storage_opts.export_retries;
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+        }
+        let result = match &target {
+            ExportTarget::Dir(export_dir) => {
+                export(&state, &file_info, key.as_ref(), export_dir.as_path()).await
+            }
+            ExportTarget::S3(bucket) => export_s3(&state, &file_info, key.as_ref(), bucket).await,
+        };
+        match result {
+            Ok(()) => {
+                debug!("Upload {} was exported.", file_info.id);
+                if state.config.storage_opts.export_remove_original {
+                    remove_original(&state, &file_info).await;
+                }
+                return;
+            }
+            Err(err) => warn!(
+                "Cannot export upload {}. Attempt: {}. Reason: {}",
+                file_info.id,
+                attempt + 1,
+                err
+            ),
+        }
+    }
+    error!(
+        "Export of upload {} failed after {} retries. Original upload is kept.",
+        file_info.id, retries
+    );
+}
+
+/// Stream contents of the upload to S3 bucket.
+///
+/// The object is named after the upload id.
+/// Export is confirmed only if S3 accepted the object
+/// and all bytes of the upload were sent.
+async fn export_s3(
+    state: &State,
+    file_info: &FileInfo,
+    key: Option<&UploadKey>,
+    bucket: &Bucket,
+) -> RustusResult<()> {
+    let body = encryption::read_data(
+        state.data_storage.as_ref(),
+        file_info,
+        key,
+        0..file_info.offset,
+    )
+    .await?;
+    let written = Cell::new(0);
+    let mut reader = StreamReader::new(body.map(|chunk| {
+        chunk
+            .inspect(|bytes| written.set(written.get() + bytes.len()))
+            .map_err(|err| io::Error::other(err.to_string()))
+    }));
+    let status = bucket
+        .put_object_stream(&mut reader, file_info.id.as_str())
+        .await?;
+    if !(200..300).contains(&status) {
+        return Err(RustusError::UnableToWrite(format!(
+            "S3 responded with status {status}"
+        )));
+    }
+    if Some(written.get()) != file_info.length {
+        return Err(RustusError::UnableToWrite(format!(
+            "Exported {} bytes instead of {}",
+            written.get(),
+            file_info.length.unwrap_or_default()
+        )));
+    }
+    Ok(())
+}
+
+/// Stream contents of the upload to the export directory.
+///
+/// Contents are written in a temporary file
+/// which is renamed after all bytes are written and synced.
+async fn export(
+    state: &State,
+    file_info: &FileInfo,
+    key: Option<&UploadKey>,
+    export_dir: &Path,
+) -> RustusResult<()> {
+    tokio::fs::create_dir_all(export_dir).await?;
+    let target_path = export_dir.join(file_info.id.as_str());
+    let tmp_path = export_dir.join(format!("{}.part", file_info.id));
+    let written = write_contents(state, file_info, key, tmp_path.as_path()).await?;
+    if Some(written) != file_info.length {
+        tokio::fs::remove_file(tmp_path).await?;
+        return Err(RustusError::UnableToWrite(format!(
+            "Exported {written} bytes instead of {}",
+            file_info.length.unwrap_or_default()
+        )));
+    }
+    tokio::fs::rename(tmp_path, target_path).await?;
+    Ok(())
+}
+
+/// Stream contents of the upload to a local file.
+///
+/// Encrypted uploads are decrypted with the key.
+///
+/// Returns number of written bytes.
+pub async fn write_contents(
+    state: &State,
+    file_info: &FileInfo,
+    key: Option<&UploadKey>,
+    path: &Path,
+) -> RustusResult<usize> {
+    let mut body = encryption::read_data(
+        state.data_storage.as_ref(),
+        file_info,
+        key,
+        0..file_info.offset,
+    )
+    .await?;
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut written = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        file.write_all(chunk.as_ref()).await?;
+        written += chunk.len();
+    }
+    file.sync_all().await?;
+    Ok(written)
+}
+
+/// Remove exported upload from rustus.
+async fn remove_original(state: &State, file_info: &FileInfo) {
+    if let Err(err) = state.data_storage.remove_file(file_info).await {
+        warn!("Cannot remove exported upload {}: {}", file_info.id, err);
+        return;
+    }
+    if let Err(err) = state.info_storage.remove_info(file_info.id.as_str()).await {
+        warn!(
+            "Cannot remove information about exported upload {}: {}",
+            file_info.id, err
+        );
+    }
+}
@@ -0,0 +1,73 @@
+use actix_web::{web, HttpRequest};
+use tokio::task::LocalSet;
+
+use crate::{info_storages::FileInfo, notifiers::Hook, State};
+
+mod abandoned;
+pub mod assembly;
+pub mod checksum;
+pub mod cleanup;
+pub mod compaction;
+pub mod derivation;
+mod eviction;
+pub mod export;
+mod partials;
+pub mod reconciliation;
+pub mod retention;
+mod trash;
+
+/// Spawn enabled background tasks.
+///
+/// Storages don't produce `Send` futures,
+/// that's why every task is spawned on the given `LocalSet`.
+pub fn spawn_tasks(state: &State, local: &LocalSet) {
+    if let Some(budget) = state.config.storage_opts.storage_budget {
+        local.spawn_local(eviction::run(state.clone(), budget));
+    }
+    if let Some(interval) = state.config.storage_opts.compaction_interval {
+        local.spawn_local(compaction::run(state.clone(), interval));
+    }
+    if let Some(ttl) = state.config.empty_upload_ttl {
+        local.spawn_local(abandoned::run(state.clone(), ttl));
+    }
+    if let Some(ttl) = state.config.partial_upload_ttl {
+        local.spawn_local(partials::run(state.clone(), ttl));
+    }
+    if let Some(grace_period) = state.config.termination_grace_period {
+        local.spawn_local(trash::run(state.clone(), grace_period));
+    }
+    if state.config.storage_opts.failover_data_dir.is_some() {
+        local.spawn_local(reconciliation::run(
+            state.clone(),
+            state.config.storage_opts.failover_reconcile_interval,
+        ));
+    }
+}
+
+/// Start background processing of the upload created by rustus itself
+/// and send post-finish hook.
+///
+/// Uploads finished by clients are processed by their handlers.
+pub fn notify_finished(state: &web::Data<State>, request: &HttpRequest, file_info: &FileInfo) {
+    export::spawn_export(state, file_info, None);
+    derivation::spawn_derivation(state, file_info, request);
+    if state.config.upload_checksum && file_info.checksum.is_none() {
+        // Post-finish hook is sent after checksum is computed.
+        checksum::spawn_checksum(state, file_info, request, None);
+    } else if state.config.hook_is_active(Hook::PostFinish) {
+        let message = state.config.notification_opts.hooks_format.format(
+            request,
+            file_info,
+            &state.config.client_ip,
+        );
+        let headers = request.headers().clone();
+        let file_info = file_info.clone();
+        let state = state.clone();
+        tokio::task::spawn_local(async move {
+            state
+                .notification_manager
+                .deliver_upload(message, Hook::PostFinish, &file_info, &headers)
+                .await;
+        });
+    }
+}
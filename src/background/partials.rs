@@ -0,0 +1,145 @@
+use std::{collections::HashSet, time::Duration};
+
+use actix_web::http::header::HeaderMap;
+use log::{debug, error, info};
+
+use crate::{background::eviction, errors::RustusResult, info_storages::FileInfo, State};
+
+/// Remove partial uploads which weren't written for too long.
+///
+/// Partial uploads are removed even if they are finished,
+/// since they are only needed to create final uploads.
+pub async fn run(state: State, ttl: u64) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.partial_cleanup_interval));
+    loop {
+        interval.tick().await;
+        let Some(_permit) = state.cleanup.start().await else {
+            continue;
+        };
+        match expire(&state, ttl).await {
+            Ok(0) => {}
+            Ok(removed) => info!("Removed {removed} expired partial uploads."),
+            Err(err) => error!("Cannot remove expired partial uploads: {err}"),
+        }
+    }
+}
+
+/// Get ids of parts of unfinished final uploads.
+pub(super) fn referenced_parts(uploads: &[FileInfo]) -> HashSet<String> {
+    uploads
+        .iter()
+        .filter(|upload| upload.is_final && upload.length != Some(upload.offset))
+        .filter_map(|upload| upload.parts.as_ref())
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+/// Remove partial uploads which weren't written for `ttl` seconds.
+///
+/// Parts of unfinished final uploads are kept until
+/// the final upload is finished or removed.
+/// Parts which are being concatenated or written are skipped.
+/// Returns number of removed uploads.
+pub async fn expire(state: &State, ttl: u64) -> RustusResult<usize> {
+    let uploads = state.info_storage.list_info().await?;
+    let referenced = referenced_parts(uploads.as_slice());
+    let ttl = chrono::Duration::seconds(i64::try_from(ttl).unwrap_or(i64::MAX / 1000));
+    let now = chrono::Utc::now();
+    let storage_name = state.data_storage.to_string();
+    let headers = HeaderMap::new();
+    let mut removed = 0;
+    for upload in uploads {
+        if state.cleanup.is_paused() {
+            break;
+        }
+        if !upload.is_partial
+            || upload.storage != storage_name
+            || now - upload.last_modified() <= ttl
+        {
+            continue;
+        }
+        if referenced.contains(&upload.id) {
+            debug!(
+                "Partial upload {} is a part of unfinished final upload.",
+                upload.id
+            );
+            continue;
+        }
+        let Some(_guard) = state.active_chunks.acquire_exclusive(upload.id.as_str()) else {
+            debug!("Partial upload {} is being used.", upload.id);
+            continue;
+        };
+        if eviction::remove(state, &upload, &headers).await? {
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expire;
+    use crate::State;
+
+    #[actix_rt::test]
+    async fn expire_partials() {
+        let state = State::test_new().await;
+        let mut expired = state.create_test_file().await;
+        let mut fresh = state.create_test_file().await;
+        let mut referenced = state.create_test_file().await;
+        let mut concatenated = state.create_test_file().await;
+        let mut regular = state.create_test_file().await;
+        for upload in [
+            &mut expired,
+            &mut fresh,
+            &mut referenced,
+            &mut concatenated,
+            &mut regular,
+        ] {
+            upload.is_partial = true;
+            upload.created_at -= chrono::Duration::hours(2);
+        }
+        fresh.created_at = chrono::Utc::now();
+        regular.is_partial = false;
+        for upload in [&expired, &fresh, &referenced, &concatenated, &regular] {
+            state.info_storage.set_info(upload, false).await.unwrap();
+        }
+        // Final upload isn't finished yet.
+        let mut final_upload = state.create_test_file().await;
+        final_upload.is_final = true;
+        final_upload.parts = Some(vec![referenced.id.clone()]);
+        state
+            .info_storage
+            .set_info(&final_upload, false)
+            .await
+            .unwrap();
+
+        let guard = state
+            .active_chunks
+            .acquire(concatenated.id.as_str(), usize::MAX)
+            .unwrap();
+        assert_eq!(expire(&state, 3600).await.unwrap(), 1);
+        let exists = |id: String| {
+            let state = state.clone();
+            async move { state.info_storage.get_info(id.as_str()).await.is_ok() }
+        };
+        assert!(!exists(expired.id.clone()).await);
+        assert!(exists(fresh.id.clone()).await);
+        assert!(exists(referenced.id.clone()).await);
+        assert!(exists(concatenated.id.clone()).await);
+        assert!(exists(regular.id.clone()).await);
+
+        // Parts are removed after the final upload is removed.
+        drop(guard);
+        state
+            .info_storage
+            .remove_info(final_upload.id.as_str())
+            .await
+            .unwrap();
+        assert_eq!(expire(&state, 3600).await.unwrap(), 2);
+        assert!(!exists(referenced.id.clone()).await);
+        assert!(!exists(concatenated.id.clone()).await);
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use log::{error, info, warn};
+
+use crate::{errors::RustusResult, storages::failover_storage::is_fallback, State};
+
+use super::compaction::relocate;
+
+/// Periodically move uploads from failover directory to the main storage.
+pub async fn run(state: State, interval: u64) {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval));
+    loop {
+        interval.tick().await;
+        match reconcile(&state).await {
+            Ok(0) => {}
+            Ok(moved) => info!("{moved} uploads were moved to the main storage."),
+            Err(err) => error!("Cannot move uploads to the main storage: {err}"),
+        }
+    }
+}
+
+/// Move finished uploads to the main storage.
+///
+/// While uploads are moved, no chunks can be written to them.
+/// Data in failover directory is removed after the upload is updated,
+/// so the upload can be read at any time.
+///
+/// Returns number of moved uploads.
+pub async fn reconcile(state: &State) -> RustusResult<usize> {
+    let storage_name = state.data_storage.to_string();
+    let mut uploads = Vec::new();
+    let mut guards = Vec::new();
+    for upload in state.info_storage.list_info().await? {
+        if upload.length != Some(upload.offset)
+            || upload.storage != storage_name
+            || !is_fallback(&upload)
+        {
+            continue;
+        }
+        if let Some(guard) = state.active_chunks.acquire_exclusive(upload.id.as_str()) {
+            guards.push(guard);
+            uploads.push(upload);
+        }
+    }
+    let mut moved = 0;
+    for (upload, path) in state.data_storage.reconcile(&uploads).await? {
+        match relocate(state, &upload, path.as_str()).await {
+            Ok(()) => moved += 1,
+            Err(err) => warn!("Cannot move upload {}: {err}", upload.id),
+        }
+    }
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::reconcile;
+    use crate::{
+        info_storages::FileInfo,
+        storages::{failover_storage::FailoverStorage, file_storage::FileStorage},
+        State, Storage,
+    };
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn move_finished_uploads() {
+        let mut state = State::test_new().await;
+        let primary = FileStorage::new(
+            state.config.storage_opts.data_dir.clone(),
+            String::new(),
+            false,
+        );
+        let fallback = FileStorage::new(
+            tempdir::TempDir::new("fallback").unwrap().into_path(),
+            String::new(),
+            false,
+        );
+        state.data_storage = Box::new(FailoverStorage::new(
+            Box::new(primary),
+            Box::new(fallback.clone()),
+            Duration::ZERO,
+        ));
+        let mut uploads = Vec::new();
+        for length in [5, 10] {
+            let mut file_info = FileInfo::new(
+                uuid::Uuid::new_v4().to_string().as_str(),
+                Some(length),
+                None,
+                state.data_storage.to_string(),
+                None,
+            );
+            // Uploads which were created while the main storage was down.
+            let path = fallback.create_file(&file_info).await.unwrap();
+            file_info.path = Some(path.clone());
+            fallback
+                .add_bytes(&file_info, Bytes::from("memes"))
+                .await
+                .unwrap();
+            file_info.offset = 5;
+            file_info.backend = Some(String::from("fallback"));
+            state.info_storage.set_info(&file_info, true).await.unwrap();
+            uploads.push((file_info, path));
+        }
+
+        assert_eq!(reconcile(&state).await.unwrap(), 1);
+        let (finished, old_path) = &uploads[0];
+        let moved = state
+            .info_storage
+            .get_info(finished.id.as_str())
+            .await
+            .unwrap();
+        let new_path = moved.path.clone().unwrap();
+        assert!(new_path.starts_with(state.config.storage_opts.data_dir.to_str().unwrap()));
+        assert_eq!(std::fs::read_to_string(new_path).unwrap(), "memes");
+        assert_eq!(moved.backend, None);
+        assert!(!std::path::Path::new(old_path).exists());
+        // Unfinished uploads stay in the failover directory.
+        let (unfinished, _) = &uploads[1];
+        let current = state
+            .info_storage
+            .get_info(unfinished.id.as_str())
+            .await
+            .unwrap();
+        assert_eq!(current.path, unfinished.path);
+        assert_eq!(reconcile(&state).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    http::StatusCode,
+    web, HttpRequest, HttpResponse,
+};
+use bytes::Bytes;
+use derive_more::{Display, From};
+use log::{debug, warn};
+use strum::EnumIter;
+use tokio::sync::oneshot;
+
+use crate::{from_str, info_storages::FileInfo, notifiers::Hook, utils::tombstones, State};
+
+/// Policy of removing uploads after they are downloaded.
+#[allow(clippy::module_name_repetitions)]
+#[derive(PartialEq, Eq, Debug, Display, EnumIter, From, Clone, Copy)]
+pub enum RetentionPolicy {
+    /// Remove upload after the whole file was sent to a client.
+    ///
+    /// Range requests never trigger removal.
+    #[display(fmt = "after-download")]
+    AfterDownload,
+    /// Remove upload after the first download request,
+    /// even if it was a range request or the client disconnected.
+    #[display(fmt = "first-request")]
+    FirstRequest,
+}
+
+from_str!(RetentionPolicy, "retention policy");
+
+impl RetentionPolicy {
+    /// Find policy for the given upload.
+    ///
+    /// Policy from upload's metadata takes precedence over global one.
+    /// Unfinished uploads are never removed.
+    pub fn for_upload(state: &State, file_info: &FileInfo) -> Option<Self> {
+        if file_info.length != Some(file_info.offset) {
+            return None;
+        }
+        state
+            .config
+            .delete_after_download_key
+            .as_ref()
+            .and_then(|key| file_info.metadata.get(key))
+            .and_then(|value| value.parse().ok())
+            .or(state.config.delete_after_download)
+    }
+
+    /// Check if the response must trigger removal.
+    fn applies_to(self, status: StatusCode) -> bool {
+        match self {
+            Self::AfterDownload => status == StatusCode::OK,
+            Self::FirstRequest => status.is_success(),
+        }
+    }
+}
+
+/// Body which reports when it was fully sent.
+#[allow(clippy::module_name_repetitions)]
+struct RetentionBody {
+    inner: BoxBody,
+    done: Option<oneshot::Sender<()>>,
+}
+
+impl MessageBody for RetentionBody {
+    type Error = <BoxBody as MessageBody>::Error;
+
+    fn size(&self) -> BodySize {
+        self.inner.size()
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            Poll::Ready(None) => {
+                if let Some(done) = self.done.take() {
+                    done.send(()).ok();
+                }
+            }
+            // Dropping the sender means that body wasn't sent.
+            Poll::Ready(Some(Err(_))) => {
+                self.done.take();
+            }
+            _ => {}
+        }
+        poll
+    }
+}
+
+/// Schedule removal of downloaded upload.
+///
+/// The upload is removed in background once
+/// the response body is sent or dropped, depending on the policy.
+pub fn schedule_removal(
+    state: &web::Data<State>,
+    file_info: &FileInfo,
+    request: &HttpRequest,
+    policy: RetentionPolicy,
+    response: HttpResponse,
+) -> HttpResponse {
+    if !policy.applies_to(response.status()) {
+        return response;
+    }
+    let (sender, receiver) = oneshot::channel();
+    let response = response
+        .map_body(|_, body| RetentionBody {
+            inner: body,
+            done: Some(sender),
+        })
+        .map_into_boxed_body();
+    let state = state.clone();
+    let file_info = file_info.clone();
+    let request = request.clone();
+    tokio::task::spawn_local(async move {
+        let downloaded = receiver.await.is_ok();
+        if !downloaded && policy == RetentionPolicy::AfterDownload {
+            debug!(
+                "Upload {} wasn't fully downloaded. Keeping it.",
+                file_info.id
+            );
+            return;
+        }
+        remove_upload(&state, &file_info, &request).await;
+    });
+    response
+}
+
+/// Remove upload and notify about termination.
+async fn remove_upload(state: &State, file_info: &FileInfo, request: &HttpRequest) {
+    if let Err(err) = state.info_storage.remove_info(file_info.id.as_str()).await {
+        // Upload was already removed by concurrent download.
+        debug!("Cannot remove downloaded upload {}: {}", file_info.id, err);
+        return;
+    }
+    if let Err(err) = state.data_storage.remove_file(file_info).await {
+        warn!(
+            "Cannot remove data of downloaded upload {}: {}",
+            file_info.id, err
+        );
+    }
+    tombstones::bury(state, file_info.id.as_str()).await;
+    debug!("Upload {} was removed after download.", file_info.id);
+    if state.config.hook_is_active(Hook::TerminateCleanup) {
+        let message = state.config.notification_opts.hooks_format.format(
+            request,
+            file_info,
+            &state.config.client_ip,
+        );
+        state
+            .notification_manager
+            .deliver_cleanup(message, request.headers())
+            .await;
+    }
+    if state.config.hook_is_active(Hook::PostTerminate) {
+        let message = state.config.notification_opts.hooks_format.format(
+            request,
+            file_info,
+            &state.config.client_ip,
+        );
+        if let Err(err) = state
+            .notification_manager
+            .send_message(message, Hook::PostTerminate, request.headers())
+            .await
+        {
+            warn!(
+                "Cannot send terminate hook for upload {}: {}",
+                file_info.id, err
+            );
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use log::{error, info, warn};
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    utils::trash,
+    State,
+};
+
+/// Interval between checks of the trash.
+const CHECK_INTERVAL: Duration = Duration::from_mins(1);
+
+/// Remove terminated uploads after their grace period.
+pub async fn run(state: State, grace_period: u64) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(_permit) = state.cleanup.start().await else {
+            continue;
+        };
+        match empty(&state, grace_period).await {
+            Ok(0) => {}
+            Ok(removed) => info!("Removed {removed} terminated uploads from the trash."),
+            Err(err) => error!("Cannot empty the trash: {err}"),
+        }
+    }
+}
+
+/// Remove data and information of terminated uploads
+/// whose grace period is over.
+///
+/// Returns number of removed uploads.
+pub async fn empty(state: &State, grace_period: u64) -> RustusResult<usize> {
+    let Some(trash) = &state.trash else {
+        return Ok(0);
+    };
+    let now = chrono::Utc::now();
+    let storage_name = state.data_storage.to_string();
+    let mut removed = 0;
+    for upload in trash.list_info().await? {
+        if state.cleanup.is_paused() {
+            break;
+        }
+        if upload.storage != storage_name {
+            continue;
+        }
+        // Termination or restoration was interrupted
+        // while the upload was in both places, it's alive.
+        if state
+            .info_storage
+            .get_info(upload.id.as_str())
+            .await
+            .is_ok()
+        {
+            trash.remove_info(upload.id.as_str()).await?;
+            continue;
+        }
+        if !trash::is_expired(&upload, grace_period, now) {
+            continue;
+        }
+        match state.data_storage.remove_file(&upload).await {
+            // Data may be already removed by the previous attempt.
+            Ok(()) | Err(RustusError::FileNotFound) => {}
+            Err(err) => {
+                warn!("Cannot remove data of upload {}: {}", upload.id, err);
+                continue;
+            }
+        }
+        trash.remove_info(upload.id.as_str()).await?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::empty;
+    use crate::{utils::trash, State};
+    use std::path::PathBuf;
+
+    #[actix_rt::test]
+    async fn empty_trash() {
+        let mut state = State::test_new().await;
+        state.enable_test_trash(3600).await;
+        let expired = state.create_test_file().await;
+        let kept = state.create_test_file().await;
+        let trash_storage = state.trash.clone().unwrap();
+        for upload in [&expired, &kept] {
+            trash::put(&state, trash_storage.as_ref(), upload)
+                .await
+                .unwrap();
+        }
+        let mut info = trash_storage.get_info(expired.id.as_str()).await.unwrap();
+        info.removed_at = info
+            .removed_at
+            .map(|time| time - chrono::Duration::hours(2));
+        trash_storage.set_info(&info, false).await.unwrap();
+
+        assert_eq!(empty(&state, 3600).await.unwrap(), 1);
+        assert!(!PathBuf::from(expired.path.unwrap()).exists());
+        assert!(trash_storage.get_info(expired.id.as_str()).await.is_err());
+        assert!(PathBuf::from(kept.path.unwrap()).exists());
+        assert!(trash_storage.get_info(kept.id.as_str()).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn interrupted_termination() {
+        let mut state = State::test_new().await;
+        state.enable_test_trash(0).await;
+        let upload = state.create_test_file().await;
+        let trash_storage = state.trash.clone().unwrap();
+        // Process was stopped before information was removed.
+        let mut trashed = upload.clone();
+        trashed.removed_at = Some(chrono::Utc::now());
+        trash_storage.set_info(&trashed, true).await.unwrap();
+
+        assert_eq!(empty(&state, 0).await.unwrap(), 0);
+        assert!(PathBuf::from(upload.path.unwrap()).exists());
+        assert!(trash_storage.get_info(upload.id.as_str()).await.is_err());
+        assert!(state
+            .info_storage
+            .get_info(upload.id.as_str())
+            .await
+            .is_ok());
+    }
+}
@@ -0,0 +1,423 @@
+use std::{
+    io::{Read, Write},
+    path::Path,
+};
+
+use bytes::Bytes;
+use futures::StreamExt;
+use log::warn;
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::{FileInfo, InfoStorage, UploadFilter, UploadPages},
+    storages::Storage,
+};
+
+/// Number of uploads read from info storage at once.
+const PAGE_SIZE: usize = 1000;
+
+/// Size of blocks of tar archives.
+const BLOCK_SIZE: usize = 512;
+
+/// Size of chunks written to storage on restore.
+const RESTORE_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Name of the entry with information about the upload.
+const INFO_ENTRY: &str = "info.json";
+
+/// Name of the entry with data of the upload.
+const DATA_ENTRY: &str = "data";
+
+/// Largest size which fits the octal size field.
+const MAX_OCTAL_SIZE: u64 = 0o77_777_777_777;
+
+/// Build header of a tar entry in ustar format.
+///
+/// Sizes of 8GiB and more don't fit the octal field,
+/// they're written in base-256 as GNU tar does.
+fn tar_header(name: &str, size: usize, mtime: i64) -> [u8; BLOCK_SIZE] {
+    let mut header = [0u8; BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    let size = size as u64;
+    if size > MAX_OCTAL_SIZE {
+        header[124] = 0x80;
+        header[128..136].copy_from_slice(&size.to_be_bytes());
+    } else {
+        header[124..136].copy_from_slice(format!("{size:011o}\0").as_bytes());
+    }
+    header[136..148].copy_from_slice(format!("{:011o}\0", mtime.max(0)).as_bytes());
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    // Checksum is computed with spaces in its own field.
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|byte| u32::from(*byte)).sum();
+    header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    header
+}
+
+/// Parse header of a tar entry.
+///
+/// Returns `None` for empty blocks,
+/// which mark the end of the archive.
+fn parse_header(header: &[u8; BLOCK_SIZE]) -> RustusResult<Option<(String, usize)>> {
+    if header.iter().all(|byte| *byte == 0) {
+        return Ok(None);
+    }
+    let invalid = || RustusError::UnableToWrite(String::from("Backup isn't a valid tar archive."));
+    if &header[257..262] != b"ustar" {
+        return Err(invalid());
+    }
+    let field = |range: std::ops::Range<usize>| {
+        std::str::from_utf8(&header[range])
+            .map(|value| value.trim_matches(|c: char| c == '\0' || c == ' '))
+            .map_err(|_| invalid())
+    };
+    let name = field(0..100)?;
+    let size = if header[124] & 0x80 == 0 {
+        u64::from_str_radix(field(124..136)?, 8).map_err(|_| invalid())?
+    } else {
+        // Base-256 size, the flag bit isn't a part of the number.
+        let mut size = u64::from(header[124] & 0x7f);
+        for byte in &header[125..136] {
+            size = size.checked_mul(256).ok_or_else(invalid)? + u64::from(*byte);
+        }
+        size
+    };
+    let size = usize::try_from(size).map_err(|_| invalid())?;
+    Ok(Some((String::from(name), size)))
+}
+
+/// Number of bytes which pad an entry to the whole block.
+fn padding(size: usize) -> usize {
+    (BLOCK_SIZE - size % BLOCK_SIZE) % BLOCK_SIZE
+}
+
+/// Check if data of the upload can be read.
+///
+/// Unfinished uploads with chunks at arbitrary offsets
+/// aren't stored as a single file, so only information
+/// about them is saved.
+fn has_readable_data(file_info: &FileInfo) -> bool {
+    file_info.offset > 0
+        && (file_info.received.is_empty() || file_info.length == Some(file_info.offset))
+}
+
+/// Write the first `size` bytes of the upload to the archive.
+async fn write_data<W: Write>(
+    storage: &dyn Storage,
+    file_info: &FileInfo,
+    size: usize,
+    out: &mut W,
+) -> RustusResult<()> {
+    // Active uploads may have more bytes than
+    // their information says, they aren't saved.
+    let mut body = storage.read_data(file_info, 0..size).await?;
+    let mut written = 0;
+    while let Some(bytes) = body.next().await {
+        let bytes = bytes?;
+        out.write_all(bytes.as_ref())?;
+        written += bytes.len();
+    }
+    if written < size {
+        return Err(RustusError::UnableToWrite(format!(
+            "Upload {} has {written} bytes instead of {size}.",
+            file_info.id
+        )));
+    }
+    Ok(())
+}
+
+/// Write archive with all uploads of the storage.
+///
+/// Every upload is saved as a directory with its information
+/// in `info.json` and its data in `data`.
+/// Data of unfinished uploads is saved up to their offset.
+/// Returns the writer and the number of saved uploads.
+pub async fn write_backup<W: Write>(
+    info_storage: &dyn InfoStorage,
+    storage: &dyn Storage,
+    mut out: W,
+) -> RustusResult<(W, usize)> {
+    let storage_name = storage.to_string();
+    let mut pages = UploadPages::new(info_storage, UploadFilter::default(), PAGE_SIZE);
+    let mut saved = 0;
+    while let Some(page) = pages.next_page().await? {
+        for file_info in &page {
+            if file_info.storage != storage_name {
+                warn!(
+                    "Upload {} is stored in {}, it isn't saved.",
+                    file_info.id, file_info.storage
+                );
+                continue;
+            }
+            saved += 1;
+            let mtime = file_info.last_modified().timestamp();
+            let info = file_info.json().await?;
+            out.write_all(&tar_header(
+                format!("{saved:08}/{INFO_ENTRY}").as_str(),
+                info.len(),
+                mtime,
+            ))?;
+            out.write_all(info.as_bytes())?;
+            out.write_all(&[0; BLOCK_SIZE][..padding(info.len())])?;
+            if !has_readable_data(file_info) {
+                continue;
+            }
+            out.write_all(&tar_header(
+                format!("{saved:08}/{DATA_ENTRY}").as_str(),
+                file_info.offset,
+                mtime,
+            ))?;
+            write_data(storage, file_info, file_info.offset, &mut out).await?;
+            out.write_all(&[0; BLOCK_SIZE][..padding(file_info.offset)])?;
+        }
+    }
+    out.write_all(&[0; 2 * BLOCK_SIZE])?;
+    out.flush()?;
+    Ok((out, saved))
+}
+
+/// Upload which is being restored.
+struct Restored {
+    /// Information from the archive.
+    file_info: FileInfo,
+    /// Number of restored bytes.
+    offset: usize,
+}
+
+/// Save information about the restored upload.
+///
+/// Uploads without restored data start from the beginning.
+async fn finish_restore(info_storage: &dyn InfoStorage, restored: Restored) -> RustusResult<()> {
+    let mut file_info = restored.file_info;
+    if restored.offset != file_info.offset {
+        warn!(
+            "Only {} bytes of upload {} are restored.",
+            restored.offset, file_info.id
+        );
+        file_info.offset = restored.offset;
+        file_info.received.clear();
+    }
+    info_storage.set_info(&file_info, true).await
+}
+
+/// Recreate uploads from the archive.
+///
+/// Uploads are created in the storage with `create_file`,
+/// their data is written with `add_bytes` and information
+/// is saved with `set_info`. Uploads which already exist are skipped.
+/// Returns the number of restored uploads.
+pub async fn read_backup<R: Read>(
+    info_storage: &dyn InfoStorage,
+    storage: &dyn Storage,
+    mut input: R,
+) -> RustusResult<usize> {
+    let mut restored = 0;
+    // Chunks of aligned storages must be multiples of the alignment.
+    let chunk_size = storage
+        .chunk_alignment()
+        .map_or(RESTORE_CHUNK_SIZE, |alignment| {
+            RESTORE_CHUNK_SIZE.max(alignment) / alignment * alignment
+        });
+    let mut current: Option<Restored> = None;
+    let mut header = [0u8; BLOCK_SIZE];
+    loop {
+        input.read_exact(&mut header)?;
+        let Some((name, size)) = parse_header(&header)? else {
+            break;
+        };
+        if name.ends_with(INFO_ENTRY) {
+            if let Some(upload) = current.take() {
+                finish_restore(info_storage, upload).await?;
+                restored += 1;
+            }
+            let mut info = vec![0u8; size];
+            input.read_exact(&mut info)?;
+            let mut file_info = FileInfo::from_json(String::from_utf8_lossy(&info).into()).await?;
+            if info_storage.get_info(file_info.id.as_str()).await.is_ok() {
+                warn!("Upload {} already exists, it isn't restored.", file_info.id);
+            } else {
+                file_info.storage = storage.to_string();
+                // Path in the old storage may be invalid here.
+                file_info.path = None;
+                file_info.backend = None;
+                storage.create_upload(&mut file_info).await?;
+                current = Some(Restored {
+                    file_info,
+                    offset: 0,
+                });
+            }
+        } else if name.ends_with(DATA_ENTRY) {
+            let mut remaining = size;
+            while remaining > 0 {
+                let mut chunk = vec![0u8; remaining.min(chunk_size)];
+                input.read_exact(&mut chunk)?;
+                remaining -= chunk.len();
+                // Data of skipped uploads is read, but not written.
+                let Some(upload) = current.as_mut() else {
+                    continue;
+                };
+                let mut file_info = upload.file_info.clone();
+                file_info.offset = upload.offset;
+                file_info.received.clear();
+                let chunk_len = chunk.len();
+                storage.add_bytes(&file_info, Bytes::from(chunk)).await?;
+                upload.offset += chunk_len;
+            }
+        } else {
+            warn!("Unknown entry {name} in the backup is skipped.");
+            std::io::copy(&mut (&mut input).take(size as u64), &mut std::io::sink())?;
+        }
+        std::io::copy(
+            &mut (&mut input).take(padding(size) as u64),
+            &mut std::io::sink(),
+        )?;
+    }
+    if let Some(upload) = current.take() {
+        finish_restore(info_storage, upload).await?;
+        restored += 1;
+    }
+    Ok(restored)
+}
+
+/// Write backup of all uploads to the file.
+pub async fn backup(
+    info_storage: &dyn InfoStorage,
+    storage: &dyn Storage,
+    output: &Path,
+) -> std::io::Result<()> {
+    let file = std::io::BufWriter::new(std::fs::File::create(output)?);
+    let (_, saved) = write_backup(info_storage, storage, file).await?;
+    log::info!("Backup contains {saved} uploads.");
+    Ok(())
+}
+
+/// Restore uploads from the backup file.
+pub async fn restore(
+    info_storage: &dyn InfoStorage,
+    storage: &dyn Storage,
+    input: &Path,
+) -> std::io::Result<()> {
+    let file = std::io::BufReader::new(std::fs::File::open(input)?);
+    // Some storages spawn local tasks.
+    let restored = tokio::task::LocalSet::new()
+        .run_until(read_backup(info_storage, storage, file))
+        .await?;
+    log::info!("{restored} uploads are restored.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_header, read_backup, tar_header, write_backup};
+    use crate::State;
+    use actix_web::test::TestRequest;
+    use bytes::Bytes;
+
+    #[test]
+    fn header_checksum() {
+        let header = tar_header("00000001/data", 5, 0);
+        let checksum = header
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| {
+                if (148..156).contains(&i) {
+                    32
+                } else {
+                    u32::from(*byte)
+                }
+            })
+            .sum::<u32>();
+        let stored = std::str::from_utf8(&header[148..154]).unwrap();
+        assert_eq!(u32::from_str_radix(stored, 8).unwrap(), checksum);
+    }
+
+    #[test]
+    fn huge_entry() {
+        let size = 20 * 1024 * 1024 * 1024;
+        let header = tar_header("00000001/data", size, 0);
+        assert_eq!(header[124], 0x80);
+        let (name, parsed) = parse_header(&header).unwrap().unwrap();
+        assert_eq!(name, "00000001/data");
+        assert_eq!(parsed, size);
+        let header = tar_header("00000001/data", 5, 0);
+        assert_eq!(parse_header(&header).unwrap().unwrap().1, 5);
+    }
+
+    #[actix_rt::test]
+    async fn backup_and_restore() {
+        let state = State::test_new().await;
+        let mut finished = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&finished, Bytes::from("memes data"))
+            .await
+            .unwrap();
+        finished.offset = 10;
+        finished
+            .metadata
+            .insert(String::from("filename"), String::from("memes.txt"));
+        state.info_storage.set_info(&finished, false).await.unwrap();
+        let mut partial = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&partial, Bytes::from("meme"))
+            .await
+            .unwrap();
+        partial.offset = 4;
+        state.info_storage.set_info(&partial, false).await.unwrap();
+        let empty = state.create_test_file().await;
+
+        let (archive, saved) = write_backup(
+            state.info_storage.as_ref(),
+            state.data_storage.as_ref(),
+            vec![],
+        )
+        .await
+        .unwrap();
+        assert_eq!(saved, 3);
+
+        let target = State::test_new().await;
+        let restored = read_backup(
+            target.info_storage.as_ref(),
+            target.data_storage.as_ref(),
+            archive.as_slice(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(restored, 3);
+        let request = TestRequest::get().to_http_request();
+        for (upload, contents) in [(finished, "memes data"), (partial, "meme"), (empty, "")] {
+            let info = target
+                .info_storage
+                .get_info(upload.id.as_str())
+                .await
+                .unwrap();
+            assert_eq!(info.offset, upload.offset);
+            assert_eq!(info.metadata, upload.metadata);
+            let response = target
+                .data_storage
+                .get_contents(&info, &request)
+                .await
+                .unwrap();
+            let body = actix_web::body::to_bytes(response.into_body())
+                .await
+                .unwrap();
+            assert_eq!(body, contents);
+        }
+
+        // Existing uploads are skipped.
+        let restored = read_backup(
+            target.info_storage.as_ref(),
+            target.data_storage.as_ref(),
+            archive.as_slice(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(restored, 0);
+    }
+}
@@ -1,16 +1,40 @@
 use std::{ffi::OsString, path::PathBuf};
 
-use clap::Parser;
+use actix_web::{http::StatusCode, HttpRequest};
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
 
 use crate::{
+    background::{
+        assembly::AssemblyResponse, compaction::parse_threshold, retention::RetentionPolicy,
+    },
+    errors::{RustusError, RustusResult},
     info_storages::AvailableInfoStores,
-    notifiers::{Format, Hook},
+    notifiers::{http_notifier::Compression, Format, Hook},
     protocol::extensions::Extensions,
+    report::{parse_date, ReportFormat},
+    utils::{
+        dir_struct::parse_prefix,
+        headers::{parse_creation_status, parse_header_size},
+        listener::client_ip,
+        metadata::{MetadataAlias, MetadataNormalizer, MetadataPattern, ServerMetadata},
+        proxy::{self, IpSource, TrustedProxy},
+        quota::TenantQuota,
+        tls::TlsVersion,
+    },
 };
 
-use crate::storages::AvailableStores;
+#[cfg(feature = "db_info_storage")]
+use crate::info_storages::db_info_storage::CardinalityLimit;
+
+use crate::storages::{
+    chunk_index::AvailableChunkIndexes,
+    file_storage::{parse_mode, Permissions},
+    AvailableStores,
+};
 
 #[derive(Parser, Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct StorageOptions {
     /// Rustus storage type.
     ///
@@ -35,6 +59,15 @@ pub struct StorageOptions {
     #[arg(long, env = "RUSTUS_DIR_STRUCTURE", default_value = "")]
     pub dir_structure: String,
 
+    /// Prefix of paths and keys of all uploads.
+    ///
+    /// Environments which share storages, like one S3 bucket
+    /// or one database, must use different prefixes.
+    /// It's applied to data and info storages.
+    /// Example: "staging".
+    #[arg(long, env = "RUSTUS_STORAGE_PREFIX", value_parser = parse_prefix)]
+    pub storage_prefix: Option<String>,
+
     /// Forces fsync call after writing chunk to filesystem.
     /// This parameter can help you when working with
     /// Network file systems. It guarantees that
@@ -44,6 +77,113 @@ pub struct StorageOptions {
     #[arg(long, env = "RUSTUS_FORCE_FSYNC")]
     pub force_fsync: bool,
 
+    /// Keep files of active uploads open
+    /// for the given number of seconds after the last write.
+    ///
+    /// It reduces syscall overhead for uploads
+    /// with lots of small chunks.
+    ///
+    /// This parameter is used only by file-storage.
+    #[arg(long, env = "RUSTUS_KEEP_FILES_OPEN")]
+    pub keep_files_open: Option<u64>,
+
+    /// Number of bytes written to a kept open file
+    /// after which fsync is called.
+    ///
+    /// It's not a write buffer, chunks are written right away.
+    /// Idle files are also synced before they're closed.
+    ///
+    /// This parameter is used only with `--keep-files-open`.
+    #[arg(
+        long,
+        env = "RUSTUS_KEEP_FILES_OPEN_FLUSH_SIZE",
+        requires = "keep_files_open"
+    )]
+    pub keep_files_open_flush_size: Option<usize>,
+
+    /// Maximum size of files which are read into memory on download.
+    ///
+    /// Smaller files are sent in one chunk instead of streaming,
+    /// it's faster for lots of small downloads.
+    ///
+    /// This parameter is used only by file-storage.
+    #[arg(long, env = "RUSTUS_BUFFERED_DOWNLOAD_SIZE")]
+    pub buffered_download_size: Option<u64>,
+
+    /// Store uploads in directories named after their creation date.
+    ///
+    /// Date path like "2024/06/15" is prepended
+    /// to the directory structure.
+    /// This parameter is used only by file-storage.
+    #[arg(long, env = "RUSTUS_DATE_PREFIX")]
+    pub date_prefix: bool,
+
+    /// Template of paths of uploaded files.
+    ///
+    /// Besides variables of directory structure, it can use
+    /// `{id}` and metadata values, like `{meta:filename}`.
+    /// Paths are relative to the directory structure.
+    /// Example: "{meta:category}/{meta:filename}".
+    ///
+    /// By default files are named after their ids.
+    /// This parameter is used only by file-storage.
+    #[arg(long, env = "RUSTUS_PATH_TEMPLATE")]
+    pub path_template: Option<String>,
+
+    /// Maximum size of a pack file in bytes.
+    ///
+    /// This parameter is used only by packed-file-storage.
+    #[arg(long, env = "RUSTUS_PACK_SIZE", default_value = "67108864")]
+    pub pack_size: u64,
+
+    /// Maximum size of an upload stored in a pack file.
+    ///
+    /// Larger uploads and uploads of unknown length
+    /// are stored in separate files.
+    /// This parameter is used only by packed-file-storage.
+    #[arg(long, env = "RUSTUS_PACK_MAX_OBJECT_SIZE", default_value = "1048576")]
+    pub pack_max_object_size: u64,
+
+    /// Store of the index of written chunks.
+    ///
+    /// "info" keeps the index in the info storage,
+    /// "redis" keeps it in Redis at `--chunk-index-dsn`.
+    /// Chunks are read from locations found in the index.
+    /// This parameter is used only by packed-file-storage.
+    #[arg(long, env = "RUSTUS_CHUNK_INDEX")]
+    pub chunk_index: Option<AvailableChunkIndexes>,
+
+    /// Connection string of Redis with the chunk index.
+    #[cfg(feature = "redis_info_storage")]
+    #[arg(long, env = "RUSTUS_CHUNK_INDEX_DSN")]
+    pub chunk_index_dsn: Option<String>,
+
+    /// Path to file with connection string of Redis with the chunk index.
+    #[cfg(feature = "redis_info_storage")]
+    #[arg(long, env = "RUSTUS_CHUNK_INDEX_DSN_PATH")]
+    pub chunk_index_dsn_path: Option<PathBuf>,
+
+    /// Octal mode of created files, e.g. "640".
+    ///
+    /// By default it's derived from umask.
+    /// This parameter is used only by file-storage.
+    #[arg(long, env = "RUSTUS_FILE_MODE", value_parser = parse_mode)]
+    pub file_mode: Option<u32>,
+
+    /// Octal mode of created directories, e.g. "750".
+    ///
+    /// By default it's derived from umask.
+    /// This parameter is used only by file-storage.
+    #[arg(long, env = "RUSTUS_DIR_MODE", value_parser = parse_mode)]
+    pub dir_mode: Option<u32>,
+
+    /// Group id of created files and directories.
+    ///
+    /// Rustus must be a member of this group.
+    /// This parameter is used only by file-storage.
+    #[arg(long, env = "RUSTUS_FILE_GROUP")]
+    pub file_group: Option<u32>,
+
     /// S3 bucket to upload files to.
     ///
     /// This parameter is required fo s3-based storages.
@@ -100,12 +240,24 @@ pub struct StorageOptions {
     #[arg(long, env = "RUSTUS_S3_SECURITY_TOKEN")]
     pub s3_security_token: Option<String>,
 
+    /// S3 security token path.
+    ///
+    /// Path to file that has s3-security-token inside.
+    #[arg(long, env = "RUSTUS_S3_SECURITY_TOKEN_PATH")]
+    pub s3_security_token_path: Option<PathBuf>,
+
     /// S3 session token.
     ///
     /// This parameter is required fo s3-based storages.
     #[arg(long, env = "RUSTUS_S3_SESSION_TOKEN")]
     pub s3_session_token: Option<String>,
 
+    /// S3 session token path.
+    ///
+    /// Path to file that has s3-session-token inside.
+    #[arg(long, env = "RUSTUS_S3_SESSION_TOKEN_PATH")]
+    pub s3_session_token_path: Option<PathBuf>,
+
     /// S3 profile.
     ///
     /// This parameter is required fo s3-based storages.
@@ -119,6 +271,221 @@ pub struct StorageOptions {
     /// This parameter is required fo s3-based storages.
     #[arg(long, env = "RUSTUS_S3_HEADERS")]
     pub s3_headers: Option<String>,
+
+    /// Accept chunks at arbitrary offsets.
+    ///
+    /// Every chunk is stored separately and all
+    /// chunks are stitched together once the whole file is received.
+    /// Requires known upload length.
+    ///
+    /// This parameter is used only by hybrid-s3 storage.
+    #[arg(long, env = "RUSTUS_S3_OUT_OF_ORDER_CHUNKS")]
+    pub s3_out_of_order_chunks: bool,
+
+    /// Base URL of a `WebDAV` collection to store files in.
+    ///
+    /// This parameter is required for webdav storage.
+    #[arg(long, required_if_eq("storage", "webdav"), env = "RUSTUS_WEBDAV_URL")]
+    pub webdav_url: Option<String>,
+
+    /// Username for basic auth on `WebDAV` server.
+    #[arg(long, env = "RUSTUS_WEBDAV_USERNAME")]
+    pub webdav_username: Option<String>,
+
+    /// Password for basic auth on `WebDAV` server.
+    #[arg(long, env = "RUSTUS_WEBDAV_PASSWORD")]
+    pub webdav_password: Option<String>,
+
+    /// Path to file with password for basic auth on `WebDAV` server.
+    #[arg(long, env = "RUSTUS_WEBDAV_PASSWORD_PATH")]
+    pub webdav_password_path: Option<PathBuf>,
+
+    /// Timeout in milliseconds for reading uploads from storage.
+    ///
+    /// If not set, reads are not limited.
+    #[arg(long, env = "RUSTUS_STORAGE_READ_TIMEOUT")]
+    pub storage_read_timeout: Option<u64>,
+
+    /// Timeout in milliseconds for writing to storage.
+    ///
+    /// It limits creation, writing and removal of uploads.
+    /// If not set, writes are not limited.
+    #[arg(long, env = "RUSTUS_STORAGE_WRITE_TIMEOUT")]
+    pub storage_write_timeout: Option<u64>,
+
+    /// Time in milliseconds to wait until written chunks can be read.
+    ///
+    /// Chunks are read back before the offset is advanced
+    /// only for storages which may show written data with a delay.
+    /// Set it to 0 to disable checks.
+    #[arg(
+        long,
+        env = "RUSTUS_STORAGE_CONSISTENCY_TIMEOUT",
+        default_value = "5000"
+    )]
+    pub storage_consistency_timeout: u64,
+
+    /// Read back written chunks for all storages.
+    ///
+    /// By default only storages which may show
+    /// written data with a delay are checked.
+    #[arg(long, env = "RUSTUS_VERIFY_WRITES")]
+    pub verify_writes: bool,
+
+    /// Size in bytes that chunks written to storage are multiples of.
+    ///
+    /// Bytes after the last aligned boundary aren't written,
+    /// clients send them again with the next chunk.
+    /// The last chunk of an upload is never aligned.
+    /// If not set, alignment required by the storage is used.
+    #[arg(long, env = "RUSTUS_CHUNK_ALIGNMENT")]
+    pub chunk_alignment: Option<usize>,
+
+    /// Size in bytes of chunks that clients should send.
+    ///
+    /// It's returned in `Rustus-Preferred-Chunk-Size`
+    /// header of `OPTIONS` responses.
+    /// If not set, size preferred by the storage is used.
+    #[arg(long, env = "RUSTUS_PREFERRED_CHUNK_SIZE")]
+    pub preferred_chunk_size: Option<usize>,
+
+    /// Maximum number of bytes for all uploads.
+    ///
+    /// If total size of uploads exceeds this value,
+    /// the oldest finished uploads are removed.
+    /// Unfinished uploads are never removed.
+    #[arg(long, env = "RUSTUS_STORAGE_BUDGET")]
+    pub storage_budget: Option<usize>,
+
+    /// Interval in seconds between storage budget checks.
+    #[arg(
+        long,
+        env = "RUSTUS_STORAGE_BUDGET_CHECK_INTERVAL",
+        default_value = "60"
+    )]
+    pub storage_budget_check_interval: u64,
+
+    /// Interval in seconds between compactions of storages.
+    ///
+    /// Compaction moves finished uploads out of fragmented
+    /// pack files and runs `VACUUM` on `SQLite` info storage.
+    /// It's disabled by default.
+    #[arg(long, env = "RUSTUS_COMPACTION_INTERVAL")]
+    pub compaction_interval: Option<u64>,
+
+    /// Fraction of removed data which triggers compaction.
+    #[arg(
+        long,
+        env = "RUSTUS_COMPACTION_THRESHOLD",
+        default_value = "0.5",
+        value_parser = parse_threshold
+    )]
+    pub compaction_threshold: f64,
+
+    /// Directory for replicas of uploads.
+    ///
+    /// If set, every upload is asynchronously
+    /// copied to this directory. Replication errors
+    /// never fail requests.
+    #[arg(long, env = "RUSTUS_REPLICATION_DATA_DIR")]
+    pub replication_data_dir: Option<PathBuf>,
+
+    /// Maximum number of pending replication tasks.
+    #[arg(long, env = "RUSTUS_REPLICATION_QUEUE_SIZE", default_value = "1000")]
+    pub replication_queue_size: usize,
+
+    /// Number of retries for failed replication tasks.
+    #[arg(long, env = "RUSTUS_REPLICATION_RETRIES", default_value = "3")]
+    pub replication_retries: usize,
+
+    /// Time in milliseconds to wait for a free slot
+    /// in replication queue. After this time task is dropped.
+    #[arg(long, env = "RUSTUS_REPLICATION_QUEUE_TIMEOUT", default_value = "1000")]
+    pub replication_queue_timeout: u64,
+
+    /// Directory for uploads created while the main storage is unavailable.
+    ///
+    /// If the main storage fails, new uploads are created
+    /// in this directory. Finished uploads are moved
+    /// to the main storage once it's available again.
+    #[arg(long, env = "RUSTUS_FAILOVER_DATA_DIR")]
+    pub failover_data_dir: Option<PathBuf>,
+
+    /// Time in seconds during which the main storage
+    /// isn't used for new uploads after it failed.
+    #[arg(long, env = "RUSTUS_FAILOVER_COOLDOWN", default_value = "30")]
+    pub failover_cooldown: u64,
+
+    /// Interval in seconds between moves of finished uploads
+    /// from the failover directory to the main storage.
+    #[arg(long, env = "RUSTUS_FAILOVER_RECONCILE_INTERVAL", default_value = "60")]
+    pub failover_reconcile_interval: u64,
+
+    /// Directory to export finished uploads to.
+    ///
+    /// If set, every finished upload is copied
+    /// to this directory in background.
+    #[arg(long, env = "RUSTUS_EXPORT_DIR")]
+    pub export_dir: Option<PathBuf>,
+
+    /// S3 bucket to export finished uploads to.
+    ///
+    /// Bucket is reached with S3 connection options,
+    /// such as `--s3-url` and credentials.
+    #[arg(
+        long,
+        env = "RUSTUS_EXPORT_S3_BUCKET",
+        conflicts_with = "export_dir",
+        requires = "s3_url",
+        requires = "s3_region"
+    )]
+    pub export_s3_bucket: Option<String>,
+
+    /// Number of retries for failed exports.
+    #[arg(long, env = "RUSTUS_EXPORT_RETRIES", default_value = "3")]
+    pub export_retries: usize,
+
+    /// Remove uploads after they are exported.
+    #[arg(long, env = "RUSTUS_EXPORT_REMOVE_ORIGINAL")]
+    pub export_remove_original: bool,
+
+    /// Command which derives a file from finished uploads.
+    ///
+    /// It's called with id of the upload, path to its contents,
+    /// path of the output file and information about the upload.
+    /// The output file becomes a new upload.
+    #[arg(long, env = "RUSTUS_DERIVE_COMMAND")]
+    pub derive_command: Option<String>,
+
+    /// Number of retries for failed derivations.
+    #[arg(long, env = "RUSTUS_DERIVE_RETRIES", default_value = "3")]
+    pub derive_retries: usize,
+
+    /// Maximum time of a derive command in seconds.
+    ///
+    /// Commands which run longer are killed
+    /// and their derivations are retried.
+    #[arg(long, env = "RUSTUS_DERIVE_TIMEOUT", default_value = "600")]
+    pub derive_timeout: u64,
+
+    /// Number of derivations which run at once.
+    ///
+    /// Every derivation copies the upload to a temporary directory,
+    /// so other derivations wait for their turn.
+    #[arg(long, env = "RUSTUS_DERIVE_CONCURRENCY", default_value = "2")]
+    pub derive_concurrency: usize,
+}
+
+impl StorageOptions {
+    /// Permissions of files and directories created by file-storage.
+    #[must_use]
+    pub fn permissions(&self) -> Permissions {
+        Permissions {
+            file_mode: self.file_mode,
+            dir_mode: self.dir_mode,
+            group: self.file_group,
+        }
+    }
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -153,17 +520,66 @@ pub struct InfoStoreOptions {
     /// are `Postgres`, `MySQL` or `Redis`.
     ///
     /// Value must include all connection details.
+    /// It's required for these storages, unless
+    /// `--info-db-dsn-path` is set.
     #[cfg(any(feature = "redis_info_storage", feature = "db_info_storage"))]
+    #[arg(long, env = "RUSTUS_INFO_DB_DSN")]
+    pub info_db_dsn: Option<String>,
+
+    /// Path to file with connection string for info storage.
+    #[cfg(any(feature = "redis_info_storage", feature = "db_info_storage"))]
+    #[arg(long, env = "RUSTUS_INFO_DB_DSN_PATH")]
+    pub info_db_dsn_path: Option<PathBuf>,
+
+    /// Metadata keys stored in separate indexed columns.
+    ///
+    /// Uploads can be found by these keys
+    /// without reading all uploads. Keys may contain
+    /// only lowercase letters, digits and underscores.
+    #[cfg(feature = "db_info_storage")]
     #[arg(
         long,
-        required_if_eq_any([("info_storage", "db-info-storage"), ("info_storage", "redis-info-storage")]),
-        env = "RUSTUS_INFO_DB_DSN"
+        env = "RUSTUS_INFO_DB_INDEXED_METADATA",
+        use_value_delimiter = true
     )]
-    pub info_db_dsn: Option<String>,
+    pub info_db_indexed_metadata: Vec<String>,
+
+    /// Limits of distinct values of indexed metadata keys.
+    ///
+    /// Limits are set as `key=limit` pairs separated by commas.
+    /// Uploads with new values of the key are rejected
+    /// once the limit is reached.
+    #[cfg(feature = "db_info_storage")]
+    #[arg(
+        long,
+        env = "RUSTUS_INFO_DB_CARDINALITY_LIMITS",
+        use_value_delimiter = true
+    )]
+    pub info_db_cardinality_limits: Vec<CardinalityLimit>,
+
+    /// Compress information about uploads with zstd.
+    ///
+    /// Uncompressed rows are still read,
+    /// they are compressed once updated.
+    #[cfg(feature = "db_info_storage")]
+    #[arg(long, env = "RUSTUS_INFO_DB_COMPRESSION")]
+    pub info_db_compression: bool,
 
     #[cfg(feature = "redis_info_storage")]
     #[arg(long, env = "RUSTUS_REDIS_INFO_EXPIRATION")]
     pub redis_info_expiration: Option<usize>,
+
+    /// Timeout in milliseconds for reading from info storage.
+    ///
+    /// If not set, reads are not limited.
+    #[arg(long, env = "RUSTUS_INFO_STORAGE_READ_TIMEOUT")]
+    pub info_storage_read_timeout: Option<u64>,
+
+    /// Timeout in milliseconds for writing to info storage.
+    ///
+    /// If not set, writes are not limited.
+    #[arg(long, env = "RUSTUS_INFO_STORAGE_WRITE_TIMEOUT")]
+    pub info_storage_write_timeout: Option<u64>,
 }
 #[derive(Parser, Debug, Clone)]
 #[allow(clippy::struct_excessive_bools)]
@@ -174,6 +590,11 @@ pub struct AMQPHooksOptions {
     #[arg(long, env = "RUSTUS_HOOKS_AMQP_URL")]
     pub hooks_amqp_url: Option<String>,
 
+    /// Path to file with url for AMQP server.
+    #[cfg(feature = "amqp_notifier")]
+    #[arg(long, env = "RUSTUS_HOOKS_AMQP_URL_PATH")]
+    pub hooks_amqp_url_path: Option<PathBuf>,
+
     /// Rustus will create exchange if enabled.
     #[cfg(feature = "amqp_notifier")]
     #[arg(long, env = "RUSTUS_HOOKS_AMQP_DECLARE_EXCHANGE")]
@@ -272,11 +693,6 @@ pub struct NotificationsOptions {
     )]
     pub hooks: Vec<Hook>,
 
-    /// Use this option if you use rustus
-    /// behind any proxy. Like Nginx or Traefik.
-    #[arg(long, env = "RUSTUS_BEHIND_PROXY")]
-    pub behind_proxy: bool,
-
     /// List of URLS to send webhooks to.
     #[arg(long, env = "RUSTUS_HOOKS_HTTP_URLS", use_value_delimiter = true)]
     pub hooks_http_urls: Vec<String>,
@@ -293,6 +709,14 @@ pub struct NotificationsOptions {
     )]
     pub hooks_http_proxy_headers: Vec<String>,
 
+    /// Compression of HTTP hook payloads.
+    ///
+    /// `auto` compresses payloads only for endpoints
+    /// which advertised supported encodings
+    /// with `Accept-Encoding` header.
+    #[arg(long, env = "RUSTUS_HOOKS_HTTP_COMPRESSION")]
+    pub hooks_http_compression: Option<Compression>,
+
     /// Directory for executable hook files.
     /// This parameter is used to call executables from dir.
     #[arg(long, env = "RUSTUS_HOOKS_DIR")]
@@ -303,15 +727,204 @@ pub struct NotificationsOptions {
     #[arg(long, env = "RUSTUS_HOOKS_FILE")]
     pub hooks_file: Option<String>,
 
+    /// Record all hooks in memory.
+    ///
+    /// Recorded hooks are available with the admin API.
+    /// This option is intended for hooks development,
+    /// don't use it in production.
+    #[arg(long, env = "RUSTUS_HOOKS_DEBUG")]
+    pub hooks_debug: bool,
+
+    /// Maximum number of hooks recorded in memory.
+    #[arg(long, env = "RUSTUS_HOOKS_DEBUG_CAPACITY", default_value = "100")]
+    pub hooks_debug_capacity: usize,
+
+    /// Apply changes returned by pre-create hooks.
+    ///
+    /// Responses must be JSON objects with
+    /// `reject`, `reason`, `metadata` and `length` fields.
+    #[arg(long, env = "RUSTUS_HOOKS_PRE_CREATE_PATCH")]
+    pub hooks_pre_create_patch: bool,
+
+    /// Number of retries for failed informational hooks.
+    ///
+    /// Hooks that block requests, like pre-create,
+    /// are never retried.
+    #[arg(long, env = "RUSTUS_HOOKS_RETRIES", default_value = "0")]
+    pub hooks_retries: usize,
+
+    /// Delay in milliseconds before the first retry of a hook.
+    ///
+    /// The delay is doubled after every failed attempt.
+    #[arg(long, env = "RUSTUS_HOOKS_RETRY_BACKOFF", default_value = "1000")]
+    pub hooks_retry_backoff: u64,
+
+    /// Number of retries for failed terminate-cleanup hooks.
+    ///
+    /// These hooks are sent before the response
+    /// of termination, so they're retried separately
+    /// from informational hooks.
+    #[arg(long, env = "RUSTUS_HOOKS_CLEANUP_RETRIES", default_value = "5")]
+    pub hooks_cleanup_retries: usize,
+
+    /// Minimal interval in milliseconds between post-receive hooks of an upload.
+    ///
+    /// Hooks sent in between are coalesced,
+    /// only the latest one is delivered after the interval.
+    #[arg(long, env = "RUSTUS_HOOKS_RECEIVE_INTERVAL", default_value = "0")]
+    pub hooks_receive_interval: u64,
+
+    /// Number of received bytes after which post-receive hook is sent.
+    ///
+    /// With the receive interval, hook is sent
+    /// when either of them is reached.
+    #[arg(long, env = "RUSTUS_HOOKS_RECEIVE_BYTES", default_value = "0")]
+    pub hooks_receive_bytes: usize,
+
+    /// Maximum number of create-rejected hooks per second.
+    ///
+    /// Hooks of other rejections are dropped,
+    /// only their number is logged. Zero disables the limit.
+    #[arg(long, env = "RUSTUS_HOOKS_REJECTED_RATE", default_value = "10")]
+    pub hooks_rejected_rate: usize,
+
+    /// File to store hooks which failed after all retries.
+    ///
+    /// Every line of the file is a JSON object
+    /// with the hook name and the message.
+    #[arg(long, env = "RUSTUS_HOOKS_DEAD_LETTER_FILE")]
+    pub hooks_dead_letter_file: Option<PathBuf>,
+
+    /// Metadata key with callback URL of an upload.
+    ///
+    /// Post-finish hook of the upload is
+    /// sent to this URL as well.
+    /// Callbacks are disabled if it's not set.
+    #[arg(long, env = "RUSTUS_HOOKS_CALLBACK_META_KEY")]
+    pub hooks_callback_meta_key: Option<String>,
+
+    /// Allowed schemes of callback URLs.
+    #[arg(
+        long,
+        env = "RUSTUS_HOOKS_CALLBACK_SCHEMES",
+        default_value = "https",
+        use_value_delimiter = true
+    )]
+    pub hooks_callback_schemes: Vec<String>,
+
+    /// Allowed hosts of callback URLs.
+    ///
+    /// Wildcards like `*.example.com` can be used.
+    /// If it's empty, any host is allowed unless
+    /// it resolves to a private or loopback address.
+    #[arg(long, env = "RUSTUS_HOOKS_CALLBACK_HOSTS", use_value_delimiter = true)]
+    pub hooks_callback_hosts: Vec<String>,
+
+    /// Send post-finish hook only to the callback URL.
+    ///
+    /// Only uploads with callback URLs are affected.
+    #[arg(long, env = "RUSTUS_HOOKS_CALLBACK_EXCLUSIVE")]
+    pub hooks_callback_exclusive: bool,
+
     #[command(flatten)]
     pub amqp_hook_opts: AMQPHooksOptions,
 }
 
+#[derive(Parser, Debug, Clone)]
+pub struct ClientIpOptions {
+    /// Use this option if you use rustus
+    /// behind any proxy. Like Nginx or Traefik.
+    ///
+    /// Without trusted proxies, forwarded headers
+    /// of all clients are trusted.
+    #[arg(long, env = "RUSTUS_BEHIND_PROXY")]
+    pub behind_proxy: bool,
+
+    /// Sources of client's IP in order of priority.
+    ///
+    /// Possible values are "peer", "forwarded", "x-real-ip"
+    /// and "x-forwarded-for:<position>", where position
+    /// is counted from the right, starting with 1.
+    /// Addresses of trusted proxies are skipped.
+    /// Peer address is used if no source has a valid IP.
+    /// By default it's "forwarded" behind proxy and "peer" otherwise.
+    #[arg(long, env = "RUSTUS_CLIENT_IP_SOURCES", use_value_delimiter = true)]
+    pub client_ip_sources: Vec<IpSource>,
+
+    /// Networks of proxies which can set client's IP.
+    ///
+    /// Example: `10.0.0.0/8,fd00::/8`.
+    /// Headers of other clients are ignored.
+    #[arg(long, env = "RUSTUS_TRUSTED_PROXIES", use_value_delimiter = true)]
+    pub trusted_proxies: Vec<TrustedProxy>,
+}
+
+impl ClientIpOptions {
+    /// Check if headers of the request are set by a trusted proxy.
+    #[must_use]
+    pub fn is_trusted(&self, request: &HttpRequest) -> bool {
+        proxy::is_trusted(request, &self.trusted_proxies, self.behind_proxy)
+    }
+
+    /// Resolve IP of the client who sent the request.
+    #[must_use]
+    pub fn resolve(&self, request: &HttpRequest) -> Option<String> {
+        let proxied = self.behind_proxy || !self.trusted_proxies.is_empty();
+        let sources = if self.client_ip_sources.is_empty() && proxied {
+            &[IpSource::Forwarded]
+        } else {
+            self.client_ip_sources.as_slice()
+        };
+        let ip = proxy::resolve(request, sources, &self.trusted_proxies, self.behind_proxy);
+        ip.map(|ip| client_ip(ip.to_string().as_str()))
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct AuthOptions {
+    /// URL of `OAuth2` token introspection endpoint.
+    ///
+    /// If set, every request must have a bearer token,
+    /// which is validated by this endpoint as described in RFC 7662.
+    /// Subject of the token becomes the owner of uploads.
+    #[arg(long, env = "RUSTUS_AUTH_INTROSPECTION_URL")]
+    pub auth_introspection_url: Option<String>,
+
+    /// Client ID used to authenticate at the introspection endpoint.
+    #[arg(long, env = "RUSTUS_AUTH_CLIENT_ID")]
+    pub auth_client_id: Option<String>,
+
+    /// Client secret used to authenticate at the introspection endpoint.
+    #[arg(long, env = "RUSTUS_AUTH_CLIENT_SECRET")]
+    pub auth_client_secret: Option<String>,
+
+    /// Path to file with client secret of the introspection endpoint.
+    #[arg(long, env = "RUSTUS_AUTH_CLIENT_SECRET_PATH")]
+    pub auth_client_secret_path: Option<PathBuf>,
+
+    /// Scopes which tokens must have.
+    ///
+    /// Example: "uploads,uploads:write".
+    /// Requests with tokens without them are rejected with `403 Forbidden`.
+    #[arg(long, env = "RUSTUS_AUTH_REQUIRED_SCOPES", use_value_delimiter = true)]
+    pub auth_required_scopes: Vec<String>,
+
+    /// Time in seconds results of introspection are cached.
+    ///
+    /// Tokens aren't cached after they expire.
+    #[arg(long, env = "RUSTUS_AUTH_CACHE_TTL", default_value = "60")]
+    pub auth_cache_ttl: u64,
+}
+
 #[derive(Debug, Parser, Clone)]
 pub struct SentryOptions {
     #[arg(name = "sentry-dsn", long, env = "RUSTUS_SENTRY_DSN")]
     pub dsn: Option<String>,
 
+    #[arg(name = "sentry-dsn-path", long, env = "RUSTUS_SENTRY_DSN_PATH")]
+    pub dsn_path: Option<PathBuf>,
+
     #[arg(
         name = "sentry-sample-rate",
         long,
@@ -321,8 +934,100 @@ pub struct SentryOptions {
     pub sample_rate: f32,
 }
 
+#[derive(Parser, Debug, Clone)]
+pub struct TracingOptions {
+    /// URL of OTLP/HTTP endpoint which receives traces.
+    ///
+    /// Spans are sent in protobuf encoding,
+    /// e.g. to `http://localhost:4318/v1/traces`.
+    /// Tracing is disabled if it's not set.
+    #[cfg(feature = "otlp_tracing")]
+    #[arg(long, env = "RUSTUS_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Fraction of new traces which are sampled.
+    ///
+    /// Requests with `traceparent` header
+    /// are sampled if the caller sampled them.
+    #[cfg(feature = "otlp_tracing")]
+    #[arg(
+        long,
+        env = "RUSTUS_OTLP_SAMPLING_RATIO",
+        default_value = "1.0",
+        value_parser = crate::telemetry::parse_ratio
+    )]
+    pub otlp_sampling_ratio: f64,
+
+    /// Name of the service in exported traces.
+    #[cfg(feature = "otlp_tracing")]
+    #[arg(long, env = "RUSTUS_OTLP_SERVICE_NAME", default_value = "rustus")]
+    pub otlp_service_name: String,
+}
+
+/// Commands which are run instead of the server.
+#[derive(Debug, Subcommand, Clone)]
+pub enum Command {
+    /// Check the storage by uploading, reading and removing a small file.
+    ///
+    /// It exits with non-zero code if any operation fails.
+    Selftest,
+    /// Write report about uploads from info storage.
+    ///
+    /// Report has id, tenant, size, creation time
+    /// and status of every upload.
+    Export(ReportOptions),
+    /// Save all uploads of the storage to a tar archive.
+    ///
+    /// Every upload is saved with information about it,
+    /// unfinished uploads are saved up to their offset.
+    Backup(BackupOptions),
+    /// Recreate uploads from an archive of `backup` command.
+    ///
+    /// Uploads which already exist are skipped.
+    Restore(RestoreOptions),
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct BackupOptions {
+    /// File for the archive.
+    #[arg(long, short)]
+    pub output: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct RestoreOptions {
+    /// Archive created by `backup` command.
+    #[arg(long, short)]
+    pub input: PathBuf,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct ReportOptions {
+    /// Format of the report: `csv` or `json`.
+    #[arg(long, default_value = "csv")]
+    pub format: ReportFormat,
+
+    /// Include uploads created at this time or later.
+    ///
+    /// Time can be a day, like `2024-01-31`,
+    /// or a time in RFC 3339 format.
+    #[arg(long, value_parser = parse_date)]
+    pub since: Option<DateTime<Utc>>,
+
+    /// Include uploads created before this time.
+    #[arg(long, value_parser = parse_date)]
+    pub until: Option<DateTime<Utc>>,
+
+    /// File for the report.
+    ///
+    /// Report is written to stdout by default.
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+}
+
 #[derive(Debug, Parser, Clone)]
 #[command(name = "Rustus")]
+#[allow(clippy::struct_excessive_bools)]
 /// Tus protocol implementation.
 ///
 /// This program is a web-server that
@@ -332,6 +1037,9 @@ pub struct SentryOptions {
 /// [here](https://tus.io/).
 pub struct RustusConf {
     /// Rustus server host
+    ///
+    /// IPv6 addresses can be written with or without brackets,
+    /// e.g. `::` or `[::1]`.
     #[arg(long, default_value = "0.0.0.0", env = "RUSTUS_SERVER_HOST")]
     pub host: String,
 
@@ -339,9 +1047,79 @@ pub struct RustusConf {
     #[arg(long, default_value = "1081", env = "RUSTUS_SERVER_PORT")]
     pub port: u16,
 
+    /// Accept IPv4 connections on IPv6 addresses.
+    ///
+    /// With `--host "::"` rustus listens on all
+    /// IPv4 and IPv6 addresses. Without it IPv6 sockets
+    /// use the default of the system.
+    #[arg(long, env = "RUSTUS_DUAL_STACK")]
+    pub dual_stack: bool,
+
     #[arg(long, env = "RUSTUS_DISABLE_HEALTH_ACCESS_LOG")]
     pub disable_health_access_log: bool,
 
+    /// Disable information about server at the root URL, `/info` and `/version`.
+    ///
+    /// If disabled, these URLs return 404 as any unknown URL.
+    #[arg(long, env = "RUSTUS_DISABLE_ROOT_INFO")]
+    pub disable_root_info: bool,
+
+    /// Path to unix domain socket to listen on.
+    ///
+    /// Server listens on the socket in addition
+    /// to TCP address. Socket is removed on shutdown.
+    #[arg(long, env = "RUSTUS_UNIX_SOCKET")]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Octal mode of unix domain socket, e.g. "660".
+    ///
+    /// By default it's derived from umask.
+    #[arg(long, env = "RUSTUS_UNIX_SOCKET_MODE", value_parser = parse_mode)]
+    pub unix_socket_mode: Option<u32>,
+
+    /// Listen only on unix domain socket.
+    ///
+    /// TCP address isn't bound if this option is enabled.
+    #[arg(long, env = "RUSTUS_UNIX_SOCKET_ONLY", requires = "unix_socket")]
+    pub unix_socket_only: bool,
+
+    /// Path to TLS certificate chain in PEM format.
+    ///
+    /// If provided, rustus serves HTTPS.
+    /// HTTP/2 is negotiated with clients if `--http2` is enabled.
+    #[arg(long, env = "RUSTUS_TLS_CERT", requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Path to private key for TLS certificate in PEM format.
+    #[arg(long, env = "RUSTUS_TLS_KEY", requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Minimum version of TLS protocol.
+    ///
+    /// Supported versions are "1.2" and "1.3".
+    #[arg(long, env = "RUSTUS_TLS_MIN_VERSION", default_value = "1.2")]
+    pub tls_min_version: TlsVersion,
+
+    /// Allowed ciphers of TLS 1.2 in OpenSSL format.
+    ///
+    /// By default secure ciphers of Mozilla intermediate profile are allowed.
+    #[arg(long, env = "RUSTUS_TLS_CIPHERS", requires = "tls_cert")]
+    pub tls_ciphers: Option<String>,
+
+    /// Allowed cipher suites of TLS 1.3 separated by colons.
+    ///
+    /// By default all cipher suites of TLS 1.3 are allowed.
+    #[arg(long, env = "RUSTUS_TLS_CIPHERSUITES", requires = "tls_cert")]
+    pub tls_ciphersuites: Option<String>,
+
+    /// Enable HTTP/2.
+    ///
+    /// With TLS it's negotiated with clients over ALPN,
+    /// without TLS cleartext HTTP/2 (h2c) with prior knowledge is accepted.
+    /// HTTP/1.1 clients work as usual.
+    #[arg(long, env = "RUSTUS_HTTP2")]
+    pub http2: bool,
+
     /// Rustus base API url
     #[arg(long, default_value = "/files", env = "RUSTUS_URL")]
     pub url: String,
@@ -363,6 +1141,19 @@ pub struct RustusConf {
     )]
     pub max_body_size: usize,
 
+    /// Maximum size of request headers in bytes.
+    ///
+    /// Requests with bigger headers are rejected with 431
+    /// and a message which names the biggest header.
+    /// It can't be greater than 131072, the limit of the HTTP server.
+    #[arg(
+        long,
+        default_value = "131072",
+        env = "RUSTUS_MAX_HEADER_SIZE",
+        value_parser = parse_header_size
+    )]
+    pub max_header_size: usize,
+
     /// Rustus maximum log level
     #[arg(long, default_value = "INFO", env = "RUSTUS_LOG_LEVEL")]
     pub log_level: log::LevelFilter,
@@ -371,6 +1162,41 @@ pub struct RustusConf {
     #[arg(long, short, env = "RUSTUS_WORKERS")]
     pub workers: Option<usize>,
 
+    /// Pin every actix worker to a separate CPU core.
+    ///
+    /// If there are more workers than cores,
+    /// workers share cores in round-robin order.
+    #[arg(long, env = "RUSTUS_PIN_WORKERS")]
+    pub pin_workers: bool,
+
+    /// Maximum number of threads for blocking operations.
+    ///
+    /// This number is divided between all workers.
+    /// Blocking threads are used by storages for disk operations.
+    /// Default value is 512.
+    #[arg(long, env = "RUSTUS_MAX_BLOCKING_THREADS")]
+    pub max_blocking_threads: Option<usize>,
+
+    /// Time in seconds to keep idle connections open.
+    ///
+    /// Zero disables keep-alive, it's the default.
+    #[arg(long, env = "RUSTUS_KEEP_ALIVE", default_value = "0")]
+    pub keep_alive: u64,
+
+    /// Time in milliseconds to receive headers of a request.
+    ///
+    /// Bodies of requests aren't limited by this timeout,
+    /// so slow clients can still upload big chunks.
+    #[arg(long, env = "RUSTUS_CLIENT_REQUEST_TIMEOUT", default_value = "10000")]
+    pub client_request_timeout: u64,
+
+    /// Maximum number of connections per worker.
+    ///
+    /// New connections wait until others are closed.
+    /// Default value is 25000.
+    #[arg(long, env = "RUSTUS_MAX_CONNECTIONS")]
+    pub max_connections: Option<usize>,
+
     /// Enabled extensions for TUS protocol.
     #[arg(
         long,
@@ -386,6 +1212,126 @@ pub struct RustusConf {
     #[arg(long, env = "RUSTUS_ALLOW_EMPTY")]
     pub allow_empty: bool,
 
+    /// Require length of new uploads.
+    ///
+    /// It disables creation-defer-length extension,
+    /// so creation requests without Upload-Length
+    /// are rejected with 400.
+    #[arg(long, env = "RUSTUS_REQUIRE_LENGTH")]
+    pub require_length: bool,
+
+    /// Allow clients to restart uploads.
+    ///
+    /// By default PATCH request with zero offset
+    /// to an upload that already has bytes is rejected with 409.
+    /// With this option enabled such request truncates
+    /// the upload and writes bytes from the beginning.
+    #[arg(long, env = "RUSTUS_ALLOW_RESTART")]
+    pub allow_restart: bool,
+
+    /// Verify received bytes before uploads are finished.
+    ///
+    /// Upload is finished only if received ranges cover
+    /// its whole length without gaps and overlaps.
+    /// Otherwise the last chunk is rejected with 409
+    /// and the response lists missing ranges.
+    #[arg(long, env = "RUSTUS_VERIFY_COVERAGE")]
+    pub verify_coverage: bool,
+
+    /// Verify size of stored data before uploads are finished.
+    ///
+    /// Size of the data is checked with the storage.
+    /// If it doesn't match the length, the last chunk
+    /// is rejected with 500 and the upload can be resumed.
+    ///
+    /// Storages which can't check the size skip it.
+    #[arg(long, env = "RUSTUS_VERIFY_FINISHED_SIZE")]
+    pub verify_finished_size: bool,
+
+    /// Accept chunks with `application/octet-stream` content type.
+    ///
+    /// TUS requires `application/offset+octet-stream`,
+    /// but some clients send this content type instead.
+    #[arg(long, env = "RUSTUS_LENIENT_CONTENT_TYPE")]
+    pub lenient_content_type: bool,
+
+    /// Maximum number of chunks written to one upload at the same time.
+    ///
+    /// PATCH requests over the limit are rejected with 429.
+    /// Storages which only append bytes need at most 1.
+    /// If not set, the number isn't limited.
+    #[arg(long, env = "RUSTUS_MAX_CONCURRENT_CHUNKS")]
+    pub max_concurrent_chunks: Option<usize>,
+
+    /// Maximum time in seconds since the last write
+    /// after which an upload can't be resumed.
+    ///
+    /// PATCH request to such upload returns 410
+    /// and the upload is removed, so the client must start again.
+    /// Finished uploads are never removed.
+    #[arg(long, env = "RUSTUS_MAX_RESUME_AGE")]
+    pub max_resume_age: Option<u64>,
+
+    /// Time in seconds to remember idempotency keys of chunks.
+    ///
+    /// PATCH request with `Idempotency-Key` header
+    /// which was already written to the upload
+    /// is acknowledged without writing bytes again.
+    #[arg(long, env = "RUSTUS_CHUNK_TOKEN_TTL")]
+    pub chunk_token_ttl: Option<u64>,
+
+    /// Status of responses to creation requests.
+    ///
+    /// TUS requires 201, but some legacy clients
+    /// expect 200 or 204. Only these statuses are allowed.
+    #[arg(
+        long,
+        default_value = "201",
+        env = "RUSTUS_CREATION_STATUS",
+        value_parser = parse_creation_status
+    )]
+    pub creation_status: StatusCode,
+
+    /// Make termination idempotent.
+    ///
+    /// By default DELETE request of an unknown upload
+    /// returns 404. With this option enabled it returns 204,
+    /// so a repeated DELETE isn't an error.
+    #[arg(long, env = "RUSTUS_IDEMPOTENT_TERMINATION")]
+    pub idempotent_termination: bool,
+
+    /// Keep tombstones of removed uploads for this number of seconds.
+    ///
+    /// HEAD and PATCH requests of terminated or expired
+    /// uploads return 410 instead of 404 until
+    /// the tombstone expires. Tombstones aren't kept by default.
+    #[arg(long, env = "RUSTUS_TOMBSTONE_TTL")]
+    pub tombstone_ttl: Option<u64>,
+
+    /// Keep terminated uploads for this number of seconds.
+    ///
+    /// Terminated uploads are moved to the trash
+    /// and can be restored with admin API until
+    /// the grace period is over. After that their data
+    /// is removed. Uploads are removed right away by default.
+    #[arg(long, env = "RUSTUS_TERMINATION_GRACE_PERIOD")]
+    pub termination_grace_period: Option<u64>,
+
+    /// Enable uploads with `multipart/form-data` requests.
+    ///
+    /// It's a compatibility endpoint for clients which can't use TUS.
+    /// Files can be uploaded with POST request to `{url}/multipart`.
+    #[arg(long, env = "RUSTUS_MULTIPART_UPLOADS")]
+    pub multipart_uploads: bool,
+
+    /// Enable websocket endpoint with progress of uploads.
+    ///
+    /// Progress is sent for uploads which
+    /// are written to by the same rustus process.
+    #[cfg(feature = "progress_websocket")]
+    #[arg(long, env = "RUSTUS_PROGRESS_WEBSOCKET")]
+    pub progress_websocket: bool,
+
     /// Remove part files after concatenation is done.
     /// By default rustus does nothing with part files after concatenation.
     ///
@@ -393,12 +1339,350 @@ pub struct RustusConf {
     #[arg(long, env = "RUSTUS_REMOVE_PARTS")]
     pub remove_parts: bool,
 
+    /// Concatenate parts of final uploads in background.
+    ///
+    /// Final uploads are created right away with the total length
+    /// and they are finished once all parts are concatenated.
+    #[arg(long, env = "RUSTUS_ASYNC_CONCAT")]
+    pub async_concat: bool,
+
+    /// Response to HEAD requests of final uploads which are being assembled.
+    ///
+    /// "progress" reports the length of the upload and the size
+    /// of concatenated data, "too-early" responds with `425 Too Early`.
+    #[arg(long, env = "RUSTUS_ASSEMBLY_RESPONSE", default_value = "progress")]
+    pub assembly_response: AssemblyResponse,
+
+    /// Remove partial uploads which weren't written for this number of seconds.
+    ///
+    /// Parts of unfinished final uploads and parts
+    /// which are being concatenated are kept.
+    /// Partial uploads aren't removed by default.
+    #[arg(long, env = "RUSTUS_PARTIAL_UPLOAD_TTL")]
+    pub partial_upload_ttl: Option<u64>,
+
+    /// Interval in seconds between removals of expired partial uploads.
+    #[arg(long, env = "RUSTUS_PARTIAL_CLEANUP_INTERVAL", default_value = "60")]
+    pub partial_cleanup_interval: u64,
+
+    /// Remove uploads without any written bytes
+    /// which weren't changed for this number of seconds.
+    ///
+    /// It's meant for uploads which clients created and abandoned,
+    /// so it's usually much shorter than other TTLs.
+    /// Empty uploads aren't removed by default.
+    #[arg(long, env = "RUSTUS_EMPTY_UPLOAD_TTL")]
+    pub empty_upload_ttl: Option<u64>,
+
+    /// Interval in seconds between removals of abandoned empty uploads.
+    #[arg(long, env = "RUSTUS_EMPTY_CLEANUP_INTERVAL", default_value = "60")]
+    pub empty_cleanup_interval: u64,
+
+    /// Remove information about uploads whose data is missing.
+    ///
+    /// Such uploads are detected when someone
+    /// tries to download them or to write to them.
+    #[arg(long, env = "RUSTUS_REMOVE_ORPHAN_INFO")]
+    pub remove_orphan_info: bool,
+
+    /// Check consistency of all uploads before the server is started.
+    ///
+    /// Every upload must have data with at least
+    /// as many bytes as its offset.
+    #[arg(long, env = "RUSTUS_STARTUP_SCAN")]
+    pub startup_scan: bool,
+
+    /// Repair uploads found by the startup scan.
+    ///
+    /// Information about uploads without data is removed
+    /// and offsets of truncated uploads are lowered
+    /// to the size of their data.
+    #[arg(long, env = "RUSTUS_STARTUP_REPAIR")]
+    pub startup_repair: bool,
+
+    /// Uploads updated during this number of seconds
+    /// aren't repaired by the startup scan.
+    ///
+    /// Info storage can be shared by several instances,
+    /// which may write these uploads right now.
+    #[arg(long, env = "RUSTUS_STARTUP_REPAIR_MIN_AGE", default_value = "300")]
+    pub startup_repair_min_age: u64,
+
+    /// Maximum number of uploads checked at once by the startup scan.
+    #[arg(long, env = "RUSTUS_STARTUP_SCAN_CONCURRENCY", default_value = "16")]
+    pub startup_scan_concurrency: usize,
+
+    /// Compute sha256 checksum of finished uploads.
+    ///
+    /// Checksum is saved in upload's info
+    /// and sent in post-finish hook.
+    #[arg(long, env = "RUSTUS_UPLOAD_CHECKSUM")]
+    pub upload_checksum: bool,
+
+    /// Return relative `Location` header after creation.
+    ///
+    /// By default the `Location` header contains absolute URL
+    /// with scheme and host. Host and scheme are resolved using
+    /// `Forwarded`, `X-Forwarded-Host` and `X-Forwarded-Proto` headers if present.
+    /// With this option enabled only the path is returned.
+    #[arg(long, env = "RUSTUS_RELATIVE_LOCATION")]
+    pub relative_location: bool,
+
+    /// Metadata key which identifies tenant of an upload.
+    ///
+    /// Tenant quotas are applied to uploads
+    /// grouped by the value of this key.
+    #[arg(long, env = "RUSTUS_TENANT_METADATA_KEY", default_value = "tenant")]
+    pub tenant_metadata_key: String,
+
+    /// Quotas for tenants in bytes.
+    ///
+    /// Example: "acme=1073741824,umbrella=2147483648".
+    #[arg(long, env = "RUSTUS_TENANT_QUOTAS", use_value_delimiter = true)]
+    pub tenant_quotas: Vec<TenantQuota>,
+
+    /// Quota in bytes for tenants which aren't listed in tenant quotas.
+    ///
+    /// If not set, such tenants are unlimited.
+    #[arg(long, env = "RUSTUS_DEFAULT_TENANT_QUOTA")]
+    pub default_tenant_quota: Option<usize>,
+
+    /// Time in seconds usage of a tenant is cached.
+    ///
+    /// Bytes of new uploads are added to the cached usage,
+    /// removed uploads are noticed when it's computed again.
+    #[arg(long, env = "RUSTUS_TENANT_USAGE_TTL", default_value = "60")]
+    pub tenant_usage_ttl: u64,
+
+    /// Header with the subject of the authenticated client.
+    ///
+    /// It must be set by an authenticating proxy and it's used
+    /// only if the proxy is trusted, like forwarded headers.
+    /// If set, clients can list their unfinished uploads.
+    #[arg(long, env = "RUSTUS_OWNER_HEADER")]
+    pub owner_header: Option<String>,
+
+    /// Metadata key with the owner of the upload.
+    ///
+    /// Owner is set from the owner header,
+    /// values sent by clients are ignored.
+    #[arg(long, env = "RUSTUS_OWNER_METADATA_KEY", default_value = "owner")]
+    pub owner_metadata_key: String,
+
+    /// Buckets of finished uploads sizes histogram in bytes.
+    ///
+    /// Example: "1024,1048576,1073741824".
+    /// By default buckets are powers of two.
+    #[arg(long, env = "RUSTUS_FINISHED_SIZE_BUCKETS", use_value_delimiter = true)]
+    pub finished_size_buckets: Vec<f64>,
+
+    /// Enable admin API.
+    ///
+    /// Admin API is available at `/admin` and
+    /// it must not be exposed to users.
+    #[arg(long, env = "RUSTUS_ADMIN_API")]
+    pub admin_api: bool,
+
+    /// Token required to use admin API.
+    ///
+    /// It must be passed in `Authorization: Bearer` header.
+    /// Admin API can't be enabled without it.
+    #[arg(long, env = "RUSTUS_ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// Path to file with token required to use admin API.
+    #[arg(long, env = "RUSTUS_ADMIN_TOKEN_PATH")]
+    pub admin_token_path: Option<PathBuf>,
+
+    /// Value of `Retry-After` header in seconds
+    /// for uploads rejected in drain mode.
+    ///
+    /// Drain mode is toggled with admin API.
+    /// It's also sent with other `503` responses.
+    #[arg(long, env = "RUSTUS_DRAIN_RETRY_AFTER", default_value = "60")]
+    pub drain_retry_after: u64,
+
+    /// Value of `Retry-After` header in seconds
+    /// for requests rejected with `429`.
+    ///
+    /// For example, chunks over `--max-concurrent-chunks`.
+    #[arg(long, env = "RUSTUS_RATE_LIMIT_RETRY_AFTER", default_value = "1")]
+    pub rate_limit_retry_after: u64,
+
+    /// Value of `Retry-After` header in seconds
+    /// for requests rejected with `507`.
+    ///
+    /// It's returned if the storage has no space left.
+    #[arg(long, env = "RUSTUS_STORAGE_FULL_RETRY_AFTER", default_value = "300")]
+    pub storage_full_retry_after: u64,
+
+    /// Directories from which files can be imported.
+    ///
+    /// Files are imported with admin API.
+    /// Other paths are rejected, so only
+    /// these directories are exposed.
+    #[arg(long, env = "RUSTUS_IMPORT_DIRS", use_value_delimiter = true)]
+    pub import_dirs: Vec<PathBuf>,
+
+    /// Token required to import files.
+    ///
+    /// It must be passed in `Authorization: Bearer` header.
+    /// Import is disabled until both token and
+    /// import directories are set.
+    #[arg(long, env = "RUSTUS_IMPORT_TOKEN")]
+    pub import_token: Option<String>,
+
+    /// Path to file with token required to import files.
+    #[arg(long, env = "RUSTUS_IMPORT_TOKEN_PATH")]
+    pub import_token_path: Option<PathBuf>,
+
     /// Maximum size of file that can be uploaded.
     ///
     /// If not set, file size is unlimited.
     #[arg(long, env = "RUSTUS_MAX_FILE_SIZE")]
     pub max_file_size: Option<usize>,
 
+    /// Normalizers for metadata of new uploads.
+    ///
+    /// They are applied in the given order
+    /// before metadata is validated and stored.
+    #[arg(long, env = "RUSTUS_METADATA_NORMALIZERS", use_value_delimiter = true)]
+    pub metadata_normalizers: Vec<MetadataNormalizer>,
+
+    /// Aliases for metadata keys.
+    ///
+    /// Aliased keys are renamed after normalization.
+    /// Example: "name=filename,title=filename".
+    #[arg(long, env = "RUSTUS_METADATA_ALIASES", use_value_delimiter = true)]
+    pub metadata_aliases: Vec<MetadataAlias>,
+
+    /// Metadata keys which are set by the server.
+    ///
+    /// Values are templates rendered for every new upload,
+    /// they replace values sent by clients.
+    /// Example: `ingest_node={env:HOSTNAME},received_at={datetime}`.
+    #[arg(long, env = "RUSTUS_SERVER_METADATA", use_value_delimiter = true)]
+    pub server_metadata: Vec<ServerMetadata>,
+
+    /// Patterns which metadata values of new uploads must match.
+    ///
+    /// Patterns are regular expressions separated by semicolons,
+    /// they must match whole values. Uploads with other values are rejected.
+    /// Example: "filename=[\w .-]{1,255};tenant=[a-z0-9-]+".
+    #[arg(long, env = "RUSTUS_METADATA_PATTERNS", value_delimiter = ';')]
+    pub metadata_patterns: Vec<MetadataPattern>,
+
+    /// Read metadata of new uploads from query parameters.
+    ///
+    /// Query is used only if `Upload-Metadata` header is absent.
+    /// It's not a part of TUS protocol and is meant
+    /// for clients which can't set custom headers.
+    #[arg(long, env = "RUSTUS_METADATA_FROM_QUERY")]
+    pub metadata_from_query: bool,
+
+    /// Metadata keys used to deduplicate new uploads.
+    ///
+    /// If a finished upload has the same length and values
+    /// of these keys, it's returned instead of a new one.
+    /// Uploads without any of these keys are never deduplicated.
+    #[arg(long, env = "RUSTUS_DEDUP_METADATA_KEYS", use_value_delimiter = true)]
+    pub dedup_metadata_keys: Vec<String>,
+
+    /// Metadata key whose values must be unique.
+    ///
+    /// New uploads with the same value as a finished upload
+    /// are rejected with 409. Uploads without the key aren't checked.
+    #[arg(long, env = "RUSTUS_UNIQUE_METADATA_KEY")]
+    pub unique_metadata_key: Option<String>,
+
+    /// Metadata key which scopes unique values.
+    ///
+    /// Values of the unique key are compared only
+    /// between uploads with the same value of this key.
+    #[arg(long, env = "RUSTUS_UNIQUE_METADATA_SCOPE")]
+    pub unique_metadata_scope: Option<String>,
+
+    /// Reject duplicates of unfinished uploads as well.
+    #[arg(long, env = "RUSTUS_UNIQUE_REJECT_UNFINISHED")]
+    pub unique_reject_unfinished: bool,
+
+    /// Remove uploads after they are downloaded.
+    ///
+    /// With `after-download` policy upload is removed
+    /// only after the whole file was sent to a client.
+    /// With `first-request` policy upload is removed
+    /// after the first successful download request.
+    #[arg(long, env = "RUSTUS_DELETE_AFTER_DOWNLOAD")]
+    pub delete_after_download: Option<RetentionPolicy>,
+
+    /// Metadata key to set retention policy for a single upload.
+    ///
+    /// Value of this key must be a name of the policy.
+    /// It takes precedence over `--delete-after-download`.
+    #[arg(long, env = "RUSTUS_DELETE_AFTER_DOWNLOAD_KEY")]
+    pub delete_after_download_key: Option<String>,
+
+    /// Max-age in seconds for caching downloads of finished uploads.
+    ///
+    /// If set, downloads of finished uploads have
+    /// `Cache-Control: public, max-age=..., immutable` header.
+    /// Unfinished uploads, signed downloads and uploads
+    /// which are removed after download are never cached.
+    #[arg(long, env = "RUSTUS_DOWNLOAD_MAX_AGE")]
+    pub download_max_age: Option<u32>,
+
+    /// Mark cached downloads as private.
+    ///
+    /// Private responses are cached only by browsers, not by CDNs.
+    #[arg(long, env = "RUSTUS_DOWNLOAD_CACHE_PRIVATE")]
+    pub download_cache_private: bool,
+
+    /// Serve downloads of unfinished uploads.
+    ///
+    /// Only received bytes are sent, so `Content-Length`
+    /// is the current offset, not the length of the upload.
+    /// By default such downloads are refused with `409 Conflict`.
+    #[arg(long, env = "RUSTUS_SERVE_INCOMPLETE_UPLOADS")]
+    pub serve_incomplete_uploads: bool,
+
+    /// Secret for signing download URLs.
+    ///
+    /// If set, files can only be downloaded
+    /// with signed URLs issued by admin API.
+    #[arg(long, env = "RUSTUS_DOWNLOAD_SIGNING_SECRET")]
+    pub download_signing_secret: Option<String>,
+
+    /// Path to file with secret for signing download URLs.
+    #[arg(long, env = "RUSTUS_DOWNLOAD_SIGNING_SECRET_PATH")]
+    pub download_signing_secret_path: Option<PathBuf>,
+
+    /// Default lifetime of signed download URLs in seconds.
+    #[arg(long, env = "RUSTUS_SIGNED_URL_TTL", default_value = "86400")]
+    pub signed_url_ttl: u64,
+
+    /// Secret for signing manifests of uploads.
+    ///
+    /// If set, manifests have `Rustus-Manifest-Signature`
+    /// header with HMAC of the manifest.
+    #[arg(long, env = "RUSTUS_MANIFEST_SIGNING_SECRET")]
+    pub manifest_signing_secret: Option<String>,
+
+    /// Path to file with secret for signing manifests of uploads.
+    #[arg(long, env = "RUSTUS_MANIFEST_SIGNING_SECRET_PATH")]
+    pub manifest_signing_secret_path: Option<PathBuf>,
+
+    /// Encrypt uploads with passphrases of clients.
+    ///
+    /// Uploads created with `Upload-Passphrase` header
+    /// are encrypted with a key derived from the passphrase.
+    /// The same header is required to write and download them.
+    #[arg(long, env = "RUSTUS_PASSPHRASE_ENCRYPTION")]
+    pub passphrase_encryption: bool,
+
+    /// Number of PBKDF2 iterations to derive keys from passphrases.
+    #[arg(long, env = "RUSTUS_PASSPHRASE_ITERATIONS", default_value = "100000")]
+    pub passphrase_iterations: usize,
+
     #[command(flatten)]
     pub storage_opts: StorageOptions,
 
@@ -408,8 +1692,20 @@ pub struct RustusConf {
     #[command(flatten)]
     pub notification_opts: NotificationsOptions,
 
+    #[command(flatten)]
+    pub client_ip: ClientIpOptions,
+
+    #[command(flatten)]
+    pub auth_opts: AuthOptions,
+
     #[command(flatten)]
     pub sentry_opts: SentryOptions,
+
+    #[command(flatten)]
+    pub tracing_opts: TracingOptions,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
 }
 
 #[cfg_attr(coverage, no_coverage)]
@@ -418,12 +1714,104 @@ impl RustusConf {
     ///
     /// This is a workaround for issue mentioned
     /// [here](https://www.reddit.com/r/rust/comments/8ddd19/confusion_with_splitting_mainrs_into_smaller/).
+    #[must_use]
     pub fn from_args() -> RustusConf {
         let mut conf = RustusConf::parse();
         conf.normalize_extentions();
+        conf.apply_storage_prefix();
         conf
     }
 
+    /// Read secrets from files given by `*-path` parameters.
+    ///
+    /// Secrets passed directly take precedence over files.
+    /// Trailing newlines are removed from secrets.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a secret file can't be read.
+    pub fn read_secrets(&mut self) -> RustusResult<()> {
+        let storage_opts = &mut self.storage_opts;
+        read_secret(
+            "s3-access-key-path",
+            &mut storage_opts.s3_access_key,
+            storage_opts.s3_access_key_path.as_ref(),
+        )?;
+        read_secret(
+            "s3-secret-key-path",
+            &mut storage_opts.s3_secret_key,
+            storage_opts.s3_secret_key_path.as_ref(),
+        )?;
+        read_secret(
+            "s3-security-token-path",
+            &mut storage_opts.s3_security_token,
+            storage_opts.s3_security_token_path.as_ref(),
+        )?;
+        read_secret(
+            "s3-session-token-path",
+            &mut storage_opts.s3_session_token,
+            storage_opts.s3_session_token_path.as_ref(),
+        )?;
+        read_secret(
+            "webdav-password-path",
+            &mut storage_opts.webdav_password,
+            storage_opts.webdav_password_path.as_ref(),
+        )?;
+        #[cfg(feature = "redis_info_storage")]
+        read_secret(
+            "chunk-index-dsn-path",
+            &mut storage_opts.chunk_index_dsn,
+            storage_opts.chunk_index_dsn_path.as_ref(),
+        )?;
+        #[cfg(any(feature = "redis_info_storage", feature = "db_info_storage"))]
+        read_secret(
+            "info-db-dsn-path",
+            &mut self.info_storage_opts.info_db_dsn,
+            self.info_storage_opts.info_db_dsn_path.as_ref(),
+        )?;
+        #[cfg(feature = "amqp_notifier")]
+        read_secret(
+            "hooks-amqp-url-path",
+            &mut self.notification_opts.amqp_hook_opts.hooks_amqp_url,
+            self.notification_opts
+                .amqp_hook_opts
+                .hooks_amqp_url_path
+                .as_ref(),
+        )?;
+        read_secret(
+            "sentry-dsn-path",
+            &mut self.sentry_opts.dsn,
+            self.sentry_opts.dsn_path.as_ref(),
+        )?;
+        read_secret(
+            "import-token-path",
+            &mut self.import_token,
+            self.import_token_path.as_ref(),
+        )?;
+        read_secret(
+            "download-signing-secret-path",
+            &mut self.download_signing_secret,
+            self.download_signing_secret_path.as_ref(),
+        )?;
+        read_secret(
+            "manifest-signing-secret-path",
+            &mut self.manifest_signing_secret,
+            self.manifest_signing_secret_path.as_ref(),
+        )?;
+        read_secret(
+            "admin-token-path",
+            &mut self.admin_token,
+            self.admin_token_path.as_ref(),
+        )?;
+        read_secret(
+            "auth-client-secret-path",
+            &mut self.auth_opts.auth_client_secret,
+            self.auth_opts.auth_client_secret_path.as_ref(),
+        )?;
+        Ok(())
+    }
+
+    #[allow(clippy::should_implement_trait)]
     pub fn from_iter<I>(iter: I) -> RustusConf
     where
         I: IntoIterator,
@@ -433,6 +1821,7 @@ impl RustusConf {
     }
 
     /// Base API url.
+    #[must_use]
     pub fn base_url(&self) -> String {
         let stripped_prefix = self.url.strip_prefix('/').unwrap_or(self.url.as_str());
         String::from(stripped_prefix.strip_suffix('/').unwrap_or(stripped_prefix))
@@ -449,11 +1838,52 @@ impl RustusConf {
         format!("/{}/", self.base_url())
     }
 
+    /// Get quota for the given tenant.
+    #[must_use]
+    pub fn tenant_quota(&self, tenant: &str) -> Option<usize> {
+        self.tenant_quotas
+            .iter()
+            .find(|quota| quota.tenant == tenant)
+            .map(|quota| quota.quota)
+            .or(self.default_tenant_quota)
+    }
+
+    /// Check if uploads have owners.
+    ///
+    /// Owners are taken either from the header
+    /// of a trusted proxy or from introspected tokens.
+    #[must_use]
+    pub fn owners_enabled(&self) -> bool {
+        self.owner_header.is_some() || self.auth_opts.auth_introspection_url.is_some()
+    }
+
     /// Check if hook is enabled by user.
+    #[must_use]
     pub fn hook_is_active(&self, hook: Hook) -> bool {
         self.notification_opts.hooks.contains(&hook)
     }
 
+    /// Add storage prefix to directories of local storages.
+    ///
+    /// Remote storages add the prefix to their keys.
+    pub fn apply_storage_prefix(&mut self) {
+        let Some(prefix) = self.storage_opts.storage_prefix.clone() else {
+            return;
+        };
+        let storage_opts = &mut self.storage_opts;
+        storage_opts.data_dir = storage_opts.data_dir.join(prefix.as_str());
+        for dir in [
+            &mut storage_opts.replication_data_dir,
+            &mut storage_opts.failover_data_dir,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *dir = dir.join(prefix.as_str());
+        }
+        self.info_storage_opts.info_dir = self.info_storage_opts.info_dir.join(prefix.as_str());
+    }
+
     /// Normalize extension vec.
     ///
     ///  Nomralization consists of two parts:
@@ -477,6 +1907,63 @@ impl RustusConf {
             ext.push(Extensions::Creation);
         }
 
+        // Deferred length can't be used if the length is required.
+        if self.require_length {
+            ext.retain(|extension| extension != &Extensions::CreationDeferLength);
+        }
+
         ext.sort();
     }
 }
+
+/// Read secret from the file if it wasn't passed directly.
+fn read_secret(name: &str, value: &mut Option<String>, path: Option<&PathBuf>) -> RustusResult<()> {
+    let (None, Some(path)) = (value.as_ref(), path) else {
+        return Ok(());
+    };
+    let secret = std::fs::read_to_string(path).map_err(|err| {
+        RustusError::UnableToReadSecret(format!("--{name} {}: {err}", path.display()))
+    })?;
+    *value = Some(String::from(secret.trim_end_matches(['\r', '\n'])));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RustusConf;
+    use crate::errors::RustusError;
+
+    #[test]
+    fn read_secrets() {
+        let dir = tempdir::TempDir::new("secrets").unwrap();
+        let secret_path = dir.path().join("signing_secret");
+        std::fs::write(&secret_path, "secret\n").unwrap();
+        let token_path = dir.path().join("import_token");
+        std::fs::write(&token_path, "from_file").unwrap();
+        let mut config = RustusConf::from_iter([
+            "rustus",
+            "--download-signing-secret-path",
+            secret_path.to_str().unwrap(),
+            "--import-token",
+            "direct",
+            "--import-token-path",
+            token_path.to_str().unwrap(),
+        ]);
+        config.read_secrets().unwrap();
+        assert_eq!(config.download_signing_secret.as_deref(), Some("secret"));
+        // Secrets passed directly aren't overridden.
+        assert_eq!(config.import_token.as_deref(), Some("direct"));
+    }
+
+    #[test]
+    fn missing_secret_file() {
+        let mut config = RustusConf::from_iter([
+            "rustus",
+            "--webdav-password-path",
+            "/definitely/missing/password",
+        ]);
+        let err = config.read_secrets().unwrap_err();
+        assert!(matches!(err, RustusError::UnableToReadSecret(_)));
+        assert!(err.to_string().contains("--webdav-password-path"));
+    }
+}
@@ -3,6 +3,8 @@ use std::io::{Error, ErrorKind};
 use actix_web::{http::StatusCode, HttpResponse, HttpResponseBuilder, ResponseError};
 use log::error;
 
+use crate::utils::hashes::CHECKSUM_ALGORITHMS;
+
 pub type RustusResult<T> = Result<T, RustusError>;
 
 #[derive(thiserror::Error, Debug)]
@@ -36,18 +38,24 @@ pub enum RustusError {
     UnableToReadInfo,
     #[error("Unable to write file {0}")]
     UnableToWrite(String),
+    #[error("Data of upload {0} is missing")]
+    DataMissing(String),
     #[error("Unable to remove file {0}")]
     UnableToRemove(String),
     #[error("Unable to prepare info storage. Reason: {0}")]
     UnableToPrepareInfoStorage(String),
     #[error("Unable to prepare storage. Reason: {0}")]
     UnableToPrepareStorage(String),
+    #[error("Unable to read secret {0}")]
+    UnableToReadSecret(String),
     #[error("Unknown extension: {0}")]
     UnknownExtension(String),
     #[error("Http request failed: {0}")]
     HttpRequestError(#[from] reqwest::Error),
     #[error("Hook invocation failed. Reason: {0}")]
     HookError(String),
+    #[error("Upload is rejected: {0}")]
+    UploadRejected(String),
     #[error("Unable to configure logging: {0}")]
     LogConfigError(#[from] log::SetLoggerError),
     #[cfg(feature = "amqp_notifier")]
@@ -72,8 +80,87 @@ pub enum RustusError {
     BlockingError(#[from] actix_web::error::BlockingError),
     #[error("HTTP hook error. Returned status: {0}, Response text: {1}")]
     HTTPHookError(u16, String, Option<String>),
+    #[error("WebDAV error: {0}")]
+    WebDavError(String),
     #[error("Found S3 error: {0}")]
     S3Error(#[from] s3::error::S3Error),
+    #[error("TLS error: {0}")]
+    TLSError(#[from] openssl::error::ErrorStack),
+    #[error("Invalid TLS configuration: {0}")]
+    InvalidTlsConfig(String),
+    #[error("Storage operation timed out: {0}")]
+    Timeout(String),
+    #[error("Invalid or expired signature")]
+    InvalidSignature,
+    #[error("Malformed multipart body: {0}")]
+    MultipartError(String),
+    #[error("Server doesn't accept new uploads. Retry after {0} seconds")]
+    Draining(u64),
+    #[error("Too many chunks are written to the upload at the same time")]
+    TooManyChunks,
+    #[error("Cannot generate path of the upload: {0}")]
+    InvalidPath(String),
+    #[error("Path {0} is already taken by another upload")]
+    PathCollision(String),
+    #[error("Upload {0} is too old to be resumed")]
+    UploadExpired(String),
+    #[error("Upload {0} was removed")]
+    UploadRemoved(String),
+    #[error("Invalid callback URL: {0}")]
+    InvalidCallbackUrl(String),
+    #[error("Metadata key `{0}` can't have more than {1} distinct values")]
+    TooManyMetadataValues(String, usize),
+    #[error("{0}")]
+    HeadersTooLarge(String),
+    #[error("Upload isn't fully received: {0}")]
+    IncompleteUpload(String),
+    #[error("Cannot decrypt upload: {0}")]
+    DecryptionFailed(String),
+    #[error("Stored data of upload {0} doesn't match its length: {1}")]
+    SizeMismatch(String, String),
+    #[error("Information about upload {0} was changed by another request")]
+    VersionConflict(String),
+    #[error("Derivation failed: {0}")]
+    DerivationFailed(String),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Token doesn't have required scope: {0}")]
+    InsufficientScope(String),
+    #[error("Upload {0} is being assembled")]
+    Assembling(String),
+    #[error("Requested range isn't received, only {0} bytes are received")]
+    RangeNotSatisfiable(usize),
+    #[error("Chunks must be multiples of {0} bytes, except for the last one")]
+    UnalignedChunk(usize),
+}
+
+impl RustusError {
+    /// Check if the storage may succeed if the operation is retried later.
+    ///
+    /// These errors are caused by unavailable
+    /// or overloaded storages, not by the upload itself.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            RustusError::Timeout(_)
+                | RustusError::S3Error(_)
+                | RustusError::HttpRequestError(_)
+                | RustusError::WebDavError(_)
+                | RustusError::StdError(_)
+        )
+    }
+}
+
+/// Check if the disk has no space left for the data.
+#[cfg(unix)]
+fn no_space_left(err: &Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::ENOSPC | libc::EDQUOT))
+}
+
+#[cfg(not(unix))]
+fn no_space_left(_err: &Error) -> bool {
+    false
 }
 
 /// This conversion allows us to use `RustusError` in the `main` function.
@@ -100,6 +187,27 @@ impl ResponseError for RustusError {
                     ))
                     .body(proxy_response.clone())
             }
+            // Client must know which algorithms are supported.
+            RustusError::UnknownHashAlgorithm => HttpResponseBuilder::new(self.status_code())
+                .insert_header(("Content-Type", "text/html; charset=utf-8"))
+                .insert_header(("Tus-Checksum-Algorithm", CHECKSUM_ALGORITHMS.join(",")))
+                .body(format!("{self}")),
+            // Clients must know that a bearer token is expected.
+            RustusError::Unauthorized(_) => HttpResponseBuilder::new(self.status_code())
+                .insert_header(("Content-Type", "text/html; charset=utf-8"))
+                .insert_header(("WWW-Authenticate", "Bearer"))
+                .body(format!("{self}")),
+            // Clients must know which bytes can be requested.
+            RustusError::RangeNotSatisfiable(received) => {
+                HttpResponseBuilder::new(self.status_code())
+                    .insert_header(("Content-Type", "text/html; charset=utf-8"))
+                    .insert_header(("Content-Range", format!("bytes */{received}")))
+                    .body(format!("{self}"))
+            }
+            RustusError::Draining(retry_after) => HttpResponseBuilder::new(self.status_code())
+                .insert_header(("Content-Type", "text/html; charset=utf-8"))
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .body(format!("{self}")),
             _ => HttpResponseBuilder::new(self.status_code())
                 .insert_header(("Content-Type", "text/html; charset=utf-8"))
                 .body(format!("{self}")),
@@ -109,13 +217,39 @@ impl ResponseError for RustusError {
     fn status_code(&self) -> StatusCode {
         match self {
             RustusError::FileNotFound => StatusCode::NOT_FOUND,
-            RustusError::WrongOffset => StatusCode::CONFLICT,
+            RustusError::WrongOffset
+            | RustusError::PathCollision(_)
+            | RustusError::IncompleteUpload(_)
+            | RustusError::VersionConflict(_) => StatusCode::CONFLICT,
+            RustusError::HeadersTooLarge(_) => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
             RustusError::FrozenFile
             | RustusError::SizeAlreadyKnown
             | RustusError::HookError(_)
             | RustusError::UnknownHashAlgorithm
-            | RustusError::WrongHeaderValue => StatusCode::BAD_REQUEST,
-            RustusError::WrongChecksum => StatusCode::EXPECTATION_FAILED,
+            | RustusError::WrongHeaderValue
+            | RustusError::MultipartError(_)
+            | RustusError::InvalidPath(_)
+            | RustusError::InvalidCallbackUrl(_)
+            | RustusError::UnalignedChunk(_)
+            | RustusError::TooManyMetadataValues(..) => StatusCode::BAD_REQUEST,
+            // 460 Checksum Mismatch is defined by TUS checksum extension.
+            RustusError::WrongChecksum => {
+                StatusCode::from_u16(460).unwrap_or(StatusCode::BAD_REQUEST)
+            }
+            RustusError::Assembling(_) => StatusCode::from_u16(425).unwrap_or(StatusCode::CONFLICT),
+            RustusError::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            RustusError::DataMissing(_)
+            | RustusError::UploadExpired(_)
+            | RustusError::UploadRemoved(_) => StatusCode::GONE,
+            RustusError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            RustusError::Draining(_) => StatusCode::SERVICE_UNAVAILABLE,
+            RustusError::TooManyChunks => StatusCode::TOO_MANY_REQUESTS,
+            RustusError::StdError(err) if no_space_left(err) => StatusCode::INSUFFICIENT_STORAGE,
+            RustusError::InvalidSignature
+            | RustusError::UploadRejected(_)
+            | RustusError::DecryptionFailed(_)
+            | RustusError::InsufficientScope(_) => StatusCode::FORBIDDEN,
+            RustusError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
             RustusError::HTTPHookError(status, _, _) => {
                 StatusCode::from_u16(*status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
             }
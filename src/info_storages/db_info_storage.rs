@@ -1,94 +1,977 @@
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashMap, io::Read, str::FromStr, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
-use rbatis::{crud::CRUD, crud_table, db::DBPoolOptions, rbatis::Rbatis};
+use base64::{engine::general_purpose, Engine};
+use rbatis::{
+    crud::{CRUDMut, CRUD},
+    crud_table,
+    db::{DBPoolOptions, DriverType},
+    executor::{RBatisTxExecutor, RbatisExecutor},
+    rbatis::Rbatis,
+    StmtConvert,
+};
+use rbson::Bson;
+use serde::Deserialize;
 
 use crate::{
     errors::{RustusError, RustusResult},
-    info_storages::{FileInfo, InfoStorage},
+    info_storages::{
+        models::upload_filter::{SortKey, SortOrder},
+        FileInfo, InfoStorage, UploadFilter,
+    },
 };
 
+/// Maximum length of values in metadata columns.
+///
+/// Longer values are stored only in `info` column.
+const MAX_INDEXED_LEN: usize = 255;
+
+/// Prefix of compressed information in `info` column.
+///
+/// Uncompressed information is a JSON object,
+/// so both formats can be stored in one table.
+const ZSTD_PREFIX: &str = "zstd:";
+
+/// Dictionary for compression of information.
+///
+/// Rows are too short to be compressed well alone,
+/// so common parts of them are taken from the dictionary.
+/// It must never change, otherwise stored rows can't be read.
+const ZSTD_DICTIONARY: &[u8] = br#"{"id":"","offset":0,"length":null,"path":"./data/","created_at":1700000000,"updated_at":1700000000,"deferred_size":false,"is_partial":false,"is_final":false,"parts":null,"storage":"file_storage","metadata":{"filename":"","filetype":"","name":"","type":""}}"#;
+
+/// Column with prefix of the environment.
+///
+/// Uploads of other environments are never read or changed.
+const NAMESPACE_COLUMN: &str = "namespace";
+
+/// Columns of the table with information.
+///
+/// Rows are keyed by namespace and ID,
+/// so environments may have uploads with the same ID.
+const MODEL_COLUMNS: &str = "id VARCHAR(40) NOT NULL, info TEXT, \
+    namespace VARCHAR(32) NOT NULL DEFAULT '', version BIGINT NOT NULL DEFAULT 0, \
+    PRIMARY KEY (namespace, id)";
+
 #[crud_table]
 struct DbModel {
     pub id: String,
     pub info: String,
+    pub namespace: String,
+    pub version: i64,
+}
+
+impl DbModel {
+    fn new(file_info: &FileInfo, compress: bool, namespace: &str) -> RustusResult<Self> {
+        let json = serde_json::to_string(file_info)?;
+        let info = if compress {
+            let compressed = zstd::bulk::Compressor::with_dictionary(0, ZSTD_DICTIONARY)?
+                .compress(json.as_bytes())?;
+            format!(
+                "{ZSTD_PREFIX}{}",
+                general_purpose::STANDARD.encode(compressed)
+            )
+        } else {
+            json
+        };
+        Ok(Self {
+            id: file_info.id.clone(),
+            info,
+            namespace: String::from(namespace),
+            version: i64::try_from(file_info.version).unwrap_or(i64::MAX),
+        })
+    }
+
+    /// Parse information, compressed or not.
+    async fn file_info(self) -> RustusResult<FileInfo> {
+        let Some(compressed) = self.info.strip_prefix(ZSTD_PREFIX) else {
+            return FileInfo::from_json(self.info).await;
+        };
+        let compressed = general_purpose::STANDARD
+            .decode(compressed)
+            .map_err(|_| RustusError::UnableToReadInfo)?;
+        let mut json = String::new();
+        zstd::stream::read::Decoder::with_dictionary(compressed.as_slice(), ZSTD_DICTIONARY)?
+            .read_to_string(&mut json)?;
+        FileInfo::from_json(json).await
+    }
+}
+
+/// Number of rows read at once while uploads are listed by ID.
+const LIST_BATCH_SIZE: usize = 500;
+
+/// Column with deduplication keys of uploads.
+const DEDUP_COLUMN: &str = "dedup_key";
+
+/// Expiration of a tombstone of a removed upload.
+#[derive(Deserialize)]
+struct Tombstone {
+    expires_at: i64,
+}
+
+/// Number and size of database pages in `SQLite`.
+#[derive(Deserialize)]
+struct SqlitePages {
+    page_count: i64,
+    freelist_count: i64,
+    page_size: i64,
+}
+
+/// Number of distinct values of indexed metadata key
+/// which uploads may have.
+///
+/// It's parsed from strings like `project=1000`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CardinalityLimit {
+    pub key: String,
+    pub limit: usize,
 }
 
-impl TryFrom<&FileInfo> for DbModel {
-    type Error = RustusError;
+impl FromStr for CardinalityLimit {
+    type Err = String;
 
-    fn try_from(value: &FileInfo) -> Result<Self, Self::Error> {
-        Ok(DbModel {
-            id: value.id.clone(),
-            info: serde_json::to_string(value)?,
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (key, limit) = input
+            .split_once('=')
+            .ok_or_else(|| format!("Cardinality limit '{input}' must be in format 'key=limit'."))?;
+        let limit = limit
+            .trim()
+            .parse::<usize>()
+            .map_err(|err| format!("Wrong cardinality limit for key '{key}': {err}"))?;
+        Ok(Self {
+            key: String::from(key.trim()),
+            limit,
         })
     }
 }
 
+/// Number of distinct values in a column.
+#[derive(Deserialize)]
+struct Cardinality {
+    count: i64,
+}
+
+/// Name of a column with the metadata key.
+fn column(key: &str) -> String {
+    format!("meta_{key}")
+}
+
+/// Check that metadata key can be used in a column name.
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.len() <= 50
+        && key
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
 #[derive(Clone)]
 pub struct DBInfoStorage {
     db: Arc<Rbatis>,
+    /// Metadata keys stored in separate columns.
+    indexed_metadata: Vec<String>,
+    /// Limits of distinct values of indexed metadata keys.
+    cardinality_limits: Vec<CardinalityLimit>,
+    /// Lock of writes which add values of limited keys.
+    cardinality_lock: Arc<tokio::sync::Mutex<()>>,
+    /// Whether deduplication keys are stored in a separate column.
+    dedup: bool,
+    /// Whether information is compressed.
+    compress: bool,
+    /// Prefix of the environment, it's empty by default.
+    namespace: String,
 }
 
 impl DBInfoStorage {
-    pub async fn new(dsn: &str) -> RustusResult<Self> {
+    /// Connect to the database.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an indexed key is invalid
+    /// or the database is unavailable.
+    pub async fn new(
+        dsn: &str,
+        indexed_metadata: Vec<String>,
+        dedup: bool,
+        compress: bool,
+    ) -> RustusResult<Self> {
+        if let Some(key) = indexed_metadata.iter().find(|key| !is_valid_key(key)) {
+            return Err(RustusError::UnableToPrepareInfoStorage(format!(
+                "Metadata key `{key}` can't be indexed. Keys may contain only lowercase letters, digits and underscores."
+            )));
+        }
         let db = Rbatis::new();
         let mut opts = DBPoolOptions::new();
         opts.connect_timeout = Duration::new(2, 0);
         db.link_opt(dsn, opts).await?;
-        Ok(Self { db: Arc::new(db) })
+        Ok(Self {
+            db: Arc::new(db),
+            indexed_metadata,
+            cardinality_limits: Vec::new(),
+            cardinality_lock: Arc::default(),
+            dedup,
+            compress,
+            namespace: String::new(),
+        })
+    }
+
+    /// Store information under the prefix.
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: String) -> Self {
+        self.namespace = prefix;
+        self
+    }
+
+    /// Limit distinct values of indexed metadata keys.
+    ///
+    /// Only indexed keys can be limited.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a limited key isn't indexed.
+    pub fn with_cardinality_limits(mut self, limits: Vec<CardinalityLimit>) -> RustusResult<Self> {
+        if let Some(limit) = limits
+            .iter()
+            .find(|limit| !self.indexed_metadata.contains(&limit.key))
+        {
+            return Err(RustusError::UnableToPrepareInfoStorage(format!(
+                "Cardinality of metadata key `{}` can't be limited, since it isn't indexed.",
+                limit.key
+            )));
+        }
+        self.cardinality_limits = limits;
+        Ok(self)
+    }
+
+    /// Check if the table has the column.
+    async fn has_column(&self, table: &str, column: &str) -> bool {
+        // Column doesn't exist if it can't be selected.
+        let probe = format!("SELECT {column} FROM {table} WHERE 1 = 0;");
+        self.db.exec(probe.as_str(), Vec::new()).await.is_ok()
+    }
+
+    /// Move information from the table created without namespaces.
+    ///
+    /// Primary key of the old table is the ID, so rows are copied
+    /// to a new table keyed by namespace and ID, which replaces the old one.
+    /// Existing rows belong to the environment without prefix.
+    /// If the move was interrupted, it's finished or started over.
+    async fn prepare_namespaces(&self) -> RustusResult<()> {
+        let has_model = self.has_column("db_model", "id").await;
+        if self.has_column("db_model_ns", "id").await {
+            let sql = if has_model {
+                // Old table wasn't dropped, so rows are copied again.
+                "DROP TABLE db_model_ns;"
+            } else {
+                "ALTER TABLE db_model_ns RENAME TO db_model;"
+            };
+            self.db.exec(sql, Vec::new()).await?;
+        }
+        if !has_model || self.has_column("db_model", NAMESPACE_COLUMN).await {
+            return Ok(());
+        }
+        for sql in [
+            format!("CREATE TABLE db_model_ns ({MODEL_COLUMNS});"),
+            String::from("INSERT INTO db_model_ns (id, info) SELECT id, info FROM db_model;"),
+            String::from("DROP TABLE db_model;"),
+            String::from("ALTER TABLE db_model_ns RENAME TO db_model;"),
+        ] {
+            self.db.exec(sql.as_str(), Vec::new()).await?;
+        }
+        Ok(())
+    }
+
+    /// Read the upload of the environment.
+    async fn fetch_model(&self, file_id: &str) -> RustusResult<Option<DbModel>> {
+        let driver = self.db.driver_type()?;
+        let mut sql =
+            format!("SELECT id, info, {NAMESPACE_COLUMN}, version FROM db_model WHERE id = ");
+        driver.stmt_convert(0, &mut sql);
+        sql.push_str(format!(" AND {NAMESPACE_COLUMN} = ").as_str());
+        driver.stmt_convert(1, &mut sql);
+        let models: Vec<DbModel> = self
+            .db
+            .fetch(
+                sql.as_str(),
+                vec![
+                    Bson::String(String::from(file_id)),
+                    Bson::String(self.namespace.clone()),
+                ],
+            )
+            .await?;
+        Ok(models.into_iter().next())
+    }
+
+    /// Add version column to the table created without it.
+    ///
+    /// Version of existing rows is zero, as in their information.
+    async fn prepare_versions(&self) -> RustusResult<()> {
+        if self.has_column("db_model", "version").await {
+            return Ok(());
+        }
+        self.db
+            .exec(
+                "ALTER TABLE db_model ADD COLUMN version BIGINT NOT NULL DEFAULT 0;",
+                Vec::new(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Read all uploads of the environment.
+    async fn fetch_models(&self) -> RustusResult<Vec<DbModel>> {
+        Ok(self
+            .db
+            .fetch_list_by_column(NAMESPACE_COLUMN, &[self.namespace.as_str()])
+            .await?)
+    }
+
+    /// Names of all separate columns.
+    fn columns(&self) -> Vec<String> {
+        let mut columns = self
+            .indexed_metadata
+            .iter()
+            .map(|key| column(key))
+            .collect::<Vec<_>>();
+        if self.dedup {
+            columns.push(String::from(DEDUP_COLUMN));
+        }
+        columns
+    }
+
+    /// Add columns for indexed metadata keys and deduplication keys.
+    ///
+    /// Existing uploads are written to new columns,
+    /// so they can be found by these keys as well.
+    async fn prepare_columns(&self) -> RustusResult<()> {
+        let mut added = false;
+        for column in self.columns() {
+            if self.has_column("db_model", column.as_str()).await {
+                continue;
+            }
+            self.db
+                .exec(
+                    format!("ALTER TABLE db_model ADD COLUMN {column} VARCHAR({MAX_INDEXED_LEN});")
+                        .as_str(),
+                    Vec::new(),
+                )
+                .await?;
+            self.db
+                .exec(
+                    format!("CREATE INDEX idx_db_model_{column} ON db_model ({column});").as_str(),
+                    Vec::new(),
+                )
+                .await?;
+            added = true;
+        }
+        if added {
+            // Columns are shared by all environments.
+            let models: Vec<DbModel> = self.db.fetch_list().await?;
+            for model in models {
+                let namespace = model.namespace.clone();
+                self.set_columns(
+                    &mut self.db.as_ref().into(),
+                    &model.file_info().await?,
+                    namespace.as_str(),
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get limits which the new values of the upload must fit into.
+    ///
+    /// Values stored in columns of the upload are already counted,
+    /// so their limits are skipped. It means that limits
+    /// aren't checked when only the offset of the upload changes.
+    async fn changed_limits(
+        &self,
+        file_info: &FileInfo,
+        create: bool,
+    ) -> RustusResult<Vec<&CardinalityLimit>> {
+        let mut limits = self
+            .cardinality_limits
+            .iter()
+            .filter(|limit| {
+                matches!(
+                    file_info.metadata.get(&limit.key),
+                    Some(value) if value.chars().count() <= MAX_INDEXED_LEN
+                )
+            })
+            .collect::<Vec<_>>();
+        if create || limits.is_empty() {
+            return Ok(limits);
+        }
+        let driver = self.db.driver_type()?;
+        let columns = limits
+            .iter()
+            .map(|limit| column(limit.key.as_str()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut sql = format!("SELECT {columns} FROM db_model WHERE id = ");
+        driver.stmt_convert(0, &mut sql);
+        sql.push_str(format!(" AND {NAMESPACE_COLUMN} = ").as_str());
+        driver.stmt_convert(1, &mut sql);
+        let rows: Vec<HashMap<String, Option<String>>> = self
+            .db
+            .fetch(
+                sql.as_str(),
+                vec![
+                    Bson::String(file_info.id.clone()),
+                    Bson::String(self.namespace.clone()),
+                ],
+            )
+            .await?;
+        if let Some(stored) = rows.first() {
+            limits.retain(|limit| {
+                stored
+                    .get(column(limit.key.as_str()).as_str())
+                    .and_then(Option::as_ref)
+                    != file_info.metadata.get(&limit.key)
+            });
+        }
+        Ok(limits)
+    }
+
+    /// Check that new values of limited metadata keys fit into their limits.
+    ///
+    /// Values which are already stored in columns are always accepted,
+    /// so existing uploads can be updated after the limit is reached.
+    async fn check_cardinality(
+        &self,
+        executor: &mut RbatisExecutor<'_, '_>,
+        file_info: &FileInfo,
+        limits: &[&CardinalityLimit],
+    ) -> RustusResult<()> {
+        let driver = self.db.driver_type()?;
+        for limit in limits {
+            let Some(value) = file_info.metadata.get(&limit.key) else {
+                continue;
+            };
+            let column = column(limit.key.as_str());
+            let mut sql = format!("SELECT COUNT(*) AS count FROM db_model WHERE {column} = ");
+            driver.stmt_convert(0, &mut sql);
+            sql.push_str(format!(" AND {NAMESPACE_COLUMN} = ").as_str());
+            driver.stmt_convert(1, &mut sql);
+            let counts: Vec<Cardinality> = executor
+                .fetch(
+                    sql.as_str(),
+                    vec![
+                        Bson::String(value.clone()),
+                        Bson::String(self.namespace.clone()),
+                    ],
+                )
+                .await?;
+            if counts.first().map_or(0, |count| count.count) > 0 {
+                continue;
+            }
+            let mut sql = format!(
+                "SELECT COUNT(DISTINCT {column}) AS count FROM db_model WHERE {NAMESPACE_COLUMN} = "
+            );
+            driver.stmt_convert(0, &mut sql);
+            let counts: Vec<Cardinality> = executor
+                .fetch(sql.as_str(), vec![Bson::String(self.namespace.clone())])
+                .await?;
+            let count = counts.first().map_or(0, |count| count.count);
+            if usize::try_from(count).unwrap_or(usize::MAX) >= limit.limit {
+                return Err(RustusError::TooManyMetadataValues(
+                    limit.key.clone(),
+                    limit.limit,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if uploads with the metadata value can be found by its column.
+    fn is_indexed(&self, key: &str, value: &str) -> bool {
+        self.indexed_metadata.iter().any(|indexed| indexed == key)
+            && value.chars().count() <= MAX_INDEXED_LEN
+    }
+
+    /// Read uploads of the environment which may match the filter.
+    ///
+    /// Conditions which have columns are checked by the database.
+    /// Rows follow `after_id` and are sorted by ID if uploads are sorted by it,
+    /// so they can be read by batches.
+    async fn fetch_filtered(
+        &self,
+        filter: &UploadFilter,
+        after_id: Option<&str>,
+        limit: Option<usize>,
+    ) -> RustusResult<Vec<DbModel>> {
+        let driver = self.db.driver_type()?;
+        let mut sql = format!(
+            "SELECT id, info, {NAMESPACE_COLUMN}, version FROM db_model WHERE {NAMESPACE_COLUMN} = "
+        );
+        driver.stmt_convert(0, &mut sql);
+        let mut args = vec![Bson::String(self.namespace.clone())];
+        if let Some((key, value)) = &filter.metadata {
+            if self.is_indexed(key, value) {
+                sql.push_str(format!(" AND {} = ", column(key)).as_str());
+                driver.stmt_convert(args.len(), &mut sql);
+                args.push(Bson::String(value.clone()));
+            }
+        }
+        let desc = filter.order == SortOrder::Desc;
+        if let Some(after_id) = after_id {
+            sql.push_str(if desc { " AND id < " } else { " AND id > " });
+            driver.stmt_convert(args.len(), &mut sql);
+            args.push(Bson::String(String::from(after_id)));
+        }
+        if filter.sort == SortKey::Id {
+            sql.push_str(if desc {
+                " ORDER BY id DESC"
+            } else {
+                " ORDER BY id"
+            });
+        }
+        if let Some(limit) = limit {
+            sql.push_str(format!(" LIMIT {limit}").as_str());
+        }
+        Ok(self.db.fetch(sql.as_str(), args).await?)
+    }
+
+    /// Write indexed metadata and deduplication key of the upload to its columns.
+    async fn set_columns(
+        &self,
+        executor: &mut RbatisExecutor<'_, '_>,
+        file_info: &FileInfo,
+        namespace: &str,
+    ) -> RustusResult<()> {
+        let columns = self.columns();
+        if columns.is_empty() {
+            return Ok(());
+        }
+        let driver = self.db.driver_type()?;
+        let mut sql = String::from("UPDATE db_model SET ");
+        let mut args = Vec::new();
+        let mut values = self
+            .indexed_metadata
+            .iter()
+            .map(|key| {
+                file_info
+                    .metadata
+                    .get(key)
+                    .filter(|value| value.chars().count() <= MAX_INDEXED_LEN)
+            })
+            .collect::<Vec<_>>();
+        if self.dedup {
+            values.push(file_info.dedup_key.as_ref());
+        }
+        for (index, (column, value)) in columns.iter().zip(values).enumerate() {
+            if index > 0 {
+                sql.push_str(", ");
+            }
+            sql.push_str(column.as_str());
+            sql.push_str(" = ");
+            driver.stmt_convert(index, &mut sql);
+            args.push(value.map_or(Bson::Null, |value| Bson::String(value.clone())));
+        }
+        sql.push_str(" WHERE id = ");
+        driver.stmt_convert(args.len(), &mut sql);
+        args.push(Bson::String(file_info.id.clone()));
+        sql.push_str(format!(" AND {NAMESPACE_COLUMN} = ").as_str());
+        driver.stmt_convert(args.len(), &mut sql);
+        args.push(Bson::String(String::from(namespace)));
+        executor.exec(sql.as_str(), args).await?;
+        Ok(())
+    }
+
+    /// Write the row of the upload and its columns in the transaction.
+    ///
+    /// Existing row is updated only if it has the expected version.
+    /// New values of limited metadata keys are checked in the same transaction.
+    ///
+    /// Returns `false` if the row wasn't updated.
+    async fn write_model(
+        &self,
+        tx: &mut RBatisTxExecutor<'_>,
+        file_info: &FileInfo,
+        create: bool,
+        expected_version: Option<u64>,
+        limits: &[&CardinalityLimit],
+    ) -> RustusResult<bool> {
+        self.check_cardinality(&mut tx.as_executor(), file_info, limits)
+            .await?;
+        let model = DbModel::new(file_info, self.compress, self.namespace.as_str())?;
+        if create {
+            tx.save(&model, &[]).await?;
+        } else {
+            let driver = self.db.driver_type()?;
+            let mut sql = String::from("UPDATE db_model SET info = ");
+            driver.stmt_convert(0, &mut sql);
+            sql.push_str(", version = ");
+            driver.stmt_convert(1, &mut sql);
+            sql.push_str(" WHERE id = ");
+            driver.stmt_convert(2, &mut sql);
+            sql.push_str(format!(" AND {NAMESPACE_COLUMN} = ").as_str());
+            driver.stmt_convert(3, &mut sql);
+            let mut args = vec![
+                Bson::String(model.info),
+                Bson::Int64(model.version),
+                Bson::String(model.id),
+                Bson::String(model.namespace),
+            ];
+            if let Some(version) = expected_version {
+                sql.push_str(" AND version = ");
+                driver.stmt_convert(4, &mut sql);
+                args.push(Bson::Int64(i64::try_from(version).unwrap_or(i64::MAX)));
+            }
+            let result = tx.as_executor().exec(sql.as_str(), args).await?;
+            if result.rows_affected == 0 && expected_version.is_some() {
+                return Ok(false);
+            }
+        }
+        self.set_columns(&mut tx.as_executor(), file_info, self.namespace.as_str())
+            .await?;
+        Ok(true)
+    }
+
+    /// Write the upload with its columns.
+    ///
+    /// The row and its columns are written in one transaction,
+    /// so columns never point to stale information.
+    ///
+    /// Returns `false` if the row wasn't updated.
+    async fn write_info(
+        &self,
+        file_info: &FileInfo,
+        create: bool,
+        expected_version: Option<u64>,
+    ) -> RustusResult<bool> {
+        let limits = self.changed_limits(file_info, create).await?;
+        // Distinct values are counted and written by one upload at a time,
+        // so concurrent uploads can't exceed limits together.
+        let _guard = if limits.is_empty() {
+            None
+        } else {
+            Some(self.cardinality_lock.lock().await)
+        };
+        let mut tx = self.db.acquire_begin().await?;
+        match self
+            .write_model(&mut tx, file_info, create, expected_version, &limits)
+            .await
+        {
+            Ok(true) => {
+                tx.commit().await?;
+                Ok(true)
+            }
+            Ok(false) => {
+                tx.rollback().await?;
+                Ok(false)
+            }
+            Err(err) => {
+                if let Err(rollback_err) = tx.rollback().await {
+                    log::warn!("Cannot roll back transaction: {rollback_err}");
+                }
+                Err(err)
+            }
+        }
     }
 }
 
 #[async_trait(?Send)]
 impl InfoStorage for DBInfoStorage {
     async fn prepare(&mut self) -> RustusResult<()> {
+        self.prepare_namespaces().await?;
         self.db
             .exec(
-                "CREATE TABLE IF NOT EXISTS db_model (id VARCHAR(40) PRIMARY KEY, info TEXT);",
+                format!("CREATE TABLE IF NOT EXISTS db_model ({MODEL_COLUMNS});").as_str(),
                 Vec::new(),
             )
             .await?;
-        Ok(())
+        self.db
+            .exec(
+                "CREATE TABLE IF NOT EXISTS tombstones (id VARCHAR(40) NOT NULL, expires_at BIGINT, \
+                namespace VARCHAR(32) NOT NULL DEFAULT '', PRIMARY KEY (namespace, id));",
+                Vec::new(),
+            )
+            .await?;
+        self.prepare_versions().await?;
+        self.prepare_columns().await
     }
 
     async fn set_info(&self, file_info: &FileInfo, create: bool) -> RustusResult<()> {
-        let model = DbModel::try_from(file_info)?;
-        if create {
-            self.db.save(&model, &[]).await?;
-        } else {
-            self.db.update_by_column("id", &model).await?;
+        self.write_info(file_info, create, None).await?;
+        Ok(())
+    }
+
+    async fn update_info(&self, file_info: &mut FileInfo) -> RustusResult<()> {
+        let mut updated = file_info.clone();
+        updated.version += 1;
+        if !self
+            .write_info(&updated, false, Some(file_info.version))
+            .await?
+        {
+            // Row is either removed or changed.
+            self.get_info(file_info.id.as_str()).await?;
+            return Err(RustusError::VersionConflict(file_info.id.clone()));
         }
+        *file_info = updated;
         Ok(())
     }
 
     async fn get_info(&self, file_id: &str) -> RustusResult<FileInfo> {
-        let model: Option<DbModel> = self.db.fetch_by_column("id", file_id).await?;
-        if let Some(info) = model {
-            FileInfo::from_json(info.info.to_string()).await
+        if let Some(model) = self.fetch_model(file_id).await? {
+            model.file_info().await
         } else {
             Err(RustusError::FileNotFound)
         }
     }
 
     async fn remove_info(&self, file_id: &str) -> RustusResult<()> {
+        let driver = self.db.driver_type()?;
+        let mut sql = String::from("DELETE FROM db_model WHERE id = ");
+        driver.stmt_convert(0, &mut sql);
+        sql.push_str(format!(" AND {NAMESPACE_COLUMN} = ").as_str());
+        driver.stmt_convert(1, &mut sql);
+        self.db
+            .exec(
+                sql.as_str(),
+                vec![
+                    Bson::String(String::from(file_id)),
+                    Bson::String(self.namespace.clone()),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn list_info(&self) -> RustusResult<Vec<FileInfo>> {
+        let models = self.fetch_models().await?;
+        let mut infos = Vec::new();
+        for model in models {
+            infos.push(model.file_info().await?);
+        }
+        Ok(infos)
+    }
+
+    async fn list_by_metadata(&self, key: &str, value: &str) -> RustusResult<Vec<FileInfo>> {
+        let models: Vec<DbModel> = if self.is_indexed(key, value) {
+            let driver = self.db.driver_type()?;
+            let mut sql = format!(
+                "SELECT id, info, {NAMESPACE_COLUMN}, version FROM db_model WHERE {} = ",
+                column(key)
+            );
+            driver.stmt_convert(0, &mut sql);
+            sql.push_str(format!(" AND {NAMESPACE_COLUMN} = ").as_str());
+            driver.stmt_convert(1, &mut sql);
+            self.db
+                .fetch(
+                    sql.as_str(),
+                    vec![
+                        Bson::String(String::from(value)),
+                        Bson::String(self.namespace.clone()),
+                    ],
+                )
+                .await?
+        } else {
+            self.fetch_models().await?
+        };
+        let mut infos = Vec::new();
+        for model in models {
+            let info = model.file_info().await?;
+            // Columns are written after the info,
+            // so the info is the source of truth.
+            if info.metadata.get(key).map(String::as_str) == Some(value) {
+                infos.push(info);
+            }
+        }
+        Ok(infos)
+    }
+
+    async fn add_tombstone(&self, file_id: &str, ttl: u64) -> RustusResult<()> {
+        let driver = self.db.driver_type()?;
+        let expires_at =
+            chrono::Utc::now().timestamp() + i64::try_from(ttl).unwrap_or(i64::MAX / 2);
+        let mut sql = String::from("DELETE FROM tombstones WHERE id = ");
+        driver.stmt_convert(0, &mut sql);
+        sql.push_str(format!(" AND {NAMESPACE_COLUMN} = ").as_str());
+        driver.stmt_convert(1, &mut sql);
+        self.db
+            .exec(
+                sql.as_str(),
+                vec![
+                    Bson::String(String::from(file_id)),
+                    Bson::String(self.namespace.clone()),
+                ],
+            )
+            .await?;
+        let mut sql =
+            format!("INSERT INTO tombstones (id, expires_at, {NAMESPACE_COLUMN}) VALUES (");
+        for index in 0..3 {
+            if index > 0 {
+                sql.push_str(", ");
+            }
+            driver.stmt_convert(index, &mut sql);
+        }
+        sql.push(')');
         self.db
-            .remove_by_column::<DbModel, &str>("id", file_id)
+            .exec(
+                sql.as_str(),
+                vec![
+                    Bson::String(String::from(file_id)),
+                    Bson::Int64(expires_at),
+                    Bson::String(self.namespace.clone()),
+                ],
+            )
             .await?;
         Ok(())
     }
+
+    async fn has_tombstone(&self, file_id: &str) -> RustusResult<bool> {
+        let driver = self.db.driver_type()?;
+        let mut sql = String::from("SELECT expires_at FROM tombstones WHERE id = ");
+        driver.stmt_convert(0, &mut sql);
+        sql.push_str(format!(" AND {NAMESPACE_COLUMN} = ").as_str());
+        driver.stmt_convert(1, &mut sql);
+        let tombstones: Vec<Tombstone> = self
+            .db
+            .fetch(
+                sql.as_str(),
+                vec![
+                    Bson::String(String::from(file_id)),
+                    Bson::String(self.namespace.clone()),
+                ],
+            )
+            .await?;
+        let now = chrono::Utc::now().timestamp();
+        Ok(tombstones
+            .iter()
+            .any(|tombstone| tombstone.expires_at > now))
+    }
+
+    async fn compact(&self, threshold: f64) -> RustusResult<u64> {
+        let driver = self.db.driver_type()?;
+        let mut sql = String::from("DELETE FROM tombstones WHERE expires_at <= ");
+        driver.stmt_convert(0, &mut sql);
+        sql.push_str(format!(" AND {NAMESPACE_COLUMN} = ").as_str());
+        driver.stmt_convert(1, &mut sql);
+        self.db
+            .exec(
+                sql.as_str(),
+                vec![
+                    Bson::Int64(chrono::Utc::now().timestamp()),
+                    Bson::String(self.namespace.clone()),
+                ],
+            )
+            .await?;
+        // Other databases reclaim space on their own.
+        if self.db.driver_type()? != DriverType::Sqlite {
+            return Ok(0);
+        }
+        let pages: Vec<SqlitePages> = self
+            .db
+            .fetch(
+                "SELECT page_count, freelist_count, page_size \
+                FROM pragma_page_count(), pragma_freelist_count(), pragma_page_size();",
+                Vec::new(),
+            )
+            .await?;
+        let Some(pages) = pages.first() else {
+            return Ok(0);
+        };
+        #[allow(clippy::cast_precision_loss)]
+        if pages.page_count == 0
+            || (pages.freelist_count as f64) < threshold * pages.page_count as f64
+        {
+            return Ok(0);
+        }
+        self.db.exec("VACUUM;", Vec::new()).await?;
+        Ok(u64::try_from(pages.freelist_count * pages.page_size).unwrap_or_default())
+    }
+
+    async fn find_by_dedup_key(&self, key: &str) -> RustusResult<Option<FileInfo>> {
+        if !self.dedup {
+            return Ok(None);
+        }
+        let driver = self.db.driver_type()?;
+        let mut sql = format!(
+            "SELECT id, info, {NAMESPACE_COLUMN}, version FROM db_model WHERE {DEDUP_COLUMN} = "
+        );
+        driver.stmt_convert(0, &mut sql);
+        sql.push_str(format!(" AND {NAMESPACE_COLUMN} = ").as_str());
+        driver.stmt_convert(1, &mut sql);
+        let models: Vec<DbModel> = self
+            .db
+            .fetch(
+                sql.as_str(),
+                vec![
+                    Bson::String(String::from(key)),
+                    Bson::String(self.namespace.clone()),
+                ],
+            )
+            .await?;
+        for model in models {
+            let info = model.file_info().await?;
+            if info.length == Some(info.offset) {
+                return Ok(Some(info));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn list_files(&self, filter: &UploadFilter) -> RustusResult<Vec<FileInfo>> {
+        // Indexed metadata and `after_id` are checked by the database,
+        // other conditions are stored only in the info and checked in memory.
+        if filter.sort != SortKey::Id {
+            // Other keys are in the info as well, so all rows are sorted in memory.
+            let mut uploads = Vec::new();
+            for model in self
+                .fetch_filtered(filter, filter.after_id.as_deref(), None)
+                .await?
+            {
+                uploads.push(model.file_info().await?);
+            }
+            return Ok(filter.apply(uploads));
+        }
+        // Rows are sorted by ID, so they're read by batches
+        // until the page is full.
+        let wanted = filter.offset.saturating_add(filter.limit());
+        let mut after_id = filter.after_id.clone();
+        let mut uploads = Vec::new();
+        loop {
+            let models = self
+                .fetch_filtered(filter, after_id.as_deref(), Some(LIST_BATCH_SIZE))
+                .await?;
+            let done = models.len() < LIST_BATCH_SIZE;
+            after_id = models.last().map(|model| model.id.clone());
+            for model in models {
+                let info = model.file_info().await?;
+                if filter.matches(&info) {
+                    uploads.push(info);
+                }
+            }
+            if done || uploads.len() >= wanted {
+                break;
+            }
+        }
+        Ok(filter.apply(uploads))
+    }
 }
 
 #[cfg(feature = "test_db")]
 #[cfg(test)]
 mod tests {
-    use super::{DBInfoStorage, DbModel};
-    use crate::{info_storages::FileInfo, InfoStorage};
+    use super::{CardinalityLimit, DBInfoStorage, DbModel};
+    use crate::{
+        errors::RustusError,
+        info_storages::{
+            models::upload_filter::{SortKey, SortOrder, UploadStatus},
+            FileInfo, UploadFilter,
+        },
+        InfoStorage,
+    };
     use rbatis::crud::CRUD;
+    use std::str::FromStr;
 
     async fn get_info_storage() -> DBInfoStorage {
         let db_url = std::env::var("TEST_DB_URL").unwrap();
-        let mut storage = DBInfoStorage::new(db_url.as_str()).await.unwrap();
+        let mut storage =
+            DBInfoStorage::new(db_url.as_str(), vec![String::from("tenant")], true, false)
+                .await
+                .unwrap();
         storage.prepare().await.unwrap();
         storage
     }
@@ -127,6 +1010,27 @@ mod tests {
         assert!(info.is_none());
     }
 
+    #[actix_rt::test]
+    async fn columns_in_transaction() {
+        let info_storage = get_info_storage().await;
+        // Column of the key doesn't exist, so columns can't be written.
+        let mut broken = info_storage.clone();
+        broken.indexed_metadata.push(String::from("missing_column"));
+        let file_info = FileInfo::new_test();
+        assert!(broken.set_info(&file_info, true).await.is_err());
+        assert!(matches!(
+            info_storage.get_info(file_info.id.as_str()).await,
+            Err(RustusError::FileNotFound)
+        ));
+        info_storage.set_info(&file_info, true).await.unwrap();
+        let mut updated = file_info.clone();
+        updated.offset = 5;
+        assert!(broken.update_info(&mut updated).await.is_err());
+        let info = info_storage.get_info(file_info.id.as_str()).await.unwrap();
+        assert_eq!(info.offset, 0);
+        assert_eq!(info.version, file_info.version);
+    }
+
     #[actix_rt::test]
     async fn deletion_not_found() {
         let info_storage = get_info_storage().await;
@@ -141,4 +1045,351 @@ mod tests {
         let res = info_storage.get_info("unknown").await;
         assert!(res.is_err());
     }
+
+    #[actix_rt::test]
+    async fn indexed_metadata() {
+        let info_storage = get_info_storage().await;
+        let mut file_info = FileInfo::new_test();
+        let tenant = uuid::Uuid::new_v4().to_string();
+        file_info.metadata.insert("tenant".into(), tenant.clone());
+        info_storage.set_info(&file_info, true).await.unwrap();
+        let mut other = FileInfo::new_test();
+        other.metadata.insert("tenant".into(), "other".into());
+        info_storage.set_info(&other, true).await.unwrap();
+        let infos = info_storage
+            .list_by_metadata("tenant", tenant.as_str())
+            .await
+            .unwrap();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].id, file_info.id);
+        // Updated metadata is indexed as well.
+        file_info.metadata.insert("tenant".into(), "moved".into());
+        info_storage.set_info(&file_info, false).await.unwrap();
+        assert!(info_storage
+            .list_by_metadata("tenant", tenant.as_str())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn cardinality_limits() {
+        let info_storage = get_info_storage()
+            .await
+            .with_prefix(uuid::Uuid::new_v4().simple().to_string())
+            .with_cardinality_limits(vec![CardinalityLimit::from_str("tenant=2").unwrap()])
+            .unwrap();
+        for tenant in ["first", "second", "second"] {
+            let mut file_info = FileInfo::new_test();
+            file_info.metadata.insert("tenant".into(), tenant.into());
+            info_storage.set_info(&file_info, true).await.unwrap();
+        }
+        let mut file_info = FileInfo::new_test();
+        file_info.metadata.insert("tenant".into(), "third".into());
+        let err = info_storage.set_info(&file_info, true).await.unwrap_err();
+        assert!(matches!(err, RustusError::TooManyMetadataValues(..)));
+        assert!(info_storage.get_info(file_info.id.as_str()).await.is_err());
+        // Known values and uploads without the key are accepted.
+        file_info.metadata.insert("tenant".into(), "first".into());
+        info_storage.set_info(&file_info, true).await.unwrap();
+        let untagged = FileInfo::new_test();
+        info_storage.set_info(&untagged, true).await.unwrap();
+        file_info.metadata.insert("tenant".into(), "third".into());
+        let err = info_storage.update_info(&mut file_info).await.unwrap_err();
+        assert!(matches!(err, RustusError::TooManyMetadataValues(..)));
+    }
+
+    #[actix_rt::test]
+    async fn concurrent_cardinality_limits() {
+        let info_storage = get_info_storage()
+            .await
+            .with_prefix(uuid::Uuid::new_v4().simple().to_string())
+            .with_cardinality_limits(vec![CardinalityLimit::from_str("tenant=1").unwrap()])
+            .unwrap();
+        let uploads = (0..5)
+            .map(|index| {
+                let mut file_info = FileInfo::new_test();
+                file_info
+                    .metadata
+                    .insert("tenant".into(), format!("tenant-{index}"));
+                file_info
+            })
+            .collect::<Vec<_>>();
+        let results = futures::future::join_all(
+            uploads
+                .iter()
+                .map(|file_info| info_storage.set_info(file_info, true)),
+        )
+        .await;
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        // Uploads with stored values are updated after the limit is reached.
+        let stored = results.iter().position(Result::is_ok).unwrap();
+        let mut file_info = uploads[stored].clone();
+        file_info.offset = 10;
+        // Limits aren't checked if values aren't changed.
+        assert!(info_storage
+            .changed_limits(&file_info, false)
+            .await
+            .unwrap()
+            .is_empty());
+        info_storage.update_info(&mut file_info).await.unwrap();
+        info_storage.set_info(&file_info, false).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn wrong_cardinality_limits() {
+        assert!(CardinalityLimit::from_str("tenant").is_err());
+        assert!(CardinalityLimit::from_str("tenant=many").is_err());
+        let info_storage = get_info_storage().await;
+        let limits = vec![CardinalityLimit::from_str("project=10").unwrap()];
+        assert!(info_storage.with_cardinality_limits(limits).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn filtered_list() {
+        let info_storage = get_info_storage().await;
+        let tenant = uuid::Uuid::new_v4().to_string();
+        let mut finished = FileInfo::new_test();
+        finished.offset = 10;
+        finished.metadata.insert("tenant".into(), tenant.clone());
+        info_storage.set_info(&finished, true).await.unwrap();
+        let mut unfinished = FileInfo::new_test();
+        unfinished.metadata.insert("tenant".into(), tenant.clone());
+        info_storage.set_info(&unfinished, true).await.unwrap();
+        let filter = UploadFilter {
+            metadata: Some((String::from("tenant"), tenant)),
+            status: Some(UploadStatus::Complete),
+            ..UploadFilter::default()
+        };
+        let uploads = info_storage.list_files(&filter).await.unwrap();
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0].id, finished.id);
+    }
+
+    #[actix_rt::test]
+    async fn list_by_pages() {
+        let info_storage = get_info_storage().await;
+        let tenant = uuid::Uuid::new_v4().to_string();
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            let mut file_info = FileInfo::new_test();
+            file_info.metadata.insert("tenant".into(), tenant.clone());
+            info_storage.set_info(&file_info, true).await.unwrap();
+            ids.push(file_info.id);
+        }
+        ids.sort();
+        let mut filter = UploadFilter {
+            metadata: Some((String::from("tenant"), tenant)),
+            sort: SortKey::Id,
+            limit: Some(2),
+            ..UploadFilter::default()
+        };
+        let mut listed = Vec::new();
+        loop {
+            let page = info_storage.list_files(&filter).await.unwrap();
+            let Some(last) = page.last() else {
+                break;
+            };
+            filter.after_id = Some(last.id.clone());
+            listed.extend(page.into_iter().map(|info| info.id));
+        }
+        assert_eq!(listed, ids);
+        filter.after_id = Some(ids[3].clone());
+        filter.order = SortOrder::Desc;
+        let page = info_storage.list_files(&filter).await.unwrap();
+        let page = page.into_iter().map(|info| info.id).collect::<Vec<_>>();
+        assert_eq!(page, vec![ids[2].clone(), ids[1].clone()]);
+    }
+
+    #[actix_rt::test]
+    async fn invalid_indexed_key() {
+        let db_url = std::env::var("TEST_DB_URL").unwrap();
+        let storage = DBInfoStorage::new(
+            db_url.as_str(),
+            vec![String::from("x; DROP TABLE")],
+            false,
+            false,
+        )
+        .await;
+        assert!(storage.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn compression() {
+        let info_storage = get_info_storage().await;
+        let mut compressed = info_storage.clone();
+        compressed.compress = true;
+        let mut file_info = FileInfo::new_test();
+        file_info.metadata.insert("tenant".into(), "memes".into());
+        // Uncompressed rows are read by compressed storage.
+        info_storage.set_info(&file_info, true).await.unwrap();
+        let info = compressed.get_info(file_info.id.as_str()).await.unwrap();
+        assert_eq!(info.metadata, file_info.metadata);
+        compressed.set_info(&file_info, false).await.unwrap();
+        let model = info_storage
+            .db
+            .fetch_by_column::<Option<DbModel>, &str>("id", file_info.id.as_str())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(model.info.starts_with(super::ZSTD_PREFIX));
+        let info = info_storage.get_info(file_info.id.as_str()).await.unwrap();
+        assert_eq!(info.metadata, file_info.metadata);
+        // Indexed columns aren't compressed.
+        let infos = compressed
+            .list_by_metadata("tenant", "memes")
+            .await
+            .unwrap();
+        assert!(infos.iter().any(|info| info.id == file_info.id));
+    }
+
+    #[test]
+    fn compressed_rows_size() {
+        let id = uuid::Uuid::new_v4().to_string();
+        let metadata = [
+            ("filename", "holiday_photo_0042.jpg"),
+            ("filetype", "image/jpeg"),
+            ("name", "holiday_photo_0042.jpg"),
+            ("type", "image/jpeg"),
+            ("tenant", "acme"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (String::from(key), String::from(value)))
+        .collect();
+        let file_info = FileInfo::new(
+            id.as_str(),
+            Some(1_048_576),
+            Some(format!("./data/2024/06/15/{id}")),
+            String::from("file_storage"),
+            Some(metadata),
+        );
+        let raw = DbModel::new(&file_info, false, "").unwrap().info.len();
+        let compressed = DbModel::new(&file_info, true, "").unwrap().info.len();
+        // Rows of uploads in the file storage are at least a third smaller.
+        assert!(compressed * 3 <= raw * 2, "{compressed} of {raw} bytes");
+    }
+
+    #[actix_rt::test]
+    async fn tombstones() {
+        let info_storage = get_info_storage().await;
+        let file_id = uuid::Uuid::new_v4().to_string();
+        assert!(!info_storage.has_tombstone(file_id.as_str()).await.unwrap());
+        info_storage
+            .add_tombstone(file_id.as_str(), 60)
+            .await
+            .unwrap();
+        assert!(info_storage.has_tombstone(file_id.as_str()).await.unwrap());
+        // Tombstone of the same upload is replaced.
+        info_storage
+            .add_tombstone(file_id.as_str(), 0)
+            .await
+            .unwrap();
+        assert!(!info_storage.has_tombstone(file_id.as_str()).await.unwrap());
+        info_storage.compact(1.0).await.unwrap();
+        assert!(!info_storage.has_tombstone(file_id.as_str()).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn prefixes() {
+        let info_storage = get_info_storage().await;
+        let staging = info_storage.clone().with_prefix(String::from("staging"));
+        let mut file_info = FileInfo::new_test();
+        let tenant = uuid::Uuid::new_v4().to_string();
+        file_info.metadata.insert("tenant".into(), tenant.clone());
+        staging.set_info(&file_info, true).await.unwrap();
+        assert!(staging.get_info(file_info.id.as_str()).await.is_ok());
+        // Uploads of other environments aren't visible.
+        assert!(info_storage.get_info(file_info.id.as_str()).await.is_err());
+        assert!(!info_storage
+            .list_info()
+            .await
+            .unwrap()
+            .iter()
+            .any(|info| info.id == file_info.id));
+        assert!(info_storage
+            .list_by_metadata("tenant", tenant.as_str())
+            .await
+            .unwrap()
+            .is_empty());
+        info_storage
+            .remove_info(file_info.id.as_str())
+            .await
+            .unwrap();
+        assert!(staging.get_info(file_info.id.as_str()).await.is_ok());
+        // The same ID is used in both environments.
+        info_storage.set_info(&file_info, true).await.unwrap();
+        let mut updated = file_info.clone();
+        updated.offset = 5;
+        info_storage.set_info(&updated, false).await.unwrap();
+        assert_eq!(
+            staging
+                .get_info(file_info.id.as_str())
+                .await
+                .unwrap()
+                .offset,
+            file_info.offset
+        );
+        staging.remove_info(file_info.id.as_str()).await.unwrap();
+        assert!(staging.get_info(file_info.id.as_str()).await.is_err());
+        assert!(info_storage.get_info(file_info.id.as_str()).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn compaction() {
+        let info_storage = get_info_storage().await;
+        let file_info = FileInfo::new_test();
+        info_storage.set_info(&file_info, true).await.unwrap();
+        info_storage
+            .remove_info(file_info.id.as_str())
+            .await
+            .unwrap();
+        info_storage.compact(0.0).await.unwrap();
+        assert!(info_storage.get_info(file_info.id.as_str()).await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn dedup_keys() {
+        let info_storage = get_info_storage().await;
+        let key = uuid::Uuid::new_v4().to_string();
+        let mut file_info = FileInfo::new_test();
+        file_info.dedup_key = Some(key.clone());
+        info_storage.set_info(&file_info, true).await.unwrap();
+        // Unfinished uploads aren't returned.
+        assert!(info_storage
+            .find_by_dedup_key(key.as_str())
+            .await
+            .unwrap()
+            .is_none());
+        file_info.offset = file_info.length.unwrap();
+        info_storage.set_info(&file_info, false).await.unwrap();
+        let found = info_storage.find_by_dedup_key(key.as_str()).await.unwrap();
+        assert_eq!(found.unwrap().id, file_info.id);
+    }
+
+    #[actix_rt::test]
+    async fn stale_update() {
+        let info_storage = get_info_storage().await;
+        let file_info = FileInfo::new_test();
+        info_storage.set_info(&file_info, true).await.unwrap();
+        let mut first = file_info.clone();
+        let mut second = file_info.clone();
+        first.metadata.insert("tenant".into(), "first".into());
+        info_storage.update_info(&mut first).await.unwrap();
+        assert_eq!(first.version, 1);
+        second.metadata.insert("tenant".into(), "second".into());
+        let err = info_storage.update_info(&mut second).await.unwrap_err();
+        assert!(matches!(err, RustusError::VersionConflict(_)));
+        let info = info_storage.get_info(file_info.id.as_str()).await.unwrap();
+        assert_eq!(info.version, 1);
+        assert_eq!(info.metadata.get("tenant").unwrap(), "first");
+        // Indexed columns are updated as well.
+        let found = info_storage
+            .list_by_metadata("tenant", "first")
+            .await
+            .unwrap();
+        assert!(found.iter().any(|info| info.id == file_info.id));
+        let mut missing = FileInfo::new_test();
+        let err = info_storage.update_info(&mut missing).await.unwrap_err();
+        assert!(matches!(err, RustusError::FileNotFound));
+    }
 }
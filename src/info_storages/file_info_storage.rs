@@ -1,12 +1,14 @@
 use std::{
+    ffi::OsStr,
     io::{Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use async_trait::async_trait;
-use log::error;
+use log::{error, warn};
 use std::{
-    fs::{remove_file, File, OpenOptions},
+    fs::{read_dir, remove_file, File, OpenOptions},
     io::{BufReader, BufWriter},
 };
 use tokio::fs::DirBuilder;
@@ -14,33 +16,79 @@ use tokio::fs::DirBuilder;
 use crate::{
     errors::{RustusError, RustusResult},
     info_storages::{FileInfo, InfoStorage},
+    storages::chunk_index::IndexedChunk,
+    utils::{
+        dir_struct::{absolute_path, check_writable},
+        durability::sync_file,
+    },
 };
 
 #[derive(Clone)]
 pub struct FileInfoStorage {
     info_dir: PathBuf,
+    /// Lock for compare-and-swap updates.
+    ///
+    /// Files can't be updated atomically,
+    /// so updates are serialized within the process.
+    update_lock: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl FileInfoStorage {
+    #[must_use]
     pub fn new(info_dir: PathBuf) -> Self {
-        Self { info_dir }
+        Self {
+            info_dir,
+            update_lock: Arc::new(tokio::sync::Mutex::new(())),
+        }
     }
 
+    #[must_use]
     pub fn info_file_path(&self, file_id: &str) -> PathBuf {
         self.info_dir.join(format!("{file_id}.info"))
     }
+
+    fn tombstone_path(&self, file_id: &str) -> PathBuf {
+        self.info_dir.join(format!("{file_id}.tombstone"))
+    }
+
+    fn chunks_path(&self, file_id: &str) -> PathBuf {
+        self.info_dir.join(format!("{file_id}.chunks"))
+    }
+}
+
+/// Read expiration timestamp of the tombstone.
+///
+/// Returns `None` if the tombstone doesn't exist.
+fn tombstone_expiration(path: &Path) -> RustusResult<Option<i64>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    // Corrupted tombstones are treated as expired.
+    Ok(Some(contents.trim().parse().unwrap_or_default()))
 }
 
 #[async_trait(?Send)]
 impl InfoStorage for FileInfoStorage {
     async fn prepare(&mut self) -> RustusResult<()> {
-        if !self.info_dir.exists() {
-            DirBuilder::new()
-                .create(self.info_dir.as_path())
-                .await
-                .map_err(|err| RustusError::UnableToPrepareInfoStorage(err.to_string()))?;
-        }
-        Ok(())
+        let info_dir = absolute_path(self.info_dir.as_path());
+        DirBuilder::new()
+            .recursive(true)
+            .create(self.info_dir.as_path())
+            .await
+            .map_err(|err| {
+                RustusError::UnableToPrepareInfoStorage(format!(
+                    "cannot create directory {}: {err}",
+                    info_dir.display()
+                ))
+            })?;
+        check_writable(self.info_dir.as_path()).map_err(|err| {
+            RustusError::UnableToPrepareInfoStorage(format!(
+                "directory {} isn't writable: {err}",
+                info_dir.display()
+            ))
+        })
     }
 
     async fn set_info(&self, file_info: &FileInfo, create: bool) -> RustusResult<()> {
@@ -67,6 +115,25 @@ impl InfoStorage for FileInfoStorage {
         .await?
     }
 
+    async fn update_info(&self, file_info: &mut FileInfo) -> RustusResult<()> {
+        let _lock = self.update_lock.lock().await;
+        let current = self.get_info(file_info.id.as_str()).await?;
+        if current.version != file_info.version {
+            return Err(RustusError::VersionConflict(file_info.id.clone()));
+        }
+        let mut updated = file_info.clone();
+        updated.version += 1;
+        self.set_info(&updated, false).await?;
+        *file_info = updated;
+        Ok(())
+    }
+
+    async fn sync_info(&self, file_info: &FileInfo) -> RustusResult<()> {
+        let path = self.info_file_path(file_info.id.as_str());
+        tokio::task::spawn_blocking(move || sync_file(path.as_path())).await??;
+        Ok(())
+    }
+
     async fn get_info(&self, file_id: &str) -> RustusResult<FileInfo> {
         let info_path = self.info_file_path(file_id);
         tokio::task::spawn_blocking(move || {
@@ -85,10 +152,17 @@ impl InfoStorage for FileInfoStorage {
     async fn remove_info(&self, file_id: &str) -> RustusResult<()> {
         let id = String::from(file_id);
         let info_path = self.info_file_path(id.as_str());
+        let chunks_path = self.chunks_path(id.as_str());
         tokio::task::spawn_blocking(move || {
             if !info_path.exists() {
                 return Err(RustusError::FileNotFound);
             }
+            // Chunk index is useless without the upload.
+            if let Err(err) = remove_file(chunks_path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Cannot remove chunk index of {}: {}", id, err);
+                }
+            }
             remove_file(info_path).map_err(|err| {
                 error!("{:?}", err);
                 RustusError::UnableToRemove(id)
@@ -96,12 +170,114 @@ impl InfoStorage for FileInfoStorage {
         })
         .await?
     }
+
+    async fn get_chunks(&self, file_id: &str) -> RustusResult<Vec<IndexedChunk>> {
+        let path = self.chunks_path(file_id);
+        tokio::task::spawn_blocking(move || match std::fs::read(path) {
+            Ok(contents) => serde_json::from_slice(contents.as_slice()).map_err(RustusError::from),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        })
+        .await?
+    }
+
+    async fn set_chunks(&self, file_id: &str, chunks: &[IndexedChunk]) -> RustusResult<()> {
+        let path = self.chunks_path(file_id);
+        if chunks.is_empty() {
+            return match tokio::fs::remove_file(path).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            };
+        }
+        let data = serde_json::to_vec(chunks).map_err(RustusError::from)?;
+        tokio::fs::write(path, data).await?;
+        Ok(())
+    }
+
+    async fn add_tombstone(&self, file_id: &str, ttl: u64) -> RustusResult<()> {
+        let path = self.tombstone_path(file_id);
+        let expires_at =
+            chrono::Utc::now().timestamp() + i64::try_from(ttl).unwrap_or(i64::MAX / 2);
+        tokio::task::spawn_blocking(move || {
+            std::fs::write(path, expires_at.to_string())?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn has_tombstone(&self, file_id: &str) -> RustusResult<bool> {
+        let path = self.tombstone_path(file_id);
+        tokio::task::spawn_blocking(move || {
+            let Some(expires_at) = tombstone_expiration(path.as_path())? else {
+                return Ok(false);
+            };
+            if expires_at > chrono::Utc::now().timestamp() {
+                return Ok(true);
+            }
+            // Expired tombstone may be removed concurrently.
+            if let Err(err) = remove_file(path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    return Err(err.into());
+                }
+            }
+            Ok(false)
+        })
+        .await?
+    }
+
+    /// Remove expired tombstones.
+    ///
+    /// Tombstones which are checked after
+    /// expiration are removed right away,
+    /// others are removed here.
+    async fn compact(&self, _threshold: f64) -> RustusResult<u64> {
+        let info_dir = self.info_dir.clone();
+        tokio::task::spawn_blocking(move || {
+            let now = chrono::Utc::now().timestamp();
+            let mut reclaimed = 0;
+            for entry in read_dir(info_dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if path.extension().and_then(OsStr::to_str) != Some("tombstone") {
+                    continue;
+                }
+                if tombstone_expiration(path.as_path())?.is_none_or(|expires_at| expires_at > now) {
+                    continue;
+                }
+                reclaimed += entry.metadata()?.len();
+                remove_file(path)?;
+            }
+            Ok(reclaimed)
+        })
+        .await?
+    }
+
+    async fn list_info(&self) -> RustusResult<Vec<FileInfo>> {
+        let info_dir = self.info_dir.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut infos = Vec::new();
+            for entry in read_dir(info_dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(OsStr::to_str) != Some("info") {
+                    continue;
+                }
+                let mut contents = String::new();
+                BufReader::new(File::open(path.as_path())?).read_to_string(&mut contents)?;
+                match serde_json::from_str::<FileInfo>(contents.as_str()) {
+                    Ok(info) => infos.push(info),
+                    Err(err) => warn!("Cannot parse {}: {}", path.display(), err),
+                }
+            }
+            Ok(infos)
+        })
+        .await?
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::FileInfoStorage;
-    use crate::{info_storages::FileInfo, InfoStorage};
+    use crate::{errors::RustusError, info_storages::FileInfo, InfoStorage};
     use std::{
         collections::HashMap,
         fs::File,
@@ -162,6 +338,44 @@ mod tests {
         assert_eq!(read_info.metadata, read_info.metadata);
     }
 
+    #[actix_rt::test]
+    async fn list_info() {
+        let dir = tempdir::TempDir::new("file_info").unwrap();
+        let storage = FileInfoStorage::new(dir.into_path());
+        let mut file_info = FileInfo::new_test();
+        file_info.offset = 5;
+        storage.set_info(&file_info, true).await.unwrap();
+        storage.set_info(&FileInfo::new_test(), true).await.unwrap();
+        File::create(storage.info_file_path("broken"))
+            .unwrap()
+            .write_all("{not a json}".as_bytes())
+            .unwrap();
+        assert_eq!(storage.list_info().await.unwrap().len(), 2);
+        assert_eq!(storage.total_size().await.unwrap(), 5);
+    }
+
+    #[actix_rt::test]
+    async fn stale_update() {
+        let dir = tempdir::TempDir::new("file_info").unwrap();
+        let storage = FileInfoStorage::new(dir.into_path());
+        storage.set_info(&FileInfo::new_test(), true).await.unwrap();
+        let file_id = storage.list_info().await.unwrap().remove(0).id;
+        let mut first = storage.get_info(file_id.as_str()).await.unwrap();
+        let mut second = first.clone();
+        first.metadata.insert("scan".into(), "clean".into());
+        storage.update_info(&mut first).await.unwrap();
+        assert_eq!(first.version, 1);
+        second
+            .metadata
+            .insert("thumbnail".into(), "thumb.png".into());
+        let err = storage.update_info(&mut second).await.unwrap_err();
+        assert!(matches!(err, RustusError::VersionConflict(_)));
+        assert_eq!(second.version, 0);
+        let info = storage.get_info(file_id.as_str()).await.unwrap();
+        assert_eq!(info.version, 1);
+        assert_eq!(info.metadata.get("scan").unwrap(), "clean");
+    }
+
     #[actix_rt::test]
     async fn get_broken_info() {
         let dir = tempdir::TempDir::new("file_info").unwrap();
@@ -172,4 +386,23 @@ mod tests {
         let read_info = storage.get_info(file_id).await;
         assert!(read_info.is_err());
     }
+
+    #[actix_rt::test]
+    async fn tombstones() {
+        let dir = tempdir::TempDir::new("file_info").unwrap();
+        let storage = FileInfoStorage::new(dir.into_path());
+        assert!(!storage.has_tombstone("removed").await.unwrap());
+        storage.add_tombstone("removed", 60).await.unwrap();
+        storage.add_tombstone("expired", 0).await.unwrap();
+        storage.add_tombstone("checked", 0).await.unwrap();
+        assert!(storage.has_tombstone("removed").await.unwrap());
+        // Expired tombstones are removed.
+        assert!(!storage.has_tombstone("checked").await.unwrap());
+        assert!(!storage.tombstone_path("checked").exists());
+        assert!(storage.compact(0.0).await.unwrap() > 0);
+        assert!(!storage.tombstone_path("expired").exists());
+        assert!(storage.tombstone_path("removed").exists());
+        // Tombstones aren't uploads.
+        assert!(storage.list_info().await.unwrap().is_empty());
+    }
 }
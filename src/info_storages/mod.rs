@@ -1,4 +1,5 @@
 pub mod file_info_storage;
+pub mod timeout_info_storage;
 
 #[cfg(feature = "db_info_storage")]
 pub mod db_info_storage;
@@ -8,5 +9,8 @@ pub mod redis_info_storage;
 pub mod models;
 
 pub use models::{
-    available_info_storages::AvailableInfoStores, file_info::FileInfo, info_store::InfoStorage,
+    available_info_storages::AvailableInfoStores,
+    file_info::FileInfo,
+    info_store::InfoStorage,
+    upload_filter::{UploadFilter, UploadPages},
 };
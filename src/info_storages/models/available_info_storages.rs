@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use derive_more::{Display, From};
 
 use crate::{errors::RustusResult, from_str, RustusConf};
@@ -31,40 +33,117 @@ impl AvailableInfoStores {
     /// # Params
     /// `config` - Rustus configuration.
     ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage can't be connected.
+    ///
     #[cfg_attr(coverage, no_coverage)]
     pub async fn get(
         &self,
         config: &RustusConf,
+    ) -> RustusResult<Box<dyn InfoStorage + Sync + Send>> {
+        self.build(
+            config,
+            config.info_storage_opts.info_dir.clone(),
+            config.storage_opts.storage_prefix.clone(),
+        )
+        .await
+    }
+
+    /// Create storage for information about terminated uploads.
+    ///
+    /// It's the same storage, but information is kept
+    /// in `.trash` directory of info dir or under `.trash` prefix.
+    /// Valid storage prefixes have no dots, so it never
+    /// collides with other environments.
+    ///
+    /// # Params
+    /// `config` - Rustus configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage can't be connected.
+    ///
+    #[cfg_attr(coverage, no_coverage)]
+    pub async fn get_trash(
+        &self,
+        config: &RustusConf,
+    ) -> RustusResult<Box<dyn InfoStorage + Sync + Send>> {
+        let prefix = format!(
+            "{}.trash",
+            config
+                .storage_opts
+                .storage_prefix
+                .as_deref()
+                .unwrap_or_default()
+        );
+        self.build(
+            config,
+            config.info_storage_opts.info_dir.join(".trash"),
+            Some(prefix),
+        )
+        .await
+    }
+
+    #[cfg_attr(coverage, no_coverage)]
+    #[allow(unused_variables)]
+    async fn build(
+        &self,
+        config: &RustusConf,
+        info_dir: PathBuf,
+        prefix: Option<String>,
     ) -> RustusResult<Box<dyn InfoStorage + Sync + Send>> {
         match self {
-            Self::Files => Ok(Box::new(file_info_storage::FileInfoStorage::new(
-                config.info_storage_opts.info_dir.clone(),
-            ))),
+            Self::Files => Ok(Box::new(file_info_storage::FileInfoStorage::new(info_dir))),
             #[cfg(feature = "db_info_storage")]
-            Self::DB => Ok(Box::new(
-                db_info_storage::DBInfoStorage::new(
-                    config
-                        .info_storage_opts
-                        .info_db_dsn
-                        .clone()
-                        .unwrap()
-                        .as_str(),
+            Self::DB => {
+                let mut storage = db_info_storage::DBInfoStorage::new(
+                    required_dsn(config)?,
+                    config.info_storage_opts.info_db_indexed_metadata.clone(),
+                    !config.dedup_metadata_keys.is_empty(),
+                    config.info_storage_opts.info_db_compression,
                 )
-                .await?,
-            )),
+                .await?
+                .with_cardinality_limits(
+                    config.info_storage_opts.info_db_cardinality_limits.clone(),
+                )?;
+                if let Some(prefix) = prefix {
+                    // Namespace column fits only 32 characters.
+                    if prefix.len() > 32 {
+                        return Err(crate::errors::RustusError::UnableToPrepareInfoStorage(
+                            format!("Prefix `{prefix}` is longer than 32 characters."),
+                        ));
+                    }
+                    storage = storage.with_prefix(prefix);
+                }
+                Ok(Box::new(storage))
+            }
             #[cfg(feature = "redis_info_storage")]
-            AvailableInfoStores::Redis => Ok(Box::new(
-                redis_info_storage::RedisStorage::new(
-                    config
-                        .info_storage_opts
-                        .info_db_dsn
-                        .clone()
-                        .unwrap()
-                        .as_str(),
+            AvailableInfoStores::Redis => {
+                let mut storage = redis_info_storage::RedisStorage::new(
+                    required_dsn(config)?,
                     config.info_storage_opts.redis_info_expiration,
                 )
-                .await?,
-            )),
+                .await?;
+                if let Some(prefix) = prefix {
+                    storage = storage.with_prefix(prefix);
+                }
+                Ok(Box::new(storage))
+            }
         }
     }
 }
+
+/// Get connection string of remote info storage.
+#[cfg(any(feature = "redis_info_storage", feature = "db_info_storage"))]
+fn required_dsn(config: &RustusConf) -> RustusResult<&str> {
+    config
+        .info_storage_opts
+        .info_db_dsn
+        .as_deref()
+        .ok_or_else(|| {
+            crate::errors::RustusError::UnableToPrepareInfoStorage(String::from(
+                "--info-db-dsn or --info-db-dsn-path is required.",
+            ))
+        })
+}
@@ -1,14 +1,41 @@
-use std::collections::HashMap;
+use std::{cmp::Ordering, collections::HashMap, fmt::Display};
 
-use crate::{errors::RustusError, RustusResult};
+use crate::{errors::RustusError, utils::encryption::Encryption, RustusResult};
 use base64::{engine::general_purpose, Engine};
-use chrono::{serde::ts_seconds, DateTime, Utc};
+use chrono::{
+    serde::{ts_seconds, ts_seconds_option},
+    DateTime, Utc,
+};
 use log::error;
 use serde::{Deserialize, Serialize};
 
+/// Range of bytes.
+///
+/// `start` is inclusive and `end` is exclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByteRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Display for ByteRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}, {})", self.start, self.end)
+    }
+}
+
+/// Idempotency key of the written chunk.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkToken {
+    pub token: String,
+    #[serde(with = "ts_seconds")]
+    pub expires_at: DateTime<Utc>,
+}
+
 /// Information about file.
 /// It has everything about stored file.
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct FileInfo {
     pub id: String,
     pub offset: usize,
@@ -16,12 +43,85 @@ pub struct FileInfo {
     pub path: Option<String>,
     #[serde(with = "ts_seconds")]
     pub created_at: DateTime<Utc>,
+    /// Time of the last write to the upload.
+    #[serde(
+        default,
+        with = "ts_seconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub updated_at: Option<DateTime<Utc>>,
     pub deferred_size: bool,
     pub is_partial: bool,
     pub is_final: bool,
+    /// Whether parts of the final upload are being concatenated.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub assembling: bool,
+    /// Whether data after the offset is being removed.
+    ///
+    /// It's saved before the data is truncated,
+    /// so interrupted truncation can be finished.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub truncating: bool,
     pub parts: Option<Vec<String>>,
     pub storage: String,
+    /// Backend of the storage which holds the upload.
+    ///
+    /// It's set by storages which keep uploads in
+    /// several backends, like failover storage.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
     pub metadata: HashMap<String, String>,
+    /// Received ranges of bytes.
+    ///
+    /// It's used only by storages which
+    /// accept chunks at arbitrary offsets.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub received: Vec<ByteRange>,
+    /// Hex encoded sha256 checksum of finished upload.
+    ///
+    /// It's set only if checksums of uploads are enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// URL which receives post-finish hook of the upload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub callback_url: Option<String>,
+    /// Key of the upload for deduplication of creation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedup_key: Option<String>,
+    /// Parameters of the key if the upload is encrypted
+    /// with the passphrase of the client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<Encryption>,
+    /// Time when the upload was terminated.
+    ///
+    /// It's set only for uploads in the trash.
+    #[serde(
+        default,
+        with = "ts_seconds_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub removed_at: Option<DateTime<Utc>>,
+    /// Version of the information.
+    ///
+    /// It's incremented by every update with `InfoStorage::update_info`.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub version: u64,
+    /// Idempotency keys of recently written chunks.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunk_tokens: Vec<ChunkToken>,
+}
+
+/// Maximum number of idempotency keys kept for an upload.
+const MAX_CHUNK_TOKENS: usize = 32;
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_false(value: &bool) -> bool {
+    !*value
 }
 
 impl FileInfo {
@@ -34,6 +134,7 @@ impl FileInfo {
     /// `file_size` - Size of a file if it's known;
     /// `path` - local path of a file;
     /// `initial_metadata` - meta information, that could be omitted.
+    #[must_use]
     pub fn new(
         file_id: &str,
         length: Option<usize>,
@@ -57,14 +158,167 @@ impl FileInfo {
             path,
             length,
             storage,
+            backend: None,
             metadata,
             deferred_size,
             offset: 0,
             is_final: false,
+            assembling: false,
+            truncating: false,
             is_partial: false,
             parts: None,
             created_at: chrono::Utc::now(),
+            updated_at: None,
+            received: Vec::new(),
+            checksum: None,
+            callback_url: None,
+            dedup_key: None,
+            encryption: None,
+            removed_at: None,
+            version: 0,
+            chunk_tokens: Vec::new(),
+        }
+    }
+
+    /// Time when the upload was changed for the last time.
+    ///
+    /// Uploads without writes are changed when they are created.
+    #[must_use]
+    pub fn last_modified(&self) -> DateTime<Utc> {
+        self.updated_at.unwrap_or(self.created_at)
+    }
+
+    /// Mark range of bytes as received.
+    ///
+    /// Overlapping and adjacent ranges are merged.
+    /// Offset becomes the end of the
+    /// contiguous range from the beginning of the file.
+    pub fn add_received_range(&mut self, start: usize, end: usize) {
+        if start < end {
+            self.received.push(ByteRange { start, end });
+        }
+        self.received.sort_by_key(|range| range.start);
+        let mut merged: Vec<ByteRange> = Vec::with_capacity(self.received.len());
+        for range in self.received.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        self.received = merged;
+        self.offset = self
+            .received
+            .first()
+            .filter(|range| range.start == 0)
+            .map_or(0, |range| range.end);
+    }
+
+    /// Check if the chunk with the given idempotency key was written.
+    #[must_use]
+    pub fn has_chunk_token(&self, token: &str, now: DateTime<Utc>) -> bool {
+        self.chunk_tokens
+            .iter()
+            .any(|chunk| chunk.token == token && chunk.expires_at > now)
+    }
+
+    /// Remember idempotency key of the written chunk for `ttl` seconds.
+    ///
+    /// Expired keys are dropped and only
+    /// `MAX_CHUNK_TOKENS` latest keys are kept.
+    pub fn add_chunk_token(&mut self, token: &str, ttl: u64, now: DateTime<Utc>) {
+        self.chunk_tokens
+            .retain(|chunk| chunk.expires_at > now && chunk.token != token);
+        self.chunk_tokens.push(ChunkToken {
+            token: String::from(token),
+            expires_at: now
+                + chrono::Duration::seconds(i64::try_from(ttl).unwrap_or(i64::MAX / 1000)),
+        });
+        if self.chunk_tokens.len() > MAX_CHUNK_TOKENS {
+            let excess = self.chunk_tokens.len() - MAX_CHUNK_TOKENS;
+            self.chunk_tokens.drain(..excess);
+        }
+    }
+
+    /// Check if any byte of the given range was already received.
+    #[must_use]
+    pub fn overlaps_received(&self, start: usize, end: usize) -> bool {
+        self.received
+            .iter()
+            .any(|range| start < range.end && range.start < end)
+    }
+
+    /// Ranges of bytes which were received.
+    ///
+    /// Uploads written sequentially don't track ranges,
+    /// so their only range is from the beginning to the offset.
+    #[must_use]
+    pub fn received_ranges(&self) -> Vec<ByteRange> {
+        if !self.received.is_empty() {
+            return self.received.clone();
+        }
+        if self.offset == 0 {
+            return Vec::new();
         }
+        vec![ByteRange {
+            start: 0,
+            end: self.offset,
+        }]
+    }
+
+    /// Check that received ranges cover the whole upload.
+    ///
+    /// Every byte from zero to the length must be
+    /// received exactly once.
+    ///
+    /// # Errors
+    ///
+    /// Returns `IncompleteUpload` with missing
+    /// and overlapping ranges.
+    pub fn check_coverage(&self) -> RustusResult<()> {
+        let Some(length) = self.length else {
+            return Err(RustusError::IncompleteUpload(String::from(
+                "length is unknown",
+            )));
+        };
+        let mut ranges = self.received_ranges();
+        ranges.sort_by_key(|range| range.start);
+        let mut missing = Vec::new();
+        let mut overlapping = Vec::new();
+        let mut covered = 0;
+        for range in ranges {
+            match range.start.cmp(&covered) {
+                Ordering::Greater => missing.push(ByteRange {
+                    start: covered,
+                    end: range.start,
+                }),
+                Ordering::Less => overlapping.push(ByteRange {
+                    start: range.start,
+                    end: covered.min(range.end),
+                }),
+                Ordering::Equal => {}
+            }
+            covered = covered.max(range.end);
+        }
+        if covered < length {
+            missing.push(ByteRange {
+                start: covered,
+                end: length,
+            });
+        }
+        let mut problems = Vec::new();
+        if !missing.is_empty() {
+            problems.push(format!("missing {}", join_ranges(&missing)));
+        }
+        if !overlapping.is_empty() {
+            problems.push(format!("overlapping {}", join_ranges(&overlapping)));
+        }
+        if covered > length {
+            problems.push(format!("received {covered} bytes of {length}"));
+        }
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(RustusError::IncompleteUpload(problems.join("; ")))
     }
 
     /// Function to construct `String` value
@@ -72,11 +326,23 @@ impl FileInfo {
     ///
     /// This algorithm can be found at
     /// [protocol page](https://tus.io/protocols/resumable-upload.html#upload-metadata).
+    ///
+    /// Keys are sorted, so the string is always the same
+    /// for the same metadata. Keys with empty values
+    /// are sent without values.
+    #[must_use]
     pub fn get_metadata_string(&self) -> Option<String> {
         let mut result = Vec::new();
 
         // Getting all metadata keys.
-        for (key, val) in &self.metadata {
+        let mut keys = self.metadata.keys().collect::<Vec<_>>();
+        keys.sort();
+        for key in keys {
+            let val = &self.metadata[key];
+            if val.is_empty() {
+                result.push(key.clone());
+                continue;
+            }
             let encoded_value = general_purpose::STANDARD.encode(val);
             // Adding metadata entry to the list.
             result.push(format!("{key} {encoded_value}"));
@@ -90,6 +356,11 @@ impl FileInfo {
         }
     }
 
+    /// Serialize information about the upload.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the information can't be serialized.
     pub async fn json(&self) -> RustusResult<String> {
         let info_clone = self.clone();
         tokio::task::spawn_blocking(move || {
@@ -102,6 +373,11 @@ impl FileInfo {
         })?
     }
 
+    /// Parse serialized information about the upload.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data isn't a valid information.
     pub async fn from_json(data: String) -> RustusResult<Self> {
         tokio::task::spawn_blocking(move || {
             serde_json::from_str::<Self>(data.as_str()).map_err(RustusError::from)
@@ -124,3 +400,125 @@ impl FileInfo {
         )
     }
 }
+
+fn join_ranges(ranges: &[ByteRange]) -> String {
+    ranges
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ByteRange, FileInfo};
+
+    #[test]
+    fn metadata_string() {
+        let mut file_info = FileInfo::new_test();
+        assert_eq!(file_info.get_metadata_string(), None);
+        file_info.metadata.insert(
+            String::from("filename"),
+            String::from("world_domination_plan.pdf"),
+        );
+        file_info
+            .metadata
+            .insert(String::from("is_confidential"), String::new());
+        file_info.metadata.insert(
+            String::from("content_type"),
+            String::from("application/pdf"),
+        );
+        assert_eq!(
+            file_info.get_metadata_string().unwrap(),
+            "content_type YXBwbGljYXRpb24vcGRm,filename d29ybGRfZG9taW5hdGlvbl9wbGFuLnBkZg==,is_confidential"
+        );
+    }
+
+    #[test]
+    fn test_received_ranges_merge() {
+        let mut file_info = FileInfo::new_test();
+        file_info.add_received_range(5, 10);
+        assert_eq!(file_info.offset, 0);
+        file_info.add_received_range(0, 3);
+        assert_eq!(file_info.offset, 3);
+        file_info.add_received_range(3, 5);
+        assert_eq!(file_info.offset, 10);
+        assert_eq!(file_info.received, vec![ByteRange { start: 0, end: 10 }]);
+    }
+
+    #[test]
+    fn test_received_ranges_overlap() {
+        let mut file_info = FileInfo::new_test();
+        file_info.add_received_range(2, 4);
+        assert!(file_info.overlaps_received(3, 5));
+        assert!(!file_info.overlaps_received(4, 6));
+        assert!(!file_info.overlaps_received(0, 2));
+    }
+
+    #[test]
+    fn coverage_of_sequential_upload() {
+        let mut file_info = FileInfo::new_test();
+        assert!(file_info.received_ranges().is_empty());
+        file_info.offset = 10;
+        assert_eq!(
+            file_info.received_ranges(),
+            vec![ByteRange { start: 0, end: 10 }]
+        );
+        assert!(file_info.check_coverage().is_ok());
+    }
+
+    #[test]
+    fn coverage_with_gaps() {
+        let mut file_info = FileInfo::new_test();
+        file_info.add_received_range(2, 4);
+        file_info.add_received_range(6, 8);
+        let err = file_info.check_coverage().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Upload isn't fully received: missing [0, 2), [4, 6), [8, 10)"
+        );
+        file_info.add_received_range(0, 2);
+        file_info.add_received_range(4, 6);
+        file_info.add_received_range(8, 10);
+        assert!(file_info.check_coverage().is_ok());
+    }
+
+    #[test]
+    fn coverage_with_overlaps() {
+        let mut file_info = FileInfo::new_test();
+        // Merged ranges never overlap, but stored ones may.
+        file_info.received = vec![
+            ByteRange { start: 0, end: 6 },
+            ByteRange { start: 4, end: 10 },
+        ];
+        let err = file_info.check_coverage().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Upload isn't fully received: overlapping [4, 6)"
+        );
+        file_info.received = vec![ByteRange { start: 0, end: 12 }];
+        assert!(file_info.check_coverage().is_err());
+        file_info.length = None;
+        assert!(file_info.check_coverage().is_err());
+    }
+
+    #[test]
+    fn chunk_tokens() {
+        let mut file_info = FileInfo::new_test();
+        let now = chrono::Utc::now();
+        file_info.add_chunk_token("first", 60, now);
+        assert!(file_info.has_chunk_token("first", now));
+        assert!(!file_info.has_chunk_token("second", now));
+        let later = now + chrono::Duration::seconds(61);
+        assert!(!file_info.has_chunk_token("first", later));
+        // Expired tokens are dropped by the next write.
+        file_info.add_chunk_token("second", 60, later);
+        assert_eq!(file_info.chunk_tokens.len(), 1);
+        for index in 0..100 {
+            file_info.add_chunk_token(index.to_string().as_str(), 60, later);
+        }
+        assert_eq!(file_info.chunk_tokens.len(), super::MAX_CHUNK_TOKENS);
+        assert!(file_info.has_chunk_token("99", later));
+        assert!(!file_info.has_chunk_token("second", later));
+    }
+}
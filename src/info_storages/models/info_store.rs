@@ -1,4 +1,8 @@
-use crate::{errors::RustusResult, info_storages::FileInfo};
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::{FileInfo, UploadFilter},
+    storages::chunk_index::IndexedChunk,
+};
 use async_trait::async_trait;
 use dyn_clone::DynClone;
 
@@ -28,6 +32,55 @@ pub trait InfoStorage: DynClone {
     /// for any update operation.
     async fn set_info(&self, file_info: &FileInfo, create: bool) -> RustusResult<()>;
 
+    /// Update information if it wasn't changed since it was read.
+    ///
+    /// `file_info.version` must be the version of the read information,
+    /// it's incremented once the information is updated.
+    /// Stale updates are rejected with `VersionConflict`,
+    /// so the caller can read the information again and retry.
+    ///
+    /// Default implementation isn't atomic. Storages
+    /// shared by several processes should override it.
+    async fn update_info(&self, file_info: &mut FileInfo) -> RustusResult<()> {
+        let current = self.get_info(file_info.id.as_str()).await?;
+        if current.version != file_info.version {
+            return Err(RustusError::VersionConflict(file_info.id.clone()));
+        }
+        let mut updated = file_info.clone();
+        updated.version += 1;
+        self.set_info(&updated, false).await?;
+        *file_info = updated;
+        Ok(())
+    }
+
+    /// Save information changed by a writer of the upload.
+    ///
+    /// Information is saved with `update_info`. Metadata
+    /// can be updated by other processes at the same time,
+    /// so on conflicts the current metadata is kept
+    /// and other changes are saved again.
+    async fn save_info(&self, file_info: &mut FileInfo) -> RustusResult<()> {
+        loop {
+            match self.update_info(file_info).await {
+                Err(RustusError::VersionConflict(_)) => {
+                    let current = self.get_info(file_info.id.as_str()).await?;
+                    file_info.metadata = current.metadata;
+                    file_info.version = current.version;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Flush information about an upload to durable storage.
+    ///
+    /// It's called once the upload is finished.
+    /// Storages which commit every change
+    /// may do nothing here.
+    async fn sync_info(&self, _file_info: &FileInfo) -> RustusResult<()> {
+        Ok(())
+    }
+
     /// Retrieve information from storage.
     ///
     /// This function must return information about file
@@ -39,6 +92,93 @@ pub trait InfoStorage: DynClone {
     /// This function must actually delete any stored information
     /// associated with the given `file_id`.
     async fn remove_info(&self, file_id: &str) -> RustusResult<()>;
+
+    /// Retrieve information about all uploads.
+    ///
+    /// This function is used by maintenance tasks,
+    /// so it's not required to be fast.
+    async fn list_info(&self) -> RustusResult<Vec<FileInfo>>;
+
+    /// Retrieve information about uploads
+    /// with the given value of a metadata key.
+    ///
+    /// Storages which can search by metadata
+    /// should override it to avoid reading all uploads.
+    async fn list_by_metadata(&self, key: &str, value: &str) -> RustusResult<Vec<FileInfo>> {
+        Ok(self
+            .list_info()
+            .await?
+            .into_iter()
+            .filter(|info| info.metadata.get(key).map(String::as_str) == Some(value))
+            .collect())
+    }
+
+    /// Find finished upload with the deduplication key.
+    ///
+    /// Storages which can search by the key
+    /// should override it to avoid reading all uploads.
+    async fn find_by_dedup_key(&self, key: &str) -> RustusResult<Option<FileInfo>> {
+        Ok(self.list_info().await?.into_iter().find(|info| {
+            info.dedup_key.as_deref() == Some(key) && info.length == Some(info.offset)
+        }))
+    }
+
+    /// Find uploads which match the filter.
+    ///
+    /// Uploads are sorted and paginated
+    /// as the filter requires. By default all
+    /// uploads are read and filtered in memory.
+    async fn list_files(&self, filter: &UploadFilter) -> RustusResult<Vec<FileInfo>> {
+        Ok(filter.apply(self.list_info().await?))
+    }
+
+    /// Remember that the upload was removed.
+    ///
+    /// Tombstone is kept for `ttl` seconds, so clients
+    /// can tell removed uploads from unknown ones.
+    async fn add_tombstone(&self, _file_id: &str, _ttl: u64) -> RustusResult<()> {
+        Ok(())
+    }
+
+    /// Check if the upload was removed and its tombstone hasn't expired.
+    async fn has_tombstone(&self, _file_id: &str) -> RustusResult<bool> {
+        Ok(false)
+    }
+
+    /// Read the chunk index of the upload.
+    ///
+    /// Uploads without indexed chunks have no chunks.
+    async fn get_chunks(&self, _file_id: &str) -> RustusResult<Vec<IndexedChunk>> {
+        Ok(Vec::new())
+    }
+
+    /// Replace the chunk index of the upload.
+    ///
+    /// Storages which can't keep chunk indexes return an error.
+    async fn set_chunks(&self, _file_id: &str, _chunks: &[IndexedChunk]) -> RustusResult<()> {
+        Err(RustusError::UnableToWrite(String::from(
+            "info storage can't keep chunk indexes",
+        )))
+    }
+
+    /// Reclaim space of removed information.
+    ///
+    /// Space is reclaimed only if the fraction
+    /// of unused space is at least `threshold`.
+    /// Returns number of reclaimed bytes.
+    async fn compact(&self, _threshold: f64) -> RustusResult<u64> {
+        Ok(0)
+    }
+
+    /// Total number of bytes written to all uploads.
+    async fn total_size(&self) -> RustusResult<usize> {
+        Ok(self
+            .list_info()
+            .await?
+            .iter()
+            .map(|file_info| file_info.offset)
+            .sum())
+    }
 }
 
 dyn_clone::clone_trait_object!(InfoStorage);
@@ -1,3 +1,4 @@
 pub mod available_info_storages;
 pub mod file_info;
 pub mod info_store;
+pub mod upload_filter;
@@ -0,0 +1,296 @@
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    errors::RustusResult,
+    info_storages::{FileInfo, InfoStorage},
+};
+
+/// Completion status of an upload.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadStatus {
+    Complete,
+    Incomplete,
+}
+
+/// Field to sort uploads by.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    CreatedAt,
+    /// Length of the upload or its offset if length is unknown.
+    Size,
+    Id,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Maximum number of listed uploads if the limit isn't set.
+pub const DEFAULT_LIMIT: usize = 1000;
+
+/// Parse metadata filter in format `key=value`.
+fn deserialize_metadata<'de, D>(deserializer: D) -> Result<Option<(String, String)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(filter) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let (key, value) = filter
+        .split_once('=')
+        .ok_or_else(|| serde::de::Error::custom("metadata filter must be in format key=value"))?;
+    Ok(Some((String::from(key), String::from(value))))
+}
+
+/// Conditions and order of listed uploads.
+///
+/// It's parsed from query parameters,
+/// all conditions are optional.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct UploadFilter {
+    pub status: Option<UploadStatus>,
+    /// Metadata key and its value.
+    #[serde(default, deserialize_with = "deserialize_metadata")]
+    pub metadata: Option<(String, String)>,
+    /// Uploads created at this timestamp or later.
+    pub created_after: Option<i64>,
+    /// Uploads created before this timestamp.
+    pub created_before: Option<i64>,
+    #[serde(default)]
+    pub sort: SortKey,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// Uploads which follow the upload with this ID
+    /// when sorted by ID.
+    pub after_id: Option<String>,
+    /// Number of uploads to skip.
+    #[serde(default)]
+    pub offset: usize,
+    /// Maximum number of uploads.
+    ///
+    /// It's `DEFAULT_LIMIT` if not set.
+    pub limit: Option<usize>,
+}
+
+impl UploadFilter {
+    /// Maximum number of uploads.
+    #[must_use]
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT)
+    }
+
+    /// Check that upload satisfies all conditions.
+    #[must_use]
+    pub fn matches(&self, file_info: &FileInfo) -> bool {
+        let complete = file_info.length == Some(file_info.offset);
+        match self.status {
+            Some(UploadStatus::Complete) if !complete => return false,
+            Some(UploadStatus::Incomplete) if complete => return false,
+            _ => {}
+        }
+        if let Some((key, value)) = &self.metadata {
+            if file_info.metadata.get(key) != Some(value) {
+                return false;
+            }
+        }
+        let created_at = file_info.created_at.timestamp();
+        self.created_after.is_none_or(|after| created_at >= after)
+            && self.created_before.is_none_or(|before| created_at < before)
+    }
+
+    /// Filter, sort and paginate uploads.
+    #[must_use]
+    pub fn apply(&self, uploads: Vec<FileInfo>) -> Vec<FileInfo> {
+        let mut uploads = uploads
+            .into_iter()
+            .filter(|file_info| self.matches(file_info))
+            .collect::<Vec<_>>();
+        // Uploads with the same key are ordered by ID,
+        // so pages are stable.
+        match self.sort {
+            SortKey::CreatedAt => {
+                uploads.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+            }
+            SortKey::Size => uploads.sort_by(|a, b| {
+                (a.length.unwrap_or(a.offset), &a.id).cmp(&(b.length.unwrap_or(b.offset), &b.id))
+            }),
+            SortKey::Id => uploads.sort_by(|a, b| a.id.cmp(&b.id)),
+        }
+        if self.order == SortOrder::Desc {
+            uploads.reverse();
+        }
+        if let Some(after) = &self.after_id {
+            uploads.retain(|file_info| match self.order {
+                SortOrder::Asc => &file_info.id > after,
+                SortOrder::Desc => &file_info.id < after,
+            });
+        }
+        uploads
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit())
+            .collect()
+    }
+}
+
+/// Pages of uploads which match the filter.
+///
+/// Uploads are sorted by ID, and every page starts
+/// after the last upload of the previous one. So uploads
+/// removed or created while pages are read don't shift pages,
+/// and every other upload is returned exactly once.
+pub struct UploadPages<'a> {
+    info_storage: &'a dyn InfoStorage,
+    filter: UploadFilter,
+    page_size: usize,
+    done: bool,
+}
+
+impl<'a> UploadPages<'a> {
+    pub fn new(info_storage: &'a dyn InfoStorage, filter: UploadFilter, page_size: usize) -> Self {
+        Self {
+            info_storage,
+            filter: UploadFilter {
+                sort: SortKey::Id,
+                order: SortOrder::Asc,
+                after_id: None,
+                offset: 0,
+                limit: Some(page_size),
+                ..filter
+            },
+            page_size,
+            done: false,
+        }
+    }
+
+    /// Read the next page.
+    ///
+    /// Returns `None` once all uploads are read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if uploads can't be listed.
+    pub async fn next_page(&mut self) -> RustusResult<Option<Vec<FileInfo>>> {
+        if self.done {
+            return Ok(None);
+        }
+        let page = self.info_storage.list_files(&self.filter).await?;
+        self.done = page.len() < self.page_size;
+        match page.last() {
+            Some(last) => self.filter.after_id = Some(last.id.clone()),
+            None => return Ok(None),
+        }
+        Ok(Some(page))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SortKey, SortOrder, UploadFilter, UploadStatus, DEFAULT_LIMIT};
+    use crate::info_storages::FileInfo;
+    use actix_web::web::Query;
+    use chrono::TimeZone;
+
+    fn uploads() -> Vec<FileInfo> {
+        let mut small = FileInfo::new("small", Some(5), None, String::new(), None);
+        small.metadata.insert("tenant".into(), "acme".into());
+        small.created_at = chrono::Utc.timestamp_opt(100, 0).unwrap();
+        let mut big = FileInfo::new("big", Some(50), None, String::new(), None);
+        big.offset = 50;
+        big.created_at = chrono::Utc.timestamp_opt(200, 0).unwrap();
+        let mut deferred = FileInfo::new("deferred", None, None, String::new(), None);
+        deferred.offset = 20;
+        deferred.metadata.insert("tenant".into(), "acme".into());
+        deferred.created_at = chrono::Utc.timestamp_opt(300, 0).unwrap();
+        vec![deferred, big, small]
+    }
+
+    fn ids(uploads: &[FileInfo]) -> Vec<&str> {
+        uploads.iter().map(|upload| upload.id.as_str()).collect()
+    }
+
+    #[test]
+    fn conditions() {
+        let filter = UploadFilter {
+            status: Some(UploadStatus::Incomplete),
+            metadata: Some(("tenant".into(), "acme".into())),
+            ..UploadFilter::default()
+        };
+        assert_eq!(ids(&filter.apply(uploads())), vec!["small", "deferred"]);
+        let filter = UploadFilter {
+            status: Some(UploadStatus::Complete),
+            ..UploadFilter::default()
+        };
+        assert_eq!(ids(&filter.apply(uploads())), vec!["big"]);
+        let filter = UploadFilter {
+            created_after: Some(200),
+            created_before: Some(300),
+            ..UploadFilter::default()
+        };
+        assert_eq!(ids(&filter.apply(uploads())), vec!["big"]);
+    }
+
+    #[test]
+    fn sorting() {
+        let filter = UploadFilter {
+            sort: SortKey::Size,
+            order: SortOrder::Desc,
+            ..UploadFilter::default()
+        };
+        assert_eq!(
+            ids(&filter.apply(uploads())),
+            vec!["big", "deferred", "small"]
+        );
+        let filter = UploadFilter {
+            offset: 1,
+            limit: Some(1),
+            ..UploadFilter::default()
+        };
+        assert_eq!(ids(&filter.apply(uploads())), vec!["big"]);
+        // Uploads are limited by default.
+        let many = (0..DEFAULT_LIMIT + 1)
+            .map(|index| FileInfo::new(index.to_string().as_str(), None, None, String::new(), None))
+            .collect();
+        assert_eq!(UploadFilter::default().apply(many).len(), DEFAULT_LIMIT);
+    }
+
+    #[test]
+    fn after_id() {
+        let filter = UploadFilter {
+            sort: SortKey::Id,
+            after_id: Some("big".into()),
+            ..UploadFilter::default()
+        };
+        assert_eq!(ids(&filter.apply(uploads())), vec!["deferred", "small"]);
+        let filter = UploadFilter {
+            sort: SortKey::Id,
+            order: SortOrder::Desc,
+            after_id: Some("small".into()),
+            ..UploadFilter::default()
+        };
+        assert_eq!(ids(&filter.apply(uploads())), vec!["deferred", "big"]);
+    }
+
+    #[test]
+    fn query() {
+        let filter = Query::<UploadFilter>::from_query(
+            "status=complete&metadata=tenant%3Dacme&sort=size&order=desc&limit=10",
+        )
+        .unwrap()
+        .into_inner();
+        assert_eq!(filter.status, Some(UploadStatus::Complete));
+        assert_eq!(filter.metadata, Some(("tenant".into(), "acme".into())));
+        assert_eq!(filter.sort, SortKey::Size);
+        assert_eq!(filter.order, SortOrder::Desc);
+        assert_eq!(filter.limit, Some(10));
+        assert!(Query::<UploadFilter>::from_query("metadata=tenant").is_err());
+        assert!(Query::<UploadFilter>::from_query("sort=name").is_err());
+    }
+}
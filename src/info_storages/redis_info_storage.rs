@@ -9,20 +9,71 @@ use redis::aio::Connection;
 use crate::{
     errors::{RustusError, RustusResult},
     info_storages::{FileInfo, InfoStorage},
+    storages::chunk_index::IndexedChunk,
 };
 
+/// Prefix of keys with tombstones of removed uploads.
+const TOMBSTONE_PREFIX: &str = "tombstone:";
+/// Prefix of keys with chunk indexes of uploads.
+const CHUNKS_PREFIX: &str = "chunks:";
+
+/// Script which sets information only if its version wasn't changed.
+///
+/// Returns -1 if there's no information, 0 if the version
+/// was changed and 1 if the information was updated.
+const UPDATE_SCRIPT: &str = r"
+local current = redis.call('GET', KEYS[1])
+if not current then
+    return -1
+end
+if (cjson.decode(current)['version'] or 0) ~= tonumber(ARGV[2]) then
+    return 0
+end
+if ARGV[3] then
+    redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[3])
+else
+    redis.call('SET', KEYS[1], ARGV[1])
+end
+return 1
+";
+
 #[derive(Clone)]
 pub struct RedisStorage {
     pool: Pool<RedisConnectionManager>,
     expiration: Option<usize>,
+    /// Prefix of all keys.
+    prefix: Option<String>,
 }
 
 impl RedisStorage {
+    /// Create connection pool of Redis.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the DSN is invalid.
     #[allow(clippy::unused_async)]
     pub async fn new(db_dsn: &str, expiration: Option<usize>) -> RustusResult<Self> {
         let manager = RedisConnectionManager::new(db_dsn)?;
         let pool = bb8::Pool::builder().max_size(100).build(manager).await?;
-        Ok(Self { pool, expiration })
+        Ok(Self {
+            pool,
+            expiration,
+            prefix: None,
+        })
+    }
+
+    /// Store information under the prefix.
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: String) -> Self {
+        self.prefix = Some(prefix);
+        self
+    }
+
+    fn key(&self, name: &str) -> String {
+        match &self.prefix {
+            Some(prefix) => format!("{prefix}/{name}"),
+            None => String::from(name),
+        }
     }
 }
 
@@ -36,7 +87,7 @@ impl InfoStorage for RedisStorage {
         let mut conn = self.pool.get().await?;
         let mut cmd = redis::cmd("SET");
         let mut cmd = cmd
-            .arg(file_info.id.as_str())
+            .arg(self.key(file_info.id.as_str()))
             .arg(file_info.json().await?.as_str());
         if let Some(expiration) = self.expiration.as_ref() {
             cmd = cmd.arg("EX").arg(expiration);
@@ -47,10 +98,35 @@ impl InfoStorage for RedisStorage {
         Ok(())
     }
 
+    async fn update_info(&self, file_info: &mut FileInfo) -> RustusResult<()> {
+        let mut conn = self.pool.get().await?;
+        let mut updated = file_info.clone();
+        updated.version += 1;
+        let script = redis::Script::new(UPDATE_SCRIPT);
+        let mut invocation = script.key(self.key(file_info.id.as_str()));
+        invocation
+            .arg(updated.json().await?.as_str())
+            .arg(file_info.version);
+        if let Some(expiration) = self.expiration.as_ref() {
+            invocation.arg(expiration);
+        }
+        match invocation
+            .invoke_async::<Connection, i64>(&mut conn)
+            .await?
+        {
+            -1 => Err(RustusError::FileNotFound),
+            0 => Err(RustusError::VersionConflict(file_info.id.clone())),
+            _ => {
+                *file_info = updated;
+                Ok(())
+            }
+        }
+    }
+
     async fn get_info(&self, file_id: &str) -> RustusResult<FileInfo> {
         let mut conn = self.pool.get().await?;
         let res = redis::cmd("GET")
-            .arg(file_id)
+            .arg(self.key(file_id))
             .query_async::<Connection, Option<String>>(&mut conn)
             .await?;
         if res.is_none() {
@@ -62,13 +138,118 @@ impl InfoStorage for RedisStorage {
     async fn remove_info(&self, file_id: &str) -> RustusResult<()> {
         let mut conn = self.pool.get().await?;
         let resp = redis::cmd("DEL")
-            .arg(file_id)
+            .arg(self.key(file_id))
             .query_async::<Connection, Option<usize>>(&mut conn)
             .await?;
         match resp {
             None | Some(0) => Err(RustusError::FileNotFound),
-            _ => Ok(()),
+            _ => {
+                // Chunk index is useless without the upload.
+                redis::cmd("DEL")
+                    .arg(self.key(format!("{CHUNKS_PREFIX}{file_id}").as_str()))
+                    .query_async::<Connection, usize>(&mut conn)
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn get_chunks(&self, file_id: &str) -> RustusResult<Vec<IndexedChunk>> {
+        let mut conn = self.pool.get().await?;
+        let chunks = redis::cmd("GET")
+            .arg(self.key(format!("{CHUNKS_PREFIX}{file_id}").as_str()))
+            .query_async::<Connection, Option<String>>(&mut conn)
+            .await?;
+        match chunks {
+            Some(chunks) => serde_json::from_str(chunks.as_str()).map_err(RustusError::from),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn set_chunks(&self, file_id: &str, chunks: &[IndexedChunk]) -> RustusResult<()> {
+        let mut conn = self.pool.get().await?;
+        let key = self.key(format!("{CHUNKS_PREFIX}{file_id}").as_str());
+        if chunks.is_empty() {
+            redis::cmd("DEL")
+                .arg(key)
+                .query_async::<Connection, usize>(&mut conn)
+                .await?;
+            return Ok(());
+        }
+        let mut cmd = redis::cmd("SET");
+        let mut cmd = cmd
+            .arg(key)
+            .arg(serde_json::to_string(chunks).map_err(RustusError::from)?);
+        // Index expires with information about the upload.
+        if let Some(expiration) = self.expiration {
+            cmd = cmd.arg("EX").arg(expiration);
         }
+        cmd.query_async::<Connection, String>(&mut conn).await?;
+        Ok(())
+    }
+
+    async fn add_tombstone(&self, file_id: &str, ttl: u64) -> RustusResult<()> {
+        let mut conn = self.pool.get().await?;
+        redis::cmd("SET")
+            .arg(self.key(format!("{TOMBSTONE_PREFIX}{file_id}").as_str()))
+            .arg(1)
+            .arg("EX")
+            .arg(ttl)
+            .query_async::<Connection, String>(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn has_tombstone(&self, file_id: &str) -> RustusResult<bool> {
+        let mut conn = self.pool.get().await?;
+        let exists = redis::cmd("EXISTS")
+            .arg(self.key(format!("{TOMBSTONE_PREFIX}{file_id}").as_str()))
+            .query_async::<Connection, usize>(&mut conn)
+            .await?;
+        Ok(exists > 0)
+    }
+
+    async fn list_info(&self) -> RustusResult<Vec<FileInfo>> {
+        let mut conn = self.pool.get().await?;
+        let mut infos = Vec::new();
+        let mut cursor = 0_u64;
+        loop {
+            let (next_cursor, keys) = redis::cmd("SCAN")
+                .cursor_arg(cursor)
+                // Keys of other prefixes are skipped.
+                .arg("MATCH")
+                .arg(self.key("*"))
+                .arg("COUNT")
+                .arg(100)
+                .query_async::<Connection, (u64, Vec<String>)>(&mut conn)
+                .await?;
+            let own_prefix = self.key("");
+            for key in keys {
+                // Pattern without prefix matches keys of all prefixes,
+                // IDs never contain slashes, so they're skipped.
+                if key
+                    .strip_prefix(own_prefix.as_str())
+                    .is_none_or(|id| id.contains('/'))
+                {
+                    continue;
+                }
+                let value = redis::cmd("GET")
+                    .arg(key.as_str())
+                    .query_async::<Connection, Option<String>>(&mut conn)
+                    .await;
+                // Database may contain keys which are not related to rustus.
+                if let Ok(Some(info)) = value {
+                    if let Ok(file_info) = FileInfo::from_json(info).await {
+                        infos.push(file_info);
+                    }
+                }
+            }
+            if next_cursor == 0 {
+                break;
+            }
+            cursor = next_cursor;
+        }
+        Ok(infos)
     }
 }
 
@@ -76,7 +257,7 @@ impl InfoStorage for RedisStorage {
 #[cfg(feature = "test_redis")]
 mod tests {
     use super::RedisStorage;
-    use crate::{info_storages::FileInfo, InfoStorage};
+    use crate::{errors::RustusError, info_storages::FileInfo, InfoStorage};
     use redis::AsyncCommands;
 
     async fn get_storage() -> RedisStorage {
@@ -106,6 +287,28 @@ mod tests {
         assert_eq!(file_info.storage, file_info_from_storage.storage);
     }
 
+    #[actix_rt::test]
+    async fn other_prefixes() {
+        let info_storage = get_storage().await;
+        let staging = get_storage().await.with_prefix(String::from("staging"));
+        let file_info = FileInfo::new_test();
+        staging.set_info(&file_info, true).await.unwrap();
+        // Keys of prefixed storages match the pattern without prefix.
+        assert!(!info_storage
+            .list_info()
+            .await
+            .unwrap()
+            .iter()
+            .any(|info| info.id == file_info.id));
+        assert!(staging
+            .list_info()
+            .await
+            .unwrap()
+            .iter()
+            .any(|info| info.id == file_info.id));
+        staging.remove_info(file_info.id.as_str()).await.unwrap();
+    }
+
     #[actix_rt::test]
     async fn no_connection() {
         let info_storage = RedisStorage::new("redis://unknonwn_url/0", None)
@@ -148,4 +351,35 @@ mod tests {
             .unwrap()
             .is_none());
     }
+
+    #[actix_rt::test]
+    async fn tombstones() {
+        let info_storage = get_storage().await;
+        let file_id = uuid::Uuid::new_v4().to_string();
+        assert!(!info_storage.has_tombstone(file_id.as_str()).await.unwrap());
+        info_storage
+            .add_tombstone(file_id.as_str(), 1)
+            .await
+            .unwrap();
+        assert!(info_storage.has_tombstone(file_id.as_str()).await.unwrap());
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+        assert!(!info_storage.has_tombstone(file_id.as_str()).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn stale_update() {
+        let info_storage = get_storage().await;
+        let file_info = FileInfo::new_test();
+        info_storage.set_info(&file_info, true).await.unwrap();
+        let mut first = file_info.clone();
+        let mut second = file_info.clone();
+        first.metadata.insert("scan".into(), "clean".into());
+        info_storage.update_info(&mut first).await.unwrap();
+        assert_eq!(first.version, 1);
+        let err = info_storage.update_info(&mut second).await.unwrap_err();
+        assert!(matches!(err, RustusError::VersionConflict(_)));
+        let mut missing = FileInfo::new_test();
+        let err = info_storage.update_info(&mut missing).await.unwrap_err();
+        assert!(matches!(err, RustusError::FileNotFound));
+    }
 }
@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::{
+    errors::RustusResult,
+    info_storages::{FileInfo, InfoStorage, UploadFilter},
+    storages::chunk_index::IndexedChunk,
+    utils::timeout::with_timeout,
+};
+
+/// Info storage wrapper that limits duration of every operation.
+///
+/// Reading operations are limited by `read_timeout`,
+/// all other operations are limited by `write_timeout`.
+#[derive(Clone)]
+pub struct TimeoutInfoStorage {
+    inner: Box<dyn InfoStorage + Send + Sync>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl TimeoutInfoStorage {
+    #[must_use]
+    pub fn new(
+        inner: Box<dyn InfoStorage + Send + Sync>,
+        read_timeout: Option<Duration>,
+        write_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            inner,
+            read_timeout,
+            write_timeout,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl InfoStorage for TimeoutInfoStorage {
+    async fn prepare(&mut self) -> RustusResult<()> {
+        self.inner.prepare().await
+    }
+
+    async fn set_info(&self, file_info: &FileInfo, create: bool) -> RustusResult<()> {
+        with_timeout(
+            self.write_timeout,
+            "set_info",
+            self.inner.set_info(file_info, create),
+        )
+        .await
+    }
+
+    async fn update_info(&self, file_info: &mut FileInfo) -> RustusResult<()> {
+        with_timeout(
+            self.write_timeout,
+            "update_info",
+            self.inner.update_info(file_info),
+        )
+        .await
+    }
+
+    async fn sync_info(&self, file_info: &FileInfo) -> RustusResult<()> {
+        with_timeout(
+            self.write_timeout,
+            "sync_info",
+            self.inner.sync_info(file_info),
+        )
+        .await
+    }
+
+    async fn get_info(&self, file_id: &str) -> RustusResult<FileInfo> {
+        with_timeout(self.read_timeout, "get_info", self.inner.get_info(file_id)).await
+    }
+
+    async fn remove_info(&self, file_id: &str) -> RustusResult<()> {
+        with_timeout(
+            self.write_timeout,
+            "remove_info",
+            self.inner.remove_info(file_id),
+        )
+        .await
+    }
+
+    async fn list_info(&self) -> RustusResult<Vec<FileInfo>> {
+        with_timeout(self.read_timeout, "list_info", self.inner.list_info()).await
+    }
+
+    async fn list_by_metadata(&self, key: &str, value: &str) -> RustusResult<Vec<FileInfo>> {
+        with_timeout(
+            self.read_timeout,
+            "list_by_metadata",
+            self.inner.list_by_metadata(key, value),
+        )
+        .await
+    }
+
+    async fn find_by_dedup_key(&self, key: &str) -> RustusResult<Option<FileInfo>> {
+        with_timeout(
+            self.read_timeout,
+            "find_by_dedup_key",
+            self.inner.find_by_dedup_key(key),
+        )
+        .await
+    }
+
+    async fn list_files(&self, filter: &UploadFilter) -> RustusResult<Vec<FileInfo>> {
+        with_timeout(
+            self.read_timeout,
+            "list_files",
+            self.inner.list_files(filter),
+        )
+        .await
+    }
+
+    async fn add_tombstone(&self, file_id: &str, ttl: u64) -> RustusResult<()> {
+        with_timeout(
+            self.write_timeout,
+            "add_tombstone",
+            self.inner.add_tombstone(file_id, ttl),
+        )
+        .await
+    }
+
+    async fn has_tombstone(&self, file_id: &str) -> RustusResult<bool> {
+        with_timeout(
+            self.read_timeout,
+            "has_tombstone",
+            self.inner.has_tombstone(file_id),
+        )
+        .await
+    }
+
+    async fn get_chunks(&self, file_id: &str) -> RustusResult<Vec<IndexedChunk>> {
+        with_timeout(
+            self.read_timeout,
+            "get_chunks",
+            self.inner.get_chunks(file_id),
+        )
+        .await
+    }
+
+    async fn set_chunks(&self, file_id: &str, chunks: &[IndexedChunk]) -> RustusResult<()> {
+        with_timeout(
+            self.write_timeout,
+            "set_chunks",
+            self.inner.set_chunks(file_id, chunks),
+        )
+        .await
+    }
+
+    async fn compact(&self, threshold: f64) -> RustusResult<u64> {
+        self.inner.compact(threshold).await
+    }
+
+    async fn total_size(&self) -> RustusResult<usize> {
+        with_timeout(self.read_timeout, "total_size", self.inner.total_size()).await
+    }
+}
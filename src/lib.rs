@@ -0,0 +1,770 @@
+#![cfg_attr(coverage, feature(no_coverage))]
+
+use std::{
+    cell::Cell,
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use actix_cors::Cors;
+use actix_http::{HttpService, Protocol, Request};
+use actix_service::{apply_fn_factory, map_config, ServiceFactoryExt};
+use actix_web::{
+    dev::{fn_service, AppConfig, Server, Service},
+    http::{KeepAlive, Method, Uri},
+    middleware, web, App,
+};
+use fern::{
+    colors::{Color, ColoredLevelConfig},
+    Dispatch,
+};
+use log::{error, LevelFilter};
+
+use config::RustusConf;
+
+use metrics::RustusMetrics;
+use wildmatch::WildMatch;
+
+use crate::{
+    admin::admin_service,
+    errors::{RustusError, RustusResult},
+    info_storages::{timeout_info_storage::TimeoutInfoStorage, InfoStorage},
+    notifiers::models::notification_manager::NotificationManager,
+    server::rustus_service,
+    state::State,
+    storages::{
+        aligned_storage::AlignedStorage, chunk_index::ChunkIndex,
+        consistent_storage::ConsistentStorage, failover_storage::FailoverStorage,
+        file_storage::FileStorage, replicated_storage::ReplicatedStorage,
+        timeout_storage::TimeoutStorage, Storage,
+    },
+    utils::orphans,
+};
+
+mod admin;
+mod background;
+mod backup;
+pub mod config;
+pub mod errors;
+pub mod info_storages;
+mod metrics;
+mod notifiers;
+mod protocol;
+mod report;
+mod routes;
+mod selftest;
+mod server;
+mod state;
+pub mod storages;
+#[cfg(feature = "otlp_tracing")]
+mod telemetry;
+mod utils;
+
+#[cfg_attr(coverage, no_coverage)]
+fn greeting(app_conf: &RustusConf) {
+    let extensions = app_conf
+        .tus_extensions
+        .clone()
+        .into_iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    let hooks = app_conf
+        .notification_opts
+        .hooks
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<String>>()
+        .join(", ");
+    let rustus_logo = include_str!("../imgs/rustus_startup_logo.txt");
+    eprintln!("\n\n{rustus_logo}");
+    eprintln!("Welcome to rustus!");
+    eprintln!("Version: {}", env!("CARGO_PKG_VERSION"));
+    eprintln!("Base URL: /{}", app_conf.base_url());
+    eprintln!("Available extensions: {extensions}");
+    eprintln!("Enabled hooks: {hooks}");
+    eprintln!();
+    eprintln!();
+}
+
+/// Create CORS rules for the server.
+///
+/// CORS rules are applied to every handler.
+///
+/// If the origins vector is empty all origins are
+/// welcome, otherwise it will create a wildcard match for
+/// every host.
+fn create_cors(origins: Vec<String>, additional_headers: Vec<String>) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(vec!["OPTIONS", "GET", "HEAD", "POST", "PATCH", "DELETE"])
+        .allowed_headers(vec![
+            "Content-Type",
+            "Upload-Offset",
+            "Upload-Checksum",
+            "Upload-Length",
+            "Upload-Metadata",
+            "Upload-Concat",
+            "Upload-Defer-Length",
+            "Upload-Passphrase",
+            "Idempotency-Key",
+            "Upload-Complete",
+            "Tus-Resumable",
+            "Tus-Version",
+            "X-HTTP-Method-Override",
+            "Authorization",
+            "Origin",
+            "X-Requested-With",
+            "X-Request-ID",
+            "X-HTTP-Method-Override",
+        ])
+        .allowed_headers(additional_headers.into_iter())
+        .expose_headers(vec![
+            "Location",
+            "Tus-Version",
+            "Tus-Resumable",
+            "Tus-Max-Size",
+            "Tus-Extension",
+            "Tus-Checksum-Algorithm",
+            "Rustus-Preferred-Chunk-Size",
+            "Rustus-Manifest-Signature",
+            "Content-Type",
+            "Content-Length",
+            "Upload-Length",
+            "Upload-Metadata",
+            "Upload-Defer-Length",
+            "Upload-Concat",
+            "Upload-Offset",
+        ])
+        .max_age(86400);
+
+    // We allow any origin by default if no origin is specified.
+    if origins.is_empty() {
+        return cors.allow_any_origin();
+    }
+
+    // Adding origins.
+    for origin in origins {
+        cors = cors.allowed_origin_fn(move |request_origin, _| {
+            WildMatch::new(&origin) == request_origin.to_str().unwrap_or_default()
+        });
+    }
+
+    cors
+}
+
+/// Time to wait for clients to close connections,
+/// the same as the default of `HttpServer`.
+const CLIENT_DISCONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Creates Actix server.
+///
+/// This function is parametrized with
+/// Storage class.
+///
+/// This storage can later be used in
+/// handlers.
+///
+/// # Errors
+///
+/// This function may throw an error
+/// if the server can't be bound to the
+/// given address.
+#[cfg_attr(coverage, no_coverage)]
+#[allow(clippy::too_many_lines)]
+// App is only wrapped with tracing middleware if the feature is enabled.
+#[cfg_attr(not(feature = "otlp_tracing"), allow(clippy::let_and_return))]
+fn create_server(state: State) -> RustusResult<Server> {
+    let host = state.config.host.clone();
+    let port = state.config.port;
+    let dual_stack = state.config.dual_stack;
+    let unix_socket = state.config.unix_socket.clone();
+    #[cfg(unix)]
+    let unix_socket_mode = state.config.unix_socket_mode;
+    let unix_socket_only = state.config.unix_socket_only;
+    let disable_health_log = state.config.disable_health_access_log;
+    let client_ip = state.config.client_ip.clone();
+    let cors_hosts = state.config.cors.clone();
+    let workers = state.config.workers;
+    let max_blocking_threads = state.config.max_blocking_threads;
+    let keep_alive = match state.config.keep_alive {
+        0 => KeepAlive::Disabled,
+        seconds => KeepAlive::Timeout(Duration::from_secs(seconds)),
+    };
+    let client_request_timeout = Duration::from_millis(state.config.client_request_timeout);
+    let max_connections = state.config.max_connections;
+    let core_ids = if state.config.pin_workers {
+        core_affinity::get_core_ids().unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    let next_core = Arc::new(AtomicUsize::new(0));
+    let admin_api = state.config.admin_api;
+    let root_info = if state.config.disable_root_info {
+        None
+    } else {
+        Some(routes::root_info(&state.config))
+    };
+    let version_info = routes::version_info().to_string();
+    let tls_config = utils::tls::TlsConfig::from_config(&state.config);
+    let http2 = state.config.http2;
+    if let Some(tls_config) = &tls_config {
+        // Checking certificates and settings before starting the server.
+        tls_config.acceptor()?;
+    }
+    let proxy_headers = state
+        .config
+        .notification_opts
+        .hooks_http_proxy_headers
+        .clone();
+    let metrics = RustusMetrics::new(&state.config)?;
+    state.compaction_metrics.register(&metrics.registry)?;
+    let metrics_middleware = actix_web_prom::PrometheusMetricsBuilder::new("")
+        .endpoint("/metrics")
+        .registry(metrics.registry.clone())
+        .build()
+        .map_err(|err| {
+            error!("{}", err);
+            RustusError::Unknown
+        })?;
+    let expect_state = state.clone();
+    let app_factory = move || {
+        // App factory is called on every worker thread.
+        if !core_ids.is_empty() {
+            pin_worker(core_ids.as_slice(), next_core.as_ref());
+        }
+        // Client's IP is logged the same way it's sent in hooks.
+        let request_ip = client_ip.clone();
+        let mut logger = middleware::Logger::new("\"%r\" \"-\" \"%s\" \"%{client_ip}xi\" \"%D\"")
+            .custom_request_replace("client_ip", move |req| {
+                request_ip
+                    .resolve(req.request())
+                    .unwrap_or_else(|| String::from("-"))
+            });
+        if disable_health_log {
+            logger = logger.exclude("/health");
+        }
+        let error_metrics = metrics.found_errors.clone();
+        let admin_state = state.clone();
+        let info_state = state.clone();
+        let app = App::new()
+            .app_data(web::Data::new(metrics.clone()))
+            .route("/health", web::get().to(routes::health_check))
+            .configure(|web_app| {
+                if let Some(info) = root_info.clone() {
+                    // Capabilities include drain mode,
+                    // so they are generated for every request.
+                    let info_state = info_state.clone();
+                    let version_info = version_info.clone();
+                    web_app
+                        .route(
+                            "/",
+                            web::get().to(move || {
+                                let response = routes::root(info.as_str());
+                                async move { response }
+                            }),
+                        )
+                        .route(
+                            "/version",
+                            web::get().to(move || {
+                                let response = routes::root(version_info.as_str());
+                                async move { response }
+                            }),
+                        )
+                        .route(
+                            "/info",
+                            web::get().to(move || {
+                                let response = routes::root(
+                                    routes::capabilities(
+                                        &info_state.config,
+                                        info_state.is_draining(),
+                                        info_state.cleanup.is_paused(),
+                                    )
+                                    .as_str(),
+                                );
+                                async move { response }
+                            }),
+                        );
+                }
+            })
+            .configure(move |web_app| {
+                if admin_api {
+                    admin_service(admin_state.clone())(web_app);
+                }
+            })
+            .configure(rustus_service(state.clone()))
+            .wrap(metrics_middleware.clone())
+            .wrap(logger)
+            .wrap(create_cors(cors_hosts.clone(), proxy_headers.clone()))
+            .wrap(sentry_actix::Sentry::new())
+            // Middleware that overrides method of a request if
+            // "X-HTTP-Method-Override" header is provided.
+            .wrap_fn(|mut req, srv| {
+                if let Some(header_value) = req.headers_mut().get("X-HTTP-Method-Override") {
+                    if let Ok(method_name) = header_value.to_str() {
+                        if let Ok(method) = Method::from_str(method_name) {
+                            req.head_mut().method = method;
+                        }
+                    }
+                }
+                srv.call(req)
+            })
+            // This is middleware that registers found errors.
+            .wrap_fn(move |req, srv| {
+                // Call the service to resolve handler and return response.
+                let fut = srv.call(req);
+                // We need this copy, since we use it in moved closure later.
+                let error_counter = error_metrics.clone();
+                async move {
+                    let srv_response = fut.await?;
+                    if let Some(err) = srv_response.response().error() {
+                        let url = match srv_response.request().match_pattern() {
+                            Some(pattern) => pattern,
+                            None => String::new(),
+                        };
+                        let err_desc = format!("{err}");
+                        error_counter
+                            .clone()
+                            .with_label_values(&[url.as_str(), err_desc.as_str()])
+                            .inc();
+                    }
+                    Ok(srv_response)
+                }
+            })
+            // Default response for unknown requests.
+            // It returns 404 status_code.
+            .default_service(web::route().to(routes::not_found));
+        // Spans of requests include all other middlewares.
+        #[cfg(feature = "otlp_tracing")]
+        let app = app.wrap_fn(telemetry::trace_request);
+        app
+    };
+    // `HttpServer` sends `100 Continue` without checking requests,
+    // so services of listeners are built the same way it does,
+    // but with our own expect service.
+    let mut server = Server::build();
+    if let Some(socket_path) = &unix_socket {
+        #[cfg(unix)]
+        {
+            use actix_web::rt::net::UnixStream;
+
+            // Socket left by the previous run is removed.
+            utils::unix_socket::remove_stale_socket(socket_path)?;
+            let listener = utils::unix_socket::bind(socket_path, unix_socket_mode)?;
+            let app_factory = app_factory.clone();
+            let expect_state = expect_state.clone();
+            server = server.listen_uds(
+                format!("rustus-{}", socket_path.display()),
+                listener,
+                move || {
+                    fn_service(|io: UnixStream| async { Ok((io, Protocol::Http1, None)) }).and_then(
+                        HttpService::build()
+                            .keep_alive(keep_alive)
+                            .client_request_timeout(client_request_timeout)
+                            .client_disconnect_timeout(CLIENT_DISCONNECT_TIMEOUT)
+                            .expect(protocol::expect_service(expect_state.clone()))
+                            // Unix sockets don't have an address, so the default config is used.
+                            .finish(map_config(app_factory(), |()| AppConfig::default())),
+                    )
+                },
+            )?;
+            log::info!("Listening on unix socket {}.", socket_path.display());
+        }
+        #[cfg(not(unix))]
+        {
+            error!(
+                "Cannot listen on {}. Unix sockets are supported only on unix systems.",
+                socket_path.display()
+            );
+            return Err(RustusError::Unknown);
+        }
+    }
+    if !unix_socket_only {
+        for listener in utils::listener::bind(host.as_str(), port, dual_stack)? {
+            let addr = listener.local_addr()?;
+            log::info!("Listening on {}.", addr);
+            let name = format!("rustus-{addr}");
+            let app_factory = app_factory.clone();
+            let expect_state = expect_state.clone();
+            server = if let Some(tls_config) = &tls_config {
+                // Every listener needs its own acceptor.
+                let acceptor = tls_config.acceptor()?;
+                server.listen(name, listener, move || {
+                    HttpService::build()
+                        .keep_alive(keep_alive)
+                        .client_request_timeout(client_request_timeout)
+                        .client_disconnect_timeout(CLIENT_DISCONNECT_TIMEOUT)
+                        .local_addr(addr)
+                        .expect(protocol::expect_service(expect_state.clone()))
+                        .finish(apply_fn_factory(
+                            map_config(app_factory(), |()| AppConfig::default()),
+                            move |req, srv| srv.call(listener_uri(req, true, addr)),
+                        ))
+                        .openssl(acceptor.clone())
+                })?
+            } else if http2 {
+                // Connections starting with HTTP/2 preface are served as h2c.
+                server.listen(name, listener, move || {
+                    HttpService::build()
+                        .keep_alive(keep_alive)
+                        .client_request_timeout(client_request_timeout)
+                        .client_disconnect_timeout(CLIENT_DISCONNECT_TIMEOUT)
+                        .local_addr(addr)
+                        .expect(protocol::expect_service(expect_state.clone()))
+                        .finish(apply_fn_factory(
+                            map_config(app_factory(), |()| AppConfig::default()),
+                            move |req, srv| srv.call(listener_uri(req, false, addr)),
+                        ))
+                        .tcp_auto_h2c()
+                })?
+            } else {
+                server.listen(name, listener, move || {
+                    HttpService::build()
+                        .keep_alive(keep_alive)
+                        .client_request_timeout(client_request_timeout)
+                        .client_disconnect_timeout(CLIENT_DISCONNECT_TIMEOUT)
+                        .local_addr(addr)
+                        .expect(protocol::expect_service(expect_state.clone()))
+                        .finish(apply_fn_factory(
+                            map_config(app_factory(), |()| AppConfig::default()),
+                            move |req, srv| srv.call(listener_uri(req, false, addr)),
+                        ))
+                        .tcp()
+                })?
+            };
+        }
+    }
+
+    // If custom workers count variable is provided.
+    if let Some(workers_count) = workers {
+        server = server.workers(workers_count);
+    }
+
+    if let Some(threads_count) = max_blocking_threads {
+        server = server.worker_max_blocking_threads(threads_count);
+    }
+
+    if let Some(connections) = max_connections {
+        server = server.max_concurrent_connections(connections);
+    }
+
+    Ok(server.run())
+}
+
+/// Add scheme and address of the listener to URI of the request.
+///
+/// Actix exposes only the default `AppConfig`, which has no
+/// address of the listener and is never secure. URLs of uploads
+/// are built from URIs if requests don't have `Host` and
+/// `X-Forwarded-Proto` headers, so they get the same scheme
+/// and address as with `HttpServer`.
+fn listener_uri(mut req: Request, secure: bool, addr: SocketAddr) -> Request {
+    let uri = &req.head().uri;
+    if uri.scheme().is_some() {
+        return req;
+    }
+    let listener_uri = Uri::builder()
+        .scheme(if secure { "https" } else { "http" })
+        .authority(addr.to_string())
+        .path_and_query(uri.path_and_query().map_or("/", |path| path.as_str()))
+        .build();
+    if let Ok(uri) = listener_uri {
+        req.head_mut().uri = uri;
+    }
+    req
+}
+
+/// Pin current worker thread to a CPU core.
+///
+/// Cores are assigned in round-robin order.
+/// Every thread is pinned only once, even if
+/// app factory is called multiple times on it.
+#[cfg_attr(coverage, no_coverage)]
+fn pin_worker(core_ids: &[core_affinity::CoreId], next_core: &AtomicUsize) {
+    thread_local! {
+        static PINNED: Cell<bool> = const { Cell::new(false) };
+    }
+    if PINNED.with(Cell::get) {
+        return;
+    }
+    let core_id = core_ids[next_core.fetch_add(1, Ordering::Relaxed) % core_ids.len()];
+    if core_affinity::set_for_current(core_id) {
+        log::debug!("Worker is pinned to core {}.", core_id.id);
+        PINNED.with(|pinned| pinned.set(true));
+    } else {
+        log::warn!("Cannot pin worker to core {}.", core_id.id);
+    }
+}
+
+#[cfg_attr(coverage, no_coverage)]
+fn setup_logging(app_config: &RustusConf) -> RustusResult<()> {
+    let colors = ColoredLevelConfig::new()
+        // use builder methods
+        .info(Color::Green)
+        .warn(Color::Yellow)
+        .debug(Color::BrightCyan)
+        .error(Color::BrightRed)
+        .trace(Color::Blue);
+
+    Dispatch::new()
+        .level(app_config.log_level)
+        .level_for("rbatis", LevelFilter::Error)
+        .chain(std::io::stdout())
+        .format(move |out, message, record| {
+            out.finish(format_args!(
+                "{}[{}] {}",
+                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S%:z]"),
+                colors.color(record.level()),
+                message
+            ));
+        })
+        .apply()?;
+    Ok(())
+}
+
+/// File storage in the given directory
+/// with the same layout as the main one.
+fn local_storage(app_conf: &RustusConf, dir: PathBuf) -> FileStorage {
+    let storage = FileStorage::new(dir, String::new(), app_conf.storage_opts.force_fsync)
+        .with_permissions(app_conf.storage_opts.permissions());
+    if app_conf.storage_opts.date_prefix {
+        storage.with_date_prefix()
+    } else {
+        storage
+    }
+}
+
+/// Create and prepare data storage.
+///
+/// Storage is wrapped to check, limit and trace its operations.
+/// If failover is enabled, timeouts of the main storage
+/// make new uploads go to the failover directory.
+///
+/// # Errors
+///
+/// Returns an error if the storage can't be prepared.
+#[cfg_attr(coverage, no_coverage)]
+async fn create_storage(
+    app_conf: &RustusConf,
+    chunk_index: Option<Box<dyn ChunkIndex + Send + Sync>>,
+) -> RustusResult<Box<dyn Storage + Send + Sync>> {
+    let mut storage = app_conf.storage_opts.storage.get(app_conf, chunk_index);
+    if app_conf.storage_opts.storage_consistency_timeout > 0
+        && (app_conf.storage_opts.verify_writes || !storage.read_after_write())
+    {
+        storage = Box::new(ConsistentStorage::new(
+            storage,
+            Duration::from_millis(app_conf.storage_opts.storage_consistency_timeout),
+        ));
+    }
+    let alignment = app_conf
+        .storage_opts
+        .chunk_alignment
+        .or_else(|| storage.chunk_alignment());
+    if let Some(alignment) = alignment.filter(|alignment| *alignment > 1) {
+        storage = Box::new(AlignedStorage::new(storage, alignment));
+    }
+    if app_conf.storage_opts.storage_read_timeout.is_some()
+        || app_conf.storage_opts.storage_write_timeout.is_some()
+    {
+        storage = Box::new(TimeoutStorage::new(
+            storage,
+            app_conf
+                .storage_opts
+                .storage_read_timeout
+                .map(Duration::from_millis),
+            app_conf
+                .storage_opts
+                .storage_write_timeout
+                .map(Duration::from_millis),
+        ));
+    }
+    if let Some(failover_dir) = app_conf.storage_opts.failover_data_dir.clone() {
+        let mut fallback = local_storage(app_conf, failover_dir);
+        fallback.prepare().await?;
+        storage = Box::new(FailoverStorage::new(
+            storage,
+            Box::new(fallback),
+            Duration::from_secs(app_conf.storage_opts.failover_cooldown),
+        ));
+    }
+    // Preparing it.
+    storage.prepare().await?;
+    #[cfg(feature = "otlp_tracing")]
+    if app_conf.tracing_opts.otlp_endpoint.is_some() {
+        storage = Box::new(storages::traced_storage::TracedStorage::new(storage));
+    }
+    Ok(storage)
+}
+
+/// Run rustus server.
+///
+/// Configuration is parsed from CLI arguments and environment.
+/// Custom storages must be registered before calling this function.
+///
+/// # Errors
+///
+/// Returns an error if storages can't be prepared
+/// or the server can't be started.
+#[cfg_attr(coverage, no_coverage)]
+#[allow(clippy::too_many_lines)]
+pub async fn run() -> std::io::Result<()> {
+    dotenvy::dotenv().ok();
+    let mut app_conf = RustusConf::from_args();
+    app_conf.read_secrets()?;
+    if app_conf.admin_api && app_conf.admin_token.is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Admin API can't be enabled without --admin-token.",
+        ));
+    }
+    // Configuring logging.
+    // I may change it to another log system like `fern` later, idk.
+    setup_logging(&app_conf)?;
+
+    #[allow(clippy::no_effect_underscore_binding)]
+    let mut _guard = None;
+    if let Some(dsn) = &app_conf.sentry_opts.dsn {
+        log::info!("Setting up sentry .");
+        _guard = Some(sentry::init((
+            dsn.as_str(),
+            sentry::ClientOptions {
+                debug: true,
+                sample_rate: app_conf.sentry_opts.sample_rate,
+                ..Default::default()
+            },
+        )));
+    }
+
+    // Printing cool message.
+    greeting(&app_conf);
+    log::info!("Build information: {}", routes::version_info());
+    #[cfg(feature = "otlp_tracing")]
+    telemetry::init(&app_conf.tracing_opts);
+
+    // Creating info storage.
+    // It's used to store info about files.
+    let mut info_storage = app_conf
+        .info_storage_opts
+        .info_storage
+        .get(&app_conf)
+        .await?;
+    // Preparing it, lol.
+    info_storage.prepare().await?;
+    if app_conf
+        .info_storage_opts
+        .info_storage_read_timeout
+        .is_some()
+        || app_conf
+            .info_storage_opts
+            .info_storage_write_timeout
+            .is_some()
+    {
+        info_storage = Box::new(TimeoutInfoStorage::new(
+            info_storage,
+            app_conf
+                .info_storage_opts
+                .info_storage_read_timeout
+                .map(Duration::from_millis),
+            app_conf
+                .info_storage_opts
+                .info_storage_write_timeout
+                .map(Duration::from_millis),
+        ));
+    }
+
+    if let Some(config::Command::Export(options)) = &app_conf.command {
+        return report::export(
+            info_storage.as_ref(),
+            options,
+            app_conf.tenant_metadata_key.as_str(),
+        )
+        .await;
+    }
+
+    // Creating file storage.
+    let chunk_index = match app_conf.storage_opts.chunk_index {
+        Some(chunk_index) => Some(chunk_index.get(&app_conf, info_storage.as_ref()).await?),
+        None => None,
+    };
+    let mut storage = create_storage(&app_conf, chunk_index).await?;
+
+    // Mirroring uploads to the secondary storage.
+    if let Some(replication_dir) = app_conf.storage_opts.replication_data_dir.clone() {
+        let mut secondary = local_storage(&app_conf, replication_dir);
+        secondary.prepare().await?;
+        storage = Box::new(ReplicatedStorage::new(
+            storage,
+            secondary,
+            app_conf.storage_opts.replication_queue_size,
+            app_conf.storage_opts.replication_retries,
+            Duration::from_millis(app_conf.storage_opts.replication_queue_timeout),
+        )?);
+    }
+
+    match &app_conf.command {
+        Some(config::Command::Selftest) => return selftest::check(storage.as_ref()).await,
+        Some(config::Command::Backup(options)) => {
+            return backup::backup(
+                info_storage.as_ref(),
+                storage.as_ref(),
+                options.output.as_path(),
+            )
+            .await;
+        }
+        Some(config::Command::Restore(options)) => {
+            return backup::restore(
+                info_storage.as_ref(),
+                storage.as_ref(),
+                options.input.as_path(),
+            )
+            .await;
+        }
+        _ => {}
+    }
+
+    // Creating notification manager.
+    let notification_manager = NotificationManager::new(&app_conf).await?;
+
+    let mut state = State::new(
+        app_conf.clone(),
+        storage,
+        info_storage,
+        notification_manager,
+    );
+    if app_conf.termination_grace_period.is_some() {
+        let mut trash = app_conf
+            .info_storage_opts
+            .info_storage
+            .get_trash(&app_conf)
+            .await?;
+        trash.prepare().await?;
+        state = state.with_trash(trash);
+    }
+
+    // Background tasks run on the main thread.
+    let local = tokio::task::LocalSet::new();
+    // Uploads are checked before anyone can change them.
+    if app_conf.startup_scan {
+        local.run_until(orphans::startup_scan(&state)).await?;
+    }
+    local
+        .run_until(background::assembly::fail_interrupted(&state))
+        .await?;
+    background::spawn_tasks(&state, &local);
+
+    // Creating actual server and running it.
+    let server = create_server(state)?;
+    let result = local.run_until(server).await;
+    #[cfg(feature = "otlp_tracing")]
+    telemetry::shutdown();
+    if let Some(socket_path) = &app_conf.unix_socket {
+        std::fs::remove_file(socket_path).ok();
+    }
+    result
+}
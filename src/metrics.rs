@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use crate::errors::RustusResult;
+use crate::{errors::RustusResult, info_storages::FileInfo, RustusConf};
 
 #[allow(clippy::module_name_repetitions)]
 #[derive(Clone)]
@@ -9,13 +9,14 @@ pub struct RustusMetrics {
     pub finished_uploads: prometheus::IntCounter,
     pub active_uploads: prometheus::IntGauge,
     pub upload_sizes: prometheus::Histogram,
+    pub finished_upload_sizes: prometheus::HistogramVec,
     pub terminated_uploads: prometheus::IntCounter,
     pub found_errors: prometheus::IntCounterVec,
     pub registry: prometheus::Registry,
 }
 
 impl RustusMetrics {
-    pub fn new() -> RustusResult<Self> {
+    pub fn new(config: &RustusConf) -> RustusResult<Self> {
         let registry = prometheus::Registry::new();
         let started_uploads =
             prometheus::IntCounter::new("started_uploads", "Number of created uploads")?;
@@ -27,6 +28,19 @@ impl RustusMetrics {
             prometheus::HistogramOpts::new("uploads_sizes", "Size of uploaded files in bytes")
                 .buckets(prometheus::exponential_buckets(2., 2., 40)?),
         )?;
+        let finished_size_buckets = if config.finished_size_buckets.is_empty() {
+            prometheus::exponential_buckets(2., 2., 40)?
+        } else {
+            config.finished_size_buckets.clone()
+        };
+        let finished_upload_sizes = prometheus::HistogramVec::new(
+            prometheus::HistogramOpts::new(
+                "finished_uploads_sizes",
+                "Size of finished uploads in bytes",
+            )
+            .buckets(finished_size_buckets),
+            &["storage"],
+        )?;
         let terminated_uploads =
             prometheus::IntCounter::new("terminated_uploads", "Number of terminated uploads")?;
         let found_errors = prometheus::IntCounterVec::new(
@@ -45,6 +59,7 @@ impl RustusMetrics {
         registry.register(Box::new(finished_uploads.clone()))?;
         registry.register(Box::new(active_uploads.clone()))?;
         registry.register(Box::new(upload_sizes.clone()))?;
+        registry.register(Box::new(finished_upload_sizes.clone()))?;
         registry.register(Box::new(terminated_uploads.clone()))?;
         registry.register(Box::new(found_errors.clone()))?;
 
@@ -53,9 +68,44 @@ impl RustusMetrics {
             finished_uploads,
             active_uploads,
             upload_sizes,
+            finished_upload_sizes,
             terminated_uploads,
             found_errors,
             registry,
         })
     }
+
+    /// Record size of finished upload.
+    ///
+    /// Unfinished uploads are ignored.
+    pub fn observe_finished(&self, file_info: &FileInfo) {
+        if file_info.length != Some(file_info.offset) {
+            return;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        self.finished_upload_sizes
+            .with_label_values(&[file_info.storage.as_str()])
+            .observe(file_info.offset as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RustusMetrics;
+    use crate::{info_storages::FileInfo, RustusConf};
+
+    #[test]
+    fn finished_upload_sizes() {
+        let config = RustusConf::from_iter(vec!["rustus", "--finished-size-buckets", "10,1000"]);
+        let metrics = RustusMetrics::new(&config).unwrap();
+        let mut file_info = FileInfo::new("test", Some(100), None, "file_storage".into(), None);
+        metrics.observe_finished(&file_info);
+        file_info.offset = 100;
+        metrics.observe_finished(&file_info);
+        let histogram = metrics
+            .finished_upload_sizes
+            .with_label_values(&["file_storage"]);
+        assert_eq!(histogram.get_sample_count(), 1);
+        assert_eq!(histogram.get_sample_sum(), 100.);
+    }
 }
@@ -237,6 +237,7 @@ mod tests {
         let amqp_url = std::env::var("TEST_AMQP_URL").unwrap();
         let mut notifier = AMQPNotifier::new(crate::config::AMQPHooksOptions {
             hooks_amqp_url: Some(amqp_url),
+            hooks_amqp_url_path: None,
             hooks_amqp_declare_exchange: true,
             hooks_amqp_declare_queues: true,
             hooks_amqp_durable_exchange: false,
@@ -290,6 +291,7 @@ mod tests {
     async fn unknown_url() {
         let notifier = AMQPNotifier::new(crate::config::AMQPHooksOptions {
             hooks_amqp_url: Some(String::from("http://unknown")),
+            hooks_amqp_url_path: None,
             hooks_amqp_declare_exchange: true,
             hooks_amqp_declare_queues: true,
             hooks_amqp_durable_exchange: false,
@@ -0,0 +1,115 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    notifiers::{Hook, Notifier},
+    RustusResult,
+};
+use actix_web::http::header::HeaderMap;
+use async_trait::async_trait;
+use log::info;
+use serde::Serialize;
+
+/// Hook that was received by debug notifier.
+#[derive(Clone, Debug, Serialize)]
+pub struct RecordedHook {
+    pub hook: String,
+    pub received_at: chrono::DateTime<chrono::Utc>,
+    /// Message is parsed as JSON if possible,
+    /// otherwise it's stored as a string.
+    pub message: serde_json::Value,
+}
+
+/// Notifier for hooks development.
+///
+/// It logs every message and keeps the last
+/// `capacity` messages in memory, so they can be inspected
+/// with the admin API.
+///
+/// It's not intended for production use.
+#[derive(Clone)]
+pub struct DebugNotifier {
+    capacity: usize,
+    records: Arc<Mutex<VecDeque<RecordedHook>>>,
+}
+
+impl DebugNotifier {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Get all recorded hooks from the oldest to the newest.
+    pub fn records(&self) -> Vec<RecordedHook> {
+        self.records
+            .lock()
+            .map(|records| records.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Remove all recorded hooks.
+    pub fn clear(&self) {
+        if let Ok(mut records) = self.records.lock() {
+            records.clear();
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Notifier for DebugNotifier {
+    #[cfg_attr(coverage, no_coverage)]
+    async fn prepare(&mut self) -> RustusResult<()> {
+        Ok(())
+    }
+
+    async fn send_message(
+        &self,
+        message: String,
+        hook: Hook,
+        _headers_map: &HeaderMap,
+    ) -> RustusResult<()> {
+        info!("Debug hook `{}`: {}", hook, message);
+        let record = RecordedHook {
+            hook: hook.to_string(),
+            received_at: chrono::Utc::now(),
+            message: serde_json::from_str(message.as_str())
+                .unwrap_or(serde_json::Value::String(message)),
+        };
+        if let Ok(mut records) = self.records.lock() {
+            while records.len() >= self.capacity.max(1) {
+                records.pop_front();
+            }
+            records.push_back(record);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DebugNotifier;
+    use crate::notifiers::{Hook, Notifier};
+    use actix_web::http::header::HeaderMap;
+
+    #[actix_rt::test]
+    async fn records_messages() {
+        let notifier = DebugNotifier::new(2);
+        for message in ["{\"id\": 1}", "plain", "{\"id\": 3}"] {
+            notifier
+                .send_message(message.into(), Hook::PostCreate, &HeaderMap::new())
+                .await
+                .unwrap();
+        }
+        let records = notifier.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].hook, "post-create");
+        assert_eq!(records[0].message, serde_json::json!("plain"));
+        assert_eq!(records[1].message["id"], 3);
+        notifier.clear();
+        assert!(notifier.records().is_empty());
+    }
+}
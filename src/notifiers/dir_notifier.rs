@@ -34,7 +34,7 @@ impl Notifier for DirNotifier {
         _headers_map: &HeaderMap,
     ) -> RustusResult<()> {
         let hook_path = self.dir.join(hook.to_string());
-        if !hook_path.exists() {
+        if tokio::fs::metadata(hook_path.as_path()).await.is_err() {
             debug!("Hook {} not found.", hook.to_string());
             return Err(RustusError::HookError(format!(
                 "Hook file {hook} not found."
@@ -1,12 +1,89 @@
-use crate::errors::{RustusError, RustusResult};
+use crate::{
+    errors::{RustusError, RustusResult},
+    from_str,
+    utils::encryption::PASSPHRASE_HEADER,
+};
 
 use crate::notifiers::{Hook, Notifier};
 
 use actix_web::http::header::HeaderMap;
 use async_trait::async_trait;
+use derive_more::Display;
 use log::debug;
-use reqwest::Client;
-use std::time::Duration;
+use reqwest::{header::HeaderValue, Client};
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use strum::EnumIter;
+
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq, EnumIter)]
+pub enum Compression {
+    /// Use encoding advertised by the endpoint.
+    #[display(fmt = "auto")]
+    Auto,
+    #[display(fmt = "gzip")]
+    Gzip,
+    #[display(fmt = "br")]
+    Brotli,
+}
+
+from_str!(Compression, "compression");
+
+impl Compression {
+    /// Find the best encoding in `Accept-Encoding` header.
+    ///
+    /// Brotli is preferred over gzip.
+    /// Encodings with zero quality are ignored.
+    fn from_accepted(header: &HeaderValue) -> Option<Self> {
+        let mut accepted = None;
+        for item in header.to_str().ok()?.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let encoding = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let rejected = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|quality| quality.parse::<f32>().ok())
+                    .is_some_and(|quality| quality <= 0.0)
+            });
+            if rejected {
+                continue;
+            }
+            match encoding.as_str() {
+                "br" => return Some(Self::Brotli),
+                "gzip" => accepted = Some(Self::Gzip),
+                _ => {}
+            }
+        }
+        accepted
+    }
+
+    /// Compress the message.
+    ///
+    /// # Errors
+    ///
+    /// It returns error if the encoder failed.
+    fn compress(self, message: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            // It's resolved to advertised encoding before compression.
+            Self::Auto => Ok(message.to_vec()),
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(message)?;
+                encoder.finish()
+            }
+            Self::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                encoder.write_all(message)?;
+                encoder.flush()?;
+                Ok(encoder.into_inner())
+            }
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct HttpNotifier {
@@ -14,6 +91,9 @@ pub struct HttpNotifier {
     client: Client,
     forward_headers: Vec<String>,
     timeout_secs: u64,
+    compression: Option<Compression>,
+    /// Encodings advertised by endpoints.
+    accepted_encodings: Arc<Mutex<HashMap<String, Compression>>>,
 }
 
 impl HttpNotifier {
@@ -24,8 +104,59 @@ impl HttpNotifier {
             client,
             forward_headers,
             timeout_secs: timeout_secs.unwrap_or(2),
+            compression: None,
+            accepted_encodings: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Compress payloads of requests.
+    ///
+    /// With `Auto`, the first request to every endpoint
+    /// isn't compressed. Following requests use encoding
+    /// from `Accept-Encoding` header of its response.
+    #[must_use]
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Send requests with another client.
+    #[must_use]
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Encoding of requests to the URL.
+    fn encoding(&self, url: &str) -> Option<Compression> {
+        match self.compression? {
+            Compression::Auto => self
+                .accepted_encodings
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .get(url)
+                .copied(),
+            compression => Some(compression),
         }
     }
+
+    /// Remember encoding advertised by the endpoint.
+    fn update_encoding(&self, url: &str, headers: &reqwest::header::HeaderMap) {
+        if self.compression != Some(Compression::Auto) {
+            return;
+        }
+        let mut encodings = self
+            .accepted_encodings
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        match headers
+            .get("Accept-Encoding")
+            .and_then(Compression::from_accepted)
+        {
+            Some(encoding) => encodings.insert(String::from(url), encoding),
+            None => encodings.remove(url),
+        };
+    }
 }
 
 #[async_trait(?Send)]
@@ -41,9 +172,21 @@ impl Notifier for HttpNotifier {
         hook: Hook,
         header_map: &HeaderMap,
     ) -> RustusResult<()> {
+        self.send_message_with_responses(message, hook, header_map)
+            .await?;
+        Ok(())
+    }
+
+    async fn send_message_with_responses(
+        &self,
+        message: String,
+        hook: Hook,
+        header_map: &HeaderMap,
+    ) -> RustusResult<Vec<String>> {
         debug!("Starting HTTP Hook.");
         let idempotency_key = uuid::Uuid::new_v4().to_string();
-        let requests_vec = self.urls.iter().map(|url| {
+        let mut responses = Vec::new();
+        for url in &self.urls {
             debug!("Preparing request for {}", url);
             let mut request = self
                 .client
@@ -53,14 +196,22 @@ impl Notifier for HttpNotifier {
                 .header("Content-Type", "application/json")
                 .timeout(Duration::from_secs(self.timeout_secs));
             for item in &self.forward_headers {
+                // Passphrases of encrypted uploads are never sent.
+                if item.eq_ignore_ascii_case(PASSPHRASE_HEADER) {
+                    continue;
+                }
                 if let Some(value) = header_map.get(item.as_str()) {
                     request = request.header(item.as_str(), value.as_bytes());
                 }
             }
-            request.body(message.clone()).send()
-        });
-        for response in requests_vec {
-            let real_resp = response.await?;
+            request = match self.encoding(url) {
+                Some(encoding) => request
+                    .header("Content-Encoding", encoding.to_string())
+                    .body(encoding.compress(message.as_bytes())?),
+                None => request.body(message.clone()),
+            };
+            let real_resp = request.send().await?;
+            self.update_encoding(url, real_resp.headers());
             if !real_resp.status().is_success() {
                 let content_type = real_resp
                     .headers()
@@ -76,18 +227,19 @@ impl Notifier for HttpNotifier {
                 );
                 return Err(RustusError::HTTPHookError(status, text, content_type));
             }
+            responses.push(real_resp.text().await.unwrap_or_default());
         }
-        Ok(())
+        Ok(responses)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::HttpNotifier;
+    use super::{Compression, HttpNotifier};
     use crate::notifiers::{Hook, Notifier};
     use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
     use httptest::{matchers::contains, responders::status_code};
-    use std::{str::FromStr, time::Duration};
+    use std::{io::Read, str::FromStr, time::Duration};
 
     #[actix_rt::test]
     async fn success_request() {
@@ -168,4 +320,131 @@ mod tests {
             .await
             .unwrap();
     }
+
+    #[actix_rt::test]
+    async fn responses() {
+        let server = httptest::Server::run();
+        server.expect(
+            httptest::Expectation::matching(httptest::matchers::request::method_path(
+                "POST", "/hook",
+            ))
+            .respond_with(status_code(200).body("{\"length\": 10}")),
+        );
+        let hook_url = server.url_str("/hook");
+        let notifier = HttpNotifier::new(vec![hook_url], vec![], None);
+        let responses = notifier
+            .send_message_with_responses("test_message".into(), Hook::PreCreate, &HeaderMap::new())
+            .await
+            .unwrap();
+        assert_eq!(responses, vec![String::from("{\"length\": 10}")]);
+    }
+
+    #[actix_rt::test]
+    async fn forced_compression() {
+        let message = "{\"upload\": \"memes\"}";
+        for compression in [Compression::Gzip, Compression::Brotli] {
+            let server = httptest::Server::run();
+            server.expect(
+                httptest::Expectation::matching(httptest::matchers::all_of![
+                    httptest::matchers::request::method_path("POST", "/hook"),
+                    httptest::matchers::request::headers(contains((
+                        "content-encoding",
+                        compression.to_string()
+                    ))),
+                    httptest::matchers::request::headers(contains((
+                        "content-type",
+                        "application/json"
+                    ))),
+                    httptest::matchers::request::body(
+                        compression.compress(message.as_bytes()).unwrap()
+                    ),
+                ])
+                .respond_with(status_code(200)),
+            );
+            let notifier = HttpNotifier::new(vec![server.url_str("/hook")], vec![], None)
+                .with_compression(Some(compression));
+            notifier
+                .send_message(message.into(), Hook::PostCreate, &HeaderMap::new())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[actix_rt::test]
+    async fn auto_compression() {
+        let mut server = httptest::Server::run();
+        server.expect(
+            httptest::Expectation::matching(httptest::matchers::all_of![
+                httptest::matchers::request::method_path("POST", "/hook"),
+                httptest::matchers::not(httptest::matchers::request::headers(contains(
+                    httptest::matchers::key("content-encoding")
+                ))),
+                httptest::matchers::request::body("test_message"),
+            ])
+            .respond_with(status_code(200).insert_header("Accept-Encoding", "br;q=0, gzip")),
+        );
+        let hook_url = server.url_str("/hook");
+        let notifier = HttpNotifier::new(vec![hook_url], vec![], None)
+            .with_compression(Some(Compression::Auto));
+        notifier
+            .send_message("test_message".into(), Hook::PostCreate, &HeaderMap::new())
+            .await
+            .unwrap();
+        server.verify_and_clear();
+        // Encoding advertised by the endpoint is used.
+        server.expect(
+            httptest::Expectation::matching(httptest::matchers::all_of![
+                httptest::matchers::request::method_path("POST", "/hook"),
+                httptest::matchers::request::headers(contains(("content-encoding", "gzip"))),
+            ])
+            .respond_with(status_code(200)),
+        );
+        notifier
+            .send_message("test_message".into(), Hook::PostCreate, &HeaderMap::new())
+            .await
+            .unwrap();
+        server.verify_and_clear();
+        // Endpoint stopped advertising encodings.
+        server.expect(
+            httptest::Expectation::matching(httptest::matchers::all_of![
+                httptest::matchers::request::method_path("POST", "/hook"),
+                httptest::matchers::not(httptest::matchers::request::headers(contains(
+                    httptest::matchers::key("content-encoding")
+                ))),
+            ])
+            .respond_with(status_code(200)),
+        );
+        notifier
+            .send_message("test_message".into(), Hook::PostCreate, &HeaderMap::new())
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn accepted_encodings() {
+        let parse =
+            |value| Compression::from_accepted(&reqwest::header::HeaderValue::from_static(value));
+        assert_eq!(parse("gzip, deflate, br"), Some(Compression::Brotli));
+        assert_eq!(parse("gzip;q=0.5"), Some(Compression::Gzip));
+        assert_eq!(parse("gzip;q=0"), None);
+        assert_eq!(parse("identity"), None);
+    }
+
+    #[test]
+    fn compressed_payloads() {
+        let message = b"memes".repeat(100);
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(Compression::Gzip.compress(&message).unwrap().as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, message);
+        decoded.clear();
+        brotli::Decompressor::new(
+            Compression::Brotli.compress(&message).unwrap().as_slice(),
+            4096,
+        )
+        .read_to_end(&mut decoded)
+        .unwrap();
+        assert_eq!(decoded, message);
+    }
 }
@@ -1,5 +1,7 @@
 #[cfg(feature = "amqp_notifier")]
 pub mod amqp_notifier;
+pub mod callback;
+pub mod debug_notifier;
 pub mod dir_notifier;
 mod file_notifier;
 pub mod http_notifier;
@@ -7,6 +7,8 @@ use strum::EnumIter;
 pub enum Hook {
     #[display(fmt = "pre-create")]
     PreCreate,
+    #[display(fmt = "create-rejected")]
+    CreateRejected,
     #[display(fmt = "post-create")]
     PostCreate,
     #[display(fmt = "post-receive")]
@@ -15,6 +17,9 @@ pub enum Hook {
     PreTerminate,
     #[display(fmt = "post-terminate")]
     PostTerminate,
+    /// Blocking hook to release resources of terminated uploads.
+    #[display(fmt = "terminate-cleanup")]
+    TerminateCleanup,
     #[display(fmt = "post-finish")]
     PostFinish,
 }
@@ -1,4 +1,9 @@
-use crate::{from_str, info_storages::FileInfo};
+use crate::{
+    config::ClientIpOptions,
+    from_str,
+    info_storages::FileInfo,
+    utils::{encryption::PASSPHRASE_HEADER, rejections::Rejection},
+};
 use actix_web::{http::header::HeaderMap, HttpRequest};
 use derive_more::{Display, From};
 use serde::Serialize;
@@ -23,12 +28,98 @@ impl Format {
         &self,
         request: &HttpRequest,
         file_info: &FileInfo,
-        behind_proxy: bool,
+        client_ip: &ClientIpOptions,
+    ) -> String {
+        let remote_addr = client_ip.resolve(request);
+        self.format_request(Some(request), file_info, remote_addr.as_deref())
+    }
+
+    /// Format message about an event
+    /// which wasn't caused by any request.
+    ///
+    /// It's used by background tasks.
+    /// All request-related fields are empty.
+    pub fn format_without_request(&self, file_info: &FileInfo) -> String {
+        self.format_request(None, file_info, None)
+    }
+
+    /// Format message about rejected creation of an upload.
+    ///
+    /// Upload contains only the length and metadata
+    /// which the client sent.
+    pub fn format_rejection(
+        &self,
+        request: &HttpRequest,
+        rejection: &Rejection,
+        client_ip: &ClientIpOptions,
+    ) -> String {
+        let remote_addr = client_ip.resolve(request);
+        let value = match self {
+            Self::Default => json!({
+                "rejection": {
+                    "reason": rejection.reason.to_string(),
+                    "message": rejection.message,
+                    "status": rejection.status,
+                },
+                "upload": {
+                    "length": rejection.upload.length,
+                    "metadata": rejection.upload.metadata,
+                },
+                "request": {
+                    "URI": request.uri().to_string(),
+                    "method": request.method().to_string(),
+                    "remote_addr": remote_addr,
+                    "headers": headers_to_value_map(Some(request.headers()), false)
+                }
+            }),
+            Self::V2 => json!({
+                "rejection": {
+                    "reason": rejection.reason.to_string(),
+                    "message": rejection.message,
+                    "status": rejection.status,
+                },
+                "upload": {
+                    "length": rejection.upload.length,
+                    "metadata": rejection.upload.metadata,
+                },
+                "request": {
+                    "uri": request.uri().to_string(),
+                    "method": request.method().to_string(),
+                    "remote_addr": remote_addr,
+                    "headers": headers_to_value_map(Some(request.headers()), false)
+                }
+            }),
+            Self::Tusd => json!({
+                "Rejection": {
+                    "Reason": rejection.reason.to_string(),
+                    "Message": rejection.message,
+                    "Status": rejection.status,
+                },
+                "Upload": {
+                    "Size": rejection.upload.length,
+                    "MetaData": rejection.upload.metadata,
+                },
+                "HTTPRequest": {
+                    "URI": request.uri().to_string(),
+                    "Method": request.method().to_string(),
+                    "RemoteAddr": remote_addr,
+                    "Header": headers_to_value_map(Some(request.headers()), true)
+                }
+            }),
+        };
+        value.to_string()
+    }
+
+    fn format_request(
+        &self,
+        request: Option<&HttpRequest>,
+        file_info: &FileInfo,
+        remote_addr: Option<&str>,
     ) -> String {
         match self {
-            Self::Default => default_format(request, file_info, behind_proxy),
-            Self::Tusd => tusd_format(request, file_info, behind_proxy),
-            Self::V2 => rustus_format_v2(request, file_info, behind_proxy),
+            Self::Default => default_format(request, file_info, remote_addr),
+            Self::Tusd => tusd_format(request, file_info, remote_addr),
+            Self::V2 => rustus_format_v2(request, file_info, remote_addr),
         }
     }
 }
@@ -54,6 +145,8 @@ struct TusdFileInfo {
     size_is_deferred: bool,
     meta_data: HashMap<String, String>,
     storage: TusdStorageInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
 }
 
 impl From<&FileInfo> for TusdFileInfo {
@@ -72,6 +165,7 @@ impl From<&FileInfo> for TusdFileInfo {
                 storage_type: file_info.storage.clone(),
                 path: file_info.path.clone(),
             },
+            checksum: file_info.checksum.clone(),
         }
     }
 }
@@ -81,9 +175,16 @@ impl From<&FileInfo> for TusdFileInfo {
 /// Keys of the resulting map are Strings,
 /// Values are serde values. It ca be either string values or
 /// arrays.
-fn headers_to_value_map(headers: &HeaderMap, use_arrays: bool) -> HashMap<String, Value> {
+/// Passphrases of encrypted uploads are never sent.
+fn headers_to_value_map(headers: Option<&HeaderMap>, use_arrays: bool) -> HashMap<String, Value> {
     let mut headers_map = HashMap::new();
+    let Some(headers) = headers else {
+        return headers_map;
+    };
     for (name, value) in headers.iter() {
+        if name.as_str().eq_ignore_ascii_case(PASSPHRASE_HEADER) {
+            continue;
+        }
         if let Ok(header_val) = value.to_str().map(String::from) {
             if use_arrays {
                 headers_map.insert(
@@ -98,33 +199,21 @@ fn headers_to_value_map(headers: &HeaderMap, use_arrays: bool) -> HashMap<String
     headers_map
 }
 
-/// Resolves real client's IP.
-///
-/// This function is used to get peer's address,
-/// but if Rustus is running behind proxy, then you
-/// it should check for `Forwarded` or `X-Forwarded-For` headers.
-fn get_remote_addr(request: &HttpRequest, behind_proxy: bool) -> Option<String> {
-    if behind_proxy {
-        request
-            .connection_info()
-            .realip_remote_addr()
-            .map(String::from)
-    } else {
-        request.connection_info().peer_addr().map(String::from)
-    }
-}
-
 /// Default format is specific for Rustus.
 ///
 /// This format is a simple serialized `FileInfo` and some parts of the request.
-pub fn default_format(request: &HttpRequest, file_info: &FileInfo, behind_proxy: bool) -> String {
+pub fn default_format(
+    request: Option<&HttpRequest>,
+    file_info: &FileInfo,
+    remote_addr: Option<&str>,
+) -> String {
     let value = json!({
         "upload": file_info,
         "request": {
-            "URI": request.uri().to_string(),
-            "method": request.method().to_string(),
-            "remote_addr": get_remote_addr(request, behind_proxy),
-            "headers": headers_to_value_map(request.headers(), false)
+            "URI": request.map(|req| req.uri().to_string()),
+            "method": request.map(|req| req.method().to_string()),
+            "remote_addr": remote_addr,
+            "headers": headers_to_value_map(request.map(HttpRequest::headers), false)
         }
     });
     value.to_string()
@@ -133,14 +222,18 @@ pub fn default_format(request: &HttpRequest, file_info: &FileInfo, behind_proxy:
 /// Default format is specific for Rustus V2.
 ///
 /// This format is almost the same as V1, but with some enhancements.
-pub fn rustus_format_v2(request: &HttpRequest, file_info: &FileInfo, behind_proxy: bool) -> String {
+pub fn rustus_format_v2(
+    request: Option<&HttpRequest>,
+    file_info: &FileInfo,
+    remote_addr: Option<&str>,
+) -> String {
     let value = json!({
         "upload": file_info,
         "request": {
-            "uri": request.uri().to_string(),
-            "method": request.method().to_string(),
-            "remote_addr": get_remote_addr(request, behind_proxy),
-            "headers": headers_to_value_map(request.headers(), false)
+            "uri": request.map(|req| req.uri().to_string()),
+            "method": request.map(|req| req.method().to_string()),
+            "remote_addr": remote_addr,
+            "headers": headers_to_value_map(request.map(HttpRequest::headers), false)
         }
     });
     value.to_string()
@@ -153,15 +246,61 @@ pub fn rustus_format_v2(request: &HttpRequest, file_info: &FileInfo, behind_prox
 ///
 /// Generally speaking, it's almost the same as the default format,
 /// but some variables are ommited and headers are added to the request.
-pub fn tusd_format(request: &HttpRequest, file_info: &FileInfo, behind_proxy: bool) -> String {
+pub fn tusd_format(
+    request: Option<&HttpRequest>,
+    file_info: &FileInfo,
+    remote_addr: Option<&str>,
+) -> String {
     let value = json!({
         "Upload": TusdFileInfo::from(file_info),
         "HTTPRequest": {
-            "URI": request.uri().to_string(),
-            "Method": request.method().to_string(),
-            "RemoteAddr": get_remote_addr(request, behind_proxy),
-            "Header": headers_to_value_map(request.headers(), true)
+            "URI": request.map(|req| req.uri().to_string()),
+            "Method": request.map(|req| req.method().to_string()),
+            "RemoteAddr": remote_addr,
+            "Header": headers_to_value_map(request.map(HttpRequest::headers), true)
         }
     });
     value.to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Format;
+    use crate::{info_storages::FileInfo, RustusConf};
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn ipv6_remote_addr() {
+        let request = TestRequest::default()
+            .peer_addr("[::1]:4711".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "[2001:db8::1]:8080"))
+            .to_http_request();
+        let file_info = FileInfo::new_test();
+        let remote_addr = |config: &RustusConf| {
+            let message = Format::Default.format(&request, &file_info, &config.client_ip);
+            let value: serde_json::Value = serde_json::from_str(message.as_str()).unwrap();
+            value["request"]["remote_addr"].clone()
+        };
+        let config = RustusConf::from_iter(vec!["rustus"]);
+        assert_eq!(remote_addr(&config), "::1");
+        let config = RustusConf::from_iter(vec!["rustus", "--behind-proxy"]);
+        assert_eq!(remote_addr(&config), "2001:db8::1");
+        let config = RustusConf::from_iter(vec!["rustus", "--trusted-proxies", "10.0.0.0/8"]);
+        assert_eq!(remote_addr(&config), "::1");
+    }
+
+    #[test]
+    fn passphrase_is_redacted() {
+        let request = TestRequest::default()
+            .insert_header(("Upload-Passphrase", "memes"))
+            .insert_header(("X-Test", "value"))
+            .to_http_request();
+        let file_info = FileInfo::new_test();
+        let config = RustusConf::from_iter(vec!["rustus"]);
+        for format in [Format::Default, Format::Tusd, Format::V2] {
+            let message = format.format(&request, &file_info, &config.client_ip);
+            assert!(message.contains("X-Test") || message.contains("x-test"));
+            assert!(!message.contains("memes"), "{format}: {message}");
+        }
+    }
+}
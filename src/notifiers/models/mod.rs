@@ -2,3 +2,6 @@ pub mod hooks;
 pub mod message_format;
 pub mod notification_manager;
 pub mod notifier;
+pub mod receive_throttle;
+pub mod rejection_limit;
+pub mod upload_patch;
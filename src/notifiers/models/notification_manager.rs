@@ -1,24 +1,86 @@
 #[cfg(feature = "amqp_notifier")]
 use crate::notifiers::amqp_notifier;
 use crate::{
-    errors::RustusResult,
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
     notifiers::{
-        dir_notifier::DirNotifier, file_notifier::FileNotifier, http_notifier, Hook, Notifier,
+        callback::CallbackPolicy,
+        debug_notifier::DebugNotifier,
+        dir_notifier::DirNotifier,
+        file_notifier::FileNotifier,
+        http_notifier::{self, Compression},
+        models::{
+            receive_throttle::{ReceiveHook, ReceiveThrottle, Throttled},
+            rejection_limit::RejectionLimit,
+        },
+        Hook, Notifier,
     },
     RustusConf,
 };
 use actix_web::http::header::HeaderMap;
-use log::debug;
+use log::{debug, error, warn};
+use reqwest::{redirect, Client};
+use serde::Serialize;
+use std::{path::PathBuf, time::Duration};
+use tokio::io::AsyncWriteExt;
+use url::Host;
+
+/// Hook which wasn't delivered after all retries.
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    hook: String,
+    message: &'a str,
+    error: String,
+    failed_at: String,
+}
 
 #[derive(Clone)]
 pub struct NotificationManager {
     notifiers: Vec<Box<dyn Notifier + Send + Sync>>,
+    debug_notifier: Option<DebugNotifier>,
+    retries: usize,
+    cleanup_retries: usize,
+    retry_backoff: Duration,
+    dead_letter_file: Option<PathBuf>,
+    callback_policy: CallbackPolicy,
+    callback_exclusive: bool,
+    http_timeout: Option<u64>,
+    http_compression: Option<Compression>,
+    receive_throttle: ReceiveThrottle,
+    rejection_limit: RejectionLimit,
 }
 
 impl NotificationManager {
     pub async fn new(rustus_config: &RustusConf) -> RustusResult<Self> {
         let mut manager = Self {
             notifiers: Vec::new(),
+            debug_notifier: None,
+            retries: rustus_config.notification_opts.hooks_retries,
+            cleanup_retries: rustus_config.notification_opts.hooks_cleanup_retries,
+            retry_backoff: Duration::from_millis(
+                rustus_config.notification_opts.hooks_retry_backoff,
+            ),
+            dead_letter_file: rustus_config
+                .notification_opts
+                .hooks_dead_letter_file
+                .clone(),
+            callback_policy: CallbackPolicy::new(
+                rustus_config
+                    .notification_opts
+                    .hooks_callback_schemes
+                    .clone(),
+                rustus_config.notification_opts.hooks_callback_hosts.clone(),
+            ),
+            callback_exclusive: rustus_config.notification_opts.hooks_callback_exclusive,
+            http_timeout: rustus_config.notification_opts.http_hook_timeout,
+            http_compression: rustus_config.notification_opts.hooks_http_compression,
+            receive_throttle: ReceiveThrottle::new(
+                Duration::from_millis(rustus_config.notification_opts.hooks_receive_interval),
+                rustus_config.notification_opts.hooks_receive_bytes,
+            ),
+            rejection_limit: RejectionLimit::new(
+                rustus_config.notification_opts.hooks_rejected_rate,
+            ),
         };
         debug!("Initializing notification manager.");
         if rustus_config.notification_opts.hooks_file.is_some() {
@@ -35,16 +97,17 @@ impl NotificationManager {
         }
         if !rustus_config.notification_opts.hooks_http_urls.is_empty() {
             debug!("Found http hook urls.");
-            manager
-                .notifiers
-                .push(Box::new(http_notifier::HttpNotifier::new(
+            manager.notifiers.push(Box::new(
+                http_notifier::HttpNotifier::new(
                     rustus_config.notification_opts.hooks_http_urls.clone(),
                     rustus_config
                         .notification_opts
                         .hooks_http_proxy_headers
                         .clone(),
                     rustus_config.notification_opts.http_hook_timeout,
-                )));
+                )
+                .with_compression(rustus_config.notification_opts.hooks_http_compression),
+            ));
         }
         #[cfg(feature = "amqp_notifier")]
         if rustus_config
@@ -61,6 +124,12 @@ impl NotificationManager {
                 .await?,
             ));
         }
+        if rustus_config.notification_opts.hooks_debug {
+            warn!("Debug hooks are enabled. Don't use them in production.");
+            let notifier = DebugNotifier::new(rustus_config.notification_opts.hooks_debug_capacity);
+            manager.notifiers.push(Box::new(notifier.clone()));
+            manager.debug_notifier = Some(notifier);
+        }
         for notifier in &mut manager.notifiers.iter_mut() {
             notifier.prepare().await?;
         }
@@ -68,6 +137,24 @@ impl NotificationManager {
         Ok(manager)
     }
 
+    /// Notifier that records hooks in memory.
+    ///
+    /// It's available only if debug hooks are enabled.
+    pub fn debug_notifier(&self) -> Option<&DebugNotifier> {
+        self.debug_notifier.as_ref()
+    }
+
+    /// Check if `create-rejected` hook can be sent
+    /// without exceeding the limit of these hooks.
+    pub fn allow_rejection(&self) -> bool {
+        self.rejection_limit.allow()
+    }
+
+    /// Rules for callback URLs of uploads.
+    pub fn callback_policy(&self) -> &CallbackPolicy {
+        &self.callback_policy
+    }
+
     pub async fn send_message(
         &self,
         message: String,
@@ -82,4 +169,430 @@ impl NotificationManager {
         }
         Ok(())
     }
+
+    /// Send a hook and collect responses of all notifiers.
+    pub async fn send_message_with_responses(
+        &self,
+        message: String,
+        hook: Hook,
+        header_map: &HeaderMap,
+    ) -> RustusResult<Vec<String>> {
+        log::debug!("Sending a `{}` hook with body `{}`", hook, message);
+        let mut responses = Vec::new();
+        for notifier in &self.notifiers {
+            responses.extend(
+                notifier
+                    .send_message_with_responses(message.clone(), hook, header_map)
+                    .await?,
+            );
+        }
+        Ok(responses)
+    }
+
+    /// Deliver informational hook.
+    ///
+    /// Failed notifiers are retried separately,
+    /// so other notifiers don't receive the hook twice.
+    /// Hooks that failed after all retries are written
+    /// to the dead-letter file, if it's configured.
+    ///
+    /// This function never fails, so it's meant
+    /// to be run in background.
+    pub async fn deliver(&self, message: String, hook: Hook, header_map: &HeaderMap) {
+        log::debug!("Delivering a `{}` hook with body `{}`", hook, message);
+        for notifier in &self.notifiers {
+            self.deliver_to(
+                notifier.as_ref(),
+                message.as_str(),
+                hook,
+                header_map,
+                self.retries,
+            )
+            .await;
+        }
+    }
+
+    /// Deliver terminate-cleanup hook.
+    ///
+    /// It works like `deliver`, but it's meant to be awaited
+    /// before the response, so external resources
+    /// are released once the termination is finished.
+    /// Hooks are retried `--hooks-cleanup-retries` times.
+    pub async fn deliver_cleanup(&self, message: String, header_map: &HeaderMap) {
+        log::debug!("Delivering a cleanup hook with body `{}`", message);
+        for notifier in &self.notifiers {
+            self.deliver_to(
+                notifier.as_ref(),
+                message.as_str(),
+                Hook::TerminateCleanup,
+                header_map,
+                self.cleanup_retries,
+            )
+            .await;
+        }
+    }
+
+    /// Deliver post-receive hook of the upload.
+    ///
+    /// Hooks are throttled by `--hooks-receive-interval`
+    /// and `--hooks-receive-bytes`. Throttled hooks are coalesced
+    /// and the latest one is delivered after a delay.
+    pub async fn deliver_receive(
+        &self,
+        message: String,
+        file_info: &FileInfo,
+        header_map: &HeaderMap,
+    ) {
+        let hook = ReceiveHook {
+            message,
+            offset: file_info.offset,
+            headers: header_map.clone(),
+        };
+        let hook = match self.receive_throttle.offer(file_info.id.as_str(), hook) {
+            Throttled::Send(hook) => hook,
+            Throttled::Delay(delay) => {
+                tokio::time::sleep(delay).await;
+                let Some(hook) = self.receive_throttle.flush(file_info.id.as_str()) else {
+                    return;
+                };
+                hook
+            }
+            Throttled::Coalesced => return,
+        };
+        self.deliver(hook.message, Hook::PostReceive, &hook.headers)
+            .await;
+    }
+
+    /// Deliver the kept post-receive hook of the upload.
+    ///
+    /// It's called when the upload is finished or terminated,
+    /// so its last progress is never lost.
+    pub async fn flush_receive(&self, upload_id: &str) {
+        if let Some(receive) = self.receive_throttle.finish(upload_id) {
+            self.deliver(receive.message, Hook::PostReceive, &receive.headers)
+                .await;
+        }
+    }
+
+    /// Deliver informational hook about the upload.
+    ///
+    /// Post-finish hook is also sent to
+    /// the callback URL of the upload.
+    /// If callbacks are exclusive, other notifiers
+    /// don't receive it.
+    pub async fn deliver_upload(
+        &self,
+        message: String,
+        hook: Hook,
+        file_info: &FileInfo,
+        header_map: &HeaderMap,
+    ) {
+        if hook == Hook::PostFinish {
+            self.flush_receive(file_info.id.as_str()).await;
+        }
+        let callback_url = file_info
+            .callback_url
+            .as_deref()
+            .filter(|_| hook == Hook::PostFinish);
+        let Some(callback_url) = callback_url else {
+            self.deliver(message, hook, header_map).await;
+            return;
+        };
+        if !self.callback_exclusive {
+            self.deliver(message.clone(), hook, header_map).await;
+        }
+        log::debug!("Delivering a `{}` hook to {}", hook, callback_url);
+        match self.callback_notifier(callback_url).await {
+            // Client headers aren't forwarded to callbacks.
+            Ok(notifier) => {
+                self.deliver_to(
+                    &notifier,
+                    message.as_str(),
+                    hook,
+                    &HeaderMap::new(),
+                    self.retries,
+                )
+                .await;
+            }
+            Err(err) => {
+                error!("Cannot deliver `{}` hook to callback: {}", hook, err);
+                self.write_dead_letter(message.as_str(), hook, &err).await;
+            }
+        }
+    }
+
+    /// Create notifier for the callback URL.
+    ///
+    /// The URL is checked again, since its host
+    /// could have been changed after the upload was created.
+    /// Requests are sent only to checked addresses
+    /// and redirects aren't followed.
+    async fn callback_notifier(&self, url: &str) -> RustusResult<http_notifier::HttpNotifier> {
+        let callback = self
+            .callback_policy
+            .resolve(url)
+            .await
+            .map_err(RustusError::InvalidCallbackUrl)?;
+        let mut builder = Client::builder().redirect(redirect::Policy::none());
+        if let Some(Host::Domain(domain)) = callback.url.host() {
+            builder = builder.resolve_to_addrs(domain, callback.addrs.as_slice());
+        }
+        Ok(http_notifier::HttpNotifier::new(
+            vec![callback.url.to_string()],
+            vec![],
+            self.http_timeout,
+        )
+        .with_client(builder.build()?)
+        .with_compression(self.http_compression))
+    }
+
+    /// Send the hook to the notifier with retries.
+    async fn deliver_to(
+        &self,
+        notifier: &dyn Notifier,
+        message: &str,
+        hook: Hook,
+        header_map: &HeaderMap,
+        retries: usize,
+    ) {
+        let mut attempt = 0;
+        loop {
+            let Err(err) = notifier
+                .send_message(String::from(message), hook, header_map)
+                .await
+            else {
+                break;
+            };
+            if attempt >= retries {
+                error!(
+                    "Cannot deliver `{}` hook after {} retries: {}",
+                    hook, attempt, err
+                );
+                self.write_dead_letter(message, hook, &err).await;
+                break;
+            }
+            warn!(
+                "Cannot deliver `{}` hook. Attempt: {}. Reason: {}",
+                hook,
+                attempt + 1,
+                err
+            );
+            tokio::time::sleep(
+                self.retry_backoff
+                    * 2u32.saturating_pow(u32::try_from(attempt).unwrap_or(u32::MAX)),
+            )
+            .await;
+            attempt += 1;
+        }
+    }
+
+    /// Append undelivered hook to the dead-letter file.
+    async fn write_dead_letter(&self, message: &str, hook: Hook, err: &RustusError) {
+        let Some(path) = &self.dead_letter_file else {
+            return;
+        };
+        let letter = DeadLetter {
+            hook: hook.to_string(),
+            message,
+            error: err.to_string(),
+            failed_at: chrono::Utc::now().to_rfc3339(),
+        };
+        let result = async {
+            let mut line = serde_json::to_string(&letter)?;
+            line.push('\n');
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?;
+            file.write_all(line.as_bytes()).await?;
+            file.sync_data().await?;
+            Ok::<_, RustusError>(())
+        }
+        .await;
+        if let Err(write_err) = result {
+            error!(
+                "Cannot write `{}` hook to the dead-letter file: {}",
+                hook, write_err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{notifiers::Hook, NotificationManager, State};
+    use actix_web::http::header::HeaderMap;
+    use httptest::{
+        matchers::request,
+        responders::{status_code, Responder},
+        Expectation, Server,
+    };
+
+    fn hooks_server(times: usize, responder: impl Responder + 'static) -> Server {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("POST", "/hook"))
+                .times(times)
+                .respond_with(responder),
+        );
+        server
+    }
+
+    #[actix_rt::test]
+    async fn retried_delivery() {
+        let server = hooks_server(
+            3,
+            httptest::cycle![status_code(500), status_code(500), status_code(200)],
+        );
+        let mut state = State::test_new().await;
+        let dir = tempdir::TempDir::new("dead_letters").unwrap();
+        let dead_letters = dir.path().join("dead.jsonl");
+        state.config.notification_opts.hooks_http_urls = vec![server.url_str("/hook")];
+        state.config.notification_opts.hooks_retries = 2;
+        state.config.notification_opts.hooks_retry_backoff = 1;
+        state.config.notification_opts.hooks_dead_letter_file = Some(dead_letters.clone());
+        let manager = NotificationManager::new(&state.config).await.unwrap();
+        manager
+            .deliver(String::from("{}"), Hook::PostFinish, &HeaderMap::new())
+            .await;
+        assert!(!dead_letters.exists());
+    }
+
+    #[actix_rt::test]
+    async fn cleanup_delivery() {
+        let server = hooks_server(
+            3,
+            httptest::cycle![status_code(500), status_code(500), status_code(502)],
+        );
+        let mut state = State::test_new().await;
+        let dir = tempdir::TempDir::new("dead_letters").unwrap();
+        let dead_letters = dir.path().join("dead.jsonl");
+        state.config.notification_opts.hooks_http_urls = vec![server.url_str("/hook")];
+        // Informational hooks aren't retried, but cleanup hooks are.
+        state.config.notification_opts.hooks_retries = 0;
+        state.config.notification_opts.hooks_cleanup_retries = 2;
+        state.config.notification_opts.hooks_retry_backoff = 1;
+        state.config.notification_opts.hooks_dead_letter_file = Some(dead_letters.clone());
+        let manager = NotificationManager::new(&state.config).await.unwrap();
+        manager
+            .deliver_cleanup(String::from("memes"), &HeaderMap::new())
+            .await;
+        let contents = std::fs::read_to_string(dead_letters).unwrap();
+        let letter: serde_json::Value = serde_json::from_str(contents.trim()).unwrap();
+        assert_eq!(letter["hook"], "terminate-cleanup");
+    }
+
+    #[actix_rt::test]
+    async fn callback() {
+        let hooks = hooks_server(2, status_code(200));
+        let callback = Server::run();
+        callback.expect(
+            Expectation::matching(request::method_path("POST", "/callback"))
+                .respond_with(status_code(200)),
+        );
+        let mut state = State::test_new().await;
+        state.config.notification_opts.hooks_http_urls = vec![hooks.url_str("/hook")];
+        state.config.notification_opts.hooks_callback_schemes = vec![String::from("http")];
+        state.config.notification_opts.hooks_callback_hosts =
+            vec![String::from("127.0.0.1"), String::from("::1")];
+        let manager = NotificationManager::new(&state.config).await.unwrap();
+        let mut file_info = state.create_test_file().await;
+        file_info.callback_url = Some(callback.url_str("/callback"));
+        manager
+            .deliver_upload(
+                String::from("{}"),
+                Hook::PostFinish,
+                &file_info,
+                &HeaderMap::new(),
+            )
+            .await;
+        // Callbacks receive only post-finish hooks.
+        manager
+            .deliver_upload(
+                String::from("{}"),
+                Hook::PostReceive,
+                &file_info,
+                &HeaderMap::new(),
+            )
+            .await;
+    }
+
+    #[actix_rt::test]
+    async fn exclusive_callback() {
+        let hooks = hooks_server(0, status_code(200));
+        let callback = Server::run();
+        callback.expect(
+            Expectation::matching(request::method_path("POST", "/callback"))
+                .respond_with(status_code(200)),
+        );
+        let mut state = State::test_new().await;
+        state.config.notification_opts.hooks_http_urls = vec![hooks.url_str("/hook")];
+        state.config.notification_opts.hooks_callback_schemes = vec![String::from("http")];
+        state.config.notification_opts.hooks_callback_hosts =
+            vec![String::from("127.0.0.1"), String::from("::1")];
+        state.config.notification_opts.hooks_callback_exclusive = true;
+        let manager = NotificationManager::new(&state.config).await.unwrap();
+        let mut file_info = state.create_test_file().await;
+        file_info.callback_url = Some(callback.url_str("/callback"));
+        manager
+            .deliver_upload(
+                String::from("{}"),
+                Hook::PostFinish,
+                &file_info,
+                &HeaderMap::new(),
+            )
+            .await;
+    }
+
+    #[actix_rt::test]
+    async fn rejected_callback() {
+        let callback = hooks_server(0, status_code(200));
+        let mut state = State::test_new().await;
+        let dir = tempdir::TempDir::new("dead_letters").unwrap();
+        let dead_letters = dir.path().join("dead.jsonl");
+        state.config.notification_opts.hooks_callback_schemes = vec![String::from("http")];
+        state.config.notification_opts.hooks_dead_letter_file = Some(dead_letters.clone());
+        let manager = NotificationManager::new(&state.config).await.unwrap();
+        let mut file_info = state.create_test_file().await;
+        // Loopback addresses aren't allowed by default.
+        file_info.callback_url = Some(callback.url_str("/hook"));
+        manager
+            .deliver_upload(
+                String::from("{}"),
+                Hook::PostFinish,
+                &file_info,
+                &HeaderMap::new(),
+            )
+            .await;
+        let contents = std::fs::read_to_string(dead_letters).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn dead_letter() {
+        let server = hooks_server(2, httptest::cycle![status_code(500), status_code(502)]);
+        let mut state = State::test_new().await;
+        let dir = tempdir::TempDir::new("dead_letters").unwrap();
+        let dead_letters = dir.path().join("dead.jsonl");
+        state.config.notification_opts.hooks_http_urls = vec![server.url_str("/hook")];
+        state.config.notification_opts.hooks_retries = 1;
+        state.config.notification_opts.hooks_retry_backoff = 1;
+        state.config.notification_opts.hooks_dead_letter_file = Some(dead_letters.clone());
+        let manager = NotificationManager::new(&state.config).await.unwrap();
+        manager
+            .deliver(
+                String::from("memes"),
+                Hook::PostTerminate,
+                &HeaderMap::new(),
+            )
+            .await;
+        let contents = std::fs::read_to_string(dead_letters).unwrap();
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+        let letter: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(letter["hook"], "post-terminate");
+        assert_eq!(letter["message"], "memes");
+        assert!(letter["failed_at"].is_string());
+    }
 }
@@ -14,6 +14,20 @@ pub trait Notifier: DynClone {
         hook: Hook,
         headers_map: &HeaderMap,
     ) -> RustusResult<()>;
+
+    /// Send a hook and get responses to it.
+    ///
+    /// Responses are used by pre-create hooks to modify uploads.
+    /// Notifiers without responses return nothing.
+    async fn send_message_with_responses(
+        &self,
+        message: String,
+        hook: Hook,
+        headers_map: &HeaderMap,
+    ) -> RustusResult<Vec<String>> {
+        self.send_message(message, hook, headers_map).await?;
+        Ok(Vec::new())
+    }
 }
 
 dyn_clone::clone_trait_object!(Notifier);
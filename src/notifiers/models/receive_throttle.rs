@@ -0,0 +1,230 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix_web::http::header::HeaderMap;
+
+/// Number of tracked uploads after which idle ones are removed.
+const PRUNE_SIZE: usize = 10_000;
+
+/// Delay of kept hooks if only bytes are throttled.
+///
+/// Stalled uploads report their last progress after it.
+const BYTES_FLUSH_DELAY: Duration = Duration::from_secs(30);
+
+/// Post-receive hook of an upload.
+pub struct ReceiveHook {
+    pub message: String,
+    pub offset: usize,
+    pub headers: HeaderMap,
+}
+
+/// What to do with a post-receive hook.
+pub enum Throttled {
+    /// Hook must be sent right away.
+    Send(ReceiveHook),
+    /// Hook is kept until the delay passes,
+    /// then the latest kept hook must be flushed.
+    Delay(Duration),
+    /// Hook replaced the one which is already kept.
+    Coalesced,
+}
+
+struct UploadProgress {
+    sent_at: Instant,
+    sent_offset: usize,
+    /// Time of the latest hook of the upload.
+    offered_at: Instant,
+    pending: Option<ReceiveHook>,
+}
+
+/// Throttle of post-receive hooks.
+///
+/// Hook of an upload is sent if the interval passed
+/// or enough bytes were received since the last sent hook.
+/// Other hooks are coalesced, only the latest one is kept
+/// and flushed after a delay.
+#[derive(Clone)]
+pub struct ReceiveThrottle {
+    interval: Duration,
+    bytes: usize,
+    uploads: Arc<Mutex<HashMap<String, UploadProgress>>>,
+}
+
+impl ReceiveThrottle {
+    pub fn new(interval: Duration, bytes: usize) -> Self {
+        Self {
+            interval,
+            bytes,
+            uploads: Arc::default(),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.interval.is_zero() || self.bytes > 0
+    }
+
+    /// Time after which the kept hook is flushed.
+    fn flush_delay(&self) -> Duration {
+        if self.interval.is_zero() {
+            BYTES_FLUSH_DELAY
+        } else {
+            self.interval
+        }
+    }
+
+    /// Decide if the hook of the upload must be sent.
+    pub fn offer(&self, upload_id: &str, hook: ReceiveHook) -> Throttled {
+        if !self.is_enabled() {
+            return Throttled::Send(hook);
+        }
+        let now = Instant::now();
+        let mut uploads = self.uploads.lock().unwrap();
+        if uploads.len() >= PRUNE_SIZE {
+            // Kept hooks of idle uploads are already flushed.
+            let flush_delay = self.flush_delay();
+            uploads.retain(|_, progress| now.duration_since(progress.offered_at) < flush_delay);
+        }
+        let Some(progress) = uploads.get_mut(upload_id) else {
+            uploads.insert(
+                String::from(upload_id),
+                UploadProgress {
+                    sent_at: now,
+                    sent_offset: hook.offset,
+                    offered_at: now,
+                    pending: None,
+                },
+            );
+            return Throttled::Send(hook);
+        };
+        progress.offered_at = now;
+        let elapsed = now.duration_since(progress.sent_at);
+        let interval_passed = !self.interval.is_zero() && elapsed >= self.interval;
+        let bytes_passed =
+            self.bytes > 0 && hook.offset.saturating_sub(progress.sent_offset) >= self.bytes;
+        if interval_passed || bytes_passed {
+            progress.sent_at = now;
+            progress.sent_offset = hook.offset;
+            progress.pending = None;
+            return Throttled::Send(hook);
+        }
+        if progress.pending.replace(hook).is_some() {
+            return Throttled::Coalesced;
+        }
+        if self.interval.is_zero() {
+            Throttled::Delay(BYTES_FLUSH_DELAY)
+        } else {
+            Throttled::Delay(self.interval.saturating_sub(elapsed))
+        }
+    }
+
+    /// Take the kept hook of the upload, it's marked as sent.
+    pub fn flush(&self, upload_id: &str) -> Option<ReceiveHook> {
+        let mut uploads = self.uploads.lock().unwrap();
+        let progress = uploads.get_mut(upload_id)?;
+        let hook = progress.pending.take()?;
+        progress.sent_at = Instant::now();
+        progress.sent_offset = hook.offset;
+        Some(hook)
+    }
+
+    /// Stop tracking the upload.
+    ///
+    /// The kept hook is returned, so the last progress
+    /// is delivered before the upload is finished or terminated.
+    pub fn finish(&self, upload_id: &str) -> Option<ReceiveHook> {
+        self.uploads
+            .lock()
+            .unwrap()
+            .remove(upload_id)
+            .and_then(|progress| progress.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReceiveHook, ReceiveThrottle, Throttled};
+    use actix_web::http::header::HeaderMap;
+    use std::time::Duration;
+
+    fn hook(offset: usize) -> ReceiveHook {
+        ReceiveHook {
+            message: offset.to_string(),
+            offset,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    #[test]
+    fn bytes_threshold() {
+        let throttle = ReceiveThrottle::new(Duration::ZERO, 100);
+        assert!(matches!(throttle.offer("id", hook(10)), Throttled::Send(_)));
+        // Kept hook is flushed after a delay, even if no more bytes come.
+        assert!(matches!(
+            throttle.offer("id", hook(50)),
+            Throttled::Delay(delay) if delay == super::BYTES_FLUSH_DELAY
+        ));
+        assert!(matches!(
+            throttle.offer("id", hook(90)),
+            Throttled::Coalesced
+        ));
+        assert!(matches!(
+            throttle.offer("id", hook(110)),
+            Throttled::Send(_)
+        ));
+        assert!(matches!(
+            throttle.offer("id", hook(150)),
+            Throttled::Delay(_)
+        ));
+        // The last progress is kept until the upload is finished.
+        assert_eq!(throttle.finish("id").unwrap().offset, 150);
+        assert!(throttle.finish("id").is_none());
+    }
+
+    #[test]
+    fn interval() {
+        let throttle = ReceiveThrottle::new(Duration::from_secs(60), 0);
+        assert!(matches!(throttle.offer("id", hook(10)), Throttled::Send(_)));
+        assert!(matches!(
+            throttle.offer("id", hook(20)),
+            Throttled::Delay(_)
+        ));
+        assert!(matches!(
+            throttle.offer("id", hook(30)),
+            Throttled::Coalesced
+        ));
+        assert_eq!(throttle.flush("id").unwrap().message, "30");
+        assert!(throttle.flush("id").is_none());
+        // Uploads are throttled separately.
+        assert!(matches!(
+            throttle.offer("other", hook(10)),
+            Throttled::Send(_)
+        ));
+    }
+
+    #[test]
+    fn prune_idle() {
+        let throttle = ReceiveThrottle::new(Duration::from_millis(10), 0);
+        throttle.offer("kept", hook(10));
+        throttle.offer("kept", hook(20));
+        for id in 1..super::PRUNE_SIZE {
+            throttle.offer(id.to_string().as_str(), hook(10));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(matches!(
+            throttle.offer("other", hook(10)),
+            Throttled::Send(_)
+        ));
+        // Uploads with kept hooks are removed too, their hooks are already flushed.
+        assert_eq!(throttle.uploads.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn disabled() {
+        let throttle = ReceiveThrottle::new(Duration::ZERO, 0);
+        assert!(matches!(throttle.offer("id", hook(10)), Throttled::Send(_)));
+        assert!(matches!(throttle.offer("id", hook(20)), Throttled::Send(_)));
+    }
+}
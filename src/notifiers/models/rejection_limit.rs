@@ -0,0 +1,96 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+/// Length of the window in which hooks are counted.
+const WINDOW: Duration = Duration::from_secs(1);
+
+struct Window {
+    started_at: Instant,
+    sent: usize,
+    dropped: usize,
+}
+
+/// Limit of `create-rejected` hooks.
+///
+/// Rejected requests are cheap for clients, so every
+/// one of them must not produce an outgoing hook.
+/// At most `rate` hooks are sent every second,
+/// others are dropped and only their number is logged.
+#[derive(Clone)]
+pub struct RejectionLimit {
+    rate: usize,
+    window: Arc<Mutex<Window>>,
+}
+
+impl RejectionLimit {
+    pub fn new(rate: usize) -> Self {
+        Self {
+            rate,
+            window: Arc::new(Mutex::new(Window {
+                started_at: Instant::now(),
+                sent: 0,
+                dropped: 0,
+            })),
+        }
+    }
+
+    /// Check if the hook of a rejection can be sent.
+    ///
+    /// Zero rate means that hooks aren't limited.
+    pub fn allow(&self) -> bool {
+        if self.rate == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let mut window = self.window.lock().unwrap();
+        if now.duration_since(window.started_at) >= WINDOW {
+            if window.dropped > 0 {
+                warn!(
+                    "{} create-rejected hooks were dropped, limit is {} per second.",
+                    window.dropped, self.rate
+                );
+            }
+            window.started_at = now;
+            window.sent = 0;
+            window.dropped = 0;
+        }
+        if window.sent < self.rate {
+            window.sent += 1;
+            true
+        } else {
+            window.dropped += 1;
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RejectionLimit;
+    use std::time::Duration;
+
+    #[test]
+    fn limited() {
+        let limit = RejectionLimit::new(2);
+        assert!(limit.allow());
+        assert!(limit.allow());
+        assert!(!limit.allow());
+        assert_eq!(limit.window.lock().unwrap().dropped, 1);
+        // Hooks are allowed again in the next window.
+        std::thread::sleep(super::WINDOW + Duration::from_millis(10));
+        assert!(limit.allow());
+        assert_eq!(limit.window.lock().unwrap().dropped, 0);
+    }
+
+    #[test]
+    fn unlimited() {
+        let limit = RejectionLimit::new(0);
+        for _ in 0..100 {
+            assert!(limit.allow());
+        }
+    }
+}
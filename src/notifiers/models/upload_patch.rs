@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+};
+
+/// Changes of an upload returned by pre-create hook.
+///
+/// Empty response means that the upload
+/// must be created as is.
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadPatch {
+    /// Whether the upload must not be created.
+    pub reject: bool,
+    /// Reason of the rejection returned to the client.
+    pub reason: Option<String>,
+    /// Metadata to update.
+    ///
+    /// Keys with null values are removed.
+    pub metadata: HashMap<String, Option<String>>,
+    /// New length of the upload.
+    pub length: Option<usize>,
+}
+
+impl UploadPatch {
+    /// Parse hook response.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if response isn't a valid patch.
+    pub fn parse(response: &str) -> RustusResult<Self> {
+        if response.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_json::from_str(response).map_err(|err| {
+            RustusError::HookError(format!("Invalid pre-create hook response: {err}"))
+        })
+    }
+
+    /// Apply patch to the upload.
+    ///
+    /// Length of final uploads is computed from
+    /// their parts, so it's never changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the hook rejects the upload.
+    pub fn apply(self, file_info: &mut FileInfo) -> RustusResult<()> {
+        if self.reject {
+            return Err(RustusError::UploadRejected(
+                self.reason
+                    .unwrap_or_else(|| String::from("Upload is rejected by hook.")),
+            ));
+        }
+        for (key, value) in self.metadata {
+            match value {
+                Some(value) => file_info.metadata.insert(key, value),
+                None => file_info.metadata.remove(key.as_str()),
+            };
+        }
+        if let Some(length) = self.length {
+            if file_info.is_final {
+                log::warn!("Length of final upload {} can't be changed.", file_info.id);
+            } else {
+                file_info.length = Some(length);
+                file_info.deferred_size = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply all responses of pre-create hooks.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any response is invalid or rejects the upload.
+    pub fn apply_responses(responses: &[String], file_info: &mut FileInfo) -> RustusResult<()> {
+        for response in responses {
+            Self::parse(response)?.apply(file_info)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UploadPatch;
+    use crate::{errors::RustusError, info_storages::FileInfo};
+
+    #[test]
+    fn empty_response() {
+        assert_eq!(UploadPatch::parse("").unwrap(), UploadPatch::default());
+        assert_eq!(UploadPatch::parse("{}").unwrap(), UploadPatch::default());
+    }
+
+    #[test]
+    fn invalid_response() {
+        assert!(UploadPatch::parse("OK").is_err());
+        assert!(UploadPatch::parse("{\"lenght\": 10}").is_err());
+    }
+
+    #[test]
+    fn apply_patch() {
+        let mut file_info = FileInfo::new_test();
+        file_info.length = None;
+        file_info.deferred_size = true;
+        file_info
+            .metadata
+            .insert(String::from("secret"), String::from("value"));
+        let patch =
+            UploadPatch::parse(r#"{"metadata": {"owner": "pepe", "secret": null}, "length": 20}"#)
+                .unwrap();
+        patch.apply(&mut file_info).unwrap();
+        assert_eq!(file_info.metadata.get("owner").unwrap(), "pepe");
+        assert!(!file_info.metadata.contains_key("secret"));
+        assert_eq!(file_info.length, Some(20));
+        assert!(!file_info.deferred_size);
+    }
+
+    #[test]
+    fn final_upload_length() {
+        let mut file_info = FileInfo::new_test();
+        file_info.is_final = true;
+        file_info.length = None;
+        let patch = UploadPatch::parse(r#"{"length": 20}"#).unwrap();
+        patch.apply(&mut file_info).unwrap();
+        assert_eq!(file_info.length, None);
+    }
+
+    #[test]
+    fn reject() {
+        let mut file_info = FileInfo::new_test();
+        let patch = UploadPatch::parse(r#"{"reject": true, "reason": "Nope"}"#).unwrap();
+        let err = patch.apply(&mut file_info).unwrap_err();
+        assert!(matches!(err, RustusError::UploadRejected(reason) if reason == "Nope"));
+    }
+}
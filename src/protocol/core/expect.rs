@@ -0,0 +1,222 @@
+use actix_http::HttpMessage;
+use actix_web::{
+    body::BoxBody,
+    dev::{fn_service, Response, ServiceFactory},
+    http::Method,
+    HttpResponse,
+};
+
+use crate::{
+    errors::RustusError,
+    utils::{
+        headers::{is_chunk_content_type, parse_header_value},
+        tombstones,
+    },
+    RustusResult, State,
+};
+
+use super::write_bytes::{check_chunk, chunk_token, updated_length};
+
+/// Get ID of the upload if the request writes a chunk.
+fn chunk_upload<'a>(state: &State, request: &'a actix_http::Request) -> Option<&'a str> {
+    let method_override = request
+        .headers()
+        .get("X-HTTP-Method-Override")
+        .and_then(|value| value.to_str().ok());
+    let is_patch = match method_override {
+        Some(method) => method == Method::PATCH.as_str(),
+        None => request.method() == Method::PATCH,
+    };
+    if !is_patch {
+        return None;
+    }
+    let path = request.path().trim_matches('/');
+    let base_url = state.config.base_url();
+    let file_id = if base_url.is_empty() {
+        path
+    } else {
+        path.strip_prefix(base_url.as_str())?.strip_prefix('/')?
+    };
+    if file_id.is_empty() || file_id.contains('/') {
+        return None;
+    }
+    Some(file_id)
+}
+
+/// Check the chunk before `100 Continue` is sent.
+///
+/// Clients with `Expect: 100-continue` header wait for this response
+/// before sending the body, so chunks which would be rejected
+/// by the handler aren't sent at all.
+///
+/// Returns the response for chunks which are rejected.
+///
+/// # Errors
+///
+/// Returns an error if the chunk can't be written to the upload.
+async fn check_expect(
+    state: &State,
+    request: &actix_http::Request,
+) -> RustusResult<Option<HttpResponse>> {
+    let Some(file_id) = chunk_upload(state, request) else {
+        return Ok(None);
+    };
+    let headers = request.headers();
+    if !is_chunk_content_type(headers, state.config.lenient_content_type) {
+        return Ok(Some(
+            HttpResponse::UnsupportedMediaType().body("Unknown content-type."),
+        ));
+    }
+    let Some(offset) = parse_header_value::<usize>(headers, "Upload-Offset") else {
+        return Ok(Some(
+            HttpResponse::UnsupportedMediaType().body("No offset provided."),
+        ));
+    };
+    // Client isn't authorized yet,
+    // so uploads can't be looked up.
+    if state.config.auth_opts.auth_introspection_url.is_some() {
+        return Ok(None);
+    }
+    let file_info = tombstones::get_info(state, file_id).await?;
+    if file_info.is_final {
+        return Ok(Some(HttpResponse::Forbidden().finish()));
+    }
+    if file_info.storage != state.data_storage.to_string() {
+        return Err(RustusError::FileNotFound);
+    }
+    // Retried chunks are acknowledged by the handler.
+    if let Some(token) = chunk_token(headers, state)? {
+        if file_info.has_chunk_token(token, chrono::Utc::now()) {
+            return Ok(None);
+        }
+    }
+    check_chunk(
+        state,
+        headers,
+        &file_info,
+        offset,
+        updated_length(state, headers),
+    )
+    .await
+}
+
+/// Service which handles `Expect: 100-continue` header.
+///
+/// Rejected requests get the final response instead of `100 Continue`
+/// and the connection is closed, so the body is never read.
+/// Other requests are passed to the app.
+pub fn expect_service(
+    state: State,
+) -> impl ServiceFactory<
+    actix_http::Request,
+    Config = (),
+    Response = actix_http::Request,
+    Error = Response<BoxBody>,
+    InitError = (),
+> {
+    fn_service(move |request: actix_http::Request| {
+        let state = state.clone();
+        async move {
+            let response = match check_expect(&state, &request).await {
+                Ok(None) => return Ok(request),
+                Ok(Some(response)) => response,
+                Err(err) => HttpResponse::from_error(err),
+            };
+            let mut response: Response<BoxBody> = response.into();
+            response
+                .head_mut()
+                .set_connection_type(actix_http::ConnectionType::Close);
+            Err(response)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expect_service;
+    use crate::State;
+    use actix_web::{
+        dev::{Service, ServiceFactory},
+        http::StatusCode,
+        test::TestRequest,
+    };
+
+    /// Call the expect service with the request.
+    async fn expect(
+        state: &State,
+        request: TestRequest,
+    ) -> Result<actix_http::Request, StatusCode> {
+        let service = expect_service(state.clone()).new_service(()).await.unwrap();
+        service
+            .call(
+                request
+                    .insert_header(("Expect", "100-continue"))
+                    .to_request(),
+            )
+            .await
+            .map_err(|response| {
+                assert_eq!(
+                    response.head().connection_type(),
+                    actix_http::ConnectionType::Close
+                );
+                response.status()
+            })
+    }
+
+    fn chunk(state: &State, file_id: &str, offset: usize, length: usize) -> TestRequest {
+        TestRequest::patch()
+            .uri(state.config.file_url(file_id).as_str())
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .insert_header(("Upload-Offset", offset))
+            .insert_header(("Content-Length", length))
+    }
+
+    #[actix_rt::test]
+    async fn chunk_checks() {
+        let state = State::test_new().await;
+        let file = state.create_test_file().await;
+        let id = file.id.as_str();
+        assert!(expect(&state, chunk(&state, id, 0, 5)).await.is_ok());
+        assert_eq!(
+            expect(&state, chunk(&state, id, 3, 5)).await.unwrap_err(),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            expect(&state, chunk(&state, id, 0, 11)).await.unwrap_err(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            expect(&state, chunk(&state, "unknown", 0, 5))
+                .await
+                .unwrap_err(),
+            StatusCode::NOT_FOUND
+        );
+        let request = chunk(&state, id, 0, 5).insert_header(("Content-Type", "text/plain"));
+        assert_eq!(
+            expect(&state, request).await.unwrap_err(),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        // Requests of other routes aren't checked.
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Content-Length", 5));
+        assert!(expect(&state, request).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn quota_exceeded() {
+        let mut state = State::test_new().await;
+        state.config.default_tenant_quota = Some(10);
+        let mut file = state.create_test_file().await;
+        file.length = None;
+        file.deferred_size = true;
+        file.metadata.insert("tenant".into(), "acme".into());
+        state.info_storage.set_info(&file, false).await.unwrap();
+        let id = file.id.as_str();
+        assert!(expect(&state, chunk(&state, id, 0, 10)).await.is_ok());
+        assert_eq!(
+            expect(&state, chunk(&state, id, 0, 11)).await.unwrap_err(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+}
@@ -5,7 +5,25 @@ use actix_web::{
 };
 use futures::stream::empty;
 
-use crate::{RustusResult, State};
+use crate::{
+    background::assembly::AssemblyResponse, info_storages::FileInfo, utils::tombstones,
+    RustusResult, State,
+};
+
+/// Size of concatenated data of the final upload.
+///
+/// It's always less than the length,
+/// so the upload isn't finished until it's assembled.
+async fn assembled_size(state: &State, file_info: &FileInfo) -> usize {
+    let stored = state
+        .data_storage
+        .stored_size(file_info)
+        .await
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    stored.min(file_info.length.unwrap_or_default().saturating_sub(1))
+}
 
 pub async fn get_file_info(
     state: web::Data<State>,
@@ -18,10 +36,16 @@ pub async fn get_file_info(
     let file_id = request.match_info().get("file_id").unwrap();
 
     // Getting file info from info_storage.
-    let file_info = state.info_storage.get_info(file_id).await?;
+    let mut file_info = tombstones::get_info(&state, file_id).await?;
     if file_info.storage != state.data_storage.to_string() {
         return Err(RustusError::FileNotFound);
     }
+    if file_info.assembling {
+        if state.config.assembly_response == AssemblyResponse::TooEarly {
+            return Err(RustusError::Assembling(file_info.id));
+        }
+        file_info.offset = assembled_size(&state, &file_info).await;
+    }
     let mut builder = HttpResponse::Ok();
     if file_info.is_partial {
         builder.insert_header(("Upload-Concat", "partial"));
@@ -54,7 +78,8 @@ pub async fn get_file_info(
         builder.insert_header(("Upload-Metadata", meta));
     }
     builder.insert_header(("Upload-Created", file_info.created_at.timestamp()));
-    builder.insert_header(CacheControl(vec![CacheDirective::NoCache]));
+    // Offsets must never be cached by clients or proxies.
+    builder.insert_header(CacheControl(vec![CacheDirective::NoStore]));
     Ok(builder.streaming(empty::<RustusResult<web::Bytes>>()))
 }
 
@@ -62,7 +87,7 @@ pub async fn get_file_info(
 mod tests {
     use actix_web::http::{Method, StatusCode};
 
-    use crate::{server::test::get_service, State};
+    use crate::{background::assembly::AssemblyResponse, server::test::get_service, State};
     use actix_web::test::{call_service, TestRequest};
 
     use base64::{engine::general_purpose, Engine};
@@ -91,7 +116,8 @@ mod tests {
             .unwrap()
             .parse::<usize>()
             .unwrap();
-        assert_eq!(file_info.offset, offset)
+        assert_eq!(file_info.offset, offset);
+        assert_eq!(response.headers().get("Cache-Control").unwrap(), "no-store");
     }
 
     #[actix_rt::test]
@@ -218,6 +244,7 @@ mod tests {
             .to_request();
         let response = call_service(&mut rustus, request).await;
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers().get("Cache-Control").unwrap(), "no-store");
     }
 
     #[actix_rt::test]
@@ -237,4 +264,35 @@ mod tests {
         let response = call_service(&mut rustus, request).await;
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
+
+    #[actix_rt::test]
+    async fn assembling_final() {
+        let mut state = State::test_new().await;
+        let mut file_info = state.create_test_file().await;
+        file_info.is_final = true;
+        file_info.assembling = true;
+        file_info.length = Some(100);
+        file_info.offset = 0;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let mut rustus = get_service(state.clone()).await;
+        let request = TestRequest::with_uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .method(Method::HEAD)
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("Upload-Length").unwrap(), "100");
+        assert_eq!(response.headers().get("Upload-Offset").unwrap(), "0");
+
+        state.config.assembly_response = AssemblyResponse::TooEarly;
+        let mut rustus = get_service(state.clone()).await;
+        let request = TestRequest::with_uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .method(Method::HEAD)
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status().as_u16(), 425);
+    }
 }
@@ -1,9 +1,14 @@
 use actix_web::{guard, middleware, web};
 
+mod expect;
 mod get_info;
+mod owner_uploads;
+mod prefix_checksum;
 mod server_info;
 mod write_bytes;
 
+pub use expect::expect_service;
+
 /// Add core TUS protocol endpoints.
 ///
 /// This part of a protocol
@@ -38,7 +43,38 @@ pub fn add_extension(web_app: &mut web::ServiceConfig) {
                 .name("core:file_info")
                 .guard(guard::Head())
                 // Header to prevent the client and/or proxies from caching the response.
+                // It is added to error responses as well.
                 .wrap(middleware::DefaultHeaders::new().add(("Cache-Control", "no-store")))
                 .to(get_info::get_file_info),
         );
 }
+
+/// Add endpoint to get checksum of stored bytes.
+///
+/// GET /api/file/checksum - to get checksum of bytes up to the current offset.
+#[cfg_attr(coverage, no_coverage)]
+pub fn add_prefix_checksum(web_app: &mut web::ServiceConfig) {
+    web_app.service(
+        // GET /base/{file_id}/checksum
+        // Checksum of the stored prefix of an upload.
+        web::resource("/{file_id}/checksum/")
+            .name("core:prefix_checksum")
+            .guard(guard::Get())
+            .to(prefix_checksum::prefix_checksum),
+    );
+}
+
+/// Add endpoint to list unfinished uploads of the client.
+///
+/// GET /api - to get uploads of the owner from the auth header.
+#[cfg_attr(coverage, no_coverage)]
+pub fn add_owner_uploads(web_app: &mut web::ServiceConfig) {
+    web_app.service(
+        // GET /base/
+        // Unfinished uploads of the client.
+        web::resource("/")
+            .name("core:owner_uploads")
+            .guard(guard::Get())
+            .to(owner_uploads::owner_uploads),
+    );
+}
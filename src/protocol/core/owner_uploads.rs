@@ -0,0 +1,117 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    info_storages::{models::upload_filter::UploadStatus, UploadFilter},
+    protocol::upload_location,
+    utils::owner,
+    State,
+};
+
+#[derive(Deserialize)]
+pub struct OwnerUploadsQuery {
+    /// Number of uploads to skip.
+    #[serde(default)]
+    offset: usize,
+    /// Maximum number of uploads.
+    limit: Option<usize>,
+}
+
+/// List unfinished uploads of the client.
+///
+/// Owner of uploads is taken from the header
+/// set by a trusted proxy, so clients can only
+/// resume their own uploads.
+pub async fn owner_uploads(
+    request: HttpRequest,
+    query: web::Query<OwnerUploadsQuery>,
+    state: web::Data<State>,
+) -> actix_web::Result<HttpResponse> {
+    let Some(owner) = owner::request_owner(&state.config, &request) else {
+        return Ok(HttpResponse::Unauthorized().finish());
+    };
+    let filter = UploadFilter {
+        status: Some(UploadStatus::Incomplete),
+        metadata: Some((state.config.owner_metadata_key.clone(), owner)),
+        offset: query.offset,
+        limit: query.limit,
+        ..UploadFilter::default()
+    };
+    let storage = state.data_storage.to_string();
+    let mut uploads = Vec::new();
+    for file_info in state.info_storage.list_files(&filter).await? {
+        if file_info.storage != storage {
+            continue;
+        }
+        uploads.push(json!({
+            "id": file_info.id,
+            "location": upload_location(&state, &request, file_info.id.as_str())?,
+            "offset": file_info.offset,
+            "length": file_info.length,
+            "metadata": file_info.metadata,
+            "created_at": file_info.created_at,
+        }));
+    }
+    Ok(HttpResponse::Ok()
+        .insert_header(("Cache-Control", "no-store"))
+        .json(json!({ "uploads": uploads })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{server::test::get_service, State};
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, read_body_json, TestRequest},
+    };
+
+    #[actix_rt::test]
+    async fn owner_uploads() {
+        let mut state = State::test_new().await;
+        state.config.owner_header = Some(String::from("X-Auth-Subject"));
+        state.config.client_ip.trusted_proxies = vec!["10.0.0.0/8".parse().unwrap()];
+        let mut own = state.create_test_file().await;
+        own.metadata
+            .insert(String::from("owner"), String::from("user-1"));
+        state.info_storage.set_info(&own, false).await.unwrap();
+        let mut other = state.create_test_file().await;
+        other
+            .metadata
+            .insert(String::from("owner"), String::from("user-2"));
+        state.info_storage.set_info(&other, false).await.unwrap();
+        let mut finished = state.create_test_file().await;
+        finished.offset = 10;
+        finished
+            .metadata
+            .insert(String::from("owner"), String::from("user-1"));
+        state.info_storage.set_info(&finished, false).await.unwrap();
+        let mut rustus = get_service(state.clone()).await;
+
+        let request = TestRequest::get()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("X-Auth-Subject", "user-1"))
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(resp).await;
+        let uploads = body["uploads"].as_array().unwrap();
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0]["id"], own.id.as_str());
+        assert_eq!(uploads[0]["offset"], 0);
+        assert!(uploads[0]["location"]
+            .as_str()
+            .unwrap()
+            .ends_with(own.id.as_str()));
+
+        // Owner from untrusted peers is ignored.
+        let request = TestRequest::get()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("X-Auth-Subject", "user-1"))
+            .peer_addr("192.168.0.1:4000".parse().unwrap())
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
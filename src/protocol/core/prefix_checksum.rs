@@ -0,0 +1,229 @@
+use actix_web::{
+    http::header::{CacheControl, CacheDirective},
+    web, HttpRequest, HttpResponse,
+};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    background::checksum::hash_contents,
+    errors::RustusError,
+    utils::{encryption, hashes::Hasher},
+    RustusResult, State,
+};
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize)]
+pub struct PrefixChecksumQuery {
+    /// Hash algorithm. It's sha256 by default.
+    algo: Option<String>,
+}
+
+/// Checksum of the stored part of an upload.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Serialize, Debug)]
+pub struct PrefixChecksum {
+    pub algorithm: String,
+    /// Offset of the upload.
+    pub offset: usize,
+    /// Number of hashed bytes.
+    ///
+    /// It's less than offset if the storage
+    /// has fewer bytes than expected.
+    pub length: usize,
+    /// Base64 encoded checksum, as in `Upload-Checksum` header.
+    pub checksum: String,
+}
+
+/// Compute checksum of bytes stored up to the current offset.
+///
+/// Clients can compare it with checksum of the bytes
+/// they've sent before resuming an upload.
+/// Encrypted uploads are hashed after decryption,
+/// so the passphrase is required.
+#[allow(clippy::module_name_repetitions)]
+pub async fn get_prefix_checksum(
+    state: &State,
+    request: &HttpRequest,
+    file_id: &str,
+    algo: &str,
+) -> RustusResult<PrefixChecksum> {
+    let mut hasher = Hasher::new(algo)?;
+    let file_info = state.info_storage.get_info(file_id).await?;
+    if file_info.storage != state.data_storage.to_string() {
+        return Err(RustusError::FileNotFound);
+    }
+    let key = encryption::request_key(request, &file_info).await?;
+    let length = if file_info.offset == 0 {
+        0
+    } else {
+        hash_contents(
+            state,
+            &file_info,
+            key.as_ref(),
+            &mut hasher,
+            Some(file_info.offset),
+        )
+        .await?
+    };
+    Ok(PrefixChecksum {
+        algorithm: String::from(algo),
+        offset: file_info.offset,
+        length,
+        checksum: base64::engine::general_purpose::STANDARD.encode(hasher.finalize()),
+    })
+}
+
+pub async fn prefix_checksum(
+    state: web::Data<State>,
+    request: HttpRequest,
+    query: web::Query<PrefixChecksumQuery>,
+) -> RustusResult<HttpResponse> {
+    let Some(file_id) = request.match_info().get("file_id") else {
+        return Err(RustusError::FileNotFound);
+    };
+    let algo = query.algo.as_deref().unwrap_or("sha256");
+    let checksum = get_prefix_checksum(&state, &request, file_id, algo).await?;
+    Ok(HttpResponse::Ok()
+        .insert_header(("Upload-Offset", checksum.offset.to_string()))
+        .insert_header((
+            "Upload-Checksum",
+            format!("{} {}", checksum.algorithm, checksum.checksum),
+        ))
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .json(checksum))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{server::test::get_service, State};
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, read_body_json, TestRequest},
+    };
+    use bytes::Bytes;
+
+    fn checksum_url(state: &State, file_id: &str, algo: Option<&str>) -> String {
+        let mut url = format!("{}checksum/", state.config.file_url(file_id));
+        if let Some(algo) = algo {
+            url = format!("{url}?algo={algo}");
+        }
+        url
+    }
+
+    #[actix_rt::test]
+    async fn prefix_checksum() {
+        let state = State::test_new().await;
+        let mut rustus = get_service(state.clone()).await;
+        let mut file_info = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        file_info.offset = 5;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let request = TestRequest::get()
+            .uri(checksum_url(&state, file_info.id.as_str(), Some("md5")).as_str())
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("Upload-Offset").unwrap(), "5");
+        assert_eq!(
+            response.headers().get("Upload-Checksum").unwrap(),
+            "md5 xIwpFX4rNYzBRAJ/Pi2MtA=="
+        );
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["algorithm"], "md5");
+        assert_eq!(body["offset"], 5);
+        assert_eq!(body["length"], 5);
+        assert_eq!(body["checksum"], "xIwpFX4rNYzBRAJ/Pi2MtA==");
+    }
+
+    #[actix_rt::test]
+    async fn prefix_checksum_up_to_offset() {
+        let state = State::test_new().await;
+        let mut rustus = get_service(state.clone()).await;
+        let mut file_info = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&file_info, Bytes::from("memes and more"))
+            .await
+            .unwrap();
+        file_info.offset = 5;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let request = TestRequest::get()
+            .uri(checksum_url(&state, file_info.id.as_str(), None).as_str())
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["algorithm"], "sha256");
+        assert_eq!(body["length"], 5);
+        // Base64 of sha256 of "memes".
+        assert_eq!(
+            body["checksum"],
+            "BksbYPqW3Fp6SfRh8c63oNs004QF9Pud6pJBrUCKAPY="
+        );
+    }
+
+    #[actix_rt::test]
+    async fn conditional_headers_ignored() {
+        let state = State::test_new().await;
+        let mut rustus = get_service(state.clone()).await;
+        let mut file_info = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        file_info.offset = 5;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        // Headers of the client must not change hashed bytes.
+        let request = TestRequest::get()
+            .uri(checksum_url(&state, file_info.id.as_str(), Some("md5")).as_str())
+            .insert_header(("Range", "bytes=2-3"))
+            .insert_header(("If-None-Match", "*"))
+            .insert_header(("If-Modified-Since", "Wed, 21 Oct 2099 07:28:00 GMT"))
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = read_body_json(response).await;
+        assert_eq!(body["length"], 5);
+        assert_eq!(body["checksum"], "xIwpFX4rNYzBRAJ/Pi2MtA==");
+    }
+
+    #[actix_rt::test]
+    async fn unknown_algorithm() {
+        let state = State::test_new().await;
+        let mut rustus = get_service(state.clone()).await;
+        let file_info = state.create_test_file().await;
+        let request = TestRequest::get()
+            .uri(checksum_url(&state, file_info.id.as_str(), Some("crc32")).as_str())
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn unknown_file() {
+        let state = State::test_new().await;
+        let mut rustus = get_service(state.clone()).await;
+        let request = TestRequest::get()
+            .uri(checksum_url(&state, "unknown", None).as_str())
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
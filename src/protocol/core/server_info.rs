@@ -1,4 +1,4 @@
-use crate::protocol::extensions::Extensions;
+use crate::{protocol::extensions::Extensions, utils::hashes::CHECKSUM_ALGORITHMS};
 use actix_web::{http::StatusCode, web, HttpResponse, HttpResponseBuilder};
 
 use crate::State;
@@ -16,14 +16,28 @@ pub async fn server_info(state: web::Data<State>) -> HttpResponse {
     let mut response_builder = HttpResponseBuilder::new(StatusCode::OK);
     response_builder.insert_header(("Tus-Extension", ext_str.as_str()));
     if state.config.tus_extensions.contains(&Extensions::Checksum) {
-        response_builder.insert_header(("Tus-Checksum-Algorithm", "md5,sha1,sha256,sha512"));
+        response_builder.insert_header((
+            "Tus-Checksum-Algorithm",
+            CHECKSUM_ALGORITHMS.join(",").as_str(),
+        ));
+    }
+    let chunk_size = state
+        .config
+        .storage_opts
+        .preferred_chunk_size
+        .or_else(|| state.data_storage.preferred_chunk_size());
+    if let Some(chunk_size) = chunk_size {
+        response_builder.insert_header(("Rustus-Preferred-Chunk-Size", chunk_size.to_string()));
     }
     response_builder.finish()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{protocol::extensions::Extensions, server::test::get_service, State};
+    use crate::{
+        protocol::extensions::Extensions, server::test::get_service,
+        storages::aligned_storage::AlignedStorage, State,
+    };
     use actix_web::test::{call_service, TestRequest};
 
     use actix_web::http::Method;
@@ -52,4 +66,51 @@ mod tests {
         assert!(extensions.contains(Extensions::Concatenation.to_string().as_str()));
         assert!(extensions.contains(Extensions::Termination.to_string().as_str()));
     }
+
+    #[actix_rt::test]
+    async fn preferred_chunk_size() {
+        let mut state = State::test_new().await;
+        let mut rustus = get_service(state.clone()).await;
+        let request = TestRequest::with_uri(state.config.test_url().as_str())
+            .method(Method::OPTIONS)
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert!(response
+            .headers()
+            .get("Rustus-Preferred-Chunk-Size")
+            .is_none());
+
+        state.data_storage = Box::new(AlignedStorage::new(state.data_storage.clone(), 1024));
+        let mut rustus = get_service(state.clone()).await;
+        let request = TestRequest::with_uri(state.config.test_url().as_str())
+            .method(Method::OPTIONS)
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(
+            response
+                .headers()
+                .get("Rustus-Preferred-Chunk-Size")
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "1024"
+        );
+
+        // Configured size takes precedence.
+        state.config.storage_opts.preferred_chunk_size = Some(4096);
+        let mut rustus = get_service(state.clone()).await;
+        let request = TestRequest::with_uri(state.config.test_url().as_str())
+            .method(Method::OPTIONS)
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(
+            response
+                .headers()
+                .get("Rustus-Preferred-Chunk-Size")
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "4096"
+        );
+    }
 }
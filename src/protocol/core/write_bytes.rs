@@ -1,35 +1,278 @@
 use actix_web::{
-    http::header::{CacheControl, CacheDirective},
+    http::{
+        header::{CacheControl, CacheDirective, HeaderMap, HeaderValue, TRANSFER_ENCODING},
+        ConnectionType,
+    },
     web,
     web::Bytes,
-    HttpRequest, HttpResponse,
+    FromRequest, HttpRequest, HttpResponse,
 };
 
 use crate::{
+    background::{checksum, derivation, export},
     errors::RustusError,
+    info_storages::FileInfo,
     metrics,
     notifiers::Hook,
     protocol::extensions::Extensions,
+    storages::aligned_len,
     utils::{
+        durability, encryption,
         hashes::verify_chunk_checksum,
-        headers::{check_header, parse_header},
+        headers::{is_chunk_content_type, parse_header, parse_header_value},
+        orphans, quota, tombstones,
     },
     RustusResult, State,
 };
 
+/// Remove unfinished upload which wasn't written for too long.
+///
+/// Clients must not resume such uploads,
+/// since their data may be removed partially.
+async fn remove_stale(state: &State, file_info: &FileInfo) -> RustusResult<()> {
+    let Some(max_age) = state.config.max_resume_age else {
+        return Ok(());
+    };
+    let max_age = chrono::Duration::seconds(i64::try_from(max_age).unwrap_or(i64::MAX / 1000));
+    if Some(file_info.offset) == file_info.length
+        || chrono::Utc::now() - file_info.last_modified() <= max_age
+    {
+        return Ok(());
+    }
+    log::info!(
+        "Upload {} wasn't written since {}. It's removed.",
+        file_info.id,
+        file_info.last_modified()
+    );
+    state
+        .info_storage
+        .remove_info(file_info.id.as_str())
+        .await?;
+    match state.data_storage.remove_file(file_info).await {
+        Ok(()) | Err(RustusError::FileNotFound) => {}
+        Err(err) => return Err(err),
+    }
+    tombstones::bury(state, file_info.id.as_str()).await;
+    state.progress.publish_terminated(file_info);
+    Err(RustusError::UploadExpired(file_info.id.clone()))
+}
+
+/// Maximum length of idempotency key of a chunk.
+const MAX_CHUNK_TOKEN_LENGTH: usize = 128;
+
+/// Get idempotency key of the chunk.
+///
+/// Keys are ignored unless `--chunk-token-ttl` is set.
+pub fn chunk_token<'a>(headers: &'a HeaderMap, state: &State) -> RustusResult<Option<&'a str>> {
+    if state.config.chunk_token_ttl.is_none() {
+        return Ok(None);
+    }
+    let Some(value) = headers.get("Idempotency-Key") else {
+        return Ok(None);
+    };
+    let token = value.to_str().map_err(|_| RustusError::WrongHeaderValue)?;
+    if token.is_empty() || token.len() > MAX_CHUNK_TOKEN_LENGTH {
+        return Err(RustusError::WrongHeaderValue);
+    }
+    Ok(Some(token))
+}
+
+/// Check if the client finishes the upload with this chunk.
+///
+/// `Upload-Complete` header is `true` or `?1` on the last chunk,
+/// so clients don't have to know the length in advance.
+fn upload_complete(request: &HttpRequest) -> RustusResult<bool> {
+    let header = request
+        .headers()
+        .get("Upload-Complete")
+        .map(HeaderValue::as_bytes);
+    match header {
+        None | Some(b"false" | b"?0") => Ok(false),
+        Some(b"true" | b"?1") => Ok(true),
+        Some(_) => Err(RustusError::WrongHeaderValue),
+    }
+}
+
+/// Get new length of the upload from the request.
+///
+/// `Upload-Length` is parsed only if
+/// the creation-defer-length extension is enabled.
+pub fn updated_length(state: &State, headers: &HeaderMap) -> Option<usize> {
+    if state
+        .config
+        .tus_extensions
+        .contains(&Extensions::CreationDeferLength)
+    {
+        parse_header_value(headers, "Upload-Length")
+    } else {
+        None
+    }
+}
+
+/// Check if chunks of the upload are accepted at arbitrary offsets.
+///
+/// Encrypted uploads are authenticated as a whole,
+/// so their chunks are always appended.
+fn out_of_order(state: &State, file_info: &FileInfo) -> bool {
+    state.data_storage.accepts_out_of_order() && file_info.encryption.is_none()
+}
+
+/// Check if the client restarts unfinished upload from the beginning.
+fn is_restart(state: &State, file_info: &FileInfo, offset: usize) -> bool {
+    state.config.allow_restart
+        && !out_of_order(state, file_info)
+        && offset == 0
+        && file_info.offset > 0
+        && Some(file_info.offset) != file_info.length
+}
+
+/// Check if the chunk can be written to the upload.
+///
+/// Only headers of the request are checked and the upload isn't changed,
+/// so it's done before the body is read and before `100 Continue` is sent.
+///
+/// Returns the response for chunks which are rejected.
+///
+/// # Errors
+///
+/// Returns an error if the upload is finished, new length
+/// of the upload is wrong or the quota can't be read.
+pub async fn check_chunk(
+    state: &State,
+    headers: &HeaderMap,
+    file_info: &FileInfo,
+    offset: usize,
+    updated_len: Option<usize>,
+) -> RustusResult<Option<HttpResponse>> {
+    // Checking if offset from request is the same as the real offset.
+    if !out_of_order(state, file_info)
+        && offset != file_info.offset
+        && !is_restart(state, file_info, offset)
+    {
+        return Ok(Some(HttpResponse::Conflict().finish()));
+    }
+    // Checking if all bytes were already written.
+    if Some(file_info.offset) == file_info.length {
+        return Err(RustusError::FrozenFile);
+    }
+    // If someone want to update file length.
+    // This required by Upload-Defer-Length extension.
+    if let Some(new_len) = updated_len {
+        // Whoop, someone gave us total file length
+        // less that he had already uploaded.
+        if new_len < file_info.offset {
+            return Err(RustusError::WrongOffset);
+        }
+        // We already know the exact size of a file.
+        // Someone want to update it.
+        // Anyway, it's not allowed, heh.
+        if file_info.length.is_some() {
+            return Err(RustusError::SizeAlreadyKnown);
+        }
+    }
+    let chunk_len = parse_header_value::<usize>(headers, "Content-Length");
+    // Chunk can't be written after the end of the upload.
+    if let (Some(length), Some(chunk_len)) = (file_info.length.or(updated_len), chunk_len) {
+        if offset + chunk_len > length {
+            return Ok(Some(
+                HttpResponse::PayloadTooLarge()
+                    .body(format!("Chunk exceeds length of the upload: {length}")),
+            ));
+        }
+    }
+    // Deferred uploads must fit into the quota of their tenant.
+    if file_info.length.is_none() {
+        let end = updated_len.or_else(|| chunk_len.map(|chunk_len| offset + chunk_len));
+        let reserved = end.map_or(0, |end| end.saturating_sub(file_info.offset));
+        if reserved > 0 && !quota::fits(state, file_info, reserved).await? {
+            return Ok(Some(
+                HttpResponse::PayloadTooLarge().body("Quota of the tenant is exceeded."),
+            ));
+        }
+    }
+    Ok(None)
+}
+
+/// Check if the request has a body.
+fn has_body(request: &HttpRequest) -> bool {
+    request.headers().contains_key(TRANSFER_ENCODING)
+        || parse_header::<usize>(request, "Content-Length").is_some_and(|length| length > 0)
+}
+
+/// Bring the upload back to bytes stored before the last chunk.
+///
+/// It's called if stored data doesn't match the finished upload.
+/// The chunk was appended to whatever was stored, so only bytes
+/// before it are kept and the client resumes from them.
+async fn reset_to_stored(
+    state: &State,
+    file_info: &mut FileInfo,
+    chunk_len: usize,
+) -> RustusResult<()> {
+    let stored = state
+        .data_storage
+        .stored_size(file_info)
+        .await?
+        .unwrap_or(file_info.offset);
+    let mut offset = stored
+        .saturating_sub(chunk_len)
+        .min(file_info.offset - chunk_len);
+    if let Some(alignment) = state.data_storage.chunk_alignment() {
+        offset -= offset % alignment;
+    }
+    if let Some(encryption) = &mut file_info.encryption {
+        // MAC of bytes before the chunk is kept,
+        // other uploads are started from the beginning.
+        if offset < file_info.offset - chunk_len {
+            offset = 0;
+            encryption.reset()?;
+        }
+    }
+    file_info.offset = offset;
+    file_info.chunk_tokens.clear();
+    // Data is truncated before offset is saved,
+    // so info never points after the end of the file.
+    state.data_storage.truncate(file_info).await?;
+    state.info_storage.save_info(file_info).await
+}
+
+/// Write a chunk of the upload.
+///
+/// Request is checked before its body is read,
+/// so rejected chunks aren't received at all.
+/// If the body is left unread, the connection is closed
+/// after the response instead of draining the body.
 pub async fn write_bytes(
     request: HttpRequest,
-    bytes: Bytes,
+    payload: web::Payload,
+    state: web::Data<State>,
+    metrics: web::Data<metrics::RustusMetrics>,
+) -> RustusResult<HttpResponse> {
+    let mut payload = Some(payload);
+    let result = write_chunk(&request, &mut payload, state, metrics).await;
+    if payload.is_none() || !has_body(&request) {
+        return result;
+    }
+    let mut response = result.unwrap_or_else(HttpResponse::from_error);
+    response
+        .head_mut()
+        .set_connection_type(ConnectionType::Close);
+    Ok(response)
+}
+
+#[allow(clippy::too_many_lines)]
+async fn write_chunk(
+    request: &HttpRequest,
+    payload: &mut Option<web::Payload>,
     state: web::Data<State>,
     metrics: web::Data<metrics::RustusMetrics>,
 ) -> RustusResult<HttpResponse> {
     // Checking if request has required headers.
-    let check_content_type = |val: &str| val == "application/offset+octet-stream";
-    if !check_header(&request, "Content-Type", check_content_type) {
+    if !is_chunk_content_type(request.headers(), state.config.lenient_content_type) {
         return Ok(HttpResponse::UnsupportedMediaType().body("Unknown content-type."));
     }
     // Getting current offset.
-    let offset: Option<usize> = parse_header(&request, "Upload-Offset");
+    let offset: Option<usize> = parse_header(request, "Upload-Offset");
 
     if offset.is_none() {
         return Ok(HttpResponse::UnsupportedMediaType().body("No offset provided."));
@@ -39,34 +282,28 @@ pub async fn write_bytes(
         return Err(RustusError::FileNotFound);
     }
 
-    if state.config.tus_extensions.contains(&Extensions::Checksum) {
-        if let Some(header) = request.headers().get("Upload-Checksum").cloned() {
-            let cloned_bytes = bytes.clone();
-            if !tokio::task::spawn_blocking(move || {
-                verify_chunk_checksum(&header, cloned_bytes.as_ref())
-            })
-            .await??
-            {
-                return Err(RustusError::WrongChecksum);
-            }
-        }
-    }
-
     // New upload length.
-    // Parses header `Upload-Length` only if the creation-defer-length extension is enabled.
-    let updated_len = if state
+    // Parses headers `Upload-Length` and `Upload-Complete`
+    // only if the creation-defer-length extension is enabled.
+    let defer_length = state
         .config
         .tus_extensions
-        .contains(&Extensions::CreationDeferLength)
-    {
-        parse_header(&request, "Upload-Length")
-    } else {
-        None
-    };
+        .contains(&Extensions::CreationDeferLength);
+    let updated_len = updated_length(&state, request.headers());
+    let complete = defer_length && upload_complete(request)?;
 
     let file_id = request.match_info().get("file_id").unwrap();
+    // Chunk is registered until the response is ready.
+    // Uploads which are being truncated reject all chunks.
+    let _chunk = state
+        .active_chunks
+        .acquire(
+            file_id,
+            state.config.max_concurrent_chunks.unwrap_or(usize::MAX),
+        )
+        .ok_or(RustusError::TooManyChunks)?;
     // Getting file info.
-    let mut file_info = state.info_storage.get_info(file_id).await?;
+    let mut file_info = tombstones::get_info(&state, file_id).await?;
 
     // According to TUS protocol you can't update final uploads.
     if file_info.is_final {
@@ -77,68 +314,240 @@ pub async fn write_bytes(
     if file_info.storage != state.data_storage.to_string() {
         return Err(RustusError::FileNotFound);
     }
-    // Checking if offset from request is the same as the real offset.
-    if offset.unwrap() != file_info.offset {
-        return Ok(HttpResponse::Conflict().finish());
-    }
-
-    // If someone want to update file length.
-    // This required by Upload-Defer-Length extension.
-    if let Some(new_len) = updated_len {
-        // Whoop, someone gave us total file length
-        // less that he had already uploaded.
-        if new_len < file_info.offset {
-            return Err(RustusError::WrongOffset);
+    remove_stale(&state, &file_info).await?;
+    orphans::check_data(&state, &file_info).await?;
+    // Truncation was interrupted, so data has bytes after the offset.
+    durability::finish_truncation(&state, &mut file_info).await?;
+    // Encrypted uploads can't be written without the passphrase.
+    let key = encryption::request_key(request, &file_info).await?;
+    // Retried chunk was already written, so it's only acknowledged.
+    let chunk_token = chunk_token(request.headers(), &state)?;
+    if let Some(token) = chunk_token {
+        if file_info.has_chunk_token(token, chrono::Utc::now()) {
+            return Ok(HttpResponse::NoContent()
+                .insert_header(("Upload-Offset", file_info.offset.to_string()))
+                .insert_header(CacheControl(vec![CacheDirective::NoCache]))
+                .finish());
         }
-        // We already know the exact size of a file.
-        // Someone want to update it.
-        // Anyway, it's not allowed, heh.
-        if file_info.length.is_some() {
-            return Err(RustusError::SizeAlreadyKnown);
+    }
+    let offset = offset.unwrap();
+    // Some storages accept chunks at arbitrary offsets.
+    let out_of_order = out_of_order(&state, &file_info);
+    // Client wants to restart unfinished upload from the beginning.
+    // Upload is reset only in memory until the chunk passes all checks,
+    // so rejected chunks keep the stored upload.
+    let restart = is_restart(&state, &file_info, offset);
+    if restart {
+        file_info.offset = 0;
+        file_info.received.clear();
+        file_info.chunk_tokens.clear();
+        if let Some(encryption) = &mut file_info.encryption {
+            encryption.reset()?;
         }
+    }
+    if let Some(response) =
+        check_chunk(&state, request.headers(), &file_info, offset, updated_len).await?
+    {
+        return Ok(response);
+    }
 
+    let length_unknown = file_info.length.is_none();
+    if let Some(new_len) = updated_len {
         // All checks are ok. Now our file will have exact size.
         file_info.deferred_size = false;
         file_info.length = Some(new_len);
     }
 
+    // Body is read only after the request is checked.
+    let payload = payload.take().ok_or(RustusError::Unknown)?;
+    let bytes = match Bytes::from_request(request, &mut payload.into_inner()).await {
+        Ok(bytes) => bytes,
+        Err(err) => return Ok(err.error_response()),
+    };
+    if state.config.tus_extensions.contains(&Extensions::Checksum) {
+        if let Some(header) = request.headers().get("Upload-Checksum").cloned() {
+            let cloned_bytes = bytes.clone();
+            if !tokio::task::spawn_blocking(move || {
+                verify_chunk_checksum(&header, cloned_bytes.as_ref())
+            })
+            .await??
+            {
+                return Err(RustusError::WrongChecksum);
+            }
+        }
+    }
+    // Upload is finished at the end of this chunk.
+    if complete {
+        let final_len = file_info
+            .received_ranges()
+            .iter()
+            .map(|range| range.end)
+            .fold(offset + bytes.len(), usize::max);
+        match file_info.length {
+            Some(length) if length != final_len => return Err(RustusError::SizeAlreadyKnown),
+            Some(_) => {}
+            None => {
+                file_info.deferred_size = false;
+                file_info.length = Some(final_len);
+            }
+        }
+    }
+
     // Checking if the size of the upload is already equals
     // to calculated offset. It means that all bytes were already written.
-    if Some(file_info.offset) == file_info.length {
+    // Empty chunk may only finish deferred upload at the current offset.
+    let finalized = bytes.is_empty() && length_unknown && file_info.length.is_some();
+    if Some(file_info.offset) == file_info.length && !finalized {
         return Err(RustusError::FrozenFile);
     }
+    // Storage gets only aligned part of the chunk,
+    // the client sends the rest again.
+    let mut bytes = bytes;
+    let mut trimmed = false;
+    if let Some(alignment) = state.data_storage.chunk_alignment() {
+        let aligned = aligned_len(Some(alignment), &file_info, bytes.len());
+        if aligned == 0 && !bytes.is_empty() {
+            return Err(RustusError::UnalignedChunk(alignment));
+        }
+        trimmed = aligned < bytes.len();
+        bytes.truncate(aligned);
+    }
     let chunk_len = bytes.len();
-    // Appending bytes to file.
-    state.data_storage.add_bytes(&file_info, bytes).await?;
-    // bytes.clear()
-    // Updating offset.
-    file_info.offset += chunk_len;
+    // Deferred uploads reserve space of their tenant as they grow.
+    let reserved = if length_unknown {
+        file_info.length.unwrap_or(file_info.offset + chunk_len) - file_info.offset
+    } else {
+        0
+    };
+    if reserved > 0 && !quota::reserve(&state, &file_info, reserved).await? {
+        return Ok(HttpResponse::PayloadTooLarge().body("Quota of the tenant is exceeded."));
+    }
+    if restart {
+        // Data is truncated before offset is reset,
+        // so info never points after the end of the file.
+        let truncated = match state.data_storage.truncate(&file_info).await {
+            Ok(()) => state.info_storage.save_info(&mut file_info).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = truncated {
+            quota::release(&state, &file_info, reserved);
+            return Err(err);
+        }
+    }
+    let (bytes, encrypted) = match (&key, &file_info.encryption) {
+        (Some(key), Some(encryption)) if chunk_len > 0 => {
+            let storage = state.data_storage.as_ref();
+            match encryption
+                .append(storage, &file_info, key, bytes.as_ref())
+                .await
+            {
+                Ok((bytes, encrypted)) => (bytes, Some(encrypted)),
+                Err(err) => {
+                    quota::release(&state, &file_info, reserved);
+                    return Err(err);
+                }
+            }
+        }
+        _ => (bytes, None),
+    };
+    if out_of_order {
+        // Chunk must fit into the file, so the length must be known.
+        match file_info.length {
+            Some(length) if offset + chunk_len <= length => {}
+            _ => return Err(RustusError::WrongOffset),
+        }
+        if file_info.overlaps_received(offset, offset + chunk_len) {
+            return Ok(HttpResponse::Conflict().finish());
+        }
+        let mut chunk_info = file_info.clone();
+        chunk_info.offset = offset;
+        state.data_storage.add_bytes(&chunk_info, bytes).await?;
+        // Other chunks might have been received while
+        // this one was written, so we merge their ranges.
+        let latest_info = state.info_storage.get_info(file_id).await?;
+        file_info.version = latest_info.version;
+        file_info.metadata = latest_info.metadata;
+        for range in latest_info.received {
+            file_info.add_received_range(range.start, range.end);
+        }
+        for token in latest_info.chunk_tokens {
+            if !file_info.chunk_tokens.contains(&token) {
+                file_info.chunk_tokens.push(token);
+            }
+        }
+        file_info.add_received_range(offset, offset + chunk_len);
+    } else {
+        // Appending bytes to file.
+        if let Err(err) = state.data_storage.add_bytes(&file_info, bytes).await {
+            quota::release(&state, &file_info, reserved);
+            return Err(err);
+        }
+        // bytes.clear()
+        // Updating offset.
+        file_info.offset += chunk_len;
+    }
+    if state.config.verify_coverage && file_info.length == Some(file_info.offset) {
+        file_info.check_coverage()?;
+    }
+    if file_info.length == Some(file_info.offset) {
+        if let Err(err) = durability::verify_size(&state, &file_info).await {
+            if !out_of_order {
+                if let Err(err) = reset_to_stored(&state, &mut file_info, chunk_len).await {
+                    log::warn!("Cannot clean up upload {}: {}", file_info.id, err);
+                }
+            }
+            return Err(err);
+        }
+    }
+    // Parameters are updated once bytes are stored,
+    // so reset uploads keep the MAC of the stored bytes.
+    if encrypted.is_some() {
+        file_info.encryption = encrypted;
+    }
+    file_info.updated_at = Some(chrono::Utc::now());
+    // Partially written chunk must be written again if it's retried.
+    if let (Some(token), Some(ttl), false) = (chunk_token, state.config.chunk_token_ttl, trimmed) {
+        file_info.add_chunk_token(token, ttl, chrono::Utc::now());
+    }
     // Saving info to info storage.
-    state.info_storage.set_info(&file_info, false).await?;
+    state.info_storage.save_info(&mut file_info).await?;
+    state.progress.publish(&file_info);
 
     let mut hook = Hook::PostReceive;
 
     if file_info.length == Some(file_info.offset) {
         hook = Hook::PostFinish;
+        durability::sync_finished(&state, &file_info).await?;
+        export::spawn_export(&state, &file_info, key.clone());
+        derivation::spawn_derivation(&state, &file_info, request);
     }
-    if state.config.hook_is_active(hook) {
+    if hook == Hook::PostFinish && state.config.upload_checksum {
+        // Post-finish hook is sent after checksum is computed.
+        checksum::spawn_checksum(&state, &file_info, request, key);
+    } else if state.config.hook_is_active(hook) {
         let message = state.config.notification_opts.hooks_format.format(
-            &request,
+            request,
             &file_info,
-            state.config.notification_opts.behind_proxy,
+            &state.config.client_ip,
         );
         let headers = request.headers().clone();
+        let file_info = file_info.clone();
         tokio::task::spawn_local(async move {
-            state
-                .notification_manager
-                .send_message(message, hook, &headers)
-                .await
+            let manager = &state.notification_manager;
+            if hook == Hook::PostReceive {
+                manager.deliver_receive(message, &file_info, &headers).await;
+            } else {
+                manager
+                    .deliver_upload(message, hook, &file_info, &headers)
+                    .await;
+            }
         });
     }
 
     if hook == Hook::PostFinish {
         metrics.active_uploads.dec();
         metrics.finished_uploads.inc();
+        metrics.observe_finished(&file_info);
     }
 
     Ok(HttpResponse::NoContent()
@@ -149,11 +558,15 @@ pub async fn write_bytes(
 
 #[cfg(test)]
 mod tests {
-    use crate::{server::test::get_service, State};
+    use crate::{
+        server::test::get_service, storages::aligned_storage::AlignedStorage,
+        utils::encryption::Encryption, State,
+    };
     use actix_web::{
-        http::StatusCode,
+        http::{ConnectionType, StatusCode},
         test::{call_service, TestRequest},
     };
+    use bytes::Bytes;
 
     #[actix_rt::test]
     /// Success test for writing bytes.
@@ -234,6 +647,121 @@ mod tests {
         assert_eq!(new_info.length, Some(20));
     }
 
+    #[actix_rt::test]
+    async fn deferred_length_quota() {
+        let mut state = State::test_new().await;
+        state.config.default_tenant_quota = Some(15);
+        let rustus = get_service(state.clone()).await;
+        let mut file = state.create_test_file().await;
+        file.length = None;
+        file.deferred_size = true;
+        file.metadata.insert("tenant".into(), "acme".into());
+        state.info_storage.set_info(&file, false).await.unwrap();
+        let request = |length: usize| {
+            TestRequest::patch()
+                .uri(state.config.file_url(file.id.as_str()).as_str())
+                .insert_header(("Content-Type", "application/offset+octet-stream"))
+                .insert_header(("Upload-Offset", 0))
+                .insert_header(("Upload-Length", length))
+                .set_payload("memes")
+                .to_request()
+        };
+        // Length is counted when it becomes known.
+        let resp = call_service(&rustus, request(20)).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let resp = call_service(&rustus, request(15)).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let mut other = state.create_test_file().await;
+        other.length = None;
+        other.deferred_size = true;
+        other.metadata.insert("tenant".into(), "acme".into());
+        state.info_storage.set_info(&other, false).await.unwrap();
+        let resp = call_service(&rustus, patch_request(&state, other.id.as_str(), 0)).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_rt::test]
+    /// Tests that deferred uploads are finished
+    /// without knowing the length in advance.
+    async fn explicit_completion() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let mut file = state.create_test_file().await;
+        file.length = None;
+        file.deferred_size = true;
+        state.info_storage.set_info(&file, false).await.unwrap();
+        let request = |offset: usize, header: (&'static str, &'static str), data: &'static str| {
+            TestRequest::patch()
+                .uri(state.config.file_url(file.id.as_str()).as_str())
+                .insert_header(("Content-Type", "application/offset+octet-stream"))
+                .insert_header(("Upload-Offset", offset))
+                .insert_header(header)
+                .set_payload(data)
+                .to_request()
+        };
+        let resp = call_service(&rustus, request(0, ("Upload-Complete", "maybe"), "memes")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = call_service(&rustus, request(0, ("Upload-Complete", "?0"), "memes")).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = call_service(&rustus, request(5, ("Upload-Complete", "?1"), "memes")).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(info.offset, 10);
+        assert_eq!(info.length, Some(10));
+        assert!(!info.deferred_size);
+        // Finished upload can't be finished again.
+        let resp = call_service(&rustus, request(10, ("Upload-Complete", "true"), "")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Empty chunk finishes the upload at the current offset.
+        for header in [("Upload-Complete", "true"), ("Upload-Length", "10")] {
+            file.length = None;
+            file.offset = 10;
+            state.info_storage.set_info(&file, false).await.unwrap();
+            let resp = call_service(&rustus, request(10, header, "")).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+            let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+            assert_eq!(info.length, Some(10));
+        }
+    }
+
+    #[actix_rt::test]
+    /// Tests that rejected chunks aren't read.
+    async fn rejected_before_body() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let file = state.create_test_file().await;
+        let request = |offset: usize, data: &'static str| {
+            TestRequest::patch()
+                .uri(state.config.file_url(file.id.as_str()).as_str())
+                .insert_header(("Content-Type", "application/offset+octet-stream"))
+                .insert_header(("Upload-Offset", offset))
+                .insert_header(("Content-Length", data.len()))
+                .set_payload(data)
+                .to_request()
+        };
+        let resp = call_service(&rustus, request(3, "memes")).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            resp.response().head().connection_type(),
+            ConnectionType::Close
+        );
+        let resp = call_service(&rustus, request(0, "memes memes")).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            resp.response().head().connection_type(),
+            ConnectionType::Close
+        );
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(info.offset, 0);
+        let resp = call_service(&rustus, request(0, "memes")).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_ne!(
+            resp.response().head().connection_type(),
+            ConnectionType::Close
+        );
+    }
+
     #[actix_rt::test]
     /// Tests that if new file length
     /// is less than current offset, error is thrown.
@@ -300,6 +828,51 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
     }
 
+    #[actix_rt::test]
+    /// Checks that chunks with wrong content type are rejected.
+    async fn wrong_content_type() {
+        let state = State::test_new().await;
+        let mut rustus = get_service(state.clone()).await;
+        let file = state.create_test_file().await;
+        for content_type in ["application/json", "application/octet-stream"] {
+            let request = TestRequest::patch()
+                .uri(state.config.file_url(file.id.as_str()).as_str())
+                .insert_header(("Upload-Offset", "0"))
+                .insert_header(("Content-Type", content_type))
+                .set_payload("memes")
+                .to_request();
+            let resp = call_service(&mut rustus, request).await;
+            assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+        let file_info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(file_info.offset, 0);
+    }
+
+    #[actix_rt::test]
+    /// Checks that `application/octet-stream` is accepted in lenient mode.
+    async fn lenient_content_type() {
+        let mut state = State::test_new().await;
+        state.config.lenient_content_type = true;
+        let mut rustus = get_service(state.clone()).await;
+        let file = state.create_test_file().await;
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Upload-Offset", "0"))
+            .insert_header(("Content-Type", "application/octet-stream"))
+            .set_payload("memes")
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Upload-Offset", "5"))
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload("memes")
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
     #[actix_rt::test]
     /// Tests that method will return error if no offset header specified.
     async fn no_offset_header() {
@@ -427,6 +1000,621 @@ mod tests {
             .set_payload("memes")
             .to_request();
         let resp = call_service(&mut rustus, request).await;
-        assert_eq!(resp.status(), StatusCode::EXPECTATION_FAILED);
+        assert_eq!(resp.status().as_u16(), 460);
+    }
+
+    #[actix_rt::test]
+    /// Tests that existing uploads can be finished in drain mode.
+    async fn write_while_draining() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let file = state.create_test_file().await;
+        state.set_draining(true);
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Upload-Offset", "0"))
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .set_payload("memes")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[actix_rt::test]
+    /// Tests that unsupported algorithm is rejected with 400.
+    async fn unknown_checksum_algorithm() {
+        let state = State::test_new().await;
+        let mut rustus = get_service(state.clone()).await;
+        let file = state.create_test_file().await;
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Upload-Offset", "0"))
+            .insert_header(("Upload-Checksum", "crc32 K9opmNmw7hl9oUKgRH9nJQ=="))
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .set_payload("memes")
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            resp.headers().get("Tus-Checksum-Algorithm").unwrap(),
+            "md5,sha1,sha256,sha512"
+        );
+        let file = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(file.offset, 0);
+    }
+
+    #[actix_rt::test]
+    /// Tests that checksum which isn't base64 is rejected with 400.
+    async fn malformed_checksum() {
+        let state = State::test_new().await;
+        let mut rustus = get_service(state.clone()).await;
+        let file = state.create_test_file().await;
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Upload-Offset", "0"))
+            .insert_header(("Upload-Checksum", "md5 not-base64!"))
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .set_payload("memes")
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let file = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(file.offset, 0);
+    }
+
+    #[actix_rt::test]
+    async fn export_finished_upload() {
+        let mut state = State::test_new().await;
+        let export_dir = tempdir::TempDir::new("export").unwrap().into_path();
+        state.config.storage_opts.export_dir = Some(export_dir.clone());
+        state.config.storage_opts.export_remove_original = true;
+        let rustus = get_service(state.clone()).await;
+        let mut file = state.create_test_file().await;
+        file.length = Some(5);
+        state.info_storage.set_info(&file, false).await.unwrap();
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .insert_header(("Upload-Offset", 0))
+            .set_payload("memes")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let export_path = export_dir.join(file.id.as_str());
+        for _ in 0..50 {
+            if state.info_storage.get_info(file.id.as_str()).await.is_err() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        assert_eq!(std::fs::read_to_string(export_path).unwrap(), "memes");
+        assert!(!std::path::Path::new(file.path.unwrap().as_str()).exists());
+    }
+
+    /// Finish the upload with exports to S3.
+    ///
+    /// Returns finished upload after its export is done.
+    async fn finish_s3_export(
+        server: &httptest::Server,
+        status: u16,
+    ) -> (State, crate::info_storages::FileInfo) {
+        let mut state = State::test_new().await;
+        state.config.storage_opts.s3_url = Some(format!("http://{}", server.addr()));
+        state.config.storage_opts.s3_region = Some(String::from("us-east-1"));
+        state.config.storage_opts.s3_access_key = Some(String::from("access"));
+        state.config.storage_opts.s3_secret_key = Some(String::from("secret"));
+        state.config.storage_opts.s3_force_path_style = true;
+        state.config.storage_opts.export_s3_bucket = Some(String::from("exports"));
+        state.config.storage_opts.export_retries = 0;
+        state.config.storage_opts.export_remove_original = true;
+        let rustus = get_service(state.clone()).await;
+        let mut file = state.create_test_file().await;
+        file.length = Some(5);
+        state.info_storage.set_info(&file, false).await.unwrap();
+        server.expect(
+            httptest::Expectation::matching(httptest::matchers::all_of![
+                httptest::matchers::request::method_path("PUT", format!("/exports/{}", file.id)),
+                httptest::matchers::request::body("memes"),
+            ])
+            .respond_with(httptest::responders::status_code(status)),
+        );
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .insert_header(("Upload-Offset", 0))
+            .set_payload("memes")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        for _ in 0..50 {
+            if state.info_storage.get_info(file.id.as_str()).await.is_err() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        (state, file)
+    }
+
+    #[actix_rt::test]
+    async fn export_to_s3() {
+        let mut server = httptest::Server::run();
+        let (state, file) = finish_s3_export(&server, 200).await;
+        server.verify_and_clear();
+        assert!(state.info_storage.get_info(file.id.as_str()).await.is_err());
+        assert!(!std::path::Path::new(file.path.unwrap().as_str()).exists());
+    }
+
+    #[actix_rt::test]
+    async fn failed_s3_export() {
+        let mut server = httptest::Server::run();
+        let (state, file) = finish_s3_export(&server, 500).await;
+        server.verify_and_clear();
+        // Original upload is kept if S3 rejected the object.
+        assert!(state.info_storage.get_info(file.id.as_str()).await.is_ok());
+        assert!(std::path::Path::new(file.path.unwrap().as_str()).exists());
+    }
+
+    /// Create upload with some bytes already written.
+    async fn create_started_file(state: &State) -> crate::info_storages::FileInfo {
+        let mut file = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&file, Bytes::from("memes"))
+            .await
+            .unwrap();
+        file.offset = 5;
+        state.info_storage.set_info(&file, false).await.unwrap();
+        file
+    }
+
+    #[actix_rt::test]
+    /// Tests that upload can't be restarted by default.
+    async fn restart_conflict() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let file = create_started_file(&state).await;
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .insert_header(("Upload-Offset", 0))
+            .set_payload("hello")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(info.offset, 5);
+        assert_eq!(
+            std::fs::read_to_string(file.path.unwrap()).unwrap(),
+            "memes"
+        );
+    }
+
+    #[actix_rt::test]
+    /// Tests that upload is truncated when restarts are allowed.
+    async fn restart_upload() {
+        let mut state = State::test_new().await;
+        state.config.allow_restart = true;
+        let rustus = get_service(state.clone()).await;
+        let file = create_started_file(&state).await;
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .insert_header(("Upload-Offset", 0))
+            .set_payload("hey")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            resp.headers()
+                .get("Upload-Offset")
+                .and_then(|header| header.to_str().ok()),
+            Some("3")
+        );
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(info.offset, 3);
+        assert_eq!(std::fs::read_to_string(file.path.unwrap()).unwrap(), "hey");
+    }
+
+    #[actix_rt::test]
+    /// Tests that rejected restart chunks keep the upload.
+    async fn restart_wrong_checksum() {
+        let mut state = State::test_new().await;
+        state.config.allow_restart = true;
+        let rustus = get_service(state.clone()).await;
+        let file = create_started_file(&state).await;
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .insert_header(("Upload-Checksum", "md5 xIwpFX4rNYzBRAJ/Pi2MtA=="))
+            .insert_header(("Upload-Offset", 0))
+            .set_payload("hey")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status().as_u16(), 460);
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(info.offset, file.offset);
+        assert_eq!(
+            std::fs::read_to_string(file.path.unwrap()).unwrap(),
+            "memes"
+        );
+    }
+
+    #[actix_rt::test]
+    /// Tests that interrupted truncation is finished before the chunk is written.
+    async fn interrupted_truncation() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let mut file = create_started_file(&state).await;
+        // Offset was saved, but data wasn't truncated.
+        file.offset = 2;
+        file.truncating = true;
+        state.info_storage.set_info(&file, false).await.unwrap();
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .insert_header(("Upload-Offset", 2))
+            .set_payload("ow")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(info.offset, 4);
+        assert!(!info.truncating);
+        assert_eq!(std::fs::read_to_string(file.path.unwrap()).unwrap(), "meow");
+    }
+
+    #[actix_rt::test]
+    /// Tests that finished uploads can't be restarted.
+    async fn restart_finished_upload() {
+        let mut state = State::test_new().await;
+        state.config.allow_restart = true;
+        let rustus = get_service(state.clone()).await;
+        let mut file = create_started_file(&state).await;
+        file.length = Some(5);
+        state.info_storage.set_info(&file, false).await.unwrap();
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .insert_header(("Upload-Offset", 0))
+            .set_payload("hey")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            std::fs::read_to_string(file.path.unwrap()).unwrap(),
+            "memes"
+        );
+    }
+
+    #[actix_rt::test]
+    /// Tests that checksum is sent in post-finish hook.
+    async fn finished_upload_checksum() {
+        let mut state = State::test_new().await;
+        state.config.upload_checksum = true;
+        state.config.notification_opts.hooks_debug = true;
+        state.notification_manager = crate::NotificationManager::new(&state.config)
+            .await
+            .unwrap();
+        let rustus = get_service(state.clone()).await;
+        let mut file = state.create_test_file().await;
+        file.length = Some(5);
+        state.info_storage.set_info(&file, false).await.unwrap();
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .insert_header(("Upload-Offset", 0))
+            .set_payload("memes")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let notifier = state.notification_manager.debug_notifier().unwrap();
+        for _ in 0..50 {
+            if !notifier.records().is_empty() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        let expected = "064b1b60fa96dc5a7a49f461f1ceb7a0db34d38405f4fb9dea9241ad408a00f6";
+        let records = notifier.records();
+        assert_eq!(records[0].message["upload"]["checksum"], expected);
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(info.checksum.as_deref(), Some(expected));
+    }
+
+    #[actix_rt::test]
+    /// Tests that checksum is absent if it's disabled.
+    async fn finished_upload_without_checksum() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let mut file = state.create_test_file().await;
+        file.length = Some(5);
+        state.info_storage.set_info(&file, false).await.unwrap();
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .insert_header(("Upload-Offset", 0))
+            .set_payload("memes")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert!(info.checksum.is_none());
+        let message = state
+            .config
+            .notification_opts
+            .hooks_format
+            .format_without_request(&info);
+        assert!(!message.contains("checksum"));
+    }
+
+    #[actix_rt::test]
+    /// Tests that orphan information is removed
+    /// if the data of the upload is missing.
+    async fn missing_data() {
+        let mut state = State::test_new().await;
+        state.config.remove_orphan_info = true;
+        let rustus = get_service(state.clone()).await;
+        let file = state.create_test_file().await;
+        std::fs::remove_file(file.path.as_deref().unwrap()).unwrap();
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file.id.as_str()).as_str())
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .insert_header(("Upload-Offset", 0))
+            .set_payload("memes")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::GONE);
+        assert!(state.info_storage.get_info(file.id.as_str()).await.is_err());
+    }
+
+    #[actix_rt::test]
+    /// Tests that chunks over the limit are rejected.
+    async fn too_many_chunks() {
+        let mut state = State::test_new().await;
+        state.config.max_concurrent_chunks = Some(1);
+        let rustus = get_service(state.clone()).await;
+        let file = state.create_test_file().await;
+        let request = || {
+            TestRequest::patch()
+                .uri(state.config.file_url(file.id.as_str()).as_str())
+                .insert_header(("Content-Type", "application/offset+octet-stream"))
+                .insert_header(("Upload-Offset", 0))
+                .set_payload("memes")
+                .to_request()
+        };
+        // Another chunk is being written.
+        let chunk = state.active_chunks.acquire(file.id.as_str(), 1).unwrap();
+        let resp = call_service(&rustus, request()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers().get("Retry-After").unwrap(), "1");
+        drop(chunk);
+        let resp = call_service(&rustus, request()).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(state.active_chunks.count(file.id.as_str()), 0);
+    }
+
+    fn patch_request(state: &State, file_id: &str, offset: usize) -> actix_http::Request {
+        TestRequest::patch()
+            .uri(state.config.file_url(file_id).as_str())
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .insert_header(("Upload-Offset", offset))
+            .set_payload("memes")
+            .to_request()
+    }
+
+    #[actix_rt::test]
+    async fn stale_upload() {
+        let mut state = State::test_new().await;
+        state.config.max_resume_age = Some(3600);
+        let rustus = get_service(state.clone()).await;
+        let mut file_info = state.create_test_file().await;
+        // Recent writes keep old uploads alive.
+        file_info.created_at -= chrono::Duration::hours(2);
+        file_info.updated_at = Some(chrono::Utc::now() - chrono::Duration::minutes(10));
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let resp = call_service(&rustus, patch_request(&state, file_info.id.as_str(), 0)).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let mut file_info = state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .unwrap();
+        assert!(file_info.updated_at.is_some());
+
+        file_info.updated_at = Some(chrono::Utc::now() - chrono::Duration::hours(2));
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let resp = call_service(&rustus, patch_request(&state, file_info.id.as_str(), 5)).await;
+        assert_eq!(resp.status(), StatusCode::GONE);
+        assert!(state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .is_err());
+        assert!(!std::path::Path::new(file_info.path.unwrap().as_str()).exists());
+    }
+
+    #[actix_rt::test]
+    async fn stale_upload_without_limit() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let mut file_info = state.create_test_file().await;
+        file_info.created_at -= chrono::Duration::days(30);
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let resp = call_service(&rustus, patch_request(&state, file_info.id.as_str(), 0)).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[actix_rt::test]
+    async fn verified_coverage() {
+        let mut state = State::test_new().await;
+        state.config.verify_coverage = true;
+        let rustus = get_service(state.clone()).await;
+        let file = create_started_file(&state).await;
+        let resp = call_service(&rustus, patch_request(&state, file.id.as_str(), 5)).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(info.offset, 10);
+    }
+
+    #[actix_rt::test]
+    async fn coverage_gap() {
+        let mut state = State::test_new().await;
+        state.config.verify_coverage = true;
+        let rustus = get_service(state.clone()).await;
+        let mut file = create_started_file(&state).await;
+        file.add_received_range(0, 2);
+        file.add_received_range(3, 5);
+        file.offset = 5;
+        state.info_storage.set_info(&file, false).await.unwrap();
+        let resp = call_service(&rustus, patch_request(&state, file.id.as_str(), 5)).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let body = actix_web::test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("missing [2, 3)"));
+        // Upload isn't finished.
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(info.offset, 5);
+    }
+
+    #[actix_rt::test]
+    async fn verified_size_mismatch() {
+        let mut state = State::test_new().await;
+        state.config.verify_finished_size = true;
+        let rustus = get_service(state.clone()).await;
+        // Only 3 of 5 received bytes were stored.
+        let mut file = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&file, Bytes::from("mem"))
+            .await
+            .unwrap();
+        file.offset = 5;
+        state.info_storage.set_info(&file, false).await.unwrap();
+        let resp = call_service(&rustus, patch_request(&state, file.id.as_str(), 5)).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        // Upload is resumed from bytes which were really stored.
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(info.offset, 3);
+        let stored = std::fs::read(info.path.as_deref().unwrap()).unwrap();
+        assert_eq!(stored, b"mem");
+        let resp = call_service(&rustus, patch_request(&state, file.id.as_str(), 3)).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(info.offset, 8);
+    }
+
+    #[actix_rt::test]
+    async fn encrypted_without_passphrase() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let mut file = state.create_test_file().await;
+        let (encryption, _) = Encryption::generate(String::from("memes"), 10)
+            .await
+            .unwrap();
+        file.encryption = Some(encryption);
+        state.info_storage.set_info(&file, false).await.unwrap();
+        for passphrase in [None, Some("not memes")] {
+            let mut request = TestRequest::patch()
+                .uri(state.config.file_url(file.id.as_str()).as_str())
+                .insert_header(("Content-Type", "application/offset+octet-stream"))
+                .insert_header(("Upload-Offset", file.offset))
+                .set_payload("memes");
+            if let Some(passphrase) = passphrase {
+                request = request.insert_header(("Upload-Passphrase", passphrase));
+            }
+            let resp = call_service(&rustus, request.to_request()).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+        let file = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(file.offset, 0);
+    }
+
+    #[actix_rt::test]
+    async fn retried_chunk() {
+        let mut state = State::test_new().await;
+        state.config.chunk_token_ttl = Some(60);
+        let rustus = get_service(state.clone()).await;
+        let file = state.create_test_file().await;
+        let chunk = |offset: usize, token: &str| {
+            TestRequest::patch()
+                .uri(state.config.file_url(file.id.as_str()).as_str())
+                .insert_header(("Content-Type", "application/offset+octet-stream"))
+                .insert_header(("Upload-Offset", offset))
+                .insert_header(("Idempotency-Key", token))
+                .set_payload("memes")
+                .to_request()
+        };
+        let resp = call_service(&rustus, chunk(0, "first")).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        // Retry is acknowledged with the current offset.
+        let resp = call_service(&rustus, chunk(0, "first")).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            resp.headers()
+                .get("Upload-Offset")
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "5"
+        );
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(info.offset, 5);
+        // Other keys are written as usual.
+        let resp = call_service(&rustus, chunk(0, "second")).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let resp = call_service(&rustus, chunk(5, "second")).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        // Finished upload still acknowledges retries.
+        let resp = call_service(&rustus, chunk(5, "second")).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = call_service(&rustus, chunk(0, &"a".repeat(200))).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let data = std::fs::read(file.path.unwrap()).unwrap();
+        assert_eq!(data.as_slice(), b"memesmemes");
+    }
+
+    #[actix_rt::test]
+    async fn aligned_chunks() {
+        let mut state = State::test_new().await;
+        state.data_storage = Box::new(AlignedStorage::new(state.data_storage.clone(), 4));
+        let rustus = get_service(state.clone()).await;
+        let mut file = state.create_test_file().await;
+        file.length = Some(10);
+        state.info_storage.set_info(&file, false).await.unwrap();
+        let request = |offset: usize, data: &'static str| {
+            TestRequest::patch()
+                .uri(state.config.file_url(file.id.as_str()).as_str())
+                .insert_header(("Content-Type", "application/offset+octet-stream"))
+                .insert_header(("Upload-Offset", offset))
+                .set_payload(data)
+                .to_request()
+        };
+        // Only aligned bytes are written and counted.
+        let resp = call_service(&rustus, request(0, "memes")).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(resp.headers().get("Upload-Offset").unwrap(), "4");
+        // Chunk doesn't reach the next boundary.
+        let resp = call_service(&rustus, request(4, "s,")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        // The last chunk is written as is.
+        let resp = call_service(&rustus, request(4, "s, inc")).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(resp.headers().get("Upload-Offset").unwrap(), "10");
+        let info = state.info_storage.get_info(file.id.as_str()).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(info.path.unwrap()).unwrap(),
+            "memes, inc"
+        );
     }
 }
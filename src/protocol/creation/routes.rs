@@ -3,12 +3,22 @@ use base64::{engine::general_purpose, Engine};
 use std::collections::HashMap;
 
 use crate::{
+    background::{assembly, checksum, derivation, export},
+    errors::RustusError,
     info_storages::FileInfo,
     metrics,
     notifiers::Hook,
-    protocol::extensions::Extensions,
-    utils::headers::{check_header, parse_header},
-    State,
+    protocol::{extensions::Extensions, upload_location},
+    storages::aligned_len,
+    utils::{
+        durability,
+        encryption::{self, Encryption},
+        headers::{check_header, is_chunk_content_type, parse_header},
+        metadata, owner, pre_create, quota,
+        rejections::{self, Reason},
+        unique::{self, Uniqueness},
+    },
+    RustusResult, State,
 };
 
 /// Get metadata info from request.
@@ -33,8 +43,9 @@ fn get_metadata(request: &HttpRequest) -> Option<HashMap<String, String>> {
             let mut meta_map = HashMap::new();
             for meta_pair in header_string.split(',') {
                 let mut split = meta_pair.trim().split(' ');
-                let key = split.next();
-                let b64val = split.next();
+                let key = split.next().filter(|key| !key.is_empty());
+                // Keys without values have empty values.
+                let b64val = split.next().or(key.map(|_| ""));
                 if key.is_none() || b64val.is_none() {
                     continue;
                 }
@@ -52,6 +63,21 @@ fn get_metadata(request: &HttpRequest) -> Option<HashMap<String, String>> {
         })
 }
 
+/// Get metadata info from query parameters.
+///
+/// Values aren't encoded with base64, E.G.
+/// `?filename=memes.png&category=memes`
+///
+/// Returns `None` if the query is empty.
+fn get_query_metadata(request: &HttpRequest) -> Option<HashMap<String, String>> {
+    if request.query_string().is_empty() {
+        return None;
+    }
+    web::Query::<HashMap<String, String>>::from_query(request.query_string())
+        .ok()
+        .map(web::Query::into_inner)
+}
+
 fn get_upload_parts(request: &HttpRequest) -> Vec<String> {
     let concat_header = request.headers().get("Upload-Concat").unwrap();
     let header_str = concat_header.to_str().unwrap();
@@ -63,6 +89,55 @@ fn get_upload_parts(request: &HttpRequest) -> Vec<String> {
         .collect()
 }
 
+/// Check that tenant has enough space for the new upload.
+///
+/// Returns response if the quota is exceeded.
+async fn check_quota(
+    state: &State,
+    meta: Option<&HashMap<String, String>>,
+    length: Option<usize>,
+) -> RustusResult<Option<HttpResponse>> {
+    let tenant_key = state.config.tenant_metadata_key.as_str();
+    if let Some(tenant) = meta.and_then(|meta| meta.get(tenant_key)) {
+        if let Some(quota) = state.config.tenant_quota(tenant) {
+            let usage = quota::cached_usage(state, tenant).await?;
+            if usage + length.unwrap_or_default() > quota {
+                return Ok(Some(rejections::reject(
+                    HttpResponse::PayloadTooLarge(),
+                    Reason::QuotaExceeded,
+                    format!("Quota of tenant {tenant} is exceeded."),
+                )));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Check length of the upload patched by pre-create hook.
+///
+/// Returns response if the upload can't be created.
+fn check_patched(
+    state: &State,
+    file_info: &FileInfo,
+    with_upload: bool,
+    received: usize,
+) -> Option<HttpResponse> {
+    let length = file_info.length?;
+    if let Some(max_file_size) = state.config.max_file_size {
+        if length > max_file_size {
+            return Some(rejections::reject(
+                HttpResponse::BadRequest(),
+                Reason::TooLarge,
+                format!("Upload-Length should be less than or equal to {max_file_size}"),
+            ));
+        }
+    }
+    if with_upload && received > length {
+        return Some(HttpResponse::BadRequest().body("Request body exceeds Upload-Length."));
+    }
+    None
+}
+
 /// Create file.
 ///
 /// This method allows you to create file to start uploading.
@@ -71,28 +146,100 @@ fn get_upload_parts(request: &HttpRequest) -> Vec<String> {
 /// you don't know actual file length and
 /// you can upload first bytes if creation-with-upload
 /// extension is enabled.
-#[allow(clippy::too_many_lines)]
 pub async fn create_file(
     metrics: web::Data<metrics::RustusMetrics>,
     state: web::Data<State>,
     request: HttpRequest,
     bytes: Bytes,
 ) -> actix_web::Result<HttpResponse> {
+    let result = create(metrics, state.clone(), request.clone(), bytes).await;
+    rejections::notify(&state, &request, &result);
+    result
+}
+
+/// Remove data of the upload which failed to be created
+/// and return its reserved space.
+async fn abort(state: &State, file_info: &FileInfo, reserved: usize) {
+    if let Err(err) = state.data_storage.remove_file(file_info).await {
+        log::warn!("Cannot clean up upload {}: {}", file_info.id, err);
+    }
+    quota::release(state, file_info, reserved);
+}
+
+#[allow(clippy::too_many_lines)]
+async fn create(
+    metrics: web::Data<metrics::RustusMetrics>,
+    state: web::Data<State>,
+    request: HttpRequest,
+    bytes: Bytes,
+) -> actix_web::Result<HttpResponse> {
+    // New uploads aren't accepted during maintenance.
+    if state.is_draining() {
+        return Err(RustusError::Draining(state.config.drain_retry_after).into());
+    }
     // Getting Upload-Length header value as usize.
     let length = parse_header(&request, "Upload-Length");
 
+    let mut meta = get_metadata(&request);
+    if meta.is_none() && state.config.metadata_from_query {
+        meta = get_query_metadata(&request);
+    }
+    let mut meta = meta.map(|meta| {
+        metadata::transform(
+            meta,
+            state.config.metadata_normalizers.as_slice(),
+            state.config.metadata_aliases.as_slice(),
+        )
+    });
+    if let Some(meta) = &mut meta {
+        derivation::strip_derived_from(meta);
+    }
+    // Owner is set by the server, so clients can't list uploads of others.
+    if state.config.owners_enabled() {
+        owner::assign(
+            &state.config,
+            &request,
+            meta.get_or_insert_with(HashMap::new),
+        );
+    }
+    // Clients can't set or override server metadata.
+    if !state.config.server_metadata.is_empty() {
+        metadata::assign_server_metadata(
+            state.config.server_metadata.as_slice(),
+            meta.get_or_insert_with(HashMap::new),
+        );
+    }
+    rejections::attempt(&request, length, meta.as_ref());
+    if let Some(key) = meta
+        .as_ref()
+        .and_then(|meta| metadata::mismatched_key(meta, state.config.metadata_patterns.as_slice()))
+    {
+        return Ok(rejections::reject(
+            HttpResponse::BadRequest(),
+            Reason::InvalidMetadata,
+            format!("Metadata value of `{key}` isn't allowed."),
+        ));
+    }
+
     // With this option enabled,
     // we have to check whether length is a non-zero number.
     if !state.config.allow_empty {
         if let Some(0) = length {
-            return Ok(HttpResponse::BadRequest().body("Upload-Length should be greater than zero"));
+            return Ok(rejections::reject(
+                HttpResponse::BadRequest(),
+                Reason::EmptyUpload,
+                String::from("Upload-Length should be greater than zero"),
+            ));
         }
     }
 
     if let Some(max_file_size) = state.config.max_file_size {
         if Some(max_file_size) < length {
-            return Ok(HttpResponse::BadRequest()
-                .body(format!("Upload-Length should be less than or equal to {}", max_file_size)));
+            return Ok(rejections::reject(
+                HttpResponse::BadRequest(),
+                Reason::TooLarge,
+                format!("Upload-Length should be less than or equal to {max_file_size}"),
+            ));
         }
     }
 
@@ -116,10 +263,40 @@ pub async fn create_file(
     // Otherwise checking that defer-size feature is enabled
     // and header provided.
     if length.is_none() && !((defer_ext && defer_size) || (concat_ext && is_final)) {
-        return Ok(HttpResponse::BadRequest().body("Upload-Length header is required"));
+        return Ok(rejections::reject(
+            HttpResponse::BadRequest(),
+            Reason::LengthRequired,
+            String::from("Upload-Length header is required"),
+        ));
+    }
+
+    // Checking creation-with-upload extension.
+    let with_upload = state
+        .config
+        .tus_extensions
+        .contains(&Extensions::CreationWithUpload);
+
+    // Received bytes must fit into the declared length.
+    if with_upload && length.is_some_and(|length| bytes.len() > length) {
+        return Ok(HttpResponse::BadRequest().body("Request body exceeds Upload-Length."));
+    }
+
+    let is_partial = check_header(&request, "Upload-Concat", |val| val == "partial");
+
+    let passphrase = encryption::get_passphrase(&request);
+    if passphrase.is_some() {
+        if !state.config.passphrase_encryption {
+            return Ok(HttpResponse::BadRequest().body("Encryption with passphrases is disabled."));
+        }
+        if concat_ext && is_final {
+            return Ok(HttpResponse::BadRequest().body("Final uploads can't be encrypted."));
+        }
     }
 
-    let meta = get_metadata(&request);
+    // Checking that tenant has enough space for the new upload.
+    if let Some(response) = check_quota(&state, meta.as_ref(), length).await? {
+        return Ok(response);
+    }
 
     let file_id = uuid::Uuid::new_v4().to_string();
     let mut file_info = FileInfo::new(
@@ -129,11 +306,24 @@ pub async fn create_file(
         state.data_storage.to_string(),
         meta,
     );
-
-    let is_partial = check_header(&request, "Upload-Concat", |val| val == "partial");
+    // Only parameters of the key are stored,
+    // the key itself is used to encrypt first bytes.
+    let mut key = None;
+    if let Some(passphrase) = passphrase {
+        let (params, upload_key) =
+            Encryption::generate(passphrase, state.config.passphrase_iterations).await?;
+        file_info.encryption = Some(params);
+        key = Some(upload_key);
+    }
 
     if concat_ext {
         if is_final {
+            // Length of the final upload is
+            // the sum of lengths of its parts.
+            if length.is_some() {
+                return Ok(HttpResponse::BadRequest()
+                    .body("Upload-Length must not be set for final uploads."));
+            }
             file_info.is_final = true;
             let upload_parts = get_upload_parts(&request);
             if upload_parts.is_empty() {
@@ -148,36 +338,84 @@ pub async fn create_file(
         }
     }
 
-    if state.config.hook_is_active(Hook::PreCreate) {
-        let message = state.config.notification_opts.hooks_format.format(
-            &request,
-            &file_info,
-            state.config.notification_opts.behind_proxy,
-        );
-        let headers = request.headers();
-        state
-            .notification_manager
-            .send_message(message, Hook::PreCreate, headers)
-            .await?;
+    pre_create::send(&state, &request, &mut file_info).await?;
+    // Upload patched by the hook must satisfy the same limits.
+    if let Some(response) = check_patched(&state, &file_info, with_upload, bytes.len()) {
+        return Ok(response);
     }
 
-    // Create file and get the it's path.
-    file_info.path = Some(state.data_storage.create_file(&file_info).await?);
-
-    // Incrementing number of active uploads
+    // Callback URL may come from patched metadata.
+    if let Some(key) = &state.config.notification_opts.hooks_callback_meta_key {
+        if let Some(url) = file_info.metadata.get(key) {
+            state
+                .notification_manager
+                .callback_policy()
+                .resolve(url)
+                .await
+                .map_err(RustusError::InvalidCallbackUrl)?;
+            file_info.callback_url = Some(url.clone());
+        }
+    }
 
-    metrics.active_uploads.inc();
-    metrics.started_uploads.inc();
+    // Unique key may come from patched metadata as well.
+    // Its value stays locked until the upload is saved.
+    let _unique = match unique::check(&state, &file_info).await? {
+        Uniqueness::Unique(guard) => guard,
+        Uniqueness::Duplicate(response) => return Ok(response),
+    };
 
-    if let Some(length) = file_info.length {
-        #[allow(clippy::cast_precision_loss)]
-        metrics.upload_sizes.observe(length as f64);
+    // Finished upload with the same intent is returned
+    // instead of creating a duplicate. It's looked up only
+    // after the client is allowed to create the upload.
+    file_info.dedup_key = match file_info.length {
+        Some(length)
+            if !state.config.dedup_metadata_keys.is_empty()
+                && !file_info.is_final
+                && !file_info.is_partial
+                && file_info.encryption.is_none() =>
+        {
+            metadata::dedup_key(
+                state.config.dedup_metadata_keys.as_slice(),
+                &file_info.metadata,
+                length,
+            )
+        }
+        _ => None,
+    };
+    if let Some(key) = &file_info.dedup_key {
+        let owner_key = state.config.owner_metadata_key.as_str();
+        let existing = state
+            .info_storage
+            .find_by_dedup_key(key)
+            .await?
+            // Uploads of other owners are never returned.
+            .filter(|existing| {
+                existing.metadata.get(owner_key) == file_info.metadata.get(owner_key)
+            });
+        if let Some(existing) = existing {
+            let location = upload_location(&state, &request, existing.id.as_str())?;
+            return Ok(HttpResponse::Ok()
+                .insert_header(("Location", location))
+                .insert_header(("Upload-Offset", existing.offset.to_string()))
+                .finish());
+        }
     }
 
+    // Parts are checked before anything is reserved or created,
+    // so rejected final uploads leave nothing behind.
+    let mut parts = None;
     if file_info.is_final {
         let mut final_size = 0;
         let mut parts_info = Vec::new();
+        // Parts can't be removed by cleanup until they are concatenated.
+        let mut parts_guards = Vec::new();
         for part_id in file_info.clone().parts.unwrap() {
+            let Some(guard) = state.active_chunks.acquire(part_id.as_str(), usize::MAX) else {
+                return Ok(
+                    HttpResponse::Conflict().body(format!("{part_id} upload is being changed."))
+                );
+            };
+            parts_guards.push(guard);
             let part = state.info_storage.get_info(part_id.as_str()).await?;
             if part.length != Some(part.offset) {
                 return Ok(
@@ -189,91 +427,191 @@ pub async fn create_file(
                     HttpResponse::BadRequest().body(format!("{} upload is not partial.", part.id))
                 );
             }
+            if part.encryption.is_some() {
+                return Ok(
+                    HttpResponse::BadRequest().body(format!("{} upload is encrypted.", part.id))
+                );
+            }
             final_size += &part.length.unwrap();
             parts_info.push(part.clone());
         }
-        state
-            .data_storage
-            .concat_files(&file_info, parts_info.clone())
-            .await?;
-        file_info.offset = final_size;
-        file_info.length = Some(final_size);
-        if state.config.remove_parts {
-            for part in parts_info {
-                state.data_storage.remove_file(&part).await?;
-                state.info_storage.remove_info(part.id.as_str()).await?;
-            }
+        parts = Some((final_size, parts_info, parts_guards));
+    }
+
+    // Space is reserved right before the upload is created,
+    // so concurrent uploads can't exceed the quota together.
+    // Deferred uploads reserve more space when they grow.
+    let reserved = file_info.length.unwrap_or(bytes.len());
+    if !quota::reserve(&state, &file_info, reserved).await? {
+        let tenant = &file_info.metadata[&state.config.tenant_metadata_key];
+        return Ok(rejections::reject(
+            HttpResponse::PayloadTooLarge(),
+            Reason::QuotaExceeded,
+            format!("Quota of tenant {tenant} is exceeded."),
+        ));
+    }
+
+    // Create file and get the it's path.
+    if let Err(err) = state.data_storage.create_upload(&mut file_info).await {
+        quota::release(&state, &file_info, reserved);
+        return Err(err.into());
+    }
+
+    // Incrementing number of active uploads
+
+    metrics.active_uploads.inc();
+    metrics.started_uploads.inc();
+
+    if let Some(length) = file_info.length {
+        #[allow(clippy::cast_precision_loss)]
+        metrics.upload_sizes.observe(length as f64);
+    }
+
+    let mut assembly = None;
+    if let Some((final_size, parts_info, parts_guards)) = parts {
+        if state.config.async_concat {
+            // Parts are concatenated after the response.
+            file_info.length = Some(final_size);
+            file_info.assembling = true;
+            assembly = Some((parts_info, parts_guards));
+        } else if let Err(err) = assembly::concat_parts(&state, &mut file_info, parts_info).await {
+            abort(&state, &file_info, reserved).await;
+            return Err(err.into());
         }
     }
 
-    // Checking if creation-with-upload extension is enabled.
-    let with_upload = state
-        .config
-        .tus_extensions
-        .contains(&Extensions::CreationWithUpload);
-    if with_upload && !bytes.is_empty() && !(concat_ext && is_final) {
-        let octet_stream = |val: &str| val == "application/offset+octet-stream";
-        if check_header(&request, "Content-Type", octet_stream) {
-            // Writing first bytes.
-            let chunk_len = bytes.len();
-            // Appending bytes to file.
-            state.data_storage.add_bytes(&file_info, bytes).await?;
-            // Updating offset.
-            file_info.offset += chunk_len;
+    // Only aligned part of first bytes is written,
+    // the client sends the rest again.
+    let bytes = bytes.slice(
+        ..aligned_len(
+            state.data_storage.chunk_alignment(),
+            &file_info,
+            bytes.len(),
+        ),
+    );
+    if with_upload
+        && !bytes.is_empty()
+        && !(concat_ext && is_final)
+        && is_chunk_content_type(request.headers(), state.config.lenient_content_type)
+    {
+        // Writing first bytes.
+        let chunk_len = bytes.len();
+        let (bytes, encrypted) = match (&key, &file_info.encryption) {
+            (Some(key), Some(encryption)) => {
+                let storage = state.data_storage.as_ref();
+                match encryption
+                    .append(storage, &file_info, key, bytes.as_ref())
+                    .await
+                {
+                    Ok((bytes, encrypted)) => (bytes, Some(encrypted)),
+                    Err(err) => {
+                        abort(&state, &file_info, reserved).await;
+                        return Err(err.into());
+                    }
+                }
+            }
+            _ => (bytes, None),
+        };
+        // Appending bytes to file.
+        if let Err(err) = state.data_storage.add_bytes(&file_info, bytes).await {
+            abort(&state, &file_info, reserved).await;
+            return Err(err.into());
+        }
+        // Updating offset.
+        file_info.offset += chunk_len;
+        if encrypted.is_some() {
+            file_info.encryption = encrypted;
+        }
+        if Some(file_info.offset) == file_info.length
+            && durability::verify_size(&state, &file_info).await.is_err()
+        {
+            // Upload is created without the first chunk,
+            // so the client can send it again.
+            file_info.offset = 0;
+            if let Some(encryption) = &mut file_info.encryption {
+                encryption.reset()?;
+            }
+            if let Err(err) = state.data_storage.truncate(&file_info).await {
+                log::warn!("Cannot clean up upload {}: {}", file_info.id, err);
+            }
         }
     }
 
-    state.info_storage.set_info(&file_info, true).await?;
+    if let Err(err) = state.info_storage.set_info(&file_info, true).await {
+        abort(&state, &file_info, reserved).await;
+        return Err(err.into());
+    }
+
+    // Create upload URL for this file.
+    let location = upload_location(&state, &request, file_info.id.as_str())?;
 
     // It's more intuitive to send post-finish
     // hook, when final upload is created.
     // https://github.com/s3rius/rustus/issues/77
     let mut post_hook = Hook::PostCreate;
-    if file_info.is_final || Some(file_info.offset) == file_info.length {
+    if let Some((parts_info, parts_guards)) = assembly {
+        // Post-finish hook is sent after the upload is assembled.
+        assembly::spawn_assembly(
+            &state,
+            &metrics,
+            &request,
+            &file_info,
+            parts_info,
+            parts_guards,
+        );
+    } else if file_info.is_final || Some(file_info.offset) == file_info.length {
         post_hook = Hook::PostFinish;
+        durability::sync_finished(&state, &file_info).await?;
+        metrics.observe_finished(&file_info);
+        export::spawn_export(&state, &file_info, key.clone());
+        derivation::spawn_derivation(&state, &file_info, &request);
     }
 
-    if state.config.hook_is_active(post_hook) {
+    if post_hook == Hook::PostFinish && state.config.upload_checksum {
+        // Post-finish hook is sent after checksum is computed.
+        checksum::spawn_checksum(&state, &file_info, &request, key);
+    } else if state.config.hook_is_active(post_hook) {
         let message = state.config.notification_opts.hooks_format.format(
             &request,
             &file_info,
-            state.config.notification_opts.behind_proxy,
+            &state.config.client_ip,
         );
         let headers = request.headers().clone();
+        let file_info = file_info.clone();
+        let state = state.clone();
         // Adding send_message task to tokio reactor.
         // Thin function would be executed in background.
         tokio::task::spawn_local(async move {
             state
                 .notification_manager
-                .send_message(message, post_hook, &headers)
-                .await
+                .deliver_upload(message, post_hook, &file_info, &headers)
+                .await;
         });
     }
 
-    // Create upload URL for this file.
-    let upload_url = request.url_for("core:write_bytes", [file_info.id.clone()])?;
-
-    Ok(HttpResponse::Created()
-        .insert_header((
-            "Location",
-            upload_url
-                .as_str()
-                .strip_suffix('/')
-                .unwrap_or(upload_url.as_str()),
-        ))
+    Ok(HttpResponse::build(state.config.creation_status)
+        .insert_header(("Location", location))
         .insert_header(("Upload-Offset", file_info.offset.to_string()))
         .finish())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{server::test::get_service, State};
+    use crate::{
+        notifiers::Hook,
+        server::test::get_service,
+        storages::file_storage::FileStorage,
+        utils::metadata::{MetadataAlias, MetadataNormalizer, MetadataPattern, ServerMetadata},
+        NotificationManager, State,
+    };
     use actix_web::{
-        http::StatusCode,
+        http::{Method, StatusCode},
         test::{call_service, TestRequest},
         web,
     };
     use base64::{engine::general_purpose, Engine};
+    use chrono::Datelike;
+    use std::str::FromStr;
 
     #[actix_rt::test]
     async fn success() {
@@ -300,6 +638,77 @@ mod tests {
         assert_eq!(file_info.offset, 0);
     }
 
+    #[actix_rt::test]
+    async fn creation_status() {
+        for status in [StatusCode::OK, StatusCode::CREATED, StatusCode::NO_CONTENT] {
+            let mut state = State::test_new().await;
+            state.config.creation_status = status;
+            let rustus = get_service(state.clone()).await;
+            let request = TestRequest::post()
+                .uri(state.config.test_url().as_str())
+                .insert_header(("Upload-Length", 100))
+                .to_request();
+            let resp = call_service(&rustus, request).await;
+            assert_eq!(resp.status(), status);
+            // Headers don't depend on the status.
+            assert!(resp.headers().contains_key("Location"));
+            assert_eq!(resp.headers().get("Upload-Offset").unwrap(), "0");
+            assert_eq!(resp.headers().get("Tus-Resumable").unwrap(), "1.0.0");
+        }
+    }
+
+    #[actix_rt::test]
+    async fn draining() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        state.set_draining(true);
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers().get("Retry-After").unwrap(), "60");
+        state.set_draining(false);
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+
+    #[actix_rt::test]
+    async fn relative_location() {
+        let mut state = State::test_new().await;
+        state.config.relative_location = true;
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let location = resp.headers().get("Location").unwrap().to_str().unwrap();
+        assert!(location.starts_with(state.config.test_url().as_str()));
+    }
+
+    #[actix_rt::test]
+    async fn absolute_location_forwarded() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header(("X-Forwarded-Host", "uploads.example.com"))
+            .insert_header(("X-Forwarded-Proto", "https"))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let location = resp.headers().get("Location").unwrap().to_str().unwrap();
+        assert!(location.starts_with("https://uploads.example.com/"));
+    }
+
     #[actix_rt::test]
     async fn wrong_length() {
         let state = State::test_new().await;
@@ -353,6 +762,19 @@ mod tests {
         assert_eq!(file_info.offset, test_data.len());
     }
 
+    #[actix_rt::test]
+    async fn passphrase_encryption_disabled() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header(("Upload-Passphrase", "memes"))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
     #[actix_rt::test]
     async fn with_bytes_wrong_content_type() {
         let state = State::test_new().await;
@@ -406,6 +828,103 @@ mod tests {
         assert!(file_info.deferred_size);
     }
 
+    #[actix_rt::test]
+    async fn required_length() {
+        let mut state = State::test_new().await;
+        state.config.require_length = true;
+        state.config.normalize_extentions();
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Defer-Length", "1"))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        // Deferred length isn't advertised.
+        let request = TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri(state.config.test_url().as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        let extensions = resp
+            .headers()
+            .get("Tus-Extension")
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(extensions.contains("creation"));
+        assert!(!extensions.contains("creation-defer-length"));
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+
+    #[actix_rt::test]
+    async fn create_rejected() {
+        let mut state = State::test_new().await;
+        state.config.max_file_size = Some(10);
+        state.config.notification_opts.hooks = vec![Hook::CreateRejected];
+        state.config.notification_opts.hooks_debug = true;
+        state.notification_manager = NotificationManager::new(&state.config).await.unwrap();
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header(("Upload-Metadata", "filename bWVtZXM="))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        // Created uploads aren't reported.
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 10))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let records = state
+            .notification_manager
+            .debug_notifier()
+            .unwrap()
+            .records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].hook, "create-rejected");
+        let message = &records[0].message;
+        assert_eq!(message["rejection"]["reason"], "too-large");
+        assert_eq!(message["rejection"]["status"], 400);
+        assert_eq!(message["upload"]["length"], 100);
+        assert_eq!(message["upload"]["metadata"]["filename"], "memes");
+    }
+
+    #[actix_rt::test]
+    async fn create_rejected_limit() {
+        let mut state = State::test_new().await;
+        state.config.max_file_size = Some(10);
+        state.config.notification_opts.hooks = vec![Hook::CreateRejected];
+        state.config.notification_opts.hooks_debug = true;
+        state.config.notification_opts.hooks_rejected_rate = 2;
+        state.notification_manager = NotificationManager::new(&state.config).await.unwrap();
+        let rustus = get_service(state.clone()).await;
+        for _ in 0..5 {
+            let request = TestRequest::post()
+                .uri(state.config.test_url().as_str())
+                .insert_header(("Upload-Length", 100))
+                .to_request();
+            let resp = call_service(&rustus, request).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let records = state
+            .notification_manager
+            .debug_notifier()
+            .unwrap()
+            .records();
+        assert_eq!(records.len(), 2);
+    }
+
     #[actix_rt::test]
     async fn success_partial_upload() {
         let state = State::test_new().await;
@@ -452,7 +971,6 @@ mod tests {
 
         let request = TestRequest::post()
             .uri(state.config.test_url().as_str())
-            .insert_header(("Upload-Length", 100))
             .insert_header((
                 "Upload-Concat",
                 format!("final;/files/{} /files/{}", part1.id, part2.id),
@@ -476,41 +994,153 @@ mod tests {
     }
 
     #[actix_rt::test]
-    async fn invalid_final_upload_no_parts() {
-        let state = State::test_new().await;
+    async fn async_final_upload() {
+        let mut state = State::test_new().await;
+        state.config.async_concat = true;
+        state.config.upload_checksum = true;
         let mut rustus = get_service(state.clone()).await;
+        let mut part1 = state.create_test_file().await;
+        let mut part2 = state.create_test_file().await;
+        for part in [&mut part1, &mut part2] {
+            part.is_partial = true;
+            part.length = Some(100);
+            part.offset = 100;
+            state.info_storage.set_info(part, false).await.unwrap();
+        }
 
         let request = TestRequest::post()
             .uri(state.config.test_url().as_str())
-            .insert_header(("Upload-Length", 100))
-            .insert_header(("Upload-Concat", "final;"))
+            .insert_header((
+                "Upload-Concat",
+                format!("final;/files/{} /files/{}", part1.id, part2.id),
+            ))
             .to_request();
         let resp = call_service(&mut rustus, request).await;
-        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers().get("Upload-Offset").unwrap(), "0");
+        let location = resp.headers().get("Location").unwrap().to_str().unwrap();
+        let item_id = location.split('/').last().unwrap();
+
+        // Upload isn't finished until it's assembled with its checksum.
+        let mut finished = false;
+        for _ in 0..100 {
+            let request = TestRequest::with_uri(state.config.file_url(item_id).as_str())
+                .method(Method::HEAD)
+                .to_request();
+            let resp = call_service(&mut rustus, request).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers().get("Upload-Length").unwrap(), "200");
+            if resp.headers().get("Upload-Offset").unwrap() == "200" {
+                finished = true;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(finished);
+        let file_info = state.info_storage.get_info(item_id).await.unwrap();
+        assert!(!file_info.assembling);
+        assert!(file_info.checksum.is_some());
     }
 
     #[actix_rt::test]
-    async fn success_with_metadata() {
+    async fn invalid_final_upload_leaves_nothing() {
         let state = State::test_new().await;
-        let mut rustus = get_service(state.clone()).await;
+        let rustus = get_service(state.clone()).await;
+        let mut part1 = state.create_test_file().await;
+        part1.is_partial = true;
+        part1.offset = 10;
+        state.info_storage.set_info(&part1, false).await.unwrap();
+        // Second part isn't complete.
+        let mut part2 = state.create_test_file().await;
+        part2.is_partial = true;
+        state.info_storage.set_info(&part2, false).await.unwrap();
+        let files = || {
+            std::fs::read_dir(&state.config.storage_opts.data_dir)
+                .unwrap()
+                .count()
+        };
+        let before = files();
+
         let request = TestRequest::post()
             .uri(state.config.test_url().as_str())
-            .insert_header(("Upload-Length", 100))
             .insert_header((
-                "Upload-Metadata",
-                format!(
-                    "test {}, pest {}",
-                    general_purpose::STANDARD.encode("data1"),
-                    general_purpose::STANDARD.encode("data2")
-                ),
+                "Upload-Concat",
+                format!("final;/files/{} /files/{}", part1.id, part2.id),
             ))
             .to_request();
-        let resp = call_service(&mut rustus, request).await;
-        assert_eq!(resp.status(), StatusCode::CREATED);
-        // Getting file from location header.
-        let item_id = resp
-            .headers()
-            .get("Location")
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(files(), before);
+    }
+
+    #[actix_rt::test]
+    async fn invalid_final_upload_no_parts() {
+        let state = State::test_new().await;
+        let mut rustus = get_service(state.clone()).await;
+
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Concat", "final;"))
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn invalid_final_upload_with_length() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let mut part = state.create_test_file().await;
+        part.is_partial = true;
+        part.offset = 10;
+        state.info_storage.set_info(&part, false).await.unwrap();
+
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 10))
+            .insert_header(("Upload-Concat", format!("final;/files/{}", part.id)))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn invalid_partial_upload_body_exceeds_length() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 3))
+            .insert_header(("Upload-Concat", "partial"))
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .set_payload("memes")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn success_with_metadata() {
+        let state = State::test_new().await;
+        let mut rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header((
+                "Upload-Metadata",
+                format!(
+                    "test {}, pest {}",
+                    general_purpose::STANDARD.encode("data1"),
+                    general_purpose::STANDARD.encode("data2")
+                ),
+            ))
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        // Getting file from location header.
+        let item_id = resp
+            .headers()
+            .get("Location")
             .unwrap()
             .to_str()
             .unwrap()
@@ -524,6 +1154,228 @@ mod tests {
         assert_eq!(file_info.offset, 0);
     }
 
+    #[actix_rt::test]
+    async fn owner_from_header() {
+        let mut state = State::test_new().await;
+        state.config.owner_header = Some(String::from("X-Auth-Subject"));
+        state.config.client_ip.trusted_proxies = vec!["10.0.0.0/8".parse().unwrap()];
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header((
+                "Upload-Metadata",
+                format!("owner {}", general_purpose::STANDARD.encode("user-2")),
+            ))
+            .insert_header(("X-Auth-Subject", "user-1"))
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let item_id = resp
+            .headers()
+            .get("Location")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split('/')
+            .last()
+            .unwrap();
+        let file_info = state.info_storage.get_info(item_id).await.unwrap();
+        // Owner sent by the client is replaced.
+        assert_eq!(file_info.metadata.get("owner").unwrap(), "user-1");
+    }
+
+    #[actix_rt::test]
+    async fn derived_from_stripped() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header((
+                "Upload-Metadata",
+                format!(
+                    "derived_from {},filename {}",
+                    general_purpose::STANDARD.encode("other"),
+                    general_purpose::STANDARD.encode("a.txt")
+                ),
+            ))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let item_id = resp
+            .headers()
+            .get("Location")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split('/')
+            .last()
+            .unwrap();
+        let file_info = state.info_storage.get_info(item_id).await.unwrap();
+        assert!(!file_info.metadata.contains_key("derived_from"));
+        assert_eq!(file_info.metadata.get("filename").unwrap(), "a.txt");
+    }
+
+    #[actix_rt::test]
+    async fn server_metadata() {
+        let mut state = State::test_new().await;
+        state.config.server_metadata = vec![
+            ServerMetadata::from_str("origin=node-1").unwrap(),
+            ServerMetadata::from_str("received_year={year}").unwrap(),
+        ];
+        let mut rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header((
+                "Upload-Metadata",
+                format!(
+                    "filename {}, origin {}",
+                    general_purpose::STANDARD.encode("memes.png"),
+                    general_purpose::STANDARD.encode("forged")
+                ),
+            ))
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let location = resp.headers().get("Location").unwrap().to_str().unwrap();
+        let item_id = location.split('/').last().unwrap();
+        let file_info = state.info_storage.get_info(item_id).await.unwrap();
+        assert_eq!(file_info.metadata.get("filename").unwrap(), "memes.png");
+        // Value sent by the client is replaced.
+        assert_eq!(file_info.metadata.get("origin").unwrap(), "node-1");
+        assert_eq!(
+            file_info.metadata.get("received_year").unwrap(),
+            &file_info.created_at.year().to_string()
+        );
+        let request = TestRequest::with_uri(state.config.file_url(item_id).as_str())
+            .method(Method::HEAD)
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        let metadata = resp.headers().get("Upload-Metadata").unwrap();
+        assert_eq!(
+            metadata.to_str().unwrap(),
+            file_info.get_metadata_string().unwrap()
+        );
+    }
+
+    #[actix_rt::test]
+    async fn metadata_transformation() {
+        let mut state = State::test_new().await;
+        state.config.metadata_normalizers = vec![
+            MetadataNormalizer::LowercaseKeys,
+            MetadataNormalizer::TrimValues,
+        ];
+        state.config.metadata_aliases = vec![MetadataAlias::from_str("name=filename").unwrap()];
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header((
+                "Upload-Metadata",
+                format!(
+                    "Name {}, Type {}",
+                    general_purpose::STANDARD.encode(" memes.png "),
+                    general_purpose::STANDARD.encode("image/png")
+                ),
+            ))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let item_id = resp
+            .headers()
+            .get("Location")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split('/')
+            .last()
+            .unwrap();
+        let file_info = state.info_storage.get_info(item_id).await.unwrap();
+        assert_eq!(file_info.metadata.get("filename").unwrap(), "memes.png");
+        assert_eq!(file_info.metadata.get("type").unwrap(), "image/png");
+        assert_eq!(file_info.metadata.len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn metadata_patterns() {
+        let mut state = State::test_new().await;
+        state.config.metadata_patterns =
+            vec![MetadataPattern::from_str(r"filename=[\w.-]+").unwrap()];
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header((
+                "Upload-Metadata",
+                format!("filename {}", general_purpose::STANDARD.encode("../passwd")),
+            ))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body = actix_web::test::read_body(resp).await;
+        assert!(String::from_utf8_lossy(&body).contains("filename"));
+
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header((
+                "Upload-Metadata",
+                format!("filename {}", general_purpose::STANDARD.encode("memes.png")),
+            ))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+
+    #[actix_rt::test]
+    async fn callback_url() {
+        let mut state = State::test_new().await;
+        state.config.notification_opts.hooks_callback_meta_key = Some(String::from("callback"));
+        let mut rustus = get_service(state.clone()).await;
+        for url in ["http://8.8.8.8/hook", "https://127.0.0.1/hook"] {
+            let request = TestRequest::post()
+                .uri(state.config.test_url().as_str())
+                .insert_header(("Upload-Length", 100))
+                .insert_header((
+                    "Upload-Metadata",
+                    format!("callback {}", general_purpose::STANDARD.encode(url)),
+                ))
+                .to_request();
+            let resp = call_service(&mut rustus, request).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{url}");
+        }
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header((
+                "Upload-Metadata",
+                format!(
+                    "callback {}",
+                    general_purpose::STANDARD.encode("https://8.8.8.8/hook")
+                ),
+            ))
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let item_id = resp
+            .headers()
+            .get("Location")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split('/')
+            .last()
+            .unwrap();
+        let file_info = state.info_storage.get_info(item_id).await.unwrap();
+        assert_eq!(
+            file_info.callback_url.as_deref(),
+            Some("https://8.8.8.8/hook")
+        );
+    }
+
     #[actix_rt::test]
     async fn success_with_metadata_wrong_encoding() {
         let state = State::test_new().await;
@@ -569,6 +1421,37 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     }
 
+    #[actix_rt::test]
+    async fn tenant_quota_exceeded() {
+        let mut state = State::test_new().await;
+        state.config.default_tenant_quota = Some(150);
+        let mut file_info = state.create_test_file().await;
+        file_info.length = Some(100);
+        file_info.metadata.insert("tenant".into(), "acme".into());
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let rustus = get_service(state.clone()).await;
+        let metadata = format!("tenant {}", general_purpose::STANDARD.encode("acme"));
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header(("Upload-Metadata", metadata.as_str()))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 50))
+            .insert_header(("Upload-Metadata", metadata.as_str()))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+
     #[actix_rt::test]
     async fn max_file_size_exceeded() {
         let mut state = State::test_new().await;
@@ -581,4 +1464,373 @@ mod tests {
         let resp = call_service(&mut rustus, request).await;
         assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     }
+
+    /// Create state with pre-create hook returning the given response.
+    async fn patching_state(response: &str) -> (State, httptest::Server) {
+        let server = httptest::Server::run();
+        server.expect(
+            httptest::Expectation::matching(httptest::matchers::request::method_path(
+                "POST", "/hook",
+            ))
+            .respond_with(httptest::responders::status_code(200).body(String::from(response))),
+        );
+        let mut state = State::test_new().await;
+        state.config.notification_opts.hooks = vec![crate::notifiers::Hook::PreCreate];
+        state.config.notification_opts.hooks_http_urls = vec![server.url_str("/hook")];
+        state.config.notification_opts.hooks_pre_create_patch = true;
+        state.notification_manager = crate::NotificationManager::new(&state.config)
+            .await
+            .unwrap();
+        (state, server)
+    }
+
+    #[actix_rt::test]
+    async fn pre_create_patch() {
+        let (state, _server) =
+            patching_state(r#"{"metadata": {"owner": "pepe", "secret": null}, "length": 50}"#)
+                .await;
+        let rustus = get_service(state.clone()).await;
+        let metadata = format!("secret {}", general_purpose::STANDARD.encode("value"));
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Defer-Length", "1"))
+            .insert_header(("Upload-Metadata", metadata.as_str()))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let item_id = resp
+            .headers()
+            .get("Location")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split('/')
+            .last()
+            .unwrap();
+        let file_info = state.info_storage.get_info(item_id).await.unwrap();
+        assert_eq!(file_info.length, Some(50));
+        assert!(!file_info.deferred_size);
+        assert_eq!(file_info.metadata.get("owner").unwrap(), "pepe");
+        assert!(!file_info.metadata.contains_key("secret"));
+    }
+
+    #[actix_rt::test]
+    async fn pre_create_reject() {
+        let (state, _server) = patching_state(r#"{"reject": true, "reason": "Nope"}"#).await;
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn pre_create_patch_exceeds_max_size() {
+        let (mut state, _server) = patching_state(r#"{"length": 1001}"#).await;
+        state.config.max_file_size = Some(1000);
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn pre_create_invalid_patch() {
+        let (state, _server) = patching_state("OK").await;
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn metadata_from_query() {
+        let mut state = State::test_new().await;
+        state.config.metadata_from_query = true;
+        state.config.metadata_normalizers = vec![MetadataNormalizer::LowercaseKeys];
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(
+                format!(
+                    "{}?Filename=memes.png&category=funny%20memes",
+                    state.config.test_url()
+                )
+                .as_str(),
+            )
+            .insert_header(("Upload-Length", 100))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let item_id = resp
+            .headers()
+            .get("Location")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split('/')
+            .last()
+            .unwrap();
+        let file_info = state.info_storage.get_info(item_id).await.unwrap();
+        assert_eq!(file_info.metadata.get("filename").unwrap(), "memes.png");
+        assert_eq!(file_info.metadata.get("category").unwrap(), "funny memes");
+        assert_eq!(file_info.metadata.len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn metadata_header_over_query() {
+        let mut state = State::test_new().await;
+        state.config.metadata_from_query = true;
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(format!("{}?filename=query.png", state.config.test_url()).as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header((
+                "Upload-Metadata",
+                format!("name {}", general_purpose::STANDARD.encode("header.png")),
+            ))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        let item_id = resp
+            .headers()
+            .get("Location")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split('/')
+            .last()
+            .unwrap();
+        let file_info = state.info_storage.get_info(item_id).await.unwrap();
+        assert_eq!(file_info.metadata.get("name").unwrap(), "header.png");
+        assert!(file_info.metadata.get("filename").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn metadata_from_query_disabled() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(format!("{}?filename=memes.png", state.config.test_url()).as_str())
+            .insert_header(("Upload-Length", 100))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        let item_id = resp
+            .headers()
+            .get("Location")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split('/')
+            .last()
+            .unwrap();
+        let file_info = state.info_storage.get_info(item_id).await.unwrap();
+        assert!(file_info.metadata.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn path_template() {
+        let mut state = State::test_new().await;
+        state.data_storage = Box::new(
+            FileStorage::new(
+                state.config.storage_opts.data_dir.clone(),
+                String::new(),
+                false,
+            )
+            .with_path_template(String::from("{meta:filename}")),
+        );
+        let rustus = get_service(state.clone()).await;
+        let create = |filename: &str| {
+            TestRequest::post()
+                .uri(state.config.test_url().as_str())
+                .insert_header(("Upload-Length", 100))
+                .insert_header((
+                    "Upload-Metadata",
+                    format!("filename {}", general_purpose::STANDARD.encode(filename)),
+                ))
+                .to_request()
+        };
+        let resp = call_service(&rustus, create("report.pdf")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let item_id = resp
+            .headers()
+            .get("Location")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split('/')
+            .last()
+            .unwrap();
+        let file_info = state.info_storage.get_info(item_id).await.unwrap();
+        assert!(file_info.path.unwrap().ends_with("/report.pdf"));
+        // Path is taken by the first upload.
+        let resp = call_service(&rustus, create("report.pdf")).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let resp = call_service(&rustus, create("..")).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn headers_too_large() {
+        let mut state = State::test_new().await;
+        state.config.max_header_size = 1024;
+        let mut rustus = get_service(state.clone()).await;
+        let metadata = general_purpose::STANDARD.encode("a".repeat(1024));
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 100))
+            .insert_header(("Upload-Metadata", format!("name {metadata}")))
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        let reason = String::from_utf8(body.to_vec()).unwrap();
+        assert!(reason.contains("upload-metadata"), "{reason}");
+        assert!(state.info_storage.list_info().await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn dedup() {
+        let mut state = State::test_new().await;
+        state.config.dedup_metadata_keys = vec![String::from("sha")];
+        let rustus = get_service(state.clone()).await;
+        let create = |meta: &str| {
+            TestRequest::post()
+                .uri(state.config.test_url().as_str())
+                .insert_header(("Upload-Length", 10))
+                .insert_header(("Upload-Metadata", meta.to_string()))
+                .to_request()
+        };
+        let sha = format!("sha {}", general_purpose::STANDARD.encode("abc"));
+        let resp = call_service(&rustus, create(sha.as_str())).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let location = resp.headers().get("Location").unwrap().clone();
+        let file_id = location.to_str().unwrap().rsplit('/').next().unwrap();
+        // Unfinished uploads aren't reused.
+        let resp = call_service(&rustus, create(sha.as_str())).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_ne!(resp.headers().get("Location").unwrap(), &location);
+
+        let mut file_info = state.info_storage.get_info(file_id).await.unwrap();
+        file_info.offset = 10;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let resp = call_service(&rustus, create(sha.as_str())).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("Location").unwrap(), &location);
+        assert_eq!(resp.headers().get("Upload-Offset").unwrap(), "10");
+        // Uploads without the key are never deduplicated.
+        let name = format!("name {}", general_purpose::STANDARD.encode("abc"));
+        let resp = call_service(&rustus, create(name.as_str())).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(state.info_storage.list_info().await.unwrap().len(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn dedup_of_other_owner() {
+        let mut state = State::test_new().await;
+        state.config.dedup_metadata_keys = vec![String::from("sha")];
+        state.config.owner_header = Some(String::from("X-Owner"));
+        state.config.client_ip.behind_proxy = true;
+        let rustus = get_service(state.clone()).await;
+        let create = |owner: &str| {
+            TestRequest::post()
+                .uri(state.config.test_url().as_str())
+                .insert_header(("Upload-Length", 10))
+                .insert_header((
+                    "Upload-Metadata",
+                    format!("sha {}", general_purpose::STANDARD.encode("abc")),
+                ))
+                .insert_header(("X-Owner", owner))
+                .to_request()
+        };
+        let resp = call_service(&rustus, create("first")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let location = resp.headers().get("Location").unwrap().clone();
+        let file_id = location.to_str().unwrap().rsplit('/').next().unwrap();
+        let mut file_info = state.info_storage.get_info(file_id).await.unwrap();
+        file_info.offset = 10;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let resp = call_service(&rustus, create("second")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let resp = call_service(&rustus, create("first")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("Location").unwrap(), &location);
+    }
+
+    #[actix_rt::test]
+    async fn unique_metadata() {
+        let mut state = State::test_new().await;
+        state.config.unique_metadata_key = Some(String::from("filename"));
+        state.config.unique_metadata_scope = Some(String::from("tenant"));
+        let rustus = get_service(state.clone()).await;
+        let create = |tenant: &str| {
+            let meta = format!(
+                "filename {},tenant {}",
+                general_purpose::STANDARD.encode("memes.txt"),
+                general_purpose::STANDARD.encode(tenant)
+            );
+            TestRequest::post()
+                .uri(state.config.test_url().as_str())
+                .insert_header(("Upload-Length", 10))
+                .insert_header(("Upload-Metadata", meta))
+                .to_request()
+        };
+        let resp = call_service(&rustus, create("first")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let location = resp.headers().get("Location").unwrap().clone();
+        let file_id = location.to_str().unwrap().rsplit('/').next().unwrap();
+        // Unfinished duplicates are allowed by default.
+        let resp = call_service(&rustus, create("first")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let mut file_info = state.info_storage.get_info(file_id).await.unwrap();
+        file_info.offset = 10;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let resp = call_service(&rustus, create("first")).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        // Other tenants may use the same name.
+        let resp = call_service(&rustus, create("second")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(state.info_storage.list_info().await.unwrap().len(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn unique_unfinished_metadata() {
+        let mut state = State::test_new().await;
+        state.config.unique_metadata_key = Some(String::from("filename"));
+        state.config.unique_reject_unfinished = true;
+        let rustus = get_service(state.clone()).await;
+        let create = || {
+            TestRequest::post()
+                .uri(state.config.test_url().as_str())
+                .insert_header(("Upload-Length", 10))
+                .insert_header((
+                    "Upload-Metadata",
+                    format!("filename {}", general_purpose::STANDARD.encode("memes.txt")),
+                ))
+                .to_request()
+        };
+        let resp = call_service(&rustus, create()).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let resp = call_service(&rustus, create()).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+    }
 }
@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+
+use actix_web::{
+    http::header::{CacheControl, CacheDirective},
+    web, HttpRequest, HttpResponse,
+};
+use serde::Serialize;
+
+use crate::{
+    background::checksum,
+    errors::RustusError,
+    info_storages::{models::file_info::ByteRange, FileInfo},
+    utils::{
+        encryption::{self, UploadKey},
+        orphans, signature,
+    },
+    RustusResult, State,
+};
+
+use super::routes::check_signature;
+
+/// Description of a finished upload.
+///
+/// Fields are always serialized in the same order
+/// and metadata is sorted, so the same upload
+/// always has the same manifest.
+#[derive(Serialize, Debug)]
+pub struct Manifest {
+    pub id: String,
+    pub length: usize,
+    /// Unix timestamp of creation.
+    pub created_at: i64,
+    /// Hex encoded sha256 checksum of the contents.
+    pub sha256: String,
+    /// Received ranges of bytes.
+    pub chunks: Vec<ByteRange>,
+    /// Size of blocks the storage writes data in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_size: Option<usize>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Compute sha256 checksum of the contents.
+///
+/// Checksum computed after the upload was finished is reused,
+/// since it's computed for the original contents.
+/// Computed checksum is saved in the information,
+/// so the upload is hashed only once.
+async fn content_checksum(
+    state: &State,
+    file_info: &FileInfo,
+    key: Option<UploadKey>,
+) -> RustusResult<String> {
+    if let Some(checksum) = &file_info.checksum {
+        return Ok(checksum.clone());
+    }
+    let checksum = checksum::compute(state, file_info, key.as_ref()).await?;
+    let mut hashed = file_info.clone();
+    hashed.checksum = Some(checksum.clone());
+    checksum::save(state, &hashed).await;
+    Ok(checksum)
+}
+
+/// Build manifest of the finished upload.
+///
+/// Encrypted uploads are hashed after decryption,
+/// so the checksum is computed for the original contents.
+pub async fn build_manifest(
+    state: &State,
+    file_info: &FileInfo,
+    key: Option<UploadKey>,
+) -> RustusResult<Manifest> {
+    let Some(length) = file_info
+        .length
+        .filter(|length| *length == file_info.offset)
+    else {
+        return Err(RustusError::IncompleteUpload(format!(
+            "received {} bytes",
+            file_info.offset
+        )));
+    };
+    Ok(Manifest {
+        id: file_info.id.clone(),
+        length,
+        created_at: file_info.created_at.timestamp(),
+        sha256: content_checksum(state, file_info, key).await?,
+        chunks: file_info.received_ranges(),
+        block_size: state.data_storage.chunk_alignment(),
+        metadata: file_info
+            .metadata
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+    })
+}
+
+/// Get manifest of the finished upload.
+///
+/// Manifest is protected the same way as downloads:
+/// it requires a signed URL if signing is enabled and
+/// the passphrase for encrypted uploads.
+pub async fn get_manifest(
+    request: HttpRequest,
+    state: web::Data<State>,
+) -> RustusResult<HttpResponse> {
+    let file_id = request
+        .match_info()
+        .get("file_id")
+        .ok_or(RustusError::FileNotFound)?;
+    check_signature(&state, &request, file_id)?;
+    let file_info = state.info_storage.get_info(file_id).await?;
+    if file_info.storage != state.data_storage.to_string() {
+        return Err(RustusError::FileNotFound);
+    }
+    orphans::check_data(&state, &file_info).await?;
+    let key = encryption::request_key(&request, &file_info).await?;
+    let manifest = build_manifest(&state, &file_info, key).await?;
+    let body = serde_json::to_vec(&manifest)?;
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type("application/json")
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]));
+    if let Some(secret) = &state.config.manifest_signing_secret {
+        response.insert_header((
+            "Rustus-Manifest-Signature",
+            signature::sign_document(secret, body.as_slice()),
+        ));
+    }
+    Ok(response.body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_manifest;
+    use crate::{
+        info_storages::FileInfo,
+        server::test::get_service,
+        storages::aligned_storage::AlignedStorage,
+        utils::{encryption::Encryption, signature},
+        State,
+    };
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, read_body, read_body_json, TestRequest},
+    };
+    use bytes::Bytes;
+    use serde_json::Value;
+
+    async fn finished_upload(state: &State) -> FileInfo {
+        let mut file_info = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&file_info, Bytes::from("0123456789"))
+            .await
+            .unwrap();
+        file_info.offset = 10;
+        file_info.metadata.insert("b".into(), "2".into());
+        file_info.metadata.insert("a".into(), "1".into());
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        file_info
+    }
+
+    fn manifest_url(state: &State, file_id: &str) -> String {
+        format!("{}manifest", state.config.file_url(file_id))
+    }
+
+    #[actix_rt::test]
+    async fn signed_manifest() {
+        let mut state = State::test_new().await;
+        state.config.manifest_signing_secret = Some(String::from("secret"));
+        let rustus = get_service(state.clone()).await;
+        let file_info = finished_upload(&state).await;
+        let request = TestRequest::get()
+            .uri(manifest_url(&state, file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let header = resp
+            .headers()
+            .get("Rustus-Manifest-Signature")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = read_body(resp).await;
+        assert_eq!(header, signature::sign_document("secret", body.as_ref()));
+        let manifest: Value = serde_json::from_slice(body.as_ref()).unwrap();
+        assert_eq!(manifest["id"], file_info.id.as_str());
+        assert_eq!(manifest["length"], 10);
+        assert_eq!(
+            manifest["sha256"],
+            "84d89877f0d4041efb6bf91a16f0248f2fd573e6af05c19f96bedb9f882f7882"
+        );
+        assert_eq!(
+            manifest["chunks"],
+            serde_json::json!([{"start": 0, "end": 10}])
+        );
+        let keys = manifest["metadata"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["a", "b"]);
+        // Checksum is saved, so the upload isn't hashed again.
+        let info = state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .unwrap();
+        assert_eq!(
+            info.checksum.as_ref(),
+            manifest["sha256"].as_str().map(String::from).as_ref()
+        );
+        // Manifest of the same upload is the same.
+        let request = TestRequest::get()
+            .uri(manifest_url(&state, file_info.id.as_str()).as_str())
+            .to_request();
+        let second = read_body(call_service(&rustus, request).await).await;
+        assert_eq!(body, second);
+    }
+
+    #[actix_rt::test]
+    async fn protected_manifest() {
+        let mut state = State::test_new().await;
+        state.config.download_signing_secret = Some(String::from("secret"));
+        let rustus = get_service(state.clone()).await;
+        let mut file_info = finished_upload(&state).await;
+        let url = manifest_url(&state, file_info.id.as_str());
+        let request = TestRequest::get().uri(url.as_str()).to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        let (encryption, key) = Encryption::generate(String::from("memes"), 10)
+            .await
+            .unwrap();
+        file_info.offset = 0;
+        let (encrypted, encryption) = encryption
+            .append(state.data_storage.as_ref(), &file_info, &key, b"0123456789")
+            .await
+            .unwrap();
+        std::fs::write(file_info.path.as_deref().unwrap(), encrypted).unwrap();
+        file_info.offset = 10;
+        file_info.encryption = Some(encryption);
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let expires = chrono::Utc::now().timestamp() + 60;
+        let signed_url = format!(
+            "{url}?expires={expires}&signature={}",
+            signature::sign("secret", file_info.id.as_str(), expires)
+        );
+        let request = TestRequest::get().uri(signed_url.as_str()).to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let request = TestRequest::get()
+            .uri(signed_url.as_str())
+            .insert_header(("Upload-Passphrase", "memes"))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        // Checksum is computed for decrypted contents.
+        let manifest: Value = read_body_json(resp).await;
+        assert_eq!(
+            manifest["sha256"],
+            "84d89877f0d4041efb6bf91a16f0248f2fd573e6af05c19f96bedb9f882f7882"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn aligned_manifest() {
+        let mut state = State::test_new().await;
+        let file_info = finished_upload(&state).await;
+        state.data_storage = Box::new(AlignedStorage::new(state.data_storage.clone(), 4));
+        let manifest = build_manifest(&state, &file_info, None).await.unwrap();
+        assert_eq!(manifest.block_size, Some(4));
+    }
+
+    #[actix_rt::test]
+    async fn unfinished_manifest() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let file_info = state.create_test_file().await;
+        let request = TestRequest::get()
+            .uri(manifest_url(&state, file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+    }
+}
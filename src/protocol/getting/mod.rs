@@ -1,5 +1,6 @@
 use actix_web::{guard, web};
 
+mod manifest;
 mod routes;
 
 /// Add getting extension.
@@ -17,4 +18,11 @@ pub fn add_extension(web_app: &mut web::ServiceConfig) {
             .guard(guard::Get())
             .to(routes::get_file),
     );
+    web_app.service(
+        // GET /base/file/manifest
+        web::resource("/{file_id}/manifest/")
+            .name("getting:manifest")
+            .guard(guard::Get())
+            .to(manifest::get_manifest),
+    );
 }
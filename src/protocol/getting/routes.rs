@@ -1,18 +1,318 @@
-use actix_web::{web, HttpRequest, HttpResponse};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
 
-use crate::{errors::RustusError, RustusResult, State};
+use actix_files::HttpRange;
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    http::{
+        header::{
+            CacheControl, CacheDirective, HeaderValue, TryIntoHeaderPair, CONTENT_RANGE, RANGE,
+        },
+        StatusCode,
+    },
+    web, HttpRequest, HttpResponse,
+};
+use bytes::Bytes;
+
+use crate::{
+    background::retention::{self, RetentionPolicy},
+    errors::RustusError,
+    info_storages::FileInfo,
+    utils::{
+        encryption::{self, UploadKey},
+        orphans,
+        signature::{self, DownloadSignature},
+    },
+    RustusResult, State,
+};
+
+/// Cache directives for the download of an upload.
+///
+/// Finished uploads never change, so they can be cached
+/// if max-age is configured. Unfinished, partial
+/// and encrypted uploads are never cached.
+///
+/// Downloads by signed URLs and downloads of uploads which
+/// are removed after download aren't cached either, otherwise
+/// caches would serve them after the URL expires or the upload is removed.
+fn cache_control(state: &State, file_info: &FileInfo) -> Option<CacheControl> {
+    let finished = !file_info.is_partial && file_info.length == Some(file_info.offset);
+    if !finished
+        || file_info.encryption.is_some()
+        || state.config.download_signing_secret.is_some()
+        || RetentionPolicy::for_upload(state, file_info).is_some()
+    {
+        return Some(CacheControl(vec![CacheDirective::NoStore]));
+    }
+    let max_age = state.config.download_max_age?;
+    let visibility = if state.config.download_cache_private {
+        CacheDirective::Private
+    } else {
+        CacheDirective::Public
+    };
+    Some(CacheControl(vec![
+        visibility,
+        CacheDirective::MaxAge(max_age),
+        CacheDirective::Extension(String::from("immutable"), None),
+    ]))
+}
+
+/// Body which has only received bytes of the requested range.
+///
+/// Storages may have bytes after the offset,
+/// E.G. if chunks are received out of order.
+/// Bytes before the range are skipped.
+struct ReceivedBody {
+    inner: BoxBody,
+    skip: u64,
+    remaining: u64,
+}
+
+impl MessageBody for ReceivedBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        match self.inner.size() {
+            BodySize::Sized(size) => {
+                BodySize::Sized(size.saturating_sub(self.skip).min(self.remaining))
+            }
+            size => size,
+        }
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        loop {
+            if self.remaining == 0 {
+                return Poll::Ready(None);
+            }
+            match Pin::new(&mut self.inner).poll_next(cx) {
+                Poll::Ready(Some(Ok(mut bytes))) => {
+                    let skipped = usize::try_from(self.skip)
+                        .unwrap_or(usize::MAX)
+                        .min(bytes.len());
+                    self.skip -= skipped as u64;
+                    let mut bytes = bytes.split_off(skipped);
+                    if bytes.is_empty() {
+                        continue;
+                    }
+                    let remaining = usize::try_from(self.remaining).unwrap_or(usize::MAX);
+                    bytes.truncate(remaining);
+                    self.remaining -= bytes.len() as u64;
+                    return Poll::Ready(Some(Ok(bytes)));
+                }
+                poll => return poll,
+            }
+        }
+    }
+}
+
+/// Find the requested range of received bytes.
+///
+/// Only the first range is served, like for finished uploads.
+///
+/// # Errors
+///
+/// Returns `RangeNotSatisfiable` if the range has no received bytes.
+fn received_range(request: &HttpRequest, received: usize) -> RustusResult<Option<HttpRange>> {
+    let Some(header) = request.headers().get(RANGE) else {
+        return Ok(None);
+    };
+    header
+        .to_str()
+        .ok()
+        .and_then(|header| HttpRange::parse(header, received as u64).ok())
+        .and_then(|ranges| ranges.first().copied())
+        .map(Some)
+        .ok_or(RustusError::RangeNotSatisfiable(received))
+}
+
+/// Get the first byte of the partial response.
+fn content_range_start(response: &HttpResponse) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("bytes "))
+        .and_then(|value| value.split('-').next())
+        .and_then(|start| start.parse().ok())
+}
+
+/// Limit the download of an unfinished upload to received bytes.
+///
+/// Storages don't know the offset of the upload,
+/// so they may resolve ranges against all stored bytes.
+/// Bytes are sliced from their response and `Content-Range`
+/// is set as if the upload had only received bytes.
+fn limit_to_received(
+    response: HttpResponse,
+    file_info: &FileInfo,
+    range: Option<HttpRange>,
+) -> RustusResult<HttpResponse> {
+    let received = file_info.offset as u64;
+    let served_from = match response.status() {
+        StatusCode::OK => 0,
+        StatusCode::PARTIAL_CONTENT if range.is_some() => {
+            content_range_start(&response).unwrap_or_default()
+        }
+        _ => return Ok(response),
+    };
+    let Some(range) = range else {
+        return Ok(response
+            .map_body(|_, body| ReceivedBody {
+                inner: body,
+                skip: 0,
+                remaining: received,
+            })
+            .map_into_boxed_body());
+    };
+    // Suffix ranges may start after the requested bytes
+    // if the storage has bytes after the offset.
+    if served_from > range.start {
+        return Err(RustusError::Unimplemented(format!(
+            "range of received bytes of upload {} can't be served",
+            file_info.id
+        )));
+    }
+    let mut response = response
+        .map_body(|_, body| ReceivedBody {
+            inner: body,
+            skip: range.start - served_from,
+            remaining: range.length,
+        })
+        .map_into_boxed_body();
+    *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+    let content_range = format!(
+        "bytes {}-{}/{}",
+        range.start,
+        range.start + range.length - 1,
+        received
+    );
+    if let Ok(value) = HeaderValue::from_str(content_range.as_str()) {
+        response.headers_mut().insert(CONTENT_RANGE, value);
+    }
+    Ok(response)
+}
+
+/// Build download of an encrypted upload.
+///
+/// Data is decrypted by rustus, so ranges are resolved here.
+/// Upload is verified while it's sent, and modified data
+/// breaks the download before all bytes are sent.
+async fn encrypted_response(
+    state: &State,
+    request: &HttpRequest,
+    file_info: &FileInfo,
+    key: &UploadKey,
+) -> RustusResult<HttpResponse> {
+    let received = file_info.offset;
+    let range = received_range(request, received)?;
+    let (start, length) = range.map_or((0, received), |range| {
+        (
+            usize::try_from(range.start).unwrap_or(usize::MAX),
+            usize::try_from(range.length).unwrap_or(usize::MAX),
+        )
+    });
+    let bytes = start..start + length;
+    let storage = state.data_storage.as_ref();
+    let body = encryption::read_data(storage, file_info, Some(key), bytes).await?;
+    let mut response = if range.is_some() {
+        HttpResponse::PartialContent()
+    } else {
+        HttpResponse::Ok()
+    };
+    if range.is_some() {
+        response.insert_header((
+            CONTENT_RANGE,
+            format!("bytes {start}-{}/{received}", start + length - 1),
+        ));
+    }
+    Ok(response
+        .content_type("application/octet-stream")
+        .no_chunking(length as u64)
+        .streaming(body))
+}
+
+/// Check signature of the download URL.
+///
+/// It does nothing if signing secret isn't configured.
+pub fn check_signature(state: &State, request: &HttpRequest, file_id: &str) -> RustusResult<()> {
+    let Some(secret) = &state.config.download_signing_secret else {
+        return Ok(());
+    };
+    let query = web::Query::<DownloadSignature>::from_query(request.query_string())
+        .map_err(|_| RustusError::InvalidSignature)?;
+    let now = chrono::Utc::now().timestamp();
+    if !signature::verify(secret, file_id, &query, now) {
+        return Err(RustusError::InvalidSignature);
+    }
+    Ok(())
+}
 
 /// Retrieve actual file.
 ///
 /// This method allows you to download files directly from storage.
+/// If signing secret is configured, request must have a valid signature.
+/// Unfinished uploads are served only if it's enabled,
+/// in this case only received bytes are sent
+/// and ranges are resolved against them.
 pub async fn get_file(request: HttpRequest, state: web::Data<State>) -> RustusResult<HttpResponse> {
     let file_id_opt = request.match_info().get("file_id").map(String::from);
     if let Some(file_id) = file_id_opt {
+        check_signature(&state, &request, file_id.as_str())?;
         let file_info = state.info_storage.get_info(file_id.as_str()).await?;
         if file_info.storage != state.data_storage.to_string() {
             return Err(RustusError::FileNotFound);
         }
-        state.data_storage.get_contents(&file_info, &request).await
+        if file_info.assembling {
+            return Err(RustusError::Assembling(file_info.id));
+        }
+        let finished = file_info.length == Some(file_info.offset);
+        if !finished && !state.config.serve_incomplete_uploads {
+            let received = file_info.offset;
+            return Err(RustusError::IncompleteUpload(
+                if let Some(length) = file_info.length {
+                    format!("received {received} bytes of {length}")
+                } else {
+                    format!("received {received} bytes, length is unknown")
+                },
+            ));
+        }
+        let range = if finished {
+            None
+        } else {
+            received_range(&request, file_info.offset)?
+        };
+        orphans::check_data(&state, &file_info).await?;
+        let key = encryption::request_key(&request, &file_info).await?;
+        let mut response = if let Some(key) = &key {
+            encrypted_response(&state, &request, &file_info, key).await?
+        } else {
+            let response = state
+                .data_storage
+                .get_contents(&file_info, &request)
+                .await?;
+            if finished {
+                response
+            } else {
+                limit_to_received(response, &file_info, range)?
+            }
+        };
+        if let Some(cache_control) = cache_control(&state, &file_info) {
+            if let Ok((name, value)) = cache_control.try_into_pair() {
+                response.headers_mut().insert(name, value);
+            }
+        }
+        if let Some(policy) = RetentionPolicy::for_upload(&state, &file_info) {
+            return Ok(retention::schedule_removal(
+                &state, &file_info, &request, policy, response,
+            ));
+        }
+        Ok(response)
     } else {
         Err(RustusError::FileNotFound)
     }
@@ -20,28 +320,141 @@ pub async fn get_file(request: HttpRequest, state: web::Data<State>) -> RustusRe
 
 #[cfg(test)]
 mod test {
-    use crate::{server::test::get_service, State};
+    use crate::{
+        background::retention::RetentionPolicy,
+        errors::RustusError,
+        info_storages::FileInfo,
+        server::test::get_service,
+        utils::{signature, tombstones},
+        State,
+    };
     use actix_web::{
+        body::{BodySize, MessageBody},
         http::StatusCode,
-        test::{call_service, TestRequest},
+        test::{call_service, read_body, try_read_body, TestRequest},
     };
     use bytes::Bytes;
+    use std::time::Duration;
+
+    /// Create finished upload with 10 bytes of data.
+    async fn create_finished_file(state: &State) -> FileInfo {
+        let mut file_info = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&file_info, Bytes::from("0123456789"))
+            .await
+            .unwrap();
+        file_info.offset = 10;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        file_info
+    }
+
+    /// Wait until upload is removed.
+    async fn is_removed(state: &State, file_id: &str) -> bool {
+        for _ in 0..50 {
+            if state.info_storage.get_info(file_id).await.is_err() {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        false
+    }
 
     #[actix_rt::test]
     async fn success() {
         let state = State::test_new().await;
         let mut rustus = get_service(state.clone()).await;
-        let file_info = state.create_test_file().await;
+        let file_info = create_finished_file(&state).await;
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert!(resp.status().is_success());
+        assert_eq!(read_body(resp).await, Bytes::from("0123456789"));
+    }
+
+    #[actix_rt::test]
+    async fn incomplete_upload() {
+        let mut state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let mut file_info = state.create_test_file().await;
         state
             .data_storage
-            .add_bytes(&file_info, Bytes::from("testing"))
+            .add_bytes(&file_info, Bytes::from("012345"))
+            .await
+            .unwrap();
+        // Bytes after the offset aren't received yet.
+        file_info.offset = 3;
+        state
+            .info_storage
+            .set_info(&file_info, false)
             .await
             .unwrap();
         let request = TestRequest::get()
             .uri(state.config.file_url(file_info.id.as_str()).as_str())
             .to_request();
-        let resp = call_service(&mut rustus, request).await;
-        assert!(resp.status().is_success());
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+        state.config.serve_incomplete_uploads = true;
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        // Content-Length is taken from the size of the body.
+        assert_eq!(resp.response().body().size(), BodySize::Sized(3));
+        assert_eq!(read_body(resp).await, Bytes::from("012"));
+    }
+
+    #[actix_rt::test]
+    async fn incomplete_upload_ranges() {
+        let mut state = State::test_new().await;
+        state.config.serve_incomplete_uploads = true;
+        let rustus = get_service(state.clone()).await;
+        let mut file_info = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&file_info, Bytes::from("0123456789"))
+            .await
+            .unwrap();
+        // Bytes after the offset aren't received yet.
+        file_info.offset = 6;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let url = state.config.file_url(file_info.id.as_str());
+        for (range, content_range, body) in [
+            ("bytes=1-3", "bytes 1-3/6", "123"),
+            ("bytes=3-5", "bytes 3-5/6", "345"),
+            ("bytes=4-8", "bytes 4-5/6", "45"),
+            ("bytes=2-", "bytes 2-5/6", "2345"),
+        ] {
+            let request = TestRequest::get()
+                .uri(url.as_str())
+                .insert_header(("Range", range))
+                .to_request();
+            let resp = call_service(&rustus, request).await;
+            assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(resp.headers().get("Content-Range").unwrap(), content_range);
+            assert_eq!(read_body(resp).await, Bytes::from(body));
+        }
+        for range in ["bytes=6-8", "bytes=20-30"] {
+            let request = TestRequest::get()
+                .uri(url.as_str())
+                .insert_header(("Range", range))
+                .to_request();
+            let resp = call_service(&rustus, request).await;
+            assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+            assert_eq!(resp.headers().get("Content-Range").unwrap(), "bytes */6");
+        }
     }
 
     #[actix_rt::test]
@@ -72,4 +485,385 @@ mod test {
         let resp = call_service(&mut rustus, request).await;
         assert_eq!(resp.status(), StatusCode::NOT_FOUND);
     }
+
+    #[actix_rt::test]
+    async fn cache_finished_upload() {
+        let mut state = State::test_new().await;
+        state.config.download_max_age = Some(3600);
+        let rustus = get_service(state.clone()).await;
+        let file_info = create_finished_file(&state).await;
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get("Cache-Control").unwrap(),
+            "public, max-age=3600, immutable"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn cache_private_upload() {
+        let mut state = State::test_new().await;
+        state.config.download_max_age = Some(60);
+        state.config.download_cache_private = true;
+        let rustus = get_service(state.clone()).await;
+        let file_info = create_finished_file(&state).await;
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(
+            resp.headers().get("Cache-Control").unwrap(),
+            "private, max-age=60, immutable"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn no_cache_of_expiring_downloads() {
+        let mut state = State::test_new().await;
+        state.config.download_max_age = Some(3600);
+        state.config.delete_after_download = Some(RetentionPolicy::AfterDownload);
+        let rustus = get_service(state.clone()).await;
+        let file_info = create_finished_file(&state).await;
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.headers().get("Cache-Control").unwrap(), "no-store");
+
+        state.config.delete_after_download = None;
+        state.config.download_signing_secret = Some(String::from("secret"));
+        let rustus = get_service(state.clone()).await;
+        let file_info = create_finished_file(&state).await;
+        let expires = chrono::Utc::now().timestamp() + 60;
+        let signature = signature::sign("secret", file_info.id.as_str(), expires);
+        let request = TestRequest::get()
+            .uri(
+                format!(
+                    "{}?expires={expires}&signature={signature}",
+                    state.config.file_url(file_info.id.as_str())
+                )
+                .as_str(),
+            )
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("Cache-Control").unwrap(), "no-store");
+    }
+
+    #[actix_rt::test]
+    async fn no_cache_without_max_age() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let file_info = create_finished_file(&state).await;
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert!(resp.headers().get("Cache-Control").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn no_store_unfinished_upload() {
+        let mut state = State::test_new().await;
+        state.config.download_max_age = Some(3600);
+        state.config.serve_incomplete_uploads = true;
+        let rustus = get_service(state.clone()).await;
+        let file_info = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&file_info, Bytes::from("012"))
+            .await
+            .unwrap();
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("Cache-Control").unwrap(), "no-store");
+    }
+
+    #[actix_rt::test]
+    async fn delete_after_download() {
+        let mut state = State::test_new().await;
+        state.config.delete_after_download = Some(RetentionPolicy::AfterDownload);
+        let rustus = get_service(state.clone()).await;
+        let file_info = create_finished_file(&state).await;
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(read_body(resp).await, Bytes::from("0123456789"));
+        assert!(is_removed(&state, file_info.id.as_str()).await);
+        assert!(!std::path::PathBuf::from(file_info.path.unwrap()).exists());
+    }
+
+    #[actix_rt::test]
+    async fn delete_after_download_tombstone() {
+        let mut state = State::test_new().await;
+        state.config.delete_after_download = Some(RetentionPolicy::AfterDownload);
+        state.config.tombstone_ttl = Some(60);
+        let rustus = get_service(state.clone()).await;
+        let file_info = create_finished_file(&state).await;
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(read_body(resp).await, Bytes::from("0123456789"));
+        assert!(is_removed(&state, file_info.id.as_str()).await);
+        // Tombstone is added right after the upload is removed.
+        let mut buried = false;
+        for _ in 0..50 {
+            buried = state
+                .info_storage
+                .has_tombstone(file_info.id.as_str())
+                .await
+                .unwrap();
+            if buried {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(buried);
+        assert!(matches!(
+            tombstones::get_info(&state, file_info.id.as_str()).await,
+            Err(RustusError::UploadRemoved(_))
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn range_download_keeps_upload() {
+        let mut state = State::test_new().await;
+        state.config.delete_after_download = Some(RetentionPolicy::AfterDownload);
+        let rustus = get_service(state.clone()).await;
+        let file_info = create_finished_file(&state).await;
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .insert_header(("Range", "bytes=0-4"))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(read_body(resp).await, Bytes::from("01234"));
+        assert!(!is_removed(&state, file_info.id.as_str()).await);
+    }
+
+    #[actix_rt::test]
+    async fn delete_on_first_request() {
+        let mut state = State::test_new().await;
+        state.config.delete_after_download = Some(RetentionPolicy::FirstRequest);
+        let rustus = get_service(state.clone()).await;
+        let file_info = create_finished_file(&state).await;
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .insert_header(("Range", "bytes=0-4"))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        drop(resp);
+        assert!(is_removed(&state, file_info.id.as_str()).await);
+    }
+
+    #[actix_rt::test]
+    async fn delete_after_download_from_metadata() {
+        let mut state = State::test_new().await;
+        state.config.delete_after_download_key = Some(String::from("retention"));
+        let rustus = get_service(state.clone()).await;
+        let mut file_info = create_finished_file(&state).await;
+        file_info
+            .metadata
+            .insert(String::from("retention"), String::from("after-download"));
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let kept_info = create_finished_file(&state).await;
+        for id in [&file_info.id, &kept_info.id] {
+            let request = TestRequest::get()
+                .uri(state.config.file_url(id.as_str()).as_str())
+                .to_request();
+            let resp = call_service(&rustus, request).await;
+            read_body(resp).await;
+        }
+        assert!(is_removed(&state, file_info.id.as_str()).await);
+        assert!(state
+            .info_storage
+            .get_info(kept_info.id.as_str())
+            .await
+            .is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn unfinished_upload_is_kept() {
+        let mut state = State::test_new().await;
+        state.config.delete_after_download = Some(RetentionPolicy::FirstRequest);
+        state.config.serve_incomplete_uploads = true;
+        let rustus = get_service(state.clone()).await;
+        let file_info = state.create_test_file().await;
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        read_body(resp).await;
+        assert!(!is_removed(&state, file_info.id.as_str()).await);
+    }
+
+    #[actix_rt::test]
+    async fn signed_download() {
+        let mut state = State::test_new().await;
+        state.config.download_signing_secret = Some(String::from("secret"));
+        let rustus = get_service(state.clone()).await;
+        let file_info = create_finished_file(&state).await;
+        let expires = chrono::Utc::now().timestamp() + 60;
+        let file_url = state.config.file_url(file_info.id.as_str());
+        let signature = signature::sign("secret", file_info.id.as_str(), expires);
+        let request = TestRequest::get()
+            .uri(format!("{file_url}?expires={expires}&signature={signature}").as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let request = TestRequest::get()
+            .uri(format!("{file_url}?expires={}&signature={signature}", expires + 1).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn signed_download_expired() {
+        let mut state = State::test_new().await;
+        state.config.download_signing_secret = Some(String::from("secret"));
+        let rustus = get_service(state.clone()).await;
+        let file_info = create_finished_file(&state).await;
+        let expires = chrono::Utc::now().timestamp() - 1;
+        let signature = signature::sign("secret", file_info.id.as_str(), expires);
+        let request = TestRequest::get()
+            .uri(
+                format!(
+                    "{}?expires={expires}&signature={signature}",
+                    state.config.file_url(file_info.id.as_str())
+                )
+                .as_str(),
+            )
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn unsigned_download() {
+        let mut state = State::test_new().await;
+        state.config.download_signing_secret = Some(String::from("secret"));
+        let rustus = get_service(state.clone()).await;
+        let file_info = create_finished_file(&state).await;
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn missing_data() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let file_info = create_finished_file(&state).await;
+        std::fs::remove_file(file_info.path.as_deref().unwrap()).unwrap();
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::GONE);
+        assert!(state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn encrypted_download() {
+        let mut state = State::test_new().await;
+        state.config.passphrase_encryption = true;
+        state.config.passphrase_iterations = 10;
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", 10))
+            .insert_header(("Upload-Passphrase", "memes"))
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .set_payload("01234")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let location = resp.headers().get("Location").unwrap().to_str().unwrap();
+        let file_id = location.split('/').last().unwrap();
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file_id).as_str())
+            .insert_header(("Upload-Offset", 5))
+            .insert_header(("Upload-Passphrase", "memes"))
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .set_payload("56789")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let file_info = state.info_storage.get_info(file_id).await.unwrap();
+        assert!(file_info.encryption.is_some());
+        let stored = std::fs::read(file_info.path.as_deref().unwrap()).unwrap();
+        assert_eq!(stored.len(), 10);
+        assert_ne!(stored, b"0123456789");
+
+        for passphrase in [None, Some("not memes")] {
+            let mut request = TestRequest::get().uri(state.config.file_url(file_id).as_str());
+            if let Some(passphrase) = passphrase {
+                request = request.insert_header(("Upload-Passphrase", passphrase));
+            }
+            let resp = call_service(&rustus, request.to_request()).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_id).as_str())
+            .insert_header(("Upload-Passphrase", "memes"))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()
+                .get("Cache-Control")
+                .unwrap()
+                .to_str()
+                .unwrap(),
+            "no-store"
+        );
+        assert_eq!(read_body(resp).await, Bytes::from("0123456789"));
+        let request = TestRequest::get()
+            .uri(state.config.file_url(file_id).as_str())
+            .insert_header(("Upload-Passphrase", "memes"))
+            .insert_header(("Range", "bytes=3-6"))
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers().get("Content-Range").unwrap(), "bytes 3-6/10");
+        assert_eq!(read_body(resp).await, Bytes::from("3456"));
+
+        // Upload is authenticated as a whole,
+        // so modified data breaks any download.
+        let mut stored = stored;
+        stored[7] ^= 1;
+        std::fs::write(file_info.path.as_deref().unwrap(), stored).unwrap();
+        for range in [None, Some("bytes=0-4")] {
+            let mut request = TestRequest::get()
+                .uri(state.config.file_url(file_id).as_str())
+                .insert_header(("Upload-Passphrase", "memes"));
+            if let Some(range) = range {
+                request = request.insert_header(("Range", range));
+            }
+            let resp = call_service(&rustus, request.to_request()).await;
+            assert!(try_read_body(resp).await.is_err());
+        }
+    }
 }
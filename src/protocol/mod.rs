@@ -1,13 +1,18 @@
-use actix_web::web;
+use actix_web::{web, HttpRequest};
 
-use crate::RustusConf;
+use crate::{RustusConf, State};
 
 mod core;
 mod creation;
 pub mod extensions;
 mod getting;
+mod multipart;
+#[cfg(feature = "progress_websocket")]
+mod progress;
 mod termination;
 
+pub use self::core::expect_service;
+
 /// Configure TUS web application.
 ///
 /// This function resolves all protocol extensions
@@ -24,9 +29,37 @@ pub fn setup(app_conf: RustusConf) -> impl Fn(&mut web::ServiceConfig) {
                 extensions::Extensions::Getting => {
                     getting::add_extension(web_app);
                 }
+                extensions::Extensions::Checksum => {
+                    core::add_prefix_checksum(web_app);
+                }
                 _ => {}
             }
         }
+        if app_conf.owners_enabled() {
+            core::add_owner_uploads(web_app);
+        }
+        if app_conf.multipart_uploads {
+            multipart::add_extension(web_app);
+        }
+        #[cfg(feature = "progress_websocket")]
+        if app_conf.progress_websocket {
+            progress::add_extension(web_app);
+        }
         core::add_extension(web_app);
     }
 }
+
+/// URL of the upload for `Location` header.
+fn upload_location(
+    state: &State,
+    request: &HttpRequest,
+    file_id: &str,
+) -> actix_web::Result<String> {
+    let upload_url = request.url_for("core:write_bytes", [file_id])?;
+    let location = if state.config.relative_location {
+        upload_url.path()
+    } else {
+        upload_url.as_str()
+    };
+    Ok(String::from(location.strip_suffix('/').unwrap_or(location)))
+}
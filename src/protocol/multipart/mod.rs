@@ -0,0 +1,21 @@
+use actix_web::{guard, web};
+
+mod routes;
+
+/// Add multipart uploads.
+///
+/// This is a compatibility endpoint for clients
+/// that can't use TUS protocol. It accepts a single file
+/// sent as `multipart/form-data` and creates a finished upload.
+///
+/// This is not a part of TUS protocol.
+#[cfg_attr(coverage, no_coverage)]
+pub fn add_extension(web_app: &mut web::ServiceConfig) {
+    web_app.service(
+        // POST /base/multipart
+        web::resource("/multipart/")
+            .name("multipart:upload")
+            .guard(guard::Post())
+            .to(routes::upload),
+    );
+}
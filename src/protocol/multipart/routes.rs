@@ -0,0 +1,522 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use bytes::{Bytes, BytesMut};
+use log::warn;
+use serde_json::json;
+
+use crate::{
+    background::{checksum, derivation, export},
+    errors::RustusError,
+    info_storages::FileInfo,
+    metrics,
+    notifiers::Hook,
+    utils::{
+        durability, metadata,
+        multipart::{get_boundary, Multipart},
+        owner, pre_create, quota,
+        rejections::{self, Reason},
+        unique::{self, Uniqueness},
+    },
+    RustusResult, State,
+};
+
+/// Maximum size of a form field.
+const MAX_FIELD_SIZE: usize = 64 * 1024;
+
+type Form = Multipart<web::Payload>;
+
+/// Read value of a form field.
+async fn read_field(form: &mut Form) -> RustusResult<String> {
+    let mut value = BytesMut::new();
+    while let Some(chunk) = form.next_chunk().await? {
+        if value.len() + chunk.len() > MAX_FIELD_SIZE {
+            return Err(RustusError::MultipartError(String::from(
+                "Form field is too large.",
+            )));
+        }
+        value.extend_from_slice(chunk.as_ref());
+    }
+    String::from_utf8(value.to_vec())
+        .map_err(|_| RustusError::MultipartError(String::from("Form field isn't UTF-8.")))
+}
+
+/// Limits of the uploaded file.
+struct Limits {
+    max_file_size: Option<usize>,
+    /// Tenant and space left in its quota.
+    quota: Option<(String, usize)>,
+}
+
+/// Write chunk of the file and save
+/// updated information about the upload.
+async fn write_chunk(state: &State, file_info: &mut FileInfo, bytes: Bytes) -> RustusResult<()> {
+    let start = file_info.offset;
+    let chunk_len = bytes.len();
+    state.data_storage.add_bytes(file_info, bytes).await?;
+    if state.data_storage.accepts_out_of_order() {
+        file_info.add_received_range(start, start + chunk_len);
+    } else {
+        file_info.offset += chunk_len;
+    }
+    state.info_storage.save_info(file_info).await
+}
+
+/// Stream file from the form to the storage.
+///
+/// Returns response if the file is rejected.
+async fn write_file(
+    state: &State,
+    file_info: &mut FileInfo,
+    form: &mut Form,
+    limits: &Limits,
+) -> RustusResult<Option<HttpResponse>> {
+    // Chunks are written with the same size as TUS requests,
+    // rounded up to the alignment of the storage.
+    let alignment = state.data_storage.chunk_alignment().unwrap_or(1);
+    let chunk_size = state.config.max_body_size.max(1).div_ceil(alignment) * alignment;
+    let mut buffer = BytesMut::new();
+    let mut pending: Option<Bytes> = None;
+    let mut received = 0;
+    while let Some(chunk) = form.next_chunk().await? {
+        received += chunk.len();
+        if let Some(max_file_size) = limits.max_file_size {
+            if received > max_file_size {
+                return Ok(Some(rejections::reject(
+                    HttpResponse::BadRequest(),
+                    Reason::TooLarge,
+                    format!("File size should be less than or equal to {max_file_size}"),
+                )));
+            }
+        }
+        if let Some((tenant, left)) = &limits.quota {
+            if received > *left {
+                return Ok(Some(rejections::reject(
+                    HttpResponse::PayloadTooLarge(),
+                    Reason::QuotaExceeded,
+                    format!("Quota of tenant {tenant} is exceeded."),
+                )));
+            }
+        }
+        buffer.extend_from_slice(chunk.as_ref());
+        if buffer.len() >= chunk_size {
+            let aligned = buffer.len() - buffer.len() % alignment;
+            if let Some(previous) = pending.replace(buffer.split_to(aligned).freeze()) {
+                write_chunk(state, file_info, previous).await?;
+            }
+        }
+    }
+    // Some storages finish uploads when the whole length is received,
+    // that's why the last chunk is written after the length is known.
+    let last_chunk = if buffer.is_empty() {
+        pending.take()
+    } else {
+        if let Some(previous) = pending.take() {
+            write_chunk(state, file_info, previous).await?;
+        }
+        Some(buffer.freeze())
+    };
+    let length = received;
+    if length == 0 && !state.config.allow_empty {
+        return Ok(Some(rejections::reject(
+            HttpResponse::BadRequest(),
+            Reason::EmptyUpload,
+            String::from("File size should be greater than zero"),
+        )));
+    }
+    file_info.length = Some(length);
+    file_info.deferred_size = false;
+    match last_chunk {
+        Some(chunk) => write_chunk(state, file_info, chunk).await?,
+        None => state.info_storage.save_info(file_info).await?,
+    }
+    // Fields after the file are ignored.
+    while form.next_part().await?.is_some() {}
+    Ok(None)
+}
+
+/// Remove upload that wasn't finished.
+async fn remove_upload(state: &State, file_info: &FileInfo) {
+    if let Err(err) = state.data_storage.remove_file(file_info).await {
+        warn!("Cannot remove rejected upload {}: {}", file_info.id, err);
+    }
+    if let Err(err) = state.info_storage.remove_info(file_info.id.as_str()).await {
+        warn!(
+            "Cannot remove information about rejected upload {}: {}",
+            file_info.id, err
+        );
+    }
+}
+
+/// Upload file with `multipart/form-data` request.
+///
+/// Form fields before the file are used as metadata.
+/// Name and content type of the file are stored
+/// as `filename` and `filetype` metadata if these keys aren't set.
+pub async fn upload(
+    request: HttpRequest,
+    payload: web::Payload,
+    state: web::Data<State>,
+    metrics: web::Data<metrics::RustusMetrics>,
+) -> actix_web::Result<HttpResponse> {
+    let result = create(request.clone(), payload, state.clone(), metrics).await;
+    rejections::notify(&state, &request, &result);
+    result
+}
+
+#[allow(clippy::too_many_lines)]
+async fn create(
+    request: HttpRequest,
+    payload: web::Payload,
+    state: web::Data<State>,
+    metrics: web::Data<metrics::RustusMetrics>,
+) -> actix_web::Result<HttpResponse> {
+    if state.is_draining() {
+        return Err(RustusError::Draining(state.config.drain_retry_after).into());
+    }
+    let boundary = request
+        .headers()
+        .get("Content-Type")
+        .and_then(|header| header.to_str().ok())
+        .and_then(get_boundary);
+    let Some(boundary) = boundary else {
+        return Ok(HttpResponse::UnsupportedMediaType()
+            .body("Content-Type should be multipart/form-data."));
+    };
+    let mut form = Multipart::new(payload, boundary.as_str());
+
+    let mut meta = HashMap::new();
+    let file_headers = loop {
+        let Some(part) = form.next_part().await? else {
+            return Ok(HttpResponse::BadRequest().body("Request has no file."));
+        };
+        if part.filename.is_some() {
+            break part;
+        }
+        if let Some(name) = part.name {
+            let value = read_field(&mut form).await?;
+            meta.insert(name, value);
+        }
+    };
+    if let Some(filename) = file_headers.filename {
+        meta.entry(String::from("filename")).or_insert(filename);
+    }
+    if let Some(content_type) = file_headers.content_type {
+        meta.entry(String::from("filetype")).or_insert(content_type);
+    }
+    let mut meta = metadata::transform(
+        meta,
+        state.config.metadata_normalizers.as_slice(),
+        state.config.metadata_aliases.as_slice(),
+    );
+    derivation::strip_derived_from(&mut meta);
+    owner::assign(&state.config, &request, &mut meta);
+    // Clients can't set or override server metadata.
+    metadata::assign_server_metadata(state.config.server_metadata.as_slice(), &mut meta);
+    rejections::attempt(&request, None, Some(&meta));
+    if let Some(key) = metadata::mismatched_key(&meta, state.config.metadata_patterns.as_slice()) {
+        return Ok(rejections::reject(
+            HttpResponse::BadRequest(),
+            Reason::InvalidMetadata,
+            format!("Metadata value of `{key}` isn't allowed."),
+        ));
+    }
+
+    let file_id = uuid::Uuid::new_v4().to_string();
+    let mut file_info = FileInfo::new(
+        file_id.as_str(),
+        None,
+        None,
+        state.data_storage.to_string(),
+        Some(meta),
+    );
+
+    pre_create::send(&state, &request, &mut file_info).await?;
+
+    // Value of the unique key stays locked until the file is received,
+    // since the upload is finished by this request.
+    let _unique = match unique::check(&state, &file_info).await? {
+        Uniqueness::Unique(guard) => guard,
+        Uniqueness::Duplicate(response) => return Ok(response),
+    };
+
+    // Length set by pre-create hook limits size of the file.
+    let mut limits = Limits {
+        max_file_size: match (state.config.max_file_size, file_info.length) {
+            (Some(max_file_size), Some(length)) => Some(max_file_size.min(length)),
+            (max_file_size, length) => max_file_size.or(length),
+        },
+        quota: None,
+    };
+    file_info.length = None;
+    file_info.deferred_size = true;
+    let tenant_key = state.config.tenant_metadata_key.as_str();
+    if let Some(tenant) = file_info.metadata.get(tenant_key) {
+        if let Some(quota) = state.config.tenant_quota(tenant) {
+            let usage = quota::cached_usage(&state, tenant).await?;
+            limits.quota = Some((tenant.clone(), quota.saturating_sub(usage)));
+        }
+    }
+
+    state.data_storage.create_upload(&mut file_info).await?;
+    state.info_storage.set_info(&file_info, true).await?;
+    metrics.active_uploads.inc();
+    metrics.started_uploads.inc();
+
+    let rejection = match write_file(&state, &mut file_info, &mut form, &limits).await {
+        Ok(rejection) => rejection,
+        Err(err) => {
+            remove_upload(&state, &file_info).await;
+            metrics.active_uploads.dec();
+            return Err(err.into());
+        }
+    };
+    if let Some(response) = rejection {
+        remove_upload(&state, &file_info).await;
+        metrics.active_uploads.dec();
+        return Ok(response);
+    }
+    // Other uploads of the tenant could take the space
+    // while the file was received, so it's reserved at once.
+    if !quota::reserve(&state, &file_info, file_info.offset).await? {
+        remove_upload(&state, &file_info).await;
+        metrics.active_uploads.dec();
+        return Ok(rejections::reject(
+            HttpResponse::PayloadTooLarge(),
+            Reason::QuotaExceeded,
+            format!(
+                "Quota of tenant {} is exceeded.",
+                file_info.metadata[tenant_key]
+            ),
+        ));
+    }
+    let finished = async {
+        durability::verify_size(&state, &file_info).await?;
+        durability::sync_finished(&state, &file_info).await
+    };
+    if let Err(err) = finished.await {
+        remove_upload(&state, &file_info).await;
+        quota::release(&state, &file_info, file_info.offset);
+        metrics.active_uploads.dec();
+        return Err(err.into());
+    }
+
+    metrics.active_uploads.dec();
+    metrics.finished_uploads.inc();
+    metrics.observe_finished(&file_info);
+    if let Some(length) = file_info.length {
+        #[allow(clippy::cast_precision_loss)]
+        metrics.upload_sizes.observe(length as f64);
+    }
+
+    let upload_url = request.url_for("core:write_bytes", [file_info.id.clone()])?;
+    let location = if state.config.relative_location {
+        upload_url.path()
+    } else {
+        upload_url.as_str()
+    };
+    let location = location.strip_suffix('/').unwrap_or(location);
+
+    export::spawn_export(&state, &file_info, None);
+    derivation::spawn_derivation(&state, &file_info, &request);
+    if state.config.upload_checksum {
+        // Post-finish hook is sent after checksum is computed.
+        checksum::spawn_checksum(&state, &file_info, &request, None);
+    } else if state.config.hook_is_active(Hook::PostFinish) {
+        let message = state.config.notification_opts.hooks_format.format(
+            &request,
+            &file_info,
+            &state.config.client_ip,
+        );
+        let headers = request.headers().clone();
+        let file_info = file_info.clone();
+        tokio::task::spawn_local(async move {
+            state
+                .notification_manager
+                .deliver_upload(message, Hook::PostFinish, &file_info, &headers)
+                .await;
+        });
+    }
+
+    Ok(HttpResponse::Created()
+        .insert_header(("Location", location))
+        .json(json!({
+            "id": file_info.id,
+            "location": location,
+        })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{server::test::get_service, utils::metadata::ServerMetadata, State};
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, read_body_json, TestRequest},
+    };
+    use serde_json::Value;
+    use std::str::FromStr;
+
+    /// Build multipart body with a single text field and a file.
+    fn form_body(file: &str) -> String {
+        format!(
+            "--bound\r\n\
+            Content-Disposition: form-data; name=\"tenant\"\r\n\r\n\
+            acme\r\n--bound\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"test.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            {file}\r\n--bound--\r\n"
+        )
+    }
+
+    fn multipart_request(state: &State, body: String) -> actix_http::Request {
+        TestRequest::post()
+            .uri(format!("/{}/multipart/", state.config.base_url()).as_str())
+            .insert_header(("Content-Type", "multipart/form-data; boundary=bound"))
+            .set_payload(body)
+            .to_request()
+    }
+
+    #[actix_rt::test]
+    async fn success() {
+        let mut state = State::test_new().await;
+        state.config.multipart_uploads = true;
+        // Small chunks make file to be written in multiple parts.
+        state.config.max_body_size = 3;
+        state.config.server_metadata = vec![ServerMetadata::from_str("tenant=node-1").unwrap()];
+        let rustus = get_service(state.clone()).await;
+        let request = multipart_request(&state, form_body("hello world"));
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let location = resp
+            .headers()
+            .get("Location")
+            .and_then(|header| header.to_str().ok())
+            .map(String::from)
+            .unwrap();
+        let body: Value = read_body_json(resp).await;
+        let file_id = body["id"].as_str().unwrap();
+        assert!(location.ends_with(file_id));
+        let file_info = state.info_storage.get_info(file_id).await.unwrap();
+        assert_eq!(file_info.length, Some(11));
+        assert_eq!(file_info.offset, 11);
+        // Value sent by the client is replaced.
+        assert_eq!(file_info.metadata["tenant"], "node-1");
+        assert_eq!(file_info.metadata["filename"], "test.txt");
+        assert_eq!(file_info.metadata["filetype"], "text/plain");
+        assert_eq!(
+            std::fs::read_to_string(file_info.path.unwrap()).unwrap(),
+            "hello world"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn max_file_size() {
+        let mut state = State::test_new().await;
+        state.config.multipart_uploads = true;
+        state.config.max_file_size = Some(5);
+        let rustus = get_service(state.clone()).await;
+        let request = multipart_request(&state, form_body("hello world"));
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(state.info_storage.list_info().await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn tenant_quota() {
+        let mut state = State::test_new().await;
+        state.config.multipart_uploads = true;
+        state.config.default_tenant_quota = Some(5);
+        let rustus = get_service(state.clone()).await;
+        let request = multipart_request(&state, form_body("hello world"));
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(state.info_storage.list_info().await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn unique_filename() {
+        let mut state = State::test_new().await;
+        state.config.multipart_uploads = true;
+        state.config.unique_metadata_key = Some(String::from("filename"));
+        let rustus = get_service(state.clone()).await;
+        let request = multipart_request(&state, form_body("hello world"));
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let request = multipart_request(&state, form_body("hello world"));
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        assert_eq!(state.info_storage.list_info().await.unwrap().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn no_file() {
+        let mut state = State::test_new().await;
+        state.config.multipart_uploads = true;
+        let rustus = get_service(state.clone()).await;
+        let body = String::from(
+            "--bound\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nmemes\r\n--bound--\r\n",
+        );
+        let resp = call_service(&rustus, multipart_request(&state, body)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn wrong_content_type() {
+        let mut state = State::test_new().await;
+        state.config.multipart_uploads = true;
+        let rustus = get_service(state.clone()).await;
+        let request = TestRequest::post()
+            .uri(format!("/{}/multipart/", state.config.base_url()).as_str())
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload("{}")
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    async fn patching_state(response: &str) -> (State, httptest::Server) {
+        let server = httptest::Server::run();
+        server.expect(
+            httptest::Expectation::matching(httptest::matchers::request::method_path(
+                "POST", "/hook",
+            ))
+            .respond_with(httptest::responders::status_code(200).body(String::from(response))),
+        );
+        let mut state = State::test_new().await;
+        state.config.multipart_uploads = true;
+        state.config.notification_opts.hooks = vec![crate::notifiers::Hook::PreCreate];
+        state.config.notification_opts.hooks_http_urls = vec![server.url_str("/hook")];
+        state.config.notification_opts.hooks_pre_create_patch = true;
+        state.notification_manager = crate::NotificationManager::new(&state.config)
+            .await
+            .unwrap();
+        (state, server)
+    }
+
+    #[actix_rt::test]
+    async fn pre_create_patch() {
+        let (state, _server) = patching_state(r#"{"metadata": {"tenant": "globex"}}"#).await;
+        let rustus = get_service(state.clone()).await;
+        let request = multipart_request(&state, form_body("hello world"));
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body: Value = read_body_json(resp).await;
+        let file_info = state
+            .info_storage
+            .get_info(body["id"].as_str().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(file_info.metadata["tenant"], "globex");
+        assert_eq!(file_info.length, Some(11));
+    }
+
+    #[actix_rt::test]
+    async fn pre_create_patch_length() {
+        let (state, _server) = patching_state(r#"{"length": 5}"#).await;
+        let rustus = get_service(state.clone()).await;
+        let request = multipart_request(&state, form_body("hello world"));
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(state.info_storage.list_info().await.unwrap().is_empty());
+    }
+}
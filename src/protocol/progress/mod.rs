@@ -0,0 +1,22 @@
+use actix_web::{guard, middleware, web};
+
+mod routes;
+
+/// Add websocket endpoint with progress of uploads.
+///
+/// Clients get events with offsets of an upload
+/// instead of polling it with HEAD requests.
+///
+/// This is not a part of TUS protocol.
+#[cfg_attr(coverage, no_coverage)]
+pub fn add_extension(web_app: &mut web::ServiceConfig) {
+    web_app.service(
+        // GET /base/{file_id}/progress
+        web::resource("/{file_id}/progress/")
+            .name("progress:websocket")
+            .guard(guard::Get())
+            // Offsets must never be cached, even in error responses.
+            .wrap(middleware::DefaultHeaders::new().add(("Cache-Control", "no-store")))
+            .to(routes::progress),
+    );
+}
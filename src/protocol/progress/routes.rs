@@ -0,0 +1,317 @@
+use std::time::Duration;
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{hash_key, verify_handshake, CloseCode, Codec, Frame, Message};
+use actix_web::{http::header, web, HttpRequest, HttpResponse, ResponseError};
+use bytes::{Bytes, BytesMut};
+use futures::{channel::mpsc, StreamExt};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    utils::progress::{ProgressEvent, ProgressKind},
+    State,
+};
+
+/// Interval of pings sent to the client.
+///
+/// Pings keep connections alive behind proxies
+/// and let us notice clients which are gone.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Websocket connection of a single client.
+struct Session {
+    state: web::Data<State>,
+    file_id: String,
+    codec: Codec,
+    frames: mpsc::UnboundedSender<Result<Bytes, RustusError>>,
+}
+
+impl Session {
+    /// Send message to the client.
+    ///
+    /// Returns false if the client is gone.
+    fn send(&mut self, message: Message) -> bool {
+        let mut buffer = BytesMut::new();
+        if self.codec.encode(message, &mut buffer).is_err() {
+            return false;
+        }
+        self.frames.unbounded_send(Ok(buffer.freeze())).is_ok()
+    }
+
+    fn send_event(&mut self, event: &ProgressEvent) -> bool {
+        match serde_json::to_string(event) {
+            Ok(text) => self.send(Message::Text(text.into())),
+            Err(_) => false,
+        }
+    }
+
+    /// Handle frames sent by the client.
+    ///
+    /// Returns false if the connection must be closed.
+    fn handle_input(&mut self, input: &mut BytesMut) -> bool {
+        loop {
+            match self.codec.decode(input) {
+                Ok(Some(Frame::Ping(message))) => {
+                    if !self.send(Message::Pong(message)) {
+                        return false;
+                    }
+                }
+                Ok(Some(Frame::Close(reason))) => {
+                    self.send(Message::Close(reason));
+                    return false;
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => return true,
+                Err(_) => {
+                    self.send(Message::Close(Some(CloseCode::Protocol.into())));
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// Current state of the upload.
+    async fn current(&self) -> Option<ProgressEvent> {
+        match self
+            .state
+            .info_storage
+            .get_info(self.file_id.as_str())
+            .await
+        {
+            Ok(file_info) => Some(ProgressEvent::current(&file_info)),
+            Err(RustusError::FileNotFound) => Some(ProgressEvent {
+                id: self.file_id.clone(),
+                event: ProgressKind::Terminated,
+                offset: 0,
+                length: None,
+            }),
+            Err(_) => None,
+        }
+    }
+
+    /// Send events of the upload until it's finished or terminated.
+    async fn run(
+        mut self,
+        first: ProgressEvent,
+        mut events: Receiver<ProgressEvent>,
+        mut payload: web::Payload,
+    ) {
+        let mut event = Some(first);
+        let mut input = BytesMut::new();
+        let mut payload_open = true;
+        let mut ping =
+            tokio::time::interval_at(tokio::time::Instant::now() + PING_INTERVAL, PING_INTERVAL);
+        loop {
+            if let Some(event) = event.take() {
+                if !self.send_event(&event) {
+                    return;
+                }
+                if event.is_last() {
+                    self.send(Message::Close(Some(CloseCode::Normal.into())));
+                    return;
+                }
+            }
+            tokio::select! {
+                received = events.recv() => match received {
+                    Ok(received) if received.id == self.file_id => event = Some(received),
+                    Ok(_) => {}
+                    // Some events were dropped, so the current state is sent.
+                    Err(RecvError::Lagged(_)) => match self.current().await {
+                        Some(current) => event = Some(current),
+                        None => return,
+                    },
+                    Err(RecvError::Closed) => return,
+                },
+                chunk = payload.next(), if payload_open => match chunk {
+                    Some(Ok(chunk)) => {
+                        input.extend_from_slice(chunk.as_ref());
+                        if !self.handle_input(&mut input) {
+                            return;
+                        }
+                    }
+                    Some(Err(_)) => return,
+                    // Client doesn't send anything,
+                    // but it still can receive events.
+                    None => payload_open = false,
+                },
+                _ = ping.tick() => {
+                    if !self.send(Message::Ping(Bytes::new())) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Stream progress of an upload over websocket.
+///
+/// Every event is a JSON object with offset of the upload.
+/// Connection is closed after the upload is finished or terminated.
+pub async fn progress(
+    request: HttpRequest,
+    payload: web::Payload,
+    state: web::Data<State>,
+) -> RustusResult<HttpResponse> {
+    let Some(file_id) = request.match_info().get("file_id") else {
+        return Err(RustusError::FileNotFound);
+    };
+    if let Err(err) = verify_handshake(request.head()) {
+        return Ok(err.error_response());
+    }
+    // Subscribing before reading the info,
+    // so changes are never missed.
+    let events = state.progress.subscribe();
+    let file_info = state.info_storage.get_info(file_id).await?;
+    if file_info.storage != state.data_storage.to_string() {
+        return Err(RustusError::FileNotFound);
+    }
+    let accept = request
+        .headers()
+        .get(header::SEC_WEBSOCKET_KEY)
+        .map(|key| hash_key(key.as_bytes()))
+        .ok_or(RustusError::WrongHeaderValue)?;
+    let (frames, stream) = mpsc::unbounded();
+    let session = Session {
+        state: state.clone(),
+        file_id: file_info.id.clone(),
+        codec: Codec::new(),
+        frames,
+    };
+    tokio::task::spawn_local(session.run(ProgressEvent::current(&file_info), events, payload));
+    Ok(HttpResponse::SwitchingProtocols()
+        .upgrade("websocket")
+        .insert_header((header::SEC_WEBSOCKET_ACCEPT, accept.as_slice()))
+        .streaming(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{info_storages::FileInfo, server::test::get_service, State};
+    use actix_codec::Decoder;
+    use actix_http::ws::{CloseCode, Codec, Frame};
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, read_body, TestRequest},
+    };
+    use bytes::BytesMut;
+    use serde_json::Value;
+
+    fn progress_request(state: &State, file_id: &str) -> actix_http::Request {
+        TestRequest::get()
+            .uri(format!("{}progress/", state.config.file_url(file_id)).as_str())
+            .insert_header(("Upgrade", "websocket"))
+            .insert_header(("Connection", "Upgrade"))
+            .insert_header(("Sec-WebSocket-Version", "13"))
+            .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="))
+            .to_request()
+    }
+
+    /// Read all frames sent by the server.
+    ///
+    /// Returns events and the close code.
+    fn parse_frames(body: &[u8]) -> (Vec<Value>, Option<CloseCode>) {
+        let mut codec = Codec::new().client_mode();
+        let mut buffer = BytesMut::from(body);
+        let mut events = Vec::new();
+        let mut close_code = None;
+        while let Some(frame) = codec.decode(&mut buffer).unwrap() {
+            match frame {
+                Frame::Text(text) => events.push(serde_json::from_slice(text.as_ref()).unwrap()),
+                Frame::Close(reason) => close_code = reason.map(|reason| reason.code),
+                _ => {}
+            }
+        }
+        (events, close_code)
+    }
+
+    async fn progress_state() -> State {
+        let mut state = State::test_new().await;
+        state.config.progress_websocket = true;
+        state
+    }
+
+    #[actix_rt::test]
+    async fn progress_until_finished() {
+        let state = progress_state().await;
+        let rustus = get_service(state.clone()).await;
+        let mut file_info = state.create_test_file().await;
+        let resp = call_service(&rustus, progress_request(&state, file_info.id.as_str())).await;
+        assert_eq!(resp.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(resp.headers().get("Cache-Control").unwrap(), "no-store");
+        assert_eq!(
+            resp.headers().get("Sec-WebSocket-Accept").unwrap(),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        // Events of other uploads are skipped.
+        state.progress.publish(&FileInfo::new_test());
+        file_info.offset = 5;
+        state.progress.publish(&file_info);
+        file_info.offset = 10;
+        state.progress.publish(&file_info);
+        let (events, close_code) = parse_frames(read_body(resp).await.as_ref());
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["event"], "progress");
+        assert_eq!(events[0]["offset"], 0);
+        assert_eq!(events[1]["offset"], 5);
+        assert_eq!(events[2]["event"], "finished");
+        assert_eq!(events[2]["offset"], 10);
+        assert_eq!(events[2]["length"], 10);
+        assert_eq!(close_code, Some(CloseCode::Normal));
+    }
+
+    #[actix_rt::test]
+    async fn finished_upload() {
+        let state = progress_state().await;
+        let rustus = get_service(state.clone()).await;
+        let mut file_info = state.create_test_file().await;
+        file_info.offset = 10;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let resp = call_service(&rustus, progress_request(&state, file_info.id.as_str())).await;
+        let (events, close_code) = parse_frames(read_body(resp).await.as_ref());
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["event"], "finished");
+        assert_eq!(close_code, Some(CloseCode::Normal));
+    }
+
+    #[actix_rt::test]
+    async fn terminated_upload() {
+        let state = progress_state().await;
+        let rustus = get_service(state.clone()).await;
+        let file_info = state.create_test_file().await;
+        let resp = call_service(&rustus, progress_request(&state, file_info.id.as_str())).await;
+        let request = TestRequest::delete()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let terminated = call_service(&rustus, request).await;
+        assert_eq!(terminated.status(), StatusCode::NO_CONTENT);
+        let (events, _) = parse_frames(read_body(resp).await.as_ref());
+        assert_eq!(events.last().unwrap()["event"], "terminated");
+    }
+
+    #[actix_rt::test]
+    async fn unknown_upload() {
+        let state = progress_state().await;
+        let rustus = get_service(state.clone()).await;
+        let resp = call_service(&rustus, progress_request(&state, "unknown")).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers().get("Cache-Control").unwrap(), "no-store");
+    }
+
+    #[actix_rt::test]
+    async fn not_websocket() {
+        let state = progress_state().await;
+        let rustus = get_service(state.clone()).await;
+        let file_info = state.create_test_file().await;
+        let request = TestRequest::get()
+            .uri(format!("{}progress/", state.config.file_url(file_info.id.as_str())).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -4,6 +4,7 @@ use crate::{
     errors::{RustusError, RustusResult},
     metrics,
     notifiers::Hook,
+    utils::{tombstones, trash},
     State,
 };
 
@@ -11,6 +12,8 @@ use crate::{
 ///
 /// This method will remove all data by id.
 /// It removes info and actual data.
+/// If grace period is enabled, the upload is moved
+/// to the trash and its data is removed later.
 pub async fn terminate(
     request: HttpRequest,
     state: web::Data<State>,
@@ -18,15 +21,24 @@ pub async fn terminate(
 ) -> RustusResult<HttpResponse> {
     let file_id_opt = request.match_info().get("file_id").map(String::from);
     if let Some(file_id) = file_id_opt {
-        let file_info = state.info_storage.get_info(file_id.as_str()).await?;
+        let file_info = match state.info_storage.get_info(file_id.as_str()).await {
+            Err(RustusError::FileNotFound) if state.config.idempotent_termination => {
+                return Ok(HttpResponse::NoContent().finish());
+            }
+            result => result?,
+        };
         if file_info.storage != state.data_storage.to_string() {
             return Err(RustusError::FileNotFound);
         }
+        // Parts are concatenated into the final upload right now.
+        if file_info.assembling {
+            return Err(RustusError::Assembling(file_id));
+        }
         if state.config.hook_is_active(Hook::PreTerminate) {
             let message = state.config.notification_opts.hooks_format.format(
                 &request,
                 &file_info,
-                state.config.notification_opts.behind_proxy,
+                &state.config.client_ip,
             );
             let headers = request.headers();
             state
@@ -34,23 +46,59 @@ pub async fn terminate(
                 .send_message(message, Hook::PreTerminate, headers)
                 .await?;
         }
-        state.info_storage.remove_info(file_id.as_str()).await?;
-        state.data_storage.remove_file(&file_info).await?;
+        let removed = match &state.trash {
+            Some(trash) => trash::put(&state, trash.as_ref(), &file_info).await,
+            None => state.info_storage.remove_info(file_id.as_str()).await,
+        };
+        match removed {
+            // Upload was removed by a concurrent request.
+            Err(RustusError::FileNotFound) if state.config.idempotent_termination => {
+                return Ok(HttpResponse::NoContent().finish());
+            }
+            result => result?,
+        }
+        if state.trash.is_none() {
+            match state.data_storage.remove_file(&file_info).await {
+                Err(RustusError::FileNotFound) if state.config.idempotent_termination => {}
+                result => result?,
+            }
+        }
+        tombstones::bury(&state, file_id.as_str()).await;
+        state.progress.publish_terminated(&file_info);
         metrics.terminated_uploads.inc();
-        if state.config.hook_is_active(Hook::PostTerminate) {
+        if state.config.hook_is_active(Hook::TerminateCleanup) {
             let message = state.config.notification_opts.hooks_format.format(
                 &request,
                 &file_info,
-                state.config.notification_opts.behind_proxy,
+                &state.config.client_ip,
             );
-            let headers = request.headers().clone();
-            tokio::task::spawn_local(async move {
+            // Upload is already removed, so failures are only retried and logged.
+            state
+                .notification_manager
+                .deliver_cleanup(message, request.headers())
+                .await;
+        }
+        let message = state.config.hook_is_active(Hook::PostTerminate).then(|| {
+            state.config.notification_opts.hooks_format.format(
+                &request,
+                &file_info,
+                &state.config.client_ip,
+            )
+        });
+        let headers = request.headers().clone();
+        tokio::task::spawn_local(async move {
+            // The last progress is delivered before the upload is terminated.
+            state
+                .notification_manager
+                .flush_receive(file_info.id.as_str())
+                .await;
+            if let Some(message) = message {
                 state
                     .notification_manager
-                    .send_message(message, Hook::PostTerminate, &headers)
-                    .await
-            });
-        }
+                    .deliver(message, Hook::PostTerminate, &headers)
+                    .await;
+            }
+        });
     }
     Ok(HttpResponse::NoContent().finish())
 }
@@ -59,7 +107,7 @@ pub async fn terminate(
 mod tests {
     use crate::{server::test::get_service, State};
     use actix_web::{
-        http::StatusCode,
+        http::{Method, StatusCode},
         test::{call_service, TestRequest},
     };
     use std::path::PathBuf;
@@ -93,6 +141,143 @@ mod tests {
         assert_eq!(result.status(), StatusCode::NOT_FOUND);
     }
 
+    #[actix_rt::test]
+    async fn repeated_termination() {
+        let state = State::test_new().await;
+        let mut rustus = get_service(state.clone()).await;
+        let file_info = state.create_test_file().await;
+        let request = TestRequest::delete()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let request = TestRequest::delete()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn assembling_upload() {
+        let state = State::test_new().await;
+        let rustus = get_service(state.clone()).await;
+        let mut file_info = state.create_test_file().await;
+        file_info.is_final = true;
+        file_info.assembling = true;
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let request = TestRequest::delete()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let response = call_service(&rustus, request).await;
+        assert_eq!(response.status().as_u16(), 425);
+        assert!(state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn idempotent_termination() {
+        let mut state = State::test_new().await;
+        state.config.idempotent_termination = true;
+        let mut rustus = get_service(state.clone()).await;
+        let file_info = state.create_test_file().await;
+        for _ in 0..2 {
+            let request = TestRequest::delete()
+                .uri(state.config.file_url(file_info.id.as_str()).as_str())
+                .to_request();
+            let response = call_service(&mut rustus, request).await;
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        }
+        let request = TestRequest::delete()
+            .uri(state.config.file_url("never_existed").as_str())
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[actix_rt::test]
+    async fn grace_period() {
+        let mut state = State::test_new().await;
+        state.enable_test_trash(3600).await;
+        let rustus = get_service(state.clone()).await;
+        let file_info = state.create_test_file().await;
+        let request = TestRequest::delete()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let response = call_service(&rustus, request).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let request = TestRequest::default()
+            .method(Method::HEAD)
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let response = call_service(&rustus, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // Data is kept until the grace period is over.
+        assert!(PathBuf::from(file_info.path.unwrap()).exists());
+        let trash = state.trash.as_ref().unwrap();
+        assert!(trash.get_info(file_info.id.as_str()).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn tombstones() {
+        let mut state = State::test_new().await;
+        state.config.tombstone_ttl = Some(60);
+        let mut rustus = get_service(state.clone()).await;
+        let file_info = state.create_test_file().await;
+        let request = TestRequest::delete()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let request = TestRequest::default()
+            .method(Method::HEAD)
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::GONE);
+        let request = TestRequest::patch()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .insert_header(("Upload-Offset", "0"))
+            .insert_header(("Content-Type", "application/offset+octet-stream"))
+            .set_payload("memes")
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::GONE);
+        // Uploads which never existed aren't affected.
+        let request = TestRequest::default()
+            .method(Method::HEAD)
+            .uri(state.config.file_url("never_existed").as_str())
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn idempotent_missing_data() {
+        let mut state = State::test_new().await;
+        state.config.idempotent_termination = true;
+        let mut rustus = get_service(state.clone()).await;
+        let file_info = state.create_test_file().await;
+        std::fs::remove_file(file_info.path.clone().unwrap()).unwrap();
+        let request = TestRequest::delete()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .is_err());
+    }
+
     #[actix_rt::test]
     async fn wrong_storage() {
         let state = State::test_new().await;
@@ -110,4 +295,36 @@ mod tests {
         let response = call_service(&mut rustus, request).await;
         assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
+
+    #[actix_rt::test]
+    async fn cleanup_hook() {
+        let mut server = httptest::Server::run();
+        server.expect(
+            httptest::Expectation::matching(httptest::matchers::request::method_path(
+                "POST", "/hook",
+            ))
+            .times(2)
+            .respond_with(httptest::cycle![
+                httptest::responders::status_code(500),
+                httptest::responders::status_code(200)
+            ]),
+        );
+        let mut state = State::test_new().await;
+        state.config.notification_opts.hooks = vec![crate::notifiers::Hook::TerminateCleanup];
+        state.config.notification_opts.hooks_http_urls = vec![server.url_str("/hook")];
+        state.config.notification_opts.hooks_cleanup_retries = 1;
+        state.config.notification_opts.hooks_retry_backoff = 1;
+        state.notification_manager = crate::NotificationManager::new(&state.config)
+            .await
+            .unwrap();
+        let mut rustus = get_service(state.clone()).await;
+        let file_info = state.create_test_file().await;
+        let request = TestRequest::delete()
+            .uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .to_request();
+        // Hook is retried before the response is sent.
+        let response = call_service(&mut rustus, request).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        server.verify_and_clear();
+    }
 }
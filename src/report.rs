@@ -0,0 +1,267 @@
+use std::io::Write;
+
+use chrono::{DateTime, NaiveDate, SecondsFormat, TimeZone, Utc};
+use derive_more::Display;
+use serde_json::json;
+use strum::EnumIter;
+
+use crate::{
+    config::ReportOptions,
+    errors::RustusResult,
+    from_str,
+    info_storages::{FileInfo, InfoStorage, UploadFilter, UploadPages},
+};
+
+/// Number of uploads read from info storage at once.
+const PAGE_SIZE: usize = 1000;
+
+/// Columns of the report.
+const COLUMNS: [&str; 5] = ["id", "tenant", "size", "created_at", "finished"];
+
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq, EnumIter)]
+#[allow(clippy::module_name_repetitions)]
+pub enum ReportFormat {
+    #[display(fmt = "csv")]
+    Csv,
+    /// JSON array of objects.
+    #[display(fmt = "json")]
+    Json,
+}
+
+from_str!(ReportFormat, "format");
+
+/// Parse date in RFC 3339 format or a day, like `2024-01-31`.
+///
+/// Days start at midnight UTC.
+pub fn parse_date(input: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(input, "%Y-%m-%d")
+        .ok()
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .map(|time| Utc.from_utc_datetime(&time))
+        .ok_or_else(|| format!("'{input}' is not a date, like 2024-01-31 or 2024-01-31T12:00:00Z"))
+}
+
+/// Quote CSV field if it contains special characters.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        String::from(value)
+    }
+}
+
+/// Writes rows of the report one by one.
+#[allow(clippy::module_name_repetitions)]
+struct ReportWriter<'a, W: Write> {
+    format: ReportFormat,
+    tenant_key: &'a str,
+    out: W,
+    rows: usize,
+}
+
+impl<W: Write> ReportWriter<'_, W> {
+    fn start(&mut self) -> std::io::Result<()> {
+        match self.format {
+            ReportFormat::Csv => writeln!(self.out, "{}", COLUMNS.join(",")),
+            ReportFormat::Json => write!(self.out, "["),
+        }
+    }
+
+    fn write(&mut self, file_info: &FileInfo) -> RustusResult<()> {
+        let tenant = file_info.metadata.get(self.tenant_key);
+        let size = file_info.length.unwrap_or(file_info.offset);
+        let created_at = file_info
+            .created_at
+            .to_rfc3339_opts(SecondsFormat::Secs, true);
+        let finished = file_info.length == Some(file_info.offset);
+        match self.format {
+            ReportFormat::Csv => writeln!(
+                self.out,
+                "{},{},{size},{created_at},{finished}",
+                csv_field(file_info.id.as_str()),
+                csv_field(tenant.map_or("", String::as_str)),
+            )?,
+            ReportFormat::Json => {
+                if self.rows > 0 {
+                    write!(self.out, ",")?;
+                }
+                let row = json!({
+                    "id": file_info.id,
+                    "tenant": tenant,
+                    "size": size,
+                    "created_at": created_at,
+                    "finished": finished,
+                });
+                write!(self.out, "\n  {row}")?;
+            }
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> std::io::Result<W> {
+        if self.format == ReportFormat::Json {
+            let end = if self.rows > 0 { "\n]" } else { "]" };
+            writeln!(self.out, "{end}")?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Write report about uploads.
+///
+/// Uploads are read by pages in order of their IDs,
+/// so only one page is kept in memory.
+/// Returns the writer and the number of written uploads.
+#[allow(clippy::module_name_repetitions)]
+pub async fn write_report<W: Write>(
+    info_storage: &dyn InfoStorage,
+    options: &ReportOptions,
+    tenant_key: &str,
+    out: W,
+) -> RustusResult<(W, usize)> {
+    let mut writer = ReportWriter {
+        format: options.format,
+        tenant_key,
+        out,
+        rows: 0,
+    };
+    writer.start()?;
+    let filter = UploadFilter {
+        created_after: options.since.map(|since| since.timestamp()),
+        created_before: options.until.map(|until| until.timestamp()),
+        ..UploadFilter::default()
+    };
+    // Pages start after the last read ID, so uploads
+    // created during the export don't shift pages.
+    let mut pages = UploadPages::new(info_storage, filter, PAGE_SIZE);
+    while let Some(page) = pages.next_page().await? {
+        for file_info in &page {
+            writer.write(file_info)?;
+        }
+    }
+    let rows = writer.rows;
+    Ok((writer.finish()?, rows))
+}
+
+/// Write the report to stdout or to the output file.
+pub async fn export(
+    info_storage: &dyn InfoStorage,
+    options: &ReportOptions,
+    tenant_key: &str,
+) -> std::io::Result<()> {
+    let rows = if let Some(path) = &options.output {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        write_report(info_storage, options, tenant_key, file)
+            .await?
+            .1
+    } else {
+        let stdout = std::io::stdout().lock();
+        write_report(info_storage, options, tenant_key, stdout)
+            .await?
+            .1
+    };
+    log::info!("Report contains {rows} uploads.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_date, write_report, ReportFormat, PAGE_SIZE};
+    use crate::{config::ReportOptions, State};
+    use chrono::{TimeZone, Utc};
+
+    fn options(format: ReportFormat) -> ReportOptions {
+        ReportOptions {
+            format,
+            since: None,
+            until: None,
+            output: None,
+        }
+    }
+
+    #[test]
+    fn dates() {
+        let day = Utc.with_ymd_and_hms(2024, 1, 31, 0, 0, 0).unwrap();
+        assert_eq!(parse_date("2024-01-31"), Ok(day));
+        assert_eq!(
+            parse_date("2024-01-31T12:00:00+02:00"),
+            Ok(Utc.with_ymd_and_hms(2024, 1, 31, 10, 0, 0).unwrap())
+        );
+        assert!(parse_date("31.01.2024").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn csv_report() {
+        let state = State::test_new().await;
+        let mut finished = state.create_test_file().await;
+        finished.offset = 10;
+        finished
+            .metadata
+            .insert(String::from("tenant"), String::from("memes, inc"));
+        state.info_storage.set_info(&finished, false).await.unwrap();
+        let mut old = state.create_test_file().await;
+        old.created_at = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        state.info_storage.set_info(&old, false).await.unwrap();
+
+        let mut options = options(ReportFormat::Csv);
+        options.since = Some(Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap());
+        let (report, rows) = write_report(state.info_storage.as_ref(), &options, "tenant", vec![])
+            .await
+            .unwrap();
+        assert_eq!(rows, 1);
+        let report = String::from_utf8(report).unwrap();
+        assert_eq!(
+            report,
+            format!(
+                "id,tenant,size,created_at,finished\n{},\"memes, inc\",10,{},true\n",
+                finished.id,
+                finished
+                    .created_at
+                    .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            )
+        );
+    }
+
+    #[actix_rt::test]
+    async fn json_report() {
+        let state = State::test_new().await;
+        let mut uploads = Vec::new();
+        // Uploads are read in several pages.
+        for _ in 0..=PAGE_SIZE {
+            uploads.push(state.create_test_file().await.id);
+        }
+        let (report, rows) = write_report(
+            state.info_storage.as_ref(),
+            &options(ReportFormat::Json),
+            "tenant",
+            vec![],
+        )
+        .await
+        .unwrap();
+        assert_eq!(rows, uploads.len());
+        let report: Vec<serde_json::Value> = serde_json::from_slice(report.as_slice()).unwrap();
+        let mut ids = report
+            .iter()
+            .map(|row| String::from(row["id"].as_str().unwrap()))
+            .collect::<Vec<_>>();
+        ids.sort();
+        uploads.sort();
+        assert_eq!(ids, uploads);
+        assert_eq!(report[0]["size"], 10);
+        assert_eq!(report[0]["tenant"], serde_json::Value::Null);
+        assert_eq!(report[0]["finished"], false);
+
+        let mut options = options(ReportFormat::Json);
+        options.until = Some(Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap());
+        let (report, rows) = write_report(state.info_storage.as_ref(), &options, "tenant", vec![])
+            .await
+            .unwrap();
+        assert_eq!(rows, 0);
+        assert_eq!(report, b"[]\n");
+    }
+}
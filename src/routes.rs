@@ -1,4 +1,10 @@
 use actix_web::HttpResponse;
+use chrono::{SecondsFormat, TimeZone, Utc};
+
+use crate::{
+    protocol::extensions::Extensions, storages::AvailableStores,
+    utils::hashes::CHECKSUM_ALGORITHMS, RustusConf,
+};
 
 /// Default response to all unknown URLs.
 /// All protocol urls can be found
@@ -6,7 +12,9 @@ use actix_web::HttpResponse;
 #[allow(clippy::unused_async)]
 #[cfg_attr(coverage, no_coverage)]
 pub async fn not_found() -> HttpResponse {
-    HttpResponse::NotFound().finish()
+    HttpResponse::NotFound()
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .finish()
 }
 
 /// Checks that application is accepting connections correctly.
@@ -15,3 +23,202 @@ pub async fn not_found() -> HttpResponse {
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+/// Information about the server.
+///
+/// It's returned at the root URL,
+/// so humans and scripts can verify
+/// that they talk to rustus.
+pub fn root_info(config: &RustusConf) -> String {
+    let extensions = config
+        .tus_extensions
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "name": "rustus",
+        "version": env!("CARGO_PKG_VERSION"),
+        "extensions": extensions,
+    })
+    .to_string()
+}
+
+/// Optional features which rustus was built with.
+fn enabled_features() -> Vec<&'static str> {
+    [
+        ("amqp_notifier", cfg!(feature = "amqp_notifier")),
+        ("db_info_storage", cfg!(feature = "db_info_storage")),
+        ("null_storage", cfg!(feature = "null_storage")),
+        ("otlp_tracing", cfg!(feature = "otlp_tracing")),
+        ("progress_websocket", cfg!(feature = "progress_websocket")),
+        ("redis_info_storage", cfg!(feature = "redis_info_storage")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(name, _)| name)
+    .collect()
+}
+
+/// Version and build of the server.
+///
+/// It's returned at `/version`, so orchestration can verify
+/// which build every node runs. Values are set at build time
+/// and don't depend on the configuration.
+pub fn version_info() -> serde_json::Value {
+    let commit = Some(env!("RUSTUS_GIT_COMMIT")).filter(|commit| !commit.is_empty());
+    let built_at = env!("RUSTUS_BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+        .map(|built_at| built_at.to_rfc3339_opts(SecondsFormat::Secs, true));
+    serde_json::json!({
+        "name": "rustus",
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_commit": commit,
+        "build_timestamp": built_at,
+        "features": enabled_features(),
+    })
+}
+
+/// Capabilities and limits of the server.
+///
+/// It's returned at `/info`, so clients and orchestration can
+/// adapt to the configuration without parsing
+/// headers of OPTIONS requests.
+/// Secrets, DSNs and paths are never included.
+pub fn capabilities(config: &RustusConf, draining: bool, cleanup_paused: bool) -> String {
+    let extensions = config
+        .tus_extensions
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    let checksum_algorithms = if config.tus_extensions.contains(&Extensions::Checksum) {
+        CHECKSUM_ALGORITHMS.to_vec()
+    } else {
+        Vec::new()
+    };
+    #[cfg(feature = "redis_info_storage")]
+    let info_expiration = config.info_storage_opts.redis_info_expiration;
+    #[cfg(not(feature = "redis_info_storage"))]
+    let info_expiration: Option<usize> = None;
+    serde_json::json!({
+        "name": "rustus",
+        "version": env!("CARGO_PKG_VERSION"),
+        "tus_version": "1.0.0",
+        "url": format!("/{}/", config.base_url()),
+        "extensions": extensions,
+        "checksum_algorithms": checksum_algorithms,
+        "max_file_size": config.max_file_size,
+        "max_body_size": config.max_body_size,
+        "allow_empty": config.allow_empty,
+        "allow_restart": config.allow_restart,
+        "multipart_uploads": config.multipart_uploads,
+        "upload_checksum": config.upload_checksum,
+        "signed_downloads": config.download_signing_secret.is_some(),
+        "draining": draining,
+        "cleanup_paused": cleanup_paused,
+        "storage": config.storage_opts.storage.to_string(),
+        "storages": AvailableStores::names(),
+        "info_storage": config.info_storage_opts.info_storage.to_string(),
+        "ttl": {
+            "signed_url": config.download_signing_secret.as_ref().map(|_| config.signed_url_ttl),
+            "info_expiration": info_expiration,
+            "delete_after_download": config.delete_after_download.map(|policy| policy.to_string()),
+        },
+    })
+    .to_string()
+}
+
+/// Response for the root URL.
+#[cfg_attr(coverage, no_coverage)]
+///
+/// It's also used for `/info` and `/version`.
+pub fn root(info: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header(("Tus-Resumable", "1.0.0"))
+        .body(String::from(info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{capabilities, not_found, root_info, version_info};
+    use crate::RustusConf;
+
+    #[actix_rt::test]
+    async fn not_found_has_tus_header() {
+        let response = not_found().await;
+        assert_eq!(response.status(), actix_web::http::StatusCode::NOT_FOUND);
+        assert_eq!(response.headers().get("Tus-Resumable").unwrap(), "1.0.0");
+    }
+
+    #[test]
+    fn root_info_contents() {
+        let config = RustusConf::from_iter(vec!["rustus", "--tus-extensions", "creation,getting"]);
+        let info: serde_json::Value = serde_json::from_str(root_info(&config).as_str()).unwrap();
+        assert_eq!(info["name"], "rustus");
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            info["extensions"],
+            serde_json::json!(["creation", "getting"])
+        );
+    }
+
+    #[test]
+    fn version_contents() {
+        let info = version_info();
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert!(info["build_timestamp"].is_string());
+        let features = info["features"].as_array().unwrap();
+        assert_eq!(
+            features.contains(&serde_json::json!("db_info_storage")),
+            cfg!(feature = "db_info_storage")
+        );
+    }
+
+    #[test]
+    fn capabilities_contents() {
+        let config = RustusConf::from_iter(vec![
+            "rustus",
+            "--tus-extensions",
+            "creation,checksum",
+            "--max-file-size",
+            "100",
+            "--download-signing-secret",
+            "very-secret",
+            "--signed-url-ttl",
+            "60",
+        ]);
+        let info = capabilities(&config, false, false);
+        assert!(!info.contains("very-secret"));
+        let info: serde_json::Value = serde_json::from_str(info.as_str()).unwrap();
+        assert_eq!(
+            info["extensions"],
+            serde_json::json!(["creation", "checksum"])
+        );
+        assert_eq!(
+            info["checksum_algorithms"],
+            serde_json::json!(["md5", "sha1", "sha256", "sha512"])
+        );
+        assert_eq!(info["max_file_size"], 100);
+        assert_eq!(info["url"], "/files/");
+        assert_eq!(info["storage"], "file-storage");
+        assert_eq!(info["signed_downloads"], true);
+        assert_eq!(info["draining"], false);
+        assert_eq!(info["cleanup_paused"], false);
+        assert_eq!(info["ttl"]["signed_url"], 60);
+        assert!(info["ttl"]["delete_after_download"].is_null());
+    }
+
+    #[test]
+    fn capabilities_without_checksum() {
+        let config = RustusConf::from_iter(vec!["rustus", "--tus-extensions", "creation"]);
+        let info: serde_json::Value =
+            serde_json::from_str(capabilities(&config, true, true).as_str()).unwrap();
+        assert_eq!(info["checksum_algorithms"], serde_json::json!([]));
+        assert!(info["max_file_size"].is_null());
+        assert!(info["ttl"]["signed_url"].is_null());
+        assert_eq!(info["draining"], true);
+        assert_eq!(info["cleanup_paused"], true);
+    }
+}
@@ -0,0 +1,253 @@
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    storages::Storage,
+};
+
+/// Chunks written to the test upload.
+const CHUNKS: [&str; 3] = ["rustus ", "self-test ", "data"];
+
+/// Result of a single operation.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Status {
+    Passed,
+    Failed(String),
+    /// Storage doesn't support the operation.
+    Skipped,
+}
+
+/// Result of a self-test step.
+#[derive(Debug)]
+pub struct Check {
+    pub operation: &'static str,
+    pub status: Status,
+}
+
+/// Results of all steps of the self-test.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Record result of an operation.
+    ///
+    /// Returns true if the operation didn't fail.
+    fn record<T>(&mut self, operation: &'static str, result: &RustusResult<T>) -> bool {
+        let status = match result {
+            Ok(_) => Status::Passed,
+            Err(RustusError::Unimplemented(_)) => Status::Skipped,
+            Err(err) => Status::Failed(err.to_string()),
+        };
+        let passed = !matches!(status, Status::Failed(_));
+        self.checks.push(Check { operation, status });
+        passed
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| !matches!(check.status, Status::Failed(_)))
+    }
+
+    /// Print results of all steps.
+    #[cfg_attr(coverage, no_coverage)]
+    pub fn print(&self) {
+        for check in &self.checks {
+            match &check.status {
+                Status::Passed => println!("{:<16} ok", check.operation),
+                Status::Skipped => println!("{:<16} not supported", check.operation),
+                Status::Failed(reason) => println!("{:<16} FAILED: {reason}", check.operation),
+            }
+        }
+        if self.is_success() {
+            println!("Storage self-test passed.");
+        } else {
+            println!("Storage self-test failed.");
+        }
+    }
+}
+
+/// Read contents of the upload and compare them with expected bytes.
+async fn check_contents(
+    storage: &dyn Storage,
+    file_info: &FileInfo,
+    expected: &[u8],
+) -> RustusResult<()> {
+    let mut body = storage.read_data(file_info, 0..expected.len()).await?;
+    let mut contents = BytesMut::new();
+    while let Some(chunk) = body.next().await {
+        contents.extend_from_slice(chunk?.as_ref());
+    }
+    if contents.as_ref() != expected {
+        return Err(RustusError::UnableToWrite(format!(
+            "Downloaded {} bytes don't match uploaded {} bytes.",
+            contents.len(),
+            expected.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Check that data of the upload exists.
+async fn check_exists(storage: &dyn Storage, file_info: &FileInfo) -> RustusResult<()> {
+    if storage.data_exists(file_info).await? {
+        return Ok(());
+    }
+    Err(RustusError::UnableToWrite(String::from(
+        "Data wasn't found.",
+    )))
+}
+
+/// Upload data to the storage, read it back and remove it.
+///
+/// Unlike `prepare`, it calls every method of the storage,
+/// so missing permissions are found before clients see them.
+/// If bytes can't be written, steps which read them are skipped.
+pub async fn run(storage: &dyn Storage) -> Report {
+    let mut report = Report::default();
+    let data = CHUNKS.concat();
+    let mut file_info = FileInfo::new(
+        format!("rustus-selftest-{}", uuid::Uuid::new_v4()).as_str(),
+        Some(data.len()),
+        None,
+        storage.to_string(),
+        None,
+    );
+    let created = storage.create_file(&file_info).await;
+    if !report.record("create_file", &created) {
+        return report;
+    }
+    file_info.path = created.ok();
+
+    let mut written = Ok(());
+    for chunk in CHUNKS {
+        written = storage.add_bytes(&file_info, Bytes::from(chunk)).await;
+        if written.is_err() {
+            break;
+        }
+        file_info.offset += chunk.len();
+    }
+    if report.record("add_bytes", &written) {
+        let synced = storage.sync_data(&file_info).await;
+        report.record("sync_data", &synced);
+        let exists = check_exists(storage, &file_info).await;
+        report.record("data_exists", &exists);
+        let contents = check_contents(storage, &file_info, data.as_bytes()).await;
+        report.record("get_contents", &contents);
+        concat(storage, &mut report, &file_info, data.as_bytes()).await;
+        let listed = storage.list_paths().await;
+        report.record("list_paths", &listed);
+        truncate(storage, &mut report, &mut file_info).await;
+    }
+
+    let removed = storage.remove_file(&file_info).await;
+    report.record("remove_file", &removed);
+    report
+}
+
+/// Concatenate the upload into a new one.
+///
+/// The new upload is removed afterwards.
+async fn concat(storage: &dyn Storage, report: &mut Report, part: &FileInfo, data: &[u8]) {
+    let mut final_info = FileInfo::new(
+        format!("rustus-selftest-{}", uuid::Uuid::new_v4()).as_str(),
+        None,
+        None,
+        storage.to_string(),
+        None,
+    );
+    final_info.is_final = true;
+    final_info.parts = Some(vec![part.id.clone()]);
+    let result = match storage.create_file(&final_info).await {
+        Ok(path) => {
+            final_info.path = Some(path);
+            let mut result = storage.concat_files(&final_info, vec![part.clone()]).await;
+            if result.is_ok() {
+                final_info.offset = data.len();
+                final_info.length = Some(data.len());
+                result = check_contents(storage, &final_info, data).await;
+            }
+            let removed = storage.remove_file(&final_info).await;
+            // Storages which can't concatenate files
+            // may not have any data to remove.
+            if result.is_ok() {
+                result = removed;
+            }
+            result
+        }
+        Err(err) => Err(err),
+    };
+    report.record("concat_files", &result);
+}
+
+/// Remove the last chunk of the upload.
+async fn truncate(storage: &dyn Storage, report: &mut Report, file_info: &mut FileInfo) {
+    if storage.accepts_out_of_order() {
+        return;
+    }
+    let offset = file_info.offset;
+    file_info.offset = CHUNKS[0].len();
+    let mut result = storage.truncate(file_info).await;
+    if result.is_ok() {
+        // Removed chunks are written again,
+        // so the upload must have the same contents.
+        result = storage
+            .add_bytes(file_info, Bytes::from(CHUNKS[1..].concat()))
+            .await;
+        if result.is_ok() {
+            file_info.offset = offset;
+            result = check_contents(storage, file_info, CHUNKS.concat().as_bytes()).await;
+        }
+    } else {
+        file_info.offset = offset;
+    }
+    report.record("truncate", &result);
+}
+
+/// Run self-test of the storage and print its results.
+///
+/// Process exits with code 1 if the test failed.
+#[cfg_attr(coverage, no_coverage)]
+pub async fn check(storage: &dyn Storage) -> std::io::Result<()> {
+    // Some storages spawn local tasks.
+    let report = tokio::task::LocalSet::new().run_until(run(storage)).await;
+    report.print();
+    if !report.is_success() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, Status};
+    use crate::storages::{file_storage::FileStorage, Storage};
+
+    #[actix_rt::test]
+    async fn file_storage() {
+        let dir = tempdir::TempDir::new("selftest").unwrap();
+        let mut storage = FileStorage::new(dir.path().to_path_buf(), String::new(), false);
+        storage.prepare().await.unwrap();
+        let report = run(&storage).await;
+        for check in &report.checks {
+            assert_eq!(check.status, Status::Passed, "{}", check.operation);
+        }
+        assert!(report.is_success());
+        // Test uploads are removed.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn failed_storage() {
+        let dir = tempdir::TempDir::new("selftest").unwrap();
+        let storage = FileStorage::new(dir.path().join("unknown"), String::new(), false);
+        let report = run(&storage).await;
+        assert!(!report.is_success());
+        assert_eq!(report.checks.len(), 1);
+        assert_eq!(report.checks[0].operation, "create_file");
+    }
+}
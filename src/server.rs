@@ -1,8 +1,16 @@
-use crate::{protocol, State};
-use actix_web::{middleware, web, web::PayloadConfig};
+use crate::{
+    protocol,
+    utils::{
+        headers::check_headers_size, introspection::IntrospectionAuth, retry_after::RetryAfter,
+    },
+    State,
+};
+use actix_web::{dev::Service, middleware, web, web::PayloadConfig};
 
 pub fn rustus_service(state: State) -> impl Fn(&mut web::ServiceConfig) {
     move |web_app| {
+        let max_header_size = state.config.max_header_size;
+        let retry_after = RetryAfter::from_config(&state.config);
         web_app.service(
             web::scope(state.config.base_url().as_str())
                 .app_data(web::Data::new(state.clone()))
@@ -10,12 +18,37 @@ pub fn rustus_service(state: State) -> impl Fn(&mut web::ServiceConfig) {
                 .wrap(middleware::NormalizePath::new(
                     middleware::TrailingSlash::Always,
                 ))
+                // Big headers are rejected with a clear reason,
+                // since they are usually caused by metadata.
+                .wrap_fn(move |req, srv| {
+                    let call = match check_headers_size(req.headers(), max_header_size) {
+                        Ok(()) => Ok(srv.call(req)),
+                        Err(err) => Err(req.error_response(err)),
+                    };
+                    async move {
+                        match call {
+                            Ok(fut) => Ok(fut.await?.map_into_boxed_body()),
+                            Err(response) => Ok(response),
+                        }
+                    }
+                })
+                // Clients are authorized by their tokens if it's configured.
+                .wrap(IntrospectionAuth::from_config(&state.config))
                 // Main middleware that appends TUS headers.
                 .wrap(
                     middleware::DefaultHeaders::new()
                         .add(("Tus-Resumable", "1.0.0"))
                         .add(("Tus-Version", "1.0.0")),
                 )
+                // Clients of an overloaded server are told when to retry.
+                .wrap_fn(move |req, srv| {
+                    let fut = srv.call(req);
+                    async move {
+                        let mut response = fut.await?;
+                        retry_after.apply(response.status(), response.headers_mut());
+                        Ok(response)
+                    }
+                })
                 .configure(protocol::setup(state.config.clone())),
         );
     }
@@ -34,7 +67,7 @@ pub mod test {
         Response = ServiceResponse,
         Error = actix_web::Error,
     > {
-        let metrics = RustusMetrics::new().unwrap();
+        let metrics = RustusMetrics::new(&state.config).unwrap();
         init_service(
             App::new()
                 .app_data(web::Data::new(metrics))
@@ -1,13 +1,44 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use tokio::sync::Semaphore;
+
 #[cfg(test)]
 use crate::info_storages::FileInfo;
-use crate::{InfoStorage, NotificationManager, RustusConf, Storage};
+use crate::{
+    background::{cleanup::CleanupSwitch, compaction::CompactionMetrics},
+    utils::{active_chunks::ActiveChunks, progress::Progress, quota::UsageCache},
+    InfoStorage, NotificationManager, RustusConf, Storage,
+};
 
 #[derive(Clone)]
 pub struct State {
     pub config: RustusConf,
     pub data_storage: Box<dyn Storage + Send + Sync>,
     pub info_storage: Box<dyn InfoStorage + Send + Sync>,
+    /// Information about terminated uploads.
+    ///
+    /// It's set only if termination grace period is enabled.
+    pub trash: Option<Box<dyn InfoStorage + Send + Sync>>,
     pub notification_manager: NotificationManager,
+    /// Whether creation of new uploads is disabled.
+    ///
+    /// It's shared between all workers.
+    draining: Arc<AtomicBool>,
+    /// Switch of background removal of uploads.
+    pub cleanup: CleanupSwitch,
+    /// Changes of uploads.
+    pub progress: Progress,
+    /// Chunks which are being written to uploads.
+    pub active_chunks: ActiveChunks,
+    /// Unique metadata values of uploads which are being created.
+    pub unique_values: ActiveChunks,
+    pub compaction_metrics: CompactionMetrics,
+    pub tenant_usage: UsageCache,
+    /// Permits of derivations which may run at once.
+    pub derivations: Arc<Semaphore>,
 }
 
 impl State {
@@ -17,14 +48,44 @@ impl State {
         info_storage: Box<dyn InfoStorage + Send + Sync>,
         notification_manager: NotificationManager,
     ) -> Self {
+        let derivations = Arc::new(Semaphore::new(
+            config.storage_opts.derive_concurrency.max(1),
+        ));
         Self {
             config,
             data_storage,
             info_storage,
+            trash: None,
             notification_manager,
+            draining: Arc::default(),
+            cleanup: CleanupSwitch::default(),
+            progress: Progress::default(),
+            active_chunks: ActiveChunks::default(),
+            unique_values: ActiveChunks::default(),
+            compaction_metrics: CompactionMetrics::default(),
+            tenant_usage: UsageCache::default(),
+            derivations,
         }
     }
 
+    /// Keep information about terminated uploads in the trash.
+    pub fn with_trash(mut self, trash: Box<dyn InfoStorage + Send + Sync>) -> Self {
+        self.trash = Some(trash);
+        self
+    }
+
+    /// Check if server is in drain mode.
+    ///
+    /// In drain mode new uploads are rejected,
+    /// but existing ones can be finished.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    pub fn set_draining(&self, draining: bool) {
+        self.draining.store(draining, Ordering::Relaxed);
+    }
+
     #[cfg(test)]
     pub async fn from_config_test(config: RustusConf) -> Self {
         Self {
@@ -39,7 +100,18 @@ impl State {
                     config.info_storage_opts.info_dir.clone(),
                 ),
             ),
+            trash: None,
             notification_manager: NotificationManager::new(&config).await.unwrap(),
+            draining: Arc::default(),
+            cleanup: CleanupSwitch::default(),
+            progress: Progress::default(),
+            active_chunks: ActiveChunks::default(),
+            unique_values: ActiveChunks::default(),
+            compaction_metrics: CompactionMetrics::default(),
+            tenant_usage: UsageCache::default(),
+            derivations: Arc::new(Semaphore::new(
+                config.storage_opts.derive_concurrency.max(1),
+            )),
         }
     }
 
@@ -60,6 +132,17 @@ impl State {
         Self::from_config_test(config).await
     }
 
+    /// Enable the trash with the given grace period.
+    #[cfg(test)]
+    pub async fn enable_test_trash(&mut self, grace_period: u64) {
+        let mut trash = crate::info_storages::file_info_storage::FileInfoStorage::new(
+            self.config.info_storage_opts.info_dir.join(".trash"),
+        );
+        trash.prepare().await.unwrap();
+        self.config.termination_grace_period = Some(grace_period);
+        self.trash = Some(Box::new(trash));
+    }
+
     #[cfg(test)]
     pub async fn create_test_file(&self) -> FileInfo {
         let mut new_file = FileInfo::new(
@@ -69,7 +152,10 @@ impl State {
             self.data_storage.to_string(),
             None,
         );
-        new_file.path = Some(self.data_storage.create_file(&new_file).await.unwrap());
+        self.data_storage
+            .create_upload(&mut new_file)
+            .await
+            .unwrap();
         self.info_storage.set_info(&new_file, true).await.unwrap();
         new_file
     }
@@ -0,0 +1,224 @@
+use std::ops::Range;
+
+use actix_web::{HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use bytes::Bytes;
+use derive_more::Display;
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    storages::{Compaction, DataLocation, DataStream, Storage},
+    utils::import::ImportSource,
+};
+
+/// Storage wrapper that writes chunks in multiples of `alignment`.
+///
+/// Only aligned part of every chunk is written,
+/// the rest is sent by the client again with the next chunk.
+/// So offset of the upload counts only bytes in the storage.
+/// The last chunk of an upload is written as is.
+///
+/// Writes and truncations at unaligned offsets are rejected,
+/// so the inner storage never gets unaligned chunks.
+#[derive(Display, Clone)]
+#[display(fmt = "{inner}")]
+pub struct AlignedStorage {
+    inner: Box<dyn Storage + Send + Sync>,
+    alignment: usize,
+}
+
+impl AlignedStorage {
+    #[must_use]
+    pub fn new(inner: Box<dyn Storage + Send + Sync>, alignment: usize) -> Self {
+        Self { inner, alignment }
+    }
+
+    fn is_aligned(&self, offset: usize) -> bool {
+        offset.is_multiple_of(self.alignment)
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for AlignedStorage {
+    async fn prepare(&mut self) -> RustusResult<()> {
+        self.inner.prepare().await
+    }
+
+    async fn get_contents(
+        &self,
+        file_info: &FileInfo,
+        request: &HttpRequest,
+    ) -> RustusResult<HttpResponse> {
+        self.inner.get_contents(file_info, request).await
+    }
+
+    async fn read_data(
+        &self,
+        file_info: &FileInfo,
+        range: Range<usize>,
+    ) -> RustusResult<DataStream> {
+        self.inner.read_data(file_info, range).await
+    }
+
+    fn preferred_chunk_size(&self) -> Option<usize> {
+        // Chunks of this size are written as a whole.
+        let size = self.inner.preferred_chunk_size().unwrap_or(1);
+        Some(size.div_ceil(self.alignment) * self.alignment)
+    }
+
+    fn data_location(&self, file_info: &FileInfo) -> Option<DataLocation> {
+        self.inner.data_location(file_info)
+    }
+
+    fn chunk_alignment(&self) -> Option<usize> {
+        Some(self.alignment)
+    }
+
+    async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+        let end = file_info.offset + bytes.len();
+        if !self.is_aligned(file_info.offset)
+            || (!self.is_aligned(end) && file_info.length != Some(end))
+        {
+            return Err(RustusError::UnalignedChunk(self.alignment));
+        }
+        self.inner.add_bytes(file_info, bytes).await
+    }
+
+    async fn truncate(&self, file_info: &FileInfo) -> RustusResult<()> {
+        if !self.is_aligned(file_info.offset) {
+            return Err(RustusError::UnalignedChunk(self.alignment));
+        }
+        self.inner.truncate(file_info).await
+    }
+
+    async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
+        self.inner.create_file(file_info).await
+    }
+
+    async fn create_upload(&self, file_info: &mut FileInfo) -> RustusResult<()> {
+        self.inner.create_upload(file_info).await
+    }
+
+    async fn concat_files(
+        &self,
+        file_info: &FileInfo,
+        parts_info: Vec<FileInfo>,
+    ) -> RustusResult<()> {
+        self.inner.concat_files(file_info, parts_info).await
+    }
+
+    async fn import_file(
+        &self,
+        file_info: &FileInfo,
+        source: &ImportSource,
+        hard_link: bool,
+    ) -> RustusResult<()> {
+        self.inner.import_file(file_info, source, hard_link).await
+    }
+
+    async fn copy_file(&self, source: &FileInfo, target: &FileInfo) -> RustusResult<()> {
+        self.inner.copy_file(source, target).await
+    }
+
+    async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+        self.inner.remove_file(file_info).await
+    }
+
+    async fn sync_data(&self, file_info: &FileInfo) -> RustusResult<()> {
+        self.inner.sync_data(file_info).await
+    }
+
+    async fn data_exists(&self, file_info: &FileInfo) -> RustusResult<bool> {
+        self.inner.data_exists(file_info).await
+    }
+
+    async fn stored_size(&self, file_info: &FileInfo) -> RustusResult<Option<usize>> {
+        self.inner.stored_size(file_info).await
+    }
+
+    async fn compact(&self, uploads: &[FileInfo], threshold: f64) -> RustusResult<Compaction> {
+        self.inner.compact(uploads, threshold).await
+    }
+
+    async fn reconcile(&self, uploads: &[FileInfo]) -> RustusResult<Vec<(FileInfo, String)>> {
+        self.inner.reconcile(uploads).await
+    }
+
+    async fn list_paths(&self) -> RustusResult<Vec<String>> {
+        self.inner.list_paths().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AlignedStorage;
+    use crate::{
+        errors::RustusError,
+        info_storages::FileInfo,
+        storages::{aligned_len, file_storage::FileStorage},
+        Storage,
+    };
+    use bytes::Bytes;
+
+    async fn get_storage() -> AlignedStorage {
+        let data_dir = tempdir::TempDir::new("aligned_data").unwrap().into_path();
+        let mut storage = AlignedStorage::new(
+            Box::new(FileStorage::new(data_dir, String::new(), false)),
+            4,
+        );
+        storage.prepare().await.unwrap();
+        storage
+    }
+
+    /// Write the chunk as the protocol does and return written bytes.
+    async fn write(
+        storage: &AlignedStorage,
+        file_info: &mut FileInfo,
+        data: &'static str,
+    ) -> usize {
+        let written = aligned_len(storage.chunk_alignment(), file_info, data.len());
+        storage
+            .add_bytes(file_info, Bytes::from(&data[..written]))
+            .await
+            .unwrap();
+        file_info.offset += written;
+        written
+    }
+
+    fn stored(file_info: &FileInfo) -> String {
+        std::fs::read_to_string(file_info.path.as_ref().unwrap()).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn aligned_chunks() {
+        let storage = get_storage().await;
+        let mut file_info = FileInfo::new("test_id", Some(11), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+
+        assert_eq!(write(&storage, &mut file_info, "mem").await, 0);
+        assert_eq!(stored(&file_info), "");
+        // Offset counts only written bytes.
+        assert_eq!(write(&storage, &mut file_info, "memes, in").await, 8);
+        assert_eq!(file_info.offset, 8);
+        assert_eq!(stored(&file_info), "memes, i");
+        // The last chunk isn't aligned.
+        assert_eq!(write(&storage, &mut file_info, "nc.").await, 3);
+        assert_eq!(stored(&file_info), "memes, inc.");
+    }
+
+    #[actix_rt::test]
+    async fn unaligned_writes() {
+        let storage = get_storage().await;
+        let mut file_info = FileInfo::new("test_id", Some(11), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        let res = storage.add_bytes(&file_info, Bytes::from("memes")).await;
+        assert!(matches!(res, Err(RustusError::UnalignedChunk(4))));
+        write(&storage, &mut file_info, "memes, ").await;
+        file_info.offset = 2;
+        let res = storage.truncate(&file_info).await;
+        assert!(matches!(res, Err(RustusError::UnalignedChunk(4))));
+        assert_eq!(stored(&file_info), "meme");
+        assert_eq!(storage.chunk_alignment(), Some(4));
+    }
+}
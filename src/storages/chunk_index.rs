@@ -0,0 +1,411 @@
+use async_trait::async_trait;
+use derive_more::{Display, From};
+use dyn_clone::DynClone;
+use serde::{Deserialize, Serialize};
+use strum::EnumIter;
+
+use crate::{errors::RustusResult, from_str, info_storages::InfoStorage, RustusConf};
+
+/// Range of bytes of an upload stored at some location.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedChunk {
+    /// Offset of the first byte of the chunk in the upload.
+    pub start: usize,
+    /// Offset after the last byte of the chunk.
+    pub end: usize,
+    /// Location of stored bytes.
+    ///
+    /// Its format is defined by the storage,
+    /// E.G. a frame of a file or an object key.
+    pub location: String,
+}
+
+impl IndexedChunk {
+    /// Check if the chunk has bytes of the range.
+    #[must_use]
+    pub fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.start < end && start < self.end
+    }
+}
+
+/// Index of chunks of uploads.
+///
+/// It maps ranges of bytes of uploads to locations
+/// where storages keep them, so storages can find
+/// bytes of a range without scanning the whole upload.
+/// Chunks of an upload never overlap.
+#[async_trait(?Send)]
+pub trait ChunkIndex: DynClone {
+    /// Find chunks which have bytes of the range.
+    ///
+    /// Chunks are sorted by their offsets.
+    async fn lookup(
+        &self,
+        upload_id: &str,
+        start: usize,
+        end: usize,
+    ) -> RustusResult<Vec<IndexedChunk>>;
+
+    /// Add chunk of the upload.
+    ///
+    /// Chunks which overlap the new one are replaced,
+    /// since their bytes were written again.
+    async fn insert(&self, upload_id: &str, chunk: IndexedChunk) -> RustusResult<()>;
+
+    /// Remove chunks which have bytes of the range.
+    async fn remove(&self, upload_id: &str, start: usize, end: usize) -> RustusResult<()>;
+}
+
+dyn_clone::clone_trait_object!(ChunkIndex);
+
+/// Stores of chunk indexes.
+#[derive(PartialEq, Eq, Debug, Display, EnumIter, From, Clone, Copy)]
+pub enum AvailableChunkIndexes {
+    /// Keep the index in the info storage.
+    #[display(fmt = "info")]
+    Info,
+    /// Keep the index in Redis.
+    #[cfg(feature = "redis_info_storage")]
+    #[display(fmt = "redis")]
+    Redis,
+}
+
+from_str!(AvailableChunkIndexes, "chunk index");
+
+impl AvailableChunkIndexes {
+    /// Create the chunk index.
+    ///
+    /// # Params
+    /// `config` - Rustus configuration.
+    /// `info_storage` - Storage for information about files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if Redis isn't available.
+    #[cfg_attr(coverage, no_coverage)]
+    #[allow(clippy::unused_async)]
+    #[cfg_attr(not(feature = "redis_info_storage"), allow(unused_variables))]
+    pub async fn get(
+        &self,
+        config: &RustusConf,
+        info_storage: &(dyn InfoStorage + Send + Sync + 'static),
+    ) -> RustusResult<Box<dyn ChunkIndex + Send + Sync>> {
+        match self {
+            Self::Info => Ok(Box::new(InfoChunkIndex::new(dyn_clone::clone_box(
+                info_storage,
+            )))),
+            #[cfg(feature = "redis_info_storage")]
+            Self::Redis => {
+                let dsn = config.storage_opts.chunk_index_dsn.as_deref().ok_or(
+                    crate::errors::RustusError::UnableToPrepareStorage(String::from(
+                        "Redis chunk index requires --chunk-index-dsn.",
+                    )),
+                )?;
+                let mut index = RedisChunkIndex::new(dsn).await?;
+                if let Some(prefix) = &config.storage_opts.storage_prefix {
+                    index = index.with_prefix(prefix.clone());
+                }
+                Ok(Box::new(index))
+            }
+        }
+    }
+}
+
+/// Index which is kept in the info storage.
+///
+/// Chunks of an upload are read and written at once,
+/// so chunks of one upload must not be added concurrently.
+#[derive(Clone)]
+pub struct InfoChunkIndex {
+    info_storage: Box<dyn InfoStorage + Send + Sync>,
+}
+
+impl InfoChunkIndex {
+    #[must_use]
+    pub fn new(info_storage: Box<dyn InfoStorage + Send + Sync>) -> Self {
+        Self { info_storage }
+    }
+}
+
+#[async_trait(?Send)]
+impl ChunkIndex for InfoChunkIndex {
+    async fn lookup(
+        &self,
+        upload_id: &str,
+        start: usize,
+        end: usize,
+    ) -> RustusResult<Vec<IndexedChunk>> {
+        let mut chunks = self.info_storage.get_chunks(upload_id).await?;
+        chunks.retain(|chunk| chunk.overlaps(start, end));
+        Ok(chunks)
+    }
+
+    async fn insert(&self, upload_id: &str, chunk: IndexedChunk) -> RustusResult<()> {
+        let mut chunks = self.info_storage.get_chunks(upload_id).await?;
+        chunks.retain(|indexed| !indexed.overlaps(chunk.start, chunk.end));
+        chunks.push(chunk);
+        chunks.sort_by_key(|chunk| chunk.start);
+        self.info_storage
+            .set_chunks(upload_id, chunks.as_slice())
+            .await
+    }
+
+    async fn remove(&self, upload_id: &str, start: usize, end: usize) -> RustusResult<()> {
+        let mut chunks = self.info_storage.get_chunks(upload_id).await?;
+        chunks.retain(|chunk| !chunk.overlaps(start, end));
+        self.info_storage
+            .set_chunks(upload_id, chunks.as_slice())
+            .await
+    }
+}
+
+#[cfg(feature = "redis_info_storage")]
+pub use redis_index::RedisChunkIndex;
+
+#[cfg(feature = "redis_info_storage")]
+mod redis_index {
+    use async_trait::async_trait;
+    use bb8::Pool;
+    use bb8_redis::RedisConnectionManager;
+    use redis::aio::Connection;
+
+    use super::{ChunkIndex, IndexedChunk};
+    use crate::errors::{RustusError, RustusResult};
+
+    /// Prefix of keys with chunk indexes.
+    ///
+    /// It differs from the prefix of chunks
+    /// kept by Redis info storage, so both
+    /// can share the same database.
+    const KEY_PREFIX: &str = "chunk_index:";
+
+    /// Index which is kept in Redis.
+    ///
+    /// Chunks of an upload are stored in a sorted set
+    /// scored by their offsets, so ranges are found
+    /// without reading all chunks of the upload.
+    #[derive(Clone)]
+    pub struct RedisChunkIndex {
+        pool: Pool<RedisConnectionManager>,
+        /// Prefix of all keys.
+        prefix: Option<String>,
+    }
+
+    impl RedisChunkIndex {
+        /// Create connection pool of Redis.
+        ///
+        /// # Errors
+        ///
+        /// Returns an error if the DSN is invalid.
+        pub async fn new(dsn: &str) -> RustusResult<Self> {
+            let manager = RedisConnectionManager::new(dsn)?;
+            let pool = bb8::Pool::builder().max_size(100).build(manager).await?;
+            Ok(Self { pool, prefix: None })
+        }
+
+        /// Store the index under the prefix.
+        #[must_use]
+        pub fn with_prefix(mut self, prefix: String) -> Self {
+            self.prefix = Some(prefix);
+            self
+        }
+
+        fn key(&self, upload_id: &str) -> String {
+            match &self.prefix {
+                Some(prefix) => format!("{prefix}/{KEY_PREFIX}{upload_id}"),
+                None => format!("{KEY_PREFIX}{upload_id}"),
+            }
+        }
+
+        /// Find encoded chunks which have bytes of the range.
+        async fn find(
+            &self,
+            upload_id: &str,
+            start: usize,
+            end: usize,
+        ) -> RustusResult<Vec<(String, IndexedChunk)>> {
+            let key = self.key(upload_id);
+            let mut conn = self.pool.get().await?;
+            // Chunks never overlap, so only the last chunk
+            // which starts before the range can reach into it.
+            let (before, inside) = redis::pipe()
+                .cmd("ZREVRANGEBYSCORE")
+                .arg(key.as_str())
+                .arg(format!("({start}"))
+                .arg("-inf")
+                .arg("LIMIT")
+                .arg(0)
+                .arg(1)
+                .cmd("ZRANGEBYSCORE")
+                .arg(key.as_str())
+                .arg(start)
+                .arg(format!("({end}"))
+                .query_async::<Connection, (Vec<String>, Vec<String>)>(&mut conn)
+                .await?;
+            let mut chunks = Vec::new();
+            for member in before.into_iter().chain(inside) {
+                let chunk = serde_json::from_str::<IndexedChunk>(member.as_str())
+                    .map_err(RustusError::from)?;
+                if chunk.overlaps(start, end) {
+                    chunks.push((member, chunk));
+                }
+            }
+            Ok(chunks)
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl ChunkIndex for RedisChunkIndex {
+        async fn lookup(
+            &self,
+            upload_id: &str,
+            start: usize,
+            end: usize,
+        ) -> RustusResult<Vec<IndexedChunk>> {
+            Ok(self
+                .find(upload_id, start, end)
+                .await?
+                .into_iter()
+                .map(|(_, chunk)| chunk)
+                .collect())
+        }
+
+        async fn insert(&self, upload_id: &str, chunk: IndexedChunk) -> RustusResult<()> {
+            let replaced = self.find(upload_id, chunk.start, chunk.end).await?;
+            let member = serde_json::to_string(&chunk).map_err(RustusError::from)?;
+            let key = self.key(upload_id);
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            for (replaced, _) in replaced {
+                pipe.cmd("ZREM").arg(key.as_str()).arg(replaced).ignore();
+            }
+            pipe.cmd("ZADD")
+                .arg(key.as_str())
+                .arg(chunk.start)
+                .arg(member)
+                .ignore();
+            let mut conn = self.pool.get().await?;
+            pipe.query_async::<Connection, ()>(&mut conn).await?;
+            Ok(())
+        }
+
+        async fn remove(&self, upload_id: &str, start: usize, end: usize) -> RustusResult<()> {
+            let removed = self.find(upload_id, start, end).await?;
+            if removed.is_empty() {
+                return Ok(());
+            }
+            let mut conn = self.pool.get().await?;
+            redis::cmd("ZREM")
+                .arg(self.key(upload_id))
+                .arg(
+                    removed
+                        .into_iter()
+                        .map(|(member, _)| member)
+                        .collect::<Vec<_>>(),
+                )
+                .query_async::<Connection, usize>(&mut conn)
+                .await?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChunkIndex, IndexedChunk, InfoChunkIndex};
+    use crate::info_storages::{file_info_storage::FileInfoStorage, InfoStorage};
+
+    fn chunk(start: usize, end: usize) -> IndexedChunk {
+        IndexedChunk {
+            start,
+            end,
+            location: format!("frame-{start}"),
+        }
+    }
+
+    /// Check that the index finds, replaces and removes chunks.
+    async fn check_index(index: &dyn ChunkIndex, upload: &str) {
+        assert!(index.lookup(upload, 0, 100).await.unwrap().is_empty());
+
+        index.insert(upload, chunk(10, 20)).await.unwrap();
+        index.insert(upload, chunk(0, 10)).await.unwrap();
+        index.insert(upload, chunk(20, 30)).await.unwrap();
+        assert_eq!(
+            index.lookup(upload, 5, 15).await.unwrap(),
+            vec![chunk(0, 10), chunk(10, 20)]
+        );
+        assert_eq!(index.lookup(upload, 30, 40).await.unwrap(), vec![]);
+        // Chunk which starts before the range reaches into it.
+        assert_eq!(
+            index.lookup(upload, 25, 26).await.unwrap(),
+            vec![chunk(20, 30)]
+        );
+
+        // Rewritten bytes replace old chunks.
+        index.insert(upload, chunk(15, 25)).await.unwrap();
+        assert_eq!(
+            index.lookup(upload, 0, 100).await.unwrap(),
+            vec![chunk(0, 10), chunk(15, 25)]
+        );
+
+        index.remove(upload, 20, 100).await.unwrap();
+        assert_eq!(
+            index.lookup(upload, 0, 100).await.unwrap(),
+            vec![chunk(0, 10)]
+        );
+        // Chunks of other uploads are separate.
+        let other = format!("{upload}-other");
+        assert!(index
+            .lookup(other.as_str(), 0, 100)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn info_chunk_index() {
+        let dir = tempdir::TempDir::new("chunk_index").unwrap();
+        let mut info_storage = FileInfoStorage::new(dir.into_path());
+        info_storage.prepare().await.unwrap();
+        let index = InfoChunkIndex::new(Box::new(info_storage));
+        check_index(&index, "upload").await;
+    }
+
+    #[cfg(feature = "test_redis")]
+    #[actix_rt::test]
+    async fn redis_chunk_index() {
+        use super::RedisChunkIndex;
+        use crate::info_storages::redis_info_storage::RedisStorage;
+
+        let redis_url = std::env::var("TEST_REDIS_URL").unwrap();
+        let index = RedisChunkIndex::new(redis_url.as_str()).await.unwrap();
+        let upload = uuid::Uuid::new_v4().to_string();
+        check_index(&index, upload.as_str()).await;
+
+        // Chunks kept by the info storage don't collide with the index.
+        let info_storage = RedisStorage::new(redis_url.as_str(), None).await.unwrap();
+        let stored = vec![chunk(0, 5)];
+        info_storage
+            .set_chunks(upload.as_str(), stored.as_slice())
+            .await
+            .unwrap();
+        assert_eq!(
+            info_storage.get_chunks(upload.as_str()).await.unwrap(),
+            stored
+        );
+        assert_eq!(
+            index.lookup(upload.as_str(), 0, 100).await.unwrap(),
+            vec![chunk(0, 10)]
+        );
+
+        let prefixed = RedisChunkIndex::new(redis_url.as_str())
+            .await
+            .unwrap()
+            .with_prefix(String::from("staging"));
+        assert!(prefixed
+            .lookup(upload.as_str(), 0, 100)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use actix_web::{HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use bytes::Bytes;
+use derive_more::Display;
+use log::warn;
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    storages::{Compaction, DataLocation, DataStream, Storage},
+};
+
+/// Delay before the first check of written data.
+const FIRST_CHECK_DELAY: Duration = Duration::from_millis(50);
+/// Maximum delay between checks of written data.
+const MAX_CHECK_DELAY: Duration = Duration::from_secs(1);
+/// How many timeouts to wait for the last chunk of an upload.
+const FINISHED_RETRIES: u32 = 3;
+
+/// Storage wrapper that waits until written chunks are visible.
+///
+/// Eventually consistent storages may show old data
+/// right after a write. Offset of the upload is advanced
+/// only after `add_bytes` returns, so every chunk is read back
+/// until the stored size includes it.
+///
+/// If written data isn't visible in `timeout`,
+/// the chunk is removed, so the client can send it again.
+/// Finished uploads may be moved by the storage, so they're never
+/// removed. Their last chunk is awaited for a few more timeouts.
+/// Chunks of storages which accept them out of order are kept,
+/// since truncation would remove chunks at later offsets.
+/// Storages which can't get the size aren't checked.
+#[derive(Display, Clone)]
+#[display(fmt = "{inner}")]
+pub struct ConsistentStorage {
+    inner: Box<dyn Storage + Send + Sync>,
+    timeout: Duration,
+}
+
+impl ConsistentStorage {
+    #[must_use]
+    pub fn new(inner: Box<dyn Storage + Send + Sync>, timeout: Duration) -> Self {
+        Self { inner, timeout }
+    }
+
+    /// Wait until the stored size of the upload reaches its offset.
+    ///
+    /// Errors of reads are treated as data which isn't visible yet,
+    /// since objects may be missing right after they're written.
+    async fn wait_visible(&self, file_info: &FileInfo, timeout: Duration) -> RustusResult<()> {
+        let deadline = Instant::now() + timeout;
+        let mut delay = FIRST_CHECK_DELAY;
+        loop {
+            let stored = match self.inner.stored_size(file_info).await {
+                Ok(None) => return Ok(()),
+                Ok(Some(stored)) if stored >= file_info.offset => return Ok(()),
+                Ok(Some(stored)) => format!("{stored} bytes"),
+                Err(err) => err.to_string(),
+            };
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RustusError::Timeout(format!(
+                    "written data of upload {} isn't visible: {stored} of {} bytes are stored",
+                    file_info.id, file_info.offset,
+                )));
+            }
+            tokio::time::sleep(delay.min(deadline - now)).await;
+            delay = (delay * 2).min(MAX_CHECK_DELAY);
+        }
+    }
+
+    /// Remove the chunk written after `file_info.offset`.
+    ///
+    /// If the storage already has bytes after the chunk,
+    /// they weren't written by this call, so nothing is removed.
+    async fn rollback(&self, file_info: &FileInfo, written: &FileInfo) {
+        if let Ok(Some(stored)) = self.inner.stored_size(file_info).await {
+            if stored > written.offset {
+                warn!(
+                    "Upload {} has {} bytes after the written chunk, it isn't removed",
+                    file_info.id,
+                    stored - written.offset
+                );
+                return;
+            }
+        }
+        if let Err(err) = self.inner.truncate(file_info).await {
+            warn!("Cannot clean up upload {}: {}", file_info.id, err);
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for ConsistentStorage {
+    async fn prepare(&mut self) -> RustusResult<()> {
+        self.inner.prepare().await
+    }
+
+    async fn get_contents(
+        &self,
+        file_info: &FileInfo,
+        request: &HttpRequest,
+    ) -> RustusResult<HttpResponse> {
+        self.inner.get_contents(file_info, request).await
+    }
+
+    async fn read_data(
+        &self,
+        file_info: &FileInfo,
+        range: Range<usize>,
+    ) -> RustusResult<DataStream> {
+        self.inner.read_data(file_info, range).await
+    }
+
+    async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+        let mut written = file_info.clone();
+        written.offset += bytes.len();
+        self.inner.add_bytes(file_info, bytes).await?;
+        let Err(err) = self.wait_visible(&written, self.timeout).await else {
+            return Ok(());
+        };
+        if written.length == Some(written.offset) {
+            return self
+                .wait_visible(&written, self.timeout * FINISHED_RETRIES)
+                .await;
+        }
+        if self.inner.accepts_out_of_order() {
+            warn!(
+                "Chunk at {} of upload {} isn't visible, it's kept",
+                file_info.offset, file_info.id
+            );
+            return Err(err);
+        }
+        self.rollback(file_info, &written).await;
+        Err(err)
+    }
+
+    fn accepts_out_of_order(&self) -> bool {
+        self.inner.accepts_out_of_order()
+    }
+
+    fn chunk_alignment(&self) -> Option<usize> {
+        self.inner.chunk_alignment()
+    }
+
+    fn preferred_chunk_size(&self) -> Option<usize> {
+        self.inner.preferred_chunk_size()
+    }
+
+    fn read_after_write(&self) -> bool {
+        true
+    }
+
+    fn data_location(&self, file_info: &FileInfo) -> Option<DataLocation> {
+        self.inner.data_location(file_info)
+    }
+
+    async fn truncate(&self, file_info: &FileInfo) -> RustusResult<()> {
+        self.inner.truncate(file_info).await
+    }
+
+    async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
+        self.inner.create_file(file_info).await
+    }
+
+    async fn create_upload(&self, file_info: &mut FileInfo) -> RustusResult<()> {
+        self.inner.create_upload(file_info).await
+    }
+
+    async fn concat_files(
+        &self,
+        file_info: &FileInfo,
+        parts_info: Vec<FileInfo>,
+    ) -> RustusResult<()> {
+        self.inner.concat_files(file_info, parts_info).await
+    }
+
+    async fn copy_file(&self, source: &FileInfo, target: &FileInfo) -> RustusResult<()> {
+        self.inner.copy_file(source, target).await
+    }
+
+    async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+        self.inner.remove_file(file_info).await
+    }
+
+    async fn sync_data(&self, file_info: &FileInfo) -> RustusResult<()> {
+        self.inner.sync_data(file_info).await
+    }
+
+    async fn data_exists(&self, file_info: &FileInfo) -> RustusResult<bool> {
+        self.inner.data_exists(file_info).await
+    }
+
+    async fn stored_size(&self, file_info: &FileInfo) -> RustusResult<Option<usize>> {
+        self.inner.stored_size(file_info).await
+    }
+
+    async fn compact(&self, uploads: &[FileInfo], threshold: f64) -> RustusResult<Compaction> {
+        self.inner.compact(uploads, threshold).await
+    }
+
+    async fn reconcile(&self, uploads: &[FileInfo]) -> RustusResult<Vec<(FileInfo, String)>> {
+        self.inner.reconcile(uploads).await
+    }
+
+    async fn list_paths(&self) -> RustusResult<Vec<String>> {
+        self.inner.list_paths().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConsistentStorage;
+    use crate::{
+        errors::{RustusError, RustusResult},
+        info_storages::FileInfo,
+        storages::file_storage::FileStorage,
+        Storage,
+    };
+    use actix_web::{HttpRequest, HttpResponse};
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use derive_more::Display;
+    use std::time::{Duration, Instant};
+
+    /// File storage that shows its files after a delay.
+    #[derive(Display, Clone)]
+    #[display(fmt = "lagging_storage")]
+    struct LaggingStorage {
+        inner: FileStorage,
+        visible_at: Instant,
+        out_of_order: bool,
+    }
+
+    #[async_trait(?Send)]
+    impl Storage for LaggingStorage {
+        async fn prepare(&mut self) -> RustusResult<()> {
+            self.inner.prepare().await
+        }
+
+        async fn get_contents(
+            &self,
+            file_info: &FileInfo,
+            request: &HttpRequest,
+        ) -> RustusResult<HttpResponse> {
+            self.inner.get_contents(file_info, request).await
+        }
+
+        async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+            self.inner.add_bytes(file_info, bytes).await
+        }
+
+        fn accepts_out_of_order(&self) -> bool {
+            self.out_of_order
+        }
+
+        fn read_after_write(&self) -> bool {
+            false
+        }
+
+        async fn truncate(&self, file_info: &FileInfo) -> RustusResult<()> {
+            self.inner.truncate(file_info).await
+        }
+
+        async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
+            self.inner.create_file(file_info).await
+        }
+
+        async fn concat_files(
+            &self,
+            file_info: &FileInfo,
+            parts_info: Vec<FileInfo>,
+        ) -> RustusResult<()> {
+            self.inner.concat_files(file_info, parts_info).await
+        }
+
+        async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+            self.inner.remove_file(file_info).await
+        }
+
+        async fn stored_size(&self, file_info: &FileInfo) -> RustusResult<Option<usize>> {
+            if Instant::now() < self.visible_at {
+                return Err(RustusError::FileNotFound);
+            }
+            self.inner.stored_size(file_info).await
+        }
+    }
+
+    fn get_storage(lag: Duration, out_of_order: bool) -> ConsistentStorage {
+        let dir = tempdir::TempDir::new("consistent_storage")
+            .unwrap()
+            .into_path();
+        let lagging = LaggingStorage {
+            inner: FileStorage::new(dir, String::new(), false),
+            visible_at: Instant::now() + lag,
+            out_of_order,
+        };
+        ConsistentStorage::new(Box::new(lagging), Duration::from_millis(300))
+    }
+
+    #[actix_rt::test]
+    async fn visible_write() {
+        let storage = get_storage(Duration::from_millis(100), false);
+        let mut file_info = FileInfo::new("test_id", Some(10), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        let contents = std::fs::read_to_string(file_info.path.unwrap()).unwrap();
+        assert_eq!(contents, "memes");
+    }
+
+    #[actix_rt::test]
+    async fn invisible_write() {
+        let storage = get_storage(Duration::from_secs(10), false);
+        let mut file_info = FileInfo::new("test_id", Some(10), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        let res = storage.add_bytes(&file_info, Bytes::from("memes")).await;
+        assert!(matches!(res, Err(RustusError::Timeout(_))));
+        // Chunk is removed, so it can be sent again.
+        let contents = std::fs::read_to_string(file_info.path.unwrap()).unwrap();
+        assert_eq!(contents, "");
+    }
+
+    #[actix_rt::test]
+    async fn out_of_order_chunks_kept() {
+        let storage = get_storage(Duration::from_secs(10), true);
+        let mut file_info = FileInfo::new("test_id", Some(10), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        let res = storage.add_bytes(&file_info, Bytes::from("memes")).await;
+        assert!(matches!(res, Err(RustusError::Timeout(_))));
+        // Other chunks may be written after this one, so nothing is removed.
+        let contents = std::fs::read_to_string(file_info.path.unwrap()).unwrap();
+        assert_eq!(contents, "memes");
+    }
+
+    #[actix_rt::test]
+    async fn finished_upload_awaited() {
+        // Visible after the first timeout, but before the retries end.
+        let storage = get_storage(Duration::from_millis(500), false);
+        let mut file_info = FileInfo::new("test_id", Some(5), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        let contents = std::fs::read_to_string(file_info.path.unwrap()).unwrap();
+        assert_eq!(contents, "memes");
+    }
+}
@@ -0,0 +1,543 @@
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix_web::{HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use bytes::Bytes;
+use derive_more::Display;
+use log::{debug, warn};
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    storages::{copy_contents, Compaction, DataLocation, DataStream, Storage},
+    utils::import::ImportSource,
+};
+
+/// Backend of uploads in the fallback storage.
+const FALLBACK_BACKEND: &str = "fallback";
+
+/// Check if the upload is stored in the fallback storage.
+#[must_use]
+pub fn is_fallback(file_info: &FileInfo) -> bool {
+    file_info.backend.as_deref() == Some(FALLBACK_BACKEND)
+}
+
+/// Storage wrapper that creates uploads in a fallback storage
+/// while the primary storage is unavailable.
+///
+/// If the primary storage fails with a transient error,
+/// all uploads created during `cooldown` go to the fallback storage.
+/// Their `backend` is set to `fallback`, so all other operations
+/// with them are sent to the fallback storage.
+///
+/// Uploads can't be split between storages, so uploads
+/// started in the primary storage keep using it.
+/// Finished uploads are moved back with `reconcile`.
+#[derive(Display, Clone)]
+#[display(fmt = "{primary}")]
+pub struct FailoverStorage {
+    primary: Box<dyn Storage + Send + Sync>,
+    fallback: Box<dyn Storage + Send + Sync>,
+    cooldown: Duration,
+    /// Time until which the primary storage isn't used for new uploads.
+    unavailable_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl FailoverStorage {
+    /// Create new failover storage.
+    ///
+    /// Fallback storage must be prepared before calling this function.
+    ///
+    /// # Params
+    /// `primary` - storage that holds uploads.
+    /// `fallback` - storage for uploads created while the primary one is unavailable.
+    /// `cooldown` - how long the primary storage isn't used after a failure.
+    #[must_use]
+    pub fn new(
+        primary: Box<dyn Storage + Send + Sync>,
+        fallback: Box<dyn Storage + Send + Sync>,
+        cooldown: Duration,
+    ) -> Self {
+        Self {
+            primary,
+            fallback,
+            cooldown,
+            unavailable_until: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        self.unavailable_until.lock().map_or(true, |until| {
+            until.is_none_or(|until| until <= Instant::now())
+        })
+    }
+
+    fn mark_unavailable(&self, err: &RustusError) {
+        warn!(
+            "Primary storage is unavailable: {err}. New uploads are created in the fallback storage."
+        );
+        if let Ok(mut until) = self.unavailable_until.lock() {
+            *until = Some(Instant::now() + self.cooldown);
+        }
+    }
+
+    /// Remember failures of the primary storage.
+    fn check<T>(&self, result: RustusResult<T>) -> RustusResult<T> {
+        if let Err(err) = &result {
+            if err.is_transient() {
+                self.mark_unavailable(err);
+            }
+        }
+        result
+    }
+
+    /// Copy finished upload from the fallback storage to the primary one.
+    ///
+    /// Returns path of the copy.
+    async fn restore(&self, source: &FileInfo) -> RustusResult<String> {
+        let mut target = source.clone();
+        target.path = None;
+        target.backend = None;
+        target.offset = 0;
+        target.received.clear();
+        let path = self.primary.create_file(&target).await?;
+        target.path = Some(path.clone());
+        let copied = async {
+            copy_contents(
+                self.fallback.as_ref(),
+                source,
+                self.primary.as_ref(),
+                &target,
+            )
+            .await?;
+            let mut finished = target.clone();
+            finished.offset = source.offset;
+            self.primary.sync_data(&finished).await
+        };
+        if let Err(err) = copied.await {
+            if let Err(remove_err) = self.primary.remove_file(&target).await {
+                warn!(
+                    "Cannot remove incomplete copy of upload {}: {remove_err}",
+                    target.id
+                );
+            }
+            return Err(err);
+        }
+        Ok(path)
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for FailoverStorage {
+    async fn prepare(&mut self) -> RustusResult<()> {
+        // Server can start while the primary storage is unavailable.
+        let result = self.primary.prepare().await;
+        match result {
+            Err(err) if err.is_transient() => {
+                self.mark_unavailable(&err);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    async fn get_contents(
+        &self,
+        file_info: &FileInfo,
+        request: &HttpRequest,
+    ) -> RustusResult<HttpResponse> {
+        if is_fallback(file_info) {
+            self.fallback.get_contents(file_info, request).await
+        } else {
+            self.check(self.primary.get_contents(file_info, request).await)
+        }
+    }
+
+    async fn read_data(
+        &self,
+        file_info: &FileInfo,
+        range: Range<usize>,
+    ) -> RustusResult<DataStream> {
+        if is_fallback(file_info) {
+            self.fallback.read_data(file_info, range).await
+        } else {
+            self.check(self.primary.read_data(file_info, range).await)
+        }
+    }
+
+    async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+        if is_fallback(file_info) {
+            self.fallback.add_bytes(file_info, bytes).await
+        } else {
+            self.check(self.primary.add_bytes(file_info, bytes).await)
+        }
+    }
+
+    fn accepts_out_of_order(&self) -> bool {
+        // Storage of the upload isn't known when this is checked.
+        self.primary.accepts_out_of_order() && self.fallback.accepts_out_of_order()
+    }
+
+    fn chunk_alignment(&self) -> Option<usize> {
+        // Aligned chunks are accepted by the fallback storage as well.
+        self.primary.chunk_alignment()
+    }
+
+    fn preferred_chunk_size(&self) -> Option<usize> {
+        // New uploads are written to the primary storage.
+        self.primary.preferred_chunk_size()
+    }
+
+    fn data_location(&self, file_info: &FileInfo) -> Option<DataLocation> {
+        if is_fallback(file_info) {
+            self.fallback.data_location(file_info)
+        } else {
+            self.primary.data_location(file_info)
+        }
+    }
+
+    async fn truncate(&self, file_info: &FileInfo) -> RustusResult<()> {
+        if is_fallback(file_info) {
+            self.fallback.truncate(file_info).await
+        } else {
+            self.check(self.primary.truncate(file_info).await)
+        }
+    }
+
+    async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
+        // Backend of the upload can't be recorded here,
+        // so files are created only in the primary storage.
+        self.check(self.primary.create_file(file_info).await)
+    }
+
+    async fn create_upload(&self, file_info: &mut FileInfo) -> RustusResult<()> {
+        file_info.backend = None;
+        if self.is_available() {
+            match self.primary.create_file(file_info).await {
+                Ok(path) => {
+                    file_info.path = Some(path);
+                    return Ok(());
+                }
+                Err(err) if err.is_transient() => self.mark_unavailable(&err),
+                Err(err) => return Err(err),
+            }
+        }
+        debug!("Creating upload {} in the fallback storage.", file_info.id);
+        file_info.path = Some(self.fallback.create_file(file_info).await?);
+        file_info.backend = Some(String::from(FALLBACK_BACKEND));
+        Ok(())
+    }
+
+    async fn concat_files(
+        &self,
+        file_info: &FileInfo,
+        parts_info: Vec<FileInfo>,
+    ) -> RustusResult<()> {
+        let fallback_parts = parts_info.iter().filter(|part| is_fallback(part)).count();
+        if is_fallback(file_info) && fallback_parts == parts_info.len() {
+            self.fallback.concat_files(file_info, parts_info).await
+        } else if !is_fallback(file_info) && fallback_parts == 0 {
+            self.check(self.primary.concat_files(file_info, parts_info).await)
+        } else {
+            Err(RustusError::UnableToWrite(format!(
+                "Parts of upload {} are stored in different storages.",
+                file_info.id
+            )))
+        }
+    }
+
+    async fn import_file(
+        &self,
+        file_info: &FileInfo,
+        source: &ImportSource,
+        hard_link: bool,
+    ) -> RustusResult<()> {
+        if is_fallback(file_info) {
+            self.fallback
+                .import_file(file_info, source, hard_link)
+                .await
+        } else {
+            self.check(self.primary.import_file(file_info, source, hard_link).await)
+        }
+    }
+
+    async fn copy_file(&self, source: &FileInfo, target: &FileInfo) -> RustusResult<()> {
+        match (is_fallback(source), is_fallback(target)) {
+            (false, false) => self.check(self.primary.copy_file(source, target).await),
+            (true, true) => self.fallback.copy_file(source, target).await,
+            (true, false) => self.check(
+                copy_contents(
+                    self.fallback.as_ref(),
+                    source,
+                    self.primary.as_ref(),
+                    target,
+                )
+                .await,
+            ),
+            (false, true) => self.check(
+                copy_contents(
+                    self.primary.as_ref(),
+                    source,
+                    self.fallback.as_ref(),
+                    target,
+                )
+                .await,
+            ),
+        }
+    }
+
+    async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+        if is_fallback(file_info) {
+            self.fallback.remove_file(file_info).await
+        } else {
+            self.check(self.primary.remove_file(file_info).await)
+        }
+    }
+
+    async fn sync_data(&self, file_info: &FileInfo) -> RustusResult<()> {
+        if is_fallback(file_info) {
+            self.fallback.sync_data(file_info).await
+        } else {
+            self.check(self.primary.sync_data(file_info).await)
+        }
+    }
+
+    async fn data_exists(&self, file_info: &FileInfo) -> RustusResult<bool> {
+        if is_fallback(file_info) {
+            self.fallback.data_exists(file_info).await
+        } else {
+            self.check(self.primary.data_exists(file_info).await)
+        }
+    }
+
+    async fn stored_size(&self, file_info: &FileInfo) -> RustusResult<Option<usize>> {
+        if is_fallback(file_info) {
+            self.fallback.stored_size(file_info).await
+        } else {
+            self.check(self.primary.stored_size(file_info).await)
+        }
+    }
+
+    async fn compact(&self, uploads: &[FileInfo], threshold: f64) -> RustusResult<Compaction> {
+        let uploads = uploads
+            .iter()
+            .filter(|upload| !is_fallback(upload))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.primary.compact(uploads.as_slice(), threshold).await
+    }
+
+    async fn reconcile(&self, uploads: &[FileInfo]) -> RustusResult<Vec<(FileInfo, String)>> {
+        let mut moved = Vec::new();
+        if !self.is_available() {
+            return Ok(moved);
+        }
+        for upload in uploads {
+            if !is_fallback(upload) {
+                continue;
+            }
+            match self.restore(upload).await {
+                Ok(path) => moved.push((upload.clone(), path)),
+                Err(err) if err.is_transient() => {
+                    self.mark_unavailable(&err);
+                    break;
+                }
+                Err(err) => warn!(
+                    "Cannot move upload {} to the primary storage: {err}",
+                    upload.id
+                ),
+            }
+        }
+        Ok(moved)
+    }
+
+    async fn list_paths(&self) -> RustusResult<Vec<String>> {
+        let mut paths = self.primary.list_paths().await?;
+        paths.extend(self.fallback.list_paths().await?);
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_fallback, FailoverStorage};
+    use crate::{
+        errors::{RustusError, RustusResult},
+        info_storages::FileInfo,
+        storages::file_storage::FileStorage,
+        Storage,
+    };
+    use actix_web::{HttpRequest, HttpResponse};
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use derive_more::Display;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    /// File storage that can be turned off.
+    #[derive(Display, Clone)]
+    #[display(fmt = "offline_storage")]
+    struct OfflineStorage {
+        inner: FileStorage,
+        online: Arc<AtomicBool>,
+    }
+
+    impl OfflineStorage {
+        fn check(&self) -> RustusResult<()> {
+            if self.online.load(Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err(RustusError::Timeout(String::from("offline")))
+            }
+        }
+    }
+
+    #[async_trait(?Send)]
+    impl Storage for OfflineStorage {
+        async fn prepare(&mut self) -> RustusResult<()> {
+            self.check()?;
+            self.inner.prepare().await
+        }
+
+        async fn get_contents(
+            &self,
+            file_info: &FileInfo,
+            request: &HttpRequest,
+        ) -> RustusResult<HttpResponse> {
+            self.check()?;
+            self.inner.get_contents(file_info, request).await
+        }
+
+        async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+            self.check()?;
+            self.inner.add_bytes(file_info, bytes).await
+        }
+
+        async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
+            self.check()?;
+            self.inner.create_file(file_info).await
+        }
+
+        async fn concat_files(
+            &self,
+            file_info: &FileInfo,
+            parts_info: Vec<FileInfo>,
+        ) -> RustusResult<()> {
+            self.check()?;
+            self.inner.concat_files(file_info, parts_info).await
+        }
+
+        async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+            self.check()?;
+            self.inner.remove_file(file_info).await
+        }
+
+        async fn list_paths(&self) -> RustusResult<Vec<String>> {
+            self.check()?;
+            self.inner.list_paths().await
+        }
+    }
+
+    fn get_storage(cooldown: Duration) -> (FailoverStorage, Arc<AtomicBool>) {
+        let online = Arc::new(AtomicBool::new(false));
+        let primary = OfflineStorage {
+            inner: FileStorage::new(
+                tempdir::TempDir::new("primary").unwrap().into_path(),
+                String::new(),
+                false,
+            ),
+            online: online.clone(),
+        };
+        let fallback = FileStorage::new(
+            tempdir::TempDir::new("fallback").unwrap().into_path(),
+            String::new(),
+            false,
+        );
+        let storage = FailoverStorage::new(Box::new(primary), Box::new(fallback), cooldown);
+        (storage, online)
+    }
+
+    async fn create_upload(storage: &FailoverStorage, id: &str) -> FileInfo {
+        let mut file_info = FileInfo::new(id, Some(5), None, storage.to_string(), None);
+        storage.create_upload(&mut file_info).await.unwrap();
+        file_info
+    }
+
+    #[actix_rt::test]
+    async fn fallback_while_unavailable() {
+        let (mut storage, online) = get_storage(Duration::from_secs(60));
+        // Server starts without the primary storage.
+        storage.prepare().await.unwrap();
+        let mut file_info = create_upload(&storage, "fallback").await;
+        assert!(is_fallback(&file_info));
+        storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        file_info.offset = 5;
+        storage.sync_data(&file_info).await.unwrap();
+        assert!(storage.data_exists(&file_info).await.unwrap());
+        // Primary storage isn't used until the cooldown is over.
+        online.store(true, Ordering::SeqCst);
+        let path = file_info.path.clone().unwrap();
+        assert!(storage.list_paths().await.unwrap().contains(&path));
+        assert!(is_fallback(&create_upload(&storage, "cooldown").await));
+        storage.remove_file(&file_info).await.unwrap();
+        assert!(!storage.data_exists(&file_info).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn primary_after_cooldown() {
+        let (storage, online) = get_storage(Duration::ZERO);
+        assert!(is_fallback(&create_upload(&storage, "fallback").await));
+        online.store(true, Ordering::SeqCst);
+        let file_info = create_upload(&storage, "primary").await;
+        assert!(!is_fallback(&file_info));
+        storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        // Uploads started in the primary storage keep using it.
+        online.store(false, Ordering::SeqCst);
+        let result = storage.add_bytes(&file_info, Bytes::from("memes")).await;
+        assert!(matches!(result, Err(RustusError::Timeout(_))));
+    }
+
+    #[actix_rt::test]
+    async fn reconcile_uploads() {
+        let (storage, online) = get_storage(Duration::ZERO);
+        let mut finished = create_upload(&storage, "finished").await;
+        storage
+            .add_bytes(&finished, Bytes::from("memes"))
+            .await
+            .unwrap();
+        finished.offset = 5;
+        // Nothing is moved while the primary storage is unavailable.
+        assert!(storage
+            .reconcile(&[finished.clone()])
+            .await
+            .unwrap()
+            .is_empty());
+        online.store(true, Ordering::SeqCst);
+        let moved = storage.reconcile(&[finished.clone()]).await.unwrap();
+        assert_eq!(moved.len(), 1);
+        let (upload, path) = &moved[0];
+        assert_eq!(upload.path, finished.path);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "memes");
+        let mut restored = finished.clone();
+        restored.path = Some(path.clone());
+        restored.backend = None;
+        assert!(storage.data_exists(&restored).await.unwrap());
+        // Data is still available at the old path.
+        assert!(storage.data_exists(&finished).await.unwrap());
+    }
+}
@@ -1,10 +1,17 @@
-use std::{io::Write, path::PathBuf};
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
 
 use actix_files::NamedFile;
-use actix_web::{HttpRequest, HttpResponse};
+use actix_web::{body::BoxBody, http::StatusCode, HttpRequest, HttpResponse};
 use async_trait::async_trait;
 use bytes::Bytes;
-use log::error;
+use log::{error, warn};
 use std::{
     fs::{remove_file, DirBuilder, OpenOptions},
     io::{copy, BufReader, BufWriter},
@@ -13,46 +20,309 @@ use std::{
 use crate::{
     errors::{RustusError, RustusResult},
     info_storages::FileInfo,
-    storages::Storage,
-    utils::dir_struct::substr_now,
+    storages::{read_file, DataStream, Storage},
+    utils::{
+        dir_struct::{absolute_path, check_writable, substr_path, substr_time},
+        durability::sync_file,
+        import::ImportSource,
+    },
 };
 use derive_more::Display;
 
+/// Cached file handle of an active upload.
+struct OpenFile {
+    file: std::fs::File,
+    /// Time of the last write.
+    last_used: Instant,
+    /// Number of bytes written since the last sync.
+    unsynced: usize,
+}
+
+/// Cache of open file handles.
+///
+/// Handles are mapped by the path of the upload.
+type OpenFiles = Arc<Mutex<HashMap<String, OpenFile>>>;
+
+/// Permissions of files and directories created by the storage.
+///
+/// Unset values keep permissions derived from the process umask.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Permissions {
+    /// Mode of created files, e.g. `0o640`.
+    pub file_mode: Option<u32>,
+    /// Mode of created directories, e.g. `0o750`.
+    pub dir_mode: Option<u32>,
+    /// Group id of created files and directories.
+    pub group: Option<u32>,
+}
+
+impl Permissions {
+    /// Set permissions of the created file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if permissions can't be changed.
+    pub fn apply_to_file(&self, path: &Path) -> std::io::Result<()> {
+        apply_permissions(path, self.file_mode, self.group)
+    }
+
+    fn apply_to_dir(&self, path: &Path) -> std::io::Result<()> {
+        apply_permissions(path, self.dir_mode, self.group)
+    }
+}
+
+/// Parse octal file mode, like `640` or `0o640`.
+///
+/// # Errors
+///
+/// Returns an error if the mode isn't an octal number.
+pub fn parse_mode(input: &str) -> Result<u32, String> {
+    let digits = input.trim_start_matches("0o");
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("'{input}' is not a valid octal file mode"))
+}
+
 #[derive(Display, Clone)]
 #[display(fmt = "file_storage")]
 pub struct FileStorage {
     data_dir: PathBuf,
     dir_struct: String,
     force_fsync: bool,
+    open_files: Option<OpenFiles>,
+    /// Number of bytes after which fsync is called for kept open files.
+    flush_size: Option<usize>,
+    permissions: Permissions,
+    date_prefix: bool,
+    path_template: Option<String>,
+    reserved_dir: Option<PathBuf>,
+    buffered_download_size: Option<u64>,
 }
 
 impl FileStorage {
+    #[must_use]
     pub fn new(data_dir: PathBuf, dir_struct: String, force_fsync: bool) -> FileStorage {
         FileStorage {
             data_dir,
             dir_struct,
             force_fsync,
+            open_files: None,
+            flush_size: None,
+            permissions: Permissions::default(),
+            date_prefix: false,
+            path_template: None,
+            reserved_dir: None,
+            buffered_download_size: None,
+        }
+    }
+
+    /// Store uploads in directories named after their creation date.
+    ///
+    /// Date path, like `2024/06/15`, is prepended to the directory structure.
+    /// It's derived from `created_at`, so uploads of one day
+    /// can be archived or removed together.
+    #[must_use]
+    pub fn with_date_prefix(mut self) -> Self {
+        self.date_prefix = true;
+        self
+    }
+
+    /// Generate paths of uploads from a template.
+    ///
+    /// The path is relative to the directory structure.
+    /// Template can use metadata of uploads, see `substr_path`.
+    /// Upload can't be created if its path is already taken.
+    #[must_use]
+    pub fn with_path_template(mut self, template: String) -> Self {
+        self.path_template = Some(template);
+        self
+    }
+
+    /// Forbid paths from the template inside a directory.
+    ///
+    /// It's used for the directory of file info storage,
+    /// so uploads can't overwrite `.info` files of other uploads.
+    #[must_use]
+    pub fn with_reserved_dir(mut self, dir: PathBuf) -> Self {
+        self.reserved_dir = Some(dir);
+        self
+    }
+
+    /// Check that path generated from the template
+    /// stays inside the data directory and out of the reserved one.
+    ///
+    /// Parent directory must already exist,
+    /// so symlinks are resolved.
+    fn check_template_path(&self, data_dir: &Path, path: &Path) -> RustusResult<()> {
+        let invalid = || RustusError::InvalidPath(format!("Path {} is invalid.", path.display()));
+        let parent = path
+            .parent()
+            .and_then(|parent| parent.canonicalize().ok())
+            .ok_or_else(invalid)?;
+        if !parent.starts_with(data_dir) {
+            return Err(invalid());
+        }
+        if let Some(reserved) = self.reserved_dir.as_ref().map(|dir| absolute_path(dir)) {
+            // Info directory may be the data directory itself,
+            // then only its own files and the trash are reserved.
+            let nested = parent.starts_with(reserved.as_path()) && !data_dir.starts_with(&reserved);
+            if nested || parent == reserved || parent.starts_with(reserved.join(".trash")) {
+                return Err(invalid());
+            }
+        }
+        Ok(())
+    }
+
+    /// Read small files into memory instead of streaming them.
+    ///
+    /// Files up to `max_size` bytes are sent in one chunk,
+    /// which is faster for lots of small downloads, like thumbnails.
+    /// Headers are the same as for streamed files,
+    /// range requests are always streamed.
+    #[must_use]
+    pub fn with_buffered_downloads(mut self, max_size: u64) -> Self {
+        self.buffered_download_size = Some(max_size);
+        self
+    }
+
+    /// Set permissions of created files and directories.
+    ///
+    /// Permissions are applied right after creation,
+    /// so files never stay with umask-derived ones
+    /// while they are being uploaded.
+    #[must_use]
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        if cfg!(not(unix)) {
+            warn!("File permissions are supported only on unix systems. They will be ignored.");
         }
+        self.permissions = permissions;
+        self
     }
 
-    pub fn data_file_path(&self, file_id: &str) -> RustusResult<PathBuf> {
-        let dir = self
+    /// Permissions of created files and directories.
+    #[must_use]
+    pub fn permissions(&self) -> Permissions {
+        self.permissions
+    }
+
+    /// Keep files of active uploads open between writes.
+    ///
+    /// It saves syscalls for opening and closing
+    /// files when uploads have lots of small chunks.
+    /// Bytes are written to files right away, so offsets
+    /// always match the contents of files.
+    ///
+    /// Files are closed when uploads are complete,
+    /// or after `idle_timeout` without writes.
+    ///
+    /// If `flush_size` is set, it's used as a threshold of fsync calls:
+    /// files are synced to disk after that number of bytes
+    /// is written to them, and before idle files are closed.
+    #[must_use]
+    pub fn with_open_files(mut self, idle_timeout: Duration, flush_size: Option<usize>) -> Self {
+        let open_files = OpenFiles::default();
+        let weak_files = Arc::downgrade(&open_files);
+        let sync_idle = flush_size.is_some();
+        let spawned = std::thread::Builder::new()
+            .name(String::from("rustus-idle-files"))
+            .spawn(move || close_idle_files(&weak_files, idle_timeout, sync_idle));
+        if let Err(err) = spawned {
+            error!(
+                "Cannot start closing idle files: {}. Files won't be kept open.",
+                err
+            );
+            return self;
+        }
+        self.open_files = Some(open_files);
+        self.flush_size = flush_size;
+        self
+    }
+
+    /// Take cached file handle of the upload out of the cache.
+    ///
+    /// Closing a file is a blocking operation,
+    /// so the handle must be dropped in a blocking thread.
+    fn take_file(&self, path: &str) -> Option<std::fs::File> {
+        let open_files = self.open_files.as_ref()?;
+        let open_file = open_files.lock().ok()?.remove(path)?;
+        Some(open_file.file)
+    }
+
+    /// Path of the upload's data, its directories are created.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if directories can't be created
+    /// or the path is outside of the data directory.
+    pub fn data_file_path(&self, file_info: &FileInfo) -> RustusResult<PathBuf> {
+        let (data_dir, path) = self.resolve_path(file_info)?;
+        if let Some(parent) = path.parent() {
+            self.create_dirs(parent).map_err(|err| {
+                error!("{}", err);
+                RustusError::UnableToWrite(err.to_string())
+            })?;
+        }
+        if self.path_template.is_some() {
+            self.check_template_path(data_dir.as_path(), path.as_path())?;
+        }
+        Ok(path)
+    }
+
+    /// Path of the upload's data in the storage.
+    ///
+    /// Unlike `data_file_path`, directories aren't created.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the path is outside of the data directory.
+    pub fn file_path(&self, file_info: &FileInfo) -> RustusResult<PathBuf> {
+        Ok(self.resolve_path(file_info)?.1)
+    }
+
+    /// Get canonical data directory and path of the upload in it.
+    fn resolve_path(&self, file_info: &FileInfo) -> RustusResult<(PathBuf, PathBuf)> {
+        let created_at = file_info.created_at;
+        let data_dir = self
             .data_dir
             // We're working wit absolute paths, because tus.io says so.
             .canonicalize()
-            .map_err(|err| {
-                error!("{}", err);
-                RustusError::UnableToWrite(err.to_string())
-            })?
-            .join(substr_now(self.dir_struct.as_str()));
-        DirBuilder::new()
-            .recursive(true)
-            .create(dir.as_path())
             .map_err(|err| {
                 error!("{}", err);
                 RustusError::UnableToWrite(err.to_string())
             })?;
-        Ok(dir.join(file_id))
+        let mut dir = data_dir.clone();
+        if self.date_prefix {
+            dir = dir.join(created_at.format("%Y/%m/%d").to_string());
+        }
+        dir = dir.join(substr_time(self.dir_struct.as_str(), created_at));
+        let path = match &self.path_template {
+            Some(template) => dir
+                .join(substr_path(template.as_str(), file_info).map_err(RustusError::InvalidPath)?),
+            None => dir.join(file_info.id.as_str()),
+        };
+        Ok((data_dir, path))
+    }
+
+    /// Create directory with all its parents.
+    ///
+    /// Permissions are applied only to the directories
+    /// which didn't exist before.
+    fn create_dirs(&self, dir: &Path) -> std::io::Result<()> {
+        let missing = dir
+            .ancestors()
+            .take_while(|ancestor| !ancestor.exists())
+            // Relative paths end with an empty one.
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        DirBuilder::new().recursive(true).create(dir)?;
+        for created in missing.into_iter().rev() {
+            self.permissions.apply_to_dir(created)?;
+        }
+        Ok(())
     }
 }
 
@@ -61,13 +331,20 @@ impl Storage for FileStorage {
     async fn prepare(&mut self) -> RustusResult<()> {
         // We're creating directory for new files
         // if it doesn't already exist.
-        if !self.data_dir.exists() {
-            DirBuilder::new()
-                .recursive(true)
-                .create(self.data_dir.as_path())
-                .map_err(|err| RustusError::UnableToPrepareStorage(err.to_string()))?;
-        }
-        Ok(())
+        let data_dir = absolute_path(self.data_dir.as_path());
+        self.create_dirs(self.data_dir.as_path()).map_err(|err| {
+            RustusError::UnableToPrepareStorage(format!(
+                "cannot create directory {}: {err}",
+                data_dir.display()
+            ))
+        })?;
+        // Permissions are checked now, so uploads don't fail later.
+        check_writable(self.data_dir.as_path()).map_err(|err| {
+            RustusError::UnableToPrepareStorage(format!(
+                "directory {} isn't writable: {err}",
+                data_dir.display()
+            ))
+        })
     }
 
     async fn get_contents(
@@ -76,18 +353,40 @@ impl Storage for FileStorage {
         request: &HttpRequest,
     ) -> RustusResult<HttpResponse> {
         if let Some(path) = &file_info.path {
-            Ok(NamedFile::open_async(path)
-                .await
-                .map_err(|err| {
-                    error!("{:?}", err);
-                    RustusError::FileNotFound
-                })?
-                .into_response(request))
+            let file = NamedFile::open_async(path).await.map_err(|err| {
+                error!("{:?}", err);
+                RustusError::FileNotFound
+            })?;
+            let buffered = self
+                .buffered_download_size
+                .filter(|max_size| file.metadata().len() <= *max_size)
+                .map(|_| file.file().try_clone())
+                .transpose()?;
+            // Response is built by `NamedFile`, so conditional
+            // and range requests are handled the same way.
+            let response = file.into_response(request);
+            match buffered {
+                Some(handle) if response.status() == StatusCode::OK => {
+                    buffer_response(response, handle).await
+                }
+                _ => Ok(response),
+            }
         } else {
             Err(RustusError::FileNotFound)
         }
     }
 
+    async fn read_data(
+        &self,
+        file_info: &FileInfo,
+        range: Range<usize>,
+    ) -> RustusResult<DataStream> {
+        let Some(path) = &file_info.path else {
+            return Err(RustusError::FileNotFound);
+        };
+        read_file(Path::new(path), range.start as u64..range.end as u64).await
+    }
+
     async fn add_bytes(&self, file_info: &FileInfo, mut bytes: Bytes) -> RustusResult<()> {
         // In normal situation this `if` statement is not
         // gonna be called, but what if it is ...
@@ -96,38 +395,86 @@ impl Storage for FileStorage {
         }
         let path = file_info.path.as_ref().unwrap().clone();
         let force_sync = self.force_fsync;
+        let open_files = self.open_files.clone();
+        let flush_size = self.flush_size;
+        // File is closed after the last chunk.
+        let is_last = file_info.length == Some(file_info.offset + bytes.len());
         tokio::task::spawn_blocking(move || {
-            // Opening file in w+a mode.
-            // It means that we're going to append some
-            // bytes to the end of a file.
-            let file = OpenOptions::new()
-                .write(true)
-                .append(true)
-                .create(false)
-                .read(false)
-                .truncate(false)
-                .open(path.as_str())
-                .map_err(|err| {
-                    error!("{:?}", err);
-                    RustusError::UnableToWrite(err.to_string())
-                })?;
+            // Handle is taken out of the cache during the write,
+            // so concurrent writes never share it.
+            let cached = open_files
+                .as_ref()
+                .and_then(|files| files.lock().ok()?.remove(path.as_str()));
+            let (file, mut unsynced) = if let Some(open_file) = cached {
+                (open_file.file, open_file.unsynced)
+            } else {
+                // Opening file in w+a mode.
+                // It means that we're going to append some
+                // bytes to the end of a file.
+                let file = OpenOptions::new()
+                    .write(true)
+                    .append(true)
+                    .create(false)
+                    .read(false)
+                    .truncate(false)
+                    .open(path.as_str())
+                    .map_err(|err| {
+                        error!("{:?}", err);
+                        RustusError::UnableToWrite(err.to_string())
+                    })?;
+                (file, 0)
+            };
             let mut writer = BufWriter::new(file);
             writer.write_all(bytes.as_ref())?;
             writer.flush()?;
-            if force_sync {
+            unsynced += bytes.len();
+            if force_sync || matches!(flush_size, Some(size) if unsynced >= size) {
                 writer.get_ref().sync_data()?;
+                unsynced = 0;
             }
             bytes.clear();
+            if let (Some(open_files), false) = (open_files, is_last) {
+                let file = writer
+                    .into_inner()
+                    .map_err(std::io::IntoInnerError::into_error)?;
+                if let Ok(mut files) = open_files.lock() {
+                    files.insert(
+                        path,
+                        OpenFile {
+                            file,
+                            last_used: Instant::now(),
+                            unsynced,
+                        },
+                    );
+                }
+            }
 
             Ok(())
         })
         .await?
     }
 
+    async fn truncate(&self, file_info: &FileInfo) -> RustusResult<()> {
+        let Some(path) = file_info.path.clone() else {
+            return Err(RustusError::FileNotFound);
+        };
+        let offset = file_info.offset as u64;
+        let cached = self.take_file(path.as_str());
+        tokio::task::spawn_blocking(move || {
+            drop(cached);
+            let file = OpenOptions::new().write(true).open(path.as_str())?;
+            file.set_len(offset)?;
+            Ok(())
+        })
+        .await?
+    }
+
     async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
-        // New path to file.
-        let file_path = self.data_file_path(file_info.id.as_str())?;
+        let storage = self.clone();
+        let info = file_info.clone();
         tokio::task::spawn_blocking(move || {
+            // New path to file.
+            let file_path = storage.data_file_path(&info)?;
             // Creating new file.
             OpenOptions::new()
                 .create(true)
@@ -137,8 +484,19 @@ impl Storage for FileStorage {
                 .open(file_path.as_path())
                 .map_err(|err| {
                     error!("{:?}", err);
-                    RustusError::FileAlreadyExists
+                    // Paths from templates may be taken by other uploads.
+                    match (&storage.path_template, err.kind()) {
+                        (Some(_), std::io::ErrorKind::AlreadyExists) => {
+                            RustusError::PathCollision(file_path.display().to_string())
+                        }
+                        _ => RustusError::FileAlreadyExists,
+                    }
                 })?;
+            if let Err(err) = storage.permissions.apply_to_file(file_path.as_path()) {
+                error!("Cannot set permissions of {}: {}", file_path.display(), err);
+                remove_file(file_path.as_path()).ok();
+                return Err(RustusError::UnableToWrite(err.to_string()));
+            }
             Ok(file_path.display().to_string())
         })
         .await?
@@ -181,9 +539,72 @@ impl Storage for FileStorage {
         .await?
     }
 
+    async fn import_file(
+        &self,
+        file_info: &FileInfo,
+        source: &ImportSource,
+        hard_link: bool,
+    ) -> RustusResult<()> {
+        let Some(path) = file_info.path.clone() else {
+            return Err(RustusError::FileNotFound);
+        };
+        let source = source.try_clone()?;
+        let force_fsync = self.force_fsync;
+        tokio::task::spawn_blocking(move || {
+            if hard_link {
+                // Linked file keeps permissions of the source.
+                remove_file(path.as_str())?;
+                match std::fs::hard_link(source.path.as_path(), path.as_str()) {
+                    // File is linked by path, so it could be
+                    // replaced after it was opened.
+                    Ok(()) if source.is_same_file(Path::new(path.as_str()))? => return Ok(()),
+                    Ok(()) => {
+                        remove_file(path.as_str())?;
+                        warn!(
+                            "{} was replaced before it was linked. It will be copied.",
+                            source.path.display()
+                        );
+                    }
+                    Err(err) => warn!(
+                        "Cannot link {}: {}. It will be copied.",
+                        source.path.display(),
+                        err
+                    ),
+                }
+            }
+            // Copying into the created file keeps its permissions.
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path.as_str())?;
+            let mut writer = BufWriter::new(file);
+            copy(&mut BufReader::new(source.file), &mut writer)?;
+            writer.flush()?;
+            if force_fsync {
+                writer.get_ref().sync_data()?;
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn copy_file(&self, source: &FileInfo, target: &FileInfo) -> RustusResult<()> {
+        let Some(path) = &source.path else {
+            return Err(RustusError::FileNotFound);
+        };
+        let source = ImportSource::open(Path::new(path))?;
+        self.import_file(target, &source, false).await
+    }
+
     async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+        let cached = file_info
+            .path
+            .as_ref()
+            .and_then(|path| self.take_file(path.as_str()));
         let info = file_info.clone();
         tokio::task::spawn_blocking(move || {
+            drop(cached);
             // Let's remove the file itself.
             let data_path = PathBuf::from(info.path.as_ref().unwrap().clone());
             if !data_path.exists() {
@@ -197,28 +618,169 @@ impl Storage for FileStorage {
         })
         .await?
     }
+
+    async fn sync_data(&self, file_info: &FileInfo) -> RustusResult<()> {
+        let Some(path) = file_info.path.clone() else {
+            return Err(RustusError::UnableToWrite("Cannot get upload path.".into()));
+        };
+        tokio::task::spawn_blocking(move || sync_file(Path::new(path.as_str()))).await??;
+        Ok(())
+    }
+
+    async fn data_exists(&self, file_info: &FileInfo) -> RustusResult<bool> {
+        let Some(path) = file_info.path.clone() else {
+            return Ok(false);
+        };
+        Ok(tokio::fs::try_exists(path).await?)
+    }
+
+    async fn stored_size(&self, file_info: &FileInfo) -> RustusResult<Option<usize>> {
+        let Some(path) = file_info.path.clone() else {
+            return Err(RustusError::FileNotFound);
+        };
+        let meta = tokio::fs::metadata(path).await?;
+        Ok(usize::try_from(meta.len()).ok())
+    }
+
+    async fn list_paths(&self) -> RustusResult<Vec<String>> {
+        let data_dir = self.data_dir.canonicalize()?;
+        tokio::task::spawn_blocking(move || {
+            let mut paths = Vec::new();
+            let mut dirs = vec![data_dir];
+            while let Some(dir) = dirs.pop() {
+                for entry in std::fs::read_dir(dir)? {
+                    let entry = entry?;
+                    if entry.file_type()?.is_dir() {
+                        dirs.push(entry.path());
+                    } else {
+                        paths.push(entry.path().display().to_string());
+                    }
+                }
+            }
+            Ok(paths)
+        })
+        .await?
+    }
+}
+
+/// Set mode and group of the path.
+#[cfg(unix)]
+fn apply_permissions(path: &Path, mode: Option<u32>, group: Option<u32>) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Group is changed first, because `chown`
+    // may reset setgid bit of the mode.
+    if let Some(gid) = group {
+        std::os::unix::fs::chown(path, None, Some(gid))?;
+    }
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn apply_permissions(_path: &Path, _mode: Option<u32>, _group: Option<u32>) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Close files which weren't used longer than `idle_timeout`.
+///
+/// If `sync_idle` is set, unsynced bytes of files
+/// are synced to disk before they're closed.
+///
+/// This function runs until the storage is dropped.
+fn close_idle_files(
+    open_files: &Weak<Mutex<HashMap<String, OpenFile>>>,
+    idle_timeout: Duration,
+    sync_idle: bool,
+) {
+    let check_interval = (idle_timeout / 2).max(Duration::from_millis(100));
+    loop {
+        std::thread::sleep(check_interval);
+        let Some(open_files) = open_files.upgrade() else {
+            return;
+        };
+        let mut idle_files = Vec::new();
+        if let Ok(mut files) = open_files.lock() {
+            let idle_paths = files
+                .iter()
+                .filter(|(_, open_file)| open_file.last_used.elapsed() >= idle_timeout)
+                .map(|(path, _)| path.clone())
+                .collect::<Vec<_>>();
+            for path in idle_paths {
+                idle_files.extend(files.remove(path.as_str()));
+            }
+        }
+        // Files are synced without holding the lock.
+        for open_file in idle_files {
+            if sync_idle && open_file.unsynced > 0 {
+                if let Err(err) = open_file.file.sync_data() {
+                    error!("Cannot sync idle file: {}", err);
+                }
+            }
+        }
+    }
+}
+
+/// Replace streamed body of the response with contents of the file.
+async fn buffer_response(
+    response: HttpResponse,
+    mut handle: std::fs::File,
+) -> RustusResult<HttpResponse> {
+    let contents = tokio::task::spawn_blocking(move || {
+        let mut contents = Vec::new();
+        handle.seek(SeekFrom::Start(0))?;
+        handle.read_to_end(&mut contents)?;
+        RustusResult::Ok(contents)
+    })
+    .await??;
+    Ok(response
+        .set_body(BoxBody::new(Bytes::from(contents)))
+        .map_into_boxed_body())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::FileStorage;
-    use crate::{info_storages::FileInfo, Storage};
-    use actix_web::test::TestRequest;
+    use super::{parse_mode, FileStorage, Permissions};
+    use crate::{
+        errors::RustusError, info_storages::FileInfo, utils::import::ImportSource, Storage,
+    };
+    use actix_web::{
+        body::{to_bytes, BodySize, MessageBody},
+        http::StatusCode,
+        test::TestRequest,
+    };
     use bytes::Bytes;
+    use futures::StreamExt;
     use std::{
         fs::File,
         io::{Read, Write},
         path::PathBuf,
+        time::Duration,
     };
 
     #[actix_rt::test]
     async fn preparation() {
         let dir = tempdir::TempDir::new("file_storage").unwrap();
-        let target_path = dir.into_path().join("not_exist");
+        let target_path = dir.into_path().join("not_exist").join("nested");
         let mut storage = FileStorage::new(target_path.clone(), String::new(), false);
         assert_eq!(target_path.exists(), false);
         storage.prepare().await.unwrap();
         assert_eq!(target_path.exists(), true);
+        // Probe file is removed.
+        assert_eq!(std::fs::read_dir(target_path).unwrap().count(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn preparation_failure() {
+        let dir = tempdir::TempDir::new("file_storage").unwrap().into_path();
+        File::create(dir.join("file")).unwrap();
+        let target_path = dir.join("file").join("data");
+        let mut storage = FileStorage::new(target_path.clone(), String::new(), false);
+        let err = storage.prepare().await.unwrap_err();
+        assert!(matches!(err, RustusError::UnableToPrepareStorage(_)));
+        assert!(err.to_string().contains(target_path.to_str().unwrap()));
     }
 
     #[actix_rt::test]
@@ -230,6 +792,150 @@ mod tests {
         assert!(PathBuf::from(new_path).exists());
     }
 
+    #[actix_rt::test]
+    async fn path_template() {
+        let dir = tempdir::TempDir::new("file_storage").unwrap();
+        let storage = FileStorage::new(dir.path().to_path_buf(), String::from("files"), false)
+            .with_path_template(String::from("{meta:category}/{meta:filename}"));
+        let mut file_info = FileInfo::new("test_id", Some(5), None, storage.to_string(), None);
+        file_info
+            .metadata
+            .insert(String::from("category"), String::from("memes"));
+        file_info
+            .metadata
+            .insert(String::from("filename"), String::from("cat.png"));
+        let path = storage.create_file(&file_info).await.unwrap();
+        let expected = dir
+            .path()
+            .canonicalize()
+            .unwrap()
+            .join("files/memes/cat.png");
+        assert_eq!(PathBuf::from(path), expected);
+        // Another upload with the same path is rejected.
+        file_info.id = String::from("other_id");
+        let res = storage.create_file(&file_info).await;
+        assert!(matches!(res, Err(RustusError::PathCollision(_))));
+        file_info
+            .metadata
+            .insert(String::from("filename"), String::from(".."));
+        let res = storage.create_file(&file_info).await;
+        assert!(matches!(res, Err(RustusError::InvalidPath(_))));
+    }
+
+    #[actix_rt::test]
+    async fn path_template_reserved_dir() {
+        let dir = tempdir::TempDir::new("file_storage").unwrap();
+        // Info directory is the data directory, like by default.
+        let storage = FileStorage::new(dir.path().to_path_buf(), String::new(), false)
+            .with_path_template(String::from("{meta:category}/{meta:filename}"))
+            .with_reserved_dir(dir.path().to_path_buf());
+        let mut file_info = FileInfo::new("test_id", Some(5), None, storage.to_string(), None);
+        file_info
+            .metadata
+            .insert(String::from("category"), String::from("memes"));
+        file_info
+            .metadata
+            .insert(String::from("filename"), String::from("other_id.info"));
+        storage.create_file(&file_info).await.unwrap();
+        // Files can't be created next to the info files.
+        let storage = storage.with_path_template(String::from("{meta:filename}"));
+        let res = storage.create_file(&file_info).await;
+        assert!(matches!(res, Err(RustusError::InvalidPath(_))));
+        assert!(!dir.path().join("other_id.info").exists());
+        // Nor inside the info directory nested into the data directory.
+        let storage = storage
+            .with_path_template(String::from("info/{meta:filename}"))
+            .with_reserved_dir(dir.path().join("info"));
+        let res = storage.create_file(&file_info).await;
+        assert!(matches!(res, Err(RustusError::InvalidPath(_))));
+    }
+
+    #[cfg(unix)]
+    #[actix_rt::test]
+    async fn path_template_symlink() {
+        let dir = tempdir::TempDir::new("file_storage").unwrap();
+        let outside = tempdir::TempDir::new("outside").unwrap();
+        std::os::unix::fs::symlink(outside.path(), dir.path().join("memes")).unwrap();
+        let storage = FileStorage::new(dir.path().to_path_buf(), String::new(), false)
+            .with_path_template(String::from("{meta:category}/{id}"));
+        let mut file_info = FileInfo::new("test_id", Some(5), None, storage.to_string(), None);
+        file_info
+            .metadata
+            .insert(String::from("category"), String::from("memes"));
+        let res = storage.create_file(&file_info).await;
+        assert!(matches!(res, Err(RustusError::InvalidPath(_))));
+        assert!(!outside.path().join("test_id").exists());
+    }
+
+    #[actix_rt::test]
+    async fn import_file() {
+        let dir = tempdir::TempDir::new("file_storage").unwrap();
+        let source = dir.path().join("source");
+        std::fs::write(source.as_path(), b"imported").unwrap();
+        let source = source.canonicalize().unwrap();
+        let mut storage = FileStorage::new(dir.path().join("data"), String::new(), false);
+        storage.prepare().await.unwrap();
+        for hard_link in [false, true] {
+            let mut file_info = FileInfo::new(
+                uuid::Uuid::new_v4().to_string().as_str(),
+                Some(8),
+                None,
+                storage.to_string(),
+                None,
+            );
+            file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+            storage
+                .import_file(
+                    &file_info,
+                    &ImportSource::open(source.as_path()).unwrap(),
+                    hard_link,
+                )
+                .await
+                .unwrap();
+            let path = file_info.path.unwrap();
+            assert_eq!(std::fs::read(path.as_str()).unwrap(), b"imported");
+            std::fs::remove_file(path).unwrap();
+        }
+        // Source is kept in both cases.
+        assert!(source.exists());
+    }
+
+    #[cfg(unix)]
+    #[actix_rt::test]
+    async fn create_file_with_permissions() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = tempdir::TempDir::new("file_storage").unwrap();
+        let base_path = dir.into_path();
+        let group = std::fs::metadata(base_path.as_path()).unwrap().gid();
+        let storage = FileStorage::new(base_path.clone(), String::from("inner/dir"), false)
+            .with_permissions(Permissions {
+                file_mode: Some(0o640),
+                dir_mode: Some(0o750),
+                group: Some(group),
+            });
+        let file_info = FileInfo::new("test_id", Some(5), None, storage.to_string(), None);
+        let new_path = storage.create_file(&file_info).await.unwrap();
+        let file_meta = std::fs::metadata(new_path).unwrap();
+        assert_eq!(file_meta.permissions().mode() & 0o7777, 0o640);
+        assert_eq!(file_meta.gid(), group);
+        for created in ["inner", "inner/dir"] {
+            let dir_meta = std::fs::metadata(base_path.join(created)).unwrap();
+            assert_eq!(dir_meta.permissions().mode() & 0o7777, 0o750);
+        }
+        // Existing directories are left untouched.
+        let base_mode = std::fs::metadata(base_path).unwrap().permissions().mode();
+        assert_ne!(base_mode & 0o7777, 0o750);
+    }
+
+    #[test]
+    fn parsing_mode() {
+        assert_eq!(parse_mode("640"), Ok(0o640));
+        assert_eq!(parse_mode("0o2750"), Ok(0o2750));
+        assert!(parse_mode("648").is_err());
+        assert!(parse_mode("77777").is_err());
+    }
+
     #[actix_rt::test]
     async fn create_file_but_it_exists() {
         let dir = tempdir::TempDir::new("file_storage").unwrap();
@@ -241,6 +947,32 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[actix_rt::test]
+    async fn create_file_with_date_prefix() {
+        let dir = tempdir::TempDir::new("file_storage").unwrap();
+        let base_path = dir.into_path().canonicalize().unwrap();
+        let storage =
+            FileStorage::new(base_path.clone(), String::from("inner"), false).with_date_prefix();
+        let mut file_info = FileInfo::new("test_id", Some(10), None, storage.to_string(), None);
+        file_info.created_at = chrono::DateTime::parse_from_rfc3339("2024-06-05T23:59:00Z")
+            .unwrap()
+            .into();
+        let new_path = storage.create_file(&file_info).await.unwrap();
+        assert_eq!(
+            PathBuf::from(new_path.as_str()),
+            base_path.join("2024/06/05/inner/test_id")
+        );
+        // Upload is resumed on the other day by the stored path.
+        file_info.path = Some(new_path.clone());
+        storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(new_path.as_str()).unwrap(), "memes");
+        storage.remove_file(&file_info).await.unwrap();
+        assert!(!PathBuf::from(new_path).exists());
+    }
+
     #[actix_rt::test]
     async fn adding_bytes() {
         let dir = tempdir::TempDir::new("file_storage").unwrap();
@@ -259,6 +991,29 @@ mod tests {
         assert_eq!(contents, String::from(test_data))
     }
 
+    #[actix_rt::test]
+    async fn read_data() {
+        let dir = tempdir::TempDir::new("file_storage").unwrap();
+        let storage = FileStorage::new(dir.path().to_path_buf(), String::new(), false);
+        let mut file_info = FileInfo::new("test_id", Some(10), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        storage
+            .add_bytes(&file_info, Bytes::from("MyTestData"))
+            .await
+            .unwrap();
+        let chunks = storage
+            .read_data(&file_info, 2..6)
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        let data = chunks.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(data.concat(), b"Test");
+        file_info.path = Some(String::from("/unknown/path"));
+        let result = storage.read_data(&file_info, 0..10).await;
+        assert!(matches!(result, Err(RustusError::FileNotFound)));
+    }
+
     #[actix_rt::test]
     async fn adding_bytes_to_unknown_file() {
         let dir = tempdir::TempDir::new("file_storage").unwrap();
@@ -291,6 +1046,47 @@ mod tests {
         assert!(file_info.is_err());
     }
 
+    #[actix_rt::test]
+    async fn buffered_downloads() {
+        let dir = tempdir::TempDir::new("file_storage").unwrap();
+        let storage = FileStorage::new(dir.into_path().clone(), String::new(), false)
+            .with_buffered_downloads(5);
+        let mut small = FileInfo::new("small", Some(4), None, storage.to_string(), None);
+        small.path = Some(storage.create_file(&small).await.unwrap());
+        storage
+            .add_bytes(&small, Bytes::from("data"))
+            .await
+            .unwrap();
+        let mut large = FileInfo::new("large", Some(10), None, storage.to_string(), None);
+        large.path = Some(storage.create_file(&large).await.unwrap());
+        storage
+            .add_bytes(&large, Bytes::from("large data"))
+            .await
+            .unwrap();
+
+        let request = TestRequest::get().to_http_request();
+        let streamed = storage.get_contents(&large, &request).await.unwrap();
+        let response = storage.get_contents(&small, &request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body().size(), BodySize::Sized(4));
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            streamed.headers().get("Content-Type")
+        );
+        assert!(response.headers().contains_key("Content-Disposition"));
+        assert_eq!(to_bytes(response.into_body()).await.unwrap(), "data");
+        assert_eq!(streamed.body().size(), BodySize::Sized(10));
+        assert_eq!(to_bytes(streamed.into_body()).await.unwrap(), "large data");
+
+        // Range requests are still streamed.
+        let request = TestRequest::get()
+            .insert_header(("Range", "bytes=1-2"))
+            .to_http_request();
+        let response = storage.get_contents(&small, &request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(to_bytes(response.into_body()).await.unwrap(), "at");
+    }
+
     #[actix_rt::test]
     async fn remove_unknown_file() {
         let dir = tempdir::TempDir::new("file_storage").unwrap();
@@ -349,4 +1145,73 @@ mod tests {
 
         assert_eq!(buffer.as_str(), "hello world");
     }
+
+    #[actix_rt::test]
+    async fn open_files_are_cached() {
+        let dir = tempdir::TempDir::new("file_storage").unwrap();
+        let storage = FileStorage::new(dir.into_path(), String::new(), false)
+            .with_open_files(Duration::from_millis(200), None);
+        let mut file_info = FileInfo::new("test_id", Some(10), None, storage.to_string(), None);
+        let path = storage.create_file(&file_info).await.unwrap();
+        file_info.path = Some(path.clone());
+        storage
+            .add_bytes(&file_info, Bytes::from("hello"))
+            .await
+            .unwrap();
+        let open_files = storage.open_files.clone().unwrap();
+        assert!(open_files.lock().unwrap().contains_key(path.as_str()));
+        // Bytes are written right away.
+        assert_eq!(std::fs::read_to_string(path.as_str()).unwrap(), "hello");
+        file_info.offset = 5;
+        storage
+            .add_bytes(&file_info, Bytes::from("world"))
+            .await
+            .unwrap();
+        // File is closed after the last chunk.
+        assert!(open_files.lock().unwrap().is_empty());
+        assert_eq!(
+            std::fs::read_to_string(path.as_str()).unwrap(),
+            "helloworld"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn open_files_are_flushed() {
+        let dir = tempdir::TempDir::new("file_storage").unwrap();
+        let storage = FileStorage::new(dir.into_path(), String::new(), false)
+            .with_open_files(Duration::from_secs(10), Some(8));
+        let mut file_info = FileInfo::new("test_id", Some(20), None, storage.to_string(), None);
+        let path = storage.create_file(&file_info).await.unwrap();
+        file_info.path = Some(path.clone());
+        let open_files = storage.open_files.clone().unwrap();
+        storage
+            .add_bytes(&file_info, Bytes::from("hello"))
+            .await
+            .unwrap();
+        assert_eq!(open_files.lock().unwrap()[path.as_str()].unsynced, 5);
+        file_info.offset = 5;
+        // File is synced once the threshold is reached.
+        storage
+            .add_bytes(&file_info, Bytes::from("world"))
+            .await
+            .unwrap();
+        assert_eq!(open_files.lock().unwrap()[path.as_str()].unsynced, 0);
+    }
+
+    #[actix_rt::test]
+    async fn idle_files_are_closed() {
+        let dir = tempdir::TempDir::new("file_storage").unwrap();
+        let storage = FileStorage::new(dir.into_path(), String::new(), false)
+            .with_open_files(Duration::from_millis(200), None);
+        let mut file_info = FileInfo::new("test_id", Some(10), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        storage
+            .add_bytes(&file_info, Bytes::from("hello"))
+            .await
+            .unwrap();
+        let open_files = storage.open_files.clone().unwrap();
+        assert_eq!(open_files.lock().unwrap().len(), 1);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(open_files.lock().unwrap().is_empty());
+    }
 }
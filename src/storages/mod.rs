@@ -1,5 +1,25 @@
+pub mod aligned_storage;
+pub mod chunk_index;
+pub mod consistent_storage;
+pub mod failover_storage;
 pub mod file_storage;
 mod models;
+#[cfg(feature = "null_storage")]
+pub mod null_storage;
+pub mod packed_storage;
+mod registry;
+pub mod replicated_storage;
 pub mod s3_hybrid_storage;
+pub mod timeout_storage;
+#[cfg(feature = "otlp_tracing")]
+pub mod traced_storage;
+pub mod webdav_storage;
 
-pub use models::{available_stores::AvailableStores, storage::Storage};
+pub use models::{
+    available_stores::AvailableStores,
+    storage::{
+        aligned_len, copy_contents, read_file, slice_stream, Compaction, DataLocation, DataStream,
+        Storage,
+    },
+};
+pub use registry::{register_storage, StorageFactory};
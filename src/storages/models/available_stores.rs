@@ -1,58 +1,168 @@
 use crate::{
-    from_str,
-    storages::{file_storage, s3_hybrid_storage},
+    info_storages::AvailableInfoStores,
+    storages::{
+        chunk_index::ChunkIndex, file_storage, packed_storage, registry, s3_hybrid_storage,
+        webdav_storage,
+    },
     RustusConf, Storage,
 };
-use derive_more::{Display, From};
-use std::{
-    fs::File,
-    io::{BufReader, Read},
-    path::PathBuf,
-};
-use strum::EnumIter;
+use derive_more::Display;
+use std::{str::FromStr, time::Duration};
+
+#[cfg(feature = "null_storage")]
+use crate::storages::null_storage;
 
 /// Enum of available Storage implementations.
-#[derive(PartialEq, Eq, From, Display, EnumIter, Clone, Debug)]
+#[derive(PartialEq, Eq, Display, Clone, Debug)]
 pub enum AvailableStores {
     #[display(fmt = "file-storage")]
     FileStorage,
     #[display(fmt = "hybrid-s3")]
     HybridS3,
+    #[display(fmt = "packed-file-storage")]
+    PackedFileStorage,
+    #[display(fmt = "webdav")]
+    WebDav,
+    /// Storage which discards data, for benchmarks only.
+    #[cfg(feature = "null_storage")]
+    #[display(fmt = "null")]
+    Null,
+    /// Storage registered with `register_storage`.
+    #[display(fmt = "{_0}")]
+    Custom(String),
 }
 
-from_str!(AvailableStores, "storage");
+impl FromStr for AvailableStores {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let builtin = [
+            Self::FileStorage,
+            Self::HybridS3,
+            Self::PackedFileStorage,
+            Self::WebDav,
+            #[cfg(feature = "null_storage")]
+            Self::Null,
+        ];
+        if let Some(store) = builtin.iter().find(|store| store.to_string() == input) {
+            return Ok(store.clone());
+        }
+        if registry::get_factory(input).is_some() {
+            return Ok(Self::Custom(String::from(input)));
+        }
+        let available_stores = Self::names()
+            .iter()
+            .map(|name| format!("\t* {name}"))
+            .collect::<Vec<String>>()
+            .join("\n");
+        Err(format!(
+            "Unknown storage '{input}'.\n Available storages:\n{available_stores}"
+        ))
+    }
+}
 
 impl AvailableStores {
+    /// Names of built-in and registered storages.
+    pub fn names() -> Vec<String> {
+        [
+            Self::FileStorage,
+            Self::HybridS3,
+            Self::PackedFileStorage,
+            Self::WebDav,
+            #[cfg(feature = "null_storage")]
+            Self::Null,
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .chain(registry::registered_names())
+        .collect()
+    }
+
     /// Convert `AvailableStores` to the Storage.
     ///
     /// # Params
     /// `config` - Rustus configuration.
-    /// `info_storage` - Storage for information about files.
+    /// `chunk_index` - Index of written chunks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if required options of the storage aren't set.
     ///
     #[cfg_attr(coverage, no_coverage)]
-    pub fn get(&self, config: &RustusConf) -> Box<dyn Storage + Send + Sync> {
-        #[allow(clippy::single_match)]
+    #[must_use]
+    #[allow(clippy::too_many_lines)]
+    pub fn get(
+        &self,
+        config: &RustusConf,
+        chunk_index: Option<Box<dyn ChunkIndex + Send + Sync>>,
+    ) -> Box<dyn Storage + Send + Sync> {
+        if config.storage_opts.path_template.is_some() && self != &Self::FileStorage {
+            log::warn!("Path template is supported only by file storage. It will be ignored.");
+        }
+        if chunk_index.is_some() && self != &Self::PackedFileStorage {
+            log::warn!("Chunk index is supported only by packed file storage. It will be ignored.");
+        }
         match self {
-            Self::FileStorage => Box::new(file_storage::FileStorage::new(
-                config.storage_opts.data_dir.clone(),
-                config.storage_opts.dir_structure.clone(),
-                config.storage_opts.force_fsync,
-            )),
+            Self::FileStorage => {
+                let mut storage = file_storage::FileStorage::new(
+                    config.storage_opts.data_dir.clone(),
+                    config.storage_opts.dir_structure.clone(),
+                    config.storage_opts.force_fsync,
+                )
+                .with_permissions(config.storage_opts.permissions());
+                if config.storage_opts.date_prefix {
+                    storage = storage.with_date_prefix();
+                }
+                if let Some(template) = &config.storage_opts.path_template {
+                    storage = storage.with_path_template(template.clone());
+                    if config.info_storage_opts.info_storage == AvailableInfoStores::Files {
+                        storage =
+                            storage.with_reserved_dir(config.info_storage_opts.info_dir.clone());
+                    }
+                }
+                if let Some(max_size) = config.storage_opts.buffered_download_size {
+                    storage = storage.with_buffered_downloads(max_size);
+                }
+                match config.storage_opts.keep_files_open {
+                    Some(idle_timeout) => Box::new(storage.with_open_files(
+                        Duration::from_secs(idle_timeout),
+                        config.storage_opts.keep_files_open_flush_size,
+                    )),
+                    None => Box::new(storage),
+                }
+            }
+            Self::PackedFileStorage => {
+                let mut files = file_storage::FileStorage::new(
+                    config.storage_opts.data_dir.clone(),
+                    config.storage_opts.dir_structure.clone(),
+                    config.storage_opts.force_fsync,
+                )
+                .with_permissions(config.storage_opts.permissions());
+                if config.storage_opts.date_prefix {
+                    files = files.with_date_prefix();
+                }
+                if let Some(max_size) = config.storage_opts.buffered_download_size {
+                    files = files.with_buffered_downloads(max_size);
+                }
+                let mut storage = packed_storage::PackedStorage::new(
+                    files,
+                    config.storage_opts.data_dir.as_path(),
+                    config.storage_opts.pack_size,
+                    config.storage_opts.pack_max_object_size,
+                    config.storage_opts.force_fsync,
+                );
+                if let Some(chunk_index) = chunk_index {
+                    storage = storage.with_chunk_index(chunk_index);
+                }
+                Box::new(storage)
+            }
             Self::HybridS3 => {
                 log::warn!("Hybrid S3 is an unstable feature. If you ecounter a problem, please raise an issue: https://github.com/s3rius/rustus/issues.");
-                let access_key = from_string_or_path(
-                    &config.storage_opts.s3_access_key,
-                    &config.storage_opts.s3_access_key_path,
-                );
-                let secret_key = from_string_or_path(
-                    &config.storage_opts.s3_secret_key,
-                    &config.storage_opts.s3_secret_key_path,
-                );
-                Box::new(s3_hybrid_storage::S3HybridStorage::new(
+                let mut storage = s3_hybrid_storage::S3HybridStorage::new(
                     config.storage_opts.s3_url.clone().unwrap(),
                     config.storage_opts.s3_region.clone().unwrap(),
-                    &Some(access_key),
-                    &Some(secret_key),
+                    &config.storage_opts.s3_access_key,
+                    &config.storage_opts.s3_secret_key,
                     &config.storage_opts.s3_security_token,
                     &config.storage_opts.s3_session_token,
                     &config.storage_opts.s3_profile,
@@ -62,25 +172,39 @@ impl AvailableStores {
                     config.storage_opts.data_dir.clone(),
                     config.storage_opts.dir_structure.clone(),
                     config.storage_opts.force_fsync,
-                ))
+                    config.storage_opts.s3_out_of_order_chunks,
+                );
+                if let Some(prefix) = &config.storage_opts.storage_prefix {
+                    storage = storage.with_prefix(prefix.clone());
+                }
+                Box::new(storage)
+            }
+            Self::WebDav => {
+                let mut storage = webdav_storage::WebDavStorage::new(
+                    config.storage_opts.webdav_url.clone().unwrap().as_str(),
+                    config.storage_opts.webdav_username.clone(),
+                    config.storage_opts.webdav_password.clone(),
+                    config.storage_opts.data_dir.clone(),
+                    config.storage_opts.dir_structure.clone(),
+                    config.storage_opts.force_fsync,
+                );
+                if let Some(prefix) = &config.storage_opts.storage_prefix {
+                    storage = storage.with_prefix(prefix.clone());
+                }
+                Box::new(storage)
+            }
+            #[cfg(feature = "null_storage")]
+            Self::Null => {
+                log::warn!(
+                    "Null storage discards all uploaded data. It must be used only for benchmarks."
+                );
+                Box::<null_storage::NullStorage>::default()
+            }
+            Self::Custom(name) => {
+                // Custom storages can only be parsed if they are registered.
+                let factory = registry::get_factory(name.as_str()).unwrap();
+                factory(config)
             }
         }
     }
 }
-
-// TODO this should probably be a COW
-fn from_string_or_path(variable: &Option<String>, path: &Option<PathBuf>) -> String {
-    if let Some(variable) = variable {
-        variable.to_string()
-    } else if let Some(path) = path {
-        let file =
-            File::open(path).unwrap_or_else(|_| panic!("failed to open path {}", path.display()));
-        let mut contents = String::new();
-        BufReader::new(file)
-            .read_to_string(&mut contents)
-            .unwrap_or_else(|_| panic!("failed to read from path {}", path.display()));
-        contents
-    } else {
-        panic!("can't find {variable:?} or path {path:?}")
-    }
-}
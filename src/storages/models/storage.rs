@@ -1,9 +1,48 @@
-use crate::{errors::RustusResult, info_storages::FileInfo};
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    utils::import::ImportSource,
+};
 use actix_web::{HttpRequest, HttpResponse};
 use async_trait::async_trait;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use dyn_clone::DynClone;
-use std::fmt::Display;
+use futures::{stream::LocalBoxStream, Stream, StreamExt};
+use serde::Serialize;
+use std::{fmt::Display, io::SeekFrom, ops::Range, path::Path};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+/// Size of chunks used to import and copy files.
+pub(crate) const IMPORT_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Stream of bytes of an upload.
+pub type DataStream = LocalBoxStream<'static, RustusResult<Bytes>>;
+
+/// Uploads moved by compaction.
+#[derive(Debug, Default)]
+pub struct Compaction {
+    /// Moved uploads with their new paths.
+    ///
+    /// Data is copied, so it's available at both paths.
+    /// Space is reclaimed once uploads are removed
+    /// with their old paths.
+    pub moved: Vec<(FileInfo, String)>,
+    /// Bytes which are reclaimed after all moved uploads are removed.
+    pub reclaimed: u64,
+}
+
+/// Place where data of an upload is stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DataLocation {
+    /// Name of the storage.
+    pub storage: String,
+    /// Local path, object key or URL of the data.
+    pub path: String,
+    /// Bucket of the object.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+}
 
 #[async_trait(?Send)]
 pub trait Storage: Display + DynClone {
@@ -32,6 +71,28 @@ pub trait Storage: Display + DynClone {
         request: &HttpRequest,
     ) -> RustusResult<HttpResponse>;
 
+    /// Read data of the upload.
+    ///
+    /// Unlike `get_contents`, it doesn't depend on a request,
+    /// so it's used to read uploads inside rustus,
+    /// E.G. to copy or hash them.
+    ///
+    /// Stream may end before the end of the range
+    /// if less bytes are stored.
+    ///
+    /// # Params
+    /// `file_info` - info about current file.
+    /// `range` - bytes of the upload to read.
+    async fn read_data(
+        &self,
+        _file_info: &FileInfo,
+        _range: Range<usize>,
+    ) -> RustusResult<DataStream> {
+        Err(RustusError::Unimplemented(format!(
+            "{self} cannot read files."
+        )))
+    }
+
     /// Add bytes to the file.
     ///
     /// This method is used to append bytes to some file.
@@ -48,6 +109,69 @@ pub trait Storage: Display + DynClone {
     /// `bytes` - bytes to append to the file.
     async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()>;
 
+    /// Check if storage accepts chunks at arbitrary offsets.
+    ///
+    /// If it returns true, `add_bytes` is called with
+    /// `file_info.offset` set to the offset of the chunk,
+    /// and `file_info.received` containing already received ranges.
+    fn accepts_out_of_order(&self) -> bool {
+        false
+    }
+
+    /// Size in bytes that chunks must be multiples of.
+    ///
+    /// If it returns some size, only aligned part of every chunk
+    /// is written, and clients send the rest again.
+    /// The last chunk of an upload may be of any size.
+    fn chunk_alignment(&self) -> Option<usize> {
+        None
+    }
+
+    /// Size in bytes of chunks that clients should send.
+    ///
+    /// It's advertised in `OPTIONS` responses,
+    /// so clients don't have to guess chunk sizes.
+    fn preferred_chunk_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Check if written data can be read right after the write.
+    ///
+    /// Storages which return false are wrapped,
+    /// so written chunks are read back before
+    /// the offset of the upload is advanced.
+    fn read_after_write(&self) -> bool {
+        true
+    }
+
+    /// Get location of the upload's data.
+    ///
+    /// It's returned by admin API for manual intervention.
+    /// By default it's the path of the upload.
+    ///
+    /// # Params
+    /// `file_info` - info about current file.
+    fn data_location(&self, file_info: &FileInfo) -> Option<DataLocation> {
+        file_info.path.as_ref().map(|path| DataLocation {
+            storage: self.to_string(),
+            path: path.clone(),
+            bucket: None,
+        })
+    }
+
+    /// Remove bytes written after `file_info.offset`.
+    ///
+    /// This method is used to clean up
+    /// after writes that were interrupted by a timeout.
+    ///
+    /// # Params
+    /// `file_info` - info about current file.
+    async fn truncate(&self, _file_info: &FileInfo) -> RustusResult<()> {
+        Err(RustusError::Unimplemented(format!(
+            "{self} cannot truncate files."
+        )))
+    }
+
     /// Create file in storage.
     ///
     /// This method is used to generate unique file id, create file and store information about it.
@@ -58,6 +182,20 @@ pub trait Storage: Display + DynClone {
     /// `file_info` - info about current file.
     async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String>;
 
+    /// Create file for the new upload.
+    ///
+    /// It sets path of the upload and other fields
+    /// which storage needs to find the data, like `backend`.
+    /// Uploads must be created with this method,
+    /// since `create_file` can't change them.
+    ///
+    /// # Params
+    /// `file_info` - info about current file.
+    async fn create_upload(&self, file_info: &mut FileInfo) -> RustusResult<()> {
+        file_info.path = Some(self.create_file(file_info).await?);
+        Ok(())
+    }
+
     /// Concatenate files.
     ///
     /// This method is used to merge multiple files together.
@@ -73,6 +211,68 @@ pub trait Storage: Display + DynClone {
         parts_info: Vec<FileInfo>,
     ) -> RustusResult<()>;
 
+    /// Import data of the upload from a local file.
+    ///
+    /// Upload must be created with `create_file` and
+    /// its length must be equal to the size of the file.
+    /// By default the file is copied in chunks with `add_bytes`.
+    ///
+    /// # Params
+    /// `file_info` - info about current file.
+    /// `source` - the opened imported file.
+    /// `hard_link` - link the file instead of copying, if the storage can.
+    async fn import_file(
+        &self,
+        file_info: &FileInfo,
+        source: &ImportSource,
+        _hard_link: bool,
+    ) -> RustusResult<()> {
+        let mut source = tokio::fs::File::from_std(source.file.try_clone()?);
+        let mut info = file_info.clone();
+        loop {
+            let mut chunk = BytesMut::with_capacity(IMPORT_CHUNK_SIZE);
+            while chunk.len() < IMPORT_CHUNK_SIZE {
+                if source.read_buf(&mut chunk).await? == 0 {
+                    break;
+                }
+            }
+            if chunk.is_empty() {
+                break;
+            }
+            let chunk_len = chunk.len();
+            if info
+                .length
+                .is_some_and(|length| info.offset + chunk_len > length)
+            {
+                return Err(RustusError::UnableToWrite(String::from(
+                    "Imported file was changed during import.",
+                )));
+            }
+            self.add_bytes(&info, chunk.freeze()).await?;
+            info.offset += chunk_len;
+        }
+        if info.length != Some(info.offset) {
+            return Err(RustusError::UnableToWrite(String::from(
+                "Imported file was changed during import.",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Copy data of a finished upload into another upload.
+    ///
+    /// Target must be created with `create_file` and
+    /// its length must be equal to the length of the source.
+    /// By default contents of the source are read with `get_contents`
+    /// and written in chunks with `add_bytes`.
+    ///
+    /// # Params
+    /// `source` - info about the copied upload.
+    /// `target` - info about the new upload.
+    async fn copy_file(&self, source: &FileInfo, target: &FileInfo) -> RustusResult<()> {
+        copy_contents(self, source, self, target).await
+    }
+
     /// Remove file from storage
     ///
     /// This method removes file and all associated
@@ -81,6 +281,213 @@ pub trait Storage: Display + DynClone {
     /// # Params
     /// `file_info` - info about current file.
     async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()>;
+
+    /// Flush data of the upload to durable storage.
+    ///
+    /// It's called once the upload is finished,
+    /// regardless of `force_fsync` option.
+    /// Storages which store data remotely
+    /// may do nothing here.
+    ///
+    /// # Params
+    /// `file_info` - info about current file.
+    async fn sync_data(&self, _file_info: &FileInfo) -> RustusResult<()> {
+        Ok(())
+    }
+
+    /// Check if data of the upload exists.
+    ///
+    /// It's used to detect uploads whose data was removed
+    /// while information about them was kept.
+    ///
+    /// # Params
+    /// `file_info` - info about current file.
+    async fn data_exists(&self, _file_info: &FileInfo) -> RustusResult<bool> {
+        Ok(true)
+    }
+
+    /// Get size of stored data of the upload.
+    ///
+    /// It's used to verify finished uploads before
+    /// hooks are sent. Storages which can't find out
+    /// the size cheaply return `None`.
+    ///
+    /// # Params
+    /// `file_info` - info about current file.
+    async fn stored_size(&self, _file_info: &FileInfo) -> RustusResult<Option<usize>> {
+        Ok(None)
+    }
+
+    /// Move uploads out of fragmented space.
+    ///
+    /// `uploads` are finished uploads of this storage.
+    /// Space is fragmented if the fraction of removed
+    /// data in it is at least `threshold`. Space which also
+    /// holds uploads that aren't given is never compacted.
+    ///
+    /// Storages which reclaim space on removal
+    /// may do nothing here.
+    ///
+    /// # Params
+    /// `uploads` - uploads which can be moved.
+    /// `threshold` - fraction of removed data.
+    async fn compact(&self, _uploads: &[FileInfo], _threshold: f64) -> RustusResult<Compaction> {
+        Ok(Compaction::default())
+    }
+
+    /// Move uploads from fallback storage back to the main one.
+    ///
+    /// It returns moved uploads with their new paths.
+    /// Data is copied, so it's available at both paths
+    /// until uploads are removed with their old paths.
+    ///
+    /// Storages without fallback do nothing here.
+    ///
+    /// # Params
+    /// `uploads` - finished uploads which can be moved.
+    async fn reconcile(&self, _uploads: &[FileInfo]) -> RustusResult<Vec<(FileInfo, String)>> {
+        Ok(Vec::new())
+    }
+
+    /// List paths of all stored files.
+    ///
+    /// It's used by maintenance scans to find
+    /// data without information about it.
+    async fn list_paths(&self) -> RustusResult<Vec<String>> {
+        Err(RustusError::Unimplemented(format!(
+            "{self} cannot list files."
+        )))
+    }
 }
 
 dyn_clone::clone_trait_object!(Storage);
+
+/// Number of bytes of the chunk that can be written to the storage.
+///
+/// Chunks must end at a multiple of `alignment`, unless
+/// they finish the upload. Bytes after the last aligned
+/// boundary aren't written, so the client sends them again.
+///
+/// # Params
+/// `alignment` - alignment of the storage.
+/// `file_info` - info about the upload, chunk starts at its offset.
+/// `chunk_len` - length of the chunk.
+#[must_use]
+pub fn aligned_len(alignment: Option<usize>, file_info: &FileInfo, chunk_len: usize) -> usize {
+    let end = file_info.offset + chunk_len;
+    match alignment {
+        Some(alignment) if file_info.length != Some(end) => {
+            (end - end % alignment).saturating_sub(file_info.offset)
+        }
+        _ => chunk_len,
+    }
+}
+
+/// Read range of bytes of a local file.
+///
+/// # Errors
+///
+/// Returns `FileNotFound` if the file doesn't exist.
+pub async fn read_file(path: &Path, range: Range<u64>) -> RustusResult<DataStream> {
+    let mut file = tokio::fs::File::open(path).await.map_err(|err| {
+        log::error!("{:?}", err);
+        RustusError::FileNotFound
+    })?;
+    file.seek(SeekFrom::Start(range.start)).await?;
+    let length = range.end.saturating_sub(range.start);
+    Ok(ReaderStream::new(file.take(length))
+        .map(|chunk| chunk.map_err(RustusError::from))
+        .boxed_local())
+}
+
+/// Take `length` bytes of the stream after skipping `skip` bytes.
+///
+/// It's used for servers which may ignore requested ranges.
+pub fn slice_stream<S>(stream: S, skip: usize, length: usize) -> DataStream
+where
+    S: Stream<Item = RustusResult<Bytes>> + 'static,
+{
+    futures::stream::unfold(
+        (stream.boxed_local(), skip, length),
+        |(mut stream, mut skip, mut remaining)| async move {
+            while remaining > 0 {
+                let mut bytes = match stream.next().await? {
+                    Ok(bytes) => bytes,
+                    Err(err) => return Some((Err(err), (stream, skip, 0))),
+                };
+                let skipped = skip.min(bytes.len());
+                bytes = bytes.slice(skipped..);
+                skip -= skipped;
+                bytes.truncate(remaining);
+                remaining -= bytes.len();
+                if !bytes.is_empty() {
+                    return Some((Ok(bytes), (stream, skip, remaining)));
+                }
+            }
+            None
+        },
+    )
+    .boxed_local()
+}
+
+/// Copy contents of a finished upload into an upload of another storage.
+///
+/// Contents of the source are read with `read_data`
+/// and written in chunks with `add_bytes`.
+///
+/// # Errors
+///
+/// Returns an error if the source can't be read, the target can't
+/// be written or the length of contents differs from the length of the target.
+///
+/// # Params
+/// `from` - storage of the copied upload.
+/// `source` - info about the copied upload.
+/// `to` - storage of the new upload.
+/// `target` - info about the new upload.
+pub async fn copy_contents<F, T>(
+    from: &F,
+    source: &FileInfo,
+    to: &T,
+    target: &FileInfo,
+) -> RustusResult<()>
+where
+    F: Storage + ?Sized,
+    T: Storage + ?Sized,
+{
+    let mut body = from.read_data(source, 0..source.offset).await?;
+    let mut info = target.clone();
+    let mut chunk = BytesMut::new();
+    loop {
+        let next = body.next().await;
+        let finished = next.is_none();
+        if let Some(bytes) = next {
+            chunk.extend_from_slice(&bytes?);
+        }
+        if chunk.len() >= IMPORT_CHUNK_SIZE || (finished && !chunk.is_empty()) {
+            let chunk_len = chunk.len();
+            if info
+                .length
+                .is_some_and(|length| info.offset + chunk_len > length)
+            {
+                return Err(RustusError::UnableToWrite(String::from(
+                    "Copied upload is longer than its length.",
+                )));
+            }
+            // Unaligned bytes are written with the next chunk.
+            let chunk_len = aligned_len(to.chunk_alignment(), &info, chunk_len);
+            to.add_bytes(&info, chunk.split_to(chunk_len).freeze())
+                .await?;
+            info.offset += chunk_len;
+        }
+        if finished {
+            break;
+        }
+    }
+    if info.length != Some(info.offset) {
+        return Err(RustusError::UnableToWrite(String::from(
+            "Copied upload is shorter than its length.",
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use bytes::Bytes;
+use derive_more::Display;
+use futures::StreamExt;
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    storages::{DataStream, Storage},
+};
+
+/// Storage which discards all written bytes.
+///
+/// It's a tool for benchmarks, which measures overhead
+/// of the server without any storage I/O.
+/// Only the number of received bytes of unfinished uploads
+/// is kept in memory and downloads are always empty.
+///
+/// It must never be used to store real uploads.
+#[derive(Display, Clone, Default)]
+#[display(fmt = "null")]
+pub struct NullStorage {
+    received: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl NullStorage {
+    fn received(&self) -> RustusResult<std::sync::MutexGuard<'_, HashMap<String, usize>>> {
+        self.received.lock().map_err(|_| RustusError::Unknown)
+    }
+
+    /// Remember number of received bytes of the upload.
+    ///
+    /// Finished uploads are forgotten, otherwise the map
+    /// would grow with every upload that is never removed.
+    fn set_received(&self, file_info: &FileInfo, received: usize) -> RustusResult<()> {
+        let mut uploads = self.received()?;
+        if file_info.length == Some(received) {
+            uploads.remove(file_info.id.as_str());
+        } else {
+            uploads.insert(file_info.id.clone(), received);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for NullStorage {
+    async fn prepare(&mut self) -> RustusResult<()> {
+        Ok(())
+    }
+
+    async fn get_contents(
+        &self,
+        _file_info: &FileInfo,
+        _request: &HttpRequest,
+    ) -> RustusResult<HttpResponse> {
+        Ok(HttpResponse::Ok().finish())
+    }
+
+    async fn read_data(
+        &self,
+        _file_info: &FileInfo,
+        _range: Range<usize>,
+    ) -> RustusResult<DataStream> {
+        Ok(futures::stream::empty().boxed_local())
+    }
+
+    async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+        self.set_received(file_info, file_info.offset + bytes.len())
+    }
+
+    async fn truncate(&self, file_info: &FileInfo) -> RustusResult<()> {
+        self.set_received(file_info, file_info.offset)
+    }
+
+    async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
+        self.set_received(file_info, 0)?;
+        Ok(file_info.id.clone())
+    }
+
+    async fn concat_files(
+        &self,
+        file_info: &FileInfo,
+        parts_info: Vec<FileInfo>,
+    ) -> RustusResult<()> {
+        let received = parts_info.iter().map(|part| part.offset).sum();
+        self.set_received(file_info, received)
+    }
+
+    async fn copy_file(&self, source: &FileInfo, target: &FileInfo) -> RustusResult<()> {
+        self.set_received(target, source.offset)
+    }
+
+    async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+        self.received()?.remove(file_info.id.as_str());
+        Ok(())
+    }
+
+    async fn stored_size(&self, file_info: &FileInfo) -> RustusResult<Option<usize>> {
+        // Sizes are lost on restart and finished uploads
+        // are forgotten, so unknown uploads aren't checked.
+        Ok(self.received()?.get(file_info.id.as_str()).copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NullStorage;
+    use crate::{info_storages::FileInfo, Storage};
+    use actix_web::{body::MessageBody, test::TestRequest};
+    use bytes::Bytes;
+
+    #[actix_rt::test]
+    async fn discarded_bytes() {
+        let storage = NullStorage::default();
+        let mut file_info = FileInfo::new("test_id", Some(10), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        file_info.offset = 5;
+        assert_eq!(storage.stored_size(&file_info).await.unwrap(), Some(5));
+        storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        // Finished uploads aren't kept in memory.
+        assert_eq!(storage.stored_size(&file_info).await.unwrap(), None);
+        let request = TestRequest::get().to_http_request();
+        let response = storage.get_contents(&file_info, &request).await.unwrap();
+        assert!(response.status().is_success());
+        let body = response.into_body().try_into_bytes().unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn removed_upload() {
+        let storage = NullStorage::default();
+        let file_info = FileInfo::new("test_id", None, None, storage.to_string(), None);
+        storage.create_file(&file_info).await.unwrap();
+        storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        assert_eq!(storage.stored_size(&file_info).await.unwrap(), Some(5));
+        storage.remove_file(&file_info).await.unwrap();
+        assert_eq!(storage.stored_size(&file_info).await.unwrap(), None);
+    }
+}
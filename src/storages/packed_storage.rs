@@ -0,0 +1,921 @@
+use std::{
+    collections::HashMap,
+    fs::{remove_file, OpenOptions},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use actix_web::{HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use bytes::Bytes;
+use derive_more::Display;
+use futures::{stream, StreamExt};
+use log::{error, warn};
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    storages::{
+        chunk_index::{ChunkIndex, IndexedChunk},
+        file_storage::FileStorage,
+        read_file, Compaction, DataStream, Storage,
+    },
+    utils::durability::sync_file,
+};
+
+/// Extension of pack files.
+const PACK_EXTENSION: &str = "pack";
+/// Extension of files with removed ranges of packs.
+const FREE_EXTENSION: &str = "free";
+
+/// Location of an upload inside of a pack.
+///
+/// It's stored as a path of the upload,
+/// so the index lives in the info storage.
+/// Format is `{pack_path}#{offset}+{length}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackEntry {
+    pub pack: PathBuf,
+    /// Position of the upload in the pack.
+    pub offset: u64,
+    /// Space reserved for the upload.
+    pub length: u64,
+}
+
+impl PackEntry {
+    /// Parse entry from the path of an upload.
+    ///
+    /// Returns `None` for uploads stored in separate files.
+    #[must_use]
+    pub fn parse(path: &str) -> Option<Self> {
+        let (pack, range) = path.rsplit_once('#')?;
+        let (offset, length) = range.split_once('+')?;
+        let pack = PathBuf::from(pack);
+        if pack.extension()? != PACK_EXTENSION {
+            return None;
+        }
+        Some(Self {
+            pack,
+            offset: offset.parse().ok()?,
+            length: length.parse().ok()?,
+        })
+    }
+
+    /// Path of the upload.
+    #[must_use]
+    pub fn to_path(&self) -> String {
+        format!("{}#{}+{}", self.pack.display(), self.offset, self.length)
+    }
+
+    /// Read bytes of the entry.
+    ///
+    /// Only first `length` bytes are read.
+    fn read(&self, length: usize) -> RustusResult<Vec<u8>> {
+        let mut pack = OpenOptions::new()
+            .read(true)
+            .open(self.pack.as_path())
+            .map_err(|err| {
+                error!("{:?}", err);
+                RustusError::FileNotFound
+            })?;
+        pack.seek(SeekFrom::Start(self.offset))?;
+        let mut contents = vec![0; length];
+        pack.read_exact(contents.as_mut_slice())?;
+        Ok(contents)
+    }
+}
+
+/// Location of a chunk in the chunk index.
+///
+/// Format is `{pack_path}#{position}`.
+fn chunk_location(pack: &Path, position: u64) -> String {
+    format!("{}#{}", pack.display(), position)
+}
+
+/// Parse pack and position of a chunk.
+fn parse_location(location: &str) -> Option<(PathBuf, u64)> {
+    let (pack, position) = location.rsplit_once('#')?;
+    Some((PathBuf::from(pack), position.parse().ok()?))
+}
+
+/// Pack which receives new uploads.
+#[derive(Debug, Default)]
+struct ActivePack {
+    index: u64,
+    /// Reserved space of the pack.
+    size: u64,
+}
+
+/// Storage which puts small uploads in large pack files.
+///
+/// Space for an upload is reserved in the active pack when
+/// the upload is created, so concurrent uploads never interleave.
+/// Once the active pack is full, the next one is started.
+///
+/// Uploads of unknown length, uploads larger than `max_object_size`
+/// and final uploads are stored as separate files.
+///
+/// Removed uploads are recorded in a `.free` file next to their pack.
+/// Packs are removed when all their uploads are removed.
+///
+/// If the chunk index is set, written chunks of packed uploads
+/// are recorded in it and ranges are read from found locations.
+#[derive(Display, Clone)]
+#[display(fmt = "packed_file_storage")]
+pub struct PackedStorage {
+    files: FileStorage,
+    packs_dir: PathBuf,
+    pack_size: u64,
+    max_object_size: u64,
+    force_fsync: bool,
+    active: Arc<Mutex<ActivePack>>,
+    chunk_index: Option<Box<dyn ChunkIndex + Send + Sync>>,
+}
+
+impl PackedStorage {
+    /// Create new storage.
+    ///
+    /// Packs are stored in the `packs` directory
+    /// inside of the data directory of `files`.
+    #[must_use]
+    pub fn new(
+        files: FileStorage,
+        data_dir: &Path,
+        pack_size: u64,
+        max_object_size: u64,
+        force_fsync: bool,
+    ) -> Self {
+        Self {
+            files,
+            packs_dir: data_dir.join("packs"),
+            pack_size,
+            max_object_size,
+            force_fsync,
+            active: Arc::default(),
+            chunk_index: None,
+        }
+    }
+
+    /// Record written chunks in the index.
+    #[must_use]
+    pub fn with_chunk_index(mut self, chunk_index: Box<dyn ChunkIndex + Send + Sync>) -> Self {
+        self.chunk_index = Some(chunk_index);
+        self
+    }
+
+    /// Find ranges of the pack with bytes `start..end` of the entry.
+    ///
+    /// Returns `None` if indexed chunks don't cover the range
+    /// or are located in another pack. It happens to uploads
+    /// written before the index was enabled and to uploads
+    /// whose compaction wasn't finished.
+    fn indexed_ranges(
+        entry: &PackEntry,
+        chunks: &[IndexedChunk],
+        start: usize,
+        end: usize,
+    ) -> Option<Vec<Range<u64>>> {
+        let mut ranges = Vec::with_capacity(chunks.len());
+        let mut position = start;
+        for chunk in chunks {
+            let (pack, chunk_position) = parse_location(chunk.location.as_str())?;
+            if pack != entry.pack || chunk.start > position {
+                return None;
+            }
+            let chunk_end = chunk.end.min(end);
+            ranges.push(
+                chunk_position + (position - chunk.start) as u64
+                    ..chunk_position + (chunk_end - chunk.start) as u64,
+            );
+            position = chunk_end;
+        }
+        (position >= end).then_some(ranges)
+    }
+
+    fn pack_path(&self, index: u64) -> PathBuf {
+        self.packs_dir
+            .join(format!("pack-{index:08}"))
+            .with_extension(PACK_EXTENSION)
+    }
+
+    /// Whether the upload must be put in a pack.
+    fn is_packed(&self, file_info: &FileInfo) -> bool {
+        !file_info.is_final
+            && file_info
+                .length
+                .is_some_and(|length| length as u64 <= self.max_object_size)
+    }
+
+    fn entry(file_info: &FileInfo) -> RustusResult<Option<PackEntry>> {
+        let Some(path) = &file_info.path else {
+            return Err(RustusError::FileNotFound);
+        };
+        Ok(PackEntry::parse(path.as_str()))
+    }
+
+    /// Reserve space for the upload in the active pack.
+    fn reserve(&self, length: u64) -> RustusResult<PackEntry> {
+        let mut active = self
+            .active
+            .lock()
+            .map_err(|err| RustusError::UnableToWrite(err.to_string()))?;
+        if active.size > 0 && active.size + length > self.pack_size {
+            active.index += 1;
+            active.size = 0;
+        }
+        let entry = PackEntry {
+            pack: self.pack_path(active.index),
+            offset: active.size,
+            length,
+        };
+        let pack = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(entry.pack.as_path())
+            .map_err(|err| {
+                error!("{:?}", err);
+                RustusError::UnableToWrite(err.to_string())
+            })?;
+        if entry.offset == 0 {
+            self.files
+                .permissions()
+                .apply_to_file(entry.pack.as_path())?;
+        }
+        // Reserved space is allocated right away, so
+        // the size of the pack is known after restarts.
+        pack.set_len(entry.offset + length)?;
+        active.size += length;
+        Ok(entry)
+    }
+
+    /// Mark space of the entry as free.
+    ///
+    /// Pack is removed if it has no uploads left.
+    fn release(&self, entry: &PackEntry) -> RustusResult<()> {
+        let active = self
+            .active
+            .lock()
+            .map_err(|err| RustusError::UnableToRemove(err.to_string()))?;
+        if !entry.pack.exists() {
+            return Err(RustusError::FileNotFound);
+        }
+        let free_path = entry.pack.with_extension(FREE_EXTENSION);
+        let mut free_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(free_path.as_path())?;
+        writeln!(free_file, "{} {}", entry.offset, entry.length)?;
+        if self.force_fsync {
+            free_file.sync_data()?;
+        }
+        if entry.pack == self.pack_path(active.index) {
+            return Ok(());
+        }
+        if freed_space(entry.pack.as_path())? >= entry.pack.metadata()?.len() {
+            remove_file(entry.pack.as_path())?;
+            remove_file(free_path)?;
+        }
+        Ok(())
+    }
+
+    /// Copy uploads of fragmented packs to the active pack.
+    ///
+    /// Packs without uploads are removed right away.
+    fn compact_packs(&self, uploads: &[FileInfo], threshold: f64) -> RustusResult<Compaction> {
+        let mut packed: HashMap<PathBuf, Vec<(&FileInfo, PackEntry)>> = HashMap::new();
+        for file_info in uploads {
+            if let Some(entry) = Self::entry(file_info)? {
+                packed
+                    .entry(entry.pack.clone())
+                    .or_default()
+                    .push((file_info, entry));
+            }
+        }
+        let active_pack = {
+            let active = self
+                .active
+                .lock()
+                .map_err(|err| RustusError::UnableToWrite(err.to_string()))?;
+            self.pack_path(active.index)
+        };
+        let mut compaction = Compaction::default();
+        let mut targets = Vec::new();
+        for dir_entry in std::fs::read_dir(self.packs_dir.as_path())? {
+            let pack = dir_entry?.path();
+            if pack.extension().is_none_or(|ext| ext != PACK_EXTENSION) || pack == active_pack {
+                continue;
+            }
+            let size = pack.metadata()?.len();
+            let freed = freed_space(pack.as_path())?.min(size);
+            let entries = packed.remove(&pack).unwrap_or_default();
+            let given: u64 = entries.iter().map(|(_, entry)| entry.length).sum();
+            // Other uploads are still written or were skipped,
+            // so they can't be moved.
+            #[allow(clippy::cast_precision_loss)]
+            if size == 0 || given < size - freed || (freed as f64) < threshold * size as f64 {
+                continue;
+            }
+            if entries.is_empty() {
+                remove_file(pack.as_path())?;
+                remove_file(pack.with_extension(FREE_EXTENSION)).ok();
+                compaction.reclaimed += size;
+                continue;
+            }
+            for (file_info, entry) in entries {
+                let contents = entry.read(file_info.offset)?;
+                let target = self.reserve(entry.length)?;
+                let mut target_pack = OpenOptions::new().write(true).open(target.pack.as_path())?;
+                target_pack.seek(SeekFrom::Start(target.offset))?;
+                target_pack.write_all(contents.as_slice())?;
+                if !targets.contains(&target.pack) {
+                    targets.push(target.pack.clone());
+                }
+                compaction.moved.push((file_info.clone(), target.to_path()));
+            }
+            compaction.reclaimed += freed;
+        }
+        // Copies must be durable before
+        // uploads are pointed to them.
+        for target in targets {
+            sync_file(target.as_path())?;
+        }
+        Ok(compaction)
+    }
+
+    /// Read stored bytes of the upload.
+    fn read_upload(file_info: &FileInfo) -> RustusResult<Vec<u8>> {
+        if let Some(entry) = Self::entry(file_info)? {
+            entry.read(file_info.offset)
+        } else {
+            let mut contents = Vec::new();
+            OpenOptions::new()
+                .read(true)
+                .open(file_info.path.as_ref().unwrap())?
+                .read_to_end(&mut contents)?;
+            Ok(contents)
+        }
+    }
+}
+
+/// Number of removed bytes of the pack.
+fn freed_space(pack: &Path) -> RustusResult<u64> {
+    let free_path = pack.with_extension(FREE_EXTENSION);
+    let free_file = match OpenOptions::new().read(true).open(free_path.as_path()) {
+        Ok(free_file) => free_file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+    let mut freed = 0;
+    for line in BufReader::new(free_file).lines() {
+        freed += line?
+            .split_once(' ')
+            .and_then(|(_, length)| length.parse::<u64>().ok())
+            .unwrap_or_default();
+    }
+    Ok(freed)
+}
+
+#[async_trait(?Send)]
+impl Storage for PackedStorage {
+    async fn prepare(&mut self) -> RustusResult<()> {
+        self.files.prepare().await?;
+        std::fs::create_dir_all(self.packs_dir.as_path())
+            .map_err(|err| RustusError::UnableToPrepareStorage(err.to_string()))?;
+        // Paths of uploads are absolute, as in file storage.
+        self.packs_dir = self.packs_dir.canonicalize()?;
+        // New uploads are added to the last pack.
+        let mut last = None;
+        for entry in std::fs::read_dir(self.packs_dir.as_path())? {
+            let path = entry?.path();
+            let index = path
+                .file_stem()
+                .and_then(|stem| stem.to_str()?.strip_prefix("pack-")?.parse::<u64>().ok())
+                .filter(|_| path.extension().is_some_and(|ext| ext == PACK_EXTENSION));
+            if let Some(index) = index {
+                if last.is_none_or(|(last_index, _)| index > last_index) {
+                    last = Some((index, path.metadata()?.len()));
+                }
+            }
+        }
+        if let Some((index, size)) = last {
+            let mut active = self
+                .active
+                .lock()
+                .map_err(|err| RustusError::UnableToPrepareStorage(err.to_string()))?;
+            active.index = index;
+            active.size = size;
+        }
+        Ok(())
+    }
+
+    async fn get_contents(
+        &self,
+        file_info: &FileInfo,
+        request: &HttpRequest,
+    ) -> RustusResult<HttpResponse> {
+        let Some(entry) = Self::entry(file_info)? else {
+            return self.files.get_contents(file_info, request).await;
+        };
+        let length = file_info.offset;
+        let contents = tokio::task::spawn_blocking(move || entry.read(length)).await??;
+        Ok(HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(contents))
+    }
+
+    async fn read_data(
+        &self,
+        file_info: &FileInfo,
+        range: Range<usize>,
+    ) -> RustusResult<DataStream> {
+        let Some(entry) = Self::entry(file_info)? else {
+            return self.files.read_data(file_info, range).await;
+        };
+        // Reserved space after the upload may belong to other uploads.
+        let end = (range.end as u64).min(entry.length);
+        if let Some(chunk_index) = &self.chunk_index {
+            let end = usize::try_from(end).unwrap_or(usize::MAX);
+            if range.start < end {
+                let chunks = chunk_index
+                    .lookup(file_info.id.as_str(), range.start, end)
+                    .await?;
+                if let Some(ranges) = Self::indexed_ranges(&entry, &chunks, range.start, end) {
+                    let mut streams = Vec::with_capacity(ranges.len());
+                    for range in ranges {
+                        streams.push(read_file(entry.pack.as_path(), range).await?);
+                    }
+                    return Ok(stream::iter(streams).flatten().boxed_local());
+                }
+            }
+        }
+        read_file(
+            entry.pack.as_path(),
+            entry.offset + range.start as u64..entry.offset + end,
+        )
+        .await
+    }
+
+    async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+        let Some(entry) = Self::entry(file_info)? else {
+            return self.files.add_bytes(file_info, bytes).await;
+        };
+        let start = file_info.offset as u64;
+        if start + bytes.len() as u64 > entry.length {
+            return Err(RustusError::UnableToWrite(format!(
+                "Upload {} exceeds reserved space.",
+                file_info.id
+            )));
+        }
+        let force_fsync = self.force_fsync;
+        let chunk = IndexedChunk {
+            start: file_info.offset,
+            end: file_info.offset + bytes.len(),
+            location: chunk_location(entry.pack.as_path(), entry.offset + start),
+        };
+        tokio::task::spawn_blocking(move || {
+            let mut pack = OpenOptions::new()
+                .write(true)
+                .open(entry.pack.as_path())
+                .map_err(|err| {
+                    error!("{:?}", err);
+                    RustusError::UnableToWrite(err.to_string())
+                })?;
+            pack.seek(SeekFrom::Start(entry.offset + start))?;
+            pack.write_all(bytes.as_ref())?;
+            if force_fsync {
+                pack.sync_data()?;
+            }
+            Ok::<_, RustusError>(())
+        })
+        .await??;
+        match &self.chunk_index {
+            Some(chunk_index) if chunk.start < chunk.end => {
+                chunk_index.insert(file_info.id.as_str(), chunk).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn truncate(&self, file_info: &FileInfo) -> RustusResult<()> {
+        // Bytes after the offset are never read
+        // and get overwritten by the next write.
+        if Self::entry(file_info)?.is_some() {
+            if let Some(chunk_index) = &self.chunk_index {
+                chunk_index
+                    .remove(file_info.id.as_str(), file_info.offset, usize::MAX)
+                    .await?;
+            }
+            return Ok(());
+        }
+        self.files.truncate(file_info).await
+    }
+
+    async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
+        if !self.is_packed(file_info) {
+            return self.files.create_file(file_info).await;
+        }
+        let storage = self.clone();
+        let length = file_info.length.unwrap_or_default() as u64;
+        tokio::task::spawn_blocking(move || Ok(storage.reserve(length)?.to_path())).await?
+    }
+
+    async fn concat_files(
+        &self,
+        file_info: &FileInfo,
+        parts_info: Vec<FileInfo>,
+    ) -> RustusResult<()> {
+        // Final uploads are never packed.
+        let Some(path) = file_info.path.clone() else {
+            return Err(RustusError::FileNotFound);
+        };
+        let force_fsync = self.force_fsync;
+        tokio::task::spawn_blocking(move || {
+            let mut file = OpenOptions::new().append(true).open(path).map_err(|err| {
+                error!("{:?}", err);
+                RustusError::UnableToWrite(err.to_string())
+            })?;
+            for part in parts_info {
+                file.write_all(Self::read_upload(&part)?.as_slice())?;
+            }
+            if force_fsync {
+                file.sync_data()?;
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+        let Some(entry) = Self::entry(file_info)? else {
+            return self.files.remove_file(file_info).await;
+        };
+        let storage = self.clone();
+        let file_id = file_info.id.clone();
+        tokio::task::spawn_blocking(move || {
+            storage.release(&entry).map_err(|err| {
+                warn!("Cannot release space of {}: {}", file_id, err);
+                match err {
+                    RustusError::FileNotFound => err,
+                    _ => RustusError::UnableToRemove(file_id.clone()),
+                }
+            })
+        })
+        .await??;
+        match &self.chunk_index {
+            Some(chunk_index) => {
+                chunk_index
+                    .remove(file_info.id.as_str(), 0, usize::MAX)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    async fn sync_data(&self, file_info: &FileInfo) -> RustusResult<()> {
+        let Some(entry) = Self::entry(file_info)? else {
+            return self.files.sync_data(file_info).await;
+        };
+        tokio::task::spawn_blocking(move || sync_file(entry.pack.as_path())).await??;
+        Ok(())
+    }
+
+    async fn data_exists(&self, file_info: &FileInfo) -> RustusResult<bool> {
+        let Some(entry) = Self::entry(file_info)? else {
+            return self.files.data_exists(file_info).await;
+        };
+        match tokio::fs::metadata(entry.pack.as_path()).await {
+            Ok(meta) => Ok(meta.len() >= entry.offset + entry.length),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn stored_size(&self, file_info: &FileInfo) -> RustusResult<Option<usize>> {
+        let Some(entry) = Self::entry(file_info)? else {
+            return self.files.stored_size(file_info).await;
+        };
+        let meta = tokio::fs::metadata(entry.pack.as_path()).await?;
+        // Data of the upload may be cut off at the end of the pack.
+        let stored = meta.len().saturating_sub(entry.offset).min(entry.length);
+        Ok(usize::try_from(stored).ok())
+    }
+
+    async fn compact(&self, uploads: &[FileInfo], threshold: f64) -> RustusResult<Compaction> {
+        let storage = self.clone();
+        let uploads = uploads.to_vec();
+        let compaction = tokio::task::spawn_blocking(move || {
+            storage.compact_packs(uploads.as_slice(), threshold)
+        })
+        .await??;
+        // Moved uploads are read from copies once they point to them.
+        if let Some(chunk_index) = &self.chunk_index {
+            for (file_info, path) in &compaction.moved {
+                let Some(target) = PackEntry::parse(path.as_str()) else {
+                    continue;
+                };
+                chunk_index
+                    .remove(file_info.id.as_str(), 0, usize::MAX)
+                    .await?;
+                if file_info.offset > 0 {
+                    let chunk = IndexedChunk {
+                        start: 0,
+                        end: file_info.offset,
+                        location: chunk_location(target.pack.as_path(), target.offset),
+                    };
+                    chunk_index.insert(file_info.id.as_str(), chunk).await?;
+                }
+            }
+        }
+        Ok(compaction)
+    }
+
+    async fn list_paths(&self) -> RustusResult<Vec<String>> {
+        // Packs have no paths of uploads in them,
+        // so only separate files are listed.
+        let packs_dir = self.packs_dir.canonicalize()?;
+        Ok(self
+            .files
+            .list_paths()
+            .await?
+            .into_iter()
+            .filter(|path| !Path::new(path).starts_with(packs_dir.as_path()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chunk_location, PackEntry, PackedStorage};
+    use crate::{
+        info_storages::{file_info_storage::FileInfoStorage, FileInfo, InfoStorage},
+        storages::{
+            chunk_index::{ChunkIndex, IndexedChunk, InfoChunkIndex},
+            file_storage::FileStorage,
+        },
+        Storage,
+    };
+    use actix_web::test::TestRequest;
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use std::path::{Path, PathBuf};
+
+    async fn packed_storage(dir: &Path, pack_size: u64) -> PackedStorage {
+        let files = FileStorage::new(dir.to_path_buf(), String::new(), false);
+        let mut storage = PackedStorage::new(files, dir, pack_size, 10, false);
+        storage.prepare().await.unwrap();
+        storage
+    }
+
+    async fn create_upload(storage: &PackedStorage, length: Option<usize>) -> FileInfo {
+        let mut file_info = FileInfo::new(
+            uuid::Uuid::new_v4().to_string().as_str(),
+            length,
+            None,
+            storage.to_string(),
+            None,
+        );
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        file_info
+    }
+
+    async fn write(storage: &PackedStorage, file_info: &mut FileInfo, data: &str) {
+        storage
+            .add_bytes(file_info, Bytes::from(String::from(data)))
+            .await
+            .unwrap();
+        file_info.offset += data.len();
+    }
+
+    async fn contents(storage: &PackedStorage, file_info: &FileInfo) -> Bytes {
+        let request = TestRequest::get().to_http_request();
+        let response = storage.get_contents(file_info, &request).await.unwrap();
+        actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn entry_path() {
+        let entry = PackEntry {
+            pack: PathBuf::from("/data/packs/pack-00000001.pack"),
+            offset: 10,
+            length: 5,
+        };
+        assert_eq!(entry.to_path(), "/data/packs/pack-00000001.pack#10+5");
+        assert_eq!(PackEntry::parse(entry.to_path().as_str()), Some(entry));
+        assert!(PackEntry::parse("/data/upload").is_none());
+        assert!(PackEntry::parse("/data/#upload/file").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn uploads_share_pack() {
+        let dir = tempdir::TempDir::new("packed_storage").unwrap();
+        let storage = packed_storage(dir.path(), 100).await;
+        let mut first = create_upload(&storage, Some(5)).await;
+        let mut second = create_upload(&storage, Some(4)).await;
+        // Chunks of different uploads are interleaved.
+        write(&storage, &mut first, "mem").await;
+        write(&storage, &mut second, "te").await;
+        write(&storage, &mut first, "es").await;
+        write(&storage, &mut second, "st").await;
+        let first_entry = PackEntry::parse(first.path.as_deref().unwrap()).unwrap();
+        let second_entry = PackEntry::parse(second.path.as_deref().unwrap()).unwrap();
+        assert_eq!(first_entry.pack, second_entry.pack);
+        assert_eq!(second_entry.offset, 5);
+        assert_eq!(contents(&storage, &first).await, "memes");
+        assert_eq!(contents(&storage, &second).await, "test");
+        assert_eq!(
+            std::fs::read_to_string(first_entry.pack).unwrap(),
+            "memestest"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn read_data() {
+        let dir = tempdir::TempDir::new("packed_storage").unwrap();
+        let storage = packed_storage(dir.path(), 100).await;
+        let mut first = create_upload(&storage, Some(5)).await;
+        let mut second = create_upload(&storage, Some(4)).await;
+        write(&storage, &mut first, "memes").await;
+        write(&storage, &mut second, "test").await;
+        let read = |range| storage.read_data(&first, range);
+        let chunks = read(1..4).await.unwrap().collect::<Vec<_>>().await;
+        let data = chunks.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(data.concat(), b"eme");
+        // Bytes of the next upload are never read.
+        let chunks = read(3..10).await.unwrap().collect::<Vec<_>>().await;
+        let data = chunks.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(data.concat(), b"es");
+    }
+
+    #[actix_rt::test]
+    async fn chunk_index() {
+        let dir = tempdir::TempDir::new("packed_storage").unwrap();
+        let mut info_storage = FileInfoStorage::new(dir.path().join("info"));
+        info_storage.prepare().await.unwrap();
+        let index = InfoChunkIndex::new(Box::new(info_storage));
+        let storage = packed_storage(dir.path(), 100)
+            .await
+            .with_chunk_index(Box::new(index.clone()));
+        let mut first = create_upload(&storage, Some(5)).await;
+        let mut second = create_upload(&storage, Some(4)).await;
+        write(&storage, &mut first, "mem").await;
+        write(&storage, &mut first, "es").await;
+        write(&storage, &mut second, "test").await;
+        let indexed = index.lookup(first.id.as_str(), 0, 5).await.unwrap();
+        assert_eq!(indexed.len(), 2);
+        let read = |file_info: FileInfo, range| {
+            let storage = storage.clone();
+            async move {
+                let chunks = storage
+                    .read_data(&file_info, range)
+                    .await
+                    .unwrap()
+                    .collect::<Vec<_>>()
+                    .await;
+                chunks
+                    .into_iter()
+                    .map(Result::unwrap)
+                    .collect::<Vec<_>>()
+                    .concat()
+            }
+        };
+        assert_eq!(read(first.clone(), 1..4).await, b"eme");
+
+        // Bytes are read from indexed locations.
+        let entry = PackEntry::parse(second.path.as_deref().unwrap()).unwrap();
+        let moved = IndexedChunk {
+            start: 3,
+            end: 5,
+            location: chunk_location(entry.pack.as_path(), entry.offset),
+        };
+        index.insert(first.id.as_str(), moved).await.unwrap();
+        assert_eq!(read(first.clone(), 1..5).await, b"emte");
+        // Locations in other packs are stale, so the entry is read.
+        let stale = IndexedChunk {
+            start: 3,
+            end: 5,
+            location: String::from("/other.pack#0"),
+        };
+        index.insert(first.id.as_str(), stale).await.unwrap();
+        assert_eq!(read(first.clone(), 1..5).await, b"emes");
+
+        first.offset = 3;
+        storage.truncate(&first).await.unwrap();
+        assert_eq!(
+            index.lookup(first.id.as_str(), 0, 5).await.unwrap().len(),
+            1
+        );
+        storage.remove_file(&second).await.unwrap();
+        assert!(index
+            .lookup(second.id.as_str(), 0, 4)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn reserved_space_exceeded() {
+        let dir = tempdir::TempDir::new("packed_storage").unwrap();
+        let storage = packed_storage(dir.path(), 100).await;
+        let file_info = create_upload(&storage, Some(3)).await;
+        let result = storage.add_bytes(&file_info, Bytes::from("memes")).await;
+        assert!(result.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn large_uploads_are_separate() {
+        let dir = tempdir::TempDir::new("packed_storage").unwrap();
+        let storage = packed_storage(dir.path(), 100).await;
+        for length in [None, Some(11)] {
+            let mut file_info = create_upload(&storage, length).await;
+            assert!(PackEntry::parse(file_info.path.as_deref().unwrap()).is_none());
+            write(&storage, &mut file_info, "memes").await;
+            assert_eq!(contents(&storage, &file_info).await, "memes");
+            storage.remove_file(&file_info).await.unwrap();
+            assert!(!Path::new(file_info.path.as_deref().unwrap()).exists());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn packs_are_rotated_and_removed() {
+        let dir = tempdir::TempDir::new("packed_storage").unwrap();
+        let storage = packed_storage(dir.path(), 8).await;
+        let first = create_upload(&storage, Some(5)).await;
+        let second = create_upload(&storage, Some(5)).await;
+        let first_entry = PackEntry::parse(first.path.as_deref().unwrap()).unwrap();
+        let second_entry = PackEntry::parse(second.path.as_deref().unwrap()).unwrap();
+        assert_ne!(first_entry.pack, second_entry.pack);
+        assert_eq!(second_entry.offset, 0);
+        // Active pack is kept even if it's empty.
+        storage.remove_file(&second).await.unwrap();
+        assert!(second_entry.pack.exists());
+        storage.remove_file(&first).await.unwrap();
+        assert!(!first_entry.pack.exists());
+        assert!(!first_entry.pack.with_extension("free").exists());
+    }
+
+    #[actix_rt::test]
+    async fn active_pack_after_restart() {
+        let dir = tempdir::TempDir::new("packed_storage").unwrap();
+        let storage = packed_storage(dir.path(), 100).await;
+        create_upload(&storage, Some(5)).await;
+        let storage = packed_storage(dir.path(), 100).await;
+        let file_info = create_upload(&storage, Some(5)).await;
+        let entry = PackEntry::parse(file_info.path.as_deref().unwrap()).unwrap();
+        assert_eq!(entry.offset, 5);
+    }
+
+    #[actix_rt::test]
+    async fn concatenation() {
+        let dir = tempdir::TempDir::new("packed_storage").unwrap();
+        let storage = packed_storage(dir.path(), 100).await;
+        let mut first = create_upload(&storage, Some(5)).await;
+        let mut second = create_upload(&storage, Some(11)).await;
+        write(&storage, &mut first, "memes").await;
+        write(&storage, &mut second, " and more").await;
+        let mut final_info = FileInfo::new("final", None, None, storage.to_string(), None);
+        final_info.is_final = true;
+        final_info.path = Some(storage.create_file(&final_info).await.unwrap());
+        storage
+            .concat_files(&final_info, vec![first, second])
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(final_info.path.unwrap()).unwrap(),
+            "memes and more"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn missing_pack() {
+        let dir = tempdir::TempDir::new("packed_storage").unwrap();
+        let storage = packed_storage(dir.path(), 100).await;
+        let file_info = create_upload(&storage, Some(5)).await;
+        assert!(storage.data_exists(&file_info).await.unwrap());
+        let entry = PackEntry::parse(file_info.path.as_deref().unwrap()).unwrap();
+        std::fs::remove_file(entry.pack).unwrap();
+        assert!(!storage.data_exists(&file_info).await.unwrap());
+        assert!(storage.list_paths().await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn empty_packs_are_compacted() {
+        let dir = tempdir::TempDir::new("packed_storage").unwrap();
+        let storage = packed_storage(dir.path(), 8).await;
+        let first = create_upload(&storage, Some(5)).await;
+        // Pack is kept, since it's still active.
+        storage.remove_file(&first).await.unwrap();
+        let second = create_upload(&storage, Some(5)).await;
+        let first_entry = PackEntry::parse(first.path.as_deref().unwrap()).unwrap();
+        assert!(first_entry.pack.exists());
+        let compaction = storage.compact(&[], 0.5).await.unwrap();
+        assert!(compaction.moved.is_empty());
+        assert_eq!(compaction.reclaimed, 5);
+        assert!(!first_entry.pack.exists());
+        assert!(storage.data_exists(&second).await.unwrap());
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use crate::{config::RustusConf, storages::Storage};
+
+/// Function that creates custom storage from configuration.
+pub type StorageFactory = Arc<dyn Fn(&RustusConf) -> Box<dyn Storage + Send + Sync> + Send + Sync>;
+
+fn registry() -> &'static RwLock<HashMap<String, StorageFactory>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, StorageFactory>>> = OnceLock::new();
+    REGISTRY.get_or_init(RwLock::default)
+}
+
+/// Register custom storage.
+///
+/// Registered storage can be selected with `--storage` by its name.
+/// Storages must be registered before configuration is parsed,
+/// E.G. before calling `rustus::run`.
+///
+/// Built-in storages can't be overridden.
+/// If storage with the same name was already registered, it's replaced.
+///
+/// # Params
+/// `name` - name of the storage.
+/// `factory` - function that creates storage from configuration.
+pub fn register_storage<F>(name: &str, factory: F)
+where
+    F: Fn(&RustusConf) -> Box<dyn Storage + Send + Sync> + Send + Sync + 'static,
+{
+    registry()
+        .write()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .insert(String::from(name), Arc::new(factory));
+}
+
+/// Find factory of a registered storage.
+pub fn get_factory(name: &str) -> Option<StorageFactory> {
+    registry()
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .get(name)
+        .cloned()
+}
+
+/// Names of all registered storages.
+pub fn registered_names() -> Vec<String> {
+    let mut names = registry()
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::register_storage;
+    use crate::{
+        config::RustusConf,
+        storages::{file_storage::FileStorage, AvailableStores},
+    };
+    use std::str::FromStr;
+
+    #[test]
+    fn custom_storage() {
+        register_storage("test-custom-storage", |config| {
+            Box::new(FileStorage::new(
+                config.storage_opts.data_dir.clone(),
+                String::new(),
+                false,
+            ))
+        });
+        let store = AvailableStores::from_str("test-custom-storage").unwrap();
+        assert_eq!(
+            store,
+            AvailableStores::Custom(String::from("test-custom-storage"))
+        );
+        let config = RustusConf::from_iter(["rustus", "--storage", "test-custom-storage"]);
+        assert_eq!(config.storage_opts.storage, store);
+        assert_eq!(store.get(&config, None).to_string(), "file_storage");
+    }
+
+    #[test]
+    fn unknown_storage() {
+        let err = AvailableStores::from_str("unknown-storage").unwrap_err();
+        assert!(err.contains("file-storage"));
+        assert!(err.contains("hybrid-s3"));
+        let config = <RustusConf as clap::Parser>::try_parse_from([
+            "rustus",
+            "--storage",
+            "unknown-storage",
+        ]);
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn builtin_storage() {
+        register_storage("file-storage", |_| unreachable!());
+        assert_eq!(
+            AvailableStores::from_str("file-storage").unwrap(),
+            AvailableStores::FileStorage
+        );
+    }
+}
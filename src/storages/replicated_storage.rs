@@ -0,0 +1,815 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    time::Duration,
+};
+
+use actix_web::{HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use derive_more::Display;
+use futures::StreamExt;
+use log::{debug, error, warn};
+use tokio::sync::mpsc::{self, error::SendTimeoutError};
+
+use crate::{
+    errors::RustusResult,
+    info_storages::FileInfo,
+    storages::{
+        file_storage::FileStorage, models::storage::IMPORT_CHUNK_SIZE, Compaction, DataLocation,
+        DataStream, Storage,
+    },
+    utils::import::ImportSource,
+};
+
+/// Job for the replication worker.
+enum ReplicationTask {
+    Create(FileInfo),
+    Write {
+        file_info: FileInfo,
+        offset: usize,
+        bytes: Bytes,
+    },
+    /// Cut the replica at the offset of the upload.
+    Truncate(FileInfo),
+    /// Upload is finished, so its replica isn't tracked anymore.
+    Finish(FileInfo),
+    /// Data of the upload was copied to another path of the primary storage.
+    Move {
+        upload_id: String,
+        from: Option<String>,
+        to: String,
+    },
+    Remove(FileInfo),
+}
+
+impl ReplicationTask {
+    fn upload_id(&self) -> &str {
+        match self {
+            Self::Create(file_info)
+            | Self::Truncate(file_info)
+            | Self::Finish(file_info)
+            | Self::Remove(file_info)
+            | Self::Write { file_info, .. } => file_info.id.as_str(),
+            Self::Move { upload_id, .. } => upload_id.as_str(),
+        }
+    }
+}
+
+/// Storage wrapper that mirrors uploads to a secondary storage.
+///
+/// Every operation is applied to the primary storage first.
+/// After it succeeds, the same operation is pushed to a bounded
+/// queue and a background worker applies it to the secondary storage.
+///
+/// Data written without `add_bytes`, like imported and copied
+/// uploads, is read back from the primary storage and queued in chunks.
+///
+/// Errors of the secondary storage are logged and retried,
+/// but they never fail requests. If chunks of a replica are lost,
+/// the whole upload is copied from the primary storage again.
+#[derive(Display, Clone)]
+#[display(fmt = "{primary}")]
+pub struct ReplicatedStorage {
+    primary: Box<dyn Storage + Send + Sync>,
+    sender: mpsc::Sender<ReplicationTask>,
+    enqueue_timeout: Duration,
+}
+
+impl ReplicatedStorage {
+    /// Create new replicated storage.
+    ///
+    /// This function spawns a dedicated thread for the replication worker.
+    /// Secondary storage must be prepared before calling this function.
+    ///
+    /// # Params
+    /// `primary` - storage that serves all requests.
+    /// `secondary` - storage that receives copies of uploads.
+    /// `queue_size` - maximum number of pending replication tasks.
+    /// `retries` - number of retries for every failed task.
+    /// `enqueue_timeout` - how long to wait for a free slot in the queue.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the worker thread can't be started.
+    pub fn new(
+        primary: Box<dyn Storage + Send + Sync>,
+        secondary: FileStorage,
+        queue_size: usize,
+        retries: usize,
+        enqueue_timeout: Duration,
+    ) -> RustusResult<Self> {
+        let (sender, receiver) = mpsc::channel(queue_size.max(1));
+        let worker = ReplicationWorker {
+            primary: dyn_clone::clone_box(&*primary),
+            secondary,
+            retries,
+            replicas: HashMap::new(),
+            moved: HashMap::new(),
+        };
+        // Storages don't produce `Send` futures,
+        // that's why the worker has its own single-threaded runtime.
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        std::thread::Builder::new()
+            .name(String::from("rustus-replication"))
+            .spawn(move || {
+                let local = tokio::task::LocalSet::new();
+                local.block_on(&runtime, worker.run(receiver));
+            })?;
+        Ok(Self {
+            primary,
+            sender,
+            enqueue_timeout,
+        })
+    }
+
+    /// Push task to the replication queue.
+    ///
+    /// If the queue is full, this function waits
+    /// until some space is available. If no space was freed
+    /// during `enqueue_timeout`, the task is dropped.
+    async fn enqueue(&self, task: ReplicationTask) {
+        let upload_id = String::from(task.upload_id());
+        match self.sender.send_timeout(task, self.enqueue_timeout).await {
+            Ok(()) => {}
+            Err(SendTimeoutError::Timeout(_)) => {
+                warn!("Replication queue is full. Task for upload {upload_id} was dropped.");
+            }
+            Err(SendTimeoutError::Closed(_)) => {
+                error!("Replication worker is stopped. Task for upload {upload_id} was dropped.");
+            }
+        }
+    }
+
+    /// Queue data of the upload stored in the primary storage.
+    async fn enqueue_data(&self, file_info: &FileInfo) {
+        if let Err(err) = self.try_enqueue_data(file_info).await {
+            warn!("Cannot replicate data of upload {}: {err}", file_info.id);
+        }
+    }
+
+    async fn try_enqueue_data(&self, file_info: &FileInfo) -> RustusResult<()> {
+        let length = file_info.length.unwrap_or(file_info.offset);
+        let mut body = self.primary.read_data(file_info, 0..length).await?;
+        let mut chunk = BytesMut::new();
+        let mut offset = 0;
+        loop {
+            let next = body.next().await;
+            let finished = next.is_none();
+            if let Some(bytes) = next {
+                chunk.extend_from_slice(&bytes?);
+            }
+            if chunk.len() >= IMPORT_CHUNK_SIZE || (finished && !chunk.is_empty()) {
+                let bytes = chunk.split().freeze();
+                let chunk_len = bytes.len();
+                let mut info = file_info.clone();
+                info.offset = offset;
+                self.enqueue(ReplicationTask::Write {
+                    file_info: info,
+                    offset,
+                    bytes,
+                })
+                .await;
+                offset += chunk_len;
+            }
+            if finished {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Queue new paths of uploads moved inside the primary storage.
+    async fn enqueue_moves(&self, moved: &[(FileInfo, String)]) {
+        for (upload, path) in moved {
+            self.enqueue(ReplicationTask::Move {
+                upload_id: upload.id.clone(),
+                from: upload.path.clone(),
+                to: path.clone(),
+            })
+            .await;
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for ReplicatedStorage {
+    async fn prepare(&mut self) -> RustusResult<()> {
+        self.primary.prepare().await
+    }
+
+    async fn get_contents(
+        &self,
+        file_info: &FileInfo,
+        request: &HttpRequest,
+    ) -> RustusResult<HttpResponse> {
+        self.primary.get_contents(file_info, request).await
+    }
+
+    async fn read_data(
+        &self,
+        file_info: &FileInfo,
+        range: Range<usize>,
+    ) -> RustusResult<DataStream> {
+        self.primary.read_data(file_info, range).await
+    }
+
+    async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+        // Cloning bytes is cheap, since it only increments reference counter.
+        self.primary.add_bytes(file_info, bytes.clone()).await?;
+        self.enqueue(ReplicationTask::Write {
+            file_info: file_info.clone(),
+            offset: file_info.offset,
+            bytes,
+        })
+        .await;
+        Ok(())
+    }
+
+    fn accepts_out_of_order(&self) -> bool {
+        self.primary.accepts_out_of_order()
+    }
+
+    fn chunk_alignment(&self) -> Option<usize> {
+        self.primary.chunk_alignment()
+    }
+
+    fn preferred_chunk_size(&self) -> Option<usize> {
+        self.primary.preferred_chunk_size()
+    }
+
+    fn data_location(&self, file_info: &FileInfo) -> Option<DataLocation> {
+        // Replicas are owned by the worker.
+        self.primary.data_location(file_info)
+    }
+
+    async fn truncate(&self, file_info: &FileInfo) -> RustusResult<()> {
+        self.primary.truncate(file_info).await?;
+        // Callers hold the upload guard, so the task is queued
+        // before chunks written from the new offset.
+        self.enqueue(ReplicationTask::Truncate(file_info.clone()))
+            .await;
+        Ok(())
+    }
+
+    async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
+        let path = self.primary.create_file(file_info).await?;
+        let mut created = file_info.clone();
+        created.path = Some(path.clone());
+        self.enqueue(ReplicationTask::Create(created)).await;
+        Ok(path)
+    }
+
+    async fn create_upload(&self, file_info: &mut FileInfo) -> RustusResult<()> {
+        self.primary.create_upload(file_info).await?;
+        self.enqueue(ReplicationTask::Create(file_info.clone()))
+            .await;
+        Ok(())
+    }
+
+    async fn concat_files(
+        &self,
+        file_info: &FileInfo,
+        parts_info: Vec<FileInfo>,
+    ) -> RustusResult<()> {
+        let length = parts_info.iter().filter_map(|part| part.length).sum();
+        self.primary.concat_files(file_info, parts_info).await?;
+        let mut final_info = file_info.clone();
+        final_info.length = Some(length);
+        self.enqueue_data(&final_info).await;
+        Ok(())
+    }
+
+    async fn import_file(
+        &self,
+        file_info: &FileInfo,
+        source: &ImportSource,
+        hard_link: bool,
+    ) -> RustusResult<()> {
+        self.primary
+            .import_file(file_info, source, hard_link)
+            .await?;
+        self.enqueue_data(file_info).await;
+        Ok(())
+    }
+
+    async fn copy_file(&self, source: &FileInfo, target: &FileInfo) -> RustusResult<()> {
+        self.primary.copy_file(source, target).await?;
+        self.enqueue_data(target).await;
+        Ok(())
+    }
+
+    async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+        self.primary.remove_file(file_info).await?;
+        self.enqueue(ReplicationTask::Remove(file_info.clone()))
+            .await;
+        Ok(())
+    }
+
+    async fn sync_data(&self, file_info: &FileInfo) -> RustusResult<()> {
+        self.primary.sync_data(file_info).await?;
+        // Data of finished uploads is synced,
+        // so it's where replicas stop being tracked.
+        self.enqueue(ReplicationTask::Finish(file_info.clone()))
+            .await;
+        Ok(())
+    }
+
+    async fn data_exists(&self, file_info: &FileInfo) -> RustusResult<bool> {
+        self.primary.data_exists(file_info).await
+    }
+
+    async fn stored_size(&self, file_info: &FileInfo) -> RustusResult<Option<usize>> {
+        self.primary.stored_size(file_info).await
+    }
+
+    async fn compact(&self, uploads: &[FileInfo], threshold: f64) -> RustusResult<Compaction> {
+        let compaction = self.primary.compact(uploads, threshold).await?;
+        self.enqueue_moves(compaction.moved.as_slice()).await;
+        Ok(compaction)
+    }
+
+    async fn reconcile(&self, uploads: &[FileInfo]) -> RustusResult<Vec<(FileInfo, String)>> {
+        let moved = self.primary.reconcile(uploads).await?;
+        self.enqueue_moves(moved.as_slice()).await;
+        Ok(moved)
+    }
+
+    async fn list_paths(&self) -> RustusResult<Vec<String>> {
+        self.primary.list_paths().await
+    }
+}
+
+/// Information about an unfinished upload in secondary storage.
+struct Replica {
+    path: String,
+    offset: usize,
+    /// Some chunks weren't written, so the upload must be copied again.
+    failed: bool,
+}
+
+/// Worker that applies replication tasks to the secondary storage.
+///
+/// Only unfinished uploads are tracked. Paths of finished replicas
+/// are computed by the secondary storage when they're needed.
+struct ReplicationWorker {
+    /// Storage from which lost chunks are copied.
+    primary: Box<dyn Storage + Send + Sync>,
+    secondary: FileStorage,
+    retries: usize,
+    replicas: HashMap<String, Replica>,
+    /// Paths of uploads which are being moved in the primary storage.
+    ///
+    /// Moved uploads are removed from one of their paths,
+    /// their replicas are kept.
+    moved: HashMap<String, HashSet<String>>,
+}
+
+impl ReplicationWorker {
+    async fn run(mut self, mut receiver: mpsc::Receiver<ReplicationTask>) {
+        while let Some(task) = receiver.recv().await {
+            self.process(&task).await;
+        }
+        debug!("Replication queue is closed. Stopping the worker.");
+    }
+
+    /// Apply task with retries.
+    async fn process(&mut self, task: &ReplicationTask) {
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+            }
+            match self.apply(task).await {
+                Ok(()) => return,
+                Err(err) => warn!(
+                    "Cannot replicate upload {}. Attempt: {}. Reason: {}",
+                    task.upload_id(),
+                    attempt + 1,
+                    err
+                ),
+            }
+        }
+        error!(
+            "Replication of upload {} failed after {} retries. Replica is marked as failed.",
+            task.upload_id(),
+            self.retries
+        );
+        // Next chunk or the end of the upload copies it again.
+        if let Some(replica) = self.replicas.get_mut(task.upload_id()) {
+            replica.failed = true;
+        }
+    }
+
+    /// Get the upload with the path of its replica.
+    ///
+    /// Returns `None` if the upload has no replica.
+    async fn replica_info(&self, file_info: &FileInfo) -> RustusResult<Option<FileInfo>> {
+        let mut replica_info = file_info.clone();
+        if let Some(replica) = self.replicas.get(file_info.id.as_str()) {
+            replica_info.path = Some(replica.path.clone());
+            replica_info.offset = replica.offset;
+            return Ok(Some(replica_info));
+        }
+        let path = self.secondary.file_path(file_info)?;
+        if !tokio::fs::try_exists(path.as_path()).await? {
+            return Ok(None);
+        }
+        replica_info.path = Some(path.display().to_string());
+        Ok(Some(replica_info))
+    }
+
+    /// Copy the upload from the primary storage again.
+    ///
+    /// It's done if chunks of the replica were lost,
+    /// otherwise all later chunks would be skipped.
+    /// Replica is marked as failed until all bytes
+    /// before `end` are copied.
+    async fn recopy(&mut self, file_info: &FileInfo, end: usize) -> RustusResult<()> {
+        warn!(
+            "Replica of upload {} is incomplete. Copying the whole upload.",
+            file_info.id
+        );
+        let mut replica_info = if let Some(replica_info) = self.replica_info(file_info).await? {
+            replica_info
+        } else {
+            let mut replica_info = file_info.clone();
+            replica_info.path = Some(self.secondary.create_file(file_info).await?);
+            replica_info
+        };
+        let path = replica_info.path.clone().unwrap_or_default();
+        self.replicas.insert(
+            file_info.id.clone(),
+            Replica {
+                path,
+                offset: 0,
+                failed: true,
+            },
+        );
+        replica_info.offset = 0;
+        self.secondary.truncate(&replica_info).await?;
+        let mut body = self.primary.read_data(file_info, 0..end).await?;
+        while let Some(bytes) = body.next().await {
+            let bytes = bytes?;
+            self.secondary
+                .add_bytes(&replica_info, bytes.clone())
+                .await?;
+            replica_info.offset += bytes.len();
+        }
+        if file_info.length == Some(replica_info.offset) {
+            self.replicas.remove(file_info.id.as_str());
+        } else if let Some(replica) = self.replicas.get_mut(file_info.id.as_str()) {
+            replica.offset = replica_info.offset;
+            replica.failed = false;
+        }
+        Ok(())
+    }
+
+    /// Write the chunk at the end of the replica.
+    async fn write(
+        &mut self,
+        file_info: &FileInfo,
+        offset: usize,
+        bytes: &Bytes,
+    ) -> RustusResult<()> {
+        let end = offset + bytes.len();
+        // Replica wasn't created, chunks were lost
+        // or the worker was restarted.
+        let Some(replica) = self.replicas.get_mut(file_info.id.as_str()) else {
+            return self.recopy(file_info, end).await;
+        };
+        if replica.failed || offset > replica.offset {
+            return self.recopy(file_info, end).await;
+        }
+        // Chunk was already written during previous attempt.
+        if offset < replica.offset {
+            return Ok(());
+        }
+        let mut replica_info = file_info.clone();
+        replica_info.path = Some(replica.path.clone());
+        self.secondary
+            .add_bytes(&replica_info, bytes.clone())
+            .await?;
+        replica.offset += bytes.len();
+        // Finished replicas aren't written anymore.
+        if file_info.length == Some(replica.offset) {
+            self.replicas.remove(file_info.id.as_str());
+        }
+        Ok(())
+    }
+
+    async fn apply(&mut self, task: &ReplicationTask) -> RustusResult<()> {
+        match task {
+            ReplicationTask::Create(file_info) => {
+                if self.replicas.contains_key(file_info.id.as_str()) {
+                    return Ok(());
+                }
+                let path = self.secondary.create_file(file_info).await?;
+                if file_info.length != Some(0) {
+                    self.replicas.insert(
+                        file_info.id.clone(),
+                        Replica {
+                            path,
+                            offset: 0,
+                            failed: false,
+                        },
+                    );
+                }
+            }
+            ReplicationTask::Write {
+                file_info,
+                offset,
+                bytes,
+            } => self.write(file_info, *offset, bytes).await?,
+            ReplicationTask::Truncate(file_info) => {
+                let Some(mut replica_info) = self.replica_info(file_info).await? else {
+                    return Ok(());
+                };
+                let path = replica_info.path.clone().unwrap_or_default();
+                let size = usize::try_from(tokio::fs::metadata(path.as_str()).await?.len())
+                    .unwrap_or(usize::MAX);
+                if file_info.offset < size {
+                    replica_info.offset = file_info.offset;
+                    self.secondary.truncate(&replica_info).await?;
+                }
+                // Chunks are written again from the new offset.
+                let failed = self
+                    .replicas
+                    .get(file_info.id.as_str())
+                    .is_some_and(|replica| replica.failed);
+                self.replicas.insert(
+                    file_info.id.clone(),
+                    Replica {
+                        path,
+                        offset: file_info.offset.min(size),
+                        failed,
+                    },
+                );
+            }
+            ReplicationTask::Finish(file_info) => {
+                let Some(replica) = self.replicas.get(file_info.id.as_str()) else {
+                    return Ok(());
+                };
+                // Last chunks were lost.
+                if replica.failed || replica.offset != file_info.offset {
+                    self.recopy(file_info, file_info.offset).await?;
+                }
+                self.replicas.remove(file_info.id.as_str());
+            }
+            ReplicationTask::Move {
+                upload_id,
+                from,
+                to,
+            } => {
+                let paths = self.moved.entry(upload_id.clone()).or_default();
+                paths.extend(from.iter().cloned());
+                paths.insert(to.clone());
+            }
+            ReplicationTask::Remove(file_info) => {
+                if let (Some(paths), Some(path)) =
+                    (self.moved.get_mut(file_info.id.as_str()), &file_info.path)
+                {
+                    if paths.remove(path) {
+                        if paths.len() <= 1 {
+                            self.moved.remove(file_info.id.as_str());
+                        }
+                        return Ok(());
+                    }
+                }
+                let replica_info = self.replica_info(file_info).await?;
+                self.replicas.remove(file_info.id.as_str());
+                if let Some(replica_info) = replica_info {
+                    self.secondary.remove_file(&replica_info).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReplicatedStorage, ReplicationTask};
+    use crate::{
+        info_storages::FileInfo, storages::file_storage::FileStorage, utils::import::ImportSource,
+        Storage,
+    };
+    use bytes::Bytes;
+    use std::{path::PathBuf, time::Duration};
+
+    fn get_storage(secondary_dir: PathBuf) -> ReplicatedStorage {
+        let primary_dir = tempdir::TempDir::new("primary").unwrap().into_path();
+        ReplicatedStorage::new(
+            Box::new(FileStorage::new(primary_dir, String::new(), false)),
+            FileStorage::new(secondary_dir, String::new(), false),
+            10,
+            1,
+            Duration::from_secs(1),
+        )
+        .unwrap()
+    }
+
+    /// Wait until replica has expected contents.
+    async fn wait_for_contents(path: PathBuf, expected: Option<&str>) -> bool {
+        for _ in 0..50 {
+            let contents = std::fs::read_to_string(path.as_path()).ok();
+            if contents.as_deref() == expected {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        false
+    }
+
+    #[actix_rt::test]
+    async fn success_replication() {
+        let secondary_dir = tempdir::TempDir::new("secondary").unwrap().into_path();
+        let storage = get_storage(secondary_dir.clone());
+        let mut file_info = FileInfo::new("test_id", Some(10), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        storage
+            .add_bytes(&file_info, Bytes::from("hello "))
+            .await
+            .unwrap();
+        file_info.offset += 6;
+        storage
+            .add_bytes(&file_info, Bytes::from("world"))
+            .await
+            .unwrap();
+        let replica_path = secondary_dir.join("test_id");
+        assert!(wait_for_contents(replica_path.clone(), Some("hello world")).await);
+
+        storage.remove_file(&file_info).await.unwrap();
+        assert!(wait_for_contents(replica_path, None).await);
+    }
+
+    #[actix_rt::test]
+    async fn truncated_replica() {
+        let secondary_dir = tempdir::TempDir::new("secondary").unwrap().into_path();
+        let storage = get_storage(secondary_dir.clone());
+        let mut file_info = FileInfo::new("test_id", Some(10), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        storage
+            .add_bytes(&file_info, Bytes::from("hello "))
+            .await
+            .unwrap();
+        file_info.offset = 2;
+        storage.truncate(&file_info).await.unwrap();
+        // Chunk is written again from the new offset.
+        storage
+            .add_bytes(&file_info, Bytes::from("y!"))
+            .await
+            .unwrap();
+        let replica_path = secondary_dir.join("test_id");
+        assert!(wait_for_contents(replica_path, Some("hey!")).await);
+    }
+
+    #[actix_rt::test]
+    async fn lost_chunks() {
+        let secondary_dir = tempdir::TempDir::new("secondary").unwrap().into_path();
+        let storage = get_storage(secondary_dir.clone());
+        let mut file_info = FileInfo::new("test_id", None, None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        // Chunks are written only to the primary storage,
+        // as if their tasks were dropped.
+        storage
+            .primary
+            .add_bytes(&file_info, Bytes::from("hello "))
+            .await
+            .unwrap();
+        file_info.offset = 6;
+        storage
+            .add_bytes(&file_info, Bytes::from("worl"))
+            .await
+            .unwrap();
+        let replica_path = secondary_dir.join("test_id");
+        assert!(wait_for_contents(replica_path.clone(), Some("hello worl")).await);
+        file_info.offset = 10;
+        storage
+            .primary
+            .add_bytes(&file_info, Bytes::from("d"))
+            .await
+            .unwrap();
+        // Last chunk is copied when the upload is finished.
+        file_info.offset = 11;
+        file_info.length = Some(11);
+        storage.sync_data(&file_info).await.unwrap();
+        assert!(wait_for_contents(replica_path, Some("hello world")).await);
+    }
+
+    #[actix_rt::test]
+    async fn copied_replica() {
+        let secondary_dir = tempdir::TempDir::new("secondary").unwrap().into_path();
+        let storage = get_storage(secondary_dir.clone());
+        let mut source = FileInfo::new("source", Some(5), None, storage.to_string(), None);
+        source.path = Some(storage.create_file(&source).await.unwrap());
+        storage
+            .add_bytes(&source, Bytes::from("memes"))
+            .await
+            .unwrap();
+        source.offset = 5;
+        let mut target = FileInfo::new("target", Some(5), None, storage.to_string(), None);
+        target.path = Some(storage.create_file(&target).await.unwrap());
+        storage.copy_file(&source, &target).await.unwrap();
+        assert!(wait_for_contents(secondary_dir.join("target"), Some("memes")).await);
+
+        let dir = tempdir::TempDir::new("import").unwrap();
+        std::fs::write(dir.path().join("imported"), "imported").unwrap();
+        let import = ImportSource::open(dir.path().join("imported").as_path()).unwrap();
+        let mut imported = FileInfo::new("imported", Some(8), None, storage.to_string(), None);
+        imported.path = Some(storage.create_file(&imported).await.unwrap());
+        storage
+            .import_file(&imported, &import, false)
+            .await
+            .unwrap();
+        assert!(wait_for_contents(secondary_dir.join("imported"), Some("imported")).await);
+    }
+
+    #[actix_rt::test]
+    async fn moved_upload() {
+        let secondary_dir = tempdir::TempDir::new("secondary").unwrap().into_path();
+        let storage = get_storage(secondary_dir.clone());
+        let mut file_info = FileInfo::new("test_id", Some(5), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        file_info.offset = 5;
+        let replica_path = secondary_dir.join("test_id");
+        assert!(wait_for_contents(replica_path.clone(), Some("memes")).await);
+        // Data is copied to a new path and removed from the old one,
+        // as compaction and reconciliation do.
+        let mut moved = file_info.clone();
+        moved.path = Some(String::from("/moved/test_id"));
+        storage
+            .enqueue_moves(&[(file_info.clone(), moved.path.clone().unwrap())])
+            .await;
+        storage.remove_file(&file_info).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(wait_for_contents(replica_path.clone(), Some("memes")).await);
+        storage.enqueue(ReplicationTask::Remove(moved)).await;
+        assert!(wait_for_contents(replica_path, None).await);
+    }
+
+    #[actix_rt::test]
+    async fn truncated_finished_replica() {
+        let secondary_dir = tempdir::TempDir::new("secondary").unwrap().into_path();
+        let storage = get_storage(secondary_dir.clone());
+        let mut file_info = FileInfo::new("test_id", Some(5), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        let replica_path = secondary_dir.join("test_id");
+        assert!(wait_for_contents(replica_path.clone(), Some("memes")).await);
+        // Finished replica isn't tracked, its path is computed.
+        file_info.offset = 2;
+        storage.truncate(&file_info).await.unwrap();
+        storage
+            .add_bytes(&file_info, Bytes::from("ow!"))
+            .await
+            .unwrap();
+        assert!(wait_for_contents(replica_path, Some("meow!")).await);
+    }
+
+    #[actix_rt::test]
+    async fn concatenated_replica() {
+        let secondary_dir = tempdir::TempDir::new("secondary").unwrap().into_path();
+        let storage = get_storage(secondary_dir.clone());
+        let mut parts = Vec::new();
+        for (id, data) in [("part1", "hello "), ("part2", "world")] {
+            let mut part = FileInfo::new(id, Some(data.len()), None, storage.to_string(), None);
+            part.path = Some(storage.create_file(&part).await.unwrap());
+            storage.add_bytes(&part, Bytes::from(data)).await.unwrap();
+            part.offset = data.len();
+            parts.push(part);
+        }
+        let mut final_info = FileInfo::new("final", None, None, storage.to_string(), None);
+        final_info.path = Some(storage.create_file(&final_info).await.unwrap());
+        storage.concat_files(&final_info, parts).await.unwrap();
+        let replica_path = secondary_dir.join("final");
+        assert!(wait_for_contents(replica_path.clone(), Some("hello world")).await);
+        final_info.length = Some(11);
+        final_info.offset = 11;
+        storage.remove_file(&final_info).await.unwrap();
+        assert!(wait_for_contents(replica_path, None).await);
+    }
+
+    #[actix_rt::test]
+    async fn secondary_failure() {
+        let storage = get_storage(PathBuf::from("/unknown/replica/dir"));
+        let mut file_info = FileInfo::new("test_id", Some(10), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        let res = storage.add_bytes(&file_info, Bytes::from("memes")).await;
+        assert!(res.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn display_primary() {
+        let secondary_dir = tempdir::TempDir::new("secondary").unwrap().into_path();
+        let storage = get_storage(secondary_dir);
+        assert_eq!(storage.to_string(), "file_storage");
+    }
+}
@@ -1,18 +1,29 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use crate::{
     errors::{RustusError, RustusResult},
     info_storages::FileInfo,
 };
 
-use super::Storage;
+use super::{DataLocation, DataStream, Storage};
 use crate::{storages::file_storage::FileStorage, utils::dir_struct::substr_time};
 use actix_web::{HttpRequest, HttpResponse, HttpResponseBuilder};
 use async_trait::async_trait;
 use bytes::Bytes;
 use derive_more::Display;
+use futures::StreamExt;
 use s3::{command::Command, request::Reqwest, request_trait::Request, Bucket};
 
+/// Time after which the stitch lock is considered
+/// to be left by a crashed process.
+const STITCH_LOCK_TTL: Duration = Duration::from_mins(10);
+
 /// This storage is useful for small files when you have chunks less than 5MB.
 /// This restriction is based on the S3 API limitations.
 ///
@@ -20,16 +31,85 @@ use s3::{command::Command, request::Reqwest, request_trait::Request, Bucket};
 /// complete, it uploads file to S3.
 ///
 /// It's not intended to use this storage for large files.
+///
+/// If `out_of_order` is enabled, every chunk is stored
+/// in a separate local file, named after its offset.
+/// Chunks are stitched together once the whole file is received.
 #[derive(Display, Clone)]
 #[display(fmt = "s3_storage")]
 pub struct S3HybridStorage {
     bucket: Bucket,
     local_storage: FileStorage,
     dir_struct: String,
+    out_of_order: bool,
+    /// Prefix of all keys.
+    prefix: Option<String>,
+}
+
+/// Create bucket instance with S3 connection options.
+///
+/// # Errors
+///
+/// Returns an error if credentials or headers are invalid.
+#[allow(clippy::too_many_arguments)]
+pub fn build_bucket(
+    endpoint: String,
+    region: String,
+    access_key: &Option<String>,
+    secret_key: &Option<String>,
+    security_token: &Option<String>,
+    session_token: &Option<String>,
+    profile: &Option<String>,
+    custom_headers: &Option<String>,
+    bucket_name: &str,
+    force_path_style: bool,
+) -> RustusResult<Bucket> {
+    let credentials = s3::creds::Credentials::new(
+        access_key.as_deref(),
+        secret_key.as_deref(),
+        security_token.as_deref(),
+        session_token.as_deref(),
+        profile.as_deref(),
+    )
+    .map_err(|err| {
+        RustusError::UnableToPrepareStorage(format!("Cannot build credentials: {err}"))
+    })?;
+    log::debug!("Parsed credentials");
+    let mut bucket = Bucket::new(
+        bucket_name,
+        s3::Region::Custom { region, endpoint },
+        credentials,
+    )
+    .map_err(|err| {
+        RustusError::UnableToPrepareStorage(format!("Cannot create bucket instance {err}"))
+    })?;
+    if let Some(raw_s3_headers) = custom_headers {
+        let headers_map =
+            serde_json::from_str::<HashMap<String, String>>(raw_s3_headers).map_err(|_| {
+                RustusError::UnableToPrepareStorage(String::from(
+                    "Cannot parse s3 headers. Please provide valid JSON object.",
+                ))
+            })?;
+        log::debug!("Found extra s3 headers.");
+        for (key, value) in &headers_map {
+            log::debug!("Adding header `{key}` with value `{value}`.");
+            bucket.add_header(key, value);
+        }
+    }
+    if force_path_style {
+        bucket = bucket.with_path_style();
+    }
+    Ok(bucket)
 }
 
 impl S3HybridStorage {
+    /// Create storage with S3 bucket and local directory for unfinished uploads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bucket can't be configured.
     #[allow(clippy::too_many_arguments)]
+    #[must_use]
     pub fn new(
         endpoint: String,
         region: String,
@@ -44,50 +124,39 @@ impl S3HybridStorage {
         data_dir: PathBuf,
         dir_struct: String,
         force_fsync: bool,
+        out_of_order: bool,
     ) -> Self {
         let local_storage = FileStorage::new(data_dir, dir_struct.clone(), force_fsync);
-        let creds = s3::creds::Credentials::new(
-            access_key.as_deref(),
-            secret_key.as_deref(),
-            security_token.as_deref(),
-            session_token.as_deref(),
-            profile.as_deref(),
-        );
-        if let Err(err) = creds {
-            panic!("Cannot build credentials: {err}")
-        }
-        log::debug!("Parsed credentials");
-        let credentials = creds.unwrap();
-        let bucket = Bucket::new(
+        let bucket = build_bucket(
+            endpoint,
+            region,
+            access_key,
+            secret_key,
+            security_token,
+            session_token,
+            profile,
+            custom_headers,
             bucket_name,
-            s3::Region::Custom { region, endpoint },
-            credentials,
-        );
-        if let Err(error) = bucket {
-            panic!("Cannot create bucket instance {error}");
-        }
-        let mut bucket = bucket.unwrap();
-        if let Some(raw_s3_headers) = custom_headers {
-            let headers_map = serde_json::from_str::<HashMap<String, String>>(raw_s3_headers)
-                .expect("Cannot parse s3 headers. Please provide valid JSON object.");
-            log::debug!("Found extra s3 headers.");
-            for (key, value) in &headers_map {
-                log::debug!("Adding header `{key}` with value `{value}`.");
-                bucket.add_header(key, value);
-            }
-        }
-
-        if force_path_style {
-            bucket = bucket.with_path_style();
-        }
+            force_path_style,
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
 
         Self {
             bucket,
             local_storage,
             dir_struct,
+            out_of_order,
+            prefix: None,
         }
     }
 
+    /// Store objects under the prefix.
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: String) -> Self {
+        self.prefix = Some(prefix);
+        self
+    }
+
     /// Upload file to S3.
     ///
     /// This function is called to upload file to s3 completely.
@@ -108,9 +177,86 @@ impl S3HybridStorage {
         Ok(())
     }
 
+    /// Store chunk received at arbitrary offset.
+    ///
+    /// Chunk is written in a temporary file and then renamed,
+    /// so partially written chunks are never visible.
+    /// If all chunks together cover the whole file,
+    /// they are stitched and the result is uploaded to S3.
+    async fn add_chunk(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+        let Some(path) = file_info.path.clone() else {
+            return Err(RustusError::UnableToWrite("Cannot get upload path.".into()));
+        };
+        let Some(length) = file_info.length else {
+            return Err(RustusError::WrongOffset);
+        };
+        let offset = file_info.offset;
+        let chunks = tokio::task::spawn_blocking(move || {
+            let chunk_path = chunk_path(path.as_str(), offset);
+            let tmp_path = format!("{chunk_path}.tmp");
+            std::fs::write(tmp_path.as_str(), bytes.as_ref())?;
+            std::fs::rename(tmp_path, chunk_path)?;
+            list_chunks(path.as_str())
+        })
+        .await??;
+        let ranges = chunks
+            .iter()
+            .map(|(offset, size, _)| (*offset, *size))
+            .collect::<Vec<_>>();
+        if !chunks_cover(ranges.as_slice(), length) {
+            return Ok(());
+        }
+        let path = file_info.path.clone().unwrap();
+        let lock_path = format!("{path}.stitch");
+        let stitched = tokio::task::spawn_blocking({
+            let lock_path = lock_path.clone();
+            move || {
+                // Only one request is allowed to stitch the file.
+                if !lock_stitching(lock_path.as_str())? {
+                    return Ok(false);
+                }
+                stitch_chunks(path.as_str(), chunks.as_slice())?;
+                Ok::<_, RustusError>(true)
+            }
+        })
+        .await?;
+        let result = match stitched {
+            Ok(false) => return Ok(()),
+            Ok(true) => self.upload_file(file_info).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            // Chunks are kept, so the last chunk can be sent again.
+            std::fs::remove_file(lock_path).ok();
+            return Err(err);
+        }
+        self.remove_local_files(file_info).await
+    }
+
+    /// Remove local file and all stored chunks.
+    async fn remove_local_files(&self, file_info: &FileInfo) -> RustusResult<()> {
+        if let Some(path) = file_info.path.clone() {
+            tokio::task::spawn_blocking(move || -> RustusResult<()> {
+                for (_, _, chunk) in list_chunks(path.as_str())? {
+                    std::fs::remove_file(chunk)?;
+                }
+                let lock_path = format!("{path}.stitch");
+                if Path::new(lock_path.as_str()).exists() {
+                    std::fs::remove_file(lock_path)?;
+                }
+                Ok(())
+            })
+            .await??;
+        }
+        self.local_storage.remove_file(file_info).await
+    }
+
     // Construct an S3 key which is used to upload files.
     fn get_s3_key(&self, file_info: &FileInfo) -> String {
-        let base_path = substr_time(self.dir_struct.as_str(), file_info.created_at);
+        let mut base_path = substr_time(self.dir_struct.as_str(), file_info.created_at);
+        if let Some(prefix) = &self.prefix {
+            base_path = format!("{prefix}/{base_path}");
+        }
         let trimmed_path = base_path.trim_end_matches(|c: char| c == '/');
         format!("{trimmed_path}/{}", file_info.id)
     }
@@ -139,7 +285,33 @@ impl Storage for S3HybridStorage {
         Ok(response.streaming(s3_response.bytes_stream()))
     }
 
+    async fn read_data(
+        &self,
+        file_info: &FileInfo,
+        range: Range<usize>,
+    ) -> RustusResult<DataStream> {
+        if file_info.length != Some(file_info.offset) {
+            return self.local_storage.read_data(file_info, range).await;
+        }
+        if range.is_empty() {
+            return Ok(futures::stream::empty().boxed_local());
+        }
+        let key = self.get_s3_key(file_info);
+        let command = Command::GetObjectRange {
+            start: range.start as u64,
+            end: Some(range.end as u64 - 1),
+        };
+        let s3_response = Reqwest::new(&self.bucket, &key, command).response().await?;
+        Ok(s3_response
+            .bytes_stream()
+            .map(|chunk| chunk.map_err(RustusError::from))
+            .boxed_local())
+    }
+
     async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+        if self.out_of_order {
+            return self.add_chunk(file_info, bytes).await;
+        }
         let part_len = bytes.len();
         self.local_storage.add_bytes(file_info, bytes).await?;
         // If upload is complete. Upload the resulting file onto S3.
@@ -150,6 +322,35 @@ impl Storage for S3HybridStorage {
         Ok(())
     }
 
+    fn accepts_out_of_order(&self) -> bool {
+        self.out_of_order
+    }
+
+    fn read_after_write(&self) -> bool {
+        // Finished uploads are read from the remote server,
+        // which may show new objects with a delay.
+        false
+    }
+
+    fn data_location(&self, file_info: &FileInfo) -> Option<DataLocation> {
+        if file_info.length != Some(file_info.offset) {
+            return self.local_storage.data_location(file_info);
+        }
+        Some(DataLocation {
+            storage: self.to_string(),
+            path: self.get_s3_key(file_info),
+            bucket: Some(self.bucket.name.clone()),
+        })
+    }
+
+    async fn truncate(&self, file_info: &FileInfo) -> RustusResult<()> {
+        // Chunks are written atomically, so there's nothing to clean up.
+        if self.out_of_order {
+            return Ok(());
+        }
+        self.local_storage.truncate(file_info).await
+    }
+
     async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
         self.local_storage.create_file(file_info).await
     }
@@ -164,14 +365,219 @@ impl Storage for S3HybridStorage {
         ))
     }
 
+    async fn copy_file(&self, source: &FileInfo, target: &FileInfo) -> RustusResult<()> {
+        // Objects are copied by S3 without downloading them.
+        let status = self
+            .bucket
+            .copy_object_internal(self.get_s3_key(source), self.get_s3_key(target))
+            .await?;
+        if !(200..300).contains(&status) {
+            return Err(RustusError::UnableToWrite(format!(
+                "S3 returned status {status} while copying object."
+            )));
+        }
+        // Finished uploads are stored only in S3.
+        self.local_storage.remove_file(target).await
+    }
+
     async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
         if Some(file_info.offset) == file_info.length {
             self.bucket
                 .delete_object(self.get_s3_key(file_info))
                 .await?;
+        } else if self.out_of_order {
+            self.remove_local_files(file_info).await?;
         } else {
             self.local_storage.remove_file(file_info).await?;
         }
         Ok(())
     }
+
+    async fn stored_size(&self, file_info: &FileInfo) -> RustusResult<Option<usize>> {
+        if Some(file_info.offset) != file_info.length {
+            if self.out_of_order {
+                return Ok(None);
+            }
+            return self.local_storage.stored_size(file_info).await;
+        }
+        let (head, status) = self.bucket.head_object(self.get_s3_key(file_info)).await?;
+        if status == 404 {
+            return Err(RustusError::FileNotFound);
+        }
+        Ok(head
+            .content_length
+            .and_then(|length| usize::try_from(length).ok()))
+    }
+}
+
+/// Path of the chunk file.
+///
+/// Offset is padded with zeroes,
+/// so chunks can be sorted by name.
+fn chunk_path(path: &str, offset: usize) -> String {
+    format!("{path}.{offset:020}")
+}
+
+/// Find all chunks of the upload.
+///
+/// Returns offset, size and path of every chunk
+/// sorted by offset.
+fn list_chunks(path: &str) -> RustusResult<Vec<(usize, usize, PathBuf)>> {
+    let upload_path = Path::new(path);
+    let Some(file_name) = upload_path.file_name().and_then(std::ffi::OsStr::to_str) else {
+        return Err(RustusError::UnableToWrite("Cannot get upload path.".into()));
+    };
+    let prefix = format!("{file_name}.");
+    let dir = upload_path.parent().unwrap_or(Path::new("."));
+    let mut chunks = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(offset) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(prefix.as_str()))
+            .filter(|suffix| suffix.len() == 20)
+            .and_then(|suffix| suffix.parse::<usize>().ok())
+        else {
+            continue;
+        };
+        let size = usize::try_from(entry.metadata()?.len()).unwrap_or(usize::MAX);
+        chunks.push((offset, size, entry.path()));
+    }
+    chunks.sort_by_key(|(offset, _, _)| *offset);
+    Ok(chunks)
+}
+
+/// Check that chunks cover the whole file without gaps.
+///
+/// Chunks must be sorted by offset.
+fn chunks_cover(chunks: &[(usize, usize)], length: usize) -> bool {
+    let mut covered = 0;
+    for (offset, size) in chunks {
+        if *offset > covered {
+            return false;
+        }
+        covered = covered.max(offset + size);
+    }
+    covered >= length
+}
+
+/// Take the lock of stitching the upload.
+///
+/// The lock holds ID of the process which took it.
+/// Returns false if the upload is stitched by another request.
+/// Locks older than `STITCH_LOCK_TTL` are broken,
+/// since they are left by crashed processes.
+fn lock_stitching(lock_path: &str) -> RustusResult<bool> {
+    for _ in 0..2 {
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(lock_path)
+        {
+            Ok(mut lock) => {
+                writeln!(lock, "{}", std::process::id())?;
+                return Ok(true);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err.into()),
+        }
+        let age = match std::fs::metadata(lock_path) {
+            Ok(meta) => meta.modified()?.elapsed().unwrap_or_default(),
+            // Lock was released meanwhile.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        if age < STITCH_LOCK_TTL {
+            return Ok(false);
+        }
+        log::warn!("Stale stitch lock {lock_path} is broken.");
+        std::fs::remove_file(lock_path).ok();
+    }
+    Ok(false)
+}
+
+/// Write all chunks into the upload file in order.
+fn stitch_chunks(path: &str, chunks: &[(usize, usize, PathBuf)]) -> RustusResult<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(path)?;
+    let mut written = 0;
+    for (offset, _, chunk) in chunks {
+        if *offset != written {
+            return Err(RustusError::UnableToWrite(format!(
+                "Upload has a gap or overlap at offset {written}."
+            )));
+        }
+        let mut buffer = Vec::new();
+        std::fs::File::open(chunk)?.read_to_end(&mut buffer)?;
+        file.write_all(buffer.as_slice())?;
+        written += buffer.len();
+    }
+    file.sync_data()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        chunk_path, chunks_cover, list_chunks, lock_stitching, stitch_chunks, STITCH_LOCK_TTL,
+    };
+
+    #[test]
+    fn test_chunks_cover() {
+        assert!(chunks_cover(&[(0, 3), (3, 7)], 10));
+        assert!(!chunks_cover(&[(0, 3), (4, 6)], 10));
+        assert!(!chunks_cover(&[(3, 7)], 10));
+        assert!(!chunks_cover(&[(0, 3)], 10));
+    }
+
+    #[test]
+    fn test_stitch_chunks() {
+        let dir = tempdir::TempDir::new("s3_chunks").unwrap();
+        let path = dir.path().join("upload");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "").unwrap();
+        std::fs::write(chunk_path(path, 6), "world").unwrap();
+        std::fs::write(chunk_path(path, 0), "hello ").unwrap();
+        let chunks = list_chunks(path).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].0, 0);
+        stitch_chunks(path, chunks.as_slice()).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "hello world");
+    }
+
+    #[test]
+    fn test_stitch_chunks_gap() {
+        let dir = tempdir::TempDir::new("s3_chunks").unwrap();
+        let path = dir.path().join("upload");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "").unwrap();
+        std::fs::write(chunk_path(path, 0), "hello").unwrap();
+        std::fs::write(chunk_path(path, 6), "world").unwrap();
+        let chunks = list_chunks(path).unwrap();
+        assert!(stitch_chunks(path, chunks.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_stale_stitch_lock() {
+        let dir = tempdir::TempDir::new("s3_chunks").unwrap();
+        let lock_path = dir.path().join("upload.stitch");
+        let lock_path = lock_path.to_str().unwrap();
+        assert!(lock_stitching(lock_path).unwrap());
+        assert!(!lock_stitching(lock_path).unwrap());
+        // Lock of a crashed process expires.
+        let lock = std::fs::File::options()
+            .write(true)
+            .open(lock_path)
+            .unwrap();
+        let expired = std::time::SystemTime::now() - STITCH_LOCK_TTL * 2;
+        lock.set_modified(expired).unwrap();
+        assert!(lock_stitching(lock_path).unwrap());
+        assert_eq!(
+            std::fs::read_to_string(lock_path).unwrap().trim(),
+            std::process::id().to_string()
+        );
+    }
 }
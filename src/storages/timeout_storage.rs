@@ -0,0 +1,403 @@
+use std::{ops::Range, time::Duration};
+
+use actix_web::{HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use bytes::Bytes;
+use derive_more::Display;
+use log::warn;
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    storages::{copy_contents, Compaction, DataLocation, DataStream, Storage},
+    utils::timeout::with_timeout,
+};
+
+/// Storage wrapper that limits duration of every operation.
+///
+/// Reading operations are limited by `read_timeout`,
+/// all other operations are limited by `write_timeout`.
+///
+/// Writes can't be interrupted in the middle.
+/// If a write times out, it keeps running in background
+/// and the written data is removed after it's done.
+#[derive(Display, Clone)]
+#[display(fmt = "{inner}")]
+pub struct TimeoutStorage {
+    inner: Box<dyn Storage + Send + Sync>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+}
+
+impl TimeoutStorage {
+    #[must_use]
+    pub fn new(
+        inner: Box<dyn Storage + Send + Sync>,
+        read_timeout: Option<Duration>,
+        write_timeout: Option<Duration>,
+    ) -> Self {
+        Self {
+            inner,
+            read_timeout,
+            write_timeout,
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for TimeoutStorage {
+    async fn prepare(&mut self) -> RustusResult<()> {
+        self.inner.prepare().await
+    }
+
+    async fn get_contents(
+        &self,
+        file_info: &FileInfo,
+        request: &HttpRequest,
+    ) -> RustusResult<HttpResponse> {
+        with_timeout(
+            self.read_timeout,
+            "get_contents",
+            self.inner.get_contents(file_info, request),
+        )
+        .await
+    }
+
+    async fn read_data(
+        &self,
+        file_info: &FileInfo,
+        range: Range<usize>,
+    ) -> RustusResult<DataStream> {
+        with_timeout(
+            self.read_timeout,
+            "read_data",
+            self.inner.read_data(file_info, range),
+        )
+        .await
+    }
+
+    async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+        let Some(timeout) = self.write_timeout else {
+            return self.inner.add_bytes(file_info, bytes).await;
+        };
+        let storage = self.inner.clone();
+        let info = file_info.clone();
+        let mut task =
+            tokio::task::spawn_local(async move { storage.add_bytes(&info, bytes).await });
+        if let Ok(result) = tokio::time::timeout(timeout, &mut task).await {
+            return result?;
+        }
+        let storage = self.inner.clone();
+        let info = file_info.clone();
+        tokio::task::spawn_local(async move {
+            // Waiting for the write to finish, whatever the result is.
+            task.await.ok();
+            if let Err(err) = storage.truncate(&info).await {
+                warn!("Cannot clean up upload {} after timeout: {}", info.id, err);
+            }
+        });
+        Err(RustusError::Timeout(String::from("add_bytes")))
+    }
+
+    fn accepts_out_of_order(&self) -> bool {
+        self.inner.accepts_out_of_order()
+    }
+
+    fn chunk_alignment(&self) -> Option<usize> {
+        self.inner.chunk_alignment()
+    }
+
+    fn preferred_chunk_size(&self) -> Option<usize> {
+        self.inner.preferred_chunk_size()
+    }
+
+    fn data_location(&self, file_info: &FileInfo) -> Option<DataLocation> {
+        self.inner.data_location(file_info)
+    }
+
+    async fn truncate(&self, file_info: &FileInfo) -> RustusResult<()> {
+        with_timeout(
+            self.write_timeout,
+            "truncate",
+            self.inner.truncate(file_info),
+        )
+        .await
+    }
+
+    async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
+        let mut info = file_info.clone();
+        self.create_upload(&mut info).await?;
+        info.path.ok_or(RustusError::FileNotFound)
+    }
+
+    async fn create_upload(&self, file_info: &mut FileInfo) -> RustusResult<()> {
+        let Some(timeout) = self.write_timeout else {
+            return self.inner.create_upload(file_info).await;
+        };
+        let storage = self.inner.clone();
+        let mut info = file_info.clone();
+        let mut task = tokio::task::spawn_local(async move {
+            storage.create_upload(&mut info).await?;
+            RustusResult::Ok(info)
+        });
+        if let Ok(result) = tokio::time::timeout(timeout, &mut task).await {
+            *file_info = result??;
+            return Ok(());
+        }
+        let storage = self.inner.clone();
+        tokio::task::spawn_local(async move {
+            // File was created after the timeout,
+            // but nobody knows about it, so we remove it.
+            if let Ok(Ok(info)) = task.await {
+                if let Err(err) = storage.remove_file(&info).await {
+                    warn!("Cannot clean up upload {} after timeout: {}", info.id, err);
+                }
+            }
+        });
+        Err(RustusError::Timeout(String::from("create_file")))
+    }
+
+    async fn concat_files(
+        &self,
+        file_info: &FileInfo,
+        parts_info: Vec<FileInfo>,
+    ) -> RustusResult<()> {
+        with_timeout(
+            self.write_timeout,
+            "concat_files",
+            self.inner.concat_files(file_info, parts_info),
+        )
+        .await
+    }
+
+    async fn copy_file(&self, source: &FileInfo, target: &FileInfo) -> RustusResult<()> {
+        // Copying takes as long as the upload is large,
+        // so every read and written chunk has its own timeout.
+        copy_contents(self, source, self, target).await
+    }
+
+    async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+        with_timeout(
+            self.write_timeout,
+            "remove_file",
+            self.inner.remove_file(file_info),
+        )
+        .await
+    }
+
+    async fn sync_data(&self, file_info: &FileInfo) -> RustusResult<()> {
+        with_timeout(
+            self.write_timeout,
+            "sync_data",
+            self.inner.sync_data(file_info),
+        )
+        .await
+    }
+
+    async fn data_exists(&self, file_info: &FileInfo) -> RustusResult<bool> {
+        with_timeout(
+            self.read_timeout,
+            "data_exists",
+            self.inner.data_exists(file_info),
+        )
+        .await
+    }
+
+    async fn stored_size(&self, file_info: &FileInfo) -> RustusResult<Option<usize>> {
+        with_timeout(
+            self.read_timeout,
+            "stored_size",
+            self.inner.stored_size(file_info),
+        )
+        .await
+    }
+
+    async fn compact(&self, uploads: &[FileInfo], threshold: f64) -> RustusResult<Compaction> {
+        self.inner.compact(uploads, threshold).await
+    }
+
+    async fn reconcile(&self, uploads: &[FileInfo]) -> RustusResult<Vec<(FileInfo, String)>> {
+        self.inner.reconcile(uploads).await
+    }
+
+    async fn list_paths(&self) -> RustusResult<Vec<String>> {
+        self.inner.list_paths().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TimeoutStorage;
+    use crate::{
+        errors::{RustusError, RustusResult},
+        info_storages::FileInfo,
+        storages::{file_storage::FileStorage, DataStream},
+        utils::import::ImportSource,
+        Storage,
+    };
+    use actix_web::{HttpRequest, HttpResponse};
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use derive_more::Display;
+    use std::{ops::Range, time::Duration};
+
+    /// File storage that writes bytes slowly.
+    #[derive(Display, Clone)]
+    #[display(fmt = "slow_storage")]
+    struct SlowStorage {
+        inner: FileStorage,
+        delay: Duration,
+    }
+
+    #[async_trait(?Send)]
+    impl Storage for SlowStorage {
+        async fn prepare(&mut self) -> RustusResult<()> {
+            self.inner.prepare().await
+        }
+
+        async fn get_contents(
+            &self,
+            file_info: &FileInfo,
+            request: &HttpRequest,
+        ) -> RustusResult<HttpResponse> {
+            self.inner.get_contents(file_info, request).await
+        }
+
+        async fn read_data(
+            &self,
+            file_info: &FileInfo,
+            range: Range<usize>,
+        ) -> RustusResult<DataStream> {
+            self.inner.read_data(file_info, range).await
+        }
+
+        async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+            self.inner.add_bytes(file_info, bytes).await?;
+            tokio::time::sleep(self.delay).await;
+            Ok(())
+        }
+
+        async fn truncate(&self, file_info: &FileInfo) -> RustusResult<()> {
+            self.inner.truncate(file_info).await
+        }
+
+        async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
+            self.inner.create_file(file_info).await
+        }
+
+        async fn concat_files(
+            &self,
+            file_info: &FileInfo,
+            parts_info: Vec<FileInfo>,
+        ) -> RustusResult<()> {
+            self.inner.concat_files(file_info, parts_info).await
+        }
+
+        async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+            self.inner.remove_file(file_info).await
+        }
+    }
+
+    fn get_storage(delay: Duration) -> TimeoutStorage {
+        let dir = tempdir::TempDir::new("timeout_storage")
+            .unwrap()
+            .into_path();
+        let slow = SlowStorage {
+            inner: FileStorage::new(dir, String::new(), false),
+            delay,
+        };
+        TimeoutStorage::new(Box::new(slow), None, Some(Duration::from_millis(100)))
+    }
+
+    #[actix_rt::test]
+    async fn write_in_time() {
+        let storage = get_storage(Duration::ZERO);
+        let mut file_info = FileInfo::new("test_id", Some(10), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        let contents = std::fs::read_to_string(file_info.path.unwrap()).unwrap();
+        assert_eq!(contents, "memes");
+    }
+
+    #[actix_rt::test]
+    async fn import_in_chunks() {
+        let storage = get_storage(Duration::ZERO);
+        let source = tempdir::TempDir::new("timeout_storage").unwrap();
+        let source = source.path().join("source");
+        std::fs::write(source.as_path(), "memes").unwrap();
+        let mut file_info = FileInfo::new("test_id", Some(5), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        // Wrapped storages can't link files, so bytes are copied.
+        storage
+            .import_file(&file_info, &ImportSource::open(&source).unwrap(), true)
+            .await
+            .unwrap();
+        let contents = std::fs::read_to_string(file_info.path.clone().unwrap()).unwrap();
+        assert_eq!(contents, "memes");
+        // Length doesn't match the size of the file.
+        let mut file_info = FileInfo::new("other_id", Some(3), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        assert!(storage
+            .import_file(&file_info, &ImportSource::open(&source).unwrap(), false)
+            .await
+            .is_err());
+    }
+
+    #[actix_rt::test]
+    async fn copy_in_chunks() {
+        let storage = get_storage(Duration::ZERO);
+        let mut source = FileInfo::new("source_id", Some(5), None, storage.to_string(), None);
+        source.path = Some(storage.create_file(&source).await.unwrap());
+        storage
+            .add_bytes(&source, Bytes::from("memes"))
+            .await
+            .unwrap();
+        source.offset = 5;
+        // Wrapped storage doesn't copy files natively,
+        // so contents are read and written again.
+        let mut target = FileInfo::new("target_id", Some(5), None, storage.to_string(), None);
+        target.path = Some(storage.create_file(&target).await.unwrap());
+        storage.copy_file(&source, &target).await.unwrap();
+        let contents = std::fs::read_to_string(target.path.clone().unwrap()).unwrap();
+        assert_eq!(contents, "memes");
+        // Length doesn't match the length of the source.
+        let mut target = FileInfo::new("other_id", Some(8), None, storage.to_string(), None);
+        target.path = Some(storage.create_file(&target).await.unwrap());
+        assert!(storage.copy_file(&source, &target).await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn long_copy() {
+        // Every chunk is written in time, but the whole copy isn't.
+        let storage = get_storage(Duration::from_millis(60));
+        let length = 8 * 1024 * 1024 + 1;
+        let mut source = FileInfo::new("source_id", Some(length), None, storage.to_string(), None);
+        source.path = Some(storage.create_file(&source).await.unwrap());
+        storage
+            .add_bytes(&source, Bytes::from(vec![b'a'; length]))
+            .await
+            .unwrap();
+        source.offset = length;
+        let mut target = FileInfo::new("target_id", Some(length), None, storage.to_string(), None);
+        target.path = Some(storage.create_file(&target).await.unwrap());
+        storage.copy_file(&source, &target).await.unwrap();
+        let contents = std::fs::read(target.path.clone().unwrap()).unwrap();
+        assert_eq!(contents.len(), length);
+    }
+
+    #[actix_rt::test]
+    async fn write_timeout_cleanup() {
+        let storage = get_storage(Duration::from_millis(300));
+        let mut file_info = FileInfo::new("test_id", Some(10), None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        let res = storage.add_bytes(&file_info, Bytes::from("memes")).await;
+        assert!(matches!(res, Err(RustusError::Timeout(_))));
+        let path = file_info.path.unwrap();
+        // Partially written upload is truncated after the write is done.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "");
+    }
+}
@@ -0,0 +1,261 @@
+use std::ops::Range;
+
+use actix_web::{HttpRequest, HttpResponse};
+use async_trait::async_trait;
+use bytes::Bytes;
+use derive_more::Display;
+
+use crate::{
+    errors::RustusResult,
+    info_storages::FileInfo,
+    storages::{Compaction, DataLocation, DataStream, Storage},
+    telemetry::{size_attribute, traced, KeyValue},
+    utils::import::ImportSource,
+};
+
+/// Storage wrapper that traces every operation.
+///
+/// Operations are traced only inside traced requests,
+/// so background tasks don't produce spans.
+#[derive(Display, Clone)]
+#[display(fmt = "{inner}")]
+pub struct TracedStorage {
+    inner: Box<dyn Storage + Send + Sync>,
+}
+
+impl TracedStorage {
+    #[must_use]
+    pub fn new(inner: Box<dyn Storage + Send + Sync>) -> Self {
+        Self { inner }
+    }
+
+    /// Attributes of spans of the upload.
+    fn attributes(&self, file_info: &FileInfo) -> Vec<KeyValue> {
+        vec![
+            KeyValue::new("rustus.storage", self.inner.to_string()),
+            KeyValue::new("rustus.upload_id", file_info.id.clone()),
+            size_attribute("rustus.upload_offset", file_info.offset),
+        ]
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for TracedStorage {
+    async fn prepare(&mut self) -> RustusResult<()> {
+        self.inner.prepare().await
+    }
+
+    async fn get_contents(
+        &self,
+        file_info: &FileInfo,
+        request: &HttpRequest,
+    ) -> RustusResult<HttpResponse> {
+        traced(
+            "storage.get_contents",
+            self.attributes(file_info),
+            self.inner.get_contents(file_info, request),
+        )
+        .await
+    }
+
+    async fn read_data(
+        &self,
+        file_info: &FileInfo,
+        range: Range<usize>,
+    ) -> RustusResult<DataStream> {
+        traced(
+            "storage.read_data",
+            self.attributes(file_info),
+            self.inner.read_data(file_info, range),
+        )
+        .await
+    }
+
+    async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+        let mut attributes = self.attributes(file_info);
+        attributes.push(size_attribute("rustus.chunk_size", bytes.len()));
+        traced(
+            "storage.add_bytes",
+            attributes,
+            self.inner.add_bytes(file_info, bytes),
+        )
+        .await
+    }
+
+    fn accepts_out_of_order(&self) -> bool {
+        self.inner.accepts_out_of_order()
+    }
+
+    fn chunk_alignment(&self) -> Option<usize> {
+        self.inner.chunk_alignment()
+    }
+
+    fn preferred_chunk_size(&self) -> Option<usize> {
+        self.inner.preferred_chunk_size()
+    }
+
+    fn data_location(&self, file_info: &FileInfo) -> Option<DataLocation> {
+        self.inner.data_location(file_info)
+    }
+
+    async fn truncate(&self, file_info: &FileInfo) -> RustusResult<()> {
+        traced(
+            "storage.truncate",
+            self.attributes(file_info),
+            self.inner.truncate(file_info),
+        )
+        .await
+    }
+
+    async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
+        traced(
+            "storage.create_file",
+            self.attributes(file_info),
+            self.inner.create_file(file_info),
+        )
+        .await
+    }
+
+    async fn create_upload(&self, file_info: &mut FileInfo) -> RustusResult<()> {
+        let attributes = self.attributes(file_info);
+        traced(
+            "storage.create_file",
+            attributes,
+            self.inner.create_upload(file_info),
+        )
+        .await
+    }
+
+    async fn concat_files(
+        &self,
+        file_info: &FileInfo,
+        parts_info: Vec<FileInfo>,
+    ) -> RustusResult<()> {
+        traced(
+            "storage.concat_files",
+            self.attributes(file_info),
+            self.inner.concat_files(file_info, parts_info),
+        )
+        .await
+    }
+
+    async fn import_file(
+        &self,
+        file_info: &FileInfo,
+        source: &ImportSource,
+        hard_link: bool,
+    ) -> RustusResult<()> {
+        traced(
+            "storage.import_file",
+            self.attributes(file_info),
+            self.inner.import_file(file_info, source, hard_link),
+        )
+        .await
+    }
+
+    async fn copy_file(&self, source: &FileInfo, target: &FileInfo) -> RustusResult<()> {
+        let mut attributes = self.attributes(target);
+        attributes.push(KeyValue::new("rustus.source_id", source.id.clone()));
+        traced(
+            "storage.copy_file",
+            attributes,
+            self.inner.copy_file(source, target),
+        )
+        .await
+    }
+
+    async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+        traced(
+            "storage.remove_file",
+            self.attributes(file_info),
+            self.inner.remove_file(file_info),
+        )
+        .await
+    }
+
+    async fn sync_data(&self, file_info: &FileInfo) -> RustusResult<()> {
+        traced(
+            "storage.sync_data",
+            self.attributes(file_info),
+            self.inner.sync_data(file_info),
+        )
+        .await
+    }
+
+    async fn data_exists(&self, file_info: &FileInfo) -> RustusResult<bool> {
+        traced(
+            "storage.data_exists",
+            self.attributes(file_info),
+            self.inner.data_exists(file_info),
+        )
+        .await
+    }
+
+    async fn stored_size(&self, file_info: &FileInfo) -> RustusResult<Option<usize>> {
+        traced(
+            "storage.stored_size",
+            self.attributes(file_info),
+            self.inner.stored_size(file_info),
+        )
+        .await
+    }
+
+    async fn compact(&self, uploads: &[FileInfo], threshold: f64) -> RustusResult<Compaction> {
+        self.inner.compact(uploads, threshold).await
+    }
+
+    async fn reconcile(&self, uploads: &[FileInfo]) -> RustusResult<Vec<(FileInfo, String)>> {
+        self.inner.reconcile(uploads).await
+    }
+
+    async fn list_paths(&self) -> RustusResult<Vec<String>> {
+        self.inner.list_paths().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TracedStorage;
+    use crate::{
+        info_storages::FileInfo,
+        storages::file_storage::FileStorage,
+        telemetry::{test::TestTracer, KeyValue},
+        Storage,
+    };
+    use actix_web::http::header::HeaderMap;
+    use bytes::Bytes;
+    use opentelemetry::trace::{FutureExt, TraceContextExt};
+
+    #[actix_rt::test]
+    async fn traced_operations() {
+        let dir = tempdir::TempDir::new("traced_storage").unwrap();
+        let storage = TracedStorage::new(Box::new(FileStorage::new(
+            dir.path().to_path_buf(),
+            String::new(),
+            false,
+        )));
+        let mut file_info = FileInfo::new("test_id", Some(5), None, storage.to_string(), None);
+        let tracer = TestTracer::new();
+        // Operations outside of requests aren't traced.
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+
+        let cx = tracer.request("request", &HeaderMap::new());
+        storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .with_context(cx.clone())
+            .await
+            .unwrap();
+        cx.span().end();
+        let spans = tracer.finished_spans();
+        assert_eq!(spans.len(), 2);
+        let add_bytes = &spans[0];
+        assert_eq!(add_bytes.name, "storage.add_bytes");
+        assert!(add_bytes
+            .attributes
+            .contains(&KeyValue::new("rustus.storage", "file_storage")));
+        assert!(add_bytes
+            .attributes
+            .contains(&KeyValue::new("rustus.chunk_size", 5_i64)));
+        assert_eq!(spans[1].name, "request");
+    }
+}
@@ -0,0 +1,533 @@
+use std::{ops::Range, path::PathBuf};
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+};
+
+use super::{slice_stream, DataLocation, DataStream, Storage};
+use crate::{storages::file_storage::FileStorage, utils::dir_struct::substr_time};
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse, HttpResponseBuilder};
+use async_trait::async_trait;
+use bytes::Bytes;
+use derive_more::Display;
+use futures::StreamExt;
+use reqwest::{Body, Client, Method, RequestBuilder, Response};
+use tokio_util::io::ReaderStream;
+
+/// Storage which lands finished uploads on a `WebDAV` server.
+///
+/// `WebDAV` has no reliable way to append bytes to existing files,
+/// so uploads are received locally and the whole file is sent
+/// with a single `PUT` request after the last chunk.
+///
+/// It means that the last request of an upload takes as long
+/// as sending the file to the server.
+#[derive(Display, Clone)]
+#[display(fmt = "webdav_storage")]
+pub struct WebDavStorage {
+    client: Client,
+    base_url: String,
+    username: Option<String>,
+    password: Option<String>,
+    local_storage: FileStorage,
+    dir_struct: String,
+    /// Prefix of all paths.
+    prefix: Option<String>,
+}
+
+impl WebDavStorage {
+    #[must_use]
+    pub fn new(
+        base_url: &str,
+        username: Option<String>,
+        password: Option<String>,
+        data_dir: PathBuf,
+        dir_struct: String,
+        force_fsync: bool,
+    ) -> Self {
+        let local_storage = FileStorage::new(data_dir, dir_struct.clone(), force_fsync);
+        Self {
+            client: Client::new(),
+            base_url: String::from(base_url.trim_end_matches('/')),
+            username,
+            password,
+            local_storage,
+            dir_struct,
+            prefix: None,
+        }
+    }
+
+    /// Store files under the prefix.
+    #[must_use]
+    pub fn with_prefix(mut self, prefix: String) -> Self {
+        self.prefix = Some(prefix);
+        self
+    }
+
+    /// Build request to the given path relative to the base URL.
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self
+            .client
+            .request(method, format!("{}/{path}", self.base_url));
+        match &self.username {
+            Some(username) => request.basic_auth(username, self.password.as_ref()),
+            None => request,
+        }
+    }
+
+    /// Path of the upload on the server relative to the base URL.
+    fn remote_path(&self, file_info: &FileInfo) -> String {
+        let base_path = substr_time(self.dir_struct.as_str(), file_info.created_at);
+        self.prefix
+            .iter()
+            .map(String::as_str)
+            .chain(base_path.split('/'))
+            .filter(|part| !part.is_empty())
+            .chain(std::iter::once(file_info.id.as_str()))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Create all parent collections of the path.
+    ///
+    /// `WebDAV` servers don't create missing directories on `PUT`.
+    async fn create_collections(&self, path: &str) -> RustusResult<()> {
+        let parts = path.split('/').collect::<Vec<_>>();
+        for depth in 1..parts.len() {
+            let collection = format!("{}/", parts[..depth].join("/"));
+            let response = self.request(mkcol(), collection.as_str()).send().await?;
+            // 405 means that the collection already exists.
+            if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+                check_response(response, "MKCOL", collection.as_str())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Upload file to the server.
+    ///
+    /// File is streamed from disk, so it's never loaded in memory.
+    async fn upload_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+        let Some(local_path) = file_info.path.clone() else {
+            return Err(RustusError::UnableToWrite("Cannot get upload path.".into()));
+        };
+        let remote_path = self.remote_path(file_info);
+        log::debug!(
+            "Starting uploading {} to WebDAV with path `{}`",
+            file_info.id,
+            remote_path,
+        );
+        self.create_collections(remote_path.as_str()).await?;
+        let file = tokio::fs::File::open(local_path).await?;
+        let response = self
+            .request(Method::PUT, remote_path.as_str())
+            .body(Body::wrap_stream(ReaderStream::new(file)))
+            .send()
+            .await?;
+        check_response(response, "PUT", remote_path.as_str())?;
+        Ok(())
+    }
+
+    /// Check that test directory can be created and removed.
+    async fn check_test_directory(&self) -> RustusResult<()> {
+        let test_dir = format!(".rustus-{}/", uuid::Uuid::new_v4());
+        let created = self.request(mkcol(), test_dir.as_str()).send().await?;
+        check_response(created, "MKCOL", test_dir.as_str())?;
+        let removed = self
+            .request(Method::DELETE, test_dir.as_str())
+            .send()
+            .await?;
+        check_response(removed, "DELETE", test_dir.as_str())?;
+        Ok(())
+    }
+}
+
+fn mkcol() -> Method {
+    Method::from_bytes(b"MKCOL").unwrap()
+}
+
+fn propfind() -> Method {
+    Method::from_bytes(b"PROPFIND").unwrap()
+}
+
+/// Return an error if server responded with a non-success status.
+fn check_response(response: Response, method: &str, path: &str) -> RustusResult<Response> {
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+        return Err(RustusError::FileNotFound);
+    }
+    if !status.is_success() {
+        return Err(RustusError::WebDavError(format!(
+            "{method} of `{path}` returned {status}"
+        )));
+    }
+    Ok(response)
+}
+
+#[async_trait(?Send)]
+impl Storage for WebDavStorage {
+    async fn prepare(&mut self) -> RustusResult<()> {
+        self.local_storage.prepare().await?;
+        let response = self
+            .request(propfind(), "")
+            .header("Depth", "0")
+            .send()
+            .await
+            .map_err(|err| {
+                RustusError::UnableToPrepareStorage(format!("WebDAV server is unavailable: {err}"))
+            })?;
+        check_response(response, "PROPFIND", self.base_url.as_str()).map_err(|err| {
+            RustusError::UnableToPrepareStorage(format!("Cannot access base URL: {err}"))
+        })?;
+        self.check_test_directory().await.map_err(|err| {
+            RustusError::UnableToPrepareStorage(format!("Cannot create test directory: {err}"))
+        })
+    }
+
+    async fn get_contents(
+        &self,
+        file_info: &FileInfo,
+        request: &HttpRequest,
+    ) -> RustusResult<HttpResponse> {
+        if file_info.length != Some(file_info.offset) {
+            log::debug!("File isn't uploaded. Returning from local storage.");
+            return self.local_storage.get_contents(file_info, request).await;
+        }
+        let remote_path = self.remote_path(file_info);
+        let response = self
+            .request(Method::GET, remote_path.as_str())
+            .send()
+            .await?;
+        let response = check_response(response, "GET", remote_path.as_str())?;
+        Ok(HttpResponseBuilder::new(StatusCode::OK).streaming(response.bytes_stream()))
+    }
+
+    async fn read_data(
+        &self,
+        file_info: &FileInfo,
+        range: Range<usize>,
+    ) -> RustusResult<DataStream> {
+        if file_info.length != Some(file_info.offset) {
+            return self.local_storage.read_data(file_info, range).await;
+        }
+        if range.is_empty() {
+            return Ok(futures::stream::empty().boxed_local());
+        }
+        let remote_path = self.remote_path(file_info);
+        let response = self
+            .request(Method::GET, remote_path.as_str())
+            .header("Range", format!("bytes={}-{}", range.start, range.end - 1))
+            .send()
+            .await?;
+        let response = check_response(response, "GET", remote_path.as_str())?;
+        // Servers without range support return the whole file.
+        let skip = if response.status() == StatusCode::PARTIAL_CONTENT {
+            0
+        } else {
+            range.start
+        };
+        Ok(slice_stream(
+            response
+                .bytes_stream()
+                .map(|chunk| chunk.map_err(RustusError::from)),
+            skip,
+            range.len(),
+        ))
+    }
+
+    async fn add_bytes(&self, file_info: &FileInfo, bytes: Bytes) -> RustusResult<()> {
+        let part_len = bytes.len();
+        self.local_storage.add_bytes(file_info, bytes).await?;
+        // If upload is complete. Upload the resulting file onto the server.
+        if Some(file_info.offset + part_len) == file_info.length {
+            if let Err(err) = self.upload_file(file_info).await {
+                // Offset isn't updated, so the last chunk is removed
+                // and the client can send it again.
+                self.local_storage.truncate(file_info).await?;
+                return Err(err);
+            }
+            self.local_storage.remove_file(file_info).await?;
+        }
+        Ok(())
+    }
+
+    fn read_after_write(&self) -> bool {
+        // Finished uploads are read from the remote server,
+        // which may show new objects with a delay.
+        false
+    }
+
+    fn data_location(&self, file_info: &FileInfo) -> Option<DataLocation> {
+        if file_info.length != Some(file_info.offset) {
+            return self.local_storage.data_location(file_info);
+        }
+        Some(DataLocation {
+            storage: self.to_string(),
+            path: format!("{}/{}", self.base_url, self.remote_path(file_info)),
+            bucket: None,
+        })
+    }
+
+    async fn truncate(&self, file_info: &FileInfo) -> RustusResult<()> {
+        self.local_storage.truncate(file_info).await
+    }
+
+    async fn create_file(&self, file_info: &FileInfo) -> RustusResult<String> {
+        self.local_storage.create_file(file_info).await
+    }
+
+    async fn concat_files(
+        &self,
+        _file_info: &FileInfo,
+        _parts_info: Vec<FileInfo>,
+    ) -> RustusResult<()> {
+        Err(RustusError::Unimplemented(
+            "WebDAV storage cannot concat files.".into(),
+        ))
+    }
+
+    async fn remove_file(&self, file_info: &FileInfo) -> RustusResult<()> {
+        if Some(file_info.offset) != file_info.length {
+            return self.local_storage.remove_file(file_info).await;
+        }
+        let remote_path = self.remote_path(file_info);
+        let response = self
+            .request(Method::DELETE, remote_path.as_str())
+            .send()
+            .await?;
+        check_response(response, "DELETE", remote_path.as_str())?;
+        Ok(())
+    }
+
+    async fn data_exists(&self, file_info: &FileInfo) -> RustusResult<bool> {
+        if Some(file_info.offset) != file_info.length {
+            return self.local_storage.data_exists(file_info).await;
+        }
+        let remote_path = self.remote_path(file_info);
+        let response = self
+            .request(Method::HEAD, remote_path.as_str())
+            .send()
+            .await?;
+        match check_response(response, "HEAD", remote_path.as_str()) {
+            Ok(_) => Ok(true),
+            Err(RustusError::FileNotFound) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn stored_size(&self, file_info: &FileInfo) -> RustusResult<Option<usize>> {
+        if Some(file_info.offset) != file_info.length {
+            return self.local_storage.stored_size(file_info).await;
+        }
+        let remote_path = self.remote_path(file_info);
+        let response = self
+            .request(Method::HEAD, remote_path.as_str())
+            .send()
+            .await?;
+        let response = check_response(response, "HEAD", remote_path.as_str())?;
+        Ok(response
+            .headers()
+            .get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WebDavStorage;
+    use crate::{info_storages::FileInfo, Storage};
+    use actix_web::test::TestRequest;
+    use bytes::Bytes;
+    use httptest::{
+        matchers::{all_of, contains, request},
+        responders::status_code,
+        Expectation, Server,
+    };
+    use std::path::PathBuf;
+
+    fn get_storage(server: &Server, data_dir: PathBuf) -> WebDavStorage {
+        WebDavStorage::new(
+            server.url_str("/dav/").as_str(),
+            Some(String::from("user")),
+            Some(String::from("pass")),
+            data_dir,
+            String::from("uploads"),
+            false,
+        )
+    }
+
+    async fn create_upload(storage: &WebDavStorage, length: usize) -> FileInfo {
+        let mut file_info = FileInfo::new_test();
+        file_info.length = Some(length);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        file_info
+    }
+
+    #[actix_rt::test]
+    async fn prepare() {
+        let dir = tempdir::TempDir::new("webdav_storage").unwrap();
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("PROPFIND", "/dav/"),
+                request::headers(contains(("depth", "0"))),
+                // "user:pass" encoded with base64.
+                request::headers(contains(("authorization", "Basic dXNlcjpwYXNz"))),
+            ])
+            .respond_with(status_code(207)),
+        );
+        server
+            .expect(Expectation::matching(request::method("MKCOL")).respond_with(status_code(201)));
+        server.expect(
+            Expectation::matching(request::method("DELETE")).respond_with(status_code(204)),
+        );
+        let mut storage = get_storage(&server, dir.into_path());
+        storage.prepare().await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn prepare_unavailable() {
+        let dir = tempdir::TempDir::new("webdav_storage").unwrap();
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("PROPFIND", "/dav/"))
+                .respond_with(status_code(401)),
+        );
+        let mut storage = get_storage(&server, dir.into_path());
+        assert!(storage.prepare().await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn upload_on_finish() {
+        let dir = tempdir::TempDir::new("webdav_storage").unwrap();
+        let server = Server::run();
+        let storage = get_storage(&server, dir.into_path());
+        let mut file_info = create_upload(&storage, 11).await;
+        let remote = format!("/dav/uploads/{}", file_info.id);
+        server.expect(
+            Expectation::matching(request::method_path("MKCOL", "/dav/uploads/"))
+                .respond_with(status_code(405)),
+        );
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("PUT", remote.clone()),
+                request::body("hello world"),
+            ])
+            .respond_with(status_code(201)),
+        );
+        storage
+            .add_bytes(&file_info, Bytes::from("hello "))
+            .await
+            .unwrap();
+        file_info.offset = 6;
+        storage
+            .add_bytes(&file_info, Bytes::from("world"))
+            .await
+            .unwrap();
+        // Local copy is removed after the upload.
+        assert!(!PathBuf::from(file_info.path.clone().unwrap()).exists());
+    }
+
+    #[actix_rt::test]
+    async fn prefixed_upload() {
+        let dir = tempdir::TempDir::new("webdav_storage").unwrap();
+        let server = Server::run();
+        let storage = get_storage(&server, dir.into_path()).with_prefix(String::from("staging"));
+        let file_info = create_upload(&storage, 5).await;
+        server.expect(
+            Expectation::matching(request::method_path("MKCOL", "/dav/staging/"))
+                .respond_with(status_code(201)),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("MKCOL", "/dav/staging/uploads/"))
+                .respond_with(status_code(201)),
+        );
+        server.expect(
+            Expectation::matching(request::method_path(
+                "PUT",
+                format!("/dav/staging/uploads/{}", file_info.id),
+            ))
+            .respond_with(status_code(201)),
+        );
+        storage
+            .add_bytes(&file_info, Bytes::from("hello"))
+            .await
+            .unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn failed_upload() {
+        let dir = tempdir::TempDir::new("webdav_storage").unwrap();
+        let server = Server::run();
+        let storage = get_storage(&server, dir.into_path());
+        let file_info = create_upload(&storage, 5).await;
+        server.expect(
+            Expectation::matching(request::method("MKCOL"))
+                .times(2)
+                .respond_with(status_code(201)),
+        );
+        server.expect(
+            Expectation::matching(request::method("PUT"))
+                .times(2)
+                .respond_with(httptest::cycle![status_code(507), status_code(201)]),
+        );
+        assert!(storage
+            .add_bytes(&file_info, Bytes::from("hello"))
+            .await
+            .is_err());
+        // Local copy is kept without the last chunk, so it can be sent again.
+        let path = PathBuf::from(file_info.path.clone().unwrap());
+        assert_eq!(std::fs::read(path.as_path()).unwrap().len(), 0);
+        storage
+            .add_bytes(&file_info, Bytes::from("hello"))
+            .await
+            .unwrap();
+        assert!(!path.exists());
+    }
+
+    #[actix_rt::test]
+    async fn finished_contents() {
+        let dir = tempdir::TempDir::new("webdav_storage").unwrap();
+        let server = Server::run();
+        let storage = get_storage(&server, dir.into_path());
+        let mut file_info = create_upload(&storage, 5).await;
+        file_info.offset = 5;
+        server.expect(
+            Expectation::matching(request::method_path(
+                "GET",
+                format!("/dav/uploads/{}", file_info.id),
+            ))
+            .respond_with(status_code(200).body("hello")),
+        );
+        let request = TestRequest::get().to_http_request();
+        let response = storage.get_contents(&file_info, &request).await.unwrap();
+        let body = actix_web::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"hello");
+    }
+
+    #[actix_rt::test]
+    async fn remove_finished() {
+        let dir = tempdir::TempDir::new("webdav_storage").unwrap();
+        let server = Server::run();
+        let storage = get_storage(&server, dir.into_path());
+        let mut file_info = create_upload(&storage, 5).await;
+        file_info.offset = 5;
+        let remote = format!("/dav/uploads/{}", file_info.id);
+        server.expect(
+            Expectation::matching(request::method_path("HEAD", remote.clone()))
+                .times(2)
+                .respond_with(httptest::cycle![status_code(200), status_code(404)]),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("DELETE", remote))
+                .respond_with(status_code(204)),
+        );
+        assert!(storage.data_exists(&file_info).await.unwrap());
+        storage.remove_file(&file_info).await.unwrap();
+        assert!(!storage.data_exists(&file_info).await.unwrap());
+    }
+}
@@ -0,0 +1,390 @@
+use std::{future::Future, pin::Pin, sync::OnceLock};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::HeaderMap,
+    Error,
+};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, TextMapPropagator},
+    trace::{FutureExt, SpanKind, Status, TraceContextExt, Tracer as _},
+    Context,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{self, Sampler, Tracer},
+    Resource,
+};
+
+use crate::{config::TracingOptions, errors::RustusResult};
+
+pub use opentelemetry::KeyValue;
+
+static TRACER: OnceLock<Tracer> = OnceLock::new();
+
+/// Tracer which created the span of the current request.
+///
+/// It's kept in the context, so operations are traced
+/// only inside traced requests.
+#[derive(Clone)]
+struct RequestTracer(Tracer);
+
+/// Parse fraction of sampled traces.
+pub fn parse_ratio(input: &str) -> Result<f64, String> {
+    input
+        .parse::<f64>()
+        .ok()
+        .filter(|ratio| (0.0..=1.0).contains(ratio))
+        .ok_or_else(|| format!("'{input}' is not a number between 0 and 1"))
+}
+
+/// Attribute with a size or an offset.
+pub fn size_attribute(key: &'static str, size: usize) -> KeyValue {
+    KeyValue::new(key, i64::try_from(size).unwrap_or(i64::MAX))
+}
+
+/// Start exporting spans if OTLP endpoint is configured.
+///
+/// Spans are batched and exported by a separate thread.
+pub fn init(options: &TracingOptions) {
+    let Some(endpoint) = &options.otlp_endpoint else {
+        return;
+    };
+    // Exporter adds the path of traces itself.
+    let base_url = endpoint
+        .trim_end_matches('/')
+        .trim_end_matches("/v1/traces");
+    let config = trace::config()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            options.otlp_sampling_ratio,
+        ))))
+        .with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            options.otlp_service_name.clone(),
+        )]));
+    let installed = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(base_url),
+        )
+        .with_trace_config(config)
+        .install_batch(runtime::TokioCurrentThread);
+    match installed {
+        Ok(tracer) => {
+            if TRACER.set(tracer).is_ok() {
+                log::info!("Exporting traces to {endpoint}.");
+            }
+        }
+        Err(err) => log::error!("Cannot export traces: {err}"),
+    }
+}
+
+/// Export remaining spans before the server exits.
+pub fn shutdown() {
+    if TRACER.get().is_some() {
+        global::shutdown_tracer_provider();
+    }
+}
+
+/// Reads `traceparent` of the caller from headers.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .keys()
+            .map(actix_web::http::header::HeaderName::as_str)
+            .collect()
+    }
+}
+
+/// Context with the span of a request.
+///
+/// If the request has `traceparent` header,
+/// the span continues its trace and the sampling
+/// decision of the caller is used.
+fn request_context(tracer: &Tracer, name: String, headers: &HeaderMap) -> Context {
+    let parent = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    let span = tracer
+        .span_builder(name)
+        .with_kind(SpanKind::Server)
+        .start_with_context(tracer, &parent);
+    parent
+        .with_span(span)
+        .with_value(RequestTracer(tracer.clone()))
+}
+
+type ResponseFuture<B> = Pin<Box<dyn Future<Output = Result<ServiceResponse<B>, Error>>>>;
+
+/// Middleware which traces requests.
+pub fn trace_request<S, B>(req: ServiceRequest, srv: &S) -> ResponseFuture<B>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    match TRACER.get() {
+        Some(tracer) => trace_with(tracer, req, srv),
+        None => Box::pin(srv.call(req)),
+    }
+}
+
+/// Trace the request with the given tracer.
+///
+/// Span is named after the method and the matched route,
+/// like `PATCH /files/{file_id}/`.
+///
+/// Resource names can't be used, because actix finds
+/// them by path only, ignoring the method.
+pub fn trace_with<S, B>(tracer: &Tracer, req: ServiceRequest, srv: &S) -> ResponseFuture<B>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    let method = req.method().to_string();
+    let cx = request_context(tracer, method.clone(), req.headers());
+    let span = cx.span();
+    span.set_attribute(KeyValue::new("http.method", method.clone()));
+    span.set_attribute(KeyValue::new("http.target", req.path().to_string()));
+    let fut = srv.call(req).with_context(cx.clone());
+    Box::pin(async move {
+        let result = fut.await;
+        let span = cx.span();
+        match &result {
+            Ok(response) => {
+                let request = response.request();
+                if let Some(pattern) = request.match_pattern() {
+                    span.update_name(format!("{method} {pattern}"));
+                    span.set_attribute(KeyValue::new("http.route", pattern));
+                }
+                // Created uploads are identified by their location.
+                let file_id = request.match_info().get("file_id").or_else(|| {
+                    response
+                        .headers()
+                        .get("Location")
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|location| location.trim_end_matches('/').rsplit('/').next())
+                });
+                if let Some(file_id) = file_id {
+                    span.set_attribute(KeyValue::new("rustus.upload_id", file_id.to_string()));
+                }
+                let status = response.status();
+                span.set_attribute(KeyValue::new(
+                    "http.status_code",
+                    i64::from(status.as_u16()),
+                ));
+                if let Some(offset) = response
+                    .headers()
+                    .get("Upload-Offset")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse::<usize>().ok())
+                {
+                    span.set_attribute(size_attribute("rustus.upload_offset", offset));
+                }
+                if status.is_server_error() {
+                    span.set_status(Status::error(status.to_string()));
+                }
+            }
+            Err(err) => span.set_status(Status::error(err.to_string())),
+        }
+        span.end();
+        result
+    })
+}
+
+/// Trace the operation if it runs inside a traced request.
+///
+/// Failed operations are marked with the error.
+pub async fn traced<T, F>(name: &'static str, attributes: Vec<KeyValue>, fut: F) -> RustusResult<T>
+where
+    F: Future<Output = RustusResult<T>>,
+{
+    let parent = Context::current();
+    let Some(RequestTracer(tracer)) = parent.get::<RequestTracer>() else {
+        return fut.await;
+    };
+    let span = tracer
+        .span_builder(name)
+        .with_kind(SpanKind::Internal)
+        .with_attributes(attributes)
+        .start_with_context(tracer, &parent);
+    let cx = parent.with_span(span);
+    let result = fut.with_context(cx.clone()).await;
+    if let Err(err) = &result {
+        cx.span().set_status(Status::error(err.to_string()));
+    }
+    cx.span().end();
+    result
+}
+
+#[cfg(test)]
+pub mod test {
+    use actix_web::http::header::HeaderMap;
+    use opentelemetry::{trace::TracerProvider as _, Context};
+    use opentelemetry_sdk::{
+        export::trace::SpanData,
+        testing::trace::InMemorySpanExporter,
+        trace::{Tracer, TracerProvider},
+    };
+
+    /// Tracer which keeps finished spans in memory.
+    pub struct TestTracer {
+        pub tracer: Tracer,
+        provider: TracerProvider,
+        exporter: InMemorySpanExporter,
+    }
+
+    impl TestTracer {
+        pub fn new() -> Self {
+            let exporter = InMemorySpanExporter::default();
+            let provider = TracerProvider::builder()
+                .with_simple_exporter(exporter.clone())
+                .build();
+            Self {
+                tracer: provider.tracer("rustus"),
+                provider,
+                exporter,
+            }
+        }
+
+        /// Context of a request with the given headers.
+        pub fn request(&self, name: &str, headers: &HeaderMap) -> Context {
+            super::request_context(&self.tracer, String::from(name), headers)
+        }
+
+        /// Spans which are finished.
+        pub fn finished_spans(&self) -> Vec<SpanData> {
+            self.provider.force_flush();
+            self.exporter.get_finished_spans().unwrap()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{test::TestTracer, trace_with, traced, KeyValue};
+    use crate::{errors::RustusError, metrics::RustusMetrics, server::rustus_service, State};
+    use actix_web::{
+        http::{
+            header::{HeaderMap, HeaderName, HeaderValue},
+            Method,
+        },
+        test::{call_service, init_service, TestRequest},
+        web, App,
+    };
+    use opentelemetry::{
+        trace::{FutureExt, SpanId, SpanKind, Status, TraceContextExt, TraceId},
+        Context,
+    };
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[actix_rt::test]
+    async fn traced_request() {
+        let state = State::test_new().await;
+        let file_info = state.create_test_file().await;
+        let tracer = TestTracer::new();
+        let service_tracer = tracer.tracer.clone();
+        let mut rustus = init_service(
+            App::new()
+                .app_data(web::Data::new(RustusMetrics::new(&state.config).unwrap()))
+                .configure(rustus_service(state.clone()))
+                .wrap_fn(move |req, srv| trace_with(&service_tracer, req, srv)),
+        )
+        .await;
+        let request = TestRequest::with_uri(state.config.file_url(file_info.id.as_str()).as_str())
+            .method(Method::HEAD)
+            .insert_header(("traceparent", TRACEPARENT))
+            .to_request();
+        call_service(&mut rustus, request).await;
+        let spans = tracer.finished_spans();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(span.name, "HEAD /files/{file_id}/");
+        assert_eq!(span.span_kind, SpanKind::Server);
+        assert_eq!(
+            span.span_context.trace_id(),
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+        );
+        assert_eq!(
+            span.parent_span_id,
+            SpanId::from_hex("00f067aa0ba902b7").unwrap()
+        );
+        assert!(span
+            .attributes
+            .contains(&KeyValue::new("rustus.upload_id", file_info.id)));
+        assert!(span
+            .attributes
+            .contains(&KeyValue::new("http.status_code", 200_i64)));
+        assert!(span
+            .attributes
+            .contains(&KeyValue::new("rustus.upload_offset", 0_i64)));
+        assert_eq!(span.status, Status::Unset);
+    }
+
+    #[actix_rt::test]
+    async fn nested_spans() {
+        let tracer = TestTracer::new();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("traceparent"),
+            HeaderValue::from_static(TRACEPARENT),
+        );
+        let cx = tracer.request("request", &headers);
+        let result = traced(
+            "storage",
+            vec![KeyValue::new("rustus.upload_id", "memes")],
+            async { Err::<(), _>(RustusError::FileNotFound) },
+        )
+        .with_context(cx.clone())
+        .await;
+        assert!(result.is_err());
+        cx.span().end();
+        let spans = tracer.finished_spans();
+        let (storage, request) = (&spans[0], &spans[1]);
+        assert_eq!(storage.name, "storage");
+        assert_eq!(storage.span_kind, SpanKind::Internal);
+        assert_eq!(
+            storage.span_context.trace_id(),
+            request.span_context.trace_id()
+        );
+        assert_eq!(storage.parent_span_id, request.span_context.span_id());
+        assert_eq!(storage.status, Status::error("Not found"));
+        assert!(storage
+            .attributes
+            .contains(&KeyValue::new("rustus.upload_id", "memes")));
+
+        // Operations outside of requests aren't traced.
+        traced("storage", vec![], async { Ok(()) })
+            .with_context(Context::new())
+            .await
+            .unwrap();
+        assert_eq!(tracer.finished_spans().len(), 2);
+    }
+
+    #[actix_rt::test]
+    async fn unsampled_spans() {
+        let tracer = TestTracer::new();
+        let mut headers = HeaderMap::new();
+        // Caller decided not to sample the trace.
+        headers.insert(
+            HeaderName::from_static("traceparent"),
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00"),
+        );
+        let cx = tracer.request("request", &headers);
+        traced("storage", vec![], async { Ok(()) })
+            .with_context(cx.clone())
+            .await
+            .unwrap();
+        cx.span().end();
+        assert!(tracer.finished_spans().is_empty());
+    }
+}
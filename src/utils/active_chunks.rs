@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+type Counts = Arc<Mutex<HashMap<String, usize>>>;
+
+/// Number of chunks which are being written to every upload.
+///
+/// It's shared between all workers.
+#[derive(Clone, Default)]
+pub struct ActiveChunks {
+    counts: Counts,
+}
+
+impl ActiveChunks {
+    /// Register new chunk of the upload.
+    ///
+    /// Returns `None` if `limit` chunks
+    /// are already being written to this upload.
+    /// The chunk is unregistered when the guard is dropped.
+    pub fn acquire(&self, file_id: &str, limit: usize) -> Option<ChunkGuard> {
+        let mut counts = self
+            .counts
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let count = counts.entry(String::from(file_id)).or_default();
+        if *count >= limit {
+            if *count == 0 {
+                counts.remove(file_id);
+            }
+            return None;
+        }
+        *count += 1;
+        Some(ChunkGuard {
+            counts: self.counts.clone(),
+            file_id: String::from(file_id),
+        })
+    }
+
+    /// Get exclusive access to the upload.
+    ///
+    /// Returns `None` if any chunk is being written.
+    /// While the guard is alive, no chunks can be registered.
+    pub fn acquire_exclusive(&self, file_id: &str) -> Option<ChunkGuard> {
+        let mut counts = self
+            .counts
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if counts.contains_key(file_id) {
+            return None;
+        }
+        counts.insert(String::from(file_id), usize::MAX);
+        Some(ChunkGuard {
+            counts: self.counts.clone(),
+            file_id: String::from(file_id),
+        })
+    }
+
+    /// Number of chunks which are being written to the upload.
+    pub fn count(&self, file_id: &str) -> usize {
+        self.counts
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(file_id)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Chunk which is being written.
+pub struct ChunkGuard {
+    counts: Counts,
+    file_id: String,
+}
+
+impl Drop for ChunkGuard {
+    fn drop(&mut self) {
+        let mut counts = self
+            .counts
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if let Some(count) = counts.get_mut(self.file_id.as_str()) {
+            // Exclusive guard holds all slots.
+            *count = if *count == usize::MAX { 0 } else { *count - 1 };
+            if *count == 0 {
+                counts.remove(self.file_id.as_str());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ActiveChunks;
+
+    #[test]
+    fn limit() {
+        let chunks = ActiveChunks::default();
+        let first = chunks.acquire("upload", 2).unwrap();
+        let second = chunks.acquire("upload", 2).unwrap();
+        assert!(chunks.acquire("upload", 2).is_none());
+        // Other uploads aren't affected.
+        assert!(chunks.acquire("other", 2).is_some());
+        drop(first);
+        assert_eq!(chunks.count("upload"), 1);
+        assert!(chunks.acquire("upload", 2).is_some());
+        drop(second);
+    }
+
+    #[test]
+    fn exclusive() {
+        let chunks = ActiveChunks::default();
+        let chunk = chunks.acquire("upload", 2).unwrap();
+        assert!(chunks.acquire_exclusive("upload").is_none());
+        drop(chunk);
+        let exclusive = chunks.acquire_exclusive("upload").unwrap();
+        assert!(chunks.acquire("upload", usize::MAX).is_none());
+        assert!(chunks.acquire_exclusive("upload").is_none());
+        drop(exclusive);
+        assert_eq!(chunks.count("upload"), 0);
+        assert!(chunks.acquire("upload", 1).is_some());
+    }
+
+    #[test]
+    fn released() {
+        let chunks = ActiveChunks::default();
+        drop(chunks.acquire("upload", 1).unwrap());
+        assert_eq!(chunks.count("upload"), 0);
+        assert!(chunks.counts.lock().unwrap().is_empty());
+        assert!(chunks.acquire("upload", 0).is_none());
+        assert!(chunks.counts.lock().unwrap().is_empty());
+    }
+}
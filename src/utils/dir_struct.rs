@@ -1,11 +1,10 @@
+use std::path::{Path, PathBuf};
+
 use chrono::{Datelike, Timelike};
 
-/// Generate directory name with user template.
-pub fn substr_now(dir_structure: &str) -> String {
-    let now = chrono::Utc::now();
-    substr_time(dir_structure, now)
-}
+use crate::info_storages::FileInfo;
 
+/// Generate directory name with user template.
 pub fn substr_time(dir_structure: &str, time: chrono::DateTime<chrono::Utc>) -> String {
     dir_structure
         .replace("{day}", time.day().to_string().as_str())
@@ -15,21 +14,179 @@ pub fn substr_time(dir_structure: &str, time: chrono::DateTime<chrono::Utc>) ->
         .replace("{minute}", time.minute().to_string().as_str())
 }
 
+/// Generate path of an upload with user template.
+///
+/// Besides time variables, template can use
+/// `{id}` and metadata values, like `{meta:filename}`.
+/// Metadata values may contain only letters, digits, `-`, `_` and `.`,
+/// and can't start with a dot, so they can't leave the directory
+/// or name hidden files.
+///
+/// # Errors
+///
+/// Returns the reason if the path can't be generated.
+pub fn substr_path(template: &str, file_info: &FileInfo) -> Result<String, String> {
+    let mut path = String::new();
+    let mut rest =
+        substr_time(template, file_info.created_at).replace("{id}", file_info.id.as_str());
+    while let Some(start) = rest.find("{meta:") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let key = &rest[start + "{meta:".len()..start + len];
+        let Some(value) = file_info.metadata.get(key) else {
+            return Err(format!("Metadata key {key} is required."));
+        };
+        if !is_safe_value(value) {
+            return Err(format!(
+                "Value of metadata key {key} can't be used in path."
+            ));
+        }
+        path.push_str(&rest[..start]);
+        path.push_str(value);
+        rest = String::from(&rest[start + len + 1..]);
+    }
+    path.push_str(rest.as_str());
+    let components = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+    if !components
+        .iter()
+        .all(|component| is_safe_component(component))
+    {
+        return Err(format!("Path {path} is invalid."));
+    }
+    Ok(components.join("/"))
+}
+
+/// Parse prefix of storage paths and keys.
+///
+/// Prefix is a single path component,
+/// so it's safe for all storages.
+pub fn parse_prefix(input: &str) -> Result<String, String> {
+    if input.is_empty()
+        || input.len() > 32
+        || !input
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "'{input}' is not a valid prefix. Prefix may contain only up to 32 letters, digits, '-' and '_'."
+        ));
+    }
+    Ok(String::from(input))
+}
+
+fn is_safe_value(value: &str) -> bool {
+    value.len() <= 255
+        && !value.starts_with('.')
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && is_safe_component(value)
+}
+
+fn is_safe_component(component: &str) -> bool {
+    !component.is_empty()
+        && component != "."
+        && component != ".."
+        && !component.contains(['/', '\\', '\0'])
+}
+
+/// Get absolute path of the directory.
+///
+/// Relative paths are resolved from the current directory,
+/// even if the directory doesn't exist.
+pub fn absolute_path(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| std::env::current_dir().map(|current| current.join(path)))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Check that files can be created and removed in the directory.
+///
+/// Probe file is removed right after it's written.
+pub fn check_writable(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(format!(".rustus-probe-{}", uuid::Uuid::new_v4()));
+    std::fs::write(probe.as_path(), b"probe")?;
+    std::fs::remove_file(probe)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::substr_now;
+    use super::{parse_prefix, substr_path, substr_time};
+    use crate::info_storages::FileInfo;
     use chrono::Datelike;
 
     #[test]
     pub fn test_time() {
         let now = chrono::Utc::now();
-        let dir = substr_now("{day}/{month}");
+        let dir = substr_time("{day}/{month}", now);
         assert_eq!(dir, format!("{}/{}", now.day(), now.month()));
     }
 
     #[test]
     pub fn test_unknown_var() {
-        let dir = substr_now("test/{quake}");
+        let dir = substr_time("test/{quake}", chrono::Utc::now());
         assert_eq!(dir, String::from("test/{quake}"));
     }
+
+    #[test]
+    pub fn test_path() {
+        let mut file_info = FileInfo::new_test();
+        file_info
+            .metadata
+            .insert(String::from("category"), String::from("memes"));
+        file_info
+            .metadata
+            .insert(String::from("filename"), String::from("cat.png"));
+        let path = substr_path("/{meta:category}/{id}-{meta:filename}", &file_info).unwrap();
+        assert_eq!(path, format!("memes/{}-cat.png", file_info.id));
+    }
+
+    #[test]
+    pub fn test_path_missing_key() {
+        let file_info = FileInfo::new_test();
+        assert!(substr_path("{meta:filename}", &file_info).is_err());
+    }
+
+    #[test]
+    pub fn test_path_traversal() {
+        let mut file_info = FileInfo::new_test();
+        for value in [
+            "..",
+            ".",
+            "...",
+            ".info",
+            "../../etc/passwd",
+            "a\\b",
+            "a b",
+            "a\nb",
+            "",
+        ] {
+            file_info
+                .metadata
+                .insert(String::from("filename"), String::from(value));
+            assert!(
+                substr_path("files/{meta:filename}", &file_info).is_err(),
+                "{value}"
+            );
+        }
+        file_info
+            .metadata
+            .insert(String::from("filename"), String::from("report-2.final.pdf"));
+        assert_eq!(
+            substr_path("files/{meta:filename}", &file_info).unwrap(),
+            "files/report-2.final.pdf"
+        );
+        // Template itself is checked as well.
+        assert!(substr_path("../{id}", &file_info).is_err());
+        assert!(substr_path("files/", &file_info).is_err());
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(parse_prefix("staging-1").unwrap(), "staging-1");
+        assert!(parse_prefix("").is_err());
+        assert!(parse_prefix("prod/../staging").is_err());
+        assert!(parse_prefix("prod:").is_err());
+    }
 }
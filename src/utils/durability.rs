@@ -0,0 +1,125 @@
+use std::{fs::File, path::Path};
+
+use log::error;
+
+use crate::{errors::RustusError, info_storages::FileInfo, RustusResult, State};
+
+/// Flush file and its directory entry to disk.
+///
+/// Directory is synced as well, otherwise
+/// a newly created file can disappear after a power loss.
+pub fn sync_file(path: &Path) -> std::io::Result<()> {
+    File::open(path)?.sync_all()?;
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Make finished upload durable.
+///
+/// It's called before hooks are sent and
+/// the response is returned, so clients and hooks
+/// never see uploads which can be lost.
+pub async fn sync_finished(state: &State, file_info: &FileInfo) -> RustusResult<()> {
+    let synced = async {
+        state.data_storage.sync_data(file_info).await?;
+        state.info_storage.sync_info(file_info).await
+    };
+    // Every finished upload goes through here.
+    #[cfg(feature = "otlp_tracing")]
+    let synced = crate::telemetry::traced(
+        "upload.finish",
+        vec![
+            crate::telemetry::KeyValue::new("rustus.upload_id", file_info.id.clone()),
+            crate::telemetry::size_attribute("rustus.upload_length", file_info.offset),
+        ],
+        synced,
+    );
+    synced.await
+}
+
+/// Check that size of stored data matches the length of the upload.
+///
+/// It's done only with `--verify-finished-size`,
+/// sizes of storages which can't get them aren't checked.
+///
+/// # Errors
+///
+/// Returns `SizeMismatch` if the size differs from the length.
+pub async fn verify_size(state: &State, file_info: &FileInfo) -> RustusResult<()> {
+    if !state.config.verify_finished_size {
+        return Ok(());
+    }
+    let Some(stored) = state.data_storage.stored_size(file_info).await? else {
+        return Ok(());
+    };
+    let length = file_info.length.unwrap_or(file_info.offset);
+    if stored == length {
+        return Ok(());
+    }
+    let message = format!("{stored} bytes are stored instead of {length}");
+    error!("Upload {} isn't finished: {}.", file_info.id, message);
+    Err(RustusError::SizeMismatch(file_info.id.clone(), message))
+}
+
+/// Finish truncation of the upload.
+///
+/// Offset of the truncated upload is saved before its data is truncated.
+/// If truncation was interrupted, data after the offset is removed here,
+/// so chunks are never written after stale bytes.
+///
+/// # Errors
+///
+/// Returns an error if data can't be truncated or the upload can't be saved.
+pub async fn finish_truncation(state: &State, file_info: &mut FileInfo) -> RustusResult<()> {
+    if !file_info.truncating {
+        return Ok(());
+    }
+    state.data_storage.truncate(file_info).await?;
+    file_info.truncating = false;
+    state.info_storage.save_info(file_info).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sync_file, sync_finished, verify_size};
+    use crate::{errors::RustusError, State};
+    use bytes::Bytes;
+
+    #[test]
+    fn missing_file() {
+        let dir = tempdir::TempDir::new("durability").unwrap();
+        assert!(sync_file(dir.path().join("unknown").as_path()).is_err());
+    }
+
+    #[actix_rt::test]
+    async fn finished_upload() {
+        let state = State::test_new().await;
+        let mut file_info = state.create_test_file().await;
+        sync_finished(&state, &file_info).await.unwrap();
+        // Upload without data can't be synced.
+        file_info.path = None;
+        assert!(sync_finished(&state, &file_info).await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn stored_size() {
+        let mut state = State::test_new().await;
+        let mut file_info = state.create_test_file().await;
+        file_info.length = Some(5);
+        file_info.offset = 5;
+        // Size isn't checked by default.
+        verify_size(&state, &file_info).await.unwrap();
+        state.config.verify_finished_size = true;
+        let err = verify_size(&state, &file_info).await.unwrap_err();
+        assert!(matches!(err, RustusError::SizeMismatch(..)));
+        state
+            .data_storage
+            .add_bytes(&file_info, Bytes::from("memes"))
+            .await
+            .unwrap();
+        verify_size(&state, &file_info).await.unwrap();
+    }
+}
@@ -0,0 +1,709 @@
+use std::ops::Range;
+
+use actix_web::HttpRequest;
+use base64::{engine::general_purpose, Engine};
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use hmac::{Hmac, Mac};
+use openssl::{
+    hash::MessageDigest,
+    symm::{Cipher, Crypter, Mode},
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    errors::RustusError,
+    info_storages::FileInfo,
+    storages::{DataStream, Storage},
+    RustusResult,
+};
+
+/// Header with the passphrase of an encrypted upload.
+pub const PASSPHRASE_HEADER: &str = "Upload-Passphrase";
+
+const SALT_SIZE: usize = 16;
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 8;
+const MAC_SIZE: usize = 32;
+const AES_BLOCK_SIZE: usize = 16;
+
+/// Size of chunks which are authenticated together.
+///
+/// Incomplete last chunk is read again when bytes
+/// are appended to it, so it must be small.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Message which is signed with the key to detect wrong passphrases.
+const KEY_CHECK: &[u8] = b"rustus key check";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Parameters of the key of an encrypted upload.
+///
+/// Key is derived from the passphrase of the client
+/// with PBKDF2, only the salt is stored.
+///
+/// Upload is split into chunks of fixed size.
+/// Every chunk is encrypted with AES-256-CTR and the nonce
+/// derived from its index, so encrypted uploads have
+/// the same size as original ones. Chunks are authenticated
+/// with a chain of HMACs, and only the MAC of whole chunks
+/// and the MAC of all bytes are stored, so parameters
+/// don't grow with the upload.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Encryption {
+    /// Base64 encoded random salt.
+    pub salt: String,
+    /// Number of PBKDF2 iterations.
+    pub iterations: usize,
+    /// Base64 encoded HMAC of a constant message with the key.
+    pub check: String,
+    /// Base64 encoded random prefix of nonces.
+    pub nonce: String,
+    /// Size of authenticated chunks.
+    pub chunk_size: usize,
+    /// Base64 encoded MAC of whole chunks,
+    /// it's empty until the first byte is written.
+    #[serde(default)]
+    pub chain: String,
+    /// Base64 encoded MAC of all written bytes,
+    /// it's empty until the first byte is written.
+    #[serde(default)]
+    pub tag: String,
+}
+
+/// Key of an encrypted upload.
+#[derive(Clone)]
+pub struct UploadKey {
+    key: [u8; KEY_SIZE],
+    cipher: [u8; KEY_SIZE],
+    mac: [u8; KEY_SIZE],
+}
+
+impl Encryption {
+    /// Generate parameters of a new key.
+    ///
+    /// Returns parameters which are stored with the upload
+    /// and the key to encrypt first bytes.
+    pub async fn generate(
+        passphrase: String,
+        iterations: usize,
+    ) -> RustusResult<(Self, UploadKey)> {
+        let mut salt = [0; SALT_SIZE];
+        openssl::rand::rand_bytes(&mut salt).map_err(crypto_error)?;
+        let key = UploadKey::derive(passphrase, salt.to_vec(), iterations).await?;
+        let mut encryption = Self {
+            salt: general_purpose::STANDARD.encode(salt),
+            iterations,
+            check: general_purpose::STANDARD.encode(key.check().finalize().into_bytes()),
+            nonce: String::new(),
+            chunk_size: CHUNK_SIZE,
+            chain: String::new(),
+            tag: String::new(),
+        };
+        encryption.reset()?;
+        Ok((encryption, key))
+    }
+
+    /// Derive the key from the passphrase.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DecryptionFailed` if the passphrase is wrong.
+    pub async fn key(&self, passphrase: String) -> RustusResult<UploadKey> {
+        let salt = general_purpose::STANDARD
+            .decode(self.salt.as_bytes())
+            .map_err(|_| RustusError::DecryptionFailed(String::from("salt is corrupted")))?;
+        let check = general_purpose::STANDARD
+            .decode(self.check.as_bytes())
+            .map_err(|_| RustusError::DecryptionFailed(String::from("key check is corrupted")))?;
+        let key = UploadKey::derive(passphrase, salt, self.iterations).await?;
+        key.check()
+            .verify_slice(check.as_slice())
+            .map_err(|_| RustusError::DecryptionFailed(String::from("wrong passphrase")))?;
+        Ok(key)
+    }
+
+    /// Forget written bytes.
+    ///
+    /// Nonces are derived from indexes of chunks,
+    /// so a new prefix is generated and bytes which are
+    /// written again never reuse the keystream.
+    /// Truncated uploads are started from the beginning,
+    /// since the MAC of remaining bytes can't be computed
+    /// without the key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if random bytes can't be generated.
+    pub fn reset(&mut self) -> RustusResult<()> {
+        let mut nonce = [0; NONCE_SIZE];
+        openssl::rand::rand_bytes(&mut nonce).map_err(crypto_error)?;
+        self.nonce = general_purpose::STANDARD.encode(nonce);
+        self.chain.clear();
+        self.tag.clear();
+        Ok(())
+    }
+
+    /// Encrypt bytes which are appended to the upload.
+    ///
+    /// Incomplete last chunk is read from the storage
+    /// and checked, since its MAC is computed again
+    /// with new bytes.
+    ///
+    /// Returns encrypted bytes and parameters,
+    /// which are saved once bytes are written.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DecryptionFailed` if stored bytes were modified.
+    pub async fn append(
+        &self,
+        storage: &(dyn Storage + Send + Sync + 'static),
+        file_info: &FileInfo,
+        key: &UploadKey,
+        data: &[u8],
+    ) -> RustusResult<(Bytes, Self)> {
+        let chunk_size = self.chunk_size()?;
+        let nonce = self.nonce()?;
+        let offset = file_info.offset;
+        let tail_start = offset - offset % chunk_size;
+        let mut chain = self.chain(key, &nonce)?;
+        let mut pending = BytesMut::new();
+        if offset > 0 {
+            let mut body = storage.read_data(file_info, tail_start..offset).await?;
+            while let Some(bytes) = body.next().await {
+                pending.extend_from_slice(bytes?.as_ref());
+            }
+            self.check_tag(key, &chain, offset, pending.as_ref())?;
+        }
+        let encrypted = key.apply(nonce, chunk_size, offset, data)?;
+        pending.extend_from_slice(encrypted.as_slice());
+        let mut chunks = pending.chunks_exact(chunk_size);
+        for (index, chunk) in (tail_start / chunk_size..).zip(&mut chunks) {
+            chain = key.step(&chain, index, chunk);
+        }
+        let mut next = self.clone();
+        next.chain = general_purpose::STANDARD.encode(chain);
+        next.tag = general_purpose::STANDARD.encode(
+            key.tag(&chain, offset + data.len(), chunks.remainder())
+                .finalize()
+                .into_bytes(),
+        );
+        Ok((Bytes::from(encrypted), next))
+    }
+
+    fn chunk_size(&self) -> RustusResult<usize> {
+        if self.chunk_size == 0 {
+            return Err(RustusError::DecryptionFailed(String::from(
+                "chunk size is corrupted",
+            )));
+        }
+        Ok(self.chunk_size)
+    }
+
+    fn nonce(&self) -> RustusResult<[u8; NONCE_SIZE]> {
+        general_purpose::STANDARD
+            .decode(self.nonce.as_bytes())
+            .ok()
+            .and_then(|nonce| nonce.try_into().ok())
+            .ok_or_else(|| RustusError::DecryptionFailed(String::from("nonce is corrupted")))
+    }
+
+    /// Get the MAC of whole chunks.
+    fn chain(&self, key: &UploadKey, nonce: &[u8]) -> RustusResult<[u8; MAC_SIZE]> {
+        if self.chain.is_empty() {
+            return Ok(key.initial(nonce));
+        }
+        general_purpose::STANDARD
+            .decode(self.chain.as_bytes())
+            .ok()
+            .and_then(|chain| chain.try_into().ok())
+            .ok_or_else(|| RustusError::DecryptionFailed(String::from("MAC is corrupted")))
+    }
+
+    /// Check the MAC of all bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DecryptionFailed` if bytes were modified.
+    fn check_tag(
+        &self,
+        key: &UploadKey,
+        chain: &[u8; MAC_SIZE],
+        length: usize,
+        tail: &[u8],
+    ) -> RustusResult<()> {
+        let modified = || RustusError::DecryptionFailed(String::from("data was modified"));
+        let tag = general_purpose::STANDARD
+            .decode(self.tag.as_bytes())
+            .map_err(|_| modified())?;
+        key.tag(chain, length, tail)
+            .verify_slice(tag.as_slice())
+            .map_err(|_| modified())
+    }
+}
+
+impl UploadKey {
+    async fn derive(passphrase: String, salt: Vec<u8>, iterations: usize) -> RustusResult<Self> {
+        // Derivation is slow on purpose, so it doesn't block workers.
+        tokio::task::spawn_blocking(move || {
+            let mut key = [0; KEY_SIZE];
+            openssl::pkcs5::pbkdf2_hmac(
+                passphrase.as_bytes(),
+                salt.as_slice(),
+                iterations,
+                MessageDigest::sha256(),
+                &mut key,
+            )
+            .map_err(crypto_error)?;
+            // Separate keys are used for encryption and authentication.
+            let subkey = |purpose: &[u8]| {
+                let mut mac = HmacSha256::new_from_slice(&key).unwrap();
+                mac.update(purpose);
+                <[u8; KEY_SIZE]>::from(mac.finalize().into_bytes())
+            };
+            Ok(Self {
+                key,
+                cipher: subkey(b"rustus encryption"),
+                mac: subkey(b"rustus authentication"),
+            })
+        })
+        .await?
+    }
+
+    fn check(&self) -> HmacSha256 {
+        // HMAC accepts keys of any length.
+        let mut mac = HmacSha256::new_from_slice(&self.key).unwrap();
+        mac.update(KEY_CHECK);
+        mac
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.mac).unwrap()
+    }
+
+    /// MAC of an empty upload.
+    ///
+    /// It depends on the nonce, so bytes written
+    /// before the upload was reset aren't accepted.
+    fn initial(&self, nonce: &[u8]) -> [u8; MAC_SIZE] {
+        let mut mac = self.mac();
+        mac.update(nonce);
+        mac.finalize().into_bytes().into()
+    }
+
+    /// Add a whole chunk to the MAC of chunks.
+    fn step(&self, chain: &[u8; MAC_SIZE], index: usize, chunk: &[u8]) -> [u8; MAC_SIZE] {
+        let mut mac = self.mac();
+        mac.update(chain);
+        mac.update(&(index as u64).to_be_bytes());
+        mac.update(chunk);
+        mac.finalize().into_bytes().into()
+    }
+
+    /// MAC of all bytes.
+    ///
+    /// Length is authenticated, so the upload can't be truncated.
+    fn tag(&self, chain: &[u8; MAC_SIZE], length: usize, tail: &[u8]) -> HmacSha256 {
+        let mut mac = self.mac();
+        mac.update(chain);
+        mac.update(&(length as u64).to_be_bytes());
+        mac.update(tail);
+        mac
+    }
+
+    /// Encrypt or decrypt bytes at the offset of the upload.
+    ///
+    /// Keystream of every chunk starts with the nonce
+    /// made of the prefix and the index of the chunk.
+    fn apply(
+        &self,
+        nonce: [u8; NONCE_SIZE],
+        chunk_size: usize,
+        offset: usize,
+        data: &[u8],
+    ) -> RustusResult<Vec<u8>> {
+        let cipher = Cipher::aes_256_ctr();
+        let mut output = Vec::with_capacity(data.len());
+        let mut position = offset;
+        let mut rest = data;
+        while !rest.is_empty() {
+            let index = u32::try_from(position / chunk_size).map_err(|_| {
+                RustusError::UnableToWrite(String::from("Encrypted upload is too large"))
+            })?;
+            let skip = position % chunk_size;
+            let (data, next) = rest.split_at(rest.len().min(chunk_size - skip));
+            let block = u32::try_from(skip / AES_BLOCK_SIZE).map_err(|_| {
+                RustusError::UnableToWrite(String::from("Chunk of the upload is too large"))
+            })?;
+            let mut iv = [0; 16];
+            iv[..NONCE_SIZE].copy_from_slice(&nonce);
+            iv[NONCE_SIZE..12].copy_from_slice(&index.to_be_bytes());
+            iv[12..].copy_from_slice(&block.to_be_bytes());
+            let mut crypter = Crypter::new(cipher, Mode::Encrypt, &self.cipher, Some(&iv))
+                .map_err(crypto_error)?;
+            // Keystream of the partial block before the offset is skipped.
+            let mut input = vec![0; skip % AES_BLOCK_SIZE];
+            input.extend_from_slice(data);
+            let mut buffer = vec![0; input.len() + cipher.block_size()];
+            let written = crypter
+                .update(input.as_slice(), &mut buffer)
+                .map_err(crypto_error)?;
+            output.extend_from_slice(&buffer[skip % AES_BLOCK_SIZE..written]);
+            position += data.len();
+            rest = next;
+        }
+        Ok(output)
+    }
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn crypto_error(err: openssl::error::ErrorStack) -> RustusError {
+    RustusError::UnableToWrite(format!("Encryption error: {err}"))
+}
+
+/// Get passphrase of the upload from the request.
+pub fn get_passphrase(request: &HttpRequest) -> Option<String> {
+    request
+        .headers()
+        .get(PASSPHRASE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+/// Get the key of the upload from the passphrase in the request.
+///
+/// Returns `None` if the upload isn't encrypted.
+///
+/// # Errors
+///
+/// Returns `DecryptionFailed` if the passphrase is missing or wrong.
+pub async fn request_key(
+    request: &HttpRequest,
+    file_info: &FileInfo,
+) -> RustusResult<Option<UploadKey>> {
+    let Some(encryption) = &file_info.encryption else {
+        return Ok(None);
+    };
+    let passphrase = get_passphrase(request).ok_or_else(|| {
+        RustusError::DecryptionFailed(format!("{PASSPHRASE_HEADER} header is required"))
+    })?;
+    encryption.key(passphrase).await.map(Some)
+}
+
+/// Read bytes of the upload in the range.
+///
+/// Encrypted uploads are decrypted with the key.
+/// All bytes are authenticated together, so the whole upload
+/// is read and checked while requested bytes are streamed.
+/// Last decrypted chunk is held back until the check is done,
+/// so modified data ends the stream with `DecryptionFailed` error
+/// before all requested bytes are sent.
+///
+/// # Errors
+///
+/// Returns `DecryptionFailed` if the upload is encrypted,
+/// but the key isn't given.
+pub async fn read_data(
+    storage: &(dyn Storage + Send + Sync + 'static),
+    file_info: &FileInfo,
+    key: Option<&UploadKey>,
+    range: Range<usize>,
+) -> RustusResult<DataStream> {
+    let Some(encryption) = &file_info.encryption else {
+        return storage.read_data(file_info, range).await;
+    };
+    let Some(key) = key else {
+        return Err(RustusError::DecryptionFailed(format!(
+            "{PASSPHRASE_HEADER} header is required"
+        )));
+    };
+    if range.is_empty() {
+        return Ok(futures::stream::empty().boxed_local());
+    }
+    if range.end > file_info.offset {
+        return Err(RustusError::IncompleteUpload(format!(
+            "bytes {}-{} weren't written",
+            file_info.offset.max(range.start),
+            range.end - 1
+        )));
+    }
+    let nonce = encryption.nonce()?;
+    let decryptor = Decryptor {
+        body: storage.read_data(file_info, 0..file_info.offset).await?,
+        encryption: encryption.clone(),
+        key: key.clone(),
+        chunk_size: encryption.chunk_size()?,
+        chain: key.initial(&nonce),
+        nonce,
+        range,
+        length: file_info.offset,
+        buffer: BytesMut::new(),
+        position: 0,
+        held: None,
+        finished: false,
+    };
+    Ok(futures::stream::unfold(decryptor, Decryptor::next).boxed_local())
+}
+
+/// Stream which decrypts chunks of the upload while they are read.
+struct Decryptor {
+    body: DataStream,
+    encryption: Encryption,
+    key: UploadKey,
+    nonce: [u8; NONCE_SIZE],
+    chunk_size: usize,
+    /// MAC of chunks which were read.
+    chain: [u8; MAC_SIZE],
+    /// Requested bytes.
+    range: Range<usize>,
+    length: usize,
+    /// Encrypted bytes of the current chunk.
+    buffer: BytesMut,
+    /// Offset of the current chunk.
+    position: usize,
+    /// Decrypted bytes which aren't checked yet.
+    held: Option<Bytes>,
+    finished: bool,
+}
+
+impl Decryptor {
+    async fn next(mut self) -> Option<(RustusResult<Bytes>, Self)> {
+        loop {
+            if self.finished {
+                return None;
+            }
+            if self.buffer.len() >= self.chunk_size {
+                let chunk = self.buffer.split_to(self.chunk_size);
+                let index = self.position / self.chunk_size;
+                self.chain = self.key.step(&self.chain, index, chunk.as_ref());
+                match self.decrypt(chunk.as_ref()) {
+                    Ok(Some(ready)) => return Some((Ok(ready), self)),
+                    Ok(None) => continue,
+                    Err(err) => return Some(self.fail(err)),
+                }
+            }
+            match self.body.next().await {
+                Some(Ok(bytes)) => self.buffer.extend_from_slice(bytes.as_ref()),
+                Some(Err(err)) => return Some(self.fail(err)),
+                None => {
+                    self.finished = true;
+                    let tail = self.buffer.split();
+                    let checked = self.encryption.check_tag(
+                        &self.key,
+                        &self.chain,
+                        self.length,
+                        tail.as_ref(),
+                    );
+                    if let Err(err) = checked {
+                        return Some(self.fail(err));
+                    }
+                    let mut rest = BytesMut::new();
+                    match self.decrypt(tail.as_ref()) {
+                        Ok(ready) => {
+                            for bytes in ready.into_iter().chain(self.held.take()) {
+                                rest.extend_from_slice(bytes.as_ref());
+                            }
+                        }
+                        Err(err) => return Some(self.fail(err)),
+                    }
+                    if rest.is_empty() {
+                        return None;
+                    }
+                    return Some((Ok(rest.freeze()), self));
+                }
+            }
+        }
+    }
+
+    /// Decrypt requested bytes of the chunk at the current position.
+    ///
+    /// Returns bytes of the previous chunk, which can be sent,
+    /// since bytes of this chunk are held back instead.
+    fn decrypt(&mut self, chunk: &[u8]) -> RustusResult<Option<Bytes>> {
+        let start = self
+            .range
+            .start
+            .saturating_sub(self.position)
+            .min(chunk.len());
+        let end = self
+            .range
+            .end
+            .saturating_sub(self.position)
+            .min(chunk.len());
+        let offset = self.position;
+        self.position += chunk.len();
+        if start >= end {
+            return Ok(None);
+        }
+        let decrypted = self.key.apply(
+            self.nonce,
+            self.chunk_size,
+            offset + start,
+            &chunk[start..end],
+        )?;
+        Ok(self.held.replace(Bytes::from(decrypted)))
+    }
+
+    fn fail(mut self, err: RustusError) -> (RustusResult<Bytes>, Self) {
+        self.finished = true;
+        self.held = None;
+        (Err(err), self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_data, Encryption, UploadKey};
+    use crate::{
+        errors::RustusError, info_storages::FileInfo, storages::file_storage::FileStorage, Storage,
+    };
+    use bytes::{Bytes, BytesMut};
+    use futures::StreamExt;
+
+    /// Encrypt and write bytes at the end of the upload.
+    async fn append(
+        storage: &FileStorage,
+        file_info: &mut FileInfo,
+        key: &UploadKey,
+        data: &[u8],
+    ) -> Bytes {
+        let encryption = file_info.encryption.clone().unwrap();
+        let (encrypted, next) = encryption
+            .append(storage, file_info, key, data)
+            .await
+            .unwrap();
+        storage
+            .add_bytes(file_info, encrypted.clone())
+            .await
+            .unwrap();
+        file_info.offset += data.len();
+        file_info.encryption = Some(next);
+        encrypted
+    }
+
+    async fn read(
+        storage: &FileStorage,
+        file_info: &FileInfo,
+        key: &UploadKey,
+        range: std::ops::Range<usize>,
+    ) -> Result<Bytes, RustusError> {
+        let mut body = read_data(storage, file_info, Some(key), range).await?;
+        let mut data = BytesMut::new();
+        while let Some(bytes) = body.next().await {
+            data.extend_from_slice(bytes?.as_ref());
+        }
+        Ok(data.freeze())
+    }
+
+    async fn encrypted_upload(dir: &tempdir::TempDir) -> (FileStorage, FileInfo, UploadKey) {
+        let storage = FileStorage::new(dir.path().to_path_buf(), String::new(), false);
+        let mut file_info = FileInfo::new("test_id", None, None, storage.to_string(), None);
+        file_info.path = Some(storage.create_file(&file_info).await.unwrap());
+        let (mut encryption, key) = Encryption::generate(String::from("memes"), 10)
+            .await
+            .unwrap();
+        // Small chunks, so bytes are split between them.
+        encryption.chunk_size = 16;
+        file_info.encryption = Some(encryption);
+        (storage, file_info, key)
+    }
+
+    #[actix_rt::test]
+    async fn encrypt_chunks() {
+        let dir = tempdir::TempDir::new("encryption").unwrap();
+        let (storage, mut file_info, key) = encrypted_upload(&dir).await;
+        let data = b"Encrypted data of the upload, which has a few chunks.";
+        let first = append(&storage, &mut file_info, &key, &data[..5]).await;
+        assert_ne!(first.as_ref(), &data[..5]);
+        let params_len = serde_json::to_string(&file_info.encryption).unwrap().len();
+        append(&storage, &mut file_info, &key, &data[5..20]).await;
+        append(&storage, &mut file_info, &key, &data[20..]).await;
+        // Parameters don't grow with the upload.
+        assert_eq!(
+            serde_json::to_string(&file_info.encryption).unwrap().len(),
+            params_len
+        );
+        let stored = std::fs::read(file_info.path.as_deref().unwrap()).unwrap();
+        assert_eq!(stored.len(), data.len());
+        // Same bytes at other offsets are encrypted differently.
+        assert_ne!(&stored[..16], &stored[16..32]);
+
+        let key = file_info
+            .encryption
+            .as_ref()
+            .unwrap()
+            .key(String::from("memes"))
+            .await
+            .unwrap();
+        let all = read(&storage, &file_info, &key, 0..data.len())
+            .await
+            .unwrap();
+        assert_eq!(all.as_ref(), data.as_slice());
+        let part = read(&storage, &file_info, &key, 3..40).await.unwrap();
+        assert_eq!(part.as_ref(), &data[3..40]);
+        assert!(matches!(
+            read(&storage, &file_info, &key, 0..100).await,
+            Err(RustusError::IncompleteUpload(_))
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn modified_chunk() {
+        let dir = tempdir::TempDir::new("encryption").unwrap();
+        let (storage, mut file_info, key) = encrypted_upload(&dir).await;
+        append(
+            &storage,
+            &mut file_info,
+            &key,
+            b"Encrypted data of the upload",
+        )
+        .await;
+        let path = file_info.path.clone().unwrap();
+        let original = std::fs::read(path.as_str()).unwrap();
+        for position in [3, 20] {
+            let mut modified = original.clone();
+            modified[position] ^= 1;
+            std::fs::write(path.as_str(), modified).unwrap();
+            let result = read(&storage, &file_info, &key, 0..5).await;
+            assert!(matches!(result, Err(RustusError::DecryptionFailed(_))));
+        }
+        // Modified last chunk isn't extended.
+        let encryption = file_info.encryption.clone().unwrap();
+        let result = encryption.append(&storage, &file_info, &key, b"!").await;
+        assert!(matches!(result, Err(RustusError::DecryptionFailed(_))));
+        // Truncated data is rejected.
+        std::fs::write(path.as_str(), &original[..20]).unwrap();
+        let result = read(&storage, &file_info, &key, 0..5).await;
+        assert!(matches!(result, Err(RustusError::DecryptionFailed(_))));
+    }
+
+    #[actix_rt::test]
+    async fn reset() {
+        let dir = tempdir::TempDir::new("encryption").unwrap();
+        let (storage, mut file_info, key) = encrypted_upload(&dir).await;
+        let first = append(&storage, &mut file_info, &key, b"memes").await;
+        let encryption = file_info.encryption.as_mut().unwrap();
+        let nonce = encryption.nonce.clone();
+        encryption.reset().unwrap();
+        assert_ne!(encryption.nonce, nonce);
+        file_info.offset = 0;
+        storage.truncate(&file_info).await.unwrap();
+        // Keystream isn't reused for rewritten bytes.
+        let second = append(&storage, &mut file_info, &key, b"memes").await;
+        assert_ne!(first, second);
+        let data = read(&storage, &file_info, &key, 0..5).await.unwrap();
+        assert_eq!(data.as_ref(), b"memes");
+    }
+
+    #[actix_rt::test]
+    async fn wrong_passphrase() {
+        let (encryption, _) = Encryption::generate(String::from("memes"), 10)
+            .await
+            .unwrap();
+        let result = encryption.key(String::from("not memes")).await;
+        assert!(matches!(result, Err(RustusError::DecryptionFailed(_))));
+    }
+}
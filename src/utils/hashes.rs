@@ -3,29 +3,59 @@ use actix_web::http::header::HeaderValue;
 use base64::Engine;
 use digest::Digest;
 
-/// Checks if hash-sum of a slice matches the given checksum.
-fn checksum_verify(algo: &str, bytes: &[u8], checksum: &[u8]) -> RustusResult<bool> {
-    match algo {
-        "sha1" => {
-            let sum = sha1::Sha1::digest(bytes);
-            Ok(sum.as_slice() == checksum)
-        }
-        "sha256" => {
-            let sum = sha2::Sha256::digest(bytes);
-            Ok(sum.as_slice() == checksum)
+/// Algorithms supported by checksum extension.
+pub const CHECKSUM_ALGORITHMS: [&str; 4] = ["md5", "sha1", "sha256", "sha512"];
+
+/// Incremental hasher for supported algorithms.
+pub enum Hasher {
+    Md5(md5::Md5),
+    Sha1(sha1::Sha1),
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+}
+
+impl Hasher {
+    /// Create hasher by the name of algorithm.
+    ///
+    /// # Errors
+    ///
+    /// It returns error if algorithm isn't supported.
+    pub fn new(algo: &str) -> RustusResult<Self> {
+        match algo {
+            "md5" => Ok(Self::Md5(md5::Md5::new())),
+            "sha1" => Ok(Self::Sha1(sha1::Sha1::new())),
+            "sha256" => Ok(Self::Sha256(sha2::Sha256::new())),
+            "sha512" => Ok(Self::Sha512(sha2::Sha512::new())),
+            _ => Err(RustusError::UnknownHashAlgorithm),
         }
-        "sha512" => {
-            let sum = sha2::Sha512::digest(bytes);
-            Ok(sum.as_slice() == checksum)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Md5(hasher) => hasher.update(bytes),
+            Self::Sha1(hasher) => hasher.update(bytes),
+            Self::Sha256(hasher) => hasher.update(bytes),
+            Self::Sha512(hasher) => hasher.update(bytes),
         }
-        "md5" => {
-            let sum = md5::Md5::digest(bytes);
-            Ok(sum.as_slice() == checksum)
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Md5(hasher) => hasher.finalize().to_vec(),
+            Self::Sha1(hasher) => hasher.finalize().to_vec(),
+            Self::Sha256(hasher) => hasher.finalize().to_vec(),
+            Self::Sha512(hasher) => hasher.finalize().to_vec(),
         }
-        _ => Err(RustusError::UnknownHashAlgorithm),
     }
 }
 
+/// Checks if hash-sum of a slice matches the given checksum.
+fn checksum_verify(algo: &str, bytes: &[u8], checksum: &[u8]) -> RustusResult<bool> {
+    let mut hasher = Hasher::new(algo)?;
+    hasher.update(bytes);
+    Ok(hasher.finalize().as_slice() == checksum)
+}
+
 /// Verify checksum of a given chunk based on header's value.
 ///
 /// This function decodes given header value.
@@ -40,30 +70,32 @@ fn checksum_verify(algo: &str, bytes: &[u8], checksum: &[u8]) -> RustusResult<bo
 ///
 /// It may return error if header value can't be represented as string,
 /// if checksum can't be decoded with base64 or if unknown algorithm is used.
+/// Unknown algorithm is checked first, so it's reported
+/// even if the checksum value is malformed.
 pub fn verify_chunk_checksum(header: &HeaderValue, data: &[u8]) -> RustusResult<bool> {
-    if let Ok(val) = header.to_str() {
-        let mut split = val.split(' ');
-        if let Some(algo) = split.next() {
-            if let Some(checksum_base) = split.next() {
-                let checksum = base64::engine::general_purpose::STANDARD
-                    .decode(checksum_base)
-                    .map_err(|_| {
-                        log::error!("Can't decode checksum value");
-                        RustusError::WrongHeaderValue
-                    })?;
-                return checksum_verify(algo, data, checksum.as_slice());
-            }
-        }
-        Err(RustusError::WrongHeaderValue)
-    } else {
+    let Ok(val) = header.to_str() else {
         log::error!("Can't decode checksum header.");
-        Err(RustusError::WrongHeaderValue)
+        return Err(RustusError::WrongHeaderValue);
+    };
+    let Some((algo, checksum_base)) = val.trim().split_once(' ') else {
+        return Err(RustusError::WrongHeaderValue);
+    };
+    if !CHECKSUM_ALGORITHMS.contains(&algo) {
+        return Err(RustusError::UnknownHashAlgorithm);
     }
+    let checksum = base64::engine::general_purpose::STANDARD
+        .decode(checksum_base.trim())
+        .map_err(|_| {
+            log::error!("Can't decode checksum value");
+            RustusError::WrongHeaderValue
+        })?;
+    checksum_verify(algo, data, checksum.as_slice())
 }
 
 #[cfg(test)]
 mod tests {
     use super::{checksum_verify, verify_chunk_checksum};
+    use crate::errors::RustusError;
     use actix_web::http::header::HeaderValue;
 
     #[test]
@@ -123,6 +155,18 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[test]
+    fn test_unknown_algorithm_with_malformed_value() {
+        let res = verify_chunk_checksum(&HeaderValue::from_str("crc32 !!!").unwrap(), b"hello");
+        assert!(matches!(res, Err(RustusError::UnknownHashAlgorithm)));
+    }
+
+    #[test]
+    fn test_malformed_checksum_value() {
+        let res = verify_chunk_checksum(&HeaderValue::from_str("md5 !!!").unwrap(), b"hello");
+        assert!(matches!(res, Err(RustusError::WrongHeaderValue)));
+    }
+
     #[test]
     fn test_badly_formatted_header() {
         let res = verify_chunk_checksum(&HeaderValue::from_str("md5").unwrap(), b"hello");
@@ -1,6 +1,20 @@
 use std::str::FromStr;
 
-use actix_web::HttpRequest;
+use actix_web::{
+    http::{
+        header::{HeaderMap, HeaderName, HeaderValue},
+        StatusCode,
+    },
+    HttpRequest,
+};
+
+use crate::errors::RustusError;
+
+/// Maximum size of a request head accepted by actix.
+///
+/// Bigger requests are rejected with 431 before
+/// they reach rustus, so this limit can't be raised.
+pub const MAX_HEAD_SIZE: usize = 131_072;
 
 /// Parse header's value.
 ///
@@ -10,8 +24,14 @@ use actix_web::HttpRequest;
 /// If header is not present or value
 /// can't be parsed then it returns None.
 pub fn parse_header<T: FromStr>(request: &HttpRequest, header_name: &str) -> Option<T> {
-    request
-        .headers()
+    parse_header_value(request.headers(), header_name)
+}
+
+/// Parse value of a header from the header map.
+///
+/// It's used if the request isn't routed yet.
+pub fn parse_header_value<T: FromStr>(headers: &HeaderMap, header_name: &str) -> Option<T> {
+    headers
         // Get header
         .get(header_name)
         .and_then(|value|
@@ -42,10 +62,85 @@ pub fn check_header(request: &HttpRequest, header_name: &str, expr: fn(&str) ->
         .unwrap_or(false)
 }
 
+/// Check that request has content type of upload chunks.
+///
+/// If `lenient` is set, `application/octet-stream`
+/// is accepted as well, since some clients send it by mistake.
+pub fn is_chunk_content_type(headers: &HeaderMap, lenient: bool) -> bool {
+    match headers.get("Content-Type").map(HeaderValue::to_str) {
+        Some(Ok("application/offset+octet-stream")) => true,
+        Some(Ok("application/octet-stream")) => lenient,
+        _ => false,
+    }
+}
+
+/// Parse maximum size of request headers.
+///
+/// # Errors
+///
+/// It returns error if the size is zero or
+/// greater than the limit of actix.
+pub fn parse_header_size(input: &str) -> Result<usize, String> {
+    let size = input.parse::<usize>().map_err(|err| err.to_string())?;
+    if size == 0 || size > MAX_HEAD_SIZE {
+        return Err(format!(
+            "Header size must be between 1 and {MAX_HEAD_SIZE} bytes. \
+            Bigger requests are rejected by the HTTP server."
+        ));
+    }
+    Ok(size)
+}
+
+/// Parse status of responses to creation requests.
+///
+/// # Errors
+///
+/// It returns error if the status isn't 200, 201 or 204.
+pub fn parse_creation_status(input: &str) -> Result<StatusCode, String> {
+    match input {
+        "200" => Ok(StatusCode::OK),
+        "201" => Ok(StatusCode::CREATED),
+        "204" => Ok(StatusCode::NO_CONTENT),
+        _ => Err(format!(
+            "'{input}' is not a valid creation status. Only 200, 201 and 204 are allowed."
+        )),
+    }
+}
+
+/// Check that headers of a request fit into the limit.
+///
+/// Size of a header includes its name, value,
+/// colon and line break, as they are sent over HTTP/1.1.
+///
+/// # Errors
+///
+/// It returns `HeadersTooLarge` with the biggest header,
+/// so clients know what must be reduced.
+pub fn check_headers_size(headers: &HeaderMap, limit: usize) -> Result<(), RustusError> {
+    let header_size =
+        |(name, value): (&HeaderName, &HeaderValue)| name.as_str().len() + value.len() + 4;
+    let size: usize = headers.iter().map(header_size).sum();
+    if size <= limit {
+        return Ok(());
+    }
+    let biggest = headers
+        .iter()
+        .max_by_key(|header| header_size(*header))
+        .map(|(name, _)| name.to_string())
+        .unwrap_or_default();
+    Err(RustusError::HeadersTooLarge(format!(
+        "Request headers are {size} bytes, but at most {limit} bytes are allowed. \
+        The biggest header is {biggest}."
+    )))
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{check_header, parse_header};
-    use actix_web::test::TestRequest;
+    use super::{
+        check_header, check_headers_size, is_chunk_content_type, parse_creation_status,
+        parse_header, parse_header_size,
+    };
+    use actix_web::{http::StatusCode, test::TestRequest};
 
     #[actix_rt::test]
     async fn test_parse_header_unknown_header() {
@@ -89,4 +184,54 @@ mod tests {
         let check = check_header(&request, "test_header", |value| value == "2");
         assert!(!check);
     }
+
+    #[test]
+    fn header_sizes() {
+        assert_eq!(parse_header_size("1024"), Ok(1024));
+        assert!(parse_header_size("0").is_err());
+        assert!(parse_header_size("262144").is_err());
+    }
+
+    #[test]
+    fn creation_status() {
+        assert_eq!(parse_creation_status("204"), Ok(StatusCode::NO_CONTENT));
+        assert!(parse_creation_status("202").is_err());
+        assert!(parse_creation_status("created").is_err());
+        let request = TestRequest::get()
+            .insert_header(("Upload-Length", "10"))
+            .insert_header(("Upload-Metadata", "a".repeat(100)))
+            .to_http_request();
+        assert!(check_headers_size(request.headers(), 200).is_ok());
+        let err = check_headers_size(request.headers(), 100).unwrap_err();
+        assert!(err.to_string().contains("upload-metadata"), "{err}");
+    }
+
+    #[actix_rt::test]
+    async fn chunk_content_type() {
+        let request = |content_type: &str| {
+            TestRequest::patch()
+                .insert_header(("Content-Type", content_type))
+                .to_http_request()
+        };
+        assert!(is_chunk_content_type(
+            request("application/offset+octet-stream").headers(),
+            false
+        ));
+        assert!(!is_chunk_content_type(
+            request("application/octet-stream").headers(),
+            false
+        ));
+        assert!(is_chunk_content_type(
+            request("application/octet-stream").headers(),
+            true
+        ));
+        assert!(!is_chunk_content_type(
+            request("application/json").headers(),
+            true
+        ));
+        assert!(!is_chunk_content_type(
+            TestRequest::patch().to_http_request().headers(),
+            true
+        ));
+    }
 }
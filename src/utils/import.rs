@@ -0,0 +1,185 @@
+use std::{
+    fs::{File, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+/// File which is imported into an upload.
+///
+/// The file is opened once when its path is checked
+/// and data is read only from the opened file,
+/// so it can't be replaced after the check.
+#[derive(Debug)]
+pub struct ImportSource {
+    /// Canonical path of the file.
+    pub path: PathBuf,
+    /// The opened file.
+    pub file: File,
+}
+
+impl ImportSource {
+    /// Open a regular file.
+    ///
+    /// Symlinks aren't followed, so the path
+    /// must be canonical.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be opened or isn't regular.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let mut options = OpenOptions::new();
+        options.read(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, libc::O_NOFOLLOW);
+        let file = options.open(path)?;
+        if !file.metadata()?.is_file() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Source is not a file",
+            ));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    /// Clone the opened file with its path.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file descriptor can't be duplicated.
+    pub fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(Self {
+            path: self.path.clone(),
+            file: self.file.try_clone()?,
+        })
+    }
+
+    /// Check that the path points to the opened file.
+    ///
+    /// It's used to check hard links, which are created by path.
+    /// Without inodes files can't be compared, so they're never the same.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if metadata can't be read.
+    pub fn is_same_file(&self, path: &Path) -> std::io::Result<bool> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let opened = self.file.metadata()?;
+            let other = std::fs::symlink_metadata(path)?;
+            Ok(opened.dev() == other.dev() && opened.ino() == other.ino())
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = path;
+            Ok(false)
+        }
+    }
+}
+
+/// Resolve and open a file which is imported.
+///
+/// Relative paths are resolved in import directories.
+/// Symlinks and `..` are resolved before the path
+/// is checked, so files outside of import directories
+/// can't be reached. The opened file is checked to be
+/// the one which was resolved.
+///
+/// Returns `None` if the file isn't a regular file in any of `dirs`.
+/// Missing files aren't distinguished from forbidden ones,
+/// so the check can't tell which files exist.
+pub fn resolve_source(dirs: &[PathBuf], path: &Path) -> Option<ImportSource> {
+    dirs.iter()
+        .filter_map(|dir| dir.canonicalize().ok())
+        .find_map(|dir| {
+            let source = dir.join(path).canonicalize().ok()?;
+            if !source.starts_with(dir.as_path()) {
+                return None;
+            }
+            let source = ImportSource::open(source.as_path()).ok()?;
+            // The path could be changed before the file was opened.
+            let unchanged = source.path.canonicalize().ok()? == source.path
+                && source.is_same_file(source.path.as_path()).unwrap_or(false);
+            unchanged.then_some(source)
+        })
+}
+
+/// Check that token from `Authorization` header is valid.
+///
+/// Tokens are compared by their hashes,
+/// so the comparison takes the same time.
+pub fn check_token(expected: &str, header: Option<&str>) -> bool {
+    let Some(token) = header.and_then(|header| header.strip_prefix("Bearer ")) else {
+        return false;
+    };
+    Sha256::digest(token.trim().as_bytes()) == Sha256::digest(expected.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_token, resolve_source, ImportSource};
+    use std::path::Path;
+
+    #[test]
+    fn sources() {
+        let dir = tempdir::TempDir::new("import").unwrap();
+        let allowed = dir.path().join("allowed");
+        std::fs::create_dir(allowed.as_path()).unwrap();
+        std::fs::write(allowed.join("file"), "memes").unwrap();
+        std::fs::write(dir.path().join("secret"), "memes").unwrap();
+        let dirs = vec![allowed.clone()];
+        let source = resolve_source(&dirs, allowed.join("file").as_path()).unwrap();
+        assert_eq!(source.path, allowed.join("file").canonicalize().unwrap());
+        // Relative paths are resolved in import directories.
+        let source = resolve_source(&dirs, Path::new("file")).unwrap();
+        assert_eq!(source.path, allowed.join("file").canonicalize().unwrap());
+        // Traversal, directories and missing files are rejected.
+        let traversal = allowed.join("../secret");
+        assert!(resolve_source(&dirs, traversal.as_path()).is_none());
+        assert!(resolve_source(&dirs, Path::new("../secret")).is_none());
+        assert!(resolve_source(&dirs, allowed.as_path()).is_none());
+        assert!(resolve_source(&dirs, allowed.join("unknown").as_path()).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        let dir = tempdir::TempDir::new("import").unwrap();
+        let allowed = dir.path().join("allowed");
+        std::fs::create_dir(allowed.as_path()).unwrap();
+        std::fs::write(dir.path().join("secret"), "memes").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("secret"), allowed.join("link")).unwrap();
+        let dirs = vec![allowed.clone()];
+        assert!(resolve_source(&dirs, allowed.join("link").as_path()).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn same_files() {
+        let dir = tempdir::TempDir::new("import").unwrap();
+        std::fs::write(dir.path().join("file"), "memes").unwrap();
+        std::fs::write(dir.path().join("other"), "memes").unwrap();
+        let source = ImportSource::open(dir.path().join("file").as_path()).unwrap();
+        std::fs::hard_link(dir.path().join("file"), dir.path().join("link")).unwrap();
+        assert!(source
+            .is_same_file(dir.path().join("link").as_path())
+            .unwrap());
+        // File replaced after it was opened isn't the same.
+        std::fs::rename(dir.path().join("other"), dir.path().join("file")).unwrap();
+        assert!(!source
+            .is_same_file(dir.path().join("file").as_path())
+            .unwrap());
+    }
+
+    #[test]
+    fn tokens() {
+        assert!(check_token("token", Some("Bearer token")));
+        assert!(!check_token("token", Some("Bearer other")));
+        assert!(!check_token("token", Some("token")));
+        assert!(!check_token("token", None));
+    }
+}
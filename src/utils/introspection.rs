@@ -0,0 +1,453 @@
+use std::{
+    collections::HashMap,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::{header::AUTHORIZATION, Method},
+    web, HttpMessage,
+};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    utils::signature::{self, DownloadSignature},
+    RustusConf,
+};
+
+/// Timeout of requests to the introspection endpoint.
+const INTROSPECTION_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of cached tokens after which expired ones are removed.
+const CACHE_PRUNE_SIZE: usize = 10_000;
+
+/// Information about the client from the introspected token.
+///
+/// It's stored in extensions of authorized requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenInfo {
+    pub subject: Option<String>,
+    pub scopes: Vec<String>,
+}
+
+/// Response of the introspection endpoint.
+///
+/// Only fields used by rustus are parsed, see RFC 7662.
+#[derive(Deserialize)]
+struct IntrospectionResponse {
+    active: bool,
+    sub: Option<String>,
+    username: Option<String>,
+    scope: Option<String>,
+    exp: Option<i64>,
+}
+
+/// Result of the introspection.
+///
+/// Inactive tokens are cached too,
+/// so invalid tokens don't flood the endpoint.
+#[derive(Clone)]
+struct CachedToken {
+    token: Option<TokenInfo>,
+    expires_at: Instant,
+}
+
+/// Client of `OAuth2` token introspection endpoint.
+///
+/// Bearer tokens are sent to the introspection endpoint
+/// and results are cached for a short time.
+/// Preflight requests and signed downloads don't need tokens.
+#[derive(Clone)]
+pub struct Introspection {
+    client: reqwest::Client,
+    url: String,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    required_scopes: Vec<String>,
+    cache_ttl: Duration,
+    /// Secret of signed download URLs.
+    download_signing_secret: Option<String>,
+    cache: Arc<Mutex<HashMap<String, CachedToken>>>,
+}
+
+impl Introspection {
+    /// Create client if introspection URL is configured.
+    pub fn from_config(config: &RustusConf) -> Option<Self> {
+        let auth_opts = &config.auth_opts;
+        let url = auth_opts.auth_introspection_url.clone()?;
+        Some(Self {
+            client: reqwest::Client::new(),
+            url,
+            client_id: auth_opts.auth_client_id.clone(),
+            client_secret: auth_opts.auth_client_secret.clone(),
+            required_scopes: auth_opts.auth_required_scopes.clone(),
+            cache_ttl: Duration::from_secs(auth_opts.auth_cache_ttl),
+            download_signing_secret: config.download_signing_secret.clone(),
+            cache: Arc::default(),
+        })
+    }
+
+    /// Check if the request is allowed without a token.
+    ///
+    /// Signed URLs are given to clients which have no tokens,
+    /// so downloads and manifests with valid signatures are allowed.
+    /// Other routes don't check signatures, so they always need tokens.
+    fn is_exempt(&self, request: &ServiceRequest) -> bool {
+        let method = request.method();
+        if method == Method::OPTIONS {
+            return true;
+        }
+        let Some(secret) = &self.download_signing_secret else {
+            return false;
+        };
+        if method != Method::GET && method != Method::HEAD {
+            return false;
+        }
+        // Middleware is called before the route is resolved,
+        // so the upload id is taken from the rest of the path.
+        let path = request.match_info().unprocessed().trim_matches('/');
+        let file_id = match path.split('/').collect::<Vec<_>>().as_slice() {
+            [file_id] | [file_id, "manifest"] if !file_id.is_empty() => *file_id,
+            _ => return false,
+        };
+        let Ok(query) = web::Query::<DownloadSignature>::from_query(request.query_string()) else {
+            return false;
+        };
+        signature::verify(secret, file_id, &query, chrono::Utc::now().timestamp())
+    }
+
+    /// Authorize the client with the bearer token of the request.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the token is missing or inactive,
+    /// if it doesn't have required scopes
+    /// or if the introspection endpoint is unavailable.
+    pub async fn authorize(&self, request: &ServiceRequest) -> RustusResult<TokenInfo> {
+        let token = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| RustusError::Unauthorized(String::from("bearer token is missing")))?;
+        let token_info = self
+            .introspect(token)
+            .await?
+            .ok_or_else(|| RustusError::Unauthorized(String::from("token isn't active")))?;
+        let missing = self
+            .required_scopes
+            .iter()
+            .filter(|scope| !token_info.scopes.contains(scope))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(RustusError::InsufficientScope(missing.join(" ")));
+        }
+        Ok(token_info)
+    }
+
+    /// Get information about the token.
+    ///
+    /// It returns `None` if the token isn't active.
+    async fn introspect(&self, token: &str) -> RustusResult<Option<TokenInfo>> {
+        // Tokens aren't kept in memory, only their hashes.
+        let key = format!("{:x}", Sha256::digest(token.as_bytes()));
+        let now = Instant::now();
+        if let Some(cached) = self.cache.lock().unwrap().get(&key) {
+            if cached.expires_at > now {
+                return Ok(cached.token.clone());
+            }
+        }
+        let mut request = self
+            .client
+            .post(self.url.as_str())
+            .timeout(INTROSPECTION_TIMEOUT)
+            .form(&[("token", token), ("token_type_hint", "access_token")]);
+        if let Some(client_id) = &self.client_id {
+            request = request.basic_auth(client_id, self.client_secret.as_ref());
+        }
+        let response = request
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<IntrospectionResponse>()
+            .await?;
+        let mut ttl = self.cache_ttl;
+        let token = if response.active {
+            // Expired tokens are never active,
+            // even if the endpoint says otherwise.
+            let lifetime = response
+                .exp
+                .map(|exp| exp - chrono::Utc::now().timestamp())
+                .map(|secs| Duration::from_secs(u64::try_from(secs).unwrap_or_default()));
+            if let Some(lifetime) = lifetime {
+                ttl = ttl.min(lifetime);
+            }
+            (lifetime != Some(Duration::ZERO)).then(|| TokenInfo {
+                subject: response.sub.or(response.username),
+                scopes: response
+                    .scope
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect(),
+            })
+        } else {
+            None
+        };
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= CACHE_PRUNE_SIZE {
+            cache.retain(|_, cached| cached.expires_at > now);
+        }
+        cache.insert(
+            key,
+            CachedToken {
+                token: token.clone(),
+                expires_at: now + ttl,
+            },
+        );
+        Ok(token)
+    }
+}
+
+/// Middleware which authorizes clients with token introspection.
+///
+/// It does nothing if introspection URL isn't configured.
+#[derive(Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct IntrospectionAuth {
+    introspection: Option<Introspection>,
+}
+
+impl IntrospectionAuth {
+    pub fn from_config(config: &RustusConf) -> Self {
+        Self {
+            introspection: Introspection::from_config(config),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IntrospectionAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Transform = IntrospectionAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IntrospectionAuthMiddleware {
+            service: Rc::new(service),
+            introspection: self.introspection.clone(),
+        }))
+    }
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub struct IntrospectionAuthMiddleware<S> {
+    service: Rc<S>,
+    introspection: Option<Introspection>,
+}
+
+impl<S, B> Service<ServiceRequest> for IntrospectionAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let introspection = self.introspection.clone();
+        Box::pin(async move {
+            if let Some(introspection) = introspection.filter(|auth| !auth.is_exempt(&req)) {
+                match introspection.authorize(&req).await {
+                    Ok(token_info) => {
+                        req.extensions_mut().insert(token_info);
+                    }
+                    Err(err) => return Ok(req.error_response(err)),
+                }
+            }
+            Ok(service.call(req).await?.map_into_boxed_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Introspection, TokenInfo};
+    use crate::{errors::RustusError, server::test::get_service, utils::signature, State};
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, TestRequest},
+    };
+    use httptest::{
+        all_of,
+        matchers::{contains, request, url_decoded},
+        responders::json_encoded,
+        Expectation,
+    };
+    use serde_json::json;
+
+    fn expect_token(server: &httptest::Server, token: &str, response: serde_json::Value) {
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("POST", "/introspect"),
+                request::body(url_decoded(contains(("token", token.to_string())))),
+            ])
+            .times(1)
+            .respond_with(json_encoded(response)),
+        );
+    }
+
+    async fn get_state(server: &httptest::Server) -> State {
+        let mut state = State::test_new().await;
+        state.config.auth_opts.auth_introspection_url = Some(server.url_str("/introspect"));
+        state.config.auth_opts.auth_required_scopes = vec![String::from("uploads")];
+        state
+    }
+
+    #[actix_rt::test]
+    async fn authorize() {
+        let server = httptest::Server::run();
+        expect_token(
+            &server,
+            "good",
+            json!({"active": true, "sub": "user-1", "scope": "profile uploads"}),
+        );
+        expect_token(&server, "inactive", json!({"active": false}));
+        expect_token(
+            &server,
+            "readonly",
+            json!({"active": true, "sub": "user-2", "scope": "profile"}),
+        );
+        let state = get_state(&server).await;
+        let auth = Introspection::from_config(&state.config).unwrap();
+        let request = |token: &str| {
+            TestRequest::default()
+                .insert_header(("Authorization", format!("Bearer {token}")))
+                .to_srv_request()
+        };
+        // Second request is served from the cache.
+        for _ in 0..2 {
+            assert_eq!(
+                auth.authorize(&request("good")).await.unwrap(),
+                TokenInfo {
+                    subject: Some(String::from("user-1")),
+                    scopes: vec![String::from("profile"), String::from("uploads")],
+                }
+            );
+            assert!(matches!(
+                auth.authorize(&request("inactive")).await,
+                Err(RustusError::Unauthorized(_))
+            ));
+        }
+        assert!(matches!(
+            auth.authorize(&request("readonly")).await,
+            Err(RustusError::InsufficientScope(_))
+        ));
+        assert!(matches!(
+            auth.authorize(&TestRequest::default().to_srv_request())
+                .await,
+            Err(RustusError::Unauthorized(_))
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn owner_from_token() {
+        let server = httptest::Server::run();
+        expect_token(
+            &server,
+            "good",
+            json!({"active": true, "sub": "user-1", "scope": "uploads"}),
+        );
+        let state = get_state(&server).await;
+        let mut rustus = get_service(state.clone()).await;
+
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", "10"))
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.headers().get("WWW-Authenticate").unwrap(), "Bearer");
+
+        let request = TestRequest::post()
+            .uri(state.config.test_url().as_str())
+            .insert_header(("Upload-Length", "10"))
+            .insert_header(("Authorization", "Bearer good"))
+            .to_request();
+        let resp = call_service(&mut rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let location = resp.headers().get("Location").unwrap().to_str().unwrap();
+        let file_id = location.trim_end_matches('/').rsplit('/').next().unwrap();
+        let file_info = state.info_storage.get_info(file_id).await.unwrap();
+        assert_eq!(file_info.metadata["owner"], "user-1");
+    }
+
+    #[actix_rt::test]
+    async fn signed_downloads() {
+        let server = httptest::Server::run();
+        let mut state = get_state(&server).await;
+        state.config.download_signing_secret = Some(String::from("secret"));
+        let rustus = get_service(state.clone()).await;
+        let file_info = state.create_test_file().await;
+        let file_id = file_info.id.as_str();
+        let expires = chrono::Utc::now().timestamp() + 60;
+        let query = |file_id: &str| {
+            format!(
+                "expires={expires}&signature={}",
+                signature::sign("secret", file_id, expires)
+            )
+        };
+        let get = |url: String| TestRequest::get().uri(url.as_str()).to_request();
+        // Forged signatures aren't enough to skip authorization.
+        let resp = call_service(
+            &rustus,
+            get(format!(
+                "{}?expires={expires}&signature=forged",
+                state.config.file_url(file_id)
+            )),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        // Signature of another upload.
+        let url = format!("{}?{}", state.config.file_url(file_id), query("other"));
+        let resp = call_service(&rustus, get(url)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        // Routes which don't check signatures need tokens.
+        let url = format!("{}?{}", state.config.test_url(), query(file_id));
+        let resp = call_service(&rustus, get(url)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let request = TestRequest::delete()
+            .uri(format!("{}?{}", state.config.file_url(file_id), query(file_id)).as_str())
+            .to_request();
+        let resp = call_service(&rustus, request).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let url = format!("{}?{}", state.config.file_url(file_id), query(file_id));
+        let resp = call_service(&rustus, get(url)).await;
+        assert_ne!(resp.status(), StatusCode::UNAUTHORIZED);
+        let url = format!(
+            "{}manifest?{}",
+            state.config.file_url(file_id),
+            query(file_id)
+        );
+        let resp = call_service(&rustus, get(url)).await;
+        assert_ne!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
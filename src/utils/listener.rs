@@ -0,0 +1,121 @@
+use std::{
+    io::{Error, ErrorKind},
+    net::{IpAddr, SocketAddr, TcpListener, ToSocketAddrs},
+};
+
+use socket2::{Domain, Socket, Type};
+
+/// Maximum number of pending connections.
+///
+/// It's the same as the default backlog of actix.
+const BACKLOG: i32 = 2048;
+
+/// Remove brackets around IPv6 literal.
+///
+/// Both `::1` and `[::1]` can be used as a host.
+pub fn parse_host(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// Bind TCP listeners to all addresses of the host.
+///
+/// If `dual_stack` is enabled, IPv6 sockets
+/// accept IPv4 connections as well. Otherwise
+/// the default of the system is used.
+///
+/// # Errors
+///
+/// It returns error if the host can't be resolved
+/// or any of its addresses can't be bound.
+pub fn bind(host: &str, port: u16, dual_stack: bool) -> std::io::Result<Vec<TcpListener>> {
+    let addrs = (parse_host(host), port)
+        .to_socket_addrs()?
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(Error::new(
+            ErrorKind::AddrNotAvailable,
+            format!("Host {host} has no addresses."),
+        ));
+    }
+    addrs
+        .into_iter()
+        .map(|addr| bind_addr(addr, dual_stack))
+        .collect()
+}
+
+fn bind_addr(addr: SocketAddr, dual_stack: bool) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() && dual_stack {
+        socket.set_only_v6(false)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// Extract IP from an address of a client.
+///
+/// Addresses from `Forwarded` header may have ports
+/// and brackets, like `[2001:db8::1]:4711`. Such addresses
+/// are converted to plain IPs. IPv4 clients of dual-stack
+/// sockets are reported as IPv4. Unknown values
+/// are returned unchanged.
+pub fn client_ip(addr: &str) -> String {
+    let ip = addr
+        .parse::<SocketAddr>()
+        .map(|socket_addr| socket_addr.ip())
+        .or_else(|_| parse_host(addr).parse::<IpAddr>());
+    match ip {
+        Ok(IpAddr::V6(ip)) => ip
+            .to_ipv4_mapped()
+            .map_or_else(|| ip.to_string(), |ip| ip.to_string()),
+        Ok(ip) => ip.to_string(),
+        Err(_) => String::from(addr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bind, client_ip, parse_host};
+    use std::net::{Ipv6Addr, TcpStream};
+
+    #[test]
+    fn hosts() {
+        assert_eq!(parse_host("[::1]"), "::1");
+        assert_eq!(parse_host("::1"), "::1");
+        assert_eq!(parse_host("127.0.0.1"), "127.0.0.1");
+    }
+
+    #[test]
+    fn ipv6_loopback() {
+        for host in ["::1", "[::1]"] {
+            let listeners = bind(host, 0, false).unwrap();
+            assert_eq!(listeners.len(), 1);
+            let addr = listeners[0].local_addr().unwrap();
+            assert_eq!(addr.ip(), Ipv6Addr::LOCALHOST);
+            TcpStream::connect(addr).unwrap();
+        }
+    }
+
+    #[test]
+    fn dual_stack() {
+        let listeners = bind("::", 0, true).unwrap();
+        let port = listeners[0].local_addr().unwrap().port();
+        TcpStream::connect(("127.0.0.1", port)).unwrap();
+        TcpStream::connect(("::1", port)).unwrap();
+    }
+
+    #[test]
+    fn client_ips() {
+        assert_eq!(client_ip("[2001:db8::1]:4711"), "2001:db8::1");
+        assert_eq!(client_ip("[2001:db8::1]"), "2001:db8::1");
+        assert_eq!(client_ip("2001:db8::1"), "2001:db8::1");
+        assert_eq!(client_ip("192.0.2.1:80"), "192.0.2.1");
+        assert_eq!(client_ip("::ffff:192.0.2.1"), "192.0.2.1");
+        assert_eq!(client_ip("unknown"), "unknown");
+    }
+}
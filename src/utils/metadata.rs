@@ -0,0 +1,350 @@
+use std::{collections::HashMap, fmt::Write};
+
+use derive_more::{Display, From};
+use sha2::{Digest, Sha256};
+use strum::EnumIter;
+
+use crate::{from_str, utils::dir_struct::substr_time};
+
+/// Built-in normalizers of upload metadata.
+#[allow(clippy::module_name_repetitions)]
+#[derive(PartialEq, Eq, Debug, Display, EnumIter, From, Clone, Copy)]
+pub enum MetadataNormalizer {
+    /// Convert all keys to lowercase.
+    #[display(fmt = "lowercase-keys")]
+    LowercaseKeys,
+    /// Remove leading and trailing whitespaces from keys.
+    #[display(fmt = "trim-keys")]
+    TrimKeys,
+    /// Remove leading and trailing whitespaces from values.
+    #[display(fmt = "trim-values")]
+    TrimValues,
+}
+
+from_str!(MetadataNormalizer, "metadata normalizer");
+
+impl MetadataNormalizer {
+    fn apply(self, key: String, value: String) -> (String, String) {
+        match self {
+            Self::LowercaseKeys => (key.to_lowercase(), value),
+            Self::TrimKeys => (String::from(key.trim()), value),
+            Self::TrimValues => (key, String::from(value.trim())),
+        }
+    }
+}
+
+/// Alias of a metadata key.
+///
+/// It's parsed from strings like `name=filename`,
+/// which means that key `name` is renamed to `filename`.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetadataAlias {
+    pub alias: String,
+    pub key: String,
+}
+
+impl std::str::FromStr for MetadataAlias {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (alias, key) = input
+            .split_once('=')
+            .ok_or_else(|| format!("Metadata alias '{input}' must be in format 'alias=key'."))?;
+        Ok(Self {
+            alias: String::from(alias.trim()),
+            key: String::from(key.trim()),
+        })
+    }
+}
+
+/// Pattern which values of a metadata key must match.
+///
+/// It's parsed from strings like `tenant=[a-z0-9-]+`.
+/// Pattern must match the whole value.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug)]
+pub struct MetadataPattern {
+    pub key: String,
+    pub pattern: regex::Regex,
+}
+
+impl std::str::FromStr for MetadataPattern {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (key, pattern) = input.split_once('=').ok_or_else(|| {
+            format!("Metadata pattern '{input}' must be in format 'key=pattern'.")
+        })?;
+        let pattern = regex::Regex::new(format!("^(?:{pattern})$").as_str())
+            .map_err(|err| format!("Invalid pattern for metadata key '{key}': {err}"))?;
+        Ok(Self {
+            key: String::from(key.trim()),
+            pattern,
+        })
+    }
+}
+
+/// Metadata key which is set by the server.
+///
+/// It's parsed from strings like `ingest_node={env:HOSTNAME}`.
+/// Value is a template which is rendered for every new upload.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerMetadata {
+    pub key: String,
+    pub template: String,
+}
+
+impl std::str::FromStr for ServerMetadata {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (key, template) = input.split_once('=').ok_or_else(|| {
+            format!("Server metadata '{input}' must be in format 'key=template'.")
+        })?;
+        let key = key.trim();
+        // Such keys can't be encoded in Upload-Metadata.
+        if key.is_empty() || key.contains([' ', ',']) {
+            return Err(format!("Server metadata key '{key}' is invalid."));
+        }
+        Ok(Self {
+            key: String::from(key),
+            template: String::from(template),
+        })
+    }
+}
+
+impl ServerMetadata {
+    /// Render the value for an upload created at the given time.
+    ///
+    /// Besides time variables, template can use `{version}`
+    /// of rustus, `{timestamp}` in seconds, `{datetime}` in RFC 3339
+    /// and environment variables, like `{env:HOSTNAME}`.
+    /// Missing variables are rendered as empty strings.
+    pub fn render(&self, time: chrono::DateTime<chrono::Utc>) -> String {
+        let mut value = String::new();
+        let mut rest = substr_time(self.template.as_str(), time)
+            .replace("{version}", env!("CARGO_PKG_VERSION"))
+            .replace("{timestamp}", time.timestamp().to_string().as_str())
+            .replace(
+                "{datetime}",
+                time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                    .as_str(),
+            );
+        while let Some(start) = rest.find("{env:") {
+            let Some(len) = rest[start..].find('}') else {
+                break;
+            };
+            let name = &rest[start + "{env:".len()..start + len];
+            value.push_str(&rest[..start]);
+            value.push_str(std::env::var(name).unwrap_or_default().as_str());
+            rest = String::from(&rest[start + len + 1..]);
+        }
+        value.push_str(rest.as_str());
+        value
+    }
+}
+
+/// Set metadata keys of a new upload which are assigned by the server.
+///
+/// Values sent by the client for the same keys are replaced.
+pub fn assign_server_metadata(
+    server_metadata: &[ServerMetadata],
+    metadata: &mut HashMap<String, String>,
+) {
+    let now = chrono::Utc::now();
+    for item in server_metadata {
+        metadata.insert(item.key.clone(), item.render(now));
+    }
+}
+
+/// Check that metadata values match their patterns.
+///
+/// Keys without patterns and missing keys are not checked.
+/// Returns the first key whose value doesn't match.
+pub fn mismatched_key<'a>(
+    metadata: &HashMap<String, String>,
+    patterns: &'a [MetadataPattern],
+) -> Option<&'a str> {
+    patterns
+        .iter()
+        .find(|pattern| {
+            metadata
+                .get(pattern.key.as_str())
+                .is_some_and(|value| !pattern.pattern.is_match(value))
+        })
+        .map(|pattern| pattern.key.as_str())
+}
+
+/// Transform metadata of a new upload.
+///
+/// Normalizers are applied first in the given order.
+/// After that aliased keys are renamed. If metadata
+/// already has the target key, the aliased key is dropped.
+pub fn transform(
+    metadata: HashMap<String, String>,
+    normalizers: &[MetadataNormalizer],
+    aliases: &[MetadataAlias],
+) -> HashMap<String, String> {
+    if normalizers.is_empty() && aliases.is_empty() {
+        return metadata;
+    }
+    let cleaned = metadata
+        .into_iter()
+        .map(|(key, value)| {
+            normalizers
+                .iter()
+                .fold((key, value), |(key, value), normalizer| {
+                    normalizer.apply(key, value)
+                })
+        })
+        .collect::<HashMap<_, _>>();
+    let mut transformed = HashMap::with_capacity(cleaned.len());
+    let mut renamed = Vec::new();
+    for (key, value) in cleaned {
+        match aliases.iter().find(|alias| alias.alias == key) {
+            Some(alias) => renamed.push((alias.key.clone(), value)),
+            None => {
+                transformed.insert(key, value);
+            }
+        }
+    }
+    for (key, value) in renamed {
+        transformed.entry(key).or_insert(value);
+    }
+    transformed
+}
+
+/// Compute deduplication key of a new upload.
+///
+/// Key is a hex encoded sha256 of the length and
+/// values of the given metadata keys.
+/// Returns `None` if any of the keys is missing.
+pub fn dedup_key(
+    keys: &[String],
+    metadata: &HashMap<String, String>,
+    length: usize,
+) -> Option<String> {
+    let values = keys
+        .iter()
+        .map(|key| metadata.get(key).map(|value| (key, value)))
+        .collect::<Option<Vec<_>>>()?;
+    // Values are serialized with their lengths,
+    // so different values never have the same input.
+    let input = serde_json::to_vec(&(length, values)).ok()?;
+    Some(
+        Sha256::digest(input)
+            .iter()
+            .fold(String::new(), |mut hex, byte| {
+                let _ = write!(hex, "{byte:02x}");
+                hex
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        dedup_key, mismatched_key, transform, MetadataAlias, MetadataNormalizer, MetadataPattern,
+        ServerMetadata,
+    };
+    use chrono::TimeZone;
+    use std::{collections::HashMap, str::FromStr};
+
+    #[test]
+    fn parse_alias() {
+        let alias = MetadataAlias::from_str("name = filename").unwrap();
+        assert_eq!(alias.alias, "name");
+        assert_eq!(alias.key, "filename");
+        assert!(MetadataAlias::from_str("name").is_err());
+    }
+
+    #[test]
+    fn server_metadata() {
+        assert!(ServerMetadata::from_str("node").is_err());
+        assert!(ServerMetadata::from_str("ingest node=1").is_err());
+        std::env::set_var("RUSTUS_TEST_NODE", "node-1");
+        let item =
+            ServerMetadata::from_str("origin={env:RUSTUS_TEST_NODE}/{year}/{timestamp}").unwrap();
+        assert_eq!(item.key, "origin");
+        let time = chrono::Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        assert_eq!(item.render(time), "node-1/2023/1700000000");
+        let item = ServerMetadata::from_str("at={datetime}{env:RUSTUS_TEST_UNKNOWN}").unwrap();
+        assert_eq!(item.render(time), "2023-11-14T22:13:20Z");
+        let item = ServerMetadata::from_str("version={version}").unwrap();
+        assert_eq!(item.render(time), env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn patterns() {
+        let patterns = [
+            MetadataPattern::from_str(r"filename=[\w.-]{1,64}").unwrap(),
+            MetadataPattern::from_str("tenant=acme|globex").unwrap(),
+        ];
+        assert!(MetadataPattern::from_str("filename").is_err());
+        assert!(MetadataPattern::from_str("filename=[").is_err());
+        let mut metadata = HashMap::from([(String::from("filename"), String::from("cat.png"))]);
+        assert_eq!(mismatched_key(&metadata, &patterns), None);
+        metadata.insert(String::from("tenant"), String::from("acme"));
+        assert_eq!(mismatched_key(&metadata, &patterns), None);
+        // Patterns must match whole values.
+        metadata.insert(String::from("tenant"), String::from("acme-evil"));
+        assert_eq!(mismatched_key(&metadata, &patterns), Some("tenant"));
+        metadata.insert(String::from("filename"), String::from("../etc/passwd"));
+        assert_eq!(mismatched_key(&metadata, &patterns), Some("filename"));
+    }
+
+    #[test]
+    fn normalizers() {
+        let metadata = HashMap::from([(String::from(" Name "), String::from(" memes.png "))]);
+        let transformed = transform(
+            metadata,
+            &[
+                MetadataNormalizer::LowercaseKeys,
+                MetadataNormalizer::TrimKeys,
+                MetadataNormalizer::TrimValues,
+            ],
+            &[],
+        );
+        assert_eq!(transformed.get("name").unwrap(), "memes.png");
+    }
+
+    #[test]
+    fn aliases() {
+        let aliases = [MetadataAlias::from_str("name=filename").unwrap()];
+        let metadata = HashMap::from([(String::from("name"), String::from("memes.png"))]);
+        let transformed = transform(metadata, &[], &aliases);
+        assert_eq!(transformed.get("filename").unwrap(), "memes.png");
+        assert!(transformed.get("name").is_none());
+
+        // Explicit key wins over the alias.
+        let metadata = HashMap::from([
+            (String::from("name"), String::from("alias.png")),
+            (String::from("filename"), String::from("memes.png")),
+        ]);
+        let transformed = transform(metadata, &[], &aliases);
+        assert_eq!(transformed.get("filename").unwrap(), "memes.png");
+        assert_eq!(transformed.len(), 1);
+    }
+
+    #[test]
+    fn dedup_keys() {
+        let keys = [String::from("filename"), String::from("sha")];
+        let metadata = HashMap::from([
+            (String::from("filename"), String::from("memes.png")),
+            (String::from("sha"), String::from("abc")),
+            (String::from("other"), String::from("ignored")),
+        ]);
+        let key = dedup_key(&keys, &metadata, 10).unwrap();
+        assert_eq!(key.len(), 64);
+        let mut changed = metadata.clone();
+        changed.insert(String::from("other"), String::from("changed"));
+        assert_eq!(dedup_key(&keys, &changed, 10), Some(key.clone()));
+        assert_ne!(dedup_key(&keys, &metadata, 11), Some(key.clone()));
+        changed.insert(String::from("sha"), String::from("abd"));
+        assert_ne!(dedup_key(&keys, &changed, 10), Some(key));
+        changed.remove("sha");
+        assert_eq!(dedup_key(&keys, &changed, 10), None);
+    }
+}
@@ -1,4 +1,28 @@
+pub mod active_chunks;
 pub mod dir_struct;
+pub mod durability;
+pub mod encryption;
 pub mod enums;
 pub mod hashes;
 pub mod headers;
+pub mod import;
+pub mod introspection;
+pub mod listener;
+pub mod metadata;
+pub mod multipart;
+pub mod orphans;
+pub mod owner;
+pub mod pre_create;
+pub mod progress;
+pub mod proxy;
+pub mod quota;
+pub mod rejections;
+pub mod retry_after;
+pub mod signature;
+pub mod timeout;
+pub mod tls;
+pub mod tombstones;
+pub mod trash;
+pub mod unique;
+#[cfg(unix)]
+pub mod unix_socket;
@@ -0,0 +1,271 @@
+use std::fmt::Display;
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+
+use crate::{errors::RustusError, RustusResult};
+
+/// Maximum size of headers of a single part.
+const MAX_HEADERS_SIZE: usize = 16 * 1024;
+
+/// Get boundary from `Content-Type` header value.
+///
+/// Returns `None` if content type isn't `multipart/form-data`
+/// or it has no boundary.
+pub fn get_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| String::from(value.trim().trim_matches('"')))
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Headers of a single part of multipart body.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PartHeaders {
+    /// Name of the form field.
+    pub name: Option<String>,
+    /// Name of the uploaded file.
+    /// It's set only for file fields.
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+}
+
+impl PartHeaders {
+    fn parse(raw: &[u8]) -> RustusResult<Self> {
+        let raw = std::str::from_utf8(raw)
+            .map_err(|_| RustusError::MultipartError(String::from("Headers aren't UTF-8.")))?;
+        let mut headers = Self::default();
+        for line in raw.split("\r\n").filter(|line| !line.is_empty()) {
+            let Some((name, value)) = line.split_once(':') else {
+                return Err(RustusError::MultipartError(format!(
+                    "Malformed header '{line}'."
+                )));
+            };
+            if name.trim().eq_ignore_ascii_case("Content-Disposition") {
+                for param in value.split(';').skip(1) {
+                    let Some((key, val)) = param.trim().split_once('=') else {
+                        continue;
+                    };
+                    let val = String::from(val.trim().trim_matches('"'));
+                    match key.trim() {
+                        "name" => headers.name = Some(val),
+                        "filename" => headers.filename = Some(val),
+                        _ => {}
+                    }
+                }
+            } else if name.trim().eq_ignore_ascii_case("Content-Type") {
+                headers.content_type = Some(String::from(value.trim()));
+            }
+        }
+        Ok(headers)
+    }
+}
+
+/// Streaming parser of `multipart/form-data` bodies.
+///
+/// Parts are read one by one. Body of the current part
+/// is returned in chunks, so files are never loaded in memory.
+pub struct Multipart<S> {
+    stream: S,
+    buffer: BytesMut,
+    /// Delimiter of parts: `\r\n--{boundary}`.
+    delimiter: Vec<u8>,
+    /// Whether the body of a part is being read.
+    in_body: bool,
+    finished: bool,
+}
+
+impl<S, E> Multipart<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Display,
+{
+    pub fn new(stream: S, boundary: &str) -> Self {
+        // First delimiter has no leading CRLF, so we add it.
+        // Everything before the first delimiter is skipped
+        // as a body of a part.
+        let mut buffer = BytesMut::new();
+        buffer.extend_from_slice(b"\r\n");
+        Self {
+            stream,
+            buffer,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            in_body: true,
+            finished: false,
+        }
+    }
+
+    /// Read more bytes from the stream.
+    async fn fill(&mut self) -> RustusResult<()> {
+        match self.stream.next().await {
+            Some(Ok(bytes)) => {
+                self.buffer.extend_from_slice(bytes.as_ref());
+                Ok(())
+            }
+            Some(Err(err)) => Err(RustusError::MultipartError(err.to_string())),
+            None => Err(RustusError::MultipartError(String::from(
+                "Unexpected end of body.",
+            ))),
+        }
+    }
+
+    fn find(&self, needle: &[u8]) -> Option<usize> {
+        self.buffer
+            .windows(needle.len())
+            .position(|window| window == needle)
+    }
+
+    /// Get headers of the next part.
+    ///
+    /// Unread body of the current part is skipped.
+    /// Returns `None` if there are no parts left.
+    pub async fn next_part(&mut self) -> RustusResult<Option<PartHeaders>> {
+        while self.next_chunk().await?.is_some() {}
+        if self.finished {
+            return Ok(None);
+        }
+        // Buffer starts with a delimiter followed by
+        // `--` for the last delimiter or by `\r\n`.
+        while self.buffer.len() < self.delimiter.len() + 2 {
+            self.fill().await?;
+        }
+        self.buffer.advance(self.delimiter.len());
+        if self.buffer.starts_with(b"--") {
+            self.finished = true;
+            return Ok(None);
+        }
+        loop {
+            if let Some(end) = self.find(b"\r\n\r\n") {
+                let raw_headers = self.buffer.split_to(end + 4);
+                let headers = PartHeaders::parse(&raw_headers[2..])?;
+                self.in_body = true;
+                return Ok(Some(headers));
+            }
+            if self.buffer.len() > MAX_HEADERS_SIZE {
+                return Err(RustusError::MultipartError(String::from(
+                    "Headers of a part are too large.",
+                )));
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// Get next chunk of the current part's body.
+    ///
+    /// Returns `None` if the body is fully read.
+    pub async fn next_chunk(&mut self) -> RustusResult<Option<Bytes>> {
+        if !self.in_body {
+            return Ok(None);
+        }
+        loop {
+            match self.find(self.delimiter.as_slice()) {
+                Some(0) => {
+                    self.in_body = false;
+                    return Ok(None);
+                }
+                Some(position) => return Ok(Some(self.buffer.split_to(position).freeze())),
+                // Delimiter may be split between chunks,
+                // so the tail of the buffer is kept.
+                None if self.buffer.len() >= self.delimiter.len() => {
+                    let available = self.buffer.len() - self.delimiter.len() + 1;
+                    return Ok(Some(self.buffer.split_to(available).freeze()));
+                }
+                None => self.fill().await?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_boundary, Multipart, PartHeaders};
+    use bytes::{Bytes, BytesMut};
+
+    const BODY: &str = "preamble\r\n--bound\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        memes\r\n--bound\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"test.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        hello\r\n--boun world\r\n--bound--\r\n";
+
+    /// Split body into chunks of the given size.
+    fn stream(
+        chunk_size: usize,
+    ) -> impl futures::Stream<Item = Result<Bytes, std::io::Error>> + Unpin {
+        let chunks = BODY
+            .as_bytes()
+            .chunks(chunk_size)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        futures::stream::iter(chunks)
+    }
+
+    async fn read_body<S>(multipart: &mut Multipart<S>) -> String
+    where
+        S: futures::Stream<Item = Result<Bytes, std::io::Error>> + Unpin,
+    {
+        let mut body = BytesMut::new();
+        while let Some(chunk) = multipart.next_chunk().await.unwrap() {
+            body.extend_from_slice(chunk.as_ref());
+        }
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn parse_parts() {
+        for chunk_size in [1, 3, 7, 1000] {
+            let mut multipart = Multipart::new(stream(chunk_size), "bound");
+            let field = multipart.next_part().await.unwrap().unwrap();
+            assert_eq!(field.name.as_deref(), Some("title"));
+            assert!(field.filename.is_none());
+            assert_eq!(read_body(&mut multipart).await, "memes");
+            let file = multipart.next_part().await.unwrap().unwrap();
+            assert_eq!(
+                file,
+                PartHeaders {
+                    name: Some(String::from("file")),
+                    filename: Some(String::from("test.txt")),
+                    content_type: Some(String::from("text/plain")),
+                }
+            );
+            assert_eq!(read_body(&mut multipart).await, "hello\r\n--boun world");
+            assert!(multipart.next_part().await.unwrap().is_none());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn skip_unread_body() {
+        let mut multipart = Multipart::new(stream(5), "bound");
+        multipart.next_part().await.unwrap();
+        let file = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(file.filename.as_deref(), Some("test.txt"));
+        assert!(multipart.next_part().await.unwrap().is_none());
+    }
+
+    #[actix_rt::test]
+    async fn unexpected_end() {
+        let chunks = vec![Ok::<_, std::io::Error>(Bytes::from("--bound\r\nContent"))];
+        let mut multipart = Multipart::new(futures::stream::iter(chunks), "bound");
+        assert!(multipart.next_part().await.is_err());
+    }
+
+    #[test]
+    fn boundary() {
+        assert_eq!(
+            get_boundary("multipart/form-data; boundary=\"abc\"").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            get_boundary("multipart/form-data;boundary=abc").as_deref(),
+            Some("abc")
+        );
+        assert!(get_boundary("application/json; boundary=abc").is_none());
+        assert!(get_boundary("multipart/form-data").is_none());
+    }
+}
@@ -0,0 +1,470 @@
+use std::{collections::HashSet, path::Path};
+
+use futures::{stream, StreamExt};
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::{AvailableInfoStores, FileInfo},
+    State,
+};
+
+/// Uploads whose data and information disagree.
+#[derive(Serialize, Debug, Default)]
+pub struct OrphanReport {
+    /// Ids of uploads which have information, but no data.
+    pub missing_data: Vec<String>,
+    /// Paths of stored files without information about them.
+    ///
+    /// It's `None` if the storage cannot list its files.
+    pub missing_info: Option<Vec<String>>,
+}
+
+/// Result of the consistency scan.
+#[derive(Serialize, Debug, Default)]
+pub struct ConsistencyReport {
+    /// Number of checked uploads.
+    pub checked: usize,
+    /// Ids of uploads which have less data than their offsets.
+    pub truncated: Vec<String>,
+    /// Ids of uploads which couldn't be checked or repaired.
+    pub failed: Vec<String>,
+    /// Ids of inconsistent uploads which weren't repaired,
+    /// since they were updated recently.
+    pub skipped: Vec<String>,
+    #[serde(flatten)]
+    pub orphans: OrphanReport,
+    /// Whether found discrepancies were repaired.
+    pub repaired: bool,
+}
+
+/// Disagreement between data and information of an upload.
+enum Discrepancy {
+    /// Data of the upload is missing.
+    MissingData,
+    /// Only the given number of bytes is stored.
+    Truncated(usize),
+}
+
+/// Check that data of the upload exists.
+///
+/// If it doesn't, information about the upload
+/// is removed when `--remove-orphan-info` is set.
+///
+/// # Errors
+///
+/// Returns `DataMissing` if the data is missing.
+pub async fn check_data(state: &State, file_info: &FileInfo) -> RustusResult<()> {
+    if state.data_storage.data_exists(file_info).await? {
+        return Ok(());
+    }
+    warn!("Data of upload {} is missing.", file_info.id);
+    if state.config.remove_orphan_info {
+        state
+            .info_storage
+            .remove_info(file_info.id.as_str())
+            .await?;
+        warn!("Information about upload {} is removed.", file_info.id);
+    }
+    Err(RustusError::DataMissing(file_info.id.clone()))
+}
+
+/// Find uploads whose data or information is missing.
+///
+/// This scan reads information about all uploads
+/// and lists all files of the storage, so it's slow.
+///
+/// # Errors
+///
+/// Returns an error if storages cannot be read.
+pub async fn scan(state: &State) -> RustusResult<OrphanReport> {
+    let storage_name = state.data_storage.to_string();
+    let mut report = OrphanReport::default();
+    let mut known_paths = HashSet::new();
+    for file_info in state.info_storage.list_info().await? {
+        if file_info.storage != storage_name {
+            continue;
+        }
+        if !state.data_storage.data_exists(&file_info).await? {
+            report.missing_data.push(file_info.id.clone());
+        }
+        if let Some(path) = file_info.path {
+            known_paths.insert(path);
+        }
+    }
+    report.missing_info = find_missing_info(state, known_paths).await?;
+    Ok(report)
+}
+
+/// Find stored files which aren't referenced by `known_paths`.
+///
+/// It returns `None` if the storage cannot list its files.
+async fn find_missing_info(
+    state: &State,
+    mut known_paths: HashSet<String>,
+) -> RustusResult<Option<Vec<String>>> {
+    // Data of terminated uploads is kept during the grace period.
+    if let Some(trash) = &state.trash {
+        for file_info in trash.list_info().await? {
+            if let Some(path) = file_info.path {
+                known_paths.insert(path);
+            }
+        }
+    }
+    let paths = match state.data_storage.list_paths().await {
+        Ok(paths) => paths,
+        Err(RustusError::Unimplemented(reason)) => {
+            warn!("Files without information can't be found: {}", reason);
+            return Ok(None);
+        }
+        Err(err) => return Err(err),
+    };
+    // Info files are skipped, since info
+    // and data directories can be the same.
+    let info_dir = (state.config.info_storage_opts.info_storage == AvailableInfoStores::Files)
+        .then(|| state.config.info_storage_opts.info_dir.canonicalize().ok())
+        .flatten();
+    let is_info_file = |path: &Path| {
+        info_dir.as_deref().is_some_and(|info_dir| {
+            path.parent() == Some(info_dir) && path.extension().is_some_and(|ext| ext == "info")
+        })
+    };
+    let mut missing_info = paths
+        .into_iter()
+        .filter(|path| !known_paths.contains(path) && !is_info_file(Path::new(path)))
+        .collect::<Vec<_>>();
+    missing_info.sort();
+    Ok(Some(missing_info))
+}
+
+/// Compare data of the upload with information about it.
+async fn check_upload(state: &State, file_info: &FileInfo) -> RustusResult<Option<Discrepancy>> {
+    if !state.data_storage.data_exists(file_info).await? {
+        return Ok(Some(Discrepancy::MissingData));
+    }
+    match state.data_storage.stored_size(file_info).await? {
+        Some(stored) if stored < file_info.offset => Ok(Some(Discrepancy::Truncated(stored))),
+        _ => Ok(None),
+    }
+}
+
+/// Check if the upload may be written by another process right now.
+///
+/// Info storage can be shared by several rustus instances,
+/// so uploads updated during the last `--startup-repair-min-age`
+/// seconds aren't repaired.
+fn is_recent(state: &State, file_info: &FileInfo) -> bool {
+    let min_age = chrono::Duration::seconds(
+        i64::try_from(state.config.startup_repair_min_age).unwrap_or(i64::MAX / 1000),
+    );
+    chrono::Utc::now() - file_info.last_modified() < min_age
+}
+
+/// Bring information about the upload in line with its data.
+///
+/// Uploads without data are removed and offsets
+/// of truncated uploads are lowered to the stored size,
+/// so clients resume them from the last stored byte.
+/// Encrypted uploads are started from the beginning,
+/// since the MAC of remaining bytes can't be computed
+/// without the key. Uploads of aligned storages
+/// are resumed from the last aligned boundary.
+/// Received ranges after the new offset and
+/// the checksum of lost data are dropped.
+async fn repair_upload(
+    state: &State,
+    file_info: &mut FileInfo,
+    discrepancy: &Discrepancy,
+) -> RustusResult<()> {
+    match discrepancy {
+        Discrepancy::MissingData => state.info_storage.remove_info(file_info.id.as_str()).await,
+        Discrepancy::Truncated(stored) => {
+            file_info.offset = *stored;
+            if let Some(alignment) = state.data_storage.chunk_alignment() {
+                file_info.offset -= file_info.offset % alignment;
+            }
+            if let Some(encryption) = &mut file_info.encryption {
+                file_info.offset = 0;
+                encryption.reset()?;
+            }
+            let out_of_order = !file_info.received.is_empty();
+            file_info.received.clear();
+            if out_of_order {
+                file_info.add_received_range(0, file_info.offset);
+            }
+            file_info.checksum = None;
+            file_info.chunk_tokens.clear();
+            if file_info.offset < *stored {
+                state.data_storage.truncate(file_info).await?;
+            }
+            state.info_storage.save_info(file_info).await
+        }
+    }
+}
+
+/// Check that data of every upload exists and has
+/// at least as many bytes as the offset of the upload.
+///
+/// Uploads are checked concurrently, at most `concurrency`
+/// at a time. Discrepancies are repaired if `repair` is set.
+/// Files without information are only reported,
+/// since rustus can't tell what they belong to.
+///
+/// # Errors
+///
+/// Returns an error if storages cannot be listed.
+pub async fn check_consistency(
+    state: &State,
+    concurrency: usize,
+    repair: bool,
+) -> RustusResult<ConsistencyReport> {
+    let storage_name = state.data_storage.to_string();
+    let uploads = state
+        .info_storage
+        .list_info()
+        .await?
+        .into_iter()
+        .filter(|file_info| file_info.storage == storage_name)
+        .collect::<Vec<_>>();
+    let mut report = ConsistencyReport {
+        checked: uploads.len(),
+        repaired: repair,
+        ..ConsistencyReport::default()
+    };
+    let known_paths = uploads
+        .iter()
+        .filter_map(|file_info| file_info.path.clone())
+        .collect::<HashSet<_>>();
+    let mut results = stream::iter(uploads)
+        .map(|mut file_info| async move {
+            let mut skipped = false;
+            let result = match check_upload(state, &file_info).await {
+                Ok(Some(discrepancy)) if repair && is_recent(state, &file_info) => {
+                    skipped = true;
+                    Ok(Some(discrepancy))
+                }
+                Ok(Some(discrepancy)) if repair => {
+                    repair_upload(state, &mut file_info, &discrepancy)
+                        .await
+                        .map(|()| Some(discrepancy))
+                }
+                result => result,
+            };
+            (file_info.id, result, skipped)
+        })
+        .buffer_unordered(concurrency.max(1));
+    while let Some((id, result, skipped)) = results.next().await {
+        if skipped {
+            warn!("Upload {} was updated recently, it isn't repaired.", id);
+            report.skipped.push(id.clone());
+        }
+        match result {
+            Ok(None) => {}
+            Ok(Some(Discrepancy::MissingData)) => report.orphans.missing_data.push(id),
+            Ok(Some(Discrepancy::Truncated(_))) => report.truncated.push(id),
+            Err(err) => {
+                warn!("Cannot check upload {}: {}", id, err);
+                report.failed.push(id);
+            }
+        }
+    }
+    report.orphans.missing_data.sort();
+    report.truncated.sort();
+    report.failed.sort();
+    report.skipped.sort();
+    report.orphans.missing_info = find_missing_info(state, known_paths).await?;
+    Ok(report)
+}
+
+/// Run the consistency scan before the server is started.
+///
+/// # Errors
+///
+/// Returns an error if storages cannot be listed.
+pub async fn startup_scan(state: &State) -> RustusResult<()> {
+    info!("Checking consistency of uploads.");
+    let report = check_consistency(
+        state,
+        state.config.startup_scan_concurrency,
+        state.config.startup_repair,
+    )
+    .await?;
+    info!(
+        "{} uploads were checked: {} without data, {} truncated, {} failed, {} skipped.",
+        report.checked,
+        report.orphans.missing_data.len(),
+        report.truncated.len(),
+        report.failed.len(),
+        report.skipped.len(),
+    );
+    if report.repaired && !(report.orphans.missing_data.is_empty() && report.truncated.is_empty()) {
+        info!("Information about inconsistent uploads is repaired.");
+    }
+    if let Some(missing_info) = &report.orphans.missing_info {
+        for path in missing_info {
+            warn!("File {} has no information about it.", path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_consistency, check_data, scan};
+    use crate::{errors::RustusError, State};
+    use bytes::Bytes;
+
+    #[actix_rt::test]
+    async fn missing_data() {
+        let mut state = State::test_new().await;
+        let file_info = state.create_test_file().await;
+        check_data(&state, &file_info).await.unwrap();
+        std::fs::remove_file(file_info.path.as_deref().unwrap()).unwrap();
+        let err = check_data(&state, &file_info).await.unwrap_err();
+        assert!(matches!(err, RustusError::DataMissing(_)));
+        // Information is kept by default.
+        assert!(state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .is_ok());
+        state.config.remove_orphan_info = true;
+        assert!(check_data(&state, &file_info).await.is_err());
+        assert!(state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .is_err());
+    }
+
+    #[actix_rt::test]
+    async fn scan_orphans() {
+        let state = State::test_new().await;
+        let valid = state.create_test_file().await;
+        let without_data = state.create_test_file().await;
+        let without_info = state.create_test_file().await;
+        std::fs::remove_file(without_data.path.as_deref().unwrap()).unwrap();
+        state
+            .info_storage
+            .remove_info(without_info.id.as_str())
+            .await
+            .unwrap();
+        let report = scan(&state).await.unwrap();
+        assert_eq!(report.missing_data, vec![without_data.id]);
+        assert_eq!(report.missing_info, Some(vec![without_info.path.unwrap()]));
+        assert!(!report.missing_data.contains(&valid.id));
+    }
+
+    #[actix_rt::test]
+    async fn repair_inconsistent() {
+        let mut state = State::test_new().await;
+        state.config.startup_repair_min_age = 0;
+        let mut valid = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&valid, Bytes::from("memes"))
+            .await
+            .unwrap();
+        valid.offset = 5;
+        state.info_storage.set_info(&valid, false).await.unwrap();
+        let mut truncated = state.create_test_file().await;
+        state
+            .data_storage
+            .add_bytes(&truncated, Bytes::from("me"))
+            .await
+            .unwrap();
+        // Offset was saved, but bytes were lost.
+        truncated.offset = 5;
+        truncated.checksum = Some(String::from("lost"));
+        state
+            .info_storage
+            .set_info(&truncated, false)
+            .await
+            .unwrap();
+        let without_data = state.create_test_file().await;
+        std::fs::remove_file(without_data.path.as_deref().unwrap()).unwrap();
+        let without_info = state.create_test_file().await;
+        state
+            .info_storage
+            .remove_info(without_info.id.as_str())
+            .await
+            .unwrap();
+
+        // Nothing is changed without repair.
+        let report = check_consistency(&state, 2, false).await.unwrap();
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.truncated, vec![truncated.id.clone()]);
+        assert_eq!(report.orphans.missing_data, vec![without_data.id.clone()]);
+        assert_eq!(
+            report.orphans.missing_info,
+            Some(vec![without_info.path.clone().unwrap()])
+        );
+        assert!(report.failed.is_empty());
+        let info = state
+            .info_storage
+            .get_info(truncated.id.as_str())
+            .await
+            .unwrap();
+        assert_eq!(info.offset, 5);
+
+        let report = check_consistency(&state, 2, true).await.unwrap();
+        assert!(report.repaired);
+        assert_eq!(report.truncated, vec![truncated.id.clone()]);
+        let info = state
+            .info_storage
+            .get_info(truncated.id.as_str())
+            .await
+            .unwrap();
+        assert_eq!(info.offset, 2);
+        assert!(info.checksum.is_none());
+        assert!(state
+            .info_storage
+            .get_info(without_data.id.as_str())
+            .await
+            .is_err());
+        assert_eq!(
+            state
+                .info_storage
+                .get_info(valid.id.as_str())
+                .await
+                .unwrap()
+                .offset,
+            5
+        );
+        // Files without information are kept.
+        assert!(std::path::Path::new(without_info.path.as_deref().unwrap()).exists());
+
+        let report = check_consistency(&state, 2, true).await.unwrap();
+        assert_eq!(report.checked, 2);
+        assert!(report.truncated.is_empty() && report.orphans.missing_data.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn recent_uploads_skipped() {
+        let mut state = State::test_new().await;
+        state.config.startup_repair_min_age = 300;
+        let mut recent = state.create_test_file().await;
+        let mut stale = state.create_test_file().await;
+        stale.created_at -= chrono::Duration::minutes(10);
+        for upload in [&mut recent, &mut stale] {
+            upload.offset = 5;
+            state.info_storage.set_info(upload, false).await.unwrap();
+        }
+        let report = check_consistency(&state, 2, true).await.unwrap();
+        assert_eq!(report.skipped, vec![recent.id.clone()]);
+        let offset = |id: String| {
+            let state = state.clone();
+            async move {
+                state
+                    .info_storage
+                    .get_info(id.as_str())
+                    .await
+                    .unwrap()
+                    .offset
+            }
+        };
+        assert_eq!(offset(recent.id.clone()).await, 5);
+        assert_eq!(offset(stale.id.clone()).await, 0);
+    }
+}
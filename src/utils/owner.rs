@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use actix_web::{HttpMessage, HttpRequest};
+
+use crate::{utils::introspection::TokenInfo, RustusConf};
+
+/// Get the owner of uploads from the request.
+///
+/// Subject of the introspected token is used first.
+/// Otherwise owner is taken from the header set by a trusted proxy,
+/// so clients can't pretend to be others.
+pub fn request_owner(config: &RustusConf, request: &HttpRequest) -> Option<String> {
+    if let Some(subject) = request
+        .extensions()
+        .get::<TokenInfo>()
+        .and_then(|token| token.subject.clone())
+    {
+        return Some(subject);
+    }
+    let header = config.owner_header.as_ref()?;
+    if !config.client_ip.is_trusted(request) {
+        return None;
+    }
+    request
+        .headers()
+        .get(header.as_str())
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|owner| !owner.is_empty())
+        .map(String::from)
+}
+
+/// Set the owner of a new upload.
+///
+/// Owner sent by the client in metadata is always removed.
+/// It does nothing if owners aren't configured.
+pub fn assign(config: &RustusConf, request: &HttpRequest, metadata: &mut HashMap<String, String>) {
+    if !config.owners_enabled() {
+        return;
+    }
+    let key = config.owner_metadata_key.as_str();
+    metadata.remove(key);
+    if let Some(owner) = request_owner(config, request) {
+        metadata.insert(String::from(key), owner);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assign, request_owner};
+    use crate::State;
+    use actix_web::test::TestRequest;
+    use std::collections::HashMap;
+
+    #[actix_rt::test]
+    async fn trusted_owner() {
+        let mut state = State::test_new().await;
+        state.config.owner_header = Some(String::from("X-Auth-Subject"));
+        let request = TestRequest::default()
+            .insert_header(("X-Auth-Subject", "user-1"))
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .to_http_request();
+        // Headers of untrusted peers are ignored.
+        assert_eq!(request_owner(&state.config, &request), None);
+        let mut metadata = HashMap::from([(String::from("owner"), String::from("user-2"))]);
+        assign(&state.config, &request, &mut metadata);
+        assert!(metadata.is_empty());
+
+        state.config.client_ip.trusted_proxies = vec!["10.0.0.0/8".parse().unwrap()];
+        assert_eq!(
+            request_owner(&state.config, &request).as_deref(),
+            Some("user-1")
+        );
+        let mut metadata = HashMap::from([(String::from("owner"), String::from("user-2"))]);
+        assign(&state.config, &request, &mut metadata);
+        assert_eq!(metadata["owner"], "user-1");
+    }
+}
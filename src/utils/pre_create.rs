@@ -0,0 +1,46 @@
+use actix_web::HttpRequest;
+
+use crate::{
+    errors::RustusResult,
+    info_storages::FileInfo,
+    notifiers::{models::upload_patch::UploadPatch, Hook},
+    State,
+};
+
+/// Send pre-create hook for the new upload.
+///
+/// Responses of hooks are applied to the upload
+/// if pre-create patches are enabled.
+///
+/// # Errors
+///
+/// Returns an error if the hook rejects the upload
+/// or its response is not a valid patch.
+pub async fn send(
+    state: &State,
+    request: &HttpRequest,
+    file_info: &mut FileInfo,
+) -> RustusResult<()> {
+    if !state.config.hook_is_active(Hook::PreCreate) {
+        return Ok(());
+    }
+    let message = state.config.notification_opts.hooks_format.format(
+        request,
+        file_info,
+        &state.config.client_ip,
+    );
+    let headers = request.headers();
+    if state.config.notification_opts.hooks_pre_create_patch {
+        let responses = state
+            .notification_manager
+            .send_message_with_responses(message, Hook::PreCreate, headers)
+            .await?;
+        UploadPatch::apply_responses(responses.as_slice(), file_info)?;
+    } else {
+        state
+            .notification_manager
+            .send_message(message, Hook::PreCreate, headers)
+            .await?;
+    }
+    Ok(())
+}
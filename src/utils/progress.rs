@@ -0,0 +1,119 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::info_storages::FileInfo;
+
+/// Number of events kept for slow subscribers.
+const CAPACITY: usize = 1024;
+
+#[allow(clippy::module_name_repetitions)]
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressKind {
+    /// Offset of the upload has changed.
+    Progress,
+    Finished,
+    Terminated,
+}
+
+/// Change of an upload.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProgressEvent {
+    pub id: String,
+    pub event: ProgressKind,
+    pub offset: usize,
+    pub length: Option<usize>,
+}
+
+impl ProgressEvent {
+    pub fn new(event: ProgressKind, file_info: &FileInfo) -> Self {
+        Self {
+            id: file_info.id.clone(),
+            event,
+            offset: file_info.offset,
+            length: file_info.length,
+        }
+    }
+
+    /// Event for current state of the upload.
+    pub fn current(file_info: &FileInfo) -> Self {
+        let kind = if file_info.length == Some(file_info.offset) {
+            ProgressKind::Finished
+        } else {
+            ProgressKind::Progress
+        };
+        Self::new(kind, file_info)
+    }
+
+    /// Whether no events of the upload will follow.
+    pub fn is_last(&self) -> bool {
+        self.event != ProgressKind::Progress
+    }
+}
+
+/// Channel of upload changes.
+///
+/// Events are dropped if nobody listens to them.
+#[derive(Clone, Debug)]
+pub struct Progress {
+    sender: broadcast::Sender<ProgressEvent>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self { sender }
+    }
+}
+
+impl Progress {
+    /// Notify about new offset of the upload.
+    pub fn publish(&self, file_info: &FileInfo) {
+        self.send(ProgressEvent::current(file_info));
+    }
+
+    /// Notify that the upload is removed.
+    pub fn publish_terminated(&self, file_info: &FileInfo) {
+        self.send(ProgressEvent::new(ProgressKind::Terminated, file_info));
+    }
+
+    fn send(&self, event: ProgressEvent) {
+        // Error means that there are no subscribers.
+        self.sender.send(event).ok();
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ProgressEvent> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Progress, ProgressKind};
+    use crate::info_storages::FileInfo;
+
+    #[actix_rt::test]
+    async fn events() {
+        let progress = Progress::default();
+        let mut file_info = FileInfo::new_test();
+        // Nobody listens yet.
+        progress.publish(&file_info);
+        let mut receiver = progress.subscribe();
+        file_info.offset = 5;
+        progress.publish(&file_info);
+        file_info.offset = 10;
+        progress.publish(&file_info);
+        progress.publish_terminated(&file_info);
+        let event = receiver.recv().await.unwrap();
+        assert_eq!(event.event, ProgressKind::Progress);
+        assert_eq!(event.offset, 5);
+        assert!(!event.is_last());
+        let event = receiver.recv().await.unwrap();
+        assert_eq!(event.event, ProgressKind::Finished);
+        assert!(event.is_last());
+        let event = receiver.recv().await.unwrap();
+        assert_eq!(event.event, ProgressKind::Terminated);
+        assert_eq!(event.id, file_info.id);
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use actix_web::HttpRequest;
+
+use crate::utils::listener::parse_host;
+
+/// Source of the client's IP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpSource {
+    /// Address of the socket peer.
+    Peer,
+    /// `for` parameter of `Forwarded` header or
+    /// the address of `X-Forwarded-For` header.
+    ///
+    /// Addresses are walked from the right and trusted
+    /// proxies are skipped, so the first untrusted one is used.
+    Forwarded,
+    /// Address of `X-Forwarded-For` at the position from the right.
+    ///
+    /// Position 1 is the address which was added
+    /// by the closest proxy. Trusted proxies aren't counted.
+    XForwardedFor(usize),
+    /// Value of `X-Real-IP` header.
+    XRealIp,
+}
+
+impl FromStr for IpSource {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim().to_lowercase();
+        match input.as_str() {
+            "peer" => return Ok(Self::Peer),
+            "forwarded" => return Ok(Self::Forwarded),
+            "x-real-ip" => return Ok(Self::XRealIp),
+            _ => {}
+        }
+        input
+            .strip_prefix("x-forwarded-for:")
+            .and_then(|position| position.parse::<usize>().ok())
+            .filter(|position| *position > 0)
+            .map(Self::XForwardedFor)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown IP source '{input}'. \
+                    Use peer, forwarded, x-real-ip or x-forwarded-for:<position>."
+                )
+            })
+    }
+}
+
+/// Network of trusted proxies, like `10.0.0.0/8`.
+///
+/// Single addresses are networks with the full prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct TrustedProxy {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl TrustedProxy {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+            IpAddr::V4(_) => ip,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or_default();
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or_default();
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for TrustedProxy {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{input}' is not a valid IP network");
+        let (network, prefix) = match input.trim().split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (input.trim(), None),
+        };
+        let network = parse_host(network)
+            .parse::<IpAddr>()
+            .map_err(|_| invalid())?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u32>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(invalid)?,
+            None => max_prefix,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+/// Parse IP from a header value.
+///
+/// Values may have ports and brackets, like `[2001:db8::1]:4711`.
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    value
+        .parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| parse_host(value).parse::<IpAddr>())
+        .ok()
+}
+
+/// Addresses of all `X-Forwarded-For` headers from left to right.
+fn forwarded_for(request: &HttpRequest) -> Vec<&str> {
+    request
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect()
+}
+
+/// `for` parameters of all `Forwarded` headers from left to right.
+fn forwarded(request: &HttpRequest) -> Vec<&str> {
+    request
+        .headers()
+        .get_all("Forwarded")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split([',', ';']))
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            name.trim().eq_ignore_ascii_case("for").then_some(value)
+        })
+        .collect()
+}
+
+/// Check if the address belongs to one of trusted proxies.
+fn is_proxy(ip: IpAddr, trusted_proxies: &[TrustedProxy]) -> bool {
+    trusted_proxies.iter().any(|proxy| proxy.contains(ip))
+}
+
+/// Get the client's IP from the source.
+///
+/// Addresses are appended by every proxy, so only the right part
+/// of forwarded headers can be trusted. They're walked from the right
+/// and addresses of trusted proxies are skipped.
+fn from_source(
+    request: &HttpRequest,
+    source: IpSource,
+    trusted_proxies: &[TrustedProxy],
+) -> Option<IpAddr> {
+    match source {
+        IpSource::Peer => request.peer_addr().map(|addr| addr.ip()),
+        IpSource::Forwarded => {
+            let mut hops = forwarded(request);
+            if hops.is_empty() {
+                hops = forwarded_for(request);
+            }
+            // Without the list of proxies every hop is trusted,
+            // so the leftmost address is used.
+            let mut client = None;
+            for hop in hops.iter().rev() {
+                let ip = parse_ip(hop)?;
+                client = Some(ip);
+                if !trusted_proxies.is_empty() && !is_proxy(ip, trusted_proxies) {
+                    break;
+                }
+            }
+            client
+        }
+        IpSource::XForwardedFor(position) => forwarded_for(request)
+            .iter()
+            .rev()
+            .map(|value| parse_ip(value))
+            .filter(|ip| !matches!(ip, Some(ip) if is_proxy(*ip, trusted_proxies)))
+            .nth(position - 1)
+            .flatten(),
+        IpSource::XRealIp => request
+            .headers()
+            .get("X-Real-IP")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_ip),
+    }
+}
+
+/// Check if the peer of the request is a trusted proxy.
+///
+/// Without trusted proxies all peers are trusted if `trust_all` is set.
+pub fn is_trusted(
+    request: &HttpRequest,
+    trusted_proxies: &[TrustedProxy],
+    trust_all: bool,
+) -> bool {
+    match request.peer_addr().map(|addr| addr.ip()) {
+        Some(peer) if !trusted_proxies.is_empty() => is_proxy(peer, trusted_proxies),
+        _ => trust_all,
+    }
+}
+
+/// Resolve the client's IP.
+///
+/// Sources are tried in the given order and the peer
+/// address is used if none of them has a valid IP.
+/// Headers are used only if the peer is trusted,
+/// otherwise clients could spoof their addresses.
+pub fn resolve(
+    request: &HttpRequest,
+    sources: &[IpSource],
+    trusted_proxies: &[TrustedProxy],
+    trust_all: bool,
+) -> Option<IpAddr> {
+    let peer = request.peer_addr().map(|addr| addr.ip());
+    if is_trusted(request, trusted_proxies, trust_all) {
+        for source in sources {
+            if let Some(ip) = from_source(request, *source, trusted_proxies) {
+                return Some(ip);
+            }
+        }
+    }
+    peer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve, IpSource, TrustedProxy};
+    use actix_web::test::TestRequest;
+    use std::{net::IpAddr, str::FromStr};
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn sources() {
+        assert_eq!(IpSource::from_str("peer"), Ok(IpSource::Peer));
+        assert_eq!(IpSource::from_str(" X-Real-IP"), Ok(IpSource::XRealIp));
+        assert_eq!(
+            IpSource::from_str("x-forwarded-for:2"),
+            Ok(IpSource::XForwardedFor(2))
+        );
+        assert!(IpSource::from_str("x-forwarded-for:0").is_err());
+        assert!(IpSource::from_str("memes").is_err());
+    }
+
+    #[test]
+    fn networks() {
+        let network = TrustedProxy::from_str("10.0.0.0/8").unwrap();
+        assert!(network.contains(ip("10.1.2.3")));
+        assert!(network.contains(ip("::ffff:10.1.2.3")));
+        assert!(!network.contains(ip("11.0.0.1")));
+        let network = TrustedProxy::from_str("fd00::/8").unwrap();
+        assert!(network.contains(ip("fd12::1")));
+        assert!(!network.contains(ip("10.0.0.1")));
+        let single = TrustedProxy::from_str("[::1]").unwrap();
+        assert!(single.contains(ip("::1")));
+        assert!(TrustedProxy::from_str("0.0.0.0/0")
+            .unwrap()
+            .contains(ip("1.2.3.4")));
+        assert!(TrustedProxy::from_str("10.0.0.0/33").is_err());
+        assert!(TrustedProxy::from_str("memes").is_err());
+    }
+
+    #[test]
+    fn trusted_proxies() {
+        let trusted = [TrustedProxy::from_str("10.0.0.0/8").unwrap()];
+        let sources = [IpSource::XForwardedFor(1), IpSource::XRealIp];
+        let request = |peer: &str| {
+            TestRequest::default()
+                .peer_addr(peer.parse().unwrap())
+                .insert_header(("X-Forwarded-For", "6.6.6.6, 1.2.3.4, 10.0.0.2"))
+                .insert_header(("X-Real-IP", "5.6.7.8"))
+                .to_http_request()
+        };
+        assert_eq!(
+            resolve(&request("10.0.0.1:80"), &sources, &trusted, false),
+            Some(ip("1.2.3.4"))
+        );
+        // Spoofed headers of untrusted clients are ignored.
+        assert_eq!(
+            resolve(&request("8.8.8.8:80"), &sources, &trusted, true),
+            Some(ip("8.8.8.8"))
+        );
+        assert_eq!(
+            resolve(&request("10.0.0.1:80"), &sources, &[], false),
+            Some(ip("10.0.0.1"))
+        );
+    }
+
+    #[test]
+    fn spoofed_hops() {
+        let trusted = [
+            TrustedProxy::from_str("10.0.0.0/8").unwrap(),
+            TrustedProxy::from_str("fd00::/8").unwrap(),
+        ];
+        // Client sent the first address, proxies appended the others.
+        let request = TestRequest::default()
+            .peer_addr("10.0.0.1:80".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "6.6.6.6, 1.2.3.4, 10.0.0.3, 10.0.0.2"))
+            .to_http_request();
+        let sources = [IpSource::Forwarded];
+        assert_eq!(
+            resolve(&request, &sources, &trusted, false),
+            Some(ip("1.2.3.4"))
+        );
+        let request = TestRequest::default()
+            .peer_addr("10.0.0.1:80".parse().unwrap())
+            .insert_header(("Forwarded", "for=6.6.6.6, for=1.2.3.4, for=\"[fd00::2]\""))
+            .to_http_request();
+        assert_eq!(
+            resolve(&request, &sources, &trusted, false),
+            Some(ip("1.2.3.4"))
+        );
+        // Requests which only passed proxies get the leftmost address.
+        let request = TestRequest::default()
+            .peer_addr("10.0.0.1:80".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "10.0.0.3, 10.0.0.2"))
+            .to_http_request();
+        assert_eq!(
+            resolve(&request, &sources, &trusted, false),
+            Some(ip("10.0.0.3"))
+        );
+        // Without the list of proxies the leftmost address is used.
+        let request = TestRequest::default()
+            .peer_addr("10.0.0.1:80".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "6.6.6.6, 1.2.3.4"))
+            .to_http_request();
+        assert_eq!(resolve(&request, &sources, &[], true), Some(ip("6.6.6.6")));
+    }
+
+    #[test]
+    fn fallbacks() {
+        let request = TestRequest::default()
+            .peer_addr("[::1]:4711".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "unknown"))
+            .insert_header(("Forwarded", "proto=https;for=\"[2001:db8::1]:8080\""))
+            .to_http_request();
+        let sources = [IpSource::XForwardedFor(1), IpSource::XRealIp];
+        assert_eq!(resolve(&request, &sources, &[], true), Some(ip("::1")));
+        let sources = [IpSource::Forwarded];
+        assert_eq!(
+            resolve(&request, &sources, &[], true),
+            Some(ip("2001:db8::1"))
+        );
+    }
+}
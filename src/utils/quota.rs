@@ -0,0 +1,302 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{errors::RustusResult, info_storages::FileInfo, InfoStorage, State};
+
+/// Quota for a single tenant.
+///
+/// It's parsed from strings like `tenant=1000`,
+/// where the number is the maximum amount of bytes.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TenantQuota {
+    pub tenant: String,
+    pub quota: usize,
+}
+
+impl FromStr for TenantQuota {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let (tenant, quota) = input
+            .split_once('=')
+            .ok_or_else(|| format!("Tenant quota '{input}' must be in format 'tenant=bytes'."))?;
+        let quota = quota
+            .trim()
+            .parse::<usize>()
+            .map_err(|err| format!("Wrong quota for tenant '{tenant}': {err}"))?;
+        Ok(Self {
+            tenant: String::from(tenant.trim()),
+            quota,
+        })
+    }
+}
+
+/// Number of bytes reserved by an upload.
+///
+/// Uploads with known length reserve the whole length,
+/// even if they aren't finished. For deferred uploads
+/// only written bytes are counted.
+pub fn reserved_size(file_info: &FileInfo) -> usize {
+    file_info.length.unwrap_or(file_info.offset)
+}
+
+/// Calculate how many bytes are used by a tenant.
+///
+/// Tenant of an upload is the value of
+/// `tenant_key` in upload's metadata.
+pub async fn tenant_usage(
+    info_storage: &(dyn InfoStorage + Send + Sync),
+    tenant_key: &str,
+    tenant: &str,
+) -> RustusResult<usize> {
+    Ok(info_storage
+        .list_by_metadata(tenant_key, tenant)
+        .await?
+        .iter()
+        .map(reserved_size)
+        .sum())
+}
+
+/// Usage of a tenant which was computed from info storage.
+struct CachedUsage {
+    bytes: usize,
+    computed_at: Instant,
+}
+
+/// Usage of tenants which is kept in memory.
+///
+/// Uploads of a tenant are read from info storage
+/// only when its usage isn't cached or the cache is stale.
+/// Bytes reserved by rustus are added to cached usage,
+/// so new uploads are counted right away.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Clone, Default)]
+pub struct UsageCache {
+    tenants: Arc<Mutex<HashMap<String, CachedUsage>>>,
+}
+
+impl UsageCache {
+    /// Get current usage of the tenant.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if uploads of the tenant can't be read.
+    pub async fn usage(
+        &self,
+        info_storage: &(dyn InfoStorage + Send + Sync),
+        tenant_key: &str,
+        tenant: &str,
+        ttl: Duration,
+    ) -> RustusResult<usize> {
+        if let Some(cached) = self.tenants.lock().unwrap().get(tenant) {
+            if cached.computed_at.elapsed() < ttl {
+                return Ok(cached.bytes);
+            }
+        }
+        let bytes = tenant_usage(info_storage, tenant_key, tenant).await?;
+        self.tenants.lock().unwrap().insert(
+            String::from(tenant),
+            CachedUsage {
+                bytes,
+                computed_at: Instant::now(),
+            },
+        );
+        Ok(bytes)
+    }
+
+    /// Reserve bytes for the tenant if they fit into the quota.
+    ///
+    /// Usage is checked and increased at once,
+    /// so concurrent uploads can't exceed the quota together.
+    ///
+    /// Returns `false` if the quota is exceeded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if uploads of the tenant can't be read.
+    pub async fn reserve(
+        &self,
+        info_storage: &(dyn InfoStorage + Send + Sync),
+        tenant_key: &str,
+        tenant: &str,
+        ttl: Duration,
+        bytes: usize,
+        quota: usize,
+    ) -> RustusResult<bool> {
+        self.usage(info_storage, tenant_key, tenant, ttl).await?;
+        let mut tenants = self.tenants.lock().unwrap();
+        let Some(cached) = tenants.get_mut(tenant) else {
+            return Ok(false);
+        };
+        if cached.bytes + bytes > quota {
+            return Ok(false);
+        }
+        cached.bytes += bytes;
+        Ok(true)
+    }
+
+    /// Count bytes which were written without reservation.
+    pub fn add(&self, tenant: &str, bytes: usize) {
+        if let Some(cached) = self.tenants.lock().unwrap().get_mut(tenant) {
+            cached.bytes += bytes;
+        }
+    }
+
+    /// Return reserved bytes, E.G. if the upload wasn't created.
+    pub fn release(&self, tenant: &str, bytes: usize) {
+        if let Some(cached) = self.tenants.lock().unwrap().get_mut(tenant) {
+            cached.bytes = cached.bytes.saturating_sub(bytes);
+        }
+    }
+}
+
+/// Get usage of the tenant, which may be cached.
+///
+/// # Errors
+///
+/// Returns an error if uploads of the tenant can't be read.
+pub async fn cached_usage(state: &State, tenant: &str) -> RustusResult<usize> {
+    state
+        .tenant_usage
+        .usage(
+            state.info_storage.as_ref(),
+            state.config.tenant_metadata_key.as_str(),
+            tenant,
+            Duration::from_secs(state.config.tenant_usage_ttl),
+        )
+        .await
+}
+
+/// Get tenant of the upload and its quota.
+fn upload_quota<'a>(state: &State, file_info: &'a FileInfo) -> Option<(&'a str, usize)> {
+    let tenant = file_info
+        .metadata
+        .get(state.config.tenant_metadata_key.as_str())?;
+    let quota = state.config.tenant_quota(tenant)?;
+    Some((tenant.as_str(), quota))
+}
+
+/// Check if bytes fit into the quota of the upload's tenant.
+///
+/// Unlike `reserve`, bytes aren't added to the usage.
+///
+/// # Errors
+///
+/// Returns an error if uploads of the tenant can't be read.
+pub async fn fits(state: &State, file_info: &FileInfo, bytes: usize) -> RustusResult<bool> {
+    let Some((tenant, quota)) = upload_quota(state, file_info) else {
+        return Ok(true);
+    };
+    Ok(cached_usage(state, tenant).await? + bytes <= quota)
+}
+
+/// Reserve bytes in the quota of the upload's tenant.
+///
+/// Uploads without tenant or quota are never limited.
+///
+/// Returns `false` if the quota is exceeded.
+///
+/// # Errors
+///
+/// Returns an error if uploads of the tenant can't be read.
+pub async fn reserve(state: &State, file_info: &FileInfo, bytes: usize) -> RustusResult<bool> {
+    let tenant_key = state.config.tenant_metadata_key.as_str();
+    let Some((tenant, quota)) = upload_quota(state, file_info) else {
+        return Ok(true);
+    };
+    state
+        .tenant_usage
+        .reserve(
+            state.info_storage.as_ref(),
+            tenant_key,
+            tenant,
+            Duration::from_secs(state.config.tenant_usage_ttl),
+            bytes,
+            quota,
+        )
+        .await
+}
+
+/// Return bytes reserved for the upload.
+pub fn release(state: &State, file_info: &FileInfo, bytes: usize) {
+    if let Some(tenant) = file_info
+        .metadata
+        .get(state.config.tenant_metadata_key.as_str())
+    {
+        state.tenant_usage.release(tenant, bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TenantQuota, UsageCache};
+    use crate::State;
+    use std::{str::FromStr, time::Duration};
+
+    #[test]
+    fn test_parse_quota() {
+        let quota = TenantQuota::from_str("acme=1000").unwrap();
+        assert_eq!(quota.tenant, "acme");
+        assert_eq!(quota.quota, 1000);
+    }
+
+    #[test]
+    fn test_parse_wrong_quota() {
+        assert!(TenantQuota::from_str("acme").is_err());
+        assert!(TenantQuota::from_str("acme=many").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn cached_usage() {
+        let state = State::test_new().await;
+        let mut file_info = state.create_test_file().await;
+        file_info.metadata.insert("tenant".into(), "acme".into());
+        state
+            .info_storage
+            .set_info(&file_info, false)
+            .await
+            .unwrap();
+        let cache = UsageCache::default();
+        let storage = state.info_storage.as_ref();
+        let ttl = Duration::from_secs(60);
+        assert_eq!(
+            cache.usage(storage, "tenant", "acme", ttl).await.unwrap(),
+            10
+        );
+        assert!(cache
+            .reserve(storage, "tenant", "acme", ttl, 5, 15)
+            .await
+            .unwrap());
+        assert!(!cache
+            .reserve(storage, "tenant", "acme", ttl, 1, 15)
+            .await
+            .unwrap());
+        // Uploads aren't read again until the cache is stale.
+        state
+            .info_storage
+            .remove_info(file_info.id.as_str())
+            .await
+            .unwrap();
+        assert_eq!(
+            cache.usage(storage, "tenant", "acme", ttl).await.unwrap(),
+            15
+        );
+        cache.release("acme", 5);
+        assert_eq!(
+            cache.usage(storage, "tenant", "acme", ttl).await.unwrap(),
+            10
+        );
+        assert_eq!(
+            cache
+                .usage(storage, "tenant", "acme", Duration::ZERO)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder};
+use derive_more::Display;
+
+use crate::{errors::RustusError, notifiers::Hook, State};
+
+/// Reason why creation of an upload was rejected.
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq)]
+pub enum Reason {
+    /// Server doesn't accept new uploads.
+    #[display(fmt = "draining")]
+    Draining,
+    /// Length of the upload is required.
+    #[display(fmt = "length-required")]
+    LengthRequired,
+    /// Upload is empty, but empty uploads aren't allowed.
+    #[display(fmt = "empty-upload")]
+    EmptyUpload,
+    /// Upload is larger than the maximum file size.
+    #[display(fmt = "too-large")]
+    TooLarge,
+    /// Tenant has no space for the upload.
+    #[display(fmt = "quota-exceeded")]
+    QuotaExceeded,
+    /// Metadata contains an invalid value or callback URL.
+    #[display(fmt = "invalid-metadata")]
+    InvalidMetadata,
+    /// Upload with the same unique metadata already exists.
+    #[display(fmt = "duplicate")]
+    Duplicate,
+    /// Pre-create hook rejected the upload.
+    #[display(fmt = "hook-rejected")]
+    HookRejected,
+    /// Any other invalid request.
+    #[display(fmt = "invalid-request")]
+    InvalidRequest,
+}
+
+/// Reason which is attached to the response.
+struct Rejected {
+    reason: Reason,
+    message: String,
+}
+
+/// Upload which a client tried to create.
+#[derive(Clone, Debug, Default)]
+pub struct Attempt {
+    pub length: Option<usize>,
+    pub metadata: HashMap<String, String>,
+}
+
+/// Rejected creation of an upload.
+#[derive(Clone, Debug)]
+pub struct Rejection {
+    pub reason: Reason,
+    pub message: String,
+    /// Status of the response.
+    pub status: u16,
+    pub upload: Attempt,
+}
+
+/// Remember the upload which the client tries to create.
+///
+/// It's sent in `create-rejected` hook if creation fails later.
+pub fn attempt(
+    request: &HttpRequest,
+    length: Option<usize>,
+    metadata: Option<&HashMap<String, String>>,
+) {
+    request.extensions_mut().insert(Attempt {
+        length,
+        metadata: metadata.cloned().unwrap_or_default(),
+    });
+}
+
+/// Build response which rejects creation of an upload.
+pub fn reject(mut builder: HttpResponseBuilder, reason: Reason, message: String) -> HttpResponse {
+    let mut response = builder.body(message.clone());
+    response
+        .extensions_mut()
+        .insert(Rejected { reason, message });
+    response
+}
+
+/// Find out why creation of the upload was rejected.
+///
+/// Returns `None` if the upload was created
+/// or the request failed because of the server.
+fn rejection(request: &HttpRequest, result: &actix_web::Result<HttpResponse>) -> Option<Rejection> {
+    let (status, reason, message) = match result {
+        Ok(response) => {
+            let status = response.status();
+            if let Some(rejected) = response.extensions().get::<Rejected>() {
+                (status, rejected.reason, rejected.message.clone())
+            } else if status.is_client_error() {
+                let message = status.canonical_reason().unwrap_or_default();
+                (status, Reason::InvalidRequest, String::from(message))
+            } else {
+                return None;
+            }
+        }
+        Err(err) => {
+            let status = err.as_response_error().status_code();
+            let reason = match err.as_error::<RustusError>() {
+                Some(RustusError::Draining(_)) => Reason::Draining,
+                _ if !status.is_client_error() => return None,
+                Some(
+                    RustusError::HookError(_)
+                    | RustusError::HTTPHookError(..)
+                    | RustusError::UploadRejected(_),
+                ) => Reason::HookRejected,
+                Some(
+                    RustusError::InvalidCallbackUrl(_) | RustusError::TooManyMetadataValues(..),
+                ) => Reason::InvalidMetadata,
+                _ => Reason::InvalidRequest,
+            };
+            (status, reason, err.to_string())
+        }
+    };
+    Some(Rejection {
+        reason,
+        message,
+        status: status.as_u16(),
+        upload: request
+            .extensions()
+            .get::<Attempt>()
+            .cloned()
+            .unwrap_or_default(),
+    })
+}
+
+/// Send `create-rejected` hook if creation of the upload was rejected.
+///
+/// Hook is sent in background, so it doesn't delay the response.
+/// Hooks over the rate limit are dropped.
+pub fn notify(
+    state: &web::Data<State>,
+    request: &HttpRequest,
+    result: &actix_web::Result<HttpResponse>,
+) {
+    if !state.config.hook_is_active(Hook::CreateRejected) {
+        return;
+    }
+    let Some(rejection) = rejection(request, result) else {
+        return;
+    };
+    if !state.notification_manager.allow_rejection() {
+        return;
+    }
+    let message = state
+        .config
+        .notification_opts
+        .hooks_format
+        .format_rejection(request, &rejection, &state.config.client_ip);
+    let headers = request.headers().clone();
+    let state = state.clone();
+    tokio::task::spawn_local(async move {
+        state
+            .notification_manager
+            .deliver(message, Hook::CreateRejected, &headers)
+            .await;
+    });
+}
@@ -0,0 +1,88 @@
+use actix_web::http::{
+    header::{HeaderMap, HeaderValue, RETRY_AFTER},
+    StatusCode,
+};
+
+use crate::config::RustusConf;
+
+/// Delays in seconds which clients are asked to wait
+/// before retrying requests to an overloaded server.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryAfter {
+    rate_limit: u64,
+    unavailable: u64,
+    storage_full: u64,
+}
+
+impl RetryAfter {
+    pub fn from_config(config: &RustusConf) -> Self {
+        Self {
+            rate_limit: config.rate_limit_retry_after,
+            unavailable: config.drain_retry_after,
+            storage_full: config.storage_full_retry_after,
+        }
+    }
+
+    /// Get the delay for responses with the status.
+    ///
+    /// Returns `None` if the request shouldn't be retried later.
+    pub fn for_status(&self, status: StatusCode) -> Option<u64> {
+        match status {
+            StatusCode::TOO_MANY_REQUESTS => Some(self.rate_limit),
+            StatusCode::SERVICE_UNAVAILABLE => Some(self.unavailable),
+            StatusCode::INSUFFICIENT_STORAGE => Some(self.storage_full),
+            _ => None,
+        }
+    }
+
+    /// Add `Retry-After` header to the response.
+    ///
+    /// Header which is already set isn't changed.
+    pub fn apply(&self, status: StatusCode, headers: &mut HeaderMap) {
+        if headers.contains_key(RETRY_AFTER) {
+            return;
+        }
+        if let Some(delay) = self.for_status(status) {
+            headers.insert(RETRY_AFTER, HeaderValue::from(delay));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryAfter;
+    use actix_web::http::{
+        header::{HeaderMap, HeaderValue, RETRY_AFTER},
+        StatusCode,
+    };
+
+    #[test]
+    fn retry_after_statuses() {
+        let retry_after = RetryAfter {
+            rate_limit: 1,
+            unavailable: 60,
+            storage_full: 300,
+        };
+        let cases = [
+            (StatusCode::TOO_MANY_REQUESTS, Some("1")),
+            (StatusCode::SERVICE_UNAVAILABLE, Some("60")),
+            (StatusCode::INSUFFICIENT_STORAGE, Some("300")),
+            (StatusCode::INTERNAL_SERVER_ERROR, None),
+            (StatusCode::NO_CONTENT, None),
+        ];
+        for (status, expected) in cases {
+            let mut headers = HeaderMap::new();
+            retry_after.apply(status, &mut headers);
+            assert_eq!(
+                headers
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok()),
+                expected
+            );
+        }
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from(10));
+        retry_after.apply(StatusCode::SERVICE_UNAVAILABLE, &mut headers);
+        assert_eq!(headers.get(RETRY_AFTER).unwrap(), "10");
+    }
+}
@@ -0,0 +1,97 @@
+use base64::{engine::general_purpose, Engine};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Query parameters of a signed download URL.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Deserialize, Debug, Clone)]
+pub struct DownloadSignature {
+    /// Unix timestamp after which URL is expired.
+    pub expires: i64,
+    /// Base64 encoded HMAC of upload id and expiration time.
+    pub signature: String,
+}
+
+fn get_mac(secret: &str, file_id: &str, expires: i64) -> HmacSha256 {
+    // HMAC accepts keys of any length.
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(format!("{file_id}:{expires}").as_bytes());
+    mac
+}
+
+/// Sign download URL of an upload.
+///
+/// Returns URL-safe base64 encoded signature.
+pub fn sign(secret: &str, file_id: &str, expires: i64) -> String {
+    let signature = get_mac(secret, file_id, expires).finalize().into_bytes();
+    general_purpose::URL_SAFE_NO_PAD.encode(signature)
+}
+
+/// Check that signature is valid and isn't expired.
+///
+/// `now` is current unix timestamp.
+pub fn verify(secret: &str, file_id: &str, signature: &DownloadSignature, now: i64) -> bool {
+    if signature.expires < now {
+        return false;
+    }
+    let Ok(decoded) = general_purpose::URL_SAFE_NO_PAD.decode(signature.signature.as_bytes())
+    else {
+        return false;
+    };
+    get_mac(secret, file_id, signature.expires)
+        .verify_slice(decoded.as_slice())
+        .is_ok()
+}
+
+/// Sign a document served by rustus.
+///
+/// Returns base64 encoded HMAC of the document.
+pub fn sign_document(secret: &str, document: &[u8]) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(document);
+    general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign, sign_document, verify, DownloadSignature};
+
+    fn signed(file_id: &str, expires: i64) -> DownloadSignature {
+        DownloadSignature {
+            expires,
+            signature: sign("secret", file_id, expires),
+        }
+    }
+
+    #[test]
+    fn valid_signature() {
+        assert!(verify("secret", "upload", &signed("upload", 100), 50));
+    }
+
+    #[test]
+    fn expired_signature() {
+        assert!(!verify("secret", "upload", &signed("upload", 100), 101));
+    }
+
+    #[test]
+    fn wrong_signature() {
+        let mut signature = signed("upload", 100);
+        assert!(!verify("other", "upload", &signature, 50));
+        assert!(!verify("secret", "other_upload", &signature, 50));
+        signature.expires = 1000;
+        assert!(!verify("secret", "upload", &signature, 50));
+        signature.signature = String::from("not base64!");
+        assert!(!verify("secret", "upload", &signature, 50));
+    }
+
+    #[test]
+    fn document_signature() {
+        let signature = sign_document("secret", b"document");
+        assert_eq!(signature, sign_document("secret", b"document"));
+        assert_ne!(signature, sign_document("other", b"document"));
+        assert_ne!(signature, sign_document("secret", b"other document"));
+    }
+}
@@ -0,0 +1,53 @@
+use std::{future::Future, time::Duration};
+
+use crate::errors::{RustusError, RustusResult};
+
+/// Run storage operation with an optional timeout.
+///
+/// If the operation doesn't finish in time,
+/// `RustusError::Timeout` is returned.
+///
+/// # Params
+/// `timeout` - maximum duration of the operation. `None` means no limit.
+/// `operation` - name of the operation for error messages.
+/// `future` - the operation itself.
+#[allow(clippy::module_name_repetitions)]
+pub async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    operation: &str,
+    future: impl Future<Output = RustusResult<T>>,
+) -> RustusResult<T> {
+    let Some(duration) = timeout else {
+        return future.await;
+    };
+    tokio::time::timeout(duration, future)
+        .await
+        .map_err(|_| RustusError::Timeout(String::from(operation)))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::with_timeout;
+    use crate::errors::RustusError;
+    use std::time::Duration;
+
+    #[actix_rt::test]
+    async fn operation_timed_out() {
+        let res = with_timeout(Some(Duration::from_millis(10)), "sleep", async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(())
+        })
+        .await;
+        assert!(matches!(res, Err(RustusError::Timeout(_))));
+    }
+
+    #[actix_rt::test]
+    async fn no_timeout() {
+        let res = with_timeout(None, "sleep", async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok(1)
+        })
+        .await;
+        assert_eq!(res.unwrap(), 1);
+    }
+}
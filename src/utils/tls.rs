@@ -0,0 +1,173 @@
+use std::path::PathBuf;
+
+use derive_more::{Display, From};
+use openssl::ssl::{
+    select_next_proto, AlpnError, SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod,
+    SslVersion,
+};
+use strum::EnumIter;
+
+use crate::{config::RustusConf, errors::RustusError, from_str, RustusResult};
+
+/// Minimum version of TLS protocol.
+#[derive(PartialEq, Eq, Debug, Display, EnumIter, From, Clone, Copy)]
+pub enum TlsVersion {
+    #[display(fmt = "1.2")]
+    Tls12,
+    #[display(fmt = "1.3")]
+    Tls13,
+}
+
+from_str!(TlsVersion, "TLS version");
+
+impl TlsVersion {
+    fn ssl_version(self) -> SslVersion {
+        match self {
+            Self::Tls12 => SslVersion::TLS1_2,
+            Self::Tls13 => SslVersion::TLS1_3,
+        }
+    }
+}
+
+/// Settings of TLS for TCP listeners.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    cert: PathBuf,
+    key: PathBuf,
+    min_version: TlsVersion,
+    ciphers: Option<String>,
+    ciphersuites: Option<String>,
+    http2: bool,
+}
+
+impl TlsConfig {
+    /// Get TLS settings from the configuration.
+    ///
+    /// Returns `None` if TLS isn't enabled.
+    pub fn from_config(config: &RustusConf) -> Option<Self> {
+        let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) else {
+            return None;
+        };
+        Some(Self {
+            cert: cert.clone(),
+            key: key.clone(),
+            min_version: config.tls_min_version,
+            ciphers: config.tls_ciphers.clone(),
+            ciphersuites: config.tls_ciphersuites.clone(),
+            http2: config.http2,
+        })
+    }
+
+    /// Create TLS acceptor for the server.
+    ///
+    /// HTTP/2 is added to ALPN protocols of this acceptor
+    /// only if it's enabled, so clients can choose it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TLSError` if certificates can't be loaded
+    /// or OpenSSL rejects ciphers, and `InvalidTlsConfig`
+    /// if settings contradict each other.
+    pub fn acceptor(&self) -> RustusResult<SslAcceptor> {
+        let mut builder = self.protocol()?;
+        builder.set_private_key_file(self.key.as_path(), SslFiletype::PEM)?;
+        builder.set_certificate_chain_file(self.cert.as_path())?;
+        builder.check_private_key()?;
+        let server_protocols = self.alpn_protocols();
+        builder.set_alpn_select_callback(move |_, protocols| {
+            select_next_proto(server_protocols, protocols).ok_or(AlpnError::NOACK)
+        });
+        Ok(builder.build())
+    }
+
+    /// ALPN protocols supported by the server in wire format.
+    fn alpn_protocols(&self) -> &'static [u8] {
+        if self.http2 {
+            b"\x02h2\x08http/1.1"
+        } else {
+            b"\x08http/1.1"
+        }
+    }
+
+    /// Create acceptor with protocol versions and ciphers.
+    ///
+    /// `ciphers` are used only by TLS 1.2 and
+    /// `ciphersuites` are used only by TLS 1.3.
+    fn protocol(&self) -> RustusResult<SslAcceptorBuilder> {
+        if self.min_version == TlsVersion::Tls13 && self.ciphers.is_some() {
+            return Err(RustusError::InvalidTlsConfig(String::from(
+                "TLS 1.2 ciphers can't be set if minimum TLS version is 1.3",
+            )));
+        }
+        // Intermediate profile allows only TLS 1.2 and later with secure ciphers.
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+        builder.set_min_proto_version(Some(self.min_version.ssl_version()))?;
+        if let Some(ciphers) = &self.ciphers {
+            builder.set_cipher_list(ciphers.as_str())?;
+        }
+        if let Some(ciphersuites) = &self.ciphersuites {
+            builder.set_ciphersuites(ciphersuites.as_str())?;
+        }
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TlsConfig, TlsVersion};
+    use std::{path::PathBuf, str::FromStr};
+
+    fn tls_config(
+        min_version: TlsVersion,
+        ciphers: Option<&str>,
+        ciphersuites: Option<&str>,
+    ) -> TlsConfig {
+        TlsConfig {
+            cert: PathBuf::from("cert.pem"),
+            key: PathBuf::from("key.pem"),
+            min_version,
+            ciphers: ciphers.map(String::from),
+            ciphersuites: ciphersuites.map(String::from),
+            http2: false,
+        }
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(TlsVersion::from_str("1.2").unwrap(), TlsVersion::Tls12);
+        assert_eq!(TlsVersion::from_str("1.3").unwrap(), TlsVersion::Tls13);
+        assert!(TlsVersion::from_str("1.1").is_err());
+    }
+
+    #[test]
+    fn alpn_protocols() {
+        let mut config = tls_config(TlsVersion::Tls12, None, None);
+        assert_eq!(config.alpn_protocols(), b"\x08http/1.1");
+        config.http2 = true;
+        assert_eq!(config.alpn_protocols(), b"\x02h2\x08http/1.1");
+    }
+
+    #[test]
+    fn protocol_settings() {
+        let valid = [
+            tls_config(TlsVersion::Tls12, None, None),
+            tls_config(
+                TlsVersion::Tls12,
+                Some("ECDHE-ECDSA-AES256-GCM-SHA384:ECDHE-RSA-AES256-GCM-SHA384"),
+                Some("TLS_AES_256_GCM_SHA384"),
+            ),
+            tls_config(TlsVersion::Tls13, None, Some("TLS_AES_256_GCM_SHA384")),
+        ];
+        for config in valid {
+            assert!(config.protocol().is_ok());
+        }
+        let invalid = [
+            tls_config(TlsVersion::Tls12, Some("UNKNOWN-CIPHER"), None),
+            tls_config(TlsVersion::Tls12, None, Some("UNKNOWN_SUITE")),
+            tls_config(TlsVersion::Tls13, Some("ECDHE-RSA-AES256-GCM-SHA384"), None),
+        ];
+        for config in invalid {
+            assert!(config.protocol().is_err());
+        }
+    }
+}
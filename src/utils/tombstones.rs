@@ -0,0 +1,38 @@
+use log::warn;
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    State,
+};
+
+/// Keep a tombstone of the removed upload.
+///
+/// It does nothing if tombstones are disabled.
+/// Upload is already removed, so errors are only logged.
+pub async fn bury(state: &State, file_id: &str) {
+    let Some(ttl) = state.config.tombstone_ttl else {
+        return;
+    };
+    if let Err(err) = state.info_storage.add_tombstone(file_id, ttl).await {
+        warn!("Cannot keep tombstone of upload {file_id}: {err}");
+    }
+}
+
+/// Get information about the upload.
+///
+/// # Errors
+///
+/// Returns `UploadRemoved` instead of `FileNotFound`
+/// if the upload has a tombstone.
+pub async fn get_info(state: &State, file_id: &str) -> RustusResult<FileInfo> {
+    match state.info_storage.get_info(file_id).await {
+        Err(RustusError::FileNotFound)
+            if state.config.tombstone_ttl.is_some()
+                && state.info_storage.has_tombstone(file_id).await? =>
+        {
+            Err(RustusError::UploadRemoved(String::from(file_id)))
+        }
+        result => result,
+    }
+}
@@ -0,0 +1,129 @@
+use chrono::{DateTime, Utc};
+use log::warn;
+
+use crate::{
+    errors::{RustusError, RustusResult},
+    info_storages::FileInfo,
+    InfoStorage, State,
+};
+
+/// Check if grace period of the terminated upload is over.
+pub fn is_expired(file_info: &FileInfo, grace_period: u64, now: DateTime<Utc>) -> bool {
+    let grace_period =
+        chrono::Duration::seconds(i64::try_from(grace_period).unwrap_or(i64::MAX / 1000));
+    file_info
+        .removed_at
+        .is_none_or(|removed_at| now - removed_at >= grace_period)
+}
+
+/// Move information about the terminated upload to the trash.
+///
+/// Data of the upload is kept in place until
+/// the grace period is over, so the upload can be restored.
+/// Trash entry is written before information is removed,
+/// so an interrupted move leaves the upload alive
+/// and the entry is dropped when the trash is emptied.
+///
+/// # Errors
+///
+/// Returns `FileNotFound` if the upload was already removed.
+pub async fn put(
+    state: &State,
+    trash: &(dyn InfoStorage + Send + Sync),
+    file_info: &FileInfo,
+) -> RustusResult<()> {
+    let mut trashed = file_info.clone();
+    trashed.removed_at = Some(Utc::now());
+    trash.set_info(&trashed, true).await?;
+    match state.info_storage.remove_info(file_info.id.as_str()).await {
+        Ok(()) => Ok(()),
+        // Entry may belong to the termination which removed the information.
+        Err(RustusError::FileNotFound) => Err(RustusError::FileNotFound),
+        Err(err) => {
+            if let Err(err) = trash.remove_info(file_info.id.as_str()).await {
+                warn!(
+                    "Cannot remove trash entry of upload {}: {}",
+                    file_info.id, err
+                );
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Take the terminated upload out of the trash.
+///
+/// Information about the upload is moved back,
+/// data wasn't removed, so the upload is the same as before.
+///
+/// # Errors
+///
+/// Returns `FileNotFound` if there's no such upload in the trash
+/// and `UploadRemoved` if its grace period is over.
+pub async fn restore(state: &State, file_id: &str) -> RustusResult<FileInfo> {
+    let (Some(trash), Some(grace_period)) = (&state.trash, state.config.termination_grace_period)
+    else {
+        return Err(RustusError::FileNotFound);
+    };
+    let mut file_info = trash.get_info(file_id).await?;
+    if file_info.storage != state.data_storage.to_string() {
+        return Err(RustusError::FileNotFound);
+    }
+    if is_expired(&file_info, grace_period, Utc::now()) {
+        return Err(RustusError::UploadRemoved(String::from(file_id)));
+    }
+    file_info.removed_at = None;
+    // Information is restored first, the entry left
+    // in the trash is dropped when the trash is emptied.
+    state.info_storage.set_info(&file_info, true).await?;
+    if let Err(err) = trash.remove_info(file_id).await {
+        warn!("Cannot remove trash entry of upload {}: {}", file_id, err);
+    }
+    Ok(file_info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{put, restore};
+    use crate::{errors::RustusError, State};
+
+    #[actix_rt::test]
+    async fn put_and_restore() {
+        let mut state = State::test_new().await;
+        state.enable_test_trash(3600).await;
+        let file_info = state.create_test_file().await;
+        let trash = state.trash.as_ref().unwrap();
+        put(&state, trash.as_ref(), &file_info).await.unwrap();
+        assert!(state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .is_err());
+        let trashed = trash.get_info(file_info.id.as_str()).await.unwrap();
+        assert!(trashed.removed_at.is_some());
+
+        let restored = restore(&state, file_info.id.as_str()).await.unwrap();
+        assert!(restored.removed_at.is_none());
+        let info = state
+            .info_storage
+            .get_info(file_info.id.as_str())
+            .await
+            .unwrap();
+        assert_eq!(info.path, file_info.path);
+        assert!(trash.get_info(file_info.id.as_str()).await.is_err());
+        // Upload isn't in the trash anymore.
+        let err = restore(&state, file_info.id.as_str()).await.unwrap_err();
+        assert!(matches!(err, RustusError::FileNotFound));
+    }
+
+    #[actix_rt::test]
+    async fn expired_grace_period() {
+        let mut state = State::test_new().await;
+        state.enable_test_trash(0).await;
+        let file_info = state.create_test_file().await;
+        let trash = state.trash.as_ref().unwrap();
+        put(&state, trash.as_ref(), &file_info).await.unwrap();
+        let err = restore(&state, file_info.id.as_str()).await.unwrap_err();
+        assert!(matches!(err, RustusError::UploadRemoved(_)));
+    }
+}
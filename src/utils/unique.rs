@@ -0,0 +1,109 @@
+use actix_web::HttpResponse;
+
+use crate::{
+    errors::RustusResult,
+    info_storages::FileInfo,
+    utils::{
+        active_chunks::ChunkGuard,
+        rejections::{self, Reason},
+    },
+    State,
+};
+
+/// Result of the uniqueness check of a new upload.
+pub enum Uniqueness {
+    /// Upload can be created.
+    ///
+    /// Its unique value is locked while the guard is alive.
+    Unique(Option<ChunkGuard>),
+    /// Upload must be rejected with the response.
+    Duplicate(HttpResponse),
+}
+
+/// Check that no upload has the same value of the unique metadata key.
+///
+/// Only uploads with the same value of the scope key are compared.
+/// Unfinished uploads are compared only if they're configured to be.
+///
+/// The value is locked before uploads are compared, so concurrent
+/// uploads with the same value can't pass the check together.
+/// The guard must be kept until the upload is saved.
+///
+/// # Errors
+///
+/// Returns an error if uploads can't be listed.
+pub async fn check(state: &State, file_info: &FileInfo) -> RustusResult<Uniqueness> {
+    let Some(key) = &state.config.unique_metadata_key else {
+        return Ok(Uniqueness::Unique(None));
+    };
+    let Some(value) = file_info.metadata.get(key) else {
+        return Ok(Uniqueness::Unique(None));
+    };
+    if file_info.is_partial {
+        return Ok(Uniqueness::Unique(None));
+    }
+    let scope_key = state.config.unique_metadata_scope.as_deref();
+    let scope = scope_key.and_then(|scope_key| file_info.metadata.get(scope_key));
+    let lock_key = format!(
+        "{key}\0{}\0{value}",
+        scope.map(String::as_str).unwrap_or_default()
+    );
+    let Some(guard) = state.unique_values.acquire_exclusive(lock_key.as_str()) else {
+        return Ok(Uniqueness::Duplicate(rejections::reject(
+            HttpResponse::Conflict(),
+            Reason::Duplicate,
+            format!("Upload with {key} `{value}` is being created."),
+        )));
+    };
+    let duplicate = state
+        .info_storage
+        .list_by_metadata(key, value)
+        .await?
+        .into_iter()
+        .any(|existing| {
+            !existing.is_partial
+                && (existing.length == Some(existing.offset)
+                    || state.config.unique_reject_unfinished)
+                && !matches!(scope_key, Some(scope_key) if existing.metadata.get(scope_key) != scope)
+        });
+    if duplicate {
+        return Ok(Uniqueness::Duplicate(rejections::reject(
+            HttpResponse::Conflict(),
+            Reason::Duplicate,
+            format!("Upload with {key} `{value}` already exists."),
+        )));
+    }
+    Ok(Uniqueness::Unique(Some(guard)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, Uniqueness};
+    use crate::State;
+
+    #[actix_rt::test]
+    async fn concurrent_uploads() {
+        let mut state = State::test_new().await;
+        state.config.unique_metadata_key = Some(String::from("filename"));
+        let mut file_info = state.create_test_file().await;
+        file_info
+            .metadata
+            .insert("filename".into(), "memes.txt".into());
+        let Uniqueness::Unique(guard) = check(&state, &file_info).await.unwrap() else {
+            panic!("Upload must be unique.");
+        };
+        assert!(guard.is_some());
+        // The value is locked until the first upload is saved.
+        let mut other = state.create_test_file().await;
+        other.metadata = file_info.metadata.clone();
+        assert!(matches!(
+            check(&state, &other).await.unwrap(),
+            Uniqueness::Duplicate(_)
+        ));
+        drop(guard);
+        assert!(matches!(
+            check(&state, &other).await.unwrap(),
+            Uniqueness::Unique(Some(_))
+        ));
+    }
+}
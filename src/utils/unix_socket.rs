@@ -0,0 +1,141 @@
+use std::{
+    fs::DirBuilder,
+    io::{Error, ErrorKind},
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+};
+
+/// Remove socket left by the previous run.
+///
+/// Sockets which accept connections and
+/// other files are never removed.
+///
+/// # Errors
+///
+/// It returns error if the socket is used by another process
+/// or if it can't be removed.
+pub fn remove_stale_socket(path: &Path) -> std::io::Result<()> {
+    let Ok(meta) = std::fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if !meta.file_type().is_socket() {
+        return Ok(());
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(Error::new(
+            ErrorKind::AddrInUse,
+            format!("Socket {} is used by another process.", path.display()),
+        ));
+    }
+    std::fs::remove_file(path)
+}
+
+/// Bind the socket with the mode.
+///
+/// The socket is bound in a private directory next to the path,
+/// its mode is set and then it's moved to the path.
+/// So the socket is never reachable with permissions from umask.
+///
+/// # Errors
+///
+/// It returns error if the socket can't be bound or moved.
+pub fn bind(path: &Path, mode: Option<u32>) -> std::io::Result<UnixListener> {
+    let Some(mode) = mode else {
+        return UnixListener::bind(path);
+    };
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let private_dir = parent.join(format!(".rustus-{}", uuid::Uuid::new_v4().simple()));
+    DirBuilder::new()
+        .mode(0o700)
+        .create(private_dir.as_path())?;
+    let private_path = private_dir.join("rustus.sock");
+    let result = UnixListener::bind(private_path.as_path()).and_then(|listener| {
+        std::fs::set_permissions(
+            private_path.as_path(),
+            std::fs::Permissions::from_mode(mode),
+        )?;
+        // Other files are never replaced, as if the socket was bound to the path.
+        if std::fs::symlink_metadata(path).is_ok() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("File {} already exists.", path.display()),
+            ));
+        }
+        std::fs::rename(private_path.as_path(), path)?;
+        Ok(listener)
+    });
+    if result.is_err() {
+        std::fs::remove_file(private_path.as_path()).ok();
+    }
+    std::fs::remove_dir(private_dir.as_path())?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bind, remove_stale_socket};
+    use std::os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    };
+
+    #[test]
+    fn bind_with_mode() {
+        let dir = tempdir::TempDir::new("unix_socket").unwrap();
+        let path = dir.path().join("rustus.sock");
+        let _listener = bind(path.as_path(), Some(0o600)).unwrap();
+        let mode = std::fs::metadata(path.as_path())
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(UnixStream::connect(path.as_path()).is_ok());
+        // Only the socket is left in the directory.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn bind_with_mode_keeps_files() {
+        let dir = tempdir::TempDir::new("unix_socket").unwrap();
+        let path = dir.path().join("rustus.sock");
+        std::fs::write(path.as_path(), "memes").unwrap();
+        assert!(bind(path.as_path(), Some(0o600)).is_err());
+        assert_eq!(std::fs::read_to_string(path.as_path()).unwrap(), "memes");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn remove_stale() {
+        let dir = tempdir::TempDir::new("unix_socket").unwrap();
+        let path = dir.path().join("rustus.sock");
+        drop(UnixListener::bind(path.as_path()).unwrap());
+        assert!(path.exists());
+        remove_stale_socket(path.as_path()).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn keep_active_socket() {
+        let dir = tempdir::TempDir::new("unix_socket").unwrap();
+        let path = dir.path().join("rustus.sock");
+        let _listener = UnixListener::bind(path.as_path()).unwrap();
+        assert!(remove_stale_socket(path.as_path()).is_err());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn keep_regular_file() {
+        let dir = tempdir::TempDir::new("unix_socket").unwrap();
+        let path = dir.path().join("rustus.sock");
+        std::fs::write(path.as_path(), "memes").unwrap();
+        remove_stale_socket(path.as_path()).unwrap();
+        assert!(path.exists());
+        assert!(remove_stale_socket(dir.path().join("unknown").as_path()).is_ok());
+    }
+}
@@ -0,0 +1,8 @@
+/// The rustc version that was used to compile this crate.
+pub const RUSTC_VERSION: Option<&'static str> = Some("1.95.0");
+/// The rustc release channel that was used to compile this crate.
+pub const RUSTC_CHANNEL: Option<&'static str> = Some("stable");
+/// The platform identifier.
+#[allow(unused)] pub const PLATFORM: &str = "linux";
+/// The CPU architecture identifier.
+pub const ARCH: &str = "x86_64";